@@ -0,0 +1,339 @@
+//! Operator endpoints under `/admin`, guarded by a bearer token
+//!
+//! Requests must carry `Authorization: Bearer <ADMIN_TOKEN>`. The token is
+//! compared in constant time, and every endpoint answers 401 when no token
+//! is configured.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use serde::Deserialize;
+use subtle::ConstantTimeEq;
+use zkclear_sequencer::SequencerError;
+use zkclear_state::{AccountExport, ImportError};
+
+use crate::handlers::{parse_address, ApiState};
+use crate::types::{BlockSummary, ErrorResponse};
+
+#[derive(Debug, Default, Deserialize)]
+pub struct BuildBlockRequest {
+    #[serde(default)]
+    pub generate_proof: bool,
+}
+
+/// Build and execute a block from the queue right away instead of waiting
+/// for the next block production tick. Answers 204 when the queue is empty.
+pub async fn force_build_block(
+    State(state): State<Arc<ApiState>>,
+    headers: HeaderMap,
+    body: Option<Json<BuildBlockRequest>>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    authorize(&state, &headers)?;
+    let Json(request) = body.unwrap_or_default();
+
+    // Proof generation blocks until the prover finishes
+    let sequencer = state.sequencer.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        sequencer.build_and_execute_block_with_proof(request.generate_proof)
+    })
+    .await
+    .map_err(|e| block_production_error(format!("Block production task failed: {}", e)))?;
+
+    match result {
+        Ok(block) => Ok(Json(BlockSummary {
+            block_id: block.id,
+            timestamp: block.timestamp,
+            transaction_count: block.transactions.len(),
+            state_root: format!("0x{}", hex::encode(block.state_root)),
+            has_proof: !block.block_proof.is_empty(),
+        })
+        .into_response()),
+        Err(SequencerError::NoTransactions) => Ok(StatusCode::NO_CONTENT.into_response()),
+        Err(e) => Err(block_production_error(format!(
+            "Failed to build block: {:?}",
+            e
+        ))),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportAccountRequest {
+    pub address: String,
+    /// As returned by `/api/v1/account/:address/export`
+    pub account: AccountExport,
+}
+
+/// Upsert an exported account into the state of a chain with no blocks
+/// yet, e.g. when seeding a new deployment from an old one's exports.
+/// Rejected once the chain has blocks, when the export belongs to another
+/// address, or when it is older than the account already here, so a stale
+/// export can't roll back its nonce.
+pub async fn import_account(
+    State(state): State<Arc<ApiState>>,
+    headers: HeaderMap,
+    Json(request): Json<ImportAccountRequest>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
+    authorize(&state, &headers)?;
+    let owner = parse_address(&request.address)?;
+
+    let account_id = state
+        .sequencer
+        .import_account(owner, request.account)
+        .map_err(|e| {
+            let (code, error, message) = match e {
+                SequencerError::ChainStarted => (
+                    StatusCode::CONFLICT,
+                    "ChainStarted",
+                    "Accounts can only be imported before the first block".to_string(),
+                ),
+                SequencerError::ImportFailed(ImportError::OwnerMismatch) => (
+                    StatusCode::BAD_REQUEST,
+                    "OwnerMismatch",
+                    "The exported account belongs to another address".to_string(),
+                ),
+                SequencerError::ImportFailed(ImportError::StaleNonce { existing, imported }) => (
+                    StatusCode::CONFLICT,
+                    "StaleNonce",
+                    format!(
+                        "Existing nonce {} is higher than the imported nonce {}",
+                        existing, imported
+                    ),
+                ),
+                SequencerError::ImportFailed(ImportError::DealIdTaken(deal_id)) => (
+                    StatusCode::CONFLICT,
+                    "DealIdTaken",
+                    format!("Deal {} already exists with different contents", deal_id),
+                ),
+                other => (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "ImportFailed",
+                    format!("Failed to import account: {:?}", other),
+                ),
+            };
+            (
+                code,
+                Json(ErrorResponse {
+                    error: error.to_string(),
+                    message,
+                }),
+            )
+        })?;
+
+    Ok(Json(serde_json::json!({ "account_id": account_id })))
+}
+
+fn authorize(
+    state: &ApiState,
+    headers: &HeaderMap,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let unauthorized = |message: &str| {
+        (
+            StatusCode::UNAUTHORIZED,
+            Json(ErrorResponse {
+                error: "Unauthorized".to_string(),
+                message: message.to_string(),
+            }),
+        )
+    };
+
+    let Some(ref expected) = state.admin_token else {
+        return Err(unauthorized("Admin endpoints are disabled"));
+    };
+    let presented = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or_else(|| unauthorized("Missing bearer token"))?;
+
+    if bool::from(presented.as_bytes().ct_eq(expected.as_bytes())) {
+        Ok(())
+    } else {
+        Err(unauthorized("Invalid bearer token"))
+    }
+}
+
+fn block_production_error(message: String) -> (StatusCode, Json<ErrorResponse>) {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrorResponse {
+            error: "BlockProductionFailed".to_string(),
+            message,
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zkclear_sequencer::Sequencer;
+    use zkclear_types::{Deposit, SignatureScheme, Tx, TxKind, TxPayload};
+
+    const TOKEN: &str = "s3cret";
+
+    fn api_state() -> Arc<ApiState> {
+        let sequencer = Arc::new(Sequencer::new());
+        Arc::new(ApiState::new(sequencer).with_admin_token(TOKEN))
+    }
+
+    fn bearer(token: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            format!("Bearer {}", token).parse().unwrap(),
+        );
+        headers
+    }
+
+    fn deposit_tx() -> Tx {
+        let owner = [1u8; 20];
+        Tx {
+            id: 0,
+            from: owner,
+            nonce: 0,
+            kind: TxKind::Deposit,
+            payload: TxPayload::Deposit(Deposit {
+                tx_hash: [1u8; 32],
+                account: owner,
+                asset_id: 0,
+                amount: 100,
+                chain_id: zkclear_types::chain_ids::ETHEREUM,
+            }),
+            fee: 0,
+            domain: 0,
+            scheme: SignatureScheme::Secp256k1Recoverable,
+            signature: vec![0u8; 65],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_authorized_flush_builds_block() {
+        let state = api_state();
+        state
+            .sequencer
+            .submit_tx_with_validation(deposit_tx(), false)
+            .unwrap();
+
+        let response = force_build_block(State(state.clone()), bearer(TOKEN), None)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let summary: BlockSummary = serde_json::from_slice(&body).unwrap();
+        assert_eq!(summary.block_id, 0);
+        assert_eq!(summary.transaction_count, 1);
+        assert!(!summary.has_proof);
+        assert_eq!(state.sequencer.queue_length(), 0);
+        assert_eq!(state.sequencer.get_current_block_id(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_flush_rejects_wrong_or_missing_token() {
+        let state = api_state();
+        state
+            .sequencer
+            .submit_tx_with_validation(deposit_tx(), false)
+            .unwrap();
+
+        for headers in [bearer("s3cres"), bearer(""), HeaderMap::new()] {
+            let (code, Json(error)) = force_build_block(State(state.clone()), headers, None)
+                .await
+                .unwrap_err();
+            assert_eq!(code, StatusCode::UNAUTHORIZED);
+            assert_eq!(error.error, "Unauthorized");
+        }
+        assert_eq!(state.sequencer.queue_length(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_exported_account_imports_into_fresh_instance() {
+        use crate::handlers::export_account;
+        use axum::extract::Path;
+
+        let source = api_state();
+        for (nonce, asset_id) in [(0, 0), (1, 1)] {
+            let mut tx = deposit_tx();
+            tx.nonce = nonce;
+            if let TxPayload::Deposit(ref mut d) = tx.payload {
+                d.tx_hash = [asset_id as u8 + 1; 32];
+                d.asset_id = asset_id;
+            }
+            source
+                .sequencer
+                .submit_tx_with_validation(tx, false)
+                .unwrap();
+        }
+        source.sequencer.build_and_execute_block().unwrap();
+
+        let address = format!("0x{}", hex::encode([1u8; 20]));
+        let Json(export) = export_account(State(source.clone()), Path(address.clone()))
+            .await
+            .unwrap();
+        assert_eq!(export.account.balances.len(), 2);
+
+        let target = api_state();
+        let request = |export| {
+            Json(ImportAccountRequest {
+                address: address.clone(),
+                account: export,
+            })
+        };
+        let (code, _) = import_account(
+            State(target.clone()),
+            HeaderMap::new(),
+            request(export.clone()),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(code, StatusCode::UNAUTHORIZED);
+
+        let Json(imported) = import_account(
+            State(target.clone()),
+            bearer(TOKEN),
+            request(export.clone()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(imported["account_id"], 0);
+        let Json(reexported) = export_account(State(target.clone()), Path(address.clone()))
+            .await
+            .unwrap();
+        assert_eq!(reexported, export);
+
+        // The source chain already has blocks
+        let (code, Json(error)) =
+            import_account(State(source), bearer(TOKEN), request(export.clone()))
+                .await
+                .unwrap_err();
+        assert_eq!(code, StatusCode::CONFLICT);
+        assert_eq!(error.error, "ChainStarted");
+
+        // An older export can't roll the nonce back
+        let mut stale = export;
+        stale.account.nonce = 1;
+        let (code, Json(error)) = import_account(State(target), bearer(TOKEN), request(stale))
+            .await
+            .unwrap_err();
+        assert_eq!(code, StatusCode::CONFLICT);
+        assert_eq!(error.error, "StaleNonce");
+    }
+
+    #[tokio::test]
+    async fn test_flush_with_empty_queue_is_no_content() {
+        let response = force_build_block(
+            State(api_state()),
+            bearer(TOKEN),
+            Some(Json(BuildBlockRequest {
+                generate_proof: true,
+            })),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+}
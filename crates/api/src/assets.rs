@@ -0,0 +1,161 @@
+use zkclear_state::State;
+use zkclear_types::{AssetId, ChainId, Deal};
+
+/// Decimals of the assets registered in state, used to render raw integer
+/// amounts as decimal strings. An asset can have different decimals on
+/// different chains, so every lookup names the chain.
+#[derive(Debug, Clone, Copy)]
+pub struct AssetDecimals<'a> {
+    state: &'a State,
+}
+
+impl<'a> AssetDecimals<'a> {
+    pub fn new(state: &'a State) -> Self {
+        Self { state }
+    }
+
+    pub fn decimals(&self, asset_id: AssetId, chain_id: ChainId) -> Option<u8> {
+        self.state
+            .get_asset(asset_id, chain_id)
+            .map(|asset| asset.decimals)
+    }
+
+    /// Format a raw amount of `asset_id` on `chain_id`, or `None` if the
+    /// asset is not registered there
+    pub fn format_amount(
+        &self,
+        asset_id: AssetId,
+        chain_id: ChainId,
+        amount: u128,
+    ) -> Option<String> {
+        self.decimals(asset_id, chain_id)
+            .map(|decimals| format_units(amount, decimals as u32))
+    }
+
+    /// Format a deal's raw `price_quote_per_base` as quote tokens per whole
+    /// base token
+    pub fn format_price(&self, deal: &Deal) -> Option<String> {
+        let base = self.decimals(deal.asset_base, deal.chain_id_base)? as u32;
+        let quote = self.decimals(deal.asset_quote, deal.chain_id_quote)? as u32;
+        let price_quote_per_base = deal.price_quote_per_base;
+
+        // price * 10^base / 10^quote
+        if base >= quote {
+            let zeros = if price_quote_per_base == 0 {
+                0
+            } else {
+                base - quote
+            };
+            Some(format!(
+                "{}{}",
+                price_quote_per_base,
+                "0".repeat(zeros as usize)
+            ))
+        } else {
+            Some(format_units(price_quote_per_base, quote - base))
+        }
+    }
+}
+
+/// Render `amount / 10^decimals` as a decimal string without trailing zeros
+pub fn format_units(amount: u128, decimals: u32) -> String {
+    if decimals == 0 {
+        return amount.to_string();
+    }
+
+    let digits = format!("{:0>width$}", amount, width = decimals as usize + 1);
+    let (int_part, frac_part) = digits.split_at(digits.len() - decimals as usize);
+    let frac_part = frac_part.trim_end_matches('0');
+
+    if frac_part.is_empty() {
+        int_part.to_string()
+    } else {
+        format!("{}.{}", int_part, frac_part)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zkclear_types::{Asset, DealStatus, DealVisibility};
+
+    fn register_asset(state: &mut State, id: AssetId, chain_id: ChainId, decimals: u8) {
+        state.register_asset(Asset {
+            id,
+            symbol: format!("ASSET{}", id),
+            decimals,
+            chain_id,
+            contract_address: None,
+            is_wrapped: false,
+            original_chain_id: None,
+        });
+    }
+
+    fn deal(asset_base: AssetId, asset_quote: AssetId, price_quote_per_base: u128) -> Deal {
+        Deal {
+            id: 1,
+            maker: [1u8; 20],
+            taker: None,
+            visibility: DealVisibility::Public,
+            asset_base,
+            asset_quote,
+            chain_id_base: 1,
+            chain_id_quote: 1,
+            amount_base: 1,
+            amount_remaining: 1,
+            price_quote_per_base,
+            status: DealStatus::Pending,
+            created_at: 0,
+            updated_at: 0,
+            expires_at: None,
+            external_ref: None,
+            is_cross_chain: false,
+            escrows: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_format_units() {
+        assert_eq!(format_units(1_500_000, 6), "1.5");
+        assert_eq!(format_units(1_000_000, 6), "1");
+        assert_eq!(format_units(1, 6), "0.000001");
+        assert_eq!(format_units(0, 6), "0");
+        assert_eq!(format_units(42, 0), "42");
+        assert_eq!(
+            format_units(u128::MAX, 18),
+            "340282366920938463463.374607431768211455"
+        );
+    }
+
+    #[test]
+    fn test_format_price_scales_by_both_decimals() {
+        let mut state = State::new();
+        register_asset(&mut state, 0, 1, 8);
+        register_asset(&mut state, 1, 1, 6);
+        let decimals = AssetDecimals::new(&state);
+
+        // BTC with 8 decimals quoted in USDC with 6 decimals:
+        // 1 sat costs 650 micro-USDC => 1 BTC costs 65,000 USDC
+        assert_eq!(decimals.format_price(&deal(0, 1, 650)).unwrap(), "65000");
+        // The inverse pair: 1 micro-USDC costs 2 sats => 1 USDC costs 0.02 BTC
+        assert_eq!(decimals.format_price(&deal(1, 0, 2)).unwrap(), "0.02");
+        assert!(decimals.format_price(&deal(0, 2, 650)).is_none());
+    }
+
+    #[test]
+    fn test_decimals_are_per_chain() {
+        let mut state = State::new();
+        register_asset(&mut state, 1, 1, 6);
+        register_asset(&mut state, 1, 56, 18);
+        let decimals = AssetDecimals::new(&state);
+
+        assert_eq!(decimals.format_amount(1, 1, 1_500_000).unwrap(), "1.5");
+        assert_eq!(
+            decimals
+                .format_amount(1, 56, 1_500_000_000_000_000_000)
+                .unwrap(),
+            "1.5"
+        );
+        assert!(decimals.format_amount(1, 137, 1).is_none());
+    }
+}
@@ -1,27 +1,92 @@
 use axum::{
     extract::{Path, Query, State},
-    http::StatusCode,
-    response::Json,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
 };
 use std::collections::HashMap;
-use zkclear_types::{DealVisibility, TxKind, TxPayload};
 use std::sync::Arc;
-use zkclear_sequencer::Sequencer;
+use zkclear_sequencer::{Sequencer, StfError, SubmitOutcome};
+use zkclear_state::AccountExport;
 use zkclear_storage::Storage;
-use zkclear_types::{AssetId, BlockId, DealId};
+use zkclear_types::{Address, AssetId, BlockId, Deal, DealId, DealStatus};
+use zkclear_types::{DealVisibility, SignatureScheme, TxKind, TxPayload};
 
+use crate::assets::{format_units, AssetDecimals};
 use crate::types::*;
+use zkclear_sequencer::envelope::{decode_tx, encode_tx_envelope, EnvelopeError};
+use zkclear_sequencer::fee::default_tx_size;
 use zkclear_sequencer::security::{sanitize_string, validate_hex_string};
 
 pub struct ApiState {
     pub sequencer: Arc<Sequencer>,
     pub storage: Option<Arc<dyn Storage>>,
+    /// Prover attached to the sequencer, reported by the readiness check
+    pub prover: Option<Arc<zkclear_prover::Prover>>,
     pub rate_limit_state: Option<Arc<crate::middleware::RateLimitState>>,
+    /// Registry the sequencer records into, rendered at `/metrics`
+    pub metrics: Arc<zkclear_sequencer::metrics::Metrics>,
+    /// Bearer token required by `/admin` endpoints; `None` disables them
+    pub admin_token: Option<String>,
+    /// Serve queries only: tx submission and admin writes are answered
+    /// with 405, as a replica builds no blocks to put the txs in
+    pub read_only: bool,
+}
+
+impl ApiState {
+    /// State serving `sequencer` and its metrics, with no storage, prover,
+    /// rate limiting or admin token
+    pub fn new(sequencer: Arc<Sequencer>) -> Self {
+        Self {
+            metrics: sequencer.metrics(),
+            sequencer,
+            storage: None,
+            prover: None,
+            rate_limit_state: None,
+            admin_token: None,
+            read_only: false,
+        }
+    }
+
+    pub fn with_storage(mut self, storage: Arc<dyn Storage>) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    pub fn with_prover(mut self, prover: Arc<zkclear_prover::Prover>) -> Self {
+        self.prover = Some(prover);
+        self
+    }
+
+    pub fn with_rate_limit_state(
+        mut self,
+        rate_limit_state: Arc<crate::middleware::RateLimitState>,
+    ) -> Self {
+        self.rate_limit_state = Some(rate_limit_state);
+        self
+    }
+
+    pub fn with_admin_token(mut self, admin_token: impl Into<String>) -> Self {
+        self.admin_token = Some(admin_token.into());
+        self
+    }
+
+    pub fn with_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+}
+
+/// Whether the client asked for decimal-formatted amounts via `?format=decimal`
+fn decimal_format_requested(params: &HashMap<String, String>) -> bool {
+    params
+        .get("format")
+        .is_some_and(|f| f.eq_ignore_ascii_case("decimal"))
 }
 
 pub async fn get_account_balance(
     State(state): State<Arc<ApiState>>,
     Path((address, asset_id)): Path<(String, AssetId)>,
+    Query(params): Query<HashMap<String, String>>,
 ) -> Result<Json<AccountBalanceResponse>, (StatusCode, Json<ErrorResponse>)> {
     // Sanitize and validate input
     let sanitized_address = sanitize_string(&address);
@@ -72,24 +137,138 @@ pub async fn get_account_balance(
         )
     })?;
 
-    let balance = account
+    // An asset held on several chains has one balance per chain: pick one
+    // with `?chain_id=` rather than silently reporting whichever comes first
+    let requested_chain = params
+        .get("chain_id")
+        .map(|chain_id| {
+            chain_id.parse::<zkclear_types::ChainId>().map_err(|_| {
+                (
+                    StatusCode::BAD_REQUEST,
+                    Json(ErrorResponse {
+                        error: "InvalidChainId".to_string(),
+                        message: format!("Invalid chain_id: {}", chain_id),
+                    }),
+                )
+            })
+        })
+        .transpose()?;
+
+    let mut held = account
         .balances
         .iter()
-        .find(|b| b.asset_id == asset_id)
-        .map(|b| (b.chain_id, b.amount))
-        .unwrap_or((zkclear_types::chain_ids::ETHEREUM, 0));
+        .filter(|b| b.asset_id == asset_id)
+        .filter(|b| requested_chain.is_none_or(|chain_id| b.chain_id == chain_id));
+    let balance = match (held.next(), held.next()) {
+        (Some(b), None) => (b.chain_id, b.amount),
+        (None, _) => (
+            requested_chain.unwrap_or(zkclear_types::chain_ids::ETHEREUM),
+            0,
+        ),
+        (Some(_), Some(_)) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse {
+                    error: "AmbiguousChain".to_string(),
+                    message: format!(
+                        "Asset {} is held on several chains; pass ?chain_id= or use /api/v1/account/:address/balance/{}/all",
+                        asset_id, asset_id
+                    ),
+                }),
+            ))
+        }
+    };
+
+    let amount_formatted = if decimal_format_requested(&params) {
+        AssetDecimals::new(&state_guard).format_amount(asset_id, balance.0, balance.1)
+    } else {
+        None
+    };
 
     Ok(Json(AccountBalanceResponse {
         address: addr,
         asset_id,
         chain_id: balance.0,
         amount: balance.1,
+        amount_formatted,
+    }))
+}
+
+/// Every chain's balance of one asset, plus the total across chains
+pub async fn get_account_asset_balances(
+    State(state): State<Arc<ApiState>>,
+    Path((address, asset_id)): Path<(String, AssetId)>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<AssetBalancesResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let addr = parse_address(&sanitize_string(&address))?;
+
+    let state_handle = state.sequencer.get_state();
+    let state_guard = state_handle.lock().unwrap();
+
+    let account = state_guard.get_account_by_address(addr).ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "AccountNotFound".to_string(),
+                message: "Account not found".to_string(),
+            }),
+        )
+    })?;
+
+    let mut held: Vec<(zkclear_types::ChainId, u128)> = account
+        .balances
+        .iter()
+        .filter(|b| b.asset_id == asset_id)
+        .map(|b| (b.chain_id, b.amount))
+        .collect();
+    held.sort_unstable();
+
+    let total = held
+        .iter()
+        .try_fold(0u128, |total, (_, amount)| total.checked_add(*amount))
+        .ok_or_else(|| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: "BalanceOverflow".to_string(),
+                    message: format!("Total of asset {} overflows", asset_id),
+                }),
+            )
+        })?;
+
+    let decimals = decimal_format_requested(&params).then(|| AssetDecimals::new(&state_guard));
+    let balances: Vec<BalanceInfo> = held
+        .into_iter()
+        .map(|(chain_id, amount)| BalanceInfo {
+            asset_id,
+            chain_id,
+            amount,
+            amount_formatted: decimals.and_then(|d| d.format_amount(asset_id, chain_id, amount)),
+        })
+        .collect();
+
+    // The total is only formatted when every chain agrees on the decimals
+    let total_decimals = decimals.and_then(|d| {
+        let mut chain_decimals = balances.iter().map(|b| d.decimals(asset_id, b.chain_id));
+        let first = chain_decimals.next().flatten()?;
+        chain_decimals
+            .all(|other| other == Some(first))
+            .then_some(first)
+    });
+
+    Ok(Json(AssetBalancesResponse {
+        address: addr,
+        asset_id,
+        balances,
+        total,
+        total_formatted: total_decimals.map(|decimals| format_units(total, decimals as u32)),
     }))
 }
 
 pub async fn get_account_state(
     State(state): State<Arc<ApiState>>,
     Path(address): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
 ) -> Result<Json<AccountStateResponse>, (StatusCode, Json<ErrorResponse>)> {
     let address_bytes = hex::decode(address.trim_start_matches("0x")).map_err(|_| {
         (
@@ -119,102 +298,95 @@ pub async fn get_account_state(
 
     // Create account automatically if it doesn't exist (on first login/request)
     // This matches the behavior of get_or_create_account_by_owner used in transactions
-    let account = state_guard.get_or_create_account_by_owner(addr);
-    
-    // Extract account data before releasing the mutable borrow
+    let account = state_guard.get_or_create_account_by_owner(addr).clone();
+    let decimals = decimal_format_requested(&params).then(|| AssetDecimals::new(&state_guard));
+
     let account_id = account.id;
     let nonce = account.nonce;
-    let balances: Vec<BalanceInfo> = account
-        .balances
-        .iter()
-        .map(|b| BalanceInfo {
-            asset_id: b.asset_id,
-            chain_id: b.chain_id,
-            amount: b.amount,
-        })
-        .collect();
+    let balance_info = |b: zkclear_types::Balance| BalanceInfo {
+        asset_id: b.asset_id,
+        chain_id: b.chain_id,
+        amount: b.amount,
+        amount_formatted: decimals
+            .and_then(|d| d.format_amount(b.asset_id, b.chain_id, b.amount)),
+    };
+    let balances: Vec<BalanceInfo> = account.balances.iter().map(balance_info).collect();
+    let reserved: Vec<BalanceInfo> = account.reserved.iter().map(balance_info).collect();
     
-    // Now we can use immutable borrow for deals
-    let open_deals: Vec<DealId> = state_guard
-        .deals
-        .values()
-        .filter(|deal| {
-            (deal.maker == addr || deal.taker == Some(addr))
-                && matches!(deal.status, zkclear_types::DealStatus::Pending)
-        })
-        .map(|deal| deal.id)
-        .collect();
+    // Now we can use immutable borrow for deals. Deals are only swept to
+    // `Expired` by the next block, so pending ones past their expiry at
+    // the last block's time count as expired already.
+    let now = state.sequencer.last_block_timestamp();
+    let mut open_deals = Vec::new();
+    let mut expired_deals = Vec::new();
+    for deal in state_guard.deals.values() {
+        if deal.maker != addr && deal.taker != Some(addr) {
+            continue;
+        }
+        match deal.status {
+            DealStatus::Pending if deal.is_expired_at(now) => expired_deals.push(deal.id),
+            DealStatus::Pending | DealStatus::Settling => open_deals.push(deal.id),
+            DealStatus::Expired => expired_deals.push(deal.id),
+            DealStatus::Settled | DealStatus::Cancelled => {}
+        }
+    }
+    open_deals.sort();
+    expired_deals.sort();
 
     Ok(Json(AccountStateResponse {
         address: addr,
         account_id,
         balances,
+        reserved,
         nonce,
         open_deals,
+        expired_deals,
     }))
 }
 
+/// Page size of `get_deals_list` when `limit` is not given
+const DEFAULT_DEALS_PAGE_LIMIT: usize = 100;
+/// Largest page `get_deals_list` will return, whatever `limit` asks for
+const MAX_DEALS_PAGE_LIMIT: usize = 1000;
+
+/// Deals in ascending id order, paged with `limit` and `after` (the last
+/// deal id of the previous page). Follow `next_cursor` until it is null.
 pub async fn get_deals_list(
     State(state): State<Arc<ApiState>>,
     Query(params): Query<HashMap<String, String>>,
 ) -> Result<Json<DealListResponse>, (StatusCode, Json<ErrorResponse>)> {
-    let state_handle = state.sequencer.get_state();
-    let state_guard = state_handle.lock().unwrap();
-
-    let mut deals: Vec<DealDetailsResponse> = state_guard
-        .deals
-        .values()
-        .map(|deal| DealDetailsResponse {
-            deal_id: deal.id,
-            maker: deal.maker,
-            taker: deal.taker,
-            asset_base: deal.asset_base,
-            asset_quote: deal.asset_quote,
-            chain_id_base: deal.chain_id_base,
-            chain_id_quote: deal.chain_id_quote,
-            amount_base: deal.amount_base,
-            amount_remaining: deal.amount_remaining,
-            price_quote_per_base: deal.price_quote_per_base,
-            status: format!("{:?}", deal.status),
-            created_at: deal.created_at,
-            expires_at: deal.expires_at,
-            is_cross_chain: deal.is_cross_chain,
-        })
-        .collect();
+    let limit = match params.get("limit") {
+        Some(limit) => limit
+            .parse::<usize>()
+            .map_err(|_| invalid_query("InvalidLimit", "limit must be a positive integer"))?
+            .clamp(1, MAX_DEALS_PAGE_LIMIT),
+        None => DEFAULT_DEALS_PAGE_LIMIT,
+    };
+    let after = params
+        .get("after")
+        .map(|after| after.parse::<DealId>())
+        .transpose()
+        .map_err(|_| invalid_query("InvalidCursor", "after must be a deal id"))?;
 
     // Filter by status if provided
-    if let Some(status_filter) = params.get("status") {
-        let status_str = status_filter.to_lowercase();
-        deals.retain(|deal| deal.status.to_lowercase() == status_str);
-    }
+    let status_filter = params.get("status").map(|status| status.to_lowercase());
 
     // Filter by address (maker or taker) if provided
-    if let Some(address_filter) = params.get("address") {
-        let address_bytes = hex::decode(address_filter.trim_start_matches("0x")).map_err(|_| {
-            (
-                StatusCode::BAD_REQUEST,
-                Json(ErrorResponse {
-                    error: "InvalidAddress".to_string(),
-                    message: "Invalid address format".to_string(),
-                }),
-            )
-        })?;
-
-        if address_bytes.len() != 20 {
-            return Err((
-                StatusCode::BAD_REQUEST,
-                Json(ErrorResponse {
-                    error: "InvalidAddress".to_string(),
-                    message: "Address must be 20 bytes".to_string(),
-                }),
-            ));
-        }
+    let address_filter = match params.get("address") {
+        Some(address_filter) => {
+            let address_bytes = hex::decode(address_filter.trim_start_matches("0x"))
+                .map_err(|_| invalid_query("InvalidAddress", "Invalid address format"))?;
 
-        let mut addr = [0u8; 20];
-        addr.copy_from_slice(&address_bytes);
+            if address_bytes.len() != 20 {
+                return Err(invalid_query("InvalidAddress", "Address must be 20 bytes"));
+            }
 
-        deals.retain(|deal| deal.maker == addr || deal.taker == Some(addr));
-    }
+            let mut addr = [0u8; 20];
+            addr.copy_from_slice(&address_bytes);
+            Some(addr)
+        }
+        None => None,
+    };
 
     // Filter by visibility if provided
     if let Some(visibility_filter) = params.get("visibility") {
@@ -224,14 +396,60 @@ pub async fn get_deals_list(
         // This is a limitation we can address later if needed
     }
 
-    let total = deals.len();
+    // Full deal scans are served from the read snapshot to avoid
+    // contending with block production on the live state lock
+    let snapshot = state.sequencer.get_read_snapshot();
+    let decimals = decimal_format_requested(&params).then(|| AssetDecimals::new(&snapshot.state));
+
+    let mut matching: Vec<&Deal> = snapshot
+        .state
+        .deals
+        .values()
+        .filter(|deal| {
+            status_filter
+                .as_ref()
+                .is_none_or(|status| format!("{:?}", deal.status).to_lowercase() == *status)
+        })
+        .filter(|deal| {
+            address_filter.is_none_or(|addr| deal.maker == addr || deal.taker == Some(addr))
+        })
+        .collect();
+    matching.sort_unstable_by_key(|deal| deal.id);
+
+    let total = matching.len();
+    let start = after.map_or(0, |after| matching.partition_point(|deal| deal.id <= after));
+    let page = &matching[start..(start + limit).min(total)];
+    let next_cursor = if start + page.len() < total {
+        page.last().map(|deal| deal.id)
+    } else {
+        None
+    };
+
+    Ok(Json(DealListResponse {
+        deals: page
+            .iter()
+            .map(|deal| deal_details_response(deal, decimals))
+            .collect(),
+        total,
+        next_cursor,
+        snapshot_block_id: snapshot.block_id,
+    }))
+}
 
-    Ok(Json(DealListResponse { deals, total }))
+fn invalid_query(error: &str, message: &str) -> (StatusCode, Json<ErrorResponse>) {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(ErrorResponse {
+            error: error.to_string(),
+            message: message.to_string(),
+        }),
+    )
 }
 
 pub async fn get_deal_details(
     State(state): State<Arc<ApiState>>,
     Path(deal_id): Path<DealId>,
+    Query(params): Query<HashMap<String, String>>,
 ) -> Result<Json<DealDetailsResponse>, (StatusCode, Json<ErrorResponse>)> {
     let state_handle = state.sequencer.get_state();
     let state_guard = state_handle.lock().unwrap();
@@ -246,7 +464,236 @@ pub async fn get_deal_details(
         )
     })?;
 
-    Ok(Json(DealDetailsResponse {
+    let decimals = decimal_format_requested(&params).then(|| AssetDecimals::new(&state_guard));
+    Ok(Json(deal_details_response(deal, decimals)))
+}
+
+pub async fn get_deal_fills(
+    State(state): State<Arc<ApiState>>,
+    Path(deal_id): Path<DealId>,
+) -> Result<Json<DealFillsResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let state_handle = state.sequencer.get_state();
+    let state_guard = state_handle.lock().unwrap();
+
+    if state_guard.get_deal(deal_id).is_none() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "DealNotFound".to_string(),
+                message: format!("Deal {} not found", deal_id),
+            }),
+        ));
+    }
+
+    let fills = state_guard
+        .get_deal_fills(deal_id)
+        .iter()
+        .map(|fill| FillResponse {
+            taker: fill.taker,
+            amount_base: fill.amount_base,
+            amount_quote: fill.amount_quote,
+            block_timestamp: fill.block_timestamp,
+        })
+        .collect();
+
+    Ok(Json(DealFillsResponse { deal_id, fills }))
+}
+
+/// Full state of one account, including its open deals, in the form
+/// `/admin/account/import` accepts
+pub async fn export_account(
+    State(state): State<Arc<ApiState>>,
+    Path(address): Path<String>,
+) -> Result<Json<AccountExport>, (StatusCode, Json<ErrorResponse>)> {
+    let addr = parse_address(&address)?;
+
+    let state_handle = state.sequencer.get_state();
+    let state_guard = state_handle.lock().unwrap();
+    let export = state_guard.export_account(addr).ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "AccountNotFound".to_string(),
+                message: format!("No account for address {}", sanitize_string(&address)),
+            }),
+        )
+    })?;
+
+    Ok(Json(export))
+}
+
+/// Nonce to sign the account's next tx with, counting its queued txs.
+/// Unknown accounts get 0 rather than a 404.
+pub async fn get_account_nonce(
+    State(state): State<Arc<ApiState>>,
+    Path(address): Path<String>,
+) -> Result<Json<NonceResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let address = parse_address(&address)?;
+    Ok(Json(NonceResponse {
+        address,
+        nonce: state.sequencer.next_nonce(address),
+    }))
+}
+
+/// Parse a `0x`-prefixed or bare hex address
+pub(crate) fn parse_address(address: &str) -> Result<Address, (StatusCode, Json<ErrorResponse>)> {
+    let invalid = |message: &str| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "InvalidAddress".to_string(),
+                message: message.to_string(),
+            }),
+        )
+    };
+
+    let bytes = hex::decode(address.trim_start_matches("0x"))
+        .map_err(|_| invalid("Invalid address format"))?;
+    bytes
+        .try_into()
+        .map_err(|_| invalid("Address must be 20 bytes"))
+}
+
+/// Deal `address` created with `external_ref`; refs are scoped to their maker
+pub async fn get_deal_by_external_ref(
+    State(state): State<Arc<ApiState>>,
+    Path((address, external_ref)): Path<(String, String)>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<DealDetailsResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let maker = parse_address(&sanitize_string(&address))?;
+
+    let state_handle = state.sequencer.get_state();
+    let state_guard = state_handle.lock().unwrap();
+
+    let deal = state_guard
+        .get_deal_by_external_ref(maker, &external_ref)
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse {
+                    error: "DealNotFound".to_string(),
+                    message: format!(
+                        "No deal with external ref {}",
+                        sanitize_string(&external_ref)
+                    ),
+                }),
+            )
+        })?;
+
+    let decimals = decimal_format_requested(&params).then(|| AssetDecimals::new(&state_guard));
+    Ok(Json(deal_details_response(deal, decimals)))
+}
+
+/// Cheapest public pending deal a taker could accept on the pair given by
+/// `asset_base`, `asset_quote`, `chain_id_base` and `chain_id_quote`, with at
+/// least `min_amount` of base left (default 1) and a price of at most
+/// `max_price` (default unbounded)
+pub async fn get_matching_deal(
+    State(state): State<Arc<ApiState>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<DealDetailsResponse>, (StatusCode, Json<ErrorResponse>)> {
+    fn param<T: std::str::FromStr>(
+        params: &HashMap<String, String>,
+        name: &str,
+        default: Option<T>,
+    ) -> Result<T, (StatusCode, Json<ErrorResponse>)> {
+        number_param(params, name, default, "InvalidMatchQuery")
+    }
+
+    let pair = (
+        param(&params, "asset_base", None)?,
+        param(&params, "asset_quote", None)?,
+        param(&params, "chain_id_base", None)?,
+        param(&params, "chain_id_quote", None)?,
+    );
+    let min_amount = param(&params, "min_amount", Some(1))?;
+    let max_price = param(&params, "max_price", Some(u128::MAX))?;
+
+    let state_handle = state.sequencer.get_state();
+    let state_guard = state_handle.lock().unwrap();
+    let now = state.sequencer.last_block_timestamp();
+
+    let deal = state_guard
+        .find_matching_deal(pair, min_amount, max_price, now)
+        .and_then(|deal_id| state_guard.get_deal(deal_id))
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse {
+                    error: "NoMatchingDeal".to_string(),
+                    message: "No open public deal matches the pair, amount and price".to_string(),
+                }),
+            )
+        })?;
+
+    let decimals = decimal_format_requested(&params).then(|| AssetDecimals::new(&state_guard));
+    Ok(Json(deal_details_response(deal, decimals)))
+}
+
+/// Aggregated order book of a pair: one level per price, cheapest first
+pub async fn get_book_depth(
+    State(state): State<Arc<ApiState>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<BookDepthResponse>, (StatusCode, Json<ErrorResponse>)> {
+    fn param<T: std::str::FromStr>(
+        params: &HashMap<String, String>,
+        name: &str,
+    ) -> Result<T, (StatusCode, Json<ErrorResponse>)> {
+        number_param(params, name, None, "InvalidBookQuery")
+    }
+
+    let asset_base = param(&params, "asset_base")?;
+    let asset_quote = param(&params, "asset_quote")?;
+    let chain_id_base = param(&params, "chain_base")?;
+    let chain_id_quote = param(&params, "chain_quote")?;
+
+    let levels = {
+        let state_handle = state.sequencer.get_state();
+        let state_guard = state_handle.lock().unwrap();
+        state_guard.book_depth(
+            (asset_base, asset_quote, chain_id_base, chain_id_quote),
+            state.sequencer.last_block_timestamp(),
+        )
+    };
+
+    Ok(Json(BookDepthResponse {
+        asset_base,
+        asset_quote,
+        chain_id_base,
+        chain_id_quote,
+        levels: levels
+            .into_iter()
+            .map(|(price_quote_per_base, amount_remaining)| BookLevel {
+                price_quote_per_base,
+                amount_remaining,
+            })
+            .collect(),
+    }))
+}
+
+/// Required numeric query parameter `name`, or `default` when it is absent
+fn number_param<T: std::str::FromStr>(
+    params: &HashMap<String, String>,
+    name: &str,
+    default: Option<T>,
+    error: &str,
+) -> Result<T, (StatusCode, Json<ErrorResponse>)> {
+    match (params.get(name), default) {
+        (Some(value), _) => value
+            .parse()
+            .map_err(|_| invalid_query(error, &format!("{} must be a number", name))),
+        (None, Some(default)) => Ok(default),
+        (None, None) => Err(invalid_query(error, &format!("{} is required", name))),
+    }
+}
+
+/// Build the API view of a deal; formatted fields are filled in when
+/// `decimals` is given and both assets are registered on the deal's chains
+pub(crate) fn deal_details_response(
+    deal: &Deal,
+    decimals: Option<AssetDecimals>,
+) -> DealDetailsResponse {
+    DealDetailsResponse {
         deal_id: deal.id,
         maker: deal.maker,
         taker: deal.taker,
@@ -259,45 +706,25 @@ pub async fn get_deal_details(
         price_quote_per_base: deal.price_quote_per_base,
         status: format!("{:?}", deal.status),
         created_at: deal.created_at,
+        updated_at: deal.updated_at,
         expires_at: deal.expires_at,
+        external_ref: deal.external_ref.clone(),
         is_cross_chain: deal.is_cross_chain,
-    }))
+        amount_base_formatted: decimals.and_then(|d| {
+            d.format_amount(deal.asset_base, deal.chain_id_base, deal.amount_base)
+        }),
+        amount_remaining_formatted: decimals.and_then(|d| {
+            d.format_amount(deal.asset_base, deal.chain_id_base, deal.amount_remaining)
+        }),
+        price_quote_per_base_formatted: decimals.and_then(|d| d.format_price(deal)),
+    }
 }
 
 pub async fn get_block_info(
     State(state): State<Arc<ApiState>>,
     Path(block_id): Path<BlockId>,
 ) -> Result<Json<BlockInfoResponse>, (StatusCode, Json<ErrorResponse>)> {
-    let block = if let Some(ref storage) = state.storage {
-        storage
-            .get_block(block_id)
-            .map_err(|_| {
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ErrorResponse {
-                        error: "StorageError".to_string(),
-                        message: "Failed to load block from storage".to_string(),
-                    }),
-                )
-            })?
-            .ok_or_else(|| {
-                (
-                    StatusCode::NOT_FOUND,
-                    Json(ErrorResponse {
-                        error: "BlockNotFound".to_string(),
-                        message: format!("Block {} not found", block_id),
-                    }),
-                )
-            })?
-    } else {
-        return Err((
-            StatusCode::SERVICE_UNAVAILABLE,
-            Json(ErrorResponse {
-                error: "StorageNotAvailable".to_string(),
-                message: "Storage not configured".to_string(),
-            }),
-        ));
-    };
+    let block = load_block(&state, block_id)?;
 
     let transactions: Vec<TransactionInfo> = block
         .transactions
@@ -318,14 +745,506 @@ pub async fn get_block_info(
     }))
 }
 
+/// Page size of `get_blocks_list` when `limit` is not given
+const DEFAULT_BLOCKS_PAGE_LIMIT: usize = 20;
+/// Largest page `get_blocks_list` will return, whatever `limit` asks for
+const MAX_BLOCKS_PAGE_LIMIT: usize = 100;
+
+/// Summaries of stored blocks with ids in `from..=to`, at most `limit` of
+/// them. Newest first unless `order=asc`, so without parameters this is
+/// the latest page of blocks; `to` defaults to the latest block.
+pub async fn get_blocks_list(
+    State(state): State<Arc<ApiState>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<BlockListResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let Some(ref storage) = state.storage else {
+        return Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ErrorResponse {
+                error: "StorageNotAvailable".to_string(),
+                message: "Storage not configured".to_string(),
+            }),
+        ));
+    };
+
+    let limit = match params.get("limit") {
+        Some(limit) => limit
+            .parse::<usize>()
+            .map_err(|_| invalid_query("InvalidLimit", "limit must be a positive integer"))?
+            .clamp(1, MAX_BLOCKS_PAGE_LIMIT),
+        None => DEFAULT_BLOCKS_PAGE_LIMIT,
+    };
+    let block_id_param = |name: &str| {
+        params
+            .get(name)
+            .map(|id| id.parse::<BlockId>())
+            .transpose()
+            .map_err(|_| invalid_query("InvalidRange", &format!("{} must be a block id", name)))
+    };
+    // The sequencer numbers stored blocks from 1
+    let from = block_id_param("from")?.unwrap_or(1).max(1);
+    let to = block_id_param("to")?;
+    let ascending = match params.get("order").map(|order| order.to_lowercase()) {
+        None => false,
+        Some(order) if order == "desc" => false,
+        Some(order) if order == "asc" => true,
+        Some(_) => return Err(invalid_query("InvalidOrder", "order must be asc or desc")),
+    };
+
+    let storage_error = |_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: "StorageError".to_string(),
+                message: "Failed to load blocks from storage".to_string(),
+            }),
+        )
+    };
+    let latest_block_id = storage.get_latest_block_id().map_err(storage_error)?;
+
+    let mut blocks = Vec::new();
+    if let Some(latest) = latest_block_id {
+        let to = to.map_or(latest, |to| to.min(latest));
+        if from <= to {
+            // Only the `limit` blocks at the requested end of the range
+            let span = limit as BlockId - 1;
+            let (first, last) = if ascending {
+                (from, to.min(from.saturating_add(span)))
+            } else {
+                (from.max(to.saturating_sub(span)), to)
+            };
+            for block in storage.iter_blocks(first, last) {
+                let block = block.map_err(storage_error)?;
+                blocks.push(BlockSummary {
+                    block_id: block.id,
+                    timestamp: block.timestamp,
+                    transaction_count: block.transactions.len(),
+                    state_root: format!("0x{}", hex::encode(block.state_root)),
+                    has_proof: !block.block_proof.is_empty(),
+                });
+            }
+        }
+    }
+    if !ascending {
+        blocks.reverse();
+    }
+
+    Ok(Json(BlockListResponse {
+        blocks,
+        latest_block_id,
+    }))
+}
+
+/// Block proof with the roots it commits to. Answers 204 No Content when
+/// the block was built without a prover (placeholder mode).
+pub async fn get_block_proof(
+    State(state): State<Arc<ApiState>>,
+    Path(block_id): Path<BlockId>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let block = load_block(&state, block_id)?;
+
+    if block.block_proof.is_empty() {
+        return Ok(StatusCode::NO_CONTENT.into_response());
+    }
+
+    Ok(Json(BlockProofResponse {
+        block_id: block.id,
+        state_root: format!("0x{}", hex::encode(block.state_root)),
+        withdrawals_root: format!("0x{}", hex::encode(block.withdrawals_root)),
+        proof_hex: format!("0x{}", hex::encode(&block.block_proof)),
+        proof_len: block.block_proof.len(),
+    })
+    .into_response())
+}
+
+/// Accounts and deals changed by a block, with their values after it
+pub async fn get_block_diff(
+    State(state): State<Arc<ApiState>>,
+    Path(block_id): Path<BlockId>,
+) -> Result<Json<BlockDiffResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let Some(ref storage) = state.storage else {
+        return Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ErrorResponse {
+                error: "StorageNotAvailable".to_string(),
+                message: "Storage not configured".to_string(),
+            }),
+        ));
+    };
+
+    let diff = storage
+        .get_state_diff(block_id)
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: "StorageError".to_string(),
+                    message: "Failed to load state diff from storage".to_string(),
+                }),
+            )
+        })?
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse {
+                    error: "DiffNotFound".to_string(),
+                    message: format!("No state diff recorded for block {}", block_id),
+                }),
+            )
+        })?;
+
+    let balance_info = |b: zkclear_types::Balance| BalanceInfo {
+        asset_id: b.asset_id,
+        chain_id: b.chain_id,
+        amount: b.amount,
+        amount_formatted: None,
+    };
+
+    Ok(Json(BlockDiffResponse {
+        block_id,
+        accounts: diff
+            .accounts
+            .iter()
+            .map(|account| AccountDiff {
+                account_id: account.id,
+                address: account.owner,
+                balances: account.balances.iter().map(balance_info).collect(),
+                reserved: account.reserved.iter().map(balance_info).collect(),
+                nonce: account.nonce,
+            })
+            .collect(),
+        deals: diff
+            .deals
+            .iter()
+            .map(|deal| DealDiff {
+                deal_id: deal.id,
+                status: format!("{:?}", deal.status),
+                amount_remaining: deal.amount_remaining,
+            })
+            .collect(),
+        removed_accounts: diff.removed_accounts,
+        removed_deals: diff.removed_deals,
+    }))
+}
+
+/// Full block including roots and proof, for L1 submission and external
+/// verification. Returns bincode when the client sends
+/// `Accept: application/octet-stream`, JSON with hex fields otherwise.
+pub async fn get_raw_block(
+    State(state): State<Arc<ApiState>>,
+    Path(block_id): Path<BlockId>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let block = load_block(&state, block_id)?;
+
+    let wants_binary = headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("application/octet-stream"));
+
+    if wants_binary {
+        let bytes = bincode::serialize(&block).map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: "SerializationFailed".to_string(),
+                    message: format!("Failed to serialize block: {}", e),
+                }),
+            )
+        })?;
+        return Ok(([(header::CONTENT_TYPE, "application/octet-stream")], bytes).into_response());
+    }
+
+    let transactions = block
+        .transactions
+        .iter()
+        .map(|tx| encode_tx_envelope(tx).map(|bytes| format!("0x{}", hex::encode(bytes))))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: "SerializationFailed".to_string(),
+                    message: format!("Failed to encode transaction: {:?}", e),
+                }),
+            )
+        })?;
+
+    Ok(Json(RawBlockResponse {
+        block_id: block.id,
+        timestamp: block.timestamp,
+        state_root: format!("0x{}", hex::encode(block.state_root)),
+        withdrawals_root: format!("0x{}", hex::encode(block.withdrawals_root)),
+        block_proof: format!("0x{}", hex::encode(&block.block_proof)),
+        transactions,
+    })
+    .into_response())
+}
+
+/// Stored transaction with the given canonical hash, as returned in
+/// `tx_hash` on submission, with the block and position it was included at
+pub async fn get_transaction_by_hash(
+    State(state): State<Arc<ApiState>>,
+    Path(tx_hash): Path<String>,
+) -> Result<Json<TransactionResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let Some(ref storage) = state.storage else {
+        return Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ErrorResponse {
+                error: "StorageNotAvailable".to_string(),
+                message: "Storage not configured".to_string(),
+            }),
+        ));
+    };
+
+    let hash: zkclear_storage::TxHash = hex::decode(tx_hash.trim_start_matches("0x"))
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse {
+                    error: "InvalidTxHash".to_string(),
+                    message: "tx_hash must be 32 bytes of hex".to_string(),
+                }),
+            )
+        })?;
+
+    let (transaction, block_id, index) = storage
+        .get_transaction_by_hash(&hash)
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: "StorageError".to_string(),
+                    message: "Failed to load transaction from storage".to_string(),
+                }),
+            )
+        })?
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse {
+                    error: "TransactionNotFound".to_string(),
+                    message: format!("Transaction {} not found", tx_hash),
+                }),
+            )
+        })?;
+
+    Ok(Json(TransactionResponse {
+        tx_hash: format!("0x{}", hex::encode(hash)),
+        block_id,
+        index,
+        transaction,
+    }))
+}
+
+/// Merkle proof that the withdrawal at position `tx_index` of a block is
+/// included in the block's `withdrawals_root`, for claiming on the
+/// destination chain. The returned index is the leaf's place in the
+/// canonical withdrawal order, not the tx position.
+pub async fn get_withdrawal_proof(
+    State(state): State<Arc<ApiState>>,
+    Path((block_id, tx_index)): Path<(BlockId, usize)>,
+) -> Result<Json<WithdrawalProofResponse>, (StatusCode, Json<ErrorResponse>)> {
+    use zkclear_prover::merkle::{withdrawal_leaves_with, MerkleTree};
+
+    let block = load_block(&state, block_id)?;
+
+    let hash_algo = state.sequencer.hash_algo();
+    let leaves = withdrawal_leaves_with(hash_algo, &block.transactions);
+    let index = leaves
+        .iter()
+        .position(|(position, _)| *position == tx_index)
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse {
+                    error: "WithdrawalNotFound".to_string(),
+                    message: format!("Block {} has no withdrawal at index {}", block_id, tx_index),
+                }),
+            )
+        })?;
+
+    let proof_error = |message: String| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: "WithdrawalProofFailed".to_string(),
+                message,
+            }),
+        )
+    };
+
+    let mut tree = MerkleTree::new_with(hash_algo);
+    for (_, leaf) in &leaves {
+        tree.add_leaf(*leaf);
+    }
+    let proof = tree
+        .prove(index)
+        .map_err(|e| proof_error(format!("Failed to build withdrawal proof: {:?}", e)))?;
+    let root = tree
+        .root()
+        .map_err(|e| proof_error(format!("Failed to compute withdrawals root: {:?}", e)))?;
+    if root != block.withdrawals_root {
+        return Err(proof_error(format!(
+            "Withdrawals root of block {} does not match its withdrawals",
+            block_id
+        )));
+    }
+
+    Ok(Json(WithdrawalProofResponse {
+        leaf: format!("0x{}", hex::encode(leaves[index].1)),
+        root: format!("0x{}", hex::encode(root)),
+        siblings: proof
+            .siblings
+            .iter()
+            .map(|sibling| format!("0x{}", hex::encode(sibling)))
+            .collect(),
+        index,
+    }))
+}
+
+fn load_block(
+    state: &ApiState,
+    block_id: BlockId,
+) -> Result<zkclear_types::Block, (StatusCode, Json<ErrorResponse>)> {
+    if let Some(ref storage) = state.storage {
+        storage
+            .get_block(block_id)
+            .map_err(|_| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(ErrorResponse {
+                        error: "StorageError".to_string(),
+                        message: "Failed to load block from storage".to_string(),
+                    }),
+                )
+            })?
+            .ok_or_else(|| {
+                (
+                    StatusCode::NOT_FOUND,
+                    Json(ErrorResponse {
+                        error: "BlockNotFound".to_string(),
+                        message: format!("Block {} not found", block_id),
+                    }),
+                )
+            })
+    } else {
+        Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ErrorResponse {
+                error: "StorageNotAvailable".to_string(),
+                message: "Storage not configured".to_string(),
+            }),
+        ))
+    }
+}
+
 pub async fn get_queue_status(State(state): State<Arc<ApiState>>) -> Json<QueueStatusResponse> {
     Json(QueueStatusResponse {
         pending_transactions: state.sequencer.queue_length(),
-        max_queue_size: 10000,
+        max_queue_size: state.sequencer.max_queue_size(),
         current_block_id: state.sequencer.get_current_block_id(),
+        pressure: state.sequencer.queue_pressure(),
+        accepting: state.sequencer.is_accepting(),
+    })
+}
+
+pub async fn get_stats(State(state): State<Arc<ApiState>>) -> Json<StatsResponse> {
+    let stats = state.sequencer.stats();
+    Json(StatsResponse {
+        current_block_id: stats.current_block_id,
+        queue_length: stats.queue_length,
+        max_queue_size: stats.max_queue_size,
+        last_snapshot_block_id: stats.last_snapshot_block_id,
+        accounts: stats.accounts,
+        deals: stats.deals,
+        has_prover: stats.has_prover,
     })
 }
 
+/// Recommended fee for a tx, from `kind` and optionally `size` (the
+/// encoded size in bytes; the kind's typical size when absent)
+pub async fn get_fee_estimate(
+    State(state): State<Arc<ApiState>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<FeeEstimateResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let kind = params
+        .get("kind")
+        .ok_or_else(|| invalid_query("MissingTxKind", "kind is required"))?;
+    let kind: TxKind =
+        serde_json::from_value(serde_json::Value::String(kind.clone())).map_err(|_| {
+            invalid_query(
+                "UnsupportedTxKind",
+                &format!("kind must be one of: {}", supported_tx_kinds().join(", ")),
+            )
+        })?;
+    let size = match params.get("size") {
+        Some(size) => size
+            .parse::<usize>()
+            .map_err(|_| invalid_query("InvalidSize", "size must be a byte count"))?,
+        None => default_tx_size(&kind),
+    };
+
+    let estimate = state.sequencer.estimate_fee(&kind, Some(size));
+    Ok(Json(FeeEstimateResponse {
+        kind: format!("{:?}", kind),
+        size,
+        priority_fee: estimate.priority_fee,
+        fee_amount: estimate.fee_amount,
+        asset_id: estimate.asset_id,
+        chain_id: estimate.chain_id,
+    }))
+}
+
+pub async fn get_state_root(
+    State(state): State<Arc<ApiState>>,
+) -> Result<Json<StateRootResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let (block_id, state_root) =
+        state
+            .sequencer
+            .current_state_root_with_block_id()
+            .map_err(|e| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(ErrorResponse {
+                        error: "StateRootError".to_string(),
+                        message: format!("Failed to compute state root: {:?}", e),
+                    }),
+                )
+            })?;
+
+    Ok(Json(StateRootResponse {
+        block_id,
+        state_root: format!("0x{}", hex::encode(state_root)),
+    }))
+}
+
+pub async fn get_checkpoint(
+    State(state): State<Arc<ApiState>>,
+) -> Result<Json<CheckpointResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let checkpoint = state.sequencer.get_last_checkpoint().ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "CheckpointNotFound".to_string(),
+                message: "No checkpoint has been exported yet".to_string(),
+            }),
+        )
+    })?;
+
+    Ok(Json(CheckpointResponse {
+        block_id: checkpoint.block_id,
+        state_root: format!("0x{}", hex::encode(checkpoint.state_root)),
+        withdrawals_root_accumulator: format!(
+            "0x{}",
+            hex::encode(checkpoint.withdrawals_root_accumulator)
+        ),
+        timestamp: checkpoint.timestamp,
+    }))
+}
+
 pub async fn get_supported_chains() -> Json<serde_json::Value> {
     Json(serde_json::json!({
         "chains": [
@@ -357,172 +1276,381 @@ pub async fn get_supported_chains() -> Json<serde_json::Value> {
     }))
 }
 
+/// Errors returned by the JSON-RPC endpoint, each with a fixed error code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RpcError {
+    /// Envelope is not a JSON-RPC 2.0 request
+    InvalidRequest,
+    MethodNotFound,
+    /// Method is only served over REST, at the given endpoint
+    RestOnly(&'static str),
+    InvalidParams(String),
+    QueueFull,
+    InvalidSignature,
+    InvalidNonce,
+    SubmissionFailed(String),
+    UnsupportedTxKind(u8),
+    UnsupportedEnvelopeVersion(u8),
+    TxKindDisabled,
+    Throttled,
+    WrongDomain,
+    ReadOnly,
+    /// The sender is over its submission limit; retry after this many
+    /// seconds
+    RateLimited(u64),
+}
+
+impl RpcError {
+    pub fn code(&self) -> i32 {
+        match self {
+            RpcError::InvalidRequest => -32600,
+            RpcError::MethodNotFound | RpcError::RestOnly(_) => -32601,
+            RpcError::InvalidParams(_) => -32602,
+            RpcError::QueueFull => -32000,
+            RpcError::InvalidSignature => -32001,
+            RpcError::InvalidNonce => -32002,
+            RpcError::SubmissionFailed(_) => -32003,
+            RpcError::UnsupportedTxKind(_) => -32004,
+            RpcError::UnsupportedEnvelopeVersion(_) => -32005,
+            RpcError::TxKindDisabled => -32006,
+            RpcError::Throttled => -32007,
+            RpcError::WrongDomain => -32008,
+            RpcError::ReadOnly => -32009,
+            RpcError::RateLimited(_) => -32010,
+        }
+    }
+
+    pub fn to_jsonrpc(&self) -> JsonRpcError {
+        let message = match self {
+            RpcError::InvalidRequest => "Invalid Request".to_string(),
+            RpcError::MethodNotFound => "Method not found".to_string(),
+            RpcError::RestOnly(endpoint) => format!("Use REST endpoint {} instead", endpoint),
+            RpcError::InvalidParams(reason) => format!("Invalid params: {}", reason),
+            RpcError::QueueFull => "Queue full".to_string(),
+            RpcError::InvalidSignature => "Invalid signature".to_string(),
+            RpcError::InvalidNonce => "Invalid nonce".to_string(),
+            RpcError::SubmissionFailed(reason) => format!("Submission failed: {}", reason),
+            RpcError::UnsupportedTxKind(kind) => format!("Unsupported tx kind: {}", kind),
+            RpcError::UnsupportedEnvelopeVersion(version) => {
+                format!("Unsupported tx envelope version: {}", version)
+            }
+            RpcError::TxKindDisabled => "Transaction kind is disabled on this node".to_string(),
+            RpcError::Throttled => "Queue near capacity, fee too low".to_string(),
+            RpcError::WrongDomain => "Transaction domain does not match this network".to_string(),
+            RpcError::ReadOnly => "This node is a read-only replica".to_string(),
+            RpcError::RateLimited(_) => "Too many submissions from this sender".to_string(),
+        };
+        let data = match self {
+            RpcError::UnsupportedTxKind(kind) => Some(serde_json::json!({ "kind": kind })),
+            RpcError::UnsupportedEnvelopeVersion(version) => {
+                Some(serde_json::json!({ "version": version }))
+            }
+            RpcError::RateLimited(retry_after) => {
+                Some(serde_json::json!({ "retry_after": retry_after }))
+            }
+            _ => None,
+        };
+
+        JsonRpcError {
+            code: self.code(),
+            message,
+            data,
+        }
+    }
+}
+
+impl From<EnvelopeError> for RpcError {
+    fn from(error: EnvelopeError) -> Self {
+        match error {
+            EnvelopeError::UnsupportedTxKind(kind) => RpcError::UnsupportedTxKind(kind),
+            EnvelopeError::UnsupportedVersion(version) => {
+                RpcError::UnsupportedEnvelopeVersion(version)
+            }
+            e => {
+                RpcError::InvalidParams(format!("failed to decode transaction envelope ({:?})", e))
+            }
+        }
+    }
+}
+
+impl From<zkclear_sequencer::SequencerError> for RpcError {
+    fn from(error: zkclear_sequencer::SequencerError) -> Self {
+        use zkclear_sequencer::SequencerError;
+
+        match error {
+            SequencerError::QueueFull => RpcError::QueueFull,
+            SequencerError::WrongDomain => RpcError::WrongDomain,
+            SequencerError::Throttled => RpcError::Throttled,
+            SequencerError::InvalidSignature => RpcError::InvalidSignature,
+            SequencerError::InvalidNonce => RpcError::InvalidNonce,
+            SequencerError::TxKindDisabled => RpcError::TxKindDisabled,
+            e => RpcError::SubmissionFailed(format!("{:?}", e)),
+        }
+    }
+}
+
 pub async fn jsonrpc_handler(
     State(state): State<Arc<ApiState>>,
     Json(request): Json<JsonRpcRequest>,
 ) -> Json<JsonRpcResponse> {
+    let (result, error) = match handle_jsonrpc(&state, &request) {
+        Ok(result) => (Some(result), None),
+        Err(e) => (None, Some(e.to_jsonrpc())),
+    };
+
+    Json(JsonRpcResponse {
+        jsonrpc: "2.0".to_string(),
+        result,
+        error,
+        id: request.id,
+    })
+}
+
+fn handle_jsonrpc(
+    state: &ApiState,
+    request: &JsonRpcRequest,
+) -> Result<serde_json::Value, RpcError> {
     if request.jsonrpc != "2.0" {
-        return Json(JsonRpcResponse {
-            jsonrpc: "2.0".to_string(),
-            result: None,
-            error: Some(JsonRpcError {
-                code: -32600,
-                message: "Invalid Request".to_string(),
-                data: None,
-            }),
-            id: request.id,
-        });
+        return Err(RpcError::InvalidRequest);
     }
 
-    let result = match request.method.as_str() {
-        "submit_tx" => {
-            let tx_hex = match request.params.get("tx") {
-                Some(serde_json::Value::String(hex_str)) => hex_str,
-                _ => {
-                    return Json(JsonRpcResponse {
-                        jsonrpc: "2.0".to_string(),
-                        result: None,
-                        error: Some(JsonRpcError {
-                            code: -32602,
-                            message: "Invalid params: 'tx' must be a hex string".to_string(),
-                            data: None,
-                        }),
-                        id: request.id,
-                    });
-                }
-            };
-
-            let tx_bytes = match hex::decode(tx_hex.trim_start_matches("0x")) {
-                Ok(bytes) => bytes,
-                Err(_) => {
-                    return Json(JsonRpcResponse {
-                        jsonrpc: "2.0".to_string(),
-                        result: None,
-                        error: Some(JsonRpcError {
-                            code: -32602,
-                            message: "Invalid params: 'tx' must be valid hex".to_string(),
-                            data: None,
-                        }),
-                        id: request.id,
-                    });
-                }
-            };
+    match request.method.as_str() {
+        "submit_tx" => rpc_submit_tx(state, &request.params),
+        "get_account_balance" => Err(RpcError::RestOnly(
+            "/api/v1/account/:address/balance/:asset_id",
+        )),
+        _ => Err(RpcError::MethodNotFound),
+    }
+}
 
-            let tx: zkclear_types::Tx = match bincode::deserialize(&tx_bytes) {
-                Ok(tx) => tx,
-                Err(_) => {
-                    return Json(JsonRpcResponse {
-                        jsonrpc: "2.0".to_string(),
-                        result: None,
-                        error: Some(JsonRpcError {
-                            code: -32602,
-                            message: "Invalid params: failed to deserialize transaction"
-                                .to_string(),
-                            data: None,
-                        }),
-                        id: request.id,
-                    });
-                }
-            };
+fn rpc_submit_tx(
+    state: &ApiState,
+    params: &serde_json::Value,
+) -> Result<serde_json::Value, RpcError> {
+    if state.read_only {
+        return Err(RpcError::ReadOnly);
+    }
 
-            match state.sequencer.submit_tx(tx) {
-                Ok(()) => {
-                    let tx_hash = hex::encode(&tx_bytes);
-                    Some(serde_json::json!({
-                        "tx_hash": tx_hash,
-                        "status": "queued"
-                    }))
-                }
-                Err(zkclear_sequencer::SequencerError::QueueFull) => {
-                    return Json(JsonRpcResponse {
-                        jsonrpc: "2.0".to_string(),
-                        result: None,
-                        error: Some(JsonRpcError {
-                            code: -32000,
-                            message: "Queue full".to_string(),
-                            data: None,
-                        }),
-                        id: request.id,
-                    });
-                }
-                Err(zkclear_sequencer::SequencerError::InvalidSignature) => {
-                    return Json(JsonRpcResponse {
-                        jsonrpc: "2.0".to_string(),
-                        result: None,
-                        error: Some(JsonRpcError {
-                            code: -32001,
-                            message: "Invalid signature".to_string(),
-                            data: None,
-                        }),
-                        id: request.id,
-                    });
-                }
-                Err(zkclear_sequencer::SequencerError::InvalidNonce) => {
-                    return Json(JsonRpcResponse {
-                        jsonrpc: "2.0".to_string(),
-                        result: None,
-                        error: Some(JsonRpcError {
-                            code: -32002,
-                            message: "Invalid nonce".to_string(),
-                            data: None,
-                        }),
-                        id: request.id,
-                    });
-                }
-                Err(e) => {
-                    return Json(JsonRpcResponse {
-                        jsonrpc: "2.0".to_string(),
-                        result: None,
-                        error: Some(JsonRpcError {
-                            code: -32003,
-                            message: format!("Submission failed: {:?}", e),
-                            data: None,
-                        }),
-                        id: request.id,
-                    });
-                }
-            }
-        }
-        "get_account_balance" => {
-            return Json(JsonRpcResponse {
-                jsonrpc: "2.0".to_string(),
-                result: None,
-                error: Some(JsonRpcError {
-                    code: -32601,
-                    message: "Use REST endpoint /api/v1/account/:address/balance/:asset_id instead"
-                        .to_string(),
-                    data: None,
-                }),
-                id: request.id,
-            });
-        }
+    let tx_hex = match params.get("tx") {
+        Some(serde_json::Value::String(hex_str)) => hex_str,
         _ => {
-            return Json(JsonRpcResponse {
-                jsonrpc: "2.0".to_string(),
-                result: None,
-                error: Some(JsonRpcError {
-                    code: -32601,
-                    message: "Method not found".to_string(),
-                    data: None,
-                }),
-                id: request.id,
-            });
+            return Err(RpcError::InvalidParams(
+                "'tx' must be a hex string".to_string(),
+            ))
         }
     };
 
-    Json(JsonRpcResponse {
-        jsonrpc: "2.0".to_string(),
-        result,
-        error: None,
-        id: request.id,
+    let tx_bytes = hex::decode(tx_hex.trim_start_matches("0x"))
+        .map_err(|_| RpcError::InvalidParams("'tx' must be valid hex".to_string()))?;
+    let tx = decode_tx(&tx_bytes)?;
+    let sender = tx.from;
+    if let Some(wait) = sender_wait(state, &sender) {
+        return Err(RpcError::RateLimited(crate::middleware::retry_after_secs(
+            wait,
+        )));
+    }
+    let tx_hash = zkclear_storage::tx_hash(&tx);
+    state.sequencer.submit_tx(tx)?;
+    record_submission(state, &sender);
+
+    Ok(serde_json::json!({
+        "tx_hash": hex::encode(tx_hash),
+        "status": "queued"
+    }))
+}
+
+/// How long `sender` must wait before submitting again, if rate limited
+fn sender_wait(state: &ApiState, sender: &Address) -> Option<std::time::Duration> {
+    state
+        .rate_limit_state
+        .as_ref()
+        .and_then(|limits| limits.check_sender(sender).err())
+}
+
+/// Count a queued submission against its sender, whose signature the
+/// sequencer has verified by now
+fn record_submission(state: &ApiState, sender: &Address) {
+    if let Some(ref limits) = state.rate_limit_state {
+        limits.record_submission(sender);
+    }
+}
+
+/// Parse a submission body, rejecting unknown `kind` tags explicitly
+fn parse_submit_request(
+    body: serde_json::Value,
+) -> Result<SubmitTransactionRequest, (StatusCode, Json<ErrorResponse>)> {
+    let kind = body.get("kind").and_then(|k| k.as_str()).ok_or_else(|| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "MissingTxKind".to_string(),
+                message: "Transaction 'kind' field is required".to_string(),
+            }),
+        )
+    })?;
+
+    let supported = supported_tx_kinds();
+    if !supported.contains(&kind) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "UnsupportedTxKind".to_string(),
+                message: format!(
+                    "Unsupported transaction kind '{}', expected one of: {}",
+                    kind,
+                    supported.join(", ")
+                ),
+            }),
+        ));
+    }
+
+    serde_json::from_value(body).map_err(|e| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "InvalidTransaction".to_string(),
+                message: format!("Invalid transaction: {}", e),
+            }),
+        )
     })
 }
 
 pub async fn submit_transaction(
     State(state): State<Arc<ApiState>>,
-    Json(request): Json<crate::types::SubmitTransactionRequest>,
-) -> Result<Json<crate::types::SubmitTransactionResponse>, (StatusCode, Json<ErrorResponse>)> {
-    use crate::types::SubmitTransactionRequest;
+    Json(body): Json<serde_json::Value>,
+) -> Result<Json<SubmitTransactionResponse>, (StatusCode, Json<ErrorResponse>)> {
+    submit_request(&state, body).map(Json)
+}
+
+/// Submit each transaction in the batch independently. Once the queue is
+/// full, the remaining entries are rejected with `QueueFull` without being
+/// attempted. A batch over the sequencer's `max_batch_size` is rejected
+/// whole.
+pub async fn submit_transaction_batch(
+    State(state): State<Arc<ApiState>>,
+    Json(bodies): Json<Vec<serde_json::Value>>,
+) -> Result<Json<Vec<BatchTransactionResult>>, (StatusCode, Json<ErrorResponse>)> {
+    let max_batch_size = state.sequencer.tx_limits().max_batch_size;
+    if bodies.len() > max_batch_size {
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            Json(ErrorResponse {
+                error: "BatchTooLarge".to_string(),
+                message: format!(
+                    "Batch has {} transactions, at most {} are accepted",
+                    bodies.len(),
+                    max_batch_size
+                ),
+            }),
+        ));
+    }
+
+    let mut queue_full = false;
+    let mut results = Vec::with_capacity(bodies.len());
+
+    for (index, body) in bodies.into_iter().enumerate() {
+        let result = if queue_full {
+            Err(queue_full_error())
+        } else {
+            submit_request(&state, body)
+        };
+
+        results.push(match result {
+            Ok(response) => BatchTransactionResult::Queued {
+                index,
+                tx_hash: response.tx_hash,
+                status: response.status,
+            },
+            Err((_, Json(error))) => {
+                queue_full |= error.error == "QueueFull";
+                BatchTransactionResult::Rejected {
+                    index,
+                    error: error.error,
+                    message: error.message,
+                }
+            }
+        });
+    }
+
+    Ok(Json(results))
+}
+
+/// Reject an unusable `external_ref` before the deal is queued, by the same
+/// rule the STF applies
+fn check_external_ref(
+    state: &ApiState,
+    external_ref: &str,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let (error, message) = match state.sequencer.stf_config().check_external_ref(external_ref) {
+        Ok(()) => return Ok(()),
+        Err(StfError::ExternalRefTooLong) => (
+            "ExternalRefTooLong",
+            format!(
+                "external_ref must be at most {} bytes",
+                state.sequencer.tx_limits().max_external_ref_len
+            ),
+        ),
+        Err(_) => (
+            "InvalidExternalRef",
+            "external_ref must not contain control characters".to_string(),
+        ),
+    };
+    Err((
+        StatusCode::BAD_REQUEST,
+        Json(ErrorResponse {
+            error: error.to_string(),
+            message,
+        }),
+    ))
+}
+
+fn queue_full_error() -> (StatusCode, Json<ErrorResponse>) {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(ErrorResponse {
+            error: "QueueFull".to_string(),
+            message: "Transaction queue is full".to_string(),
+        }),
+    )
+}
+
+/// Optional client `request_id` of a submission, which must be a UUID
+fn parse_request_id(
+    body: &serde_json::Value,
+) -> Result<Option<String>, (StatusCode, Json<ErrorResponse>)> {
+    let invalid = || {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "InvalidRequestId".to_string(),
+                message: "request_id must be a UUID".to_string(),
+            }),
+        )
+    };
+
+    match body.get("request_id") {
+        None | Some(serde_json::Value::Null) => Ok(None),
+        Some(serde_json::Value::String(id)) if is_uuid(id) => Ok(Some(id.to_lowercase())),
+        Some(_) => Err(invalid()),
+    }
+}
+
+/// Whether `s` is a hyphenated UUID, e.g. `67e55044-10b1-426f-9247-bb680e5fe0c8`
+fn is_uuid(s: &str) -> bool {
+    const GROUP_LENGTHS: [usize; 5] = [8, 4, 4, 4, 12];
+    let groups: Vec<&str> = s.split('-').collect();
+    groups.len() == GROUP_LENGTHS.len()
+        && groups
+            .iter()
+            .zip(GROUP_LENGTHS)
+            .all(|(group, len)| group.len() == len && group.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// Build the tx a submission request describes, signature included
+fn tx_from_request(
+    state: &ApiState,
+    request: SubmitTransactionRequest,
+) -> Result<zkclear_types::Tx, (StatusCode, Json<ErrorResponse>)> {
     use zkclear_types::Tx;
 
     let (tx, _from_address) = match request {
@@ -533,6 +1661,7 @@ pub async fn submit_transaction(
             amount,
             chain_id,
             nonce,
+            domain,
             signature,
         } => {
             let tx_hash_bytes = hex::decode(tx_hash.trim_start_matches("0x"))
@@ -604,9 +1733,6 @@ pub async fn submit_transaction(
                 ));
             }
 
-            let mut sig = [0u8; 65];
-            sig.copy_from_slice(&sig_bytes);
-
             let tx = Tx {
                 id: 0,
                 from: addr,
@@ -619,7 +1745,10 @@ pub async fn submit_transaction(
                     amount,
                     chain_id,
                 }),
-                signature: sig,
+                fee: 0,
+                domain,
+                scheme: SignatureScheme::Secp256k1Recoverable,
+                signature: sig_bytes,
             };
 
             (tx, addr)
@@ -638,6 +1767,7 @@ pub async fn submit_transaction(
             expires_at,
             external_ref,
             nonce,
+            domain,
             signature,
         } => {
             let from_bytes = hex::decode(from.trim_start_matches("0x"))
@@ -664,6 +1794,10 @@ pub async fn submit_transaction(
             let mut from_address = [0u8; 20];
             from_address.copy_from_slice(&from_bytes);
 
+            if let Some(ref external_ref) = external_ref {
+                check_external_ref(state, external_ref)?;
+            }
+
             let visibility_enum = match visibility.as_str() {
                 "Public" => DealVisibility::Public,
                 "Direct" => DealVisibility::Direct,
@@ -709,9 +1843,6 @@ pub async fn submit_transaction(
                 ));
             }
 
-            let mut sig = [0u8; 65];
-            sig.copy_from_slice(&sig_bytes);
-
             let tx = Tx {
                 id: 0,
                 from: from_address,
@@ -730,16 +1861,33 @@ pub async fn submit_transaction(
                     expires_at,
                     external_ref,
                 }),
-                signature: sig,
+                fee: 0,
+                domain,
+                scheme: SignatureScheme::Secp256k1Recoverable,
+                signature: sig_bytes,
             };
 
             (tx, from_address)
         }
-        SubmitTransactionRequest::AcceptDeal {
+        SubmitTransactionRequest::CreateFundedDeal {
             from,
+            tx_hash,
+            deposit_asset_id,
+            deposit_amount,
+            deposit_chain_id,
             deal_id,
-            amount,
+            visibility,
+            taker,
+            asset_base,
+            asset_quote,
+            chain_id_base,
+            chain_id_quote,
+            amount_base,
+            price_quote_per_base,
+            expires_at,
+            external_ref,
             nonce,
+            domain,
             signature,
         } => {
             let from_bytes = hex::decode(from.trim_start_matches("0x"))
@@ -766,6 +1914,58 @@ pub async fn submit_transaction(
             let mut from_address = [0u8; 20];
             from_address.copy_from_slice(&from_bytes);
 
+            let tx_hash_bytes = hex::decode(tx_hash.trim_start_matches("0x"))
+                .map_err(|_| {
+                    (
+                        StatusCode::BAD_REQUEST,
+                        Json(ErrorResponse {
+                            error: "InvalidTxHash".to_string(),
+                            message: "Invalid tx_hash format".to_string(),
+                        }),
+                    )
+                })?;
+
+            if tx_hash_bytes.len() != 32 {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    Json(ErrorResponse {
+                        error: "InvalidTxHash".to_string(),
+                        message: "tx_hash must be 32 bytes".to_string(),
+                    }),
+                ));
+            }
+
+            let mut tx_hash_array = [0u8; 32];
+            tx_hash_array.copy_from_slice(&tx_hash_bytes);
+
+            if let Some(ref external_ref) = external_ref {
+                check_external_ref(state, external_ref)?;
+            }
+
+            let visibility_enum = match visibility.as_str() {
+                "Public" => DealVisibility::Public,
+                "Direct" => DealVisibility::Direct,
+                _ => {
+                    return Err((
+                        StatusCode::BAD_REQUEST,
+                        Json(ErrorResponse {
+                            error: "InvalidVisibility".to_string(),
+                            message: "Visibility must be 'Public' or 'Direct'".to_string(),
+                        }),
+                    ));
+                }
+            };
+
+            let taker_addr = taker.and_then(|t| {
+                let bytes = hex::decode(t.trim_start_matches("0x")).ok()?;
+                if bytes.len() != 20 {
+                    return None;
+                }
+                let mut addr = [0u8; 20];
+                addr.copy_from_slice(&bytes);
+                Some(addr)
+            });
+
             let sig_bytes = hex::decode(signature.trim_start_matches("0x"))
                 .map_err(|_| {
                     (
@@ -787,27 +1987,48 @@ pub async fn submit_transaction(
                 ));
             }
 
-            let mut sig = [0u8; 65];
-            sig.copy_from_slice(&sig_bytes);
-
             let tx = Tx {
                 id: 0,
                 from: from_address,
                 nonce,
-                kind: TxKind::AcceptDeal,
-                payload: TxPayload::AcceptDeal(zkclear_types::AcceptDeal {
-                    deal_id,
-                    amount,
+                kind: TxKind::CreateFundedDeal,
+                payload: TxPayload::CreateFundedDeal(zkclear_types::CreateFundedDeal {
+                    deposit: zkclear_types::Deposit {
+                        tx_hash: tx_hash_array,
+                        account: from_address,
+                        asset_id: deposit_asset_id,
+                        amount: deposit_amount,
+                        chain_id: deposit_chain_id,
+                    },
+                    deal: zkclear_types::CreateDeal {
+                        deal_id,
+                        visibility: visibility_enum,
+                        taker: taker_addr,
+                        asset_base,
+                        asset_quote,
+                        chain_id_base,
+                        chain_id_quote,
+                        amount_base,
+                        price_quote_per_base,
+                        expires_at,
+                        external_ref,
+                    },
                 }),
-                signature: sig,
+                fee: 0,
+                domain,
+                scheme: SignatureScheme::Secp256k1Recoverable,
+                signature: sig_bytes,
             };
 
             (tx, from_address)
         }
-        SubmitTransactionRequest::CancelDeal {
+        SubmitTransactionRequest::AcceptDeal {
             from,
             deal_id,
+            amount,
+            max_price_quote_per_base,
             nonce,
+            domain,
             signature,
         } => {
             let from_bytes = hex::decode(from.trim_start_matches("0x"))
@@ -855,27 +2076,29 @@ pub async fn submit_transaction(
                 ));
             }
 
-            let mut sig = [0u8; 65];
-            sig.copy_from_slice(&sig_bytes);
-
             let tx = Tx {
                 id: 0,
                 from: from_address,
                 nonce,
-                kind: TxKind::CancelDeal,
-                payload: TxPayload::CancelDeal(zkclear_types::CancelDeal { deal_id }),
-                signature: sig,
+                kind: TxKind::AcceptDeal,
+                payload: TxPayload::AcceptDeal(zkclear_types::AcceptDeal {
+                    deal_id,
+                    amount,
+                    max_price_quote_per_base,
+                }),
+                fee: 0,
+                domain,
+                scheme: SignatureScheme::Secp256k1Recoverable,
+                signature: sig_bytes,
             };
 
             (tx, from_address)
         }
-        SubmitTransactionRequest::Withdraw {
+        SubmitTransactionRequest::CancelDeal {
             from,
-            asset_id,
-            amount,
-            to,
-            chain_id,
+            deal_id,
             nonce,
+            domain,
             signature,
         } => {
             let from_bytes = hex::decode(from.trim_start_matches("0x"))
@@ -902,29 +2125,71 @@ pub async fn submit_transaction(
             let mut from_address = [0u8; 20];
             from_address.copy_from_slice(&from_bytes);
 
-            let to_bytes = hex::decode(to.trim_start_matches("0x"))
+            let sig_bytes = hex::decode(signature.trim_start_matches("0x"))
+                .map_err(|_| {
+                    (
+                        StatusCode::BAD_REQUEST,
+                        Json(ErrorResponse {
+                            error: "InvalidSignature".to_string(),
+                            message: "Invalid signature format".to_string(),
+                        }),
+                    )
+                })?;
+
+            if sig_bytes.len() != 65 {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    Json(ErrorResponse {
+                        error: "InvalidSignature".to_string(),
+                        message: "Signature must be 65 bytes".to_string(),
+                    }),
+                ));
+            }
+
+            let tx = Tx {
+                id: 0,
+                from: from_address,
+                nonce,
+                kind: TxKind::CancelDeal,
+                payload: TxPayload::CancelDeal(zkclear_types::CancelDeal { deal_id }),
+                fee: 0,
+                domain,
+                scheme: SignatureScheme::Secp256k1Recoverable,
+                signature: sig_bytes,
+            };
+
+            (tx, from_address)
+        }
+        SubmitTransactionRequest::DeclineDeal {
+            from,
+            deal_id,
+            nonce,
+            domain,
+            signature,
+        } => {
+            let from_bytes = hex::decode(from.trim_start_matches("0x"))
                 .map_err(|_| {
                     (
                         StatusCode::BAD_REQUEST,
                         Json(ErrorResponse {
                             error: "InvalidAddress".to_string(),
-                            message: "Invalid to address format".to_string(),
+                            message: "Invalid from address format".to_string(),
                         }),
                     )
                 })?;
 
-            if to_bytes.len() != 20 {
+            if from_bytes.len() != 20 {
                 return Err((
                     StatusCode::BAD_REQUEST,
                     Json(ErrorResponse {
                         error: "InvalidAddress".to_string(),
-                        message: "To address must be 20 bytes".to_string(),
+                        message: "From address must be 20 bytes".to_string(),
                     }),
                 ));
             }
 
-            let mut to_address = [0u8; 20];
-            to_address.copy_from_slice(&to_bytes);
+            let mut from_address = [0u8; 20];
+            from_address.copy_from_slice(&from_bytes);
 
             let sig_bytes = hex::decode(signature.trim_start_matches("0x"))
                 .map_err(|_| {
@@ -947,112 +2212,2159 @@ pub async fn submit_transaction(
                 ));
             }
 
-            let mut sig = [0u8; 65];
-            sig.copy_from_slice(&sig_bytes);
-
             let tx = Tx {
                 id: 0,
                 from: from_address,
                 nonce,
-                kind: TxKind::Withdraw,
-                payload: TxPayload::Withdraw(zkclear_types::Withdraw {
-                    asset_id,
-                    amount,
-                    to: to_address,
-                    chain_id,
-                }),
-                signature: sig,
+                kind: TxKind::DeclineDeal,
+                payload: TxPayload::DeclineDeal(zkclear_types::DeclineDeal { deal_id }),
+                fee: 0,
+                domain,
+                scheme: SignatureScheme::Secp256k1Recoverable,
+                signature: sig_bytes,
             };
 
             (tx, from_address)
         }
-    };
-
-    // Serialize transaction before submitting (for tx_hash generation)
-    let tx_hash = hex::encode(&bincode::serialize(&tx).unwrap_or_default());
-    
-    match state.sequencer.submit_tx_with_validation(tx, false) {
-        Ok(()) => {
-            Ok(Json(crate::types::SubmitTransactionResponse {
-                tx_hash,
-                status: "queued".to_string(),
-            }))
-        }
-        Err(zkclear_sequencer::SequencerError::QueueFull) => Err((
-            StatusCode::SERVICE_UNAVAILABLE,
-            Json(ErrorResponse {
-                error: "QueueFull".to_string(),
-                message: "Transaction queue is full".to_string(),
-            }),
-        )),
-        Err(zkclear_sequencer::SequencerError::InvalidSignature) => Err((
-            StatusCode::BAD_REQUEST,
+        SubmitTransactionRequest::ConfirmSettlement {
+            from,
+            deal_id,
+            nonce,
+            domain,
+            signature,
+        } => {
+            let from_bytes = hex::decode(from.trim_start_matches("0x"))
+                .map_err(|_| {
+                    (
+                        StatusCode::BAD_REQUEST,
+                        Json(ErrorResponse {
+                            error: "InvalidAddress".to_string(),
+                            message: "Invalid from address format".to_string(),
+                        }),
+                    )
+                })?;
+
+            if from_bytes.len() != 20 {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    Json(ErrorResponse {
+                        error: "InvalidAddress".to_string(),
+                        message: "From address must be 20 bytes".to_string(),
+                    }),
+                ));
+            }
+
+            let mut from_address = [0u8; 20];
+            from_address.copy_from_slice(&from_bytes);
+
+            let sig_bytes = hex::decode(signature.trim_start_matches("0x"))
+                .map_err(|_| {
+                    (
+                        StatusCode::BAD_REQUEST,
+                        Json(ErrorResponse {
+                            error: "InvalidSignature".to_string(),
+                            message: "Invalid signature format".to_string(),
+                        }),
+                    )
+                })?;
+
+            if sig_bytes.len() != 65 {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    Json(ErrorResponse {
+                        error: "InvalidSignature".to_string(),
+                        message: "Signature must be 65 bytes".to_string(),
+                    }),
+                ));
+            }
+
+            let tx = Tx {
+                id: 0,
+                from: from_address,
+                nonce,
+                kind: TxKind::ConfirmSettlement,
+                payload: TxPayload::ConfirmSettlement(zkclear_types::ConfirmSettlement { deal_id }),
+                fee: 0,
+                domain,
+                scheme: SignatureScheme::Secp256k1Recoverable,
+                signature: sig_bytes,
+            };
+
+            (tx, from_address)
+        }
+        SubmitTransactionRequest::BatchCreateDeal {
+            from,
+            deals,
+            nonce,
+            domain,
+            signature,
+        } => {
+            let from_bytes = hex::decode(from.trim_start_matches("0x"))
+                .map_err(|_| {
+                    (
+                        StatusCode::BAD_REQUEST,
+                        Json(ErrorResponse {
+                            error: "InvalidAddress".to_string(),
+                            message: "Invalid from address format".to_string(),
+                        }),
+                    )
+                })?;
+
+            if from_bytes.len() != 20 {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    Json(ErrorResponse {
+                        error: "InvalidAddress".to_string(),
+                        message: "From address must be 20 bytes".to_string(),
+                    }),
+                ));
+            }
+
+            let mut from_address = [0u8; 20];
+            from_address.copy_from_slice(&from_bytes);
+
+            let mut batch = Vec::with_capacity(deals.len());
+            for deal in deals {
+                if let Some(ref external_ref) = deal.external_ref {
+                    check_external_ref(state, external_ref)?;
+                }
+
+                let visibility = match deal.visibility.as_str() {
+                    "Public" => DealVisibility::Public,
+                    "Direct" => DealVisibility::Direct,
+                    _ => {
+                        return Err((
+                            StatusCode::BAD_REQUEST,
+                            Json(ErrorResponse {
+                                error: "InvalidVisibility".to_string(),
+                                message: "Visibility must be 'Public' or 'Direct'".to_string(),
+                            }),
+                        ));
+                    }
+                };
+
+                let taker = deal.taker.and_then(|t| {
+                    let bytes = hex::decode(t.trim_start_matches("0x")).ok()?;
+                    if bytes.len() != 20 {
+                        return None;
+                    }
+                    let mut addr = [0u8; 20];
+                    addr.copy_from_slice(&bytes);
+                    Some(addr)
+                });
+
+                batch.push(zkclear_types::CreateDeal {
+                    deal_id: deal.deal_id,
+                    visibility,
+                    taker,
+                    asset_base: deal.asset_base,
+                    asset_quote: deal.asset_quote,
+                    chain_id_base: deal.chain_id_base,
+                    chain_id_quote: deal.chain_id_quote,
+                    amount_base: deal.amount_base,
+                    price_quote_per_base: deal.price_quote_per_base,
+                    expires_at: deal.expires_at,
+                    external_ref: deal.external_ref,
+                });
+            }
+
+            let sig_bytes = hex::decode(signature.trim_start_matches("0x"))
+                .map_err(|_| {
+                    (
+                        StatusCode::BAD_REQUEST,
+                        Json(ErrorResponse {
+                            error: "InvalidSignature".to_string(),
+                            message: "Invalid signature format".to_string(),
+                        }),
+                    )
+                })?;
+
+            if sig_bytes.len() != 65 {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    Json(ErrorResponse {
+                        error: "InvalidSignature".to_string(),
+                        message: "Signature must be 65 bytes".to_string(),
+                    }),
+                ));
+            }
+
+            let tx = Tx {
+                id: 0,
+                from: from_address,
+                nonce,
+                kind: TxKind::BatchCreateDeal,
+                payload: TxPayload::BatchCreateDeal(zkclear_types::BatchCreateDeal {
+                    deals: batch,
+                }),
+                fee: 0,
+                domain,
+                scheme: SignatureScheme::Secp256k1Recoverable,
+                signature: sig_bytes,
+            };
+
+            (tx, from_address)
+        }
+        SubmitTransactionRequest::CancelAllDeals {
+            from,
+            asset_base,
+            nonce,
+            domain,
+            signature,
+        } => {
+            let from_bytes = hex::decode(from.trim_start_matches("0x"))
+                .map_err(|_| {
+                    (
+                        StatusCode::BAD_REQUEST,
+                        Json(ErrorResponse {
+                            error: "InvalidAddress".to_string(),
+                            message: "Invalid from address format".to_string(),
+                        }),
+                    )
+                })?;
+
+            if from_bytes.len() != 20 {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    Json(ErrorResponse {
+                        error: "InvalidAddress".to_string(),
+                        message: "From address must be 20 bytes".to_string(),
+                    }),
+                ));
+            }
+
+            let mut from_address = [0u8; 20];
+            from_address.copy_from_slice(&from_bytes);
+
+            let sig_bytes = hex::decode(signature.trim_start_matches("0x"))
+                .map_err(|_| {
+                    (
+                        StatusCode::BAD_REQUEST,
+                        Json(ErrorResponse {
+                            error: "InvalidSignature".to_string(),
+                            message: "Invalid signature format".to_string(),
+                        }),
+                    )
+                })?;
+
+            if sig_bytes.len() != 65 {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    Json(ErrorResponse {
+                        error: "InvalidSignature".to_string(),
+                        message: "Signature must be 65 bytes".to_string(),
+                    }),
+                ));
+            }
+
+            let tx = Tx {
+                id: 0,
+                from: from_address,
+                nonce,
+                kind: TxKind::CancelAllDeals,
+                payload: TxPayload::CancelAllDeals(zkclear_types::CancelAllDeals { asset_base }),
+                fee: 0,
+                domain,
+                scheme: SignatureScheme::Secp256k1Recoverable,
+                signature: sig_bytes,
+            };
+
+            (tx, from_address)
+        }
+        SubmitTransactionRequest::Withdraw {
+            from,
+            asset_id,
+            amount,
+            to,
+            chain_id,
+            nonce,
+            domain,
+            signature,
+        } => {
+            let from_bytes = hex::decode(from.trim_start_matches("0x"))
+                .map_err(|_| {
+                    (
+                        StatusCode::BAD_REQUEST,
+                        Json(ErrorResponse {
+                            error: "InvalidAddress".to_string(),
+                            message: "Invalid from address format".to_string(),
+                        }),
+                    )
+                })?;
+
+            if from_bytes.len() != 20 {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    Json(ErrorResponse {
+                        error: "InvalidAddress".to_string(),
+                        message: "From address must be 20 bytes".to_string(),
+                    }),
+                ));
+            }
+
+            let mut from_address = [0u8; 20];
+            from_address.copy_from_slice(&from_bytes);
+
+            let to_bytes = hex::decode(to.trim_start_matches("0x"))
+                .map_err(|_| {
+                    (
+                        StatusCode::BAD_REQUEST,
+                        Json(ErrorResponse {
+                            error: "InvalidAddress".to_string(),
+                            message: "Invalid to address format".to_string(),
+                        }),
+                    )
+                })?;
+
+            if to_bytes.len() != 20 {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    Json(ErrorResponse {
+                        error: "InvalidAddress".to_string(),
+                        message: "To address must be 20 bytes".to_string(),
+                    }),
+                ));
+            }
+
+            let mut to_address = [0u8; 20];
+            to_address.copy_from_slice(&to_bytes);
+
+            let sig_bytes = hex::decode(signature.trim_start_matches("0x"))
+                .map_err(|_| {
+                    (
+                        StatusCode::BAD_REQUEST,
+                        Json(ErrorResponse {
+                            error: "InvalidSignature".to_string(),
+                            message: "Invalid signature format".to_string(),
+                        }),
+                    )
+                })?;
+
+            if sig_bytes.len() != 65 {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    Json(ErrorResponse {
+                        error: "InvalidSignature".to_string(),
+                        message: "Signature must be 65 bytes".to_string(),
+                    }),
+                ));
+            }
+
+            let tx = Tx {
+                id: 0,
+                from: from_address,
+                nonce,
+                kind: TxKind::Withdraw,
+                payload: TxPayload::Withdraw(zkclear_types::Withdraw {
+                    asset_id,
+                    amount,
+                    to: to_address,
+                    chain_id,
+                }),
+                fee: 0,
+                domain,
+                scheme: SignatureScheme::Secp256k1Recoverable,
+                signature: sig_bytes,
+            };
+
+            (tx, from_address)
+        }
+        SubmitTransactionRequest::Transfer {
+            from,
+            to,
+            asset_id,
+            amount,
+            chain_id,
+            nonce,
+            domain,
+            signature,
+        } => {
+            let from_bytes = hex::decode(from.trim_start_matches("0x"))
+                .map_err(|_| {
+                    (
+                        StatusCode::BAD_REQUEST,
+                        Json(ErrorResponse {
+                            error: "InvalidAddress".to_string(),
+                            message: "Invalid from address format".to_string(),
+                        }),
+                    )
+                })?;
+
+            if from_bytes.len() != 20 {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    Json(ErrorResponse {
+                        error: "InvalidAddress".to_string(),
+                        message: "From address must be 20 bytes".to_string(),
+                    }),
+                ));
+            }
+
+            let mut from_address = [0u8; 20];
+            from_address.copy_from_slice(&from_bytes);
+
+            let to_bytes = hex::decode(to.trim_start_matches("0x"))
+                .map_err(|_| {
+                    (
+                        StatusCode::BAD_REQUEST,
+                        Json(ErrorResponse {
+                            error: "InvalidAddress".to_string(),
+                            message: "Invalid to address format".to_string(),
+                        }),
+                    )
+                })?;
+
+            if to_bytes.len() != 20 {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    Json(ErrorResponse {
+                        error: "InvalidAddress".to_string(),
+                        message: "To address must be 20 bytes".to_string(),
+                    }),
+                ));
+            }
+
+            let mut to_address = [0u8; 20];
+            to_address.copy_from_slice(&to_bytes);
+
+            let sig_bytes = hex::decode(signature.trim_start_matches("0x"))
+                .map_err(|_| {
+                    (
+                        StatusCode::BAD_REQUEST,
+                        Json(ErrorResponse {
+                            error: "InvalidSignature".to_string(),
+                            message: "Invalid signature format".to_string(),
+                        }),
+                    )
+                })?;
+
+            if sig_bytes.len() != 65 {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    Json(ErrorResponse {
+                        error: "InvalidSignature".to_string(),
+                        message: "Signature must be 65 bytes".to_string(),
+                    }),
+                ));
+            }
+
+            let tx = Tx {
+                id: 0,
+                from: from_address,
+                nonce,
+                kind: TxKind::Transfer,
+                payload: TxPayload::Transfer(zkclear_types::Transfer {
+                    to: to_address,
+                    asset_id,
+                    amount,
+                    chain_id,
+                }),
+                fee: 0,
+                domain,
+                scheme: SignatureScheme::Secp256k1Recoverable,
+                signature: sig_bytes,
+            };
+
+            (tx, from_address)
+        }
+    };
+
+    Ok(tx)
+}
+
+/// Parse, build and enqueue a single transaction submission. A submission
+/// carrying a `request_id` already seen recently is not enqueued again and
+/// answers with the original `tx_hash` and status `duplicate`.
+fn submit_request(
+    state: &ApiState,
+    body: serde_json::Value,
+) -> Result<SubmitTransactionResponse, (StatusCode, Json<ErrorResponse>)> {
+    let request_id = parse_request_id(&body)?;
+    let request = parse_submit_request(body)?;
+    let tx = tx_from_request(state, request)?;
+
+    let sender = tx.from;
+    if let Some(wait) = sender_wait(state, &sender) {
+        return Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(ErrorResponse {
+                error: "RateLimitExceeded".to_string(),
+                message: format!(
+                    "Too many submissions from this sender, retry in {} seconds",
+                    crate::middleware::retry_after_secs(wait)
+                ),
+            }),
+        ));
+    }
+
+    // Hash before submitting, as the sequencer takes ownership of the tx
+    let tx_hash = hex::encode(zkclear_storage::tx_hash(&tx));
+
+    let submitted = match request_id {
+        Some(ref request_id) => {
+            state
+                .sequencer
+                .submit_tx_with_request_id(tx, request_id, tx_hash.clone(), true)
+        }
+        None => state
+            .sequencer
+            .submit_tx_with_validation(tx, true)
+            .map(|()| SubmitOutcome::Queued),
+    };
+
+    match submitted {
+        Ok(SubmitOutcome::Queued) => {
+            record_submission(state, &sender);
+            Ok(SubmitTransactionResponse {
+                tx_hash,
+                status: "queued".to_string(),
+            })
+        }
+        Ok(SubmitOutcome::Duplicate(tx_hash)) => Ok(SubmitTransactionResponse {
+            tx_hash,
+            status: "duplicate".to_string(),
+        }),
+        Err(zkclear_sequencer::SequencerError::QueueFull) => Err(queue_full_error()),
+        Err(zkclear_sequencer::SequencerError::WrongDomain) => Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "WrongDomain".to_string(),
+                message: format!(
+                    "Transaction domain does not match this network (expected {})",
+                    state.sequencer.network_id()
+                ),
+            }),
+        )),
+        Err(zkclear_sequencer::SequencerError::Throttled) => Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ErrorResponse {
+                error: "Throttled".to_string(),
+                message: "Transaction queue is near capacity; only higher-fee transactions are accepted".to_string(),
+            }),
+        )),
+        Err(zkclear_sequencer::SequencerError::InvalidSignature) => Err((
+            StatusCode::BAD_REQUEST,
             Json(ErrorResponse {
                 error: "InvalidSignature".to_string(),
                 message: "Transaction signature is invalid".to_string(),
             }),
-        )),
-        Err(zkclear_sequencer::SequencerError::InvalidNonce) => Err((
-            StatusCode::BAD_REQUEST,
-            Json(ErrorResponse {
-                error: "InvalidNonce".to_string(),
-                message: "Transaction nonce is invalid".to_string(),
+        )),
+        Err(zkclear_sequencer::SequencerError::InvalidNonce) => Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "InvalidNonce".to_string(),
+                message: "Transaction nonce is invalid".to_string(),
+            }),
+        )),
+        Err(zkclear_sequencer::SequencerError::TxTooLarge) => Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            Json(ErrorResponse {
+                error: "TxTooLarge".to_string(),
+                message: format!(
+                    "Transaction exceeds {} bytes encoded",
+                    state.sequencer.tx_limits().max_tx_size
+                ),
+            }),
+        )),
+        Err(zkclear_sequencer::SequencerError::TxKindDisabled) => Err((
+            StatusCode::FORBIDDEN,
+            Json(ErrorResponse {
+                error: "TxKindDisabled".to_string(),
+                message: "This transaction kind is disabled on this node".to_string(),
+            }),
+        )),
+        Err(zkclear_sequencer::SequencerError::ExecutionFailed(stf_err)) => {
+            // Extract error message from StfError
+            let error_msg = format!("{:?}", stf_err);
+            let (error_code, message): (String, String) = if error_msg.contains("BalanceTooLow") {
+                (
+                    "BalanceTooLow".to_string(),
+                    "Insufficient balance to execute this transaction. Please ensure you have enough funds on the required chain.".to_string(),
+                )
+            } else if error_msg.contains("DealNotFound") {
+                (
+                    "DealNotFound".to_string(),
+                    "The deal you are trying to accept does not exist.".to_string(),
+                )
+            } else if error_msg.contains("DealAlreadyClosed") {
+                (
+                    "DealAlreadyClosed".to_string(),
+                    "This deal has already been settled or cancelled.".to_string(),
+                )
+            } else if error_msg.contains("Unauthorized") {
+                (
+                    "Unauthorized".to_string(),
+                    "You are not authorized to perform this action on this deal.".to_string(),
+                )
+            } else if error_msg.contains("InvalidNonce") {
+                (
+                    "InvalidNonce".to_string(),
+                    "Transaction nonce is invalid. Please try again.".to_string(),
+                )
+            } else if error_msg.contains("DealExpired") {
+                (
+                    "DealExpired".to_string(),
+                    "This deal has expired.".to_string(),
+                )
+            } else if error_msg.contains("DuplicateExternalRef") {
+                (
+                    "DuplicateExternalRef".to_string(),
+                    "A deal with this external reference already exists.".to_string(),
+                )
+            } else if error_msg.contains("UnknownAsset") {
+                (
+                    "UnknownAsset".to_string(),
+                    "The deal references an asset that is not registered on that chain.".to_string(),
+                )
+            } else if error_msg.contains("InvalidTransferRecipient") {
+                (
+                    "InvalidTransferRecipient".to_string(),
+                    "Transfers must go to a non-zero address other than the sender.".to_string(),
+                )
+            } else if error_msg.contains("InvalidDealParams") {
+                (
+                    "InvalidDealParams".to_string(),
+                    "Deal amount and price must be non-zero and meet the minimum deal size.".to_string(),
+                )
+            } else if error_msg.contains("FillTooSmall") {
+                (
+                    "FillTooSmall".to_string(),
+                    "The fill amount is too small to be worth any quote units.".to_string(),
+                )
+            } else if error_msg.contains("PriceExceeded") {
+                (
+                    "PriceExceeded".to_string(),
+                    "The deal's price is above the taker's maximum price.".to_string(),
+                )
+            } else if error_msg.contains("DuplicateDeposit") {
+                (
+                    "DuplicateDeposit".to_string(),
+                    "This deposit has already been credited.".to_string(),
+                )
+            } else if error_msg.contains("TooManyOpenDeals") {
+                (
+                    "TooManyOpenDeals".to_string(),
+                    "The maker has reached the limit of open deals per account.".to_string(),
+                )
+            } else {
+                (
+                    "ExecutionFailed".to_string(),
+                    format!("Transaction execution failed: {}", error_msg),
+                )
+            };
+            
+            Err((
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse {
+                    error: error_code,
+                    message: message,
+                }),
+            ))
+        },
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: "SubmissionFailed".to_string(),
+                message: format!("Failed to submit transaction: {:?}", e),
+            }),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zkclear_types::{Address, DealStatus, Deposit, Tx};
+
+    fn test_deal(id: DealId) -> Deal {
+        Deal {
+            id,
+            maker: [1u8; 20],
+            taker: None,
+            visibility: DealVisibility::Public,
+            asset_base: 0,
+            asset_quote: 1,
+            chain_id_base: zkclear_types::chain_ids::ETHEREUM,
+            chain_id_quote: zkclear_types::chain_ids::ETHEREUM,
+            amount_base: 100,
+            amount_remaining: 100,
+            price_quote_per_base: 1,
+            status: DealStatus::Pending,
+            created_at: 0,
+            updated_at: 0,
+            expires_at: None,
+            external_ref: None,
+            is_cross_chain: false,
+            escrows: Vec::new(),
+        }
+    }
+
+    fn deposit_tx(nonce: u64) -> Tx {
+        Tx {
+            id: nonce,
+            from: [1u8; 20],
+            nonce,
+            kind: TxKind::Deposit,
+            payload: TxPayload::Deposit(Deposit {
+                tx_hash: [nonce as u8; 32],
+                account: [1u8; 20],
+                asset_id: 0,
+                amount: 100,
+                chain_id: zkclear_types::chain_ids::ETHEREUM,
+            }),
+            fee: 0,
+            domain: 0,
+            scheme: SignatureScheme::Secp256k1Recoverable,
+            signature: vec![0u8; 65],
+        }
+    }
+
+    fn test_key() -> k256::ecdsa::SigningKey {
+        k256::ecdsa::SigningKey::from_bytes(&[7u8; 32].into()).unwrap()
+    }
+
+    fn address_of(key: &k256::ecdsa::SigningKey) -> Address {
+        use k256::elliptic_curve::sec1::ToEncodedPoint;
+        use sha3::Digest;
+
+        let point = k256::PublicKey::from(key.verifying_key()).to_encoded_point(false);
+        let hash = sha3::Keccak256::digest(&point.as_bytes()[1..]);
+        let mut address = [0u8; 20];
+        address.copy_from_slice(&hash[12..]);
+        address
+    }
+
+    fn sign_tx(key: &k256::ecdsa::SigningKey, tx: &mut Tx) {
+        let (signature, recovery_id) = key.sign_prehash_recoverable(&tx.signing_hash()).unwrap();
+        tx.signature = signature.to_bytes().to_vec();
+        tx.signature.push(recovery_id.to_byte() + 27);
+    }
+
+    /// `body` with its `signature` replaced by one from `key` over the tx
+    /// it describes
+    fn signed_request(
+        key: &k256::ecdsa::SigningKey,
+        mut body: serde_json::Value,
+    ) -> serde_json::Value {
+        let request = parse_submit_request(body.clone()).unwrap();
+        let mut tx = tx_from_request(&rpc_state(Sequencer::new()), request).unwrap();
+        sign_tx(key, &mut tx);
+        body["signature"] = serde_json::json!(format!("0x{}", hex::encode(&tx.signature)));
+        body
+    }
+
+    #[tokio::test]
+    async fn test_fee_estimate_reads_kind_and_size() {
+        let sequencer = Arc::new(Sequencer::new());
+        let api_state = Arc::new(ApiState::new(sequencer.clone()));
+        let query = |pairs: &[(&str, &str)]| {
+            Query(
+                pairs
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect::<HashMap<_, _>>(),
+            )
+        };
+
+        let Json(estimate) = get_fee_estimate(
+            State(api_state.clone()),
+            query(&[("kind", "Withdraw"), ("size", "320")]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(estimate.kind, "Withdraw");
+        assert_eq!(estimate.size, 320);
+        assert_eq!(estimate.priority_fee, 320);
+        assert_eq!(estimate.fee_amount, 320);
+        assert_eq!(estimate.asset_id, None);
+
+        let Json(estimate) =
+            get_fee_estimate(State(api_state.clone()), query(&[("kind", "CancelDeal")]))
+                .await
+                .unwrap();
+        assert_eq!(estimate.size, 150);
+
+        for (pairs, expected) in [
+            (&[("kind", "Mint")][..], "UnsupportedTxKind"),
+            (&[("size", "10")][..], "MissingTxKind"),
+            (&[("kind", "Deposit"), ("size", "-1")][..], "InvalidSize"),
+        ] {
+            let (code, Json(error)) = get_fee_estimate(State(api_state.clone()), query(pairs))
+                .await
+                .unwrap_err();
+            assert_eq!(code, StatusCode::BAD_REQUEST);
+            assert_eq!(error.error, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_account_state_buckets_expired_deals() {
+        let sequencer = Arc::new(Sequencer::new());
+        let api_state = Arc::new(ApiState::new(sequencer.clone()));
+        sequencer
+            .execute_block(zkclear_types::Block {
+                id: 0,
+                transactions: vec![deposit_tx(0)],
+                timestamp: 1000,
+                state_root: [0u8; 32],
+                withdrawals_root: [0u8; 32],
+                block_proof: Vec::new(),
+            })
+            .unwrap();
+
+        {
+            let state_handle = sequencer.get_state();
+            let mut state = state_handle.lock().unwrap();
+            for (id, expires_at, status) in [
+                (1, Some(1000), DealStatus::Pending),
+                (2, Some(999), DealStatus::Pending),
+                (3, Some(500), DealStatus::Expired),
+                (4, None, DealStatus::Pending),
+                (5, None, DealStatus::Settled),
+            ] {
+                let mut deal = test_deal(id);
+                deal.expires_at = expires_at;
+                deal.status = status;
+                state.upsert_deal(deal);
+            }
+        }
+
+        let Json(response) = get_account_state(
+            State(api_state),
+            Path(format!("0x{}", hex::encode([1u8; 20]))),
+            Query(HashMap::new()),
+        )
+        .await
+        .unwrap();
+        // Deal 1 is still live at exactly its expiry; deal 2 is past it but
+        // not yet swept
+        assert_eq!(response.open_deals, vec![1, 4]);
+        assert_eq!(response.expired_deals, vec![2, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_asset_balance_across_chains() {
+        use zkclear_types::chain_ids::{ARBITRUM, BASE, ETHEREUM};
+
+        let sequencer = Arc::new(Sequencer::new());
+        let api_state = Arc::new(ApiState::new(sequencer.clone()));
+        {
+            let state_handle = sequencer.get_state();
+            let mut state = state_handle.lock().unwrap();
+            let account = state.get_or_create_account_by_owner([1u8; 20]);
+            for (chain_id, amount) in [(BASE, 300), (ETHEREUM, 100), (ARBITRUM, 200)] {
+                account.credit(0, chain_id, amount).unwrap();
+            }
+            account.credit(1, ETHEREUM, 7).unwrap();
+        }
+        let address = format!("0x{}", hex::encode([1u8; 20]));
+        let query = |pairs: &[(&str, &str)]| {
+            Query(
+                pairs
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect::<HashMap<_, _>>(),
+            )
+        };
+
+        let Json(all) = get_account_asset_balances(
+            State(api_state.clone()),
+            Path((address.clone(), 0)),
+            query(&[]),
+        )
+        .await
+        .unwrap();
+        let breakdown: Vec<_> = all
+            .balances
+            .iter()
+            .map(|b| (b.chain_id, b.amount))
+            .collect();
+        assert_eq!(
+            breakdown,
+            vec![(ETHEREUM, 100), (BASE, 300), (ARBITRUM, 200)]
+        );
+        assert_eq!(all.total, 600);
+
+        // The single-chain endpoint no longer guesses which chain is meant
+        let (code, Json(error)) = get_account_balance(
+            State(api_state.clone()),
+            Path((address.clone(), 0)),
+            query(&[]),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(code, StatusCode::BAD_REQUEST);
+        assert_eq!(error.error, "AmbiguousChain");
+
+        let arbitrum = ARBITRUM.to_string();
+        let Json(on_arbitrum) = get_account_balance(
+            State(api_state.clone()),
+            Path((address.clone(), 0)),
+            query(&[("chain_id", &arbitrum)]),
+        )
+        .await
+        .unwrap();
+        assert_eq!((on_arbitrum.chain_id, on_arbitrum.amount), (ARBITRUM, 200));
+
+        // An asset held on one chain needs no chain id
+        let Json(single) = get_account_balance(State(api_state), Path((address, 1)), query(&[]))
+            .await
+            .unwrap();
+        assert_eq!((single.chain_id, single.amount), (ETHEREUM, 7));
+    }
+
+    #[tokio::test]
+    async fn test_deals_list_served_from_read_snapshot() {
+        let sequencer = Arc::new(Sequencer::new());
+        let api_state = Arc::new(ApiState::new(sequencer.clone()));
+        let list = || get_deals_list(State(api_state.clone()), Query(HashMap::new()));
+
+        let Json(response) = list().await.unwrap();
+        assert_eq!(response.total, 0);
+        assert_eq!(response.snapshot_block_id, None);
+
+        let create_deal = Tx {
+            kind: TxKind::CreateDeal,
+            payload: TxPayload::CreateDeal(zkclear_types::CreateDeal {
+                deal_id: 1,
+                visibility: DealVisibility::Public,
+                taker: None,
+                asset_base: 0,
+                asset_quote: 1,
+                chain_id_base: zkclear_types::chain_ids::ETHEREUM,
+                chain_id_quote: zkclear_types::chain_ids::ETHEREUM,
+                amount_base: 100,
+                price_quote_per_base: 1,
+                expires_at: None,
+                external_ref: None,
+            }),
+            ..deposit_tx(1)
+        };
+        for tx in [deposit_tx(0), create_deal] {
+            sequencer.submit_tx_with_validation(tx, false).unwrap();
+        }
+        let block = sequencer.build_and_execute_block().unwrap();
+
+        // The block only shows once the snapshot is refreshed
+        let Json(response) = list().await.unwrap();
+        assert_eq!(response.total, 0);
+        sequencer.refresh_read_snapshot();
+
+        let Json(response) = get_deals_list(State(api_state), Query(HashMap::new()))
+            .await
+            .unwrap();
+        assert_eq!(response.total, 1);
+        assert_eq!(response.deals[0].deal_id, 1);
+        assert_eq!(response.snapshot_block_id, Some(block.id));
+    }
+
+    /// Follow `next_cursor` from the first page to the last, returning the
+    /// deal ids in the order they were served
+    async fn collect_deal_pages(
+        api_state: &Arc<ApiState>,
+        mut params: HashMap<String, String>,
+    ) -> Vec<DealId> {
+        let mut ids = Vec::new();
+        loop {
+            let Json(page) = get_deals_list(State(api_state.clone()), Query(params.clone()))
+                .await
+                .unwrap();
+            ids.extend(page.deals.iter().map(|deal| deal.deal_id));
+            match page.next_cursor {
+                Some(cursor) => {
+                    params.insert("after".to_string(), cursor.to_string());
+                }
+                None => return ids,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_deals_list_pagination() {
+        let sequencer = Arc::new(Sequencer::new());
+        let api_state = Arc::new(ApiState::new(sequencer.clone()));
+
+        {
+            let state_handle = sequencer.get_state();
+            let mut state = state_handle.lock().unwrap();
+            for id in 1..=250 {
+                let mut deal = test_deal(id);
+                if id % 3 == 0 {
+                    deal.maker = [2u8; 20];
+                }
+                state.upsert_deal(deal);
+            }
+        }
+        sequencer.reset_read_snapshot();
+
+        let params = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+
+        let Json(first) = get_deals_list(State(api_state.clone()), Query(HashMap::new()))
+            .await
+            .unwrap();
+        assert_eq!(first.deals.len(), 100);
+        assert_eq!(first.total, 250);
+        assert_eq!(first.next_cursor, Some(100));
+
+        let ids = collect_deal_pages(&api_state, params(&[("limit", "40")])).await;
+        assert_eq!(ids, (1..=250).collect::<Vec<_>>());
+
+        // Filters apply before paging
+        let maker = format!("0x{}", hex::encode([2u8; 20]));
+        let ids =
+            collect_deal_pages(&api_state, params(&[("limit", "7"), ("address", &maker)])).await;
+        assert_eq!(ids, (1..=250).filter(|id| id % 3 == 0).collect::<Vec<_>>());
+
+        // Oversized limits are capped, not rejected
+        let Json(all) = get_deals_list(
+            State(api_state.clone()),
+            Query(params(&[("limit", "5000")])),
+        )
+        .await
+        .unwrap();
+        assert_eq!(all.deals.len(), 250);
+        assert_eq!(all.next_cursor, None);
+
+        let (status, _) = get_deals_list(State(api_state), Query(params(&[("after", "x")])))
+            .await
+            .unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_deal_details_decimal_format() {
+        let sequencer = Arc::new(Sequencer::new());
+        let api_state = Arc::new(ApiState::new(sequencer.clone()));
+
+        let mut deal = test_deal(1);
+        deal.amount_base = 150_000_000;
+        deal.amount_remaining = 50_000_000;
+        deal.price_quote_per_base = 650;
+        {
+            let state_handle = sequencer.get_state();
+            let mut state = state_handle.lock().unwrap();
+            for (id, decimals) in [(0, 8), (1, 6)] {
+                state.register_asset(zkclear_types::Asset {
+                    id,
+                    symbol: format!("ASSET{}", id),
+                    decimals,
+                    chain_id: zkclear_types::chain_ids::ETHEREUM,
+                    contract_address: None,
+                    is_wrapped: false,
+                    original_chain_id: None,
+                });
+            }
+            state.upsert_deal(deal);
+        }
+
+        let Json(raw) = get_deal_details(State(api_state.clone()), Path(1), Query(HashMap::new()))
+            .await
+            .unwrap();
+        assert_eq!(raw.amount_base, 150_000_000);
+        assert!(raw.amount_base_formatted.is_none());
+        assert!(raw.price_quote_per_base_formatted.is_none());
+
+        let params = HashMap::from([("format".to_string(), "decimal".to_string())]);
+        let Json(formatted) = get_deal_details(State(api_state), Path(1), Query(params))
+            .await
+            .unwrap();
+        assert_eq!(formatted.amount_base, raw.amount_base);
+        assert_eq!(formatted.amount_base_formatted.as_deref(), Some("1.5"));
+        assert_eq!(formatted.amount_remaining_formatted.as_deref(), Some("0.5"));
+        assert_eq!(
+            formatted.price_quote_per_base_formatted.as_deref(),
+            Some("65000")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_deal_by_external_ref_scoped_to_maker() {
+        let sequencer = Arc::new(Sequencer::new());
+        let api_state = Arc::new(ApiState::new(sequencer.clone()));
+        let mut deal = test_deal(1);
+        deal.external_ref = Some("order-1".to_string());
+        sequencer.get_state().lock().unwrap().upsert_deal(deal);
+
+        let lookup = |maker: [u8; 20]| {
+            get_deal_by_external_ref(
+                State(api_state.clone()),
+                Path((format!("0x{}", hex::encode(maker)), "order-1".to_string())),
+                Query(HashMap::new()),
+            )
+        };
+        let Json(found) = lookup([1u8; 20]).await.unwrap();
+        assert_eq!(found.deal_id, 1);
+        let (status, _) = lookup([2u8; 20]).await.unwrap_err();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_matching_deal_query() {
+        let sequencer = Arc::new(Sequencer::new());
+        let api_state = Arc::new(ApiState::new(sequencer.clone()));
+        for (id, price) in [(1, 30), (2, 10), (3, 20)] {
+            let mut deal = test_deal(id);
+            deal.price_quote_per_base = price;
+            sequencer.get_state().lock().unwrap().upsert_deal(deal);
+        }
+
+        let query = |extra: &[(&str, &str)]| {
+            let chain = zkclear_types::chain_ids::ETHEREUM.to_string();
+            let mut params = HashMap::from([
+                ("asset_base".to_string(), "0".to_string()),
+                ("asset_quote".to_string(), "1".to_string()),
+                ("chain_id_base".to_string(), chain.clone()),
+                ("chain_id_quote".to_string(), chain),
+            ]);
+            for (key, value) in extra {
+                params.insert(key.to_string(), value.to_string());
+            }
+            Query(params)
+        };
+
+        let Json(deal) = get_matching_deal(State(api_state.clone()), query(&[]))
+            .await
+            .unwrap();
+        assert_eq!(deal.deal_id, 2);
+
+        let (code, Json(error)) =
+            get_matching_deal(State(api_state.clone()), query(&[("max_price", "5")]))
+                .await
+                .unwrap_err();
+        assert_eq!(code, StatusCode::NOT_FOUND);
+        assert_eq!(error.error, "NoMatchingDeal");
+
+        let (code, _) = get_matching_deal(State(api_state), query(&[("min_amount", "x")]))
+            .await
+            .unwrap_err();
+        assert_eq!(code, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_book_depth_aggregates_price_levels() {
+        let sequencer = Arc::new(Sequencer::new());
+        let api_state = Arc::new(ApiState::new(sequencer.clone()));
+        {
+            let state_handle = sequencer.get_state();
+            let mut state = state_handle.lock().unwrap();
+            for (id, price, remaining) in [(1, 20, 100), (2, 10, 40), (3, 20, 50), (4, 10, 60)] {
+                let mut deal = test_deal(id);
+                deal.price_quote_per_base = price;
+                deal.amount_remaining = remaining;
+                state.upsert_deal(deal);
+            }
+            // Not part of the book: private, closed, or on another pair
+            let mut direct = test_deal(5);
+            direct.visibility = DealVisibility::Direct;
+            let mut settled = test_deal(6);
+            settled.status = DealStatus::Settled;
+            let mut other_pair = test_deal(7);
+            other_pair.asset_quote = 2;
+            for mut deal in [direct, settled, other_pair] {
+                deal.price_quote_per_base = 10;
+                state.upsert_deal(deal);
+            }
+        }
+
+        let chain = zkclear_types::chain_ids::ETHEREUM.to_string();
+        let mut params = HashMap::from([
+            ("asset_base".to_string(), "0".to_string()),
+            ("asset_quote".to_string(), "1".to_string()),
+            ("chain_base".to_string(), chain.clone()),
+            ("chain_quote".to_string(), chain),
+        ]);
+        let Json(book) = get_book_depth(State(api_state.clone()), Query(params.clone()))
+            .await
+            .unwrap();
+        let levels: Vec<_> = book
+            .levels
+            .iter()
+            .map(|level| (level.price_quote_per_base, level.amount_remaining))
+            .collect();
+        assert_eq!(levels, vec![(10, 100), (20, 150)]);
+
+        params.remove("chain_quote");
+        let (code, Json(error)) = get_book_depth(State(api_state), Query(params))
+            .await
+            .unwrap_err();
+        assert_eq!(code, StatusCode::BAD_REQUEST);
+        assert_eq!(error.error, "InvalidBookQuery");
+    }
+
+    #[tokio::test]
+    async fn test_deal_fills_history() {
+        let sequencer = Arc::new(Sequencer::new());
+        let api_state = Arc::new(ApiState::new(sequencer.clone()));
+
+        {
+            let state = sequencer.get_state();
+            let mut state = state.lock().unwrap();
+            state.upsert_deal(test_deal(1));
+            for (amount_base, block_timestamp) in [(40, 10), (60, 20)] {
+                state.record_fill(zkclear_types::Fill {
+                    deal_id: 1,
+                    taker: [2u8; 20],
+                    amount_base,
+                    amount_quote: amount_base,
+                    block_timestamp,
+                });
+            }
+        }
+
+        let Json(response) = get_deal_fills(State(api_state.clone()), Path(1))
+            .await
+            .unwrap();
+        assert_eq!(response.deal_id, 1);
+        let amounts: Vec<_> = response.fills.iter().map(|f| f.amount_base).collect();
+        assert_eq!(amounts, vec![40, 60]);
+        assert_eq!(response.fills[1].block_timestamp, 20);
+
+        let (status, _) = get_deal_fills(State(api_state), Path(2)).await.unwrap_err();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_state_root_endpoint() {
+        let sequencer = Arc::new(Sequencer::new());
+        let api_state = Arc::new(ApiState::new(sequencer.clone()));
+
+        sequencer
+            .submit_tx_with_validation(deposit_tx(0), false)
+            .unwrap();
+        let block = sequencer.build_and_execute_block().unwrap();
+
+        let Json(response) = get_state_root(State(api_state)).await.unwrap();
+        assert_eq!(response.block_id, block.id);
+        assert_eq!(
+            response.state_root,
+            format!("0x{}", hex::encode(block.state_root))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_retry_with_request_id_returns_original_tx_hash() {
+        let sequencer = Arc::new(Sequencer::new());
+        let api_state = Arc::new(ApiState::new(sequencer.clone()));
+        let key = test_key();
+        let deposit = |request_id: &str| {
+            signed_request(
+                &key,
+                serde_json::json!({
+                    "kind": "Deposit",
+                    "request_id": request_id,
+                    "tx_hash": format!("0x{}", hex::encode([7u8; 32])),
+                    "account": format!("0x{}", hex::encode(address_of(&key))),
+                    "asset_id": 0,
+                    "amount": "100",
+                    "chain_id": zkclear_types::chain_ids::ETHEREUM,
+                    "nonce": 0,
+                    "signature": format!("0x{}", hex::encode([0u8; 65])),
+                }),
+            )
+        };
+        let request_id = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+
+        let first = submit_request(&api_state, deposit(request_id)).unwrap();
+        assert_eq!(first.status, "queued");
+        assert_eq!(sequencer.queue_length(), 1);
+
+        let retry = submit_request(&api_state, deposit(&request_id.to_uppercase())).unwrap();
+        assert_eq!(retry.status, "duplicate");
+        assert_eq!(retry.tx_hash, first.tx_hash);
+        assert_eq!(sequencer.queue_length(), 1);
+
+        let (status, Json(error)) = submit_request(&api_state, deposit("retry-1")).unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(error.error, "InvalidRequestId");
+    }
+
+    #[test]
+    fn test_transfer_with_forged_signature_rejected() {
+        let api_state = rpc_state(Sequencer::new());
+        let key = test_key();
+        let forger = k256::ecdsa::SigningKey::from_bytes(&[8u8; 32].into()).unwrap();
+        let transfer = serde_json::json!({
+            "kind": "Transfer",
+            "from": format!("0x{}", hex::encode(address_of(&key))),
+            "to": format!("0x{}", hex::encode([2u8; 20])),
+            "asset_id": 0,
+            "amount": "100",
+            "chain_id": zkclear_types::chain_ids::ETHEREUM,
+            "nonce": 0,
+            "signature": format!("0x{}", hex::encode([0u8; 65])),
+        });
+
+        for forged in [transfer.clone(), signed_request(&forger, transfer.clone())] {
+            let (status, Json(error)) = submit_request(&api_state, forged).unwrap_err();
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert_eq!(error.error, "InvalidSignature");
+        }
+        assert_eq!(api_state.sequencer.queue_length(), 0);
+
+        let response = submit_request(&api_state, signed_request(&key, transfer)).unwrap();
+        assert_eq!(response.status, "queued");
+    }
+
+    #[test]
+    fn test_only_verified_submissions_count_against_sender() {
+        let sequencer = Arc::new(Sequencer::new());
+        let api_state = ApiState::new(sequencer.clone()).with_rate_limit_state(Arc::new(
+            crate::middleware::RateLimitState::new(100, 60).with_sender_limit(2),
+        ));
+        let key = test_key();
+        let forger = k256::ecdsa::SigningKey::from_bytes(&[8u8; 32].into()).unwrap();
+        let transfer = |nonce: u64| {
+            serde_json::json!({
+                "kind": "Transfer",
+                "from": format!("0x{}", hex::encode(address_of(&key))),
+                "to": format!("0x{}", hex::encode([2u8; 20])),
+                "asset_id": 0,
+                "amount": "100",
+                "chain_id": zkclear_types::chain_ids::ETHEREUM,
+                "nonce": nonce,
+                "signature": format!("0x{}", hex::encode([0u8; 65])),
+            })
+        };
+
+        // Forged submissions in the sender's name don't use up its budget
+        for _ in 0..3 {
+            let (status, _) =
+                submit_request(&api_state, signed_request(&forger, transfer(0))).unwrap_err();
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+        }
+
+        for nonce in 0..2 {
+            submit_request(&api_state, signed_request(&key, transfer(nonce))).unwrap();
+        }
+        let (status, Json(error)) =
+            submit_request(&api_state, signed_request(&key, transfer(2))).unwrap_err();
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(error.error, "RateLimitExceeded");
+        assert_eq!(sequencer.queue_length(), 2);
+    }
+
+    #[test]
+    fn test_create_deal_with_bad_external_ref_rejected_before_queueing() {
+        let sequencer = Arc::new(Sequencer::new());
+        let api_state = ApiState::new(sequencer.clone());
+        let key = test_key();
+        let create_deal = |external_ref: String| {
+            serde_json::json!({
+                "kind": "CreateDeal",
+                "from": format!("0x{}", hex::encode(address_of(&key))),
+                "deal_id": 1,
+                "visibility": "Public",
+                "asset_base": 0,
+                "asset_quote": 1,
+                "chain_id_base": zkclear_types::chain_ids::ETHEREUM,
+                "chain_id_quote": zkclear_types::chain_ids::ETHEREUM,
+                "amount_base": "100",
+                "price_quote_per_base": "5",
+                "external_ref": external_ref,
+                "nonce": 0,
+                "signature": format!("0x{}", hex::encode([0u8; 65])),
+            })
+        };
+
+        for (external_ref, expected) in [
+            ("x".repeat(257), "ExternalRefTooLong"),
+            ("order\0-1".to_string(), "InvalidExternalRef"),
+        ] {
+            let (status, Json(error)) =
+                submit_request(&api_state, create_deal(external_ref)).unwrap_err();
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert_eq!(error.error, expected);
+        }
+
+        assert_eq!(sequencer.queue_length(), 0);
+
+        let response = submit_request(
+            &api_state,
+            signed_request(&key, create_deal("x".repeat(256))),
+        )
+        .unwrap();
+        assert_eq!(response.status, "queued");
+        assert_eq!(sequencer.queue_length(), 1);
+    }
+
+    #[test]
+    fn test_funded_deal_submission_opens_backed_deal() {
+        let sequencer = Arc::new(Sequencer::new());
+        let api_state = ApiState::new(sequencer.clone());
+        let key = test_key();
+        let maker = address_of(&key);
+
+        let response = submit_request(
+            &api_state,
+            signed_request(
+                &key,
+                serde_json::json!({
+                    "kind": "CreateFundedDeal",
+                    "from": format!("0x{}", hex::encode(maker)),
+                    "tx_hash": format!("0x{}", hex::encode([7u8; 32])),
+                    "deposit_asset_id": 0,
+                    "deposit_amount": "100",
+                    "deposit_chain_id": zkclear_types::chain_ids::ETHEREUM,
+                    "deal_id": 1,
+                    "visibility": "Public",
+                    "asset_base": 0,
+                    "asset_quote": 1,
+                    "chain_id_base": zkclear_types::chain_ids::ETHEREUM,
+                    "chain_id_quote": zkclear_types::chain_ids::ETHEREUM,
+                    "amount_base": "60",
+                    "price_quote_per_base": "5",
+                    "nonce": 0,
+                    "signature": format!("0x{}", hex::encode([0u8; 65])),
+                }),
+            ),
+        )
+        .unwrap();
+        assert_eq!(response.status, "queued");
+        sequencer.build_and_execute_block().unwrap();
+
+        let state = sequencer.get_state();
+        let state = state.lock().unwrap();
+        let account = state.get_account_by_address(maker).unwrap();
+        assert_eq!(
+            account.balance_of(0, zkclear_types::chain_ids::ETHEREUM),
+            40
+        );
+        assert_eq!(
+            account.reserved.get(0, zkclear_types::chain_ids::ETHEREUM),
+            60
+        );
+        assert_eq!(state.get_deal(1).unwrap().amount_remaining, 60);
+    }
+
+    #[test]
+    fn test_confirm_settlement_submission_queued() {
+        let api_state = rpc_state(Sequencer::new());
+        let key = test_key();
+        let body = signed_request(
+            &key,
+            serde_json::json!({
+                "kind": "ConfirmSettlement",
+                "from": format!("0x{}", hex::encode(address_of(&key))),
+                "deal_id": 7,
+                "nonce": 0,
+                "signature": format!("0x{}", hex::encode([0u8; 65])),
+            }),
+        );
+
+        let tx = tx_from_request(&api_state, parse_submit_request(body.clone()).unwrap()).unwrap();
+        assert_eq!(tx.kind, TxKind::ConfirmSettlement);
+        assert!(matches!(
+            tx.payload,
+            TxPayload::ConfirmSettlement(zkclear_types::ConfirmSettlement { deal_id: 7 })
+        ));
+
+        let response = submit_request(&api_state, body).unwrap();
+        assert_eq!(response.status, "queued");
+        assert_eq!(api_state.sequencer.queue_length(), 1);
+    }
+
+    #[test]
+    fn test_every_tx_kind_submittable() {
+        for kind in TxKind::ALL {
+            assert_eq!(
+                serde_json::to_value(&kind).unwrap(),
+                serde_json::json!(kind.name())
+            );
+        }
+
+        let api_state = rpc_state(Sequencer::new());
+        let key = test_key();
+        let from = format!("0x{}", hex::encode(address_of(&key)));
+        let deals = [1, 2].map(|deal_id| {
+            serde_json::json!({
+                "deal_id": deal_id,
+                "visibility": "Public",
+                "taker": null,
+                "asset_base": 0,
+                "asset_quote": 1,
+                "chain_id_base": zkclear_types::chain_ids::ETHEREUM,
+                "chain_id_quote": zkclear_types::chain_ids::ETHEREUM,
+                "amount_base": "100",
+                "price_quote_per_base": "2",
+                "expires_at": null,
+                "external_ref": null,
+            })
+        });
+        let batch = signed_request(
+            &key,
+            serde_json::json!({
+                "kind": "BatchCreateDeal",
+                "from": from,
+                "deals": deals,
+                "nonce": 0,
+                "signature": format!("0x{}", hex::encode([0u8; 65])),
             }),
-        )),
-        Err(zkclear_sequencer::SequencerError::ExecutionFailed(stf_err)) => {
-            // Extract error message from StfError
-            let error_msg = format!("{:?}", stf_err);
-            let (error_code, message): (String, String) = if error_msg.contains("BalanceTooLow") {
-                (
-                    "BalanceTooLow".to_string(),
-                    "Insufficient balance to execute this transaction. Please ensure you have enough funds on the required chain.".to_string(),
-                )
-            } else if error_msg.contains("DealNotFound") {
-                (
-                    "DealNotFound".to_string(),
-                    "The deal you are trying to accept does not exist.".to_string(),
-                )
-            } else if error_msg.contains("DealAlreadyClosed") {
-                (
-                    "DealAlreadyClosed".to_string(),
-                    "This deal has already been settled or cancelled.".to_string(),
-                )
-            } else if error_msg.contains("Unauthorized") {
-                (
-                    "Unauthorized".to_string(),
-                    "You are not authorized to perform this action on this deal.".to_string(),
-                )
-            } else if error_msg.contains("InvalidNonce") {
-                (
-                    "InvalidNonce".to_string(),
-                    "Transaction nonce is invalid. Please try again.".to_string(),
-                )
-            } else if error_msg.contains("DealExpired") {
-                (
-                    "DealExpired".to_string(),
-                    "This deal has expired.".to_string(),
-                )
-            } else {
-                (
-                    "ExecutionFailed".to_string(),
-                    format!("Transaction execution failed: {}", error_msg),
-                )
-            };
-            
-            Err((
-                StatusCode::BAD_REQUEST,
-                Json(ErrorResponse {
-                    error: error_code,
-                    message: message,
+        );
+        let cancel_all = signed_request(
+            &key,
+            serde_json::json!({
+                "kind": "CancelAllDeals",
+                "from": from,
+                "asset_base": 0,
+                "nonce": 1,
+                "signature": format!("0x{}", hex::encode([0u8; 65])),
+            }),
+        );
+
+        let tx = tx_from_request(&api_state, parse_submit_request(batch.clone()).unwrap()).unwrap();
+        let TxPayload::BatchCreateDeal(ref payload) = tx.payload else {
+            panic!("expected a BatchCreateDeal, got {:?}", tx.payload);
+        };
+        assert_eq!(tx.kind, TxKind::BatchCreateDeal);
+        assert_eq!(
+            payload
+                .deals
+                .iter()
+                .map(|deal| deal.deal_id)
+                .collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+
+        let tx = tx_from_request(
+            &api_state,
+            parse_submit_request(cancel_all.clone()).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(tx.kind, TxKind::CancelAllDeals);
+        assert!(matches!(
+            tx.payload,
+            TxPayload::CancelAllDeals(zkclear_types::CancelAllDeals {
+                asset_base: Some(0)
+            })
+        ));
+
+        for body in [batch, cancel_all] {
+            assert_eq!(submit_request(&api_state, body).unwrap().status, "queued");
+        }
+        assert_eq!(api_state.sequencer.queue_length(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_batch_submission_reports_each_entry() {
+        let sequencer = Arc::new(Sequencer::with_config(2, 10));
+        let api_state = Arc::new(ApiState::new(sequencer.clone()));
+
+        let key = test_key();
+        let deposit = |nonce: u64| {
+            signed_request(
+                &key,
+                serde_json::json!({
+                    "kind": "Deposit",
+                    "tx_hash": format!("0x{}", hex::encode([nonce as u8; 32])),
+                    "account": format!("0x{}", hex::encode(address_of(&key))),
+                    "asset_id": 0,
+                    "amount": "100",
+                    "chain_id": zkclear_types::chain_ids::ETHEREUM,
+                    "nonce": nonce,
+                    "signature": format!("0x{}", hex::encode([0u8; 65])),
                 }),
-            ))
-        },
-        Err(e) => Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: "SubmissionFailed".to_string(),
-                message: format!("Failed to submit transaction: {:?}", e),
+            )
+        };
+        let malformed = serde_json::json!({
+            "kind": "CancelDeal",
+            "from": "0x1234",
+            "deal_id": 1,
+            "nonce": 0,
+            "signature": format!("0x{}", hex::encode([0u8; 65])),
+        });
+
+        let Json(results) = submit_transaction_batch(
+            State(api_state),
+            Json(vec![
+                deposit(0),
+                malformed,
+                deposit(1),
+                deposit(2),
+                deposit(3),
+            ]),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(results.len(), 5);
+        assert!(matches!(
+            &results[0],
+            BatchTransactionResult::Queued { index: 0, status, .. } if status == "queued"
+        ));
+        assert!(matches!(
+            &results[1],
+            BatchTransactionResult::Rejected { index: 1, error, .. } if error == "InvalidAddress"
+        ));
+        assert!(matches!(
+            &results[2],
+            BatchTransactionResult::Queued { index: 2, .. }
+        ));
+        for (i, result) in results.iter().enumerate().skip(3) {
+            assert!(matches!(
+                result,
+                BatchTransactionResult::Rejected { index, error, .. }
+                    if *index == i && error == "QueueFull"
+            ));
+        }
+        assert_eq!(sequencer.queue_length(), 2);
+
+        let json = serde_json::to_value(&results[1]).unwrap();
+        assert_eq!(json["index"], 1);
+        assert!(json.get("tx_hash").is_none());
+        assert!(json["message"].is_string());
+    }
+
+    #[tokio::test]
+    async fn test_tx_limits_match_direct_submission() {
+        use zkclear_sequencer::security::encoded_tx_size;
+        use zkclear_sequencer::{SequencerError, TxLimits};
+
+        let api_state_with = |limits: TxLimits| {
+            let sequencer = Arc::new(Sequencer::new().with_tx_limits(limits));
+            Arc::new(ApiState::new(sequencer.clone()))
+        };
+        let key = test_key();
+        let body = signed_request(
+            &key,
+            serde_json::json!({
+                "kind": "Deposit",
+                "tx_hash": format!("0x{}", hex::encode([0u8; 32])),
+                "account": format!("0x{}", hex::encode(address_of(&key))),
+                "asset_id": 0,
+                "amount": "100",
+                "chain_id": zkclear_types::chain_ids::ETHEREUM,
+                "nonce": 0,
+                "signature": format!("0x{}", hex::encode([0u8; 65])),
             }),
-        )),
+        );
+        // The tx the API builds from `body`
+        let tx = deposit_tx(0);
+        let size = encoded_tx_size(&tx);
+
+        let at_limit = api_state_with(TxLimits {
+            max_tx_size: size,
+            ..Default::default()
+        });
+        let Json(response) = submit_transaction(State(at_limit.clone()), Json(body.clone()))
+            .await
+            .unwrap();
+        assert_eq!(response.status, "queued");
+
+        let over_limit = api_state_with(TxLimits {
+            max_tx_size: size - 1,
+            ..Default::default()
+        });
+        let (code, Json(error)) = submit_transaction(State(over_limit.clone()), Json(body.clone()))
+            .await
+            .unwrap_err();
+        assert_eq!(code, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(error.error, "TxTooLarge");
+        assert!(matches!(
+            over_limit.sequencer.submit_tx_with_validation(tx, false),
+            Err(SequencerError::TxTooLarge)
+        ));
+
+        let small_batches = api_state_with(TxLimits {
+            max_batch_size: 1,
+            ..Default::default()
+        });
+        let (code, Json(error)) =
+            submit_transaction_batch(State(small_batches.clone()), Json(vec![body.clone(), body]))
+                .await
+                .unwrap_err();
+        assert_eq!(code, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(error.error, "BatchTooLarge");
+        assert_eq!(small_batches.sequencer.queue_length(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_blocks_list_newest_first() {
+        let storage: Arc<dyn Storage> = Arc::new(zkclear_storage::InMemoryStorage::new());
+        let sequencer = Arc::new(Sequencer::with_storage_arc(storage.clone()).unwrap());
+        let api_state = Arc::new(ApiState::new(sequencer.clone()).with_storage(storage));
+
+        // Block i holds i deposits
+        let mut nonce = 0;
+        for block_id in 1..=5 {
+            for _ in 0..block_id {
+                sequencer
+                    .submit_tx_with_validation(deposit_tx(nonce), false)
+                    .unwrap();
+                nonce += 1;
+            }
+            sequencer.build_and_execute_block().unwrap();
+        }
+
+        let list = |query: &[(&str, &str)]| {
+            let params = query
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            get_blocks_list(State(api_state.clone()), Query(params))
+        };
+        let ids = |response: &BlockListResponse| {
+            response
+                .blocks
+                .iter()
+                .map(|b| b.block_id)
+                .collect::<Vec<_>>()
+        };
+
+        let Json(all) = list(&[]).await.unwrap();
+        assert_eq!(all.latest_block_id, Some(5));
+        assert_eq!(ids(&all), vec![5, 4, 3, 2, 1]);
+        let counts: Vec<usize> = all.blocks.iter().map(|b| b.transaction_count).collect();
+        assert_eq!(counts, vec![5, 4, 3, 2, 1]);
+        assert!(all.blocks.iter().all(|b| !b.has_proof));
+
+        let Json(latest) = list(&[("limit", "2")]).await.unwrap();
+        assert_eq!(ids(&latest), vec![5, 4]);
+        let Json(oldest) = list(&[("limit", "2"), ("order", "asc")]).await.unwrap();
+        assert_eq!(ids(&oldest), vec![1, 2]);
+        let Json(range) = list(&[("from", "2"), ("to", "4")]).await.unwrap();
+        assert_eq!(ids(&range), vec![4, 3, 2]);
+
+        let (code, _) = list(&[("from", "x")]).await.unwrap_err();
+        assert_eq!(code, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_blocks_list_requires_storage() {
+        let sequencer = Arc::new(Sequencer::new());
+        let api_state = Arc::new(ApiState::new(sequencer.clone()));
+
+        let (code, Json(error)) = get_blocks_list(State(api_state), Query(HashMap::new()))
+            .await
+            .unwrap_err();
+        assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(error.error, "StorageNotAvailable");
+    }
+
+    #[tokio::test]
+    async fn test_raw_block_includes_proof_and_roots() {
+        let storage: Arc<dyn Storage> = Arc::new(zkclear_storage::InMemoryStorage::new());
+        let sequencer = Arc::new(
+            Sequencer::with_storage_arc(storage.clone())
+                .unwrap()
+                .with_prover_config(zkclear_prover::ProverConfig::default())
+                .unwrap(),
+        );
+        let api_state = Arc::new(ApiState::new(sequencer.clone()).with_storage(storage));
+
+        sequencer
+            .submit_tx_with_validation(deposit_tx(0), false)
+            .unwrap();
+        let block = sequencer.build_and_execute_block_with_proof(true).unwrap();
+        assert!(!block.block_proof.is_empty());
+
+        let response = get_raw_block(State(api_state.clone()), Path(block.id), HeaderMap::new())
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let raw: RawBlockResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(raw.block_id, block.id);
+        assert_eq!(
+            raw.state_root,
+            format!("0x{}", hex::encode(block.state_root))
+        );
+        assert_eq!(
+            raw.withdrawals_root,
+            format!("0x{}", hex::encode(block.withdrawals_root))
+        );
+        assert_eq!(
+            raw.block_proof,
+            format!("0x{}", hex::encode(&block.block_proof))
+        );
+        assert_eq!(raw.transactions.len(), 1);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, "application/octet-stream".parse().unwrap());
+        let response = get_raw_block(State(api_state), Path(block.id), headers)
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let decoded: zkclear_types::Block = bincode::deserialize(&body).unwrap();
+        assert_eq!(decoded.block_proof, block.block_proof);
+        assert_eq!(decoded.state_root, block.state_root);
+    }
+
+    #[tokio::test]
+    async fn test_account_nonce_counts_queued_txs() {
+        let sequencer = Arc::new(Sequencer::new());
+        let api_state = Arc::new(ApiState::new(sequencer.clone()));
+        let nonce_of =
+            |address: &str| get_account_nonce(State(api_state.clone()), Path(address.to_string()));
+
+        let Json(unknown) = nonce_of("0x0202020202020202020202020202020202020202")
+            .await
+            .unwrap();
+        assert_eq!(unknown.address, [2u8; 20]);
+        assert_eq!(unknown.nonce, 0);
+
+        for nonce in 0..3 {
+            sequencer
+                .submit_tx_with_validation(deposit_tx(nonce), false)
+                .unwrap();
+        }
+        sequencer.build_and_execute_block().unwrap();
+        let known = hex::encode([1u8; 20]);
+        assert_eq!(nonce_of(&known).await.unwrap().0.nonce, 3);
+
+        // Queued but not yet executed txs are already spoken for
+        for nonce in 3..5 {
+            sequencer
+                .submit_tx_with_validation(deposit_tx(nonce), false)
+                .unwrap();
+        }
+        assert_eq!(nonce_of(&known).await.unwrap().0.nonce, 5);
+
+        let (status, _) = nonce_of("0x1234").await.unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_transaction_lookup_by_hash() {
+        let storage: Arc<dyn Storage> = Arc::new(zkclear_storage::InMemoryStorage::new());
+        let sequencer = Arc::new(Sequencer::with_storage_arc(storage.clone()).unwrap());
+        let api_state = Arc::new(ApiState::new(sequencer.clone()).with_storage(storage));
+
+        for nonce in 0..3 {
+            sequencer
+                .submit_tx_with_validation(deposit_tx(nonce), false)
+                .unwrap();
+        }
+        let block = sequencer.build_and_execute_block().unwrap();
+        assert_eq!(block.transactions.len(), 3);
+
+        for (index, tx) in block.transactions.iter().enumerate() {
+            let tx_hash = hex::encode(zkclear_storage::tx_hash(tx));
+            let Json(found) =
+                get_transaction_by_hash(State(api_state.clone()), Path(tx_hash.clone()))
+                    .await
+                    .unwrap();
+            assert_eq!(found.tx_hash, format!("0x{}", tx_hash));
+            assert_eq!(found.block_id, block.id);
+            assert_eq!(found.index, index);
+            assert_eq!(found.transaction.nonce, tx.nonce);
+        }
+
+        let (status, Json(error)) =
+            get_transaction_by_hash(State(api_state.clone()), Path(hex::encode([0xEE; 32])))
+                .await
+                .unwrap_err();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(error.error, "TransactionNotFound");
+
+        let (status, _) = get_transaction_by_hash(State(api_state), Path("0x1234".to_string()))
+            .await
+            .unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_block_proof_round_trips_through_storage() {
+        let storage: Arc<dyn Storage> = Arc::new(zkclear_storage::InMemoryStorage::new());
+        let sequencer = Arc::new(
+            Sequencer::with_storage_arc(storage.clone())
+                .unwrap()
+                .with_prover_config(zkclear_prover::ProverConfig::default())
+                .unwrap(),
+        );
+        let api_state = Arc::new(ApiState::new(sequencer.clone()).with_storage(storage.clone()));
+
+        sequencer
+            .submit_tx_with_validation(deposit_tx(0), false)
+            .unwrap();
+        let proven = sequencer.build_and_execute_block_with_proof(true).unwrap();
+        assert!(!proven.block_proof.is_empty());
+        assert_eq!(
+            storage.get_block_proof(proven.id).unwrap().unwrap(),
+            proven.block_proof
+        );
+
+        let response = get_block_proof(State(api_state.clone()), Path(proven.id))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let proof: BlockProofResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(proof.block_id, proven.id);
+        assert_eq!(proof.proof_len, proven.block_proof.len());
+        assert_eq!(
+            hex::decode(proof.proof_hex.trim_start_matches("0x")).unwrap(),
+            proven.block_proof
+        );
+        assert_eq!(
+            proof.state_root,
+            format!("0x{}", hex::encode(proven.state_root))
+        );
+
+        sequencer
+            .submit_tx_with_validation(deposit_tx(1), false)
+            .unwrap();
+        let unproven = sequencer.build_and_execute_block().unwrap();
+        let response = get_block_proof(State(api_state.clone()), Path(unproven.id))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let (status, _) = get_block_proof(State(api_state), Path(unproven.id + 1))
+            .await
+            .unwrap_err();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    fn withdraw_tx(nonce: u64, amount: u128) -> Tx {
+        Tx {
+            id: nonce,
+            from: [1u8; 20],
+            nonce,
+            kind: TxKind::Withdraw,
+            payload: TxPayload::Withdraw(zkclear_types::Withdraw {
+                asset_id: 0,
+                amount,
+                to: [1u8; 20],
+                chain_id: zkclear_types::chain_ids::ETHEREUM,
+            }),
+            fee: 0,
+            domain: 0,
+            scheme: SignatureScheme::Secp256k1Recoverable,
+            signature: vec![0u8; 65],
+        }
+    }
+
+    fn decode_hash(value: &str) -> [u8; 32] {
+        hex::decode(value.trim_start_matches("0x"))
+            .unwrap()
+            .try_into()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_withdrawal_proofs_reconstruct_root() {
+        use zkclear_prover::merkle::{verify_merkle_proof, MerkleProof};
+
+        let storage: Arc<dyn Storage> = Arc::new(zkclear_storage::InMemoryStorage::new());
+        let sequencer = Arc::new(Sequencer::with_storage_arc(storage.clone()).unwrap());
+        let api_state = Arc::new(ApiState::new(sequencer.clone()).with_storage(storage));
+
+        for tx in [deposit_tx(0), withdraw_tx(1, 30), withdraw_tx(2, 20)] {
+            sequencer.submit_tx_with_validation(tx, false).unwrap();
+        }
+        let block = sequencer.build_and_execute_block().unwrap();
+
+        // Leaves are sorted by amount here, so the later, smaller withdrawal
+        // comes first
+        for (tx_index, leaf_index) in [(1, 1), (2, 0)] {
+            let Json(proof) =
+                get_withdrawal_proof(State(api_state.clone()), Path((block.id, tx_index)))
+                    .await
+                    .unwrap();
+            assert_eq!(proof.index, leaf_index);
+            assert_eq!(
+                proof.root,
+                format!("0x{}", hex::encode(block.withdrawals_root))
+            );
+
+            let merkle_proof = MerkleProof {
+                leaf_index: proof.index,
+                siblings: proof.siblings.iter().map(|s| decode_hash(s)).collect(),
+            };
+            assert!(verify_merkle_proof(
+                &decode_hash(&proof.leaf),
+                &merkle_proof,
+                &block.withdrawals_root,
+            ));
+        }
+
+        // The deposit and an out-of-range position have no withdrawal
+        for tx_index in [0, 3] {
+            let (status, _) =
+                get_withdrawal_proof(State(api_state.clone()), Path((block.id, tx_index)))
+                    .await
+                    .unwrap_err();
+            assert_eq!(status, StatusCode::NOT_FOUND);
+        }
+    }
+
+    fn rpc_state(sequencer: Sequencer) -> Arc<ApiState> {
+        let sequencer = Arc::new(sequencer);
+        Arc::new(ApiState::new(sequencer))
+    }
+
+    /// Deposit from the address of `key`, signed and wrapped in an envelope
+    fn signed_envelope(key: &k256::ecdsa::SigningKey, nonce: u64) -> String {
+        let from = address_of(key);
+        let mut tx = Tx {
+            from,
+            payload: TxPayload::Deposit(Deposit {
+                tx_hash: [nonce as u8; 32],
+                account: from,
+                asset_id: 0,
+                amount: 100,
+                chain_id: zkclear_types::chain_ids::ETHEREUM,
+            }),
+            ..deposit_tx(nonce)
+        };
+        sign_tx(key, &mut tx);
+        format!("0x{}", hex::encode(encode_tx_envelope(&tx).unwrap()))
+    }
+
+    #[tokio::test]
+    async fn test_jsonrpc_submit_tx_accepts_bare_bincode() {
+        let key = test_key();
+        let state = rpc_state(Sequencer::with_config(1, 10));
+        let envelope = hex::decode(signed_envelope(&key, 0).trim_start_matches("0x")).unwrap();
+        let tx = decode_tx(&envelope).unwrap();
+
+        let bare = format!("0x{}", hex::encode(bincode::serialize(&tx).unwrap()));
+        let response = submit_over_rpc(&state, &bare).await;
+        assert!(response.error.is_none());
+        assert_eq!(
+            response.result.unwrap()["tx_hash"],
+            hex::encode(zkclear_storage::tx_hash(&tx))
+        );
+        assert_eq!(state.sequencer.queue_length(), 1);
+    }
+
+    async fn submit_over_rpc(state: &Arc<ApiState>, tx: &str) -> JsonRpcResponse {
+        let Json(response) = jsonrpc_handler(
+            State(state.clone()),
+            Json(JsonRpcRequest {
+                jsonrpc: "2.0".to_string(),
+                method: "submit_tx".to_string(),
+                params: serde_json::json!({ "tx": tx }),
+                id: Some(serde_json::json!(1)),
+            }),
+        )
+        .await;
+        response
+    }
+
+    fn rpc_error_code(response: &JsonRpcResponse) -> i32 {
+        assert!(response.result.is_none());
+        response.error.as_ref().expect("error response").code
+    }
+
+    #[tokio::test]
+    async fn test_jsonrpc_submit_tx_error_codes() {
+        let key = test_key();
+        let state = rpc_state(Sequencer::with_config(1, 10));
+
+        let response = submit_over_rpc(&state, "0xnot-hex").await;
+        assert_eq!(rpc_error_code(&response), -32602);
+        assert_eq!(
+            response.error.unwrap().message,
+            "Invalid params: 'tx' must be valid hex"
+        );
+
+        let response = submit_over_rpc(&state, &signed_envelope(&key, 0)).await;
+        assert!(response.error.is_none());
+        assert_eq!(response.result.unwrap()["status"], "queued");
+        assert_eq!(response.id, Some(serde_json::json!(1)));
+
+        // Nonce 0 is already queued
+        let response = submit_over_rpc(&state, &signed_envelope(&key, 0)).await;
+        assert_eq!(rpc_error_code(&response), -32002);
+
+        // The next nonce is valid, but the queue only holds one tx
+        let response = submit_over_rpc(&state, &signed_envelope(&key, 1)).await;
+        assert_eq!(rpc_error_code(&response), -32000);
+        assert_eq!(state.sequencer.queue_length(), 1);
+    }
+
+    #[test]
+    fn test_rpc_error_data_carries_envelope_details() {
+        let error = RpcError::from(EnvelopeError::UnsupportedTxKind(0xEE)).to_jsonrpc();
+        assert_eq!(error.code, -32004);
+        assert_eq!(error.data, Some(serde_json::json!({ "kind": 0xEE })));
+
+        let error = RpcError::from(EnvelopeError::Truncated).to_jsonrpc();
+        assert_eq!(error.code, -32602);
+        assert!(error.data.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_block_diff_lists_only_touched_entries() {
+        let storage: Arc<dyn Storage> = Arc::new(zkclear_storage::InMemoryStorage::new());
+        let sequencer = Arc::new(Sequencer::with_storage_arc(storage.clone()).unwrap());
+        let api_state = Arc::new(ApiState::new(sequencer.clone()).with_storage(storage));
+
+        let maker = [1u8; 20];
+        let other = [2u8; 20];
+        let deposit = |owner: Address, nonce: u64| Tx {
+            from: owner,
+            payload: TxPayload::Deposit(Deposit {
+                tx_hash: [owner[0] + 10 * nonce as u8; 32],
+                account: owner,
+                asset_id: 0,
+                amount: 100,
+                chain_id: zkclear_types::chain_ids::ETHEREUM,
+            }),
+            ..deposit_tx(nonce)
+        };
+        let create_deal = Tx {
+            kind: TxKind::CreateDeal,
+            payload: TxPayload::CreateDeal(zkclear_types::CreateDeal {
+                deal_id: 1,
+                visibility: DealVisibility::Public,
+                taker: None,
+                asset_base: 0,
+                asset_quote: 1,
+                chain_id_base: zkclear_types::chain_ids::ETHEREUM,
+                chain_id_quote: zkclear_types::chain_ids::ETHEREUM,
+                amount_base: 10,
+                price_quote_per_base: 1,
+                expires_at: None,
+                external_ref: None,
+            }),
+            ..deposit_tx(1)
+        };
+
+        for tx in [deposit(maker, 0), deposit(other, 0), create_deal] {
+            sequencer.submit_tx_with_validation(tx, false).unwrap();
+        }
+        let first = sequencer.build_and_execute_block().unwrap();
+        sequencer
+            .submit_tx_with_validation(deposit(other, 1), false)
+            .unwrap();
+        let second = sequencer.build_and_execute_block().unwrap();
+
+        let Json(diff) = get_block_diff(State(api_state.clone()), Path(first.id))
+            .await
+            .unwrap();
+        let owners: Vec<Address> = diff.accounts.iter().map(|a| a.address).collect();
+        assert_eq!(owners, vec![maker, other]);
+        assert_eq!(diff.deals.len(), 1);
+        assert_eq!(diff.deals[0].status, "Pending");
+        assert_eq!(diff.deals[0].amount_remaining, 10);
+
+        // Only the second deposit's account changed in the next block
+        let Json(diff) = get_block_diff(State(api_state.clone()), Path(second.id))
+            .await
+            .unwrap();
+        assert_eq!(diff.accounts.len(), 1);
+        assert_eq!(diff.accounts[0].address, other);
+        assert_eq!(diff.accounts[0].nonce, 2);
+        assert_eq!(diff.accounts[0].balances[0].amount, 200);
+        assert!(diff.deals.is_empty());
+        assert!(diff.removed_accounts.is_empty() && diff.removed_deals.is_empty());
+
+        let (code, Json(error)) = get_block_diff(State(api_state), Path(second.id + 1))
+            .await
+            .unwrap_err();
+        assert_eq!(code, StatusCode::NOT_FOUND);
+        assert_eq!(error.error, "DiffNotFound");
     }
 }
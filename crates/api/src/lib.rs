@@ -1,8 +1,12 @@
+mod admin;
+mod assets;
 mod handlers;
 mod middleware;
 mod routes;
 mod types;
+mod ws;
 
+pub use assets::AssetDecimals;
 pub use handlers::ApiState;
-pub use routes::create_router;
 pub use middleware::RateLimitState;
+pub use routes::create_router;
@@ -6,10 +6,18 @@ use zkclear_api::{create_router, ApiState};
 use zkclear_prover::{Prover, ProverConfig};
 use zkclear_sequencer::Sequencer;
 use zkclear_sequencer::SequencerError;
+use zkclear_sequencer::TxLimits;
+use zkclear_sequencer::{
+    FeePolicy, GenesisConfig, OrderingPolicy, QuoteRounding, StfConfig,
+    WithdrawalDestinationPolicy, DEFAULT_MAX_BATCH_DEALS, DEFAULT_MAX_CANCEL_ALL_DEALS,
+    DEFAULT_MAX_EXTERNAL_REF_LEN, DEFAULT_SETTLEMENT_TIMEOUT_SECONDS,
+};
+use zkclear_state::HashAlgo;
 #[cfg(not(feature = "rocksdb"))]
 use zkclear_storage::InMemoryStorage;
 #[cfg(feature = "rocksdb")]
 use zkclear_storage::RocksDBStorage;
+use zkclear_types::TxKind;
 use zkclear_watcher::{Watcher, WatcherConfig};
 
 fn get_block_interval_seconds() -> u64 {
@@ -19,16 +27,290 @@ fn get_block_interval_seconds() -> u64 {
         .unwrap_or(zkclear_sequencer::config::DEFAULT_BLOCK_INTERVAL_SECONDS)
 }
 
+fn get_read_snapshot_refresh_seconds() -> u64 {
+    std::env::var("READ_SNAPSHOT_REFRESH_SEC")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(zkclear_sequencer::config::DEFAULT_READ_SNAPSHOT_REFRESH_SECONDS)
+}
+
+fn get_replica_sync_seconds() -> u64 {
+    std::env::var("REPLICA_SYNC_SEC")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(zkclear_sequencer::config::DEFAULT_REPLICA_SYNC_SECONDS)
+}
+
+fn get_checkpoint_interval_blocks() -> u64 {
+    std::env::var("CHECKPOINT_INTERVAL_BLOCKS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(zkclear_sequencer::config::DEFAULT_CHECKPOINT_INTERVAL)
+}
+
+fn get_snapshot_retention() -> usize {
+    std::env::var("SNAPSHOT_RETENTION")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(zkclear_sequencer::config::DEFAULT_SNAPSHOT_RETENTION)
+}
+
+fn parse_tx_kind(kind: &str) -> Result<TxKind, Box<dyn std::error::Error>> {
+    match kind.to_lowercase().as_str() {
+        "deposit" => Ok(TxKind::Deposit),
+        "withdraw" => Ok(TxKind::Withdraw),
+        "createdeal" => Ok(TxKind::CreateDeal),
+        "acceptdeal" => Ok(TxKind::AcceptDeal),
+        "canceldeal" => Ok(TxKind::CancelDeal),
+        "transfer" => Ok(TxKind::Transfer),
+        "declinedeal" => Ok(TxKind::DeclineDeal),
+        "createfundeddeal" => Ok(TxKind::CreateFundedDeal),
+        "confirmsettlement" => Ok(TxKind::ConfirmSettlement),
+        "batchcreatedeal" => Ok(TxKind::BatchCreateDeal),
+        "cancelalldeals" => Ok(TxKind::CancelAllDeals),
+        other => Err(format!("Unknown tx kind in ENABLED_TX_KINDS: {}", other).into()),
+    }
+}
+
+fn get_stf_config() -> Result<StfConfig, Box<dyn std::error::Error>> {
+    let policy = std::env::var("WITHDRAWAL_DESTINATION_POLICY").unwrap_or_default();
+    let withdrawal_destination_policy = match policy.to_lowercase().as_str() {
+        "" | "any" => WithdrawalDestinationPolicy::AnyNonZero,
+        "sender" => WithdrawalDestinationPolicy::SenderOnly,
+        "allowlist" => {
+            let mut allowlist = std::collections::HashSet::new();
+            for entry in std::env::var("WITHDRAWAL_ALLOWLIST")
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|e| !e.is_empty())
+            {
+                allowlist.insert(parse_address("WITHDRAWAL_ALLOWLIST entry", entry)?);
+            }
+            WithdrawalDestinationPolicy::Allowlist(allowlist)
+        }
+        other => return Err(format!("Unknown WITHDRAWAL_DESTINATION_POLICY: {}", other).into()),
+    };
+
+    let enabled_tx_kinds = match std::env::var("ENABLED_TX_KINDS") {
+        Ok(kinds) if !kinds.trim().is_empty() => {
+            let mut enabled = std::collections::HashSet::new();
+            for kind in kinds.split(',').map(str::trim).filter(|k| !k.is_empty()) {
+                enabled.insert(parse_tx_kind(kind)?);
+            }
+            Some(enabled)
+        }
+        _ => None,
+    };
+
+    let quote_rounding = match std::env::var("QUOTE_ROUNDING")
+        .unwrap_or_default()
+        .to_lowercase()
+        .as_str()
+    {
+        "" | "up" => QuoteRounding::QuoteUp,
+        "down" => QuoteRounding::QuoteDown,
+        "banker" => QuoteRounding::Banker,
+        other => return Err(format!("Unknown QUOTE_ROUNDING: {}", other).into()),
+    };
+
+    let price_scale = match std::env::var("PRICE_SCALE") {
+        Ok(value) if !value.trim().is_empty() => match value.trim().parse::<u128>() {
+            Ok(scale) if scale > 0 => scale,
+            _ => {
+                return Err(format!("PRICE_SCALE must be a positive integer, got {}", value).into())
+            }
+        },
+        _ => 1,
+    };
+
+    let settlement_confirmer = match std::env::var("SETTLEMENT_CONFIRMER") {
+        Ok(address) if !address.trim().is_empty() => {
+            Some(parse_address("SETTLEMENT_CONFIRMER", address.trim())?)
+        }
+        _ => None,
+    };
+    let deposit_watcher = match std::env::var("DEPOSIT_WATCHER") {
+        Ok(address) if !address.trim().is_empty() => {
+            Some(parse_address("DEPOSIT_WATCHER", address.trim())?)
+        }
+        _ => None,
+    };
+
+    Ok(StfConfig {
+        withdrawal_destination_policy,
+        enabled_tx_kinds,
+        price_scale,
+        quote_rounding,
+        fee: get_fee_policy()?,
+        deposit_dedup_retention_seconds: std::env::var("DEPOSIT_DEDUP_RETENTION_SECONDS")
+            .ok()
+            .and_then(|v| v.parse().ok()),
+        min_notional: std::env::var("MIN_DEAL_NOTIONAL")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0),
+        max_open_deals_per_account: std::env::var("MAX_OPEN_DEALS_PER_ACCOUNT")
+            .ok()
+            .and_then(|v| v.parse().ok()),
+        max_external_ref_len: std::env::var("MAX_EXTERNAL_REF_LEN")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_MAX_EXTERNAL_REF_LEN),
+        decimal_aware_prices: env_flag("DECIMAL_AWARE_PRICES"),
+        settlement_confirmer,
+        settlement_timeout_seconds: std::env::var("SETTLEMENT_TIMEOUT_SECONDS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_SETTLEMENT_TIMEOUT_SECONDS),
+        deposit_watcher,
+        max_batch_deals: std::env::var("MAX_BATCH_DEALS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_MAX_BATCH_DEALS),
+        max_cancel_all_deals: std::env::var("MAX_CANCEL_ALL_DEALS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_MAX_CANCEL_ALL_DEALS),
+    })
+}
+
+/// Parse `TX_FEE` as `asset_id:chain_id:amount`
+fn get_fee_policy() -> Result<Option<FeePolicy>, Box<dyn std::error::Error>> {
+    let value = match std::env::var("TX_FEE") {
+        Ok(value) if !value.trim().is_empty() => value,
+        _ => return Ok(None),
+    };
+
+    let parts: Vec<&str> = value.trim().split(':').collect();
+    let [asset_id, chain_id, amount] = parts[..] else {
+        return Err(format!("TX_FEE must be asset_id:chain_id:amount, got {}", value).into());
+    };
+    let invalid = |e: std::num::ParseIntError| format!("Invalid TX_FEE {}: {}", value, e);
+
+    Ok(Some(FeePolicy {
+        asset_id: asset_id.parse().map_err(invalid)?,
+        chain_id: chain_id.parse().map_err(invalid)?,
+        amount: amount.parse().map_err(invalid)?,
+    }))
+}
+
+/// Parse `REGISTERED_ASSETS` as comma-separated
+/// `asset_id:chain_id:symbol:decimals` entries, e.g. `1:1:USDC:6,1:8453:USDC:6`
+fn get_registered_assets() -> Result<Vec<zkclear_types::Asset>, Box<dyn std::error::Error>> {
+    let value = std::env::var("REGISTERED_ASSETS").unwrap_or_default();
+    let mut assets = Vec::new();
+    for entry in value.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let parts: Vec<&str> = entry.split(':').collect();
+        let [asset_id, chain_id, symbol, decimals] = parts[..] else {
+            return Err(format!(
+                "REGISTERED_ASSETS entries must be asset_id:chain_id:symbol:decimals, got {}",
+                entry
+            )
+            .into());
+        };
+        let invalid = |e: std::num::ParseIntError| {
+            format!("Invalid REGISTERED_ASSETS entry {}: {}", entry, e)
+        };
+
+        assets.push(zkclear_types::Asset {
+            id: asset_id.parse().map_err(invalid)?,
+            symbol: symbol.to_string(),
+            decimals: decimals.parse().map_err(invalid)?,
+            chain_id: chain_id.parse().map_err(invalid)?,
+            contract_address: None,
+            is_wrapped: false,
+            original_chain_id: None,
+        });
+    }
+    Ok(assets)
+}
+
+fn parse_address(
+    what: &str,
+    value: &str,
+) -> Result<zkclear_types::Address, Box<dyn std::error::Error>> {
+    let bytes = hex::decode(value.trim_start_matches("0x"))
+        .map_err(|e| format!("Invalid {} {}: {}", what, value, e))?;
+    let address = bytes
+        .try_into()
+        .map_err(|_| format!("{} {} must be 20 bytes", what, value))?;
+    Ok(address)
+}
+
+/// EIP-712 signing domain from `EIP712_CHAIN_ID` and
+/// `EIP712_VERIFYING_CONTRACT`; typed-data signatures are off when unset
+fn get_eip712_domain() -> Result<Option<zkclear_types::Eip712Domain>, Box<dyn std::error::Error>> {
+    let chain_id = match std::env::var("EIP712_CHAIN_ID") {
+        Ok(value) => value
+            .trim()
+            .parse()
+            .map_err(|e| format!("Invalid EIP712_CHAIN_ID {}: {}", value, e))?,
+        Err(_) => return Ok(None),
+    };
+    let contract = std::env::var("EIP712_VERIFYING_CONTRACT")
+        .map_err(|_| "EIP712_VERIFYING_CONTRACT must be set with EIP712_CHAIN_ID")?;
+    let verifying_contract = parse_address("EIP712_VERIFYING_CONTRACT", contract.trim())?;
+    Ok(Some(zkclear_types::Eip712Domain::new(
+        chain_id,
+        verifying_contract,
+    )))
+}
+
+/// Merkle tree hash from `MERKLE_HASH` (`sha256` or `keccak256`), SHA-256
+/// when unset
+fn get_hash_algo() -> Result<HashAlgo, Box<dyn std::error::Error>> {
+    match std::env::var("MERKLE_HASH") {
+        Ok(value) => Ok(value
+            .trim()
+            .parse()
+            .map_err(|e| format!("Invalid MERKLE_HASH: {}", e))?),
+        Err(_) => Ok(HashAlgo::Sha256),
+    }
+}
+
+fn env_flag(name: &str) -> bool {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(false)
+}
+
 fn get_storage_path() -> PathBuf {
     std::env::var("STORAGE_PATH")
         .map(PathBuf::from)
         .unwrap_or_else(|_| PathBuf::from("./data"))
 }
 
-fn init_storage() -> Result<Arc<dyn zkclear_storage::Storage>, Box<dyn std::error::Error>> {
+/// Where a read-only replica keeps the logs of its secondary RocksDB
+/// instance; defaults to the storage path with a `-secondary` suffix
+#[cfg(feature = "rocksdb")]
+fn get_secondary_storage_path(storage_path: &std::path::Path) -> PathBuf {
+    std::env::var("SECONDARY_STORAGE_PATH")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from(format!("{}-secondary", storage_path.display())))
+}
+
+/// Open storage for writing, or as a secondary of the sequencer's RocksDB
+/// when running as a read-only replica
+fn init_storage(
+    read_only: bool,
+) -> Result<Arc<dyn zkclear_storage::Storage>, Box<dyn std::error::Error>> {
     #[cfg(feature = "rocksdb")]
     {
         let path = get_storage_path();
+        if read_only {
+            let secondary_path = get_secondary_storage_path(&path);
+            println!(
+                "Opening RocksDB storage at {} as a secondary in {}",
+                path.display(),
+                secondary_path.display()
+            );
+            let storage = RocksDBStorage::open_as_secondary(&path, &secondary_path)
+                .map_err(|e| format!("Failed to open RocksDB storage as secondary: {:?}", e))?;
+            return Ok(Arc::new(storage));
+        }
+
         std::fs::create_dir_all(&path)
             .map_err(|e| format!("Failed to create storage directory: {}", e))?;
 
@@ -41,11 +323,51 @@ fn init_storage() -> Result<Arc<dyn zkclear_storage::Storage>, Box<dyn std::erro
 
     #[cfg(not(feature = "rocksdb"))]
     {
+        if read_only {
+            return Err("READ_ONLY replicas need RocksDB storage".into());
+        }
         println!("Using InMemoryStorage (RocksDB not enabled)");
         Ok(Arc::new(InMemoryStorage::new()))
     }
 }
 
+/// Write a final state snapshot so the next start needs no replay. Gives up
+/// after a timeout rather than hang the shutdown.
+async fn snapshot_on_shutdown(sequencer: Arc<Sequencer>) {
+    let timeout = zkclear_sequencer::config::DEFAULT_SHUTDOWN_SNAPSHOT_TIMEOUT;
+    let snapshot = tokio::task::spawn_blocking(move || sequencer.create_state_snapshot());
+    match tokio::time::timeout(timeout, snapshot).await {
+        Ok(Ok(Ok(()))) => println!("Saved shutdown state snapshot"),
+        Ok(Ok(Err(e))) => eprintln!("Failed to save shutdown state snapshot: {:?}", e),
+        Ok(Err(e)) => eprintln!("Shutdown snapshot task failed: {}", e),
+        Err(_) => eprintln!(
+            "Shutdown state snapshot timed out after {}s",
+            timeout.as_secs()
+        ),
+    }
+}
+
+async fn read_snapshot_refresh_task(sequencer: Arc<Sequencer>) {
+    let mut interval_timer = interval(Duration::from_secs(get_read_snapshot_refresh_seconds()));
+
+    loop {
+        interval_timer.tick().await;
+        sequencer.refresh_read_snapshot();
+    }
+}
+
+/// Follow the blocks the sequencer writes to the shared storage
+async fn replica_sync_task(sequencer: Arc<Sequencer>) {
+    let mut interval_timer = interval(Duration::from_secs(get_replica_sync_seconds()));
+
+    loop {
+        interval_timer.tick().await;
+        if let Err(e) = sequencer.sync_from_storage() {
+            eprintln!("Failed to sync replica from storage: {:?}", e);
+        }
+    }
+}
+
 async fn block_production_task(sequencer: Arc<Sequencer>) {
     let interval_secs = get_block_interval_seconds();
     let mut interval_timer = interval(Duration::from_secs(interval_secs));
@@ -58,7 +380,10 @@ async fn block_production_task(sequencer: Arc<Sequencer>) {
     );
 
     loop {
-        interval_timer.tick().await;
+        tokio::select! {
+            _ = interval_timer.tick() => {}
+            _ = sequencer.build_threshold_reached() => {}
+        }
 
         if !sequencer.has_pending_txs() {
             consecutive_errors = 0; // Reset error counter on successful skip
@@ -102,11 +427,17 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     tracing_subscriber::fmt()
         .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
         .init();
+    let read_only = env_flag("READ_ONLY");
+    if read_only {
+        println!("Running as a read-only replica");
+    }
+
     // Initialize storage
-    let storage = init_storage()?;
+    let storage = init_storage(read_only)?;
     let storage_trait: Arc<dyn zkclear_storage::Storage> = storage.clone();
 
     // Initialize prover (optional - will use placeholders if not configured)
+    let hash_algo = get_hash_algo()?;
     let prover_config = ProverConfig {
         use_placeholders: std::env::var("USE_PLACEHOLDER_PROVER")
             .ok()
@@ -119,15 +450,17 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             .ok()
             .and_then(|v| v.parse().ok())
             .unwrap_or(false),
-        ..Default::default()
+        hash_algo,
     };
 
-    let prover = match Prover::new(prover_config) {
-        Ok(p) => {
+    // A replica proves nothing
+    let prover = match (!read_only).then(|| Prover::new(prover_config)) {
+        None => None,
+        Some(Ok(p)) => {
             println!("Prover initialized successfully");
             Some(Arc::new(p))
         }
-        Err(e) => {
+        Some(Err(e)) => {
             eprintln!(
                 "Warning: Failed to initialize prover: {:?}. Continuing without proof generation.",
                 e
@@ -138,8 +471,123 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Initialize sequencer with storage (will load state from storage if available)
     println!("Initializing sequencer with storage...");
-    let mut sequencer = Sequencer::with_storage_arc(storage.clone())
-        .map_err(|e| format!("Failed to initialize sequencer with storage: {:?}", e))?;
+    let genesis = GenesisConfig {
+        assets: get_registered_assets()?,
+        accounts: Vec::new(),
+        fee_collector: std::env::var("FEE_COLLECTOR")
+            .ok()
+            .map(|collector| parse_address("FEE_COLLECTOR", collector.trim()))
+            .transpose()?,
+    };
+    let mut sequencer =
+        Sequencer::with_storage_and_config(storage.clone(), get_stf_config()?, hash_algo)
+            .and_then(|sequencer| sequencer.with_genesis(genesis))
+            .map_err(|e| format!("Failed to initialize sequencer with storage: {:?}", e))?
+            .with_checkpoint_interval(get_checkpoint_interval_blocks())
+            .with_snapshot_retention(get_snapshot_retention());
+
+    if let Some(max) = std::env::var("MAX_TXS_PER_SENDER_PER_BLOCK")
+        .ok()
+        .and_then(|v| v.parse().ok())
+    {
+        sequencer = sequencer.with_max_txs_per_sender(max);
+    }
+
+    if let Some(threshold) = std::env::var("BUILD_THRESHOLD")
+        .ok()
+        .and_then(|v| v.parse().ok())
+    {
+        sequencer = sequencer.with_build_threshold(threshold);
+    }
+
+    if let Some(network_id) = std::env::var("NETWORK_ID")
+        .ok()
+        .and_then(|v| v.parse().ok())
+    {
+        sequencer = sequencer.with_network_id(network_id);
+    }
+
+    if let Some(min_fee) = std::env::var("THROTTLE_MIN_FEE")
+        .ok()
+        .and_then(|v| v.parse().ok())
+    {
+        let high_water_mark = std::env::var("THROTTLE_HIGH_WATER_MARK")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(zkclear_sequencer::config::DEFAULT_THROTTLE_HIGH_WATER_MARK);
+        sequencer = sequencer.with_throttling(high_water_mark, min_fee);
+    }
+
+    let default_curve = zkclear_sequencer::fee::FeeCurve::default();
+    sequencer = sequencer.with_fee_curve(zkclear_sequencer::fee::FeeCurve {
+        base_fee_per_byte: std::env::var("FEE_ESTIMATE_BASE_PER_BYTE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(default_curve.base_fee_per_byte),
+        min_fee: std::env::var("FEE_ESTIMATE_MIN")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(default_curve.min_fee),
+        max_multiplier: std::env::var("FEE_ESTIMATE_MAX_MULTIPLIER")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(default_curve.max_multiplier),
+    });
+
+    if let Some(gap) = std::env::var("MAX_NONCE_GAP")
+        .ok()
+        .and_then(|v| v.parse().ok())
+    {
+        sequencer = sequencer.with_max_nonce_gap(gap);
+    }
+
+    if let Some(ttl) = std::env::var("MEMPOOL_TTL_SEC")
+        .ok()
+        .and_then(|v| v.parse().ok())
+    {
+        sequencer = sequencer.with_mempool_ttl(Duration::from_secs(ttl));
+    }
+
+    if let Some(ttl) = std::env::var("NONCE_BUFFER_TTL_SEC")
+        .ok()
+        .and_then(|v| v.parse().ok())
+    {
+        sequencer = sequencer.with_nonce_buffer_ttl(Duration::from_secs(ttl));
+    }
+
+    let default_limits = *sequencer.tx_limits();
+    sequencer = sequencer.with_tx_limits(TxLimits {
+        max_tx_size: std::env::var("MAX_TX_SIZE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(default_limits.max_tx_size),
+        max_batch_size: std::env::var("MAX_BATCH_SIZE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(default_limits.max_batch_size),
+        ..default_limits
+    });
+
+    if let Some(size) = std::env::var("REQUEST_ID_CACHE_SIZE")
+        .ok()
+        .and_then(|v| v.parse().ok())
+    {
+        sequencer = sequencer.with_request_id_cache_size(size);
+    }
+
+    match std::env::var("TX_ORDERING")
+        .unwrap_or_default()
+        .to_lowercase()
+        .as_str()
+    {
+        "" | "fifo" => {}
+        "fee" => sequencer = sequencer.with_ordering_policy(OrderingPolicy::FeePriority),
+        other => return Err(format!("Unknown TX_ORDERING: {}", other).into()),
+    }
+
+    if let Some(domain) = get_eip712_domain()? {
+        sequencer = sequencer.with_eip712_domain(domain);
+    }
 
     // Set prover if available
     if let Some(ref prover) = prover {
@@ -147,26 +595,40 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         println!("Prover attached to sequencer");
     }
 
+    if let Some(seconds) = std::env::var("PROOF_TIMEOUT_SECONDS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+    {
+        sequencer = sequencer.with_proof_timeout(std::time::Duration::from_secs(seconds));
+    }
+
+    if env_flag("STARTUP_SELF_CHECK") {
+        sequencer =
+            sequencer.with_self_check_proof_verification(env_flag("SELF_CHECK_VERIFY_PROOF"));
+        sequencer
+            .self_check()
+            .map_err(|e| format!("Startup self-check failed: {:?}", e))?;
+        println!("Startup self-check passed");
+    }
+
     let sequencer = Arc::new(sequencer);
 
     println!("Sequencer initialized with storage");
     println!("Current block ID: {}", sequencer.get_current_block_id());
 
     // Initialize rate limiting
-    let max_requests = std::env::var("RATE_LIMIT_MAX_REQUESTS")
-        .ok()
-        .and_then(|v| v.parse().ok())
-        .unwrap_or(100);
-    let window_seconds = std::env::var("RATE_LIMIT_WINDOW_SECONDS")
-        .ok()
-        .and_then(|v| v.parse().ok())
-        .unwrap_or(60);
-    let rate_limit_state = Arc::new(zkclear_api::RateLimitState::new(max_requests, window_seconds));
+    let rate_limit_state = Arc::new(zkclear_api::RateLimitState::from_env());
 
     let api_state = Arc::new(ApiState {
         sequencer: sequencer.clone(),
         storage: Some(storage_trait),
+        prover,
         rate_limit_state: Some(rate_limit_state),
+        metrics: sequencer.metrics(),
+        admin_token: std::env::var("ADMIN_TOKEN")
+            .ok()
+            .filter(|token| !token.is_empty()),
+        read_only,
     });
 
     let app = create_router(api_state);
@@ -215,6 +677,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     .ok()
                     .and_then(|v| v.parse().ok())
                     .unwrap_or(10),
+                allowed_assets: zkclear_watcher::allowed_assets_from_env("ETHEREUM_ALLOWED_ASSETS"),
+                settlement_contract_address: std::env::var("ETHEREUM_SETTLEMENT_CONTRACT").ok(),
             });
         }
         
@@ -254,6 +718,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     .ok()
                     .and_then(|v| v.parse().ok())
                     .unwrap_or(10),
+                allowed_assets: zkclear_watcher::allowed_assets_from_env("BASE_ALLOWED_ASSETS"),
+                settlement_contract_address: std::env::var("BASE_SETTLEMENT_CONTRACT").ok(),
             });
         }
         
@@ -296,6 +762,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     .ok()
                     .and_then(|v| v.parse().ok())
                     .unwrap_or(0),
+                allowed_assets: zkclear_watcher::allowed_assets_from_env("ALLOWED_ASSETS"),
+                settlement_contract_address: std::env::var("SETTLEMENT_CONTRACT_ADDRESS").ok(),
             }],
         }
     } else {
@@ -345,12 +813,21 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             .await
     });
 
-    let block_production_handle = tokio::spawn(block_production_task(sequencer.clone()));
-    let watcher_handle = tokio::spawn(async move {
-        if let Err(e) = watcher.start().await {
-            eprintln!("Watcher error: {}", e);
-        }
-    });
+    // A replica only follows the sequencer's blocks: it builds none and
+    // watches no chain for deposits
+    let background_handles = if read_only {
+        vec![tokio::spawn(replica_sync_task(sequencer.clone()))]
+    } else {
+        vec![
+            tokio::spawn(block_production_task(sequencer.clone())),
+            tokio::spawn(read_snapshot_refresh_task(sequencer.clone())),
+            tokio::spawn(async move {
+                if let Err(e) = watcher.start().await {
+                    eprintln!("Watcher error: {}", e);
+                }
+            }),
+        ]
+    };
 
     // Wait for shutdown signal
     shutdown_signal.await;
@@ -365,8 +842,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
 
     // Abort background tasks
-    block_production_handle.abort();
-    watcher_handle.abort();
+    for handle in background_handles {
+        handle.abort();
+    }
+
+    if env_flag("SNAPSHOT_ON_SHUTDOWN") && !read_only {
+        snapshot_on_shutdown(sequencer).await;
+    }
 
     println!("Graceful shutdown completed");
 
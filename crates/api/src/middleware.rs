@@ -1,6 +1,6 @@
 use axum::{
     extract::Request,
-    http::StatusCode,
+    http::{header, Method, StatusCode},
     middleware::Next,
     response::{IntoResponse, Response},
 };
@@ -10,55 +10,189 @@ use std::{
     time::{Duration, Instant},
 };
 
+use zkclear_types::Address;
+
 /// Rate limiter state
+///
+/// Each client IP gets its own bucket of `max_requests` per window, and all
+/// clients together share a bucket of `global_max_requests`. Transaction
+/// submissions also count against their sender's bucket of
+/// `sender_max_requests`, but only once the signature has been verified, so
+/// a forged `from` can't use up someone else's budget.
 #[derive(Clone)]
 pub struct RateLimitState {
     max_requests: u32,
+    sender_max_requests: u32,
+    global_max_requests: u32,
     window_seconds: u64,
-    requests: Arc<Mutex<HashMap<String, Vec<Instant>>>>,
+    requests: Arc<Mutex<Buckets>>,
+    global_requests: Arc<Mutex<Vec<Instant>>>,
+}
+
+/// Request timestamps per principal, `ip:<addr>` or `address:0x<hex>`
+struct Buckets {
+    by_principal: HashMap<String, Vec<Instant>>,
+    /// When buckets without a request in the window were last dropped
+    last_pruned: Instant,
 }
 
 impl RateLimitState {
+    /// `max_requests` per client IP and per sender per window, with no
+    /// global limit
     pub fn new(max_requests: u32, window_seconds: u64) -> Self {
         Self {
             max_requests,
+            sender_max_requests: max_requests,
+            global_max_requests: u32::MAX,
             window_seconds,
-            requests: Arc::new(Mutex::new(HashMap::new())),
+            requests: Arc::new(Mutex::new(Buckets {
+                by_principal: HashMap::new(),
+                last_pruned: Instant::now(),
+            })),
+            global_requests: Arc::new(Mutex::new(Vec::new())),
         }
     }
 
-    /// Check if request should be allowed
-    pub fn check_rate_limit(&self, client_ip: &str) -> Result<(), StatusCode> {
+    /// Also cap the requests of all clients together per window
+    pub fn with_global_limit(mut self, global_max_requests: u32) -> Self {
+        self.global_max_requests = global_max_requests;
+        self
+    }
+
+    /// Cap the verified submissions of each sender per window
+    pub fn with_sender_limit(mut self, sender_max_requests: u32) -> Self {
+        self.sender_max_requests = sender_max_requests;
+        self
+    }
+
+    /// Limits from `RATE_LIMIT_MAX_REQUESTS` (per client IP, default 100),
+    /// `RATE_LIMIT_SENDER_MAX_REQUESTS` (default: the per-IP limit),
+    /// `RATE_LIMIT_GLOBAL_MAX_REQUESTS` (no global limit when unset) and
+    /// `RATE_LIMIT_WINDOW_SECONDS` (default 60)
+    pub fn from_env() -> Self {
+        fn var<T: std::str::FromStr>(name: &str, default: T) -> T {
+            std::env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        }
+
+        let max_requests = var("RATE_LIMIT_MAX_REQUESTS", 100);
+        Self::new(max_requests, var("RATE_LIMIT_WINDOW_SECONDS", 60))
+            .with_sender_limit(var("RATE_LIMIT_SENDER_MAX_REQUESTS", max_requests))
+            .with_global_limit(var("RATE_LIMIT_GLOBAL_MAX_REQUESTS", u32::MAX))
+    }
+
+    /// Count a request from `principal` if both its bucket and the global
+    /// one have room; otherwise return how long until the fuller one does.
+    pub fn check_rate_limit(&self, principal: &str) -> Result<(), Duration> {
         let now = Instant::now();
-        let window = Duration::from_secs(self.window_seconds);
-
-        let mut requests_map = self.requests.lock().unwrap();
-        
-        // Clean up old requests outside the window
-        if let Some(timestamps) = requests_map.get_mut(client_ip) {
-            timestamps.retain(|&timestamp| now.duration_since(timestamp) < window);
-            
-            // Check if limit exceeded
-            if timestamps.len() >= self.max_requests as usize {
-                return Err(StatusCode::TOO_MANY_REQUESTS);
-            }
-            
-            // Add current request
-            timestamps.push(now);
-        } else {
-            // First request from this IP
-            requests_map.insert(client_ip.to_string(), vec![now]);
+        let mut buckets = self.buckets(now);
+        let mut global = self.global_requests.lock().unwrap();
+
+        global.retain(|&timestamp| now.duration_since(timestamp) < self.window());
+        let timestamps = buckets
+            .by_principal
+            .entry(principal.to_string())
+            .or_default();
+        let principal_wait = self.wait(timestamps, self.max_requests, now);
+        let global_wait = self.wait(&global, self.global_max_requests, now);
+        if let Some(wait) = principal_wait.max(global_wait) {
+            return Err(wait);
         }
 
+        timestamps.push(now);
+        global.push(now);
         Ok(())
     }
+
+    /// How long until `sender` may submit again, if its bucket is full.
+    /// Nothing is counted; see `record_submission`.
+    pub fn check_sender(&self, sender: &Address) -> Result<(), Duration> {
+        let now = Instant::now();
+        let buckets = self.buckets(now);
+        let wait = buckets
+            .by_principal
+            .get(&sender_principal(sender))
+            .and_then(|timestamps| self.wait(timestamps, self.sender_max_requests, now));
+        wait.map_or(Ok(()), Err)
+    }
+
+    /// Count a submission from `sender` whose signature was verified
+    pub fn record_submission(&self, sender: &Address) {
+        let now = Instant::now();
+        self.buckets(now)
+            .by_principal
+            .entry(sender_principal(sender))
+            .or_default()
+            .push(now);
+    }
+
+    fn window(&self) -> Duration {
+        Duration::from_secs(self.window_seconds)
+    }
+
+    /// Lock the buckets, dropping timestamps that left the window. Every
+    /// bucket is swept at most once per window, and those left empty are
+    /// removed, so one-off principals don't accumulate.
+    fn buckets(&self, now: Instant) -> std::sync::MutexGuard<'_, Buckets> {
+        let window = self.window();
+        let mut buckets = self.requests.lock().unwrap();
+        if now.duration_since(buckets.last_pruned) >= window {
+            buckets.by_principal.retain(|_, timestamps| {
+                timestamps.retain(|&timestamp| now.duration_since(timestamp) < window);
+                !timestamps.is_empty()
+            });
+            buckets.last_pruned = now;
+        }
+        buckets
+    }
+
+    /// How long until `timestamps` holds fewer than `limit` requests in the
+    /// window, if it is full now
+    fn wait(&self, timestamps: &[Instant], limit: u32, now: Instant) -> Option<Duration> {
+        let window = self.window();
+        let in_window: Vec<Instant> = timestamps
+            .iter()
+            .copied()
+            .filter(|&timestamp| now.duration_since(timestamp) < window)
+            .collect();
+        if in_window.len() < limit as usize {
+            return None;
+        }
+        Some(match in_window.get(in_window.len() - limit as usize) {
+            Some(&oldest) => window.saturating_sub(now.duration_since(oldest)),
+            None => window,
+        })
+    }
+}
+
+fn sender_principal(sender: &Address) -> String {
+    format!("address:0x{}", hex::encode(sender))
+}
+
+/// 429 answer for a request over a limit for `wait` more
+pub(crate) fn rate_limited(wait: Duration) -> Response {
+    let body = serde_json::json!({
+        "error": "RateLimitExceeded",
+        "message": "Rate limit exceeded. Please try again later."
+    });
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, retry_after_secs(wait).to_string())],
+        axum::Json(body),
+    )
+        .into_response()
 }
 
-/// Rate limit middleware function
-pub async fn rate_limit_middleware(
-    request: Request,
-    next: Next,
-) -> Response {
+/// Whole seconds to wait, at least 1
+pub(crate) fn retry_after_secs(wait: Duration) -> u64 {
+    wait.as_secs_f64().ceil().max(1.0) as u64
+}
+
+/// Rate limit middleware function. Every request counts against its client
+/// IP; submissions are counted against their sender by the handlers.
+pub async fn rate_limit_middleware(request: Request, next: Next) -> Response {
     // Get rate limit state from request extensions
     let rate_limit_state = request
         .extensions()
@@ -66,32 +200,66 @@ pub async fn rate_limit_middleware(
         .cloned();
 
     // If rate limiting is enabled, check it
-    if let Some(state) = rate_limit_state {
-        // Get client IP for rate limiting
-        let client_ip = request
-            .headers()
-            .get("x-forwarded-for")
-            .and_then(|v| v.to_str().ok())
-            .or_else(|| {
-                request
-                    .headers()
-                    .get("x-real-ip")
-                    .and_then(|v| v.to_str().ok())
-            })
-            .unwrap_or("unknown");
-
-        // Check rate limit
-        if let Err(_) = state.check_rate_limit(client_ip) {
-            // Rate limit exceeded
-            let body = serde_json::json!({
-                "error": "RateLimitExceeded",
-                "message": "Rate limit exceeded. Please try again later."
-            });
-            return (StatusCode::TOO_MANY_REQUESTS, axum::Json(body)).into_response();
-        }
+    let Some(state) = rate_limit_state else {
+        return next.run(request).await;
+    };
+
+    let client_ip = request
+        .headers()
+        .get("x-forwarded-for")
+        .and_then(|v| v.to_str().ok())
+        .or_else(|| {
+            request
+                .headers()
+                .get("x-real-ip")
+                .and_then(|v| v.to_str().ok())
+        })
+        .unwrap_or("unknown");
+
+    if let Err(wait) = state.check_rate_limit(&format!("ip:{}", client_ip)) {
+        return rate_limited(wait);
     }
 
     // Allow request
     next.run(request).await
 }
 
+/// Reject POST requests whose body isn't JSON with 415. A bodiless POST
+/// without a `Content-Type`, such as `POST /admin/build-block`, is let
+/// through.
+pub async fn json_content_type_middleware(request: Request, next: Next) -> Response {
+    if request.method() != Method::POST {
+        return next.run(request).await;
+    }
+
+    let headers = request.headers();
+    let is_json = match headers.get(header::CONTENT_TYPE) {
+        Some(value) => value
+            .to_str()
+            .ok()
+            .and_then(|value| value.split(';').next())
+            .map(|mime| {
+                let mime = mime.trim().to_ascii_lowercase();
+                mime == "application/json" || mime.ends_with("+json")
+            })
+            .unwrap_or(false),
+        None => {
+            let has_body = headers.contains_key(header::TRANSFER_ENCODING)
+                || headers
+                    .get(header::CONTENT_LENGTH)
+                    .and_then(|v| v.to_str().ok())
+                    .is_some_and(|length| length.trim() != "0");
+            !has_body
+        }
+    };
+
+    if !is_json {
+        let body = serde_json::json!({
+            "error": "UnsupportedMediaType",
+            "message": "Request body must be sent as application/json"
+        });
+        return (StatusCode::UNSUPPORTED_MEDIA_TYPE, axum::Json(body)).into_response();
+    }
+
+    next.run(request).await
+}
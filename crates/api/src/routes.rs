@@ -1,130 +1,645 @@
 use axum::{
-    extract::{Request, State},
+    extract::{DefaultBodyLimit, Request, State},
+    http::{header, StatusCode},
     middleware::{from_fn, Next},
-    routing::{get, post},
-    response::Json,
+    response::{IntoResponse, Json},
+    routing::{get, post, MethodRouter},
     Router,
 };
+use serde_json::json;
 use std::sync::Arc;
-use tower_http::cors::CorsLayer;
+use tower_http::{cors::CorsLayer, limit::RequestBodyLimitLayer};
 
+use crate::admin::{force_build_block, import_account};
 use crate::handlers::ApiState;
 use crate::handlers::*;
-use crate::middleware::{rate_limit_middleware, RateLimitState};
+use crate::middleware::{json_content_type_middleware, rate_limit_middleware, RateLimitState};
+use crate::types::ErrorResponse;
+use crate::ws::ws_handler;
+
+/// Largest request body accepted when `API_MAX_BODY_BYTES` is unset
+pub const DEFAULT_MAX_BODY_BYTES: usize = 1024 * 1024;
 
 pub fn create_router(state: Arc<ApiState>) -> Router {
-    // Get rate limit configuration from environment variables
-    let max_requests = std::env::var("RATE_LIMIT_MAX_REQUESTS")
+    // Get rate limit configuration from environment variables unless the
+    // caller brought its own
+    let rate_limit_state = state
+        .rate_limit_state
+        .clone()
+        .unwrap_or_else(|| Arc::new(RateLimitState::from_env()));
+
+    // Bodies past this are answered with 413 before any handler runs
+    let max_body_bytes = std::env::var("API_MAX_BODY_BYTES")
         .ok()
         .and_then(|v| v.parse().ok())
-        .unwrap_or(100); // Default: 100 requests per window
+        .unwrap_or(DEFAULT_MAX_BODY_BYTES);
     
-    let window_seconds = std::env::var("RATE_LIMIT_WINDOW_SECONDS")
-        .ok()
-        .and_then(|v| v.parse().ok())
-        .unwrap_or(60); // Default: 60 seconds window
+    // Writes on a read-only replica all land on the same 405 handler
+    let read_only = state.read_only;
+    let write = |handler: MethodRouter<Arc<ApiState>>| {
+        if read_only {
+            post(reject_write)
+        } else {
+            handler
+        }
+    };
 
-    let rate_limit_state = Arc::new(RateLimitState::new(max_requests, window_seconds));
-    
     // Add rate limit state to ApiState
     let api_state = Arc::new(ApiState {
         sequencer: state.sequencer.clone(),
         storage: state.storage.clone(),
+        prover: state.prover.clone(),
         rate_limit_state: Some(rate_limit_state.clone()),
+        metrics: state.metrics.clone(),
+        admin_token: state.admin_token.clone(),
+        read_only: state.read_only,
     });
 
-    Router::new()
-        // Health and readiness endpoints (no rate limiting)
-        .route("/health", get(health_check))
-        .route("/ready", get(readiness_check))
-        // API endpoints with rate limiting
+    // API endpoints with rate limiting
+    let api = Router::new()
         .route(
             "/api/v1/account/:address/balance/:asset_id",
             get(get_account_balance),
         )
+        .route(
+            "/api/v1/account/:address/balance/:asset_id/all",
+            get(get_account_asset_balances),
+        )
         .route("/api/v1/account/:address", get(get_account_state))
+        .route("/api/v1/account/:address/export", get(export_account))
+        .route("/api/v1/account/:address/nonce", get(get_account_nonce))
+        .route(
+            "/api/v1/account/:address/deals/by-ref/:ref",
+            get(get_deal_by_external_ref),
+        )
         .route("/api/v1/deals", get(get_deals_list))
+        .route("/api/v1/deals/match", get(get_matching_deal))
+        .route("/api/v1/book", get(get_book_depth))
         .route("/api/v1/deal/:deal_id", get(get_deal_details))
+        .route("/api/v1/deal/:deal_id/fills", get(get_deal_fills))
+        .route("/api/v1/blocks", get(get_blocks_list))
         .route("/api/v1/block/:block_id", get(get_block_info))
-        .route("/api/v1/transactions", post(submit_transaction))
+        .route("/api/v1/block/:block_id/proof", get(get_block_proof))
+        .route("/api/v1/block/:block_id/diff", get(get_block_diff))
+        .route("/api/v1/blocks/:block_id/raw", get(get_raw_block))
+        .route("/api/v1/tx/:tx_hash", get(get_transaction_by_hash))
+        .route(
+            "/api/v1/withdrawals/:block_id/:tx_index/proof",
+            get(get_withdrawal_proof),
+        )
+        .route("/api/v1/transactions", write(post(submit_transaction)))
+        .route(
+            "/api/v1/transactions/batch",
+            write(post(submit_transaction_batch)),
+        )
         .route("/api/v1/queue/status", get(get_queue_status))
+        .route("/api/v1/stats", get(get_stats))
+        .route("/api/v1/fee/estimate", get(get_fee_estimate))
         .route("/api/v1/chains", get(get_supported_chains))
+        .route("/api/v1/checkpoint", get(get_checkpoint))
+        .route("/api/v1/state/root", get(get_state_root))
         .route("/jsonrpc", post(jsonrpc_handler))
-        // Add rate limit state to request extensions
+        .route("/ws", get(ws_handler))
+        .route("/admin/build-block", write(post(force_build_block)))
+        .route("/admin/account/import", write(post(import_account)))
+        // Apply rate limiting middleware
+        .layer(from_fn(rate_limit_middleware))
+        // Add rate limit state to request extensions; layered after so it
+        // runs before the rate limiter
         .layer(axum::middleware::from_fn(move |mut request: Request, next: Next| {
             let state = Arc::clone(&rate_limit_state);
             async move {
                 request.extensions_mut().insert(state);
                 next.run(request).await
             }
-        }))
-        // Apply rate limiting middleware
-        .layer(from_fn(rate_limit_middleware))
+        }));
+
+    Router::new()
+        // Health, readiness and metrics endpoints (no rate limiting), so
+        // probes are answered however busy the API is
+        .route("/health", get(health_check))
+        .route("/ready", get(readiness_check))
+        .route("/metrics", get(metrics))
+        .merge(api)
+        .layer(from_fn(json_content_type_middleware))
+        // Replaces axum's built-in 2 MB extractor limit
+        .layer(DefaultBodyLimit::disable())
+        .layer(RequestBodyLimitLayer::new(max_body_bytes))
         .layer(CorsLayer::permissive())
         .with_state(api_state)
 }
 
-/// Health check endpoint with component status
+fn unix_timestamp() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Liveness endpoint: answers 200 whenever the process is serving requests
+/// and touches no subsystem that could block or fail
 async fn health_check(State(state): State<Arc<ApiState>>) -> Json<serde_json::Value> {
-    use serde_json::json;
-    
-    // Check sequencer status
-    // BlockId is u64, so it's always valid (no need to check >= 0)
-    let sequencer_healthy = true; // Sequencer is healthy if it exists
-    let queue_length = state.sequencer.queue_length();
-    
-    // Check storage status
-    let storage_healthy = state.storage.is_some();
-    let storage_available = if let Some(ref storage) = state.storage {
-        // Try to read a block to verify storage is working
-        storage.get_block(0).is_ok()
-    } else {
-        false
-    };
-    
-    // Overall health status
-    let healthy = sequencer_healthy && storage_healthy && storage_available;
-    
-    let status = if healthy { "healthy" } else { "degraded" };
-    
     Json(json!({
-        "status": status,
-        "timestamp": std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs(),
-        "components": {
-            "sequencer": {
-                "status": if sequencer_healthy { "healthy" } else { "unhealthy" },
-                "current_block_id": state.sequencer.get_current_block_id(),
-                "queue_length": queue_length
-            },
-            "storage": {
-                "status": if storage_available { "healthy" } else { "unhealthy" },
-                "configured": storage_healthy
-            }
-        }
+        "status": "alive",
+        "timestamp": unix_timestamp(),
+        "current_block_id": state.sequencer.get_current_block_id(),
+        "queue_length": state.sequencer.queue_length(),
     }))
 }
 
-/// Readiness check endpoint (for Kubernetes/Docker health checks)
-async fn readiness_check(State(state): State<Arc<ApiState>>) -> Result<Json<serde_json::Value>, axum::http::StatusCode> {
-    use serde_json::json;
-    
-    // Check if all critical components are ready
-    // BlockId is u64, so sequencer is always ready if it exists
-    let sequencer_ready = true; // Sequencer is ready if it exists
-    let storage_ready = state.storage.is_some();
-    
-    if sequencer_ready && storage_ready {
-        Ok(Json(json!({
-            "status": "ready",
-            "timestamp": std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs()
-        })))
+/// Readiness endpoint for load balancers. Probes storage with a cheap
+/// `get_latest_block_id` read and checks that a prover is attached, except
+/// on a read-only replica, which proves nothing; answers 503 listing the
+/// failed subsystems if any is unavailable.
+async fn readiness_check(
+    State(state): State<Arc<ApiState>>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let storage = match state.storage {
+        Some(ref storage) => match storage.get_latest_block_id() {
+            Ok(_) => json!({ "status": "ready" }),
+            Err(e) => json!({ "status": "unavailable", "error": format!("{:?}", e) }),
+        },
+        None => json!({ "status": "unavailable", "error": "Storage not configured" }),
+    };
+    let mut components = vec![("storage", storage)];
+    if !state.read_only {
+        let prover = if state.prover.is_some() {
+            json!({ "status": "ready" })
+        } else {
+            json!({ "status": "unavailable", "error": "No prover attached" })
+        };
+        components.push(("prover", prover));
+    }
+
+    let failed: Vec<&str> = components
+        .iter()
+        .filter(|(_, component)| component["status"] != "ready")
+        .map(|(name, _)| *name)
+        .collect();
+    let (code, status) = if failed.is_empty() {
+        (StatusCode::OK, "ready")
     } else {
-        Err(axum::http::StatusCode::SERVICE_UNAVAILABLE)
+        (StatusCode::SERVICE_UNAVAILABLE, "not_ready")
+    };
+
+    (
+        code,
+        Json(json!({
+            "status": status,
+            "timestamp": unix_timestamp(),
+            "failed": failed,
+            "components": components
+                .into_iter()
+                .map(|(name, component)| (name.to_string(), component))
+                .collect::<serde_json::Map<_, _>>(),
+        })),
+    )
+}
+
+/// Answer to every write on a read-only replica
+async fn reject_write() -> (StatusCode, Json<ErrorResponse>) {
+    (
+        StatusCode::METHOD_NOT_ALLOWED,
+        Json(ErrorResponse {
+            error: "ReadOnly".to_string(),
+            message: "This node is a read-only replica; submit to the sequencer".to_string(),
+        }),
+    )
+}
+
+/// Prometheus scrape endpoint: the sequencer's counters and histograms plus
+/// gauges read from its current state
+async fn metrics(State(state): State<Arc<ApiState>>) -> impl IntoResponse {
+    let body = state.metrics.encode(
+        state.sequencer.queue_length(),
+        state.sequencer.get_current_block_id(),
+    );
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zkclear_sequencer::Sequencer;
+    use zkclear_state::{State as SequencerState, StateDiff};
+    use zkclear_storage::{
+        BlockIter, InMemoryStorage, Storage, StorageError, TxHash, STORAGE_VERSION,
+    };
+    use zkclear_types::{
+        Block, BlockId, Checkpoint, Deal, DealId, Deposit, SignatureScheme, Tx, TxKind, TxPayload,
+    };
+
+    /// Storage whose every call fails, as a broken database would
+    struct FailingStorage;
+
+    fn failure<T>() -> Result<T, StorageError> {
+        Err(StorageError::DatabaseError("connection lost".to_string()))
+    }
+
+    impl Storage for FailingStorage {
+        fn version(&self) -> u32 {
+            STORAGE_VERSION
+        }
+        fn set_version(&self, _: u32) -> Result<(), StorageError> {
+            failure()
+        }
+        fn save_block(&self, _: &Block) -> Result<(), StorageError> {
+            failure()
+        }
+        fn get_block(&self, _: BlockId) -> Result<Option<Block>, StorageError> {
+            failure()
+        }
+        fn get_latest_block_id(&self) -> Result<Option<BlockId>, StorageError> {
+            failure()
+        }
+        fn iter_blocks(&self, _: BlockId, _: BlockId) -> BlockIter {
+            Box::new(std::iter::once(failure()))
+        }
+        fn save_transaction(&self, _: &Tx, _: BlockId, _: usize) -> Result<(), StorageError> {
+            failure()
+        }
+        fn get_transaction(&self, _: BlockId, _: usize) -> Result<Option<Tx>, StorageError> {
+            failure()
+        }
+        fn get_transactions_by_block(&self, _: BlockId) -> Result<Vec<Tx>, StorageError> {
+            failure()
+        }
+        fn get_transaction_by_hash(
+            &self,
+            _: &TxHash,
+        ) -> Result<Option<(Tx, BlockId, usize)>, StorageError> {
+            failure()
+        }
+        fn save_deal(&self, _: &Deal) -> Result<(), StorageError> {
+            failure()
+        }
+        fn get_deal(&self, _: DealId) -> Result<Option<Deal>, StorageError> {
+            failure()
+        }
+        fn get_all_deals(&self) -> Result<Vec<Deal>, StorageError> {
+            failure()
+        }
+        fn save_state_snapshot(&self, _: &SequencerState, _: BlockId) -> Result<(), StorageError> {
+            failure()
+        }
+        fn get_latest_state_snapshot(
+            &self,
+        ) -> Result<Option<(SequencerState, BlockId)>, StorageError> {
+            failure()
+        }
+        fn get_state_snapshot_at_or_before(
+            &self,
+            _: BlockId,
+        ) -> Result<Option<(SequencerState, BlockId)>, StorageError> {
+            failure()
+        }
+        fn prune_snapshots_before(&self, _: BlockId) -> Result<usize, StorageError> {
+            failure()
+        }
+        fn save_state_diff(&self, _: BlockId, _: &StateDiff) -> Result<(), StorageError> {
+            failure()
+        }
+        fn get_state_diff(&self, _: BlockId) -> Result<Option<StateDiff>, StorageError> {
+            failure()
+        }
+        fn save_withdrawals_accumulator(
+            &self,
+            _: BlockId,
+            _: &[u8; 32],
+        ) -> Result<(), StorageError> {
+            failure()
+        }
+        fn get_withdrawals_accumulator(
+            &self,
+            _: BlockId,
+        ) -> Result<Option<[u8; 32]>, StorageError> {
+            failure()
+        }
+        fn truncate_after(&self, _: BlockId) -> Result<usize, StorageError> {
+            failure()
+        }
+        fn save_checkpoint(&self, _: &Checkpoint) -> Result<(), StorageError> {
+            failure()
+        }
+        fn get_latest_checkpoint(&self) -> Result<Option<Checkpoint>, StorageError> {
+            failure()
+        }
+        fn claim_block_builder(
+            &self,
+            _: BlockId,
+            _: &zkclear_storage::BuilderClaim,
+        ) -> Result<bool, StorageError> {
+            failure()
+        }
+        fn flush(&self) -> Result<(), StorageError> {
+            failure()
+        }
+    }
+
+    fn api_state(
+        storage: Arc<dyn Storage>,
+        prover: Option<Arc<zkclear_prover::Prover>>,
+    ) -> Arc<ApiState> {
+        Arc::new(ApiState {
+            prover,
+            ..ApiState::new(Arc::new(Sequencer::new())).with_storage(storage)
+        })
+    }
+
+    #[tokio::test]
+    async fn test_ready_with_healthy_storage_and_prover() {
+        let prover = zkclear_prover::Prover::new(zkclear_prover::ProverConfig::default()).unwrap();
+        let state = api_state(Arc::new(InMemoryStorage::new()), Some(Arc::new(prover)));
+
+        let (code, Json(body)) = readiness_check(State(state)).await;
+        assert_eq!(code, StatusCode::OK);
+        assert_eq!(body["status"], "ready");
+        assert_eq!(body["failed"], json!([]));
+    }
+
+    #[tokio::test]
+    async fn test_ready_reports_failed_subsystems() {
+        let state = api_state(Arc::new(FailingStorage), None);
+
+        let (code, Json(body)) = readiness_check(State(state.clone())).await;
+        assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["failed"], json!(["storage", "prover"]));
+        assert_eq!(body["components"]["storage"]["status"], "unavailable");
+
+        // Liveness does not depend on the failing subsystems
+        let Json(body) = health_check(State(state)).await;
+        assert_eq!(body["status"], "alive");
+    }
+
+    async fn scrape(state: Arc<ApiState>) -> String {
+        let response = metrics(State(state)).await.into_response();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_metrics_advance_after_block() {
+        let state = api_state(Arc::new(InMemoryStorage::new()), None);
+        let owner = [1u8; 20];
+        let deposit = |domain: u64| Tx {
+            id: 0,
+            from: owner,
+            nonce: 0,
+            kind: TxKind::Deposit,
+            payload: TxPayload::Deposit(Deposit {
+                tx_hash: [1u8; 32],
+                account: owner,
+                asset_id: 0,
+                amount: 100,
+                chain_id: zkclear_types::chain_ids::ETHEREUM,
+            }),
+            fee: 0,
+            domain,
+            scheme: SignatureScheme::Secp256k1Recoverable,
+            signature: vec![0u8; 65],
+        };
+
+        let before = scrape(state.clone()).await;
+        assert!(before.contains("zkclear_blocks_total 0\n"));
+        assert!(before.contains("# TYPE zkclear_proof_seconds histogram\n"));
+
+        assert!(state
+            .sequencer
+            .submit_tx_with_validation(deposit(7), false)
+            .is_err());
+        state
+            .sequencer
+            .submit_tx_with_validation(deposit(0), false)
+            .unwrap();
+        assert!(scrape(state.clone())
+            .await
+            .contains("zkclear_queue_length 1\n"));
+        state.sequencer.build_and_execute_block().unwrap();
+
+        let after = scrape(state).await;
+        assert!(after.contains("zkclear_blocks_total 1\n"));
+        assert!(after.contains("zkclear_current_block_id 1\n"));
+        assert!(after.contains("zkclear_queue_length 0\n"));
+        assert!(after.contains("zkclear_txs_total{kind=\"deposit\"} 1\n"));
+        assert!(after.contains("zkclear_tx_rejected_total{reason=\"wrong_domain\"} 1\n"));
+    }
+
+    #[tokio::test]
+    async fn test_read_only_replica_serves_reads_and_rejects_writes() {
+        use tower::ServiceExt;
+
+        let storage: Arc<dyn Storage> = Arc::new(InMemoryStorage::new());
+        let primary = Sequencer::with_storage_arc(storage.clone()).unwrap();
+        let owner = [1u8; 20];
+        let deposit = Tx {
+            id: 0,
+            from: owner,
+            nonce: 0,
+            kind: TxKind::Deposit,
+            payload: TxPayload::Deposit(Deposit {
+                tx_hash: [1u8; 32],
+                account: owner,
+                asset_id: 0,
+                amount: 100,
+                chain_id: zkclear_types::chain_ids::ETHEREUM,
+            }),
+            fee: 0,
+            domain: 0,
+            scheme: SignatureScheme::Secp256k1Recoverable,
+            signature: vec![0u8; 65],
+        };
+        primary.submit_tx_with_validation(deposit, false).unwrap();
+        primary.build_and_execute_block().unwrap();
+
+        let replica = Arc::new(Sequencer::with_storage_arc(storage.clone()).unwrap());
+        let state = Arc::new(
+            ApiState::new(replica)
+                .with_storage(storage)
+                .with_admin_token("secret")
+                .with_read_only(true),
+        );
+        let app = create_router(state.clone());
+        let send = |request: Request| app.clone().oneshot(request);
+
+        let request = Request::get(format!("/api/v1/account/0x{}/nonce", hex::encode(owner)))
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = send(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["nonce"], 1);
+
+        // Ready without a prover, as a replica needs none
+        let (code, _) = readiness_check(State(state.clone())).await;
+        assert_eq!(code, StatusCode::OK);
+
+        for path in [
+            "/api/v1/transactions",
+            "/api/v1/transactions/batch",
+            "/admin/build-block",
+        ] {
+            let request = Request::post(path)
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(header::AUTHORIZATION, "Bearer secret")
+                .body(axum::body::Body::from("{}"))
+                .unwrap();
+            let response = send(request).await.unwrap();
+            assert_eq!(
+                response.status(),
+                StatusCode::METHOD_NOT_ALLOWED,
+                "{}",
+                path
+            );
+        }
+
+        let body =
+            json!({ "jsonrpc": "2.0", "method": "submit_tx", "params": { "tx": "00" }, "id": 1 });
+        let request = Request::post("/jsonrpc")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(axum::body::Body::from(body.to_string()))
+            .unwrap();
+        let response = send(request).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"]["code"], -32009);
+        assert_eq!(state.sequencer.queue_length(), 0);
+    }
+
+    async fn post(body: Vec<u8>, content_type: Option<&str>) -> StatusCode {
+        use tower::ServiceExt;
+
+        let app = create_router(api_state(Arc::new(InMemoryStorage::new()), None));
+        let mut request = Request::post("/jsonrpc");
+        if let Some(content_type) = content_type {
+            request = request.header(header::CONTENT_TYPE, content_type);
+        }
+        let request = request.body(axum::body::Body::from(body)).unwrap();
+        app.oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_oversized_body_is_rejected() {
+        let padding = "x".repeat(DEFAULT_MAX_BODY_BYTES);
+        let body = json!({ "jsonrpc": "2.0", "method": padding, "params": {}, "id": 1 });
+        assert_eq!(
+            post(body.to_string().into_bytes(), Some("application/json")).await,
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+    }
+
+    #[tokio::test]
+    async fn test_post_requires_json_content_type() {
+        let body =
+            json!({ "jsonrpc": "2.0", "method": "get_account_balance", "params": {}, "id": 1 })
+                .to_string()
+                .into_bytes();
+
+        assert_eq!(
+            post(body.clone(), Some("text/plain")).await,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+        assert_eq!(
+            post(body.clone(), None).await,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+        assert_eq!(
+            post(body, Some("application/json; charset=utf-8")).await,
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn test_submissions_are_limited_per_client_ip() {
+        use tower::ServiceExt;
+
+        let mut state = api_state(Arc::new(InMemoryStorage::new()), None);
+        Arc::get_mut(&mut state).unwrap().rate_limit_state =
+            Some(Arc::new(RateLimitState::new(3, 60).with_global_limit(5)));
+        let app = create_router(state);
+
+        let submit = |sender: u8, client_ip: &str| {
+            let body = json!({
+                "kind": "Deposit",
+                "tx_hash": format!("0x{}", hex::encode([sender; 32])),
+                "account": format!("0x{}", hex::encode([sender; 20])),
+                "asset_id": 0,
+                "amount": "100",
+                "chain_id": zkclear_types::chain_ids::ETHEREUM,
+                "nonce": 0,
+                "signature": format!("0x{}", hex::encode([0u8; 65])),
+            });
+            let request = Request::post("/api/v1/transactions")
+                .header(header::CONTENT_TYPE, "application/json")
+                .header("x-forwarded-for", client_ip)
+                .body(axum::body::Body::from(body.to_string()))
+                .unwrap();
+            app.clone().oneshot(request)
+        };
+
+        // Naming a different sender in each submission doesn't get one
+        // client past its own limit
+        for sender in 1..=3 {
+            let response = submit(sender, "10.0.0.1").await.unwrap();
+            assert_ne!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        }
+        let response = submit(4, "10.0.0.1").await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = response.headers()[header::RETRY_AFTER]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((1..=60).contains(&retry_after));
+
+        // Another client is served until the global limit of 5 is reached
+        for sender in 5..=6 {
+            let response = submit(sender, "10.0.0.2").await.unwrap();
+            assert_ne!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        }
+        assert_eq!(
+            submit(7, "10.0.0.3").await.unwrap().status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+    }
+
+    #[tokio::test]
+    async fn test_probes_bypass_rate_limit() {
+        use tower::ServiceExt;
+
+        let prover = zkclear_prover::Prover::new(zkclear_prover::ProverConfig::default()).unwrap();
+        let mut state = api_state(Arc::new(InMemoryStorage::new()), Some(Arc::new(prover)));
+        Arc::get_mut(&mut state).unwrap().rate_limit_state =
+            Some(Arc::new(RateLimitState::new(100, 60).with_global_limit(2)));
+        let app = create_router(state);
+
+        let get = |uri: &str, client_ip: &str| {
+            let request = Request::get(uri)
+                .header("x-forwarded-for", client_ip)
+                .body(axum::body::Body::empty())
+                .unwrap();
+            app.clone().oneshot(request)
+        };
+
+        // Rotating client IPs exhausts the global bucket
+        for client in 1..=2 {
+            let response = get("/api/v1/stats", &format!("10.0.0.{}", client))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let response = get("/api/v1/stats", "10.0.0.3").await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        for uri in ["/health", "/ready", "/metrics"] {
+            let response = get(uri, "10.0.0.4").await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+        }
     }
 }
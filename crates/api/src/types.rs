@@ -1,5 +1,5 @@
 use serde::{Deserialize, Deserializer, Serialize};
-use zkclear_types::{Address, AssetId, BlockId, DealId};
+use zkclear_types::{Address, AssetId, BlockId, DealId, Tx};
 
 // Helper to deserialize u128 from string (JSON doesn't support numbers > 2^53)
 fn deserialize_u128_from_string<'de, D>(deserializer: D) -> Result<u128, D::Error>
@@ -121,6 +121,21 @@ pub struct AccountBalanceResponse {
     pub asset_id: AssetId,
     pub chain_id: zkclear_types::ChainId,
     pub amount: u128,
+    /// Decimal rendering of `amount`, only with `?format=decimal`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amount_formatted: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AssetBalancesResponse {
+    pub address: Address,
+    pub asset_id: AssetId,
+    /// One entry per chain the asset is held on, by chain id
+    pub balances: Vec<BalanceInfo>,
+    /// Sum of `balances` across chains
+    pub total: u128,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_formatted: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -128,8 +143,15 @@ pub struct AccountStateResponse {
     pub address: Address,
     pub account_id: u64,
     pub balances: Vec<BalanceInfo>,
+    /// Amounts locked by the account's open deals, not included in `balances`
+    pub reserved: Vec<BalanceInfo>,
     pub nonce: u64,
+    /// Pending deals the account makes or is the taker of that are still
+    /// live at the last block's timestamp
     pub open_deals: Vec<DealId>,
+    /// The account's deals that have expired, whether or not a block has
+    /// swept them to `Expired` yet
+    pub expired_deals: Vec<DealId>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -137,6 +159,8 @@ pub struct BalanceInfo {
     pub asset_id: AssetId,
     pub chain_id: zkclear_types::ChainId,
     pub amount: u128,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amount_formatted: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -153,14 +177,46 @@ pub struct DealDetailsResponse {
     pub price_quote_per_base: u128,
     pub status: String,
     pub created_at: u64,
+    /// Block time of the deal's last fill, settlement or close
+    pub updated_at: u64,
     pub expires_at: Option<u64>,
+    pub external_ref: Option<String>,
     pub is_cross_chain: bool,
+    /// Decimal renderings of the raw amounts, only with `?format=decimal`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amount_base_formatted: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amount_remaining_formatted: Option<String>,
+    /// Quote tokens per whole base token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price_quote_per_base_formatted: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FillResponse {
+    pub taker: Address,
+    pub amount_base: u128,
+    pub amount_quote: u128,
+    pub block_timestamp: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DealFillsResponse {
+    pub deal_id: DealId,
+    /// Fills in execution order
+    pub fills: Vec<FillResponse>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DealListResponse {
     pub deals: Vec<DealDetailsResponse>,
+    /// Deals matching the filters across all pages
     pub total: usize,
+    /// Pass as `after` to fetch the next page; `None` on the last page
+    pub next_cursor: Option<DealId>,
+    /// Block the read snapshot was taken at, `None` before the first block;
+    /// results may lag the live state
+    pub snapshot_block_id: Option<BlockId>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -171,6 +227,92 @@ pub struct BlockInfoResponse {
     pub transactions: Vec<TransactionInfo>,
 }
 
+/// One row of the block list; roots are 0x-prefixed hex
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlockSummary {
+    pub block_id: BlockId,
+    pub timestamp: u64,
+    pub transaction_count: usize,
+    pub state_root: String,
+    pub has_proof: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlockListResponse {
+    pub blocks: Vec<BlockSummary>,
+    /// Newest stored block; `None` before the first block is executed
+    pub latest_block_id: Option<BlockId>,
+}
+
+/// Accounts and deals changed by one block, so light clients can follow
+/// state without downloading it in full
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlockDiffResponse {
+    pub block_id: BlockId,
+    pub accounts: Vec<AccountDiff>,
+    pub deals: Vec<DealDiff>,
+    pub removed_accounts: Vec<u64>,
+    pub removed_deals: Vec<DealId>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccountDiff {
+    pub account_id: u64,
+    pub address: Address,
+    pub balances: Vec<BalanceInfo>,
+    pub reserved: Vec<BalanceInfo>,
+    pub nonce: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DealDiff {
+    pub deal_id: DealId,
+    pub status: String,
+    pub amount_remaining: u128,
+}
+
+/// Full block for L1 submission tooling; byte fields are 0x-prefixed hex
+/// and transactions are hex-encoded tx envelopes
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RawBlockResponse {
+    pub block_id: BlockId,
+    pub timestamp: u64,
+    pub state_root: String,
+    pub withdrawals_root: String,
+    pub block_proof: String,
+    pub transactions: Vec<String>,
+}
+
+/// Proof of a block's state transition for external verification
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlockProofResponse {
+    pub block_id: BlockId,
+    pub state_root: String,
+    pub withdrawals_root: String,
+    pub proof_hex: String,
+    pub proof_len: usize,
+}
+
+/// Inclusion proof of a withdrawal in its block's `withdrawals_root`.
+/// `index` is the leaf position among the block's withdrawals; siblings are
+/// ordered from the leaf up.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WithdrawalProofResponse {
+    pub leaf: String,
+    pub root: String,
+    pub siblings: Vec<String>,
+    pub index: usize,
+}
+
+/// Stored transaction looked up by hash; `tx_hash` is 0x-prefixed hex
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TransactionResponse {
+    pub tx_hash: String,
+    pub block_id: BlockId,
+    pub index: usize,
+    pub transaction: Tx,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TransactionInfo {
     pub id: u64,
@@ -179,11 +321,79 @@ pub struct TransactionInfo {
     pub kind: String,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BookDepthResponse {
+    pub asset_base: AssetId,
+    pub asset_quote: AssetId,
+    pub chain_id_base: zkclear_types::ChainId,
+    pub chain_id_quote: zkclear_types::ChainId,
+    /// Cheapest price first
+    pub levels: Vec<BookLevel>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BookLevel {
+    pub price_quote_per_base: u128,
+    /// Base left across the open public deals at this price
+    pub amount_remaining: u128,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NonceResponse {
+    pub address: Address,
+    pub nonce: u64,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct QueueStatusResponse {
     pub pending_transactions: usize,
     pub max_queue_size: usize,
     pub current_block_id: BlockId,
+    /// Fraction of the queue in use, from 0.0 to 1.0
+    pub pressure: f32,
+    /// False once the queue is full or low-fee txs are being throttled
+    pub accepting: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StatsResponse {
+    pub current_block_id: BlockId,
+    pub queue_length: usize,
+    pub max_queue_size: usize,
+    pub last_snapshot_block_id: BlockId,
+    pub accounts: usize,
+    pub deals: usize,
+    pub has_prover: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FeeEstimateResponse {
+    pub kind: String,
+    /// Encoded tx size in bytes the estimate is for
+    pub size: usize,
+    /// Value to put in the tx's `fee` field
+    pub priority_fee: u128,
+    /// Total the tx will be charged: the deployment's flat fee plus
+    /// `priority_fee`
+    pub fee_amount: u128,
+    /// Asset and chain the fee is paid in; null when no fee is charged
+    pub asset_id: Option<AssetId>,
+    pub chain_id: Option<zkclear_types::ChainId>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StateRootResponse {
+    /// Last executed block the root reflects
+    pub block_id: BlockId,
+    pub state_root: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CheckpointResponse {
+    pub block_id: BlockId,
+    pub state_root: String,
+    pub withdrawals_root_accumulator: String,
+    pub timestamp: u64,
 }
 
 #[allow(dead_code)]
@@ -229,6 +439,16 @@ pub struct ErrorResponse {
 }
 
 // Transaction submission types
+
+/// Values of the `kind` tag accepted by `SubmitTransactionRequest`, one
+/// per `TxKind`
+pub fn supported_tx_kinds() -> Vec<&'static str> {
+    zkclear_types::TxKind::ALL
+        .iter()
+        .map(|kind| kind.name())
+        .collect()
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "kind")]
 pub enum SubmitTransactionRequest {
@@ -240,12 +460,42 @@ pub enum SubmitTransactionRequest {
         amount: u128,
         chain_id: zkclear_types::ChainId,
         nonce: u64,
+        /// Network id the tx is signed for; must match the sequencer's
+        #[serde(default)]
+        domain: u64,
         signature: String, // hex string (65 bytes)
     },
     CreateDeal {
         from: String, // hex string
         deal_id: DealId,
-        visibility: String, // "Public" or "Direct"
+        visibility: String,    // "Public" or "Direct"
+        taker: Option<String>, // hex string
+        asset_base: AssetId,
+        asset_quote: AssetId,
+        chain_id_base: zkclear_types::ChainId,
+        chain_id_quote: zkclear_types::ChainId,
+        #[serde(deserialize_with = "deserialize_u128_from_string")]
+        amount_base: u128,
+        #[serde(deserialize_with = "deserialize_u128_from_string")]
+        price_quote_per_base: u128,
+        expires_at: Option<u64>,
+        external_ref: Option<String>,
+        nonce: u64,
+        /// Network id the tx is signed for; must match the sequencer's
+        #[serde(default)]
+        domain: u64,
+        signature: String, // hex string (65 bytes)
+    },
+    /// A deposit crediting `from` and a deal funded by it, applied together
+    CreateFundedDeal {
+        from: String,    // hex string
+        tx_hash: String, // hex string, of the deposit
+        deposit_asset_id: AssetId,
+        #[serde(deserialize_with = "deserialize_u128_from_string")]
+        deposit_amount: u128,
+        deposit_chain_id: zkclear_types::ChainId,
+        deal_id: DealId,
+        visibility: String,    // "Public" or "Direct"
         taker: Option<String>, // hex string
         asset_base: AssetId,
         asset_quote: AssetId,
@@ -258,6 +508,9 @@ pub enum SubmitTransactionRequest {
         expires_at: Option<u64>,
         external_ref: Option<String>,
         nonce: u64,
+        /// Network id the tx is signed for; must match the sequencer's
+        #[serde(default)]
+        domain: u64,
         signature: String, // hex string (65 bytes)
     },
     AcceptDeal {
@@ -265,13 +518,63 @@ pub enum SubmitTransactionRequest {
         deal_id: DealId,
         #[serde(deserialize_with = "deserialize_option_u128_from_string")]
         amount: Option<u128>,
+        /// Reject the accept if the deal's price is above this
+        #[serde(default, deserialize_with = "deserialize_option_u128_from_string")]
+        max_price_quote_per_base: Option<u128>,
         nonce: u64,
+        /// Network id the tx is signed for; must match the sequencer's
+        #[serde(default)]
+        domain: u64,
         signature: String, // hex string (65 bytes)
     },
     CancelDeal {
         from: String, // hex string
         deal_id: DealId,
         nonce: u64,
+        /// Network id the tx is signed for; must match the sequencer's
+        #[serde(default)]
+        domain: u64,
+        signature: String, // hex string (65 bytes)
+    },
+    DeclineDeal {
+        from: String, // hex string
+        deal_id: DealId,
+        nonce: u64,
+        /// Network id the tx is signed for; must match the sequencer's
+        #[serde(default)]
+        domain: u64,
+        signature: String, // hex string (65 bytes)
+    },
+    /// Signed by the configured settlement confirmer once a cross-chain
+    /// fill has settled on its destination chain
+    ConfirmSettlement {
+        from: String, // hex string
+        deal_id: DealId,
+        nonce: u64,
+        /// Network id the tx is signed for; must match the sequencer's
+        #[serde(default)]
+        domain: u64,
+        signature: String, // hex string (65 bytes)
+    },
+    /// Several deals from `from`, applied together or not at all
+    BatchCreateDeal {
+        from: String, // hex string
+        deals: Vec<BatchDealRequest>,
+        nonce: u64,
+        /// Network id the tx is signed for; must match the sequencer's
+        #[serde(default)]
+        domain: u64,
+        signature: String, // hex string (65 bytes)
+    },
+    /// Cancel `from`'s pending deals, only those selling `asset_base` if set
+    CancelAllDeals {
+        from: String, // hex string
+        #[serde(default)]
+        asset_base: Option<AssetId>,
+        nonce: u64,
+        /// Network id the tx is signed for; must match the sequencer's
+        #[serde(default)]
+        domain: u64,
         signature: String, // hex string (65 bytes)
     },
     Withdraw {
@@ -282,12 +585,63 @@ pub enum SubmitTransactionRequest {
         to: String, // hex string
         chain_id: zkclear_types::ChainId,
         nonce: u64,
+        /// Network id the tx is signed for; must match the sequencer's
+        #[serde(default)]
+        domain: u64,
+        signature: String, // hex string (65 bytes)
+    },
+    Transfer {
+        from: String, // hex string
+        to: String,   // hex string
+        asset_id: AssetId,
+        #[serde(deserialize_with = "deserialize_u128_from_string")]
+        amount: u128,
+        chain_id: zkclear_types::ChainId,
+        nonce: u64,
+        /// Network id the tx is signed for; must match the sequencer's
+        #[serde(default)]
+        domain: u64,
         signature: String, // hex string (65 bytes)
     },
 }
 
+/// One deal of a `BatchCreateDeal` submission
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BatchDealRequest {
+    pub deal_id: DealId,
+    pub visibility: String,    // "Public" or "Direct"
+    pub taker: Option<String>, // hex string
+    pub asset_base: AssetId,
+    pub asset_quote: AssetId,
+    pub chain_id_base: zkclear_types::ChainId,
+    pub chain_id_quote: zkclear_types::ChainId,
+    #[serde(deserialize_with = "deserialize_u128_from_string")]
+    pub amount_base: u128,
+    #[serde(deserialize_with = "deserialize_u128_from_string")]
+    pub price_quote_per_base: u128,
+    pub expires_at: Option<u64>,
+    pub external_ref: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SubmitTransactionResponse {
     pub tx_hash: String,
     pub status: String,
 }
+
+/// Outcome of one entry of a batch submission, keyed by its position in the
+/// request array
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum BatchTransactionResult {
+    Queued {
+        index: usize,
+        tx_hash: String,
+        status: String,
+    },
+    Rejected {
+        index: usize,
+        error: String,
+        message: String,
+    },
+}
@@ -0,0 +1,297 @@
+//! WebSocket feed of sequencer events at `GET /ws`
+//!
+//! Clients pick topics with `{"op": "subscribe", "topic": "blocks"}` and
+//! `{"op": "unsubscribe", ...}`, where the topic is `blocks`, `deals` or
+//! `account:<address>`. Each request is answered with an acknowledgement
+//! or an `ErrorResponse`. Events are pushed as
+//! `{"topic": ..., "event": ..., "data": ...}`:
+//!
+//! - `blocks`: `block_executed` with the block id and state root
+//! - `deals`: `deal_updated` for every deal a block creates, fills or closes
+//! - `account:<address>`: `deal_updated` for deals the address makes or
+//!   takes, and `account_updated` when its balances or nonce change
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    response::Response,
+};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::broadcast::{self, error::RecvError};
+use zkclear_sequencer::events::SequencerEvent;
+use zkclear_types::Address;
+
+use crate::handlers::{deal_details_response, ApiState};
+use crate::types::{BalanceInfo, ErrorResponse};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Topic {
+    Blocks,
+    Deals,
+    Account(Address),
+}
+
+impl Topic {
+    fn parse(topic: &str) -> Option<Self> {
+        match topic {
+            "blocks" => Some(Topic::Blocks),
+            "deals" => Some(Topic::Deals),
+            _ => {
+                let hex_address = topic.strip_prefix("account:")?;
+                let bytes = hex::decode(hex_address.trim_start_matches("0x")).ok()?;
+                Some(Topic::Account(bytes.try_into().ok()?))
+            }
+        }
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Topic::Blocks => "blocks".to_string(),
+            Topic::Deals => "deals".to_string(),
+            Topic::Account(address) => format!("account:0x{}", hex::encode(address)),
+        }
+    }
+
+    fn matches(&self, event: &SequencerEvent) -> bool {
+        match (self, event) {
+            (Topic::Blocks, SequencerEvent::BlockExecuted { .. }) => true,
+            (Topic::Deals, SequencerEvent::DealUpdated(_)) => true,
+            (Topic::Account(address), SequencerEvent::DealUpdated(deal)) => {
+                deal.maker == *address || deal.taker == Some(*address)
+            }
+            (Topic::Account(address), SequencerEvent::AccountUpdated(account)) => {
+                account.owner == *address
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum ClientRequest {
+    Subscribe { topic: String },
+    Unsubscribe { topic: String },
+}
+
+pub async fn ws_handler(ws: WebSocketUpgrade, State(state): State<Arc<ApiState>>) -> Response {
+    // Subscribe before the upgrade completes so no block executed in
+    // between is missed
+    let events = state.sequencer.subscribe_events();
+    ws.on_upgrade(move |socket| handle_socket(socket, events))
+}
+
+async fn handle_socket(mut socket: WebSocket, mut events: broadcast::Receiver<SequencerEvent>) {
+    let mut topics = HashSet::new();
+
+    loop {
+        tokio::select! {
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    let reply = handle_request(&text, &mut topics);
+                    if socket.send(Message::Text(reply)).await.is_err() {
+                        break;
+                    }
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+            event = events.recv() => match event {
+                Ok(event) => {
+                    for message in event_messages(&event, &topics) {
+                        if socket.send(Message::Text(message)).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    let message = json!({ "event": "lagged", "skipped": skipped }).to_string();
+                    if socket.send(Message::Text(message)).await.is_err() {
+                        break;
+                    }
+                }
+                Err(RecvError::Closed) => break,
+            },
+        }
+    }
+}
+
+/// Apply a subscribe or unsubscribe request and return the reply
+fn handle_request(text: &str, topics: &mut HashSet<Topic>) -> String {
+    let error = |error: &str, message: String| {
+        serde_json::to_string(&ErrorResponse {
+            error: error.to_string(),
+            message,
+        })
+        .unwrap_or_default()
+    };
+
+    let (op, topic) = match serde_json::from_str::<ClientRequest>(text) {
+        Ok(ClientRequest::Subscribe { topic }) => ("subscribe", topic),
+        Ok(ClientRequest::Unsubscribe { topic }) => ("unsubscribe", topic),
+        Err(e) => return error("InvalidRequest", format!("Invalid request: {}", e)),
+    };
+    let Some(parsed) = Topic::parse(&topic) else {
+        return error(
+            "InvalidTopic",
+            format!(
+                "Unknown topic {}; expected blocks, deals or account:<address>",
+                topic
+            ),
+        );
+    };
+
+    if op == "subscribe" {
+        topics.insert(parsed);
+    } else {
+        topics.remove(&parsed);
+    }
+    json!({ "op": op, "topic": parsed.name(), "status": "ok" }).to_string()
+}
+
+/// One message per subscribed topic the event belongs to
+fn event_messages(event: &SequencerEvent, topics: &HashSet<Topic>) -> Vec<String> {
+    let mut matching: Vec<&Topic> = topics.iter().filter(|t| t.matches(event)).collect();
+    if matching.is_empty() {
+        return Vec::new();
+    }
+    matching.sort_by_key(|t| t.name());
+
+    let (name, data) = match event {
+        SequencerEvent::BlockExecuted {
+            block_id,
+            state_root,
+            timestamp,
+            transaction_count,
+        } => (
+            "block_executed",
+            json!({
+                "block_id": block_id,
+                "state_root": format!("0x{}", hex::encode(state_root)),
+                "timestamp": timestamp,
+                "transaction_count": transaction_count,
+            }),
+        ),
+        SequencerEvent::DealUpdated(deal) => (
+            "deal_updated",
+            serde_json::to_value(deal_details_response(deal, None)).unwrap_or_default(),
+        ),
+        SequencerEvent::AccountUpdated(account) => {
+            let balance_info = |b: zkclear_types::Balance| BalanceInfo {
+                asset_id: b.asset_id,
+                chain_id: b.chain_id,
+                amount: b.amount,
+                amount_formatted: None,
+            };
+            (
+                "account_updated",
+                json!({
+                    "address": account.owner,
+                    "account_id": account.id,
+                    "nonce": account.nonce,
+                    "balances": account.balances.iter().map(balance_info).collect::<Vec<_>>(),
+                    "reserved": account.reserved.iter().map(balance_info).collect::<Vec<_>>(),
+                }),
+            )
+        }
+    };
+
+    matching
+        .into_iter()
+        .map(|topic| json!({ "topic": topic.name(), "event": name, "data": data }).to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routes::create_router;
+    use futures::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite::Message as ClientMessage;
+    use zkclear_sequencer::Sequencer;
+    use zkclear_types::{Deposit, SignatureScheme, Tx, TxKind, TxPayload};
+
+    async fn next_json<S>(client: &mut S) -> serde_json::Value
+    where
+        S: StreamExt<Item = Result<ClientMessage, tokio_tungstenite::tungstenite::Error>> + Unpin,
+    {
+        loop {
+            let message = tokio::time::timeout(std::time::Duration::from_secs(5), client.next())
+                .await
+                .expect("timed out waiting for a message")
+                .expect("connection closed")
+                .unwrap();
+            if let ClientMessage::Text(text) = message {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_blocks_subscription_pushes_executed_block() {
+        let sequencer = Arc::new(Sequencer::new());
+        let app = create_router(Arc::new(ApiState::new(sequencer.clone())));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr))
+            .await
+            .unwrap();
+
+        client
+            .send(ClientMessage::Text(
+                r#"{"op":"subscribe","topic":"nope"}"#.into(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(next_json(&mut client).await["error"], "InvalidTopic");
+
+        client
+            .send(ClientMessage::Text(
+                r#"{"op":"subscribe","topic":"blocks"}"#.into(),
+            ))
+            .await
+            .unwrap();
+        let ack = next_json(&mut client).await;
+        assert_eq!(ack["status"], "ok");
+        assert_eq!(ack["topic"], "blocks");
+
+        let owner = [1u8; 20];
+        let deposit = Tx {
+            id: 0,
+            from: owner,
+            nonce: 0,
+            kind: TxKind::Deposit,
+            payload: TxPayload::Deposit(Deposit {
+                tx_hash: [1u8; 32],
+                account: owner,
+                asset_id: 0,
+                amount: 100,
+                chain_id: zkclear_types::chain_ids::ETHEREUM,
+            }),
+            fee: 0,
+            domain: 0,
+            scheme: SignatureScheme::Secp256k1Recoverable,
+            signature: vec![0u8; 65],
+        };
+        sequencer.submit_tx_with_validation(deposit, false).unwrap();
+        let block = sequencer.build_and_execute_block().unwrap();
+
+        let event = next_json(&mut client).await;
+        assert_eq!(event["topic"], "blocks");
+        assert_eq!(event["event"], "block_executed");
+        assert_eq!(event["data"]["block_id"], block.id);
+        assert_eq!(
+            event["data"]["state_root"],
+            format!("0x{}", hex::encode(block.state_root))
+        );
+        assert_eq!(event["data"]["transaction_count"], 1);
+    }
+}
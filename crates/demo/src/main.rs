@@ -13,10 +13,13 @@ use std::sync::Arc;
 use zkclear_prover::{Prover, ProverConfig};
 use zkclear_sequencer::Sequencer;
 use zkclear_types::{
-    AcceptDeal, Address, AssetId, CreateDeal, DealVisibility, Deposit, Tx, TxKind, TxPayload,
-    Withdraw,
+    AcceptDeal, Address, Amount, AssetId, CreateDeal, DealVisibility, Deposit, SignatureScheme, Tx,
+    TxKind, TxPayload, Withdraw,
 };
 
+/// Network id the demo sequencer runs as; every tx is signed for it
+const NETWORK_ID: u64 = 1337;
+
 fn addr(byte: u8) -> Address {
     [byte; 20]
 }
@@ -62,7 +65,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         println!("   Groth16 keys ready");
     }
 
-    let sequencer = Arc::new(Sequencer::new().with_prover(prover.clone()));
+    let sequencer = Arc::new(
+        Sequencer::new()
+            .with_network_id(NETWORK_ID)
+            .with_prover(prover.clone()),
+    );
 
     println!("   Prover initialized");
     println!("   Sequencer initialized");
@@ -105,7 +112,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             amount: 1_000_000, // 1 USDC (6 decimals)
             chain_id: ethereum_chain,
         }),
-        signature: [0u8; 65],
+        fee: 0,
+        domain: NETWORK_ID,
+        scheme: SignatureScheme::Secp256k1Recoverable,
+        signature: vec![0u8; 65],
     };
     sequencer
         .submit_tx_with_validation(maker_usdc_deposit, false)
@@ -125,7 +135,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             amount: 1_000_000, // 1 USDC
             chain_id: ethereum_chain,
         }),
-        signature: [0u8; 65],
+        fee: 0,
+        domain: NETWORK_ID,
+        scheme: SignatureScheme::Secp256k1Recoverable,
+        signature: vec![0u8; 65],
     };
     sequencer
         .submit_tx_with_validation(taker_usdc_deposit, false)
@@ -145,7 +158,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             amount: 10_000, // 0.1 BTC (5 decimals)
             chain_id: base_chain,
         }),
-        signature: [0u8; 65],
+        fee: 0,
+        domain: NETWORK_ID,
+        scheme: SignatureScheme::Secp256k1Recoverable,
+        signature: vec![0u8; 65],
     };
     sequencer
         .submit_tx_with_validation(maker_btc_deposit, false)
@@ -173,7 +189,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             expires_at: None,
             external_ref: None,
         }),
-        signature: [0u8; 65],
+        fee: 0,
+        domain: NETWORK_ID,
+        scheme: SignatureScheme::Secp256k1Recoverable,
+        signature: vec![0u8; 65],
     };
     sequencer
         .submit_tx_with_validation(create_deal_tx, false)
@@ -195,8 +214,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         payload: TxPayload::AcceptDeal(AcceptDeal {
             deal_id: 42,
             amount: None, // Accept full amount
+            max_price_quote_per_base: None,
         }),
-        signature: [0u8; 65],
+        fee: 0,
+        domain: NETWORK_ID,
+        scheme: SignatureScheme::Secp256k1Recoverable,
+        signature: vec![0u8; 65],
     };
     sequencer
         .submit_tx_with_validation(accept_deal_tx, false)
@@ -266,11 +289,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 x if x == base_chain => "Base",
                 _ => "Unknown",
             };
-            let amount = if b.asset_id == usdc {
-                format!("{:.6}", b.amount as f64 / 1_000_000.0)
-            } else {
-                format!("{:.5}", b.amount as f64 / 100_000.0)
-            };
+            let decimals = if b.asset_id == usdc { 6 } else { 5 };
+            let amount = Amount(b.amount).format_units(decimals);
             println!("         {} {} on {}", amount, asset_name, chain_name);
         }
     }
@@ -300,7 +320,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             to: maker,
             chain_id: ethereum_chain,
         }),
-        signature: [0u8; 65],
+        fee: 0,
+        domain: NETWORK_ID,
+        scheme: SignatureScheme::Secp256k1Recoverable,
+        signature: vec![0u8; 65],
     };
     sequencer
         .submit_tx_with_validation(withdraw_tx, false)
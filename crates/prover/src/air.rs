@@ -9,8 +9,10 @@
 //! 2. Applying transactions to prev_state results in new_state
 //! 3. The state roots are correctly computed
 //! 4. The withdrawals root is correctly computed
+//! 5. The transition was produced by exactly the committed transaction list
 
 use crate::error::ProverError;
+use crate::merkle::{hash_tx_with, HashAlgo, MerkleTree};
 use sha2::{Digest, Sha256};
 use zkclear_state::State;
 use zkclear_stf::apply_tx;
@@ -22,6 +24,9 @@ pub struct BlockTransitionInputs {
     pub prev_state_root: [u8; 32],
     pub new_state_root: [u8; 32],
     pub withdrawals_root: [u8; 32],
+    /// Merkle root over the block's tx hashes, see
+    /// `merkle::compute_tx_list_root`
+    pub tx_list_root: [u8; 32],
     pub block_id: u64,
     pub timestamp: u64,
 }
@@ -101,11 +106,22 @@ impl MinimalStarkProof {
 }
 
 /// Minimal STARK prover
-pub struct MinimalStarkProver;
+pub struct MinimalStarkProver {
+    /// Hash of the trace's state roots and tx list root; the trace and
+    /// constraint commitments always use SHA-256
+    hash_algo: HashAlgo,
+}
 
 impl MinimalStarkProver {
     pub fn new() -> Self {
-        Self
+        Self {
+            hash_algo: HashAlgo::Sha256,
+        }
+    }
+
+    pub fn with_hash_algo(mut self, hash_algo: HashAlgo) -> Self {
+        self.hash_algo = hash_algo;
+        self
     }
 
     /// Generate a STARK proof for block state transition
@@ -165,6 +181,7 @@ impl MinimalStarkProver {
 
         // Build trace rows
         let mut rows = Vec::new();
+        let mut tx_list = MerkleTree::new_with(self.hash_algo);
 
         // Initial row
         rows.push(TraceRow {
@@ -178,10 +195,8 @@ impl MinimalStarkProver {
         // Process transactions
         for (tx_index, tx) in block.transactions.iter().enumerate() {
             // Compute transaction hash
-            let tx_bytes = bincode::serialize(tx).map_err(|e| {
-                ProverError::Serialization(format!("Failed to serialize tx: {}", e))
-            })?;
-            let tx_hash: [u8; 32] = Sha256::digest(&tx_bytes).into();
+            let tx_hash = hash_tx_with(self.hash_algo, tx)?;
+            tx_list.add_leaf(tx_hash);
 
             // Apply transaction
             apply_tx(&mut state, tx, block.timestamp)
@@ -224,57 +239,17 @@ impl MinimalStarkProver {
             width: 8, // prev_state_root (2) + tx_hash (2) + new_state_root (2) + tx_index (1) + timestamp (1)
             length: trace_length,
             rows,
+            tx_list_root: tx_list.root()?,
         })
     }
 
     /// Compute state root from state
-    fn compute_state_root(&self, state: &State) -> Result<[u8; 32], ProverError> {
-        use crate::merkle::{hash_state_leaf, MerkleTree};
-
-        let mut tree = MerkleTree::new();
-
-        // Add all accounts as leaves
-        let mut account_ids: Vec<_> = state.accounts.keys().collect();
-        account_ids.sort();
-
-        for account_id in account_ids {
-            let account = state.accounts.get(account_id).ok_or_else(|| {
-                ProverError::StarkProof(format!("Account {} not found", account_id))
-            })?;
-
-            let account_bytes = bincode::serialize(account).map_err(|e| {
-                ProverError::Serialization(format!("Failed to serialize account: {}", e))
-            })?;
-
-            let leaf = hash_state_leaf(&account_bytes);
-            tree.add_leaf(leaf);
-        }
-
-        // Add all deals as leaves
-        let mut deal_ids: Vec<_> = state.deals.keys().collect();
-        deal_ids.sort();
-
-        for deal_id in deal_ids {
-            let deal = state
-                .deals
-                .get(deal_id)
-                .ok_or_else(|| ProverError::StarkProof(format!("Deal {} not found", deal_id)))?;
-
-            let deal_bytes = bincode::serialize(deal).map_err(|e| {
-                ProverError::Serialization(format!("Failed to serialize deal: {}", e))
-            })?;
-
-            let leaf = hash_state_leaf(&deal_bytes);
-            tree.add_leaf(leaf);
-        }
-
-        tree.root()
+    fn compute_state_root(&self, state: &State) -> Result<[u8; 32], ProverError>{
+        crate::merkle::compute_state_root_with(self.hash_algo, state)
     }
 
     /// Compute trace commitment (Merkle root of trace)
     fn compute_trace_commitment(&self, trace: &ExecutionTrace) -> Result<[u8; 32], ProverError> {
-        use crate::merkle::MerkleTree;
-
         let mut tree = MerkleTree::new();
 
         for row in &trace.rows {
@@ -403,6 +378,21 @@ impl MinimalStarkProver {
         hasher.update(&public_inputs.new_state_root);
         constraints.push(hasher.finalize().into());
 
+        // Constraint 6: Transaction list root assertion
+        // The trace's transactions must be exactly the committed list
+        if trace.tx_list_root != public_inputs.tx_list_root {
+            return Err(ProverError::StarkProof(format!(
+                "Transaction list root mismatch: trace has {:?}, expected {:?}",
+                trace.tx_list_root, public_inputs.tx_list_root
+            )));
+        }
+
+        let mut hasher = Sha256::new();
+        hasher.update(b"tx_list_root");
+        hasher.update(trace.tx_list_root);
+        hasher.update(public_inputs.tx_list_root);
+        constraints.push(hasher.finalize().into());
+
         Ok(constraints)
     }
 
@@ -411,8 +401,6 @@ impl MinimalStarkProver {
         &self,
         constraints: &[[u8; 32]],
     ) -> Result<[u8; 32], ProverError> {
-        use crate::merkle::MerkleTree;
-
         let mut tree = MerkleTree::new();
 
         for constraint in constraints {
@@ -441,6 +429,8 @@ pub struct ExecutionTrace {
     pub width: usize,
     pub length: usize,
     pub rows: Vec<TraceRow>,
+    /// Merkle root over the rows' tx hashes, padding excluded
+    pub tx_list_root: [u8; 32],
 }
 
 /// Minimal STARK verifier
@@ -511,6 +501,9 @@ impl MinimalStarkVerifier {
         if proof.public_inputs.withdrawals_root != expected_public_inputs.withdrawals_root {
             return Ok(false);
         }
+        if proof.public_inputs.tx_list_root != expected_public_inputs.tx_list_root {
+            return Ok(false);
+        }
 
         Ok(true)
     }
@@ -11,7 +11,7 @@ use std::time::SystemTime;
 use zkclear_prover::{Prover, ProverConfig};
 use zkclear_state::State;
 use zkclear_stf::apply_tx;
-use zkclear_types::{Address, Block, Deposit, Tx, TxKind, TxPayload};
+use zkclear_types::{Address, Block, Deposit, SignatureScheme, Tx, TxKind, TxPayload};
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let rt = tokio::runtime::Builder::new_multi_thread()
@@ -35,6 +35,7 @@ async fn async_main() -> Result<(), Box<dyn std::error::Error>> {
         groth16_keys_dir: Some("./keys".into()),
         force_regenerate_keys: false,
         use_placeholders: false,
+        hash_algo: Default::default(),
     };
 
     let prover = Prover::new(config).map_err(|e| format!("Failed to create prover: {}", e))?;
@@ -63,7 +64,10 @@ async fn async_main() -> Result<(), Box<dyn std::error::Error>> {
                     amount: 1000,
                     chain_id: 1,
                 }),
-                signature: [0u8; 65],
+                fee: 0,
+                domain: 0,
+                scheme: SignatureScheme::Secp256k1Recoverable,
+                signature: vec![0u8; 65],
             },
             Tx {
                 id: 1,
@@ -77,7 +81,10 @@ async fn async_main() -> Result<(), Box<dyn std::error::Error>> {
                     amount: 2000,
                     chain_id: 1,
                 }),
-                signature: [0u8; 65],
+                fee: 0,
+                domain: 0,
+                scheme: SignatureScheme::Secp256k1Recoverable,
+                signature: vec![0u8; 65],
             },
         ],
         state_root: [0u8; 32],
@@ -14,6 +14,9 @@ pub enum ProverError {
     #[error("Invalid state root: {0}")]
     InvalidStateRoot(String),
 
+    #[error("Missing proof: {0}")]
+    MissingProof(String),
+
     #[error("Invalid withdrawals root: {0}")]
     InvalidWithdrawalsRoot(String),
 
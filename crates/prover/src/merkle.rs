@@ -1,15 +1,27 @@
 use crate::error::ProverError;
-use sha2::{Digest, Sha256};
-use zkclear_types::{Address, AssetId, ChainId};
+use zkclear_state::State;
+use zkclear_types::{Address, AssetId, ChainId, Tx, TxPayload};
+
+pub use zkclear_state::HashAlgo;
 
 /// Merkle tree for state roots and withdrawals roots
 pub struct MerkleTree {
     pub(crate) leaves: Vec<[u8; 32]>,
+    algo: HashAlgo,
 }
 
 impl MerkleTree {
+    /// Empty tree hashing with SHA-256
     pub fn new() -> Self {
-        Self { leaves: Vec::new() }
+        Self::new_with(HashAlgo::Sha256)
+    }
+
+    /// Empty tree hashing with `algo`
+    pub fn new_with(algo: HashAlgo) -> Self {
+        Self {
+            leaves: Vec::new(),
+            algo,
+        }
     }
 
     /// Add a leaf to the tree
@@ -18,43 +30,22 @@ impl MerkleTree {
     }
 
     /// Compute the Merkle root
-    /// Optimized to avoid unnecessary allocations
     pub fn root(&self) -> Result<[u8; 32], ProverError> {
         if self.leaves.is_empty() {
             return Ok([0u8; 32]);
         }
 
-        if self.leaves.len() == 1 {
-            return Ok(self.leaves[0]);
-        }
-
-        // Pre-allocate with capacity estimate to reduce reallocations
-        let mut current_level = Vec::with_capacity(self.leaves.len());
-        current_level.extend_from_slice(&self.leaves);
-
+        let mut current_level = self.leaves.clone();
         while current_level.len() > 1 {
-            let next_level_len = (current_level.len() + 1) / 2;
-            let mut next_level = Vec::with_capacity(next_level_len);
-
-            for i in (0..current_level.len()).step_by(2) {
-                if i + 1 < current_level.len() {
-                    let hash = hash_pair(&current_level[i], &current_level[i + 1]);
-                    next_level.push(hash);
-                } else {
-                    // Odd number of nodes, duplicate the last one
-                    let hash = hash_pair(&current_level[i], &current_level[i]);
-                    next_level.push(hash);
-                }
-            }
-
-            current_level = next_level;
+            current_level = self.parent_level(&current_level);
         }
 
         Ok(current_level[0])
     }
 
-    /// Generate a Merkle proof for a leaf at the given index
-    pub fn proof(&self, leaf_index: usize) -> Result<Vec<[u8; 32]>, ProverError> {
+    /// Inclusion proof for the leaf at `leaf_index`: its sibling on every
+    /// level from the leaves up, built the same way as `root`
+    pub fn prove(&self, leaf_index: usize) -> Result<MerkleProof, ProverError> {
         if leaf_index >= self.leaves.len() {
             return Err(ProverError::MerkleTree(format!(
                 "Leaf index {} out of bounds",
@@ -62,55 +53,46 @@ impl MerkleTree {
             )));
         }
 
-        if self.leaves.len() == 1 {
-            return Ok(Vec::new());
-        }
-
-        let mut proof = Vec::new();
+        let mut siblings = Vec::new();
         let mut current_level = self.leaves.clone();
         let mut current_index = leaf_index;
 
         while current_level.len() > 1 {
-            let sibling_index = if current_index % 2 == 0 {
-                current_index + 1
-            } else {
-                current_index - 1
-            };
-
-            if sibling_index < current_level.len() {
-                proof.push(current_level[sibling_index]);
-            } else {
-                // Sibling doesn't exist (odd number of nodes), duplicate the current node
-                proof.push(current_level[current_index]);
-            }
-
-            // Build next level
-            let mut next_level = Vec::new();
-            for i in (0..current_level.len()).step_by(2) {
-                if i + 1 < current_level.len() {
-                    let hash = hash_pair(&current_level[i], &current_level[i + 1]);
-                    next_level.push(hash);
-                } else {
-                    // Odd number, duplicate the last node
-                    let hash = hash_pair(&current_level[i], &current_level[i]);
-                    next_level.push(hash);
-                }
-            }
+            // The last node of an odd level is paired with itself
+            let sibling_index = (current_index ^ 1).min(current_level.len() - 1);
+            siblings.push(current_level[sibling_index]);
 
-            // Update current_index for next level
-            current_index = current_index / 2;
-            current_level = next_level;
+            current_level = self.parent_level(&current_level);
+            current_index /= 2;
         }
 
-        Ok(proof)
+        Ok(MerkleProof {
+            leaf_index,
+            siblings,
+        })
+    }
+
+    /// Hash each pair of nodes into the level above, pairing the last node
+    /// of an odd level with itself
+    fn parent_level(&self, level: &[[u8; 32]]) -> Vec<[u8; 32]> {
+        level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => self.algo.hash_pair(left, right),
+                [last] => self.algo.hash_pair(last, last),
+                _ => unreachable!(),
+            })
+            .collect()
     }
 }
 
-fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
-    let mut hasher = Sha256::new();
-    hasher.update(left);
-    hasher.update(right);
-    hasher.finalize().into()
+/// Inclusion proof of one leaf of a `MerkleTree`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleProof {
+    /// Position of the leaf among the tree's leaves
+    pub leaf_index: usize,
+    /// Sibling of the leaf's path on each level, from the leaves up
+    pub siblings: Vec<[u8; 32]>,
 }
 
 /// Hash a withdrawal to create a leaf
@@ -120,88 +102,203 @@ pub fn hash_withdrawal(
     amount: u128,
     chain_id: ChainId,
 ) -> [u8; 32] {
-    let mut hasher = Sha256::new();
-    hasher.update(&user);
-    hasher.update(&asset_id.to_le_bytes());
-    hasher.update(&amount.to_le_bytes());
-    hasher.update(&chain_id.to_le_bytes());
-    hasher.finalize().into()
+    hash_withdrawal_with(HashAlgo::Sha256, user, asset_id, amount, chain_id)
+}
+
+/// `hash_withdrawal` under `algo`
+pub fn hash_withdrawal_with(
+    algo: HashAlgo,
+    user: Address,
+    asset_id: AssetId,
+    amount: u128,
+    chain_id: ChainId,
+) -> [u8; 32] {
+    let mut data = Vec::with_capacity(20 + 2 + 16 + 8);
+    data.extend_from_slice(&user);
+    data.extend_from_slice(&asset_id.to_le_bytes());
+    data.extend_from_slice(&amount.to_le_bytes());
+    data.extend_from_slice(&chain_id.to_le_bytes());
+    algo.hash(&data)
+}
+
+/// Withdrawal leaves of a block in canonical order, each paired with the
+/// position of its tx in the block.
+///
+/// Leaves are sorted by `(user, asset_id, chain_id, amount)` rather than
+/// kept in tx order, so the withdrawals root does not depend on how the
+/// block ordered its txs and a verifier can locate a withdrawal's leaf from
+/// the withdrawal alone. Identical withdrawals hash to identical leaves, so
+/// ties do not affect the root.
+pub fn withdrawal_leaves(transactions: &[Tx]) -> Vec<(usize, [u8; 32])> {
+    withdrawal_leaves_with(HashAlgo::Sha256, transactions)
+}
+
+/// `withdrawal_leaves` hashed under `algo`
+pub fn withdrawal_leaves_with(algo: HashAlgo, transactions: &[Tx]) -> Vec<(usize, [u8; 32])> {
+    let mut withdrawals: Vec<_> = transactions
+        .iter()
+        .enumerate()
+        .filter_map(|(position, tx)| match &tx.payload {
+            TxPayload::Withdraw(w) => Some((position, tx.from, w)),
+            _ => None,
+        })
+        .collect();
+    withdrawals.sort_by_key(|(_, user, w)| (*user, w.asset_id, w.chain_id, w.amount));
+
+    withdrawals
+        .into_iter()
+        .map(|(position, user, w)| {
+            (
+                position,
+                hash_withdrawal_with(algo, user, w.asset_id, w.amount, w.chain_id),
+            )
+        })
+        .collect()
+}
+
+/// Merkle root over a block's withdrawals, in the order of `withdrawal_leaves`
+pub fn compute_withdrawals_root(transactions: &[Tx]) -> Result<[u8; 32], ProverError> {
+    compute_withdrawals_root_with(HashAlgo::Sha256, transactions)
+}
+
+/// `compute_withdrawals_root` under `algo`
+pub fn compute_withdrawals_root_with(
+    algo: HashAlgo,
+    transactions: &[Tx],
+) -> Result<[u8; 32], ProverError> {
+    let mut tree = MerkleTree::new_with(algo);
+    for (_, leaf) in withdrawal_leaves_with(algo, transactions) {
+        tree.add_leaf(leaf);
+    }
+    tree.root()
+}
+
+/// Hash of a tx as committed in the tx list root: SHA-256 of its bincode
+/// encoding
+pub fn hash_tx(tx: &Tx) -> Result<[u8; 32], ProverError> {
+    hash_tx_with(HashAlgo::Sha256, tx)
+}
+
+/// `hash_tx` under `algo`
+pub fn hash_tx_with(algo: HashAlgo, tx: &Tx) -> Result<[u8; 32], ProverError> {
+    let tx_bytes = bincode::serialize(tx)
+        .map_err(|e| ProverError::Serialization(format!("Failed to serialize tx: {}", e)))?;
+    Ok(algo.hash(&tx_bytes))
+}
+
+/// Merkle root over the hashes of a block's txs in block order. Unlike the
+/// state roots it binds the exact tx list, so two lists reaching the same
+/// state still commit differently.
+pub fn compute_tx_list_root(transactions: &[Tx]) -> Result<[u8; 32], ProverError> {
+    compute_tx_list_root_with(HashAlgo::Sha256, transactions)
+}
+
+/// `compute_tx_list_root` under `algo`
+pub fn compute_tx_list_root_with(
+    algo: HashAlgo,
+    transactions: &[Tx],
+) -> Result<[u8; 32], ProverError> {
+    let mut tree = MerkleTree::new_with(algo);
+    for tx in transactions {
+        tree.add_leaf(hash_tx_with(algo, tx)?);
+    }
+    tree.root()
 }
 
 /// Hash state data to create a leaf for state root
 pub fn hash_state_leaf(data: &[u8]) -> [u8; 32] {
-    let mut hasher = Sha256::new();
-    hasher.update(data);
-    hasher.finalize().into()
+    HashAlgo::Sha256.hash(data)
 }
 
-/// Verify a Merkle proof
-///
-/// This verifies that a leaf is included in a Merkle tree with the given root.
-/// The proof should be generated by the same tree structure.
-///
-/// # Arguments
-/// * `leaf` - The leaf hash to verify
-/// * `proof` - The Merkle proof (list of sibling hashes from leaf to root)
-/// * `root` - The expected Merkle root
-/// * `leaf_index` - The index of the leaf in the original tree (required for trees with >2 leaves)
+/// Merkle root over the state: one leaf per account in id order, followed by
+/// one leaf per deal in id order. This is the state root committed in blocks
+/// and used as the prover's public input.
+pub fn compute_state_root(state: &State) -> Result<[u8; 32], ProverError> {
+    compute_state_root_with(HashAlgo::Sha256, state)
+}
+
+/// `compute_state_root` under `algo`; matches `State::root_with(algo)`
+pub fn compute_state_root_with(algo: HashAlgo, state: &State) -> Result<[u8; 32], ProverError> {
+    // Use Merkle tree approach for proper state root computation
+    let mut tree = MerkleTree::new_with(algo);
+
+    // Add all accounts as leaves
+    let mut account_ids: Vec<_> = state.accounts.keys().collect();
+    account_ids.sort();
+
+    for account_id in account_ids {
+        let account = state
+            .accounts
+            .get(account_id)
+            .ok_or_else(|| ProverError::StarkProof(format!("Account {} not found", account_id)))?;
+
+        let account_bytes = bincode::serialize(account).map_err(|e| {
+            ProverError::Serialization(format!("Failed to serialize account: {}", e))
+        })?;
+
+        let leaf = algo.hash(&account_bytes);
+        tree.add_leaf(leaf);
+    }
+
+    // Add all deals as leaves
+    let mut deal_ids: Vec<_> = state.deals.keys().collect();
+    deal_ids.sort();
+
+    for deal_id in deal_ids {
+        let deal = state
+            .deals
+            .get(deal_id)
+            .ok_or_else(|| ProverError::StarkProof(format!("Deal {} not found", deal_id)))?;
+
+        let deal_bytes = bincode::serialize(deal)
+            .map_err(|e| ProverError::Serialization(format!("Failed to serialize deal: {}", e)))?;
+
+        let leaf = algo.hash(&deal_bytes);
+        tree.add_leaf(leaf);
+    }
+
+    tree.root()
+}
+
+/// Whether `proof` shows `leaf` at `proof.leaf_index` in the SHA-256 tree
+/// with `root`. The index decides on which side each sibling is hashed in,
+/// so a proof only verifies at the position it was made for.
 ///
-/// # Returns
-/// `true` if the proof is valid, `false` otherwise
-pub fn verify_merkle_proof(
+/// Because the last node of an odd level is paired with itself, the last
+/// leaf of an odd-sized tree also verifies one position past the end; check
+/// the index against the leaf count where it is known.
+pub fn verify_merkle_proof(leaf: &[u8; 32], proof: &MerkleProof, root: &[u8; 32]) -> bool {
+    verify_merkle_proof_with(HashAlgo::Sha256, leaf, proof, root)
+}
+
+/// `verify_merkle_proof` for a tree hashed under `algo`
+pub fn verify_merkle_proof_with(
+    algo: HashAlgo,
     leaf: &[u8; 32],
-    proof: &[[u8; 32]],
+    proof: &MerkleProof,
     root: &[u8; 32],
-    leaf_index: Option<usize>,
 ) -> bool {
-    if proof.is_empty() {
-        return leaf == root;
-    }
-
     let mut current = *leaf;
-
-    // If we have the leaf index, use it to correctly track position at each level
-    if let Some(mut idx) = leaf_index {
-        for sibling in proof {
-            // Determine if current node is left or right at this level
-            let is_left = idx % 2 == 0;
-
-            if is_left {
-                // Current is left, sibling is right
-                current = hash_pair(&current, sibling);
-            } else {
-                // Current is right, sibling is left
-                current = hash_pair(sibling, &current);
-            }
-
-            // Move to parent level (divide by 2)
-            idx /= 2;
-        }
-    } else {
-        // Without index, we can only verify for simple cases (1-2 leaves)
-        // For trees with >2 leaves, we need the index to determine position
-        // at each level. For backward compatibility, we'll try a simple approach
-        // that works for 2 leaves but may fail for larger trees.
-
-        // Simple heuristic: assume alternating left/right
-        // This works for 2 leaves but not for larger trees
-        let mut is_left = true;
-        for sibling in proof {
-            if is_left {
-                current = hash_pair(&current, sibling);
-            } else {
-                current = hash_pair(sibling, &current);
-            }
-            is_left = !is_left;
-        }
+    let mut index = proof.leaf_index;
+
+    for sibling in &proof.siblings {
+        current = if index % 2 == 0 {
+            algo.hash_pair(&current, sibling)
+        } else {
+            algo.hash_pair(sibling, &current)
+        };
+        index /= 2;
     }
 
-    current == *root
+    // An index past the tree's depth would otherwise verify as the same
+    // path with its high bits ignored
+    index == 0 && current == *root
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use zkclear_types::SignatureScheme;
 
     #[test]
     fn test_merkle_tree_single_leaf() {
@@ -213,61 +310,115 @@ mod tests {
     }
 
     #[test]
-    fn test_merkle_tree_two_leaves() {
-        let mut tree = MerkleTree::new();
-        let leaf1 = [1u8; 32];
-        let leaf2 = [2u8; 32];
-        tree.add_leaf(leaf1);
-        tree.add_leaf(leaf2);
-        let root = tree.root().unwrap();
-
-        // Verify proof
-        let proof = tree.proof(0).unwrap();
-        assert!(verify_merkle_proof(&leaf1, &proof, &root, Some(0)));
+    fn test_proofs_verify_for_every_leaf() {
+        for size in [1usize, 2, 3, 8] {
+            let mut tree = MerkleTree::new();
+            for i in 0..size {
+                tree.add_leaf([i as u8 + 1; 32]);
+            }
+            let root = tree.root().unwrap();
+
+            for i in 0..size {
+                let leaf = [i as u8 + 1; 32];
+                let proof = tree.prove(i).unwrap();
+                assert!(
+                    verify_merkle_proof(&leaf, &proof, &root),
+                    "leaf {} of {}",
+                    i,
+                    size
+                );
+
+                let mut flipped = leaf;
+                flipped[0] ^= 1;
+                assert!(!verify_merkle_proof(&flipped, &proof, &root));
+
+                // The same siblings don't verify at another position
+                let moved = MerkleProof {
+                    leaf_index: i + 4,
+                    ..proof
+                };
+                assert!(!verify_merkle_proof(&leaf, &moved, &root));
+            }
+            assert!(tree.prove(size).is_err());
+        }
     }
 
-    #[test]
-    fn test_merkle_tree_multiple_leaves() {
-        let mut tree = MerkleTree::new();
-        for i in 0..4 {
-            tree.add_leaf([i as u8; 32]);
+    fn withdraw_tx(from: u8, asset_id: AssetId, amount: u128) -> Tx {
+        Tx {
+            id: 0,
+            from: [from; 20],
+            nonce: 0,
+            kind: zkclear_types::TxKind::Withdraw,
+            payload: TxPayload::Withdraw(zkclear_types::Withdraw {
+                asset_id,
+                amount,
+                to: [from; 20],
+                chain_id: zkclear_types::chain_ids::ETHEREUM,
+            }),
+            fee: 0,
+            domain: 0,
+            scheme: SignatureScheme::Secp256k1Recoverable,
+            signature: vec![0u8; 65],
         }
-        let root = tree.root().unwrap();
+    }
 
-        // Verify proof for each leaf (using power of 2 for simpler tree structure)
-        for i in 0..4 {
-            let leaf = [i as u8; 32];
-            let proof = tree.proof(i).unwrap();
-            assert!(
-                verify_merkle_proof(&leaf, &proof, &root, Some(i)),
-                "Failed to verify proof for leaf {}: proof len={}, root={:?}",
-                i,
-                proof.len(),
-                root
-            );
-        }
+    #[test]
+    fn test_withdrawals_root_ignores_tx_order() {
+        let txs = vec![
+            withdraw_tx(2, 0, 10),
+            withdraw_tx(1, 1, 50),
+            withdraw_tx(1, 0, 70),
+            withdraw_tx(1, 0, 30),
+        ];
+        let mut reversed = txs.clone();
+        reversed.reverse();
+
+        assert_eq!(
+            compute_withdrawals_root(&txs).unwrap(),
+            compute_withdrawals_root(&reversed).unwrap()
+        );
+
+        let positions: Vec<usize> = withdrawal_leaves(&txs).iter().map(|(p, _)| *p).collect();
+        assert_eq!(positions, vec![3, 2, 1, 0]);
     }
 
     #[test]
-    fn test_merkle_tree_larger_tree() {
-        // Test with 8 leaves to ensure it works for larger trees
-        let mut tree = MerkleTree::new();
-        for i in 0..8 {
-            tree.add_leaf([i as u8; 32]);
+    fn test_roots_differ_per_hash_algo_and_proofs_verify() {
+        let leaves: Vec<[u8; 32]> = (0..5).map(|i| [i as u8; 32]).collect();
+        let build = |algo| {
+            let mut tree = MerkleTree::new_with(algo);
+            for leaf in &leaves {
+                tree.add_leaf(*leaf);
+            }
+            tree
+        };
+
+        let sha = build(HashAlgo::Sha256);
+        let keccak = build(HashAlgo::Keccak256);
+        let sha_root = sha.root().unwrap();
+        let keccak_root = keccak.root().unwrap();
+        assert_ne!(sha_root, keccak_root);
+        // SHA-256 stays the default
+        let mut default = MerkleTree::new();
+        for leaf in &leaves {
+            default.add_leaf(*leaf);
         }
-        let root = tree.root().unwrap();
-
-        // Verify proof for each leaf
-        for i in 0..8 {
-            let leaf = [i as u8; 32];
-            let proof = tree.proof(i).unwrap();
-            assert!(
-                verify_merkle_proof(&leaf, &proof, &root, Some(i)),
-                "Failed to verify proof for leaf {}: proof len={}, root={:?}",
-                i,
-                proof.len(),
-                root
-            );
+        assert_eq!(default.root().unwrap(), sha_root);
+        assert_eq!(build(HashAlgo::Sha256).root().unwrap(), sha_root);
+        assert_eq!(build(HashAlgo::Keccak256).root().unwrap(), keccak_root);
+
+        for (i, leaf) in leaves.iter().enumerate() {
+            let proof = keccak.prove(i).unwrap();
+            assert!(verify_merkle_proof_with(
+                HashAlgo::Keccak256,
+                leaf,
+                &proof,
+                &keccak_root
+            ));
+            assert!(!verify_merkle_proof(leaf, &proof, &keccak_root));
+
+            let proof = sha.prove(i).unwrap();
+            assert!(verify_merkle_proof(leaf, &proof, &sha_root));
         }
     }
 }
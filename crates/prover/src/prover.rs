@@ -1,11 +1,18 @@
 use crate::error::ProverError;
-use crate::merkle::{hash_withdrawal, verify_merkle_proof, MerkleTree};
+use crate::merkle::{
+    hash_withdrawal_with, verify_merkle_proof_with, withdrawal_leaves_with, HashAlgo, MerkleProof,
+    MerkleTree,
+};
 use crate::nullifier::generate_nullifier_from_withdrawal;
 use crate::snark::SnarkProver;
 use crate::stark::StarkProver;
 use zkclear_state::State;
 use zkclear_types::{Address, Block, BlockProof, Withdraw, WithdrawalProof};
 
+/// Marks the proof of an empty block that leaves the state root
+/// unchanged; the root itself follows it
+const NOOP_PROOF_PREFIX: &[u8] = b"ZKCLEAR_NOOP_PROOF_V1";
+
 /// Configuration for the ZK prover
 #[derive(Debug, Clone)]
 pub struct ProverConfig {
@@ -15,6 +22,9 @@ pub struct ProverConfig {
     pub groth16_keys_dir: Option<std::path::PathBuf>,
     /// Force regeneration of Groth16 keys even if they exist
     pub force_regenerate_keys: bool,
+    /// Hash of the state, withdrawals and tx list Merkle trees; must match
+    /// the sequencer's and the destination contract's
+    pub hash_algo: HashAlgo,
 }
 
 impl Default for ProverConfig {
@@ -23,6 +33,7 @@ impl Default for ProverConfig {
             use_placeholders: true,
             groth16_keys_dir: None,
             force_regenerate_keys: false,
+            hash_algo: HashAlgo::Sha256,
         }
     }
 }
@@ -33,6 +44,7 @@ impl Default for ProverConfig {
 pub struct Prover {
     stark_prover: Box<dyn StarkProver>,
     snark_prover: Box<dyn SnarkProver>,
+    hash_algo: HashAlgo,
 }
 
 impl Prover {
@@ -43,7 +55,7 @@ impl Prover {
         } else {
             #[cfg(feature = "stark")]
             {
-                Box::new(crate::stark::MinimalStarkProver::new())
+                Box::new(crate::stark::MinimalStarkProver::new().with_hash_algo(config.hash_algo))
             }
             #[cfg(not(feature = "stark"))]
             {
@@ -76,13 +88,43 @@ impl Prover {
         Ok(Self {
             stark_prover,
             snark_prover,
+            hash_algo: config.hash_algo,
         })
     }
 
+    /// Create a prover from explicit STARK and SNARK backends, e.g. to
+    /// plug in a custom or mock implementation
+    pub fn from_provers(
+        stark_prover: Box<dyn StarkProver>,
+        snark_prover: Box<dyn SnarkProver>,
+    ) -> Self {
+        Self {
+            stark_prover,
+            snark_prover,
+            hash_algo: HashAlgo::Sha256,
+        }
+    }
+
+    /// Hash the Merkle trees with `hash_algo` instead of SHA-256. The STARK
+    /// backend must have been built with the same algorithm.
+    pub fn with_hash_algo(mut self, hash_algo: HashAlgo) -> Self {
+        self.hash_algo = hash_algo;
+        self
+    }
+
+    pub fn hash_algo(&self) -> HashAlgo {
+        self.hash_algo
+    }
+
     /// Generate a block proof (STARK + SNARK)
     ///
     /// This generates a STARK proof for the block state transition,
-    /// then wraps it in a SNARK for compact on-chain verification
+    /// then wraps it in a SNARK for compact on-chain verification.
+    ///
+    /// A block with no transactions that leaves the state root unchanged
+    /// gets a no-op proof instead, without building a trace. The no-op
+    /// proof only attests that nothing changed: `verify_block` accepts it
+    /// only for an empty block whose roots are equal.
     pub async fn prove_block(
         &self,
         block: &Block,
@@ -94,6 +136,15 @@ impl Prover {
         let new_state_root = self.compute_state_root(new_state)?;
         let withdrawals_root = self.compute_withdrawals_root(block)?;
 
+        if block.transactions.is_empty() && prev_state_root == new_state_root {
+            return Ok(BlockProof {
+                prev_state_root,
+                new_state_root,
+                withdrawals_root,
+                zk_proof: Self::noop_proof(&new_state_root),
+            });
+        }
+
         // Serialize block data for proof generation
         let block_data = bincode::serialize(block)
             .map_err(|e| ProverError::Serialization(format!("Failed to serialize block: {}", e)))?;
@@ -141,6 +192,83 @@ impl Prover {
             .await
     }
 
+    /// Verify a stored block's proof
+    ///
+    /// The public inputs are rebuilt from `prev_state_root` and the block's
+    /// own `state_root` and withdrawals, so a light client can check a block
+    /// without re-executing it. Returns `Ok(false)` when the proof does not
+    /// match the block and an error when the block carries no usable proof.
+    pub async fn verify_block(
+        &self,
+        block: &Block,
+        prev_state_root: &[u8; 32],
+    ) -> Result<bool, ProverError> {
+        if block.block_proof.is_empty() {
+            return Err(ProverError::MissingProof(format!(
+                "block {} was built without a proof",
+                block.id
+            )));
+        }
+
+        // Blocks store the SNARK proof bincode-encoded
+        let proof: Vec<u8> = bincode::deserialize(&block.block_proof).map_err(|e| {
+            ProverError::Serialization(format!(
+                "Failed to deserialize proof of block {}: {}",
+                block.id, e
+            ))
+        })?;
+
+        let withdrawals_root = self.compute_withdrawals_root(block)?;
+        if withdrawals_root != block.withdrawals_root {
+            return Ok(false);
+        }
+
+        if proof.starts_with(NOOP_PROOF_PREFIX) {
+            return Ok(block.transactions.is_empty()
+                && *prev_state_root == block.state_root
+                && proof == Self::noop_proof(&block.state_root));
+        }
+
+        let public_inputs =
+            bincode::serialize(&(*prev_state_root, block.state_root, withdrawals_root)).map_err(
+                |e| ProverError::Serialization(format!("Failed to serialize public inputs: {}", e)),
+            )?;
+
+        if !self
+            .snark_prover
+            .verify_snark_proof(&proof, &public_inputs)
+            .await?
+        {
+            return Ok(false);
+        }
+
+        // Also check the wrapped STARK proof when the SNARK format carries it
+        #[cfg(feature = "stark")]
+        if let Some(stark_proof) = self.snark_prover.embedded_stark_proof(&proof) {
+            let stark_inputs = bincode::serialize(&crate::air::BlockTransitionInputs {
+                prev_state_root: *prev_state_root,
+                new_state_root: block.state_root,
+                withdrawals_root,
+                tx_list_root: crate::merkle::compute_tx_list_root_with(
+                    self.hash_algo,
+                    &block.transactions,
+                )?,
+                block_id: block.id,
+                timestamp: block.timestamp,
+            })
+            .map_err(|e| {
+                ProverError::Serialization(format!("Failed to serialize STARK inputs: {}", e))
+            })?;
+
+            return self
+                .stark_prover
+                .verify_stark_proof(&stark_proof, &stark_inputs)
+                .await;
+        }
+
+        Ok(true)
+    }
+
     /// Generate a withdrawal proof
     ///
     /// This generates a Merkle proof for inclusion in withdrawals_root
@@ -150,7 +278,7 @@ impl Prover {
         withdrawal: &Withdraw,
         user: Address,
         withdrawals_root: &[u8; 32],
-        merkle_proof: Vec<[u8; 32]>,
+        merkle_proof: MerkleProof,
         secret: &[u8; 32],
     ) -> Result<WithdrawalProof, ProverError> {
         // Generate nullifier
@@ -163,17 +291,15 @@ impl Prover {
         );
 
         // Verify Merkle proof
-        let leaf = hash_withdrawal(
+        let leaf = hash_withdrawal_with(
+            self.hash_algo,
             user,
             withdrawal.asset_id,
             withdrawal.amount,
             withdrawal.chain_id,
         );
 
-        // Note: For proper verification with trees >2 leaves, we need the withdrawal index.
-        // For now, we pass None which works for simple cases (1-2 leaves).
-        // In production, the withdrawal index should be passed to this function.
-        if !verify_merkle_proof(&leaf, &merkle_proof, withdrawals_root, None) {
+        if !verify_merkle_proof_with(self.hash_algo, &leaf, &merkle_proof, withdrawals_root) {
             return Err(ProverError::InvalidWithdrawalsRoot(
                 "Merkle proof verification failed".to_string(),
             ));
@@ -186,6 +312,7 @@ impl Prover {
 
         Ok(WithdrawalProof {
             merkle_proof: merkle_proof
+                .siblings
                 .iter()
                 .flat_map(|p| p.iter().copied())
                 .collect(),
@@ -194,54 +321,18 @@ impl Prover {
         })
     }
 
+    fn noop_proof(state_root: &[u8; 32]) -> Vec<u8> {
+        [NOOP_PROOF_PREFIX, state_root.as_slice()].concat()
+    }
+
     /// Compute state root from state
     fn compute_state_root(&self, state: &State) -> Result<[u8; 32], ProverError> {
-        Self::compute_state_root_static(state)
+        crate::merkle::compute_state_root_with(self.hash_algo, state)
     }
 
     /// Compute state root from state (static method for use in tests)
-    pub fn compute_state_root_static(state: &State) -> Result<[u8; 32], ProverError> {
-        // Use Merkle tree approach for proper state root computation
-        use crate::merkle::{hash_state_leaf, MerkleTree};
-
-        let mut tree = MerkleTree::new();
-
-        // Add all accounts as leaves
-        let mut account_ids: Vec<_> = state.accounts.keys().collect();
-        account_ids.sort();
-
-        for account_id in account_ids {
-            let account = state.accounts.get(account_id).ok_or_else(|| {
-                ProverError::StarkProof(format!("Account {} not found", account_id))
-            })?;
-
-            let account_bytes = bincode::serialize(account).map_err(|e| {
-                ProverError::Serialization(format!("Failed to serialize account: {}", e))
-            })?;
-
-            let leaf = hash_state_leaf(&account_bytes);
-            tree.add_leaf(leaf);
-        }
-
-        // Add all deals as leaves
-        let mut deal_ids: Vec<_> = state.deals.keys().collect();
-        deal_ids.sort();
-
-        for deal_id in deal_ids {
-            let deal = state
-                .deals
-                .get(deal_id)
-                .ok_or_else(|| ProverError::StarkProof(format!("Deal {} not found", deal_id)))?;
-
-            let deal_bytes = bincode::serialize(deal).map_err(|e| {
-                ProverError::Serialization(format!("Failed to serialize deal: {}", e))
-            })?;
-
-            let leaf = hash_state_leaf(&deal_bytes);
-            tree.add_leaf(leaf);
-        }
-
-        tree.root()
+    pub fn compute_state_root_static(state: &State) -> Result<[u8; 32], ProverError>{
+        crate::merkle::compute_state_root(state)
     }
 
     /// Get reference to STARK prover (for testing/profiling)
@@ -257,45 +348,34 @@ impl Prover {
     /// Compute withdrawals root from block
     /// Made public for testing/profiling
     pub fn compute_withdrawals_root(&self, block: &Block) -> Result<[u8; 32], ProverError> {
-        let mut tree = MerkleTree::new();
-
-        // Extract withdrawals from block transactions
-        for tx in &block.transactions {
-            if let zkclear_types::TxPayload::Withdraw(w) = &tx.payload {
-                let leaf = hash_withdrawal(tx.from, w.asset_id, w.amount, w.chain_id);
-                tree.add_leaf(leaf);
-            }
-        }
-
-        tree.root()
+        crate::merkle::compute_withdrawals_root_with(self.hash_algo, &block.transactions)
     }
 
-    /// Generate Merkle proof for a withdrawal
+    /// Generate Merkle proof for the `withdrawal_index`-th withdrawal of a
+    /// block, counting withdrawals in tx order. The proof is against the
+    /// canonical leaf order of `withdrawal_leaves`.
     pub fn generate_withdrawal_merkle_proof(
         &self,
         block: &Block,
         withdrawal_index: usize,
-    ) -> Result<(Vec<[u8; 32]>, [u8; 32]), ProverError> {
-        let mut tree = MerkleTree::new();
-        let mut target_index = None;
-
-        // Build tree and find withdrawal index
-        let mut current_index = 0;
-        for tx in &block.transactions {
-            if let zkclear_types::TxPayload::Withdraw(w) = &tx.payload {
-                let leaf = hash_withdrawal(tx.from, w.asset_id, w.amount, w.chain_id);
-                tree.add_leaf(leaf);
-
-                if current_index == withdrawal_index {
-                    target_index = Some(tree.leaves.len() - 1);
-                }
-                current_index += 1;
-            }
+    ) -> Result<(MerkleProof, [u8; 32]), ProverError> {
+        let position = block
+            .transactions
+            .iter()
+            .enumerate()
+            .filter(|(_, tx)| matches!(tx.payload, zkclear_types::TxPayload::Withdraw(_)))
+            .nth(withdrawal_index)
+            .map(|(position, _)| position);
+
+        let leaves = withdrawal_leaves_with(self.hash_algo, &block.transactions);
+        let mut tree = MerkleTree::new_with(self.hash_algo);
+        for (_, leaf) in &leaves {
+            tree.add_leaf(*leaf);
         }
 
         let root = tree.root()?;
-        let proof = if let Some(idx) = target_index {
-            tree.proof(idx)?
+        let proof = if let Some(idx) = leaves.iter().position(|(p, _)| Some(*p) == position) {
+            tree.prove(idx)?
         } else {
             return Err(ProverError::InvalidWithdrawalsRoot(format!(
                 "Withdrawal index {} not found",
@@ -331,4 +411,110 @@ mod tests {
         let proof = prover.prove_block(&block, &prev_state, &new_state).await;
         assert!(proof.is_ok());
     }
+
+    /// Block 1 crediting one account, proven by the non-placeholder prover
+    async fn proven_block(prover: &Prover) -> (Block, [u8; 32]) {
+        let prev_state = State::new();
+        let mut new_state = State::new();
+        new_state.get_or_create_account_by_owner([1u8; 20]).credit(
+            0,
+            zkclear_types::chain_ids::ETHEREUM,
+            100,
+        );
+
+        let mut block = Block {
+            id: 1,
+            transactions: vec![],
+            timestamp: 1000,
+            state_root: Prover::compute_state_root_static(&new_state).unwrap(),
+            withdrawals_root: [0u8; 32],
+            block_proof: vec![],
+        };
+        block.withdrawals_root = prover.compute_withdrawals_root(&block).unwrap();
+
+        let proof = prover
+            .prove_block(&block, &prev_state, &new_state)
+            .await
+            .unwrap();
+        block.block_proof = bincode::serialize(&proof.zk_proof).unwrap();
+        (block, proof.prev_state_root)
+    }
+
+    fn verifying_prover() -> Prover {
+        Prover::new(ProverConfig {
+            use_placeholders: false,
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_verify_block_accepts_valid_proof() {
+        let prover = verifying_prover();
+        let (block, prev_state_root) = proven_block(&prover).await;
+
+        assert!(prover.verify_block(&block, &prev_state_root).await.unwrap());
+        assert!(!prover.verify_block(&block, &[9u8; 32]).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_verify_block_rejects_tampered_state_root() {
+        let prover = verifying_prover();
+        let (mut block, prev_state_root) = proven_block(&prover).await;
+        block.state_root[0] ^= 1;
+
+        assert!(!prover.verify_block(&block, &prev_state_root).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_empty_block_gets_noop_proof() {
+        let prover = verifying_prover();
+        let state = State::new();
+        let state_root = Prover::compute_state_root_static(&state).unwrap();
+        let mut block = Block {
+            id: 1,
+            transactions: vec![],
+            timestamp: 1000,
+            state_root,
+            withdrawals_root: [0u8; 32],
+            block_proof: vec![],
+        };
+        block.withdrawals_root = prover.compute_withdrawals_root(&block).unwrap();
+
+        let proof = prover.prove_block(&block, &state, &state).await.unwrap();
+        assert_eq!(proof.zk_proof, Prover::noop_proof(&state_root));
+        block.block_proof = bincode::serialize(&proof.zk_proof).unwrap();
+
+        assert!(prover.verify_block(&block, &state_root).await.unwrap());
+        // Only valid while the root is unchanged
+        assert!(!prover.verify_block(&block, &[9u8; 32]).await.unwrap());
+        block.state_root[0] ^= 1;
+        assert!(!prover.verify_block(&block, &state_root).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_noop_proof_rejected_for_changed_state() {
+        let prover = verifying_prover();
+        let (mut block, prev_state_root) = proven_block(&prover).await;
+        assert!(!block.block_proof.is_empty());
+        assert_ne!(
+            bincode::deserialize::<Vec<u8>>(&block.block_proof).unwrap(),
+            Prover::noop_proof(&block.state_root)
+        );
+
+        block.block_proof = bincode::serialize(&Prover::noop_proof(&block.state_root)).unwrap();
+        assert!(!prover.verify_block(&block, &prev_state_root).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_verify_block_without_proof_is_an_error() {
+        let prover = verifying_prover();
+        let (mut block, prev_state_root) = proven_block(&prover).await;
+        block.block_proof.clear();
+
+        assert!(matches!(
+            prover.verify_block(&block, &prev_state_root).await,
+            Err(ProverError::MissingProof(_))
+        ));
+    }
 }
@@ -21,6 +21,12 @@ pub trait SnarkProver: Send + Sync {
         proof: &[u8],
         public_inputs: &[u8],
     ) -> Result<bool, ProverError>;
+
+    /// Extract the STARK proof wrapped by a SNARK proof, for formats that
+    /// carry it alongside the SNARK
+    fn embedded_stark_proof(&self, _proof: &[u8]) -> Option<Vec<u8>> {
+        None
+    }
 }
 
 /// Placeholder SNARK prover implementation
@@ -102,6 +108,22 @@ impl SimplifiedSnarkProver {
     }
 }
 
+/// Proof format produced by `SimplifiedSnarkProver`
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SimplifiedSnarkProof {
+    stark_proof: Vec<u8>,
+    public_inputs: Vec<u8>,
+    version: u8,
+    metadata: SimplifiedSnarkMetadata,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SimplifiedSnarkMetadata {
+    stark_proof_size: u32,
+    public_inputs_size: u32,
+    timestamp: u64,
+}
+
 #[cfg(feature = "arkworks")]
 #[async_trait::async_trait]
 impl SnarkProver for ArkworksSnarkProver {
@@ -312,26 +334,11 @@ impl SnarkProver for SimplifiedSnarkProver {
         public_inputs: &[u8],
     ) -> Result<Vec<u8>, ProverError> {
         // Simplified wrapper for MVP (when arkworks feature is not enabled)
-        #[derive(serde::Serialize, serde::Deserialize)]
-        struct SnarkProofWrapper {
-            stark_proof: Vec<u8>,
-            public_inputs: Vec<u8>,
-            version: u8,
-            metadata: SnarkMetadata,
-        }
-
-        #[derive(serde::Serialize, serde::Deserialize)]
-        struct SnarkMetadata {
-            stark_proof_size: u32,
-            public_inputs_size: u32,
-            timestamp: u64,
-        }
-
-        let wrapper = SnarkProofWrapper {
+        let wrapper = SimplifiedSnarkProof {
             stark_proof: stark_proof.to_vec(),
             public_inputs: public_inputs.to_vec(),
             version: 1,
-            metadata: SnarkMetadata {
+            metadata: SimplifiedSnarkMetadata {
                 stark_proof_size: stark_proof.len() as u32,
                 public_inputs_size: public_inputs.len() as u32,
                 timestamp: std::time::SystemTime::now()
@@ -351,22 +358,7 @@ impl SnarkProver for SimplifiedSnarkProver {
         proof: &[u8],
         public_inputs: &[u8],
     ) -> Result<bool, ProverError> {
-        #[derive(serde::Serialize, serde::Deserialize)]
-        struct SnarkProofWrapper {
-            stark_proof: Vec<u8>,
-            public_inputs: Vec<u8>,
-            version: u8,
-            metadata: SnarkMetadata,
-        }
-
-        #[derive(serde::Serialize, serde::Deserialize)]
-        struct SnarkMetadata {
-            stark_proof_size: u32,
-            public_inputs_size: u32,
-            timestamp: u64,
-        }
-
-        let wrapper: SnarkProofWrapper = bincode::deserialize(proof).map_err(|e| {
+        let wrapper: SimplifiedSnarkProof = bincode::deserialize(proof).map_err(|e| {
             ProverError::Serialization(format!("Failed to deserialize SNARK wrapper: {}", e))
         })?;
 
@@ -387,4 +379,10 @@ impl SnarkProver for SimplifiedSnarkProver {
 
         Ok(true)
     }
+
+    fn embedded_stark_proof(&self, proof: &[u8]) -> Option<Vec<u8>> {
+        bincode::deserialize::<SimplifiedSnarkProof>(proof)
+            .ok()
+            .map(|wrapper| wrapper.stark_proof)
+    }
 }
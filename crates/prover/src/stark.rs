@@ -63,6 +63,7 @@ impl StarkProver for PlaceholderStarkProver {
 pub struct MinimalStarkProver {
     prover: crate::air::MinimalStarkProver,
     verifier: crate::air::MinimalStarkVerifier,
+    hash_algo: crate::merkle::HashAlgo,
 }
 
 #[cfg(feature = "stark")]
@@ -71,6 +72,16 @@ impl MinimalStarkProver {
         Self {
             prover: crate::air::MinimalStarkProver::new(),
             verifier: crate::air::MinimalStarkVerifier::new(),
+            hash_algo: crate::merkle::HashAlgo::Sha256,
+        }
+    }
+
+    /// Commit to the tx list with `hash_algo` instead of SHA-256
+    pub fn with_hash_algo(self, hash_algo: crate::merkle::HashAlgo) -> Self {
+        Self {
+            prover: crate::air::MinimalStarkProver::new().with_hash_algo(hash_algo),
+            hash_algo,
+            ..self
         }
     }
 }
@@ -98,6 +109,10 @@ impl StarkProver for MinimalStarkProver {
             prev_state_root: *prev_state_root,
             new_state_root: *new_state_root,
             withdrawals_root: *withdrawals_root,
+            tx_list_root: crate::merkle::compute_tx_list_root_with(
+                self.hash_algo,
+                &block.transactions,
+            )?,
             block_id: block.id,
             timestamp: block.timestamp,
         };
@@ -24,7 +24,7 @@ fn create_test_block(id: u64, num_txs: usize) -> Block {
 /// Helper to create a test block with address offset
 #[cfg(any(feature = "stark", feature = "arkworks"))]
 fn create_test_block_with_offset(id: u64, num_txs: usize, address_offset: usize) -> Block {
-    use zkclear_types::{Deposit, TxKind};
+    use zkclear_types::{Deposit, SignatureScheme, TxKind};
 
     let mut transactions = Vec::new();
 
@@ -37,13 +37,16 @@ fn create_test_block_with_offset(id: u64, num_txs: usize, address_offset: usize)
             nonce: 0, // Each address is new, so nonce starts at 0
             kind: TxKind::Deposit,
             payload: TxPayload::Deposit(Deposit {
-                tx_hash: [i as u8; 32],
+                tx_hash: [addr_byte; 32],
                 account: Address::from([addr_byte; 20]),
                 asset_id: 1,
                 amount: 1000 + i as u128,
                 chain_id: 1,
             }),
-            signature: [0u8; 65],
+            fee: 0,
+            domain: 0,
+            scheme: SignatureScheme::Secp256k1Recoverable,
+            signature: vec![0u8; 65],
         });
     }
 
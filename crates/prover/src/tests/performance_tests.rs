@@ -16,7 +16,7 @@ use zkclear_types::{Address, Tx, TxPayload};
 /// Helper to create a test block
 #[cfg(any(feature = "stark", feature = "arkworks"))]
 fn create_test_block(id: u64, num_txs: usize) -> Block {
-    use zkclear_types::{Deposit, TxKind};
+    use zkclear_types::{Deposit, SignatureScheme, TxKind};
 
     let mut transactions = Vec::new();
 
@@ -33,7 +33,10 @@ fn create_test_block(id: u64, num_txs: usize) -> Block {
                 amount: 1000 + i as u128,
                 chain_id: 1,
             }),
-            signature: [0u8; 65],
+            fee: 0,
+            domain: 0,
+            scheme: SignatureScheme::Secp256k1Recoverable,
+            signature: vec![0u8; 65],
         });
     }
 
@@ -18,7 +18,7 @@ use zkclear_types::{Address, Tx, TxPayload};
 /// Helper function to create a test block with transactions
 #[cfg(feature = "stark")]
 fn create_test_block(id: u64, num_txs: usize) -> Block {
-    use zkclear_types::{Deposit, TxKind};
+    use zkclear_types::{Deposit, SignatureScheme, TxKind};
 
     let mut transactions = Vec::new();
 
@@ -35,7 +35,10 @@ fn create_test_block(id: u64, num_txs: usize) -> Block {
                 amount: 1000 + i as u128,
                 chain_id: 1,
             }),
-            signature: [0u8; 65],
+            fee: 0,
+            domain: 0,
+            scheme: SignatureScheme::Secp256k1Recoverable,
+            signature: vec![0u8; 65],
         });
     }
 
@@ -117,6 +120,7 @@ async fn test_stark_proof_generation_empty_block() {
         prev_state_root,
         new_state_root,
         withdrawals_root,
+        tx_list_root: crate::merkle::compute_tx_list_root(&block.transactions).unwrap(),
         block_id: block.id,
         timestamp: block.timestamp,
     };
@@ -175,6 +179,7 @@ async fn test_stark_proof_generation_single_transaction() {
         prev_state_root,
         new_state_root,
         withdrawals_root,
+        tx_list_root: crate::merkle::compute_tx_list_root(&block.transactions).unwrap(),
         block_id: block.id,
         timestamp: block.timestamp,
     };
@@ -234,6 +239,7 @@ async fn test_stark_proof_generation_multiple_transactions() {
             prev_state_root,
             new_state_root,
             withdrawals_root,
+            tx_list_root: crate::merkle::compute_tx_list_root(&block.transactions).unwrap(),
             block_id: block.id,
             timestamp: block.timestamp,
         };
@@ -291,6 +297,7 @@ async fn test_stark_proof_verification_fails_with_wrong_public_inputs() {
         prev_state_root: [1u8; 32], // Wrong prev_state_root
         new_state_root,
         withdrawals_root,
+        tx_list_root: crate::merkle::compute_tx_list_root(&block.transactions).unwrap(),
         block_id: block.id,
         timestamp: block.timestamp,
     };
@@ -316,3 +323,83 @@ async fn test_stark_proof_verification_fails_with_wrong_public_inputs() {
         }
     }
 }
+
+#[cfg(feature = "stark")]
+#[tokio::test]
+async fn test_tx_list_root_binds_transaction_list() {
+    use zkclear_types::{Deposit, SignatureScheme, TxKind};
+
+    let owner = Address::from([7u8; 20]);
+    let deposit = |nonce: u64, tx_hash: u8, amount: u128| Tx {
+        id: nonce,
+        from: owner,
+        nonce,
+        kind: TxKind::Deposit,
+        payload: TxPayload::Deposit(Deposit {
+            tx_hash: [tx_hash; 32],
+            account: owner,
+            asset_id: 1,
+            amount,
+            chain_id: 1,
+        }),
+        fee: 0,
+        domain: 0,
+        scheme: SignatureScheme::Secp256k1Recoverable,
+        signature: vec![0u8; 65],
+    };
+
+    // The same two deposits in either order credit the same total
+    let mut first = create_test_block(1, 0);
+    first.transactions = vec![deposit(0, 1, 100), deposit(1, 2, 250)];
+    let mut second = create_test_block(1, 0);
+    second.transactions = vec![deposit(0, 2, 250), deposit(1, 1, 100)];
+
+    let prev_state = create_test_state();
+    let prev_state_root = crate::prover::Prover::compute_state_root_static(&prev_state).unwrap();
+    let new_state_root = crate::prover::Prover::compute_state_root_static(
+        &apply_transactions_to_state(&mut prev_state.clone(), &first).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(
+        crate::prover::Prover::compute_state_root_static(
+            &apply_transactions_to_state(&mut prev_state.clone(), &second).unwrap(),
+        )
+        .unwrap(),
+        new_state_root
+    );
+
+    let first_root = crate::merkle::compute_tx_list_root(&first.transactions).unwrap();
+    let second_root = crate::merkle::compute_tx_list_root(&second.transactions).unwrap();
+    assert_ne!(first_root, second_root);
+
+    // A proof of one list doesn't verify against the other's commitment
+    let prover = MinimalStarkProver::new();
+    let proof = prover
+        .prove_block_transition(
+            &prev_state_root,
+            &new_state_root,
+            &[0u8; 32],
+            &bincode::serialize(&first).unwrap(),
+        )
+        .await
+        .unwrap();
+    let inputs = |tx_list_root| {
+        bincode::serialize(&BlockTransitionInputs {
+            prev_state_root,
+            new_state_root,
+            withdrawals_root: [0u8; 32],
+            tx_list_root,
+            block_id: first.id,
+            timestamp: first.timestamp,
+        })
+        .unwrap()
+    };
+    assert!(prover
+        .verify_stark_proof(&proof, &inputs(first_root))
+        .await
+        .unwrap());
+    assert!(!prover
+        .verify_stark_proof(&proof, &inputs(second_root))
+        .await
+        .unwrap());
+}
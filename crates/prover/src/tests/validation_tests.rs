@@ -26,7 +26,7 @@ use zkclear_types::{Address, Tx, TxPayload};
 /// Helper to create a test block
 #[cfg(any(feature = "stark", feature = "arkworks"))]
 fn create_test_block(id: u64, num_txs: usize) -> Block {
-    use zkclear_types::{Deposit, TxKind};
+    use zkclear_types::{Deposit, SignatureScheme, TxKind};
 
     let mut transactions = Vec::new();
 
@@ -43,7 +43,10 @@ fn create_test_block(id: u64, num_txs: usize) -> Block {
                 amount: 1000 + i as u128,
                 chain_id: 1,
             }),
-            signature: [0u8; 65],
+            fee: 0,
+            domain: 0,
+            scheme: SignatureScheme::Secp256k1Recoverable,
+            signature: vec![0u8; 65],
         });
     }
 
@@ -140,6 +143,7 @@ async fn test_validate_stark_proof_structure() {
         prev_state_root,
         new_state_root,
         withdrawals_root,
+        tx_list_root: crate::merkle::compute_tx_list_root(&block.transactions).unwrap(),
         block_id: block.id,
         timestamp: block.timestamp,
     };
@@ -524,6 +528,7 @@ async fn test_validate_stark_commitments() {
         prev_state_root,
         new_state_root,
         withdrawals_root,
+        tx_list_root: crate::merkle::compute_tx_list_root(&block.transactions).unwrap(),
         block_id: block.id,
         timestamp: block.timestamp,
     };
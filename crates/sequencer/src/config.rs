@@ -1,6 +1,25 @@
+use std::time::Duration;
+
 use crate::BlockId;
 
 pub const DEFAULT_MAX_QUEUE_SIZE: usize = 10_000;
+pub const DEFAULT_NETWORK_ID: u64 = 0;
+pub const DEFAULT_THROTTLE_HIGH_WATER_MARK: f32 = 0.8;
 pub const DEFAULT_MAX_TXS_PER_BLOCK: usize = 100;
+pub const DEFAULT_MAX_NONCE_GAP: u64 = 16;
+pub const DEFAULT_NONCE_BUFFER_TTL: Duration = Duration::from_secs(60);
+pub const DEFAULT_REQUEST_ID_CACHE_SIZE: usize = 10_000;
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 100;
 pub const DEFAULT_SNAPSHOT_INTERVAL: BlockId = 100;
+pub const DEFAULT_SNAPSHOT_RETENTION: usize = 3;
+pub const DEFAULT_CHECKPOINT_INTERVAL: BlockId = 100;
+pub const DEFAULT_PROOF_TIMEOUT: Duration = Duration::from_secs(300);
+pub const DEFAULT_EVENT_CHANNEL_CAPACITY: usize = 1024;
 pub const DEFAULT_BLOCK_INTERVAL_SECONDS: u64 = 5;
+pub const DEFAULT_READ_SNAPSHOT_REFRESH_SECONDS: u64 = 5;
+/// Blocks whose changes are kept for the next read snapshot refresh; past
+/// this, the refresh copies the live state instead
+pub const MAX_READ_SNAPSHOT_DELTAS: usize = 256;
+pub const DEFAULT_REPLICA_SYNC_SECONDS: u64 = 1;
+pub const DEFAULT_SHUTDOWN_SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_BLOCK_BUILDER_LEASE_TTL: Duration = Duration::from_secs(30);
@@ -0,0 +1,180 @@
+//! Versioned transaction envelope
+//!
+//! Transactions submitted in binary form are wrapped in a small header so the
+//! sequencer can reject unknown versions or tx kinds before attempting to
+//! deserialize the body:
+//!
+//! ```text
+//! [version: u8][kind tag: u8][bincode(Tx)]
+//! ```
+//!
+//! `decode_tx` also takes a bare `bincode(Tx)`, as sent by clients that
+//! predate the envelope.
+
+use zkclear_types::{Tx, TxKind};
+
+/// Current envelope format version. Version 2 added `Tx::fee` to the body,
+/// version 3 `Tx::domain`, version 4 `AcceptDeal::max_price_quote_per_base`,
+/// version 5 `Tx::scheme` and variable-length signatures.
+pub const TX_ENVELOPE_VERSION: u8 = 5;
+
+/// Size of the envelope header (version + kind tag)
+pub const TX_ENVELOPE_HEADER_SIZE: usize = 2;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum EnvelopeError {
+    /// Envelope is shorter than the header
+    Truncated,
+    /// Envelope version is not supported by this node
+    UnsupportedVersion(u8),
+    /// Kind tag does not correspond to any known `TxKind`
+    UnsupportedTxKind(u8),
+    /// Kind tag in the header does not match the decoded transaction
+    KindMismatch,
+    /// Transaction body could not be deserialized
+    Malformed,
+}
+
+/// Wrap a transaction in a versioned envelope
+pub fn encode_tx_envelope(tx: &Tx) -> Result<Vec<u8>, EnvelopeError> {
+    let body = bincode::serialize(tx).map_err(|_| EnvelopeError::Malformed)?;
+
+    let mut bytes = Vec::with_capacity(TX_ENVELOPE_HEADER_SIZE + body.len());
+    bytes.push(TX_ENVELOPE_VERSION);
+    bytes.push(tx.kind.as_tag());
+    bytes.extend_from_slice(&body);
+    Ok(bytes)
+}
+
+/// Decode a versioned envelope into a transaction
+pub fn decode_tx_envelope(bytes: &[u8]) -> Result<Tx, EnvelopeError> {
+    if bytes.len() < TX_ENVELOPE_HEADER_SIZE {
+        return Err(EnvelopeError::Truncated);
+    }
+
+    let version = bytes[0];
+    if version != TX_ENVELOPE_VERSION {
+        return Err(EnvelopeError::UnsupportedVersion(version));
+    }
+
+    let kind_tag = bytes[1];
+    let kind = TxKind::from_tag(kind_tag).ok_or(EnvelopeError::UnsupportedTxKind(kind_tag))?;
+
+    let tx: Tx = bincode::deserialize(&bytes[TX_ENVELOPE_HEADER_SIZE..])
+        .map_err(|_| EnvelopeError::Malformed)?;
+
+    if tx.kind.as_tag() != kind.as_tag() {
+        return Err(EnvelopeError::KindMismatch);
+    }
+
+    Ok(tx)
+}
+
+/// Decode a versioned envelope, or failing that a bare bincode `Tx`. The
+/// envelope's error is returned when neither decodes, so an unknown kind
+/// is still reported as such.
+pub fn decode_tx(bytes: &[u8]) -> Result<Tx, EnvelopeError> {
+    decode_tx_envelope(bytes).or_else(|error| bincode::deserialize(bytes).map_err(|_| error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zkclear_types::{Deposit, SignatureScheme, TxPayload};
+
+    fn deposit_tx() -> Tx {
+        Tx {
+            id: 0,
+            from: [1u8; 20],
+            nonce: 0,
+            kind: TxKind::Deposit,
+            payload: TxPayload::Deposit(Deposit {
+                tx_hash: [0u8; 32],
+                account: [1u8; 20],
+                asset_id: 0,
+                amount: 100,
+                chain_id: zkclear_types::chain_ids::ETHEREUM,
+            }),
+            fee: 0,
+            domain: 0,
+            scheme: SignatureScheme::Secp256k1Recoverable,
+            signature: vec![0u8; 65],
+        }
+    }
+
+    #[test]
+    fn test_envelope_roundtrip() {
+        let tx = deposit_tx();
+        let bytes = encode_tx_envelope(&tx).unwrap();
+        assert_eq!(bytes[0], TX_ENVELOPE_VERSION);
+        assert_eq!(bytes[1], TxKind::Deposit.as_tag());
+
+        let decoded = decode_tx_envelope(&bytes).unwrap();
+        assert_eq!(decoded.from, tx.from);
+        assert!(matches!(decoded.kind, TxKind::Deposit));
+    }
+
+    #[test]
+    fn test_decode_tx_accepts_envelope_and_bare_tx() {
+        let tx = deposit_tx();
+        let bare = bincode::serialize(&tx).unwrap();
+        assert_eq!(decode_tx(&bare).unwrap().from, tx.from);
+
+        let envelope = encode_tx_envelope(&tx).unwrap();
+        assert_eq!(decode_tx(&envelope).unwrap().from, tx.from);
+
+        assert_eq!(
+            decode_tx(&[TX_ENVELOPE_VERSION, 42, 0xFF, 0xFF, 0xFF]).unwrap_err(),
+            EnvelopeError::UnsupportedTxKind(42)
+        );
+    }
+
+    #[test]
+    fn test_envelope_unknown_kind() {
+        let mut bytes = encode_tx_envelope(&deposit_tx()).unwrap();
+        bytes[1] = 0xEE;
+
+        assert_eq!(
+            decode_tx_envelope(&bytes).unwrap_err(),
+            EnvelopeError::UnsupportedTxKind(0xEE)
+        );
+    }
+
+    #[test]
+    fn test_envelope_unknown_kind_with_unparseable_body() {
+        // A future kind would carry a payload this node cannot decode;
+        // the kind tag must be rejected before the body is touched.
+        let bytes = vec![TX_ENVELOPE_VERSION, 42, 0xFF, 0xFF, 0xFF];
+
+        assert_eq!(
+            decode_tx_envelope(&bytes).unwrap_err(),
+            EnvelopeError::UnsupportedTxKind(42)
+        );
+    }
+
+    #[test]
+    fn test_envelope_unsupported_version() {
+        let mut bytes = encode_tx_envelope(&deposit_tx()).unwrap();
+        bytes[0] = TX_ENVELOPE_VERSION + 1;
+
+        assert_eq!(
+            decode_tx_envelope(&bytes).unwrap_err(),
+            EnvelopeError::UnsupportedVersion(TX_ENVELOPE_VERSION + 1)
+        );
+    }
+
+    #[test]
+    fn test_envelope_kind_mismatch_and_truncated() {
+        let mut bytes = encode_tx_envelope(&deposit_tx()).unwrap();
+        bytes[1] = TxKind::Withdraw.as_tag();
+        assert_eq!(
+            decode_tx_envelope(&bytes).unwrap_err(),
+            EnvelopeError::KindMismatch
+        );
+
+        assert_eq!(
+            decode_tx_envelope(&[TX_ENVELOPE_VERSION]).unwrap_err(),
+            EnvelopeError::Truncated
+        );
+    }
+}
@@ -0,0 +1,45 @@
+//! Broadcast feed of executed blocks and state changes for live subscribers
+//!
+//! Unlike observers, subscribers run on their own tasks: `execute_block`
+//! only pushes into a bounded `tokio::sync::broadcast` channel and never
+//! waits on them. A subscriber that falls more than the channel capacity
+//! behind skips the oldest events and sees `RecvError::Lagged`.
+
+use zkclear_types::{Account, Block, BlockId, Deal};
+
+use crate::observer::StateDiff;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SequencerEvent {
+    BlockExecuted {
+        block_id: BlockId,
+        state_root: [u8; 32],
+        timestamp: u64,
+        transaction_count: usize,
+    },
+    /// A deal created, filled or closed by the block
+    DealUpdated(Deal),
+    /// An account whose balances or nonce changed in the block
+    AccountUpdated(Account),
+}
+
+impl SequencerEvent {
+    /// Events for `block` in publication order: the block itself, then its
+    /// changed deals and accounts
+    pub fn for_block(block: &Block, diff: StateDiff) -> Vec<Self> {
+        let mut events = Vec::with_capacity(1 + diff.deals.len() + diff.accounts.len());
+        events.push(SequencerEvent::BlockExecuted {
+            block_id: block.id,
+            state_root: block.state_root,
+            timestamp: block.timestamp,
+            transaction_count: block.transactions.len(),
+        });
+        events.extend(diff.deals.into_iter().map(SequencerEvent::DealUpdated));
+        events.extend(
+            diff.accounts
+                .into_iter()
+                .map(SequencerEvent::AccountUpdated),
+        );
+        events
+    }
+}
@@ -0,0 +1,169 @@
+//! Fee estimates for clients choosing a tx's priority fee
+//!
+//! The STF charges every tx the deployment's flat `FeePolicy::amount` plus
+//! the tx's own `fee`. The estimate recommends that priority fee from a
+//! base-fee curve: a per-byte fee that grows linearly with queue pressure
+//! up to `max_multiplier` times the base at a full queue. Once low-fee txs
+//! are being throttled it never recommends less than the throttle minimum.
+
+use zkclear_stf::FeePolicy;
+use zkclear_types::{AssetId, ChainId, TxKind};
+
+/// Pressure is applied in thousandths so the curve stays in integers
+const PRESSURE_SCALE: u128 = 1_000;
+
+/// Base-fee curve the estimate is read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeeCurve {
+    /// Fee per encoded byte at an empty queue
+    pub base_fee_per_byte: u128,
+    /// Smallest priority fee recommended, whatever the size
+    pub min_fee: u128,
+    /// Factor the per-byte fee reaches at a full queue; 1 keeps it flat
+    pub max_multiplier: u32,
+}
+
+impl Default for FeeCurve {
+    fn default() -> Self {
+        Self {
+            base_fee_per_byte: 1,
+            min_fee: 1,
+            max_multiplier: 10,
+        }
+    }
+}
+
+impl FeeCurve {
+    /// Priority fee recommended for a tx of `size` encoded bytes at
+    /// `pressure` (0.0–1.0). Never decreases as the pressure rises.
+    pub fn priority_fee(&self, size: usize, pressure: f32) -> u128 {
+        let permille = (pressure.clamp(0.0, 1.0) * PRESSURE_SCALE as f32) as u128;
+        let multiplier = PRESSURE_SCALE
+            + u128::from(self.max_multiplier.saturating_sub(1)).saturating_mul(permille);
+        self.base_fee_per_byte
+            .saturating_mul(size as u128)
+            .saturating_mul(multiplier)
+            / PRESSURE_SCALE
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct FeeEstimate {
+    /// Value to put in the tx's `fee` field
+    pub priority_fee: u128,
+    /// Total the tx will be charged: the flat fee plus `priority_fee`
+    pub fee_amount: u128,
+    /// Asset and chain the fee is paid in; `None` when the deployment
+    /// charges no fees
+    pub asset_id: Option<AssetId>,
+    pub chain_id: Option<ChainId>,
+}
+
+/// Typical encoded size of a tx of `kind`, used when the client doesn't
+/// give one
+pub fn default_tx_size(kind: &TxKind) -> usize {
+    match kind {
+        TxKind::Deposit => 200,
+        TxKind::CreateDeal => 250,
+        TxKind::CreateFundedDeal => 350,
+        TxKind::BatchCreateDeal => 1000,
+        TxKind::AcceptDeal => 180,
+        TxKind::CancelDeal
+        | TxKind::DeclineDeal
+        | TxKind::ConfirmSettlement
+        | TxKind::CancelAllDeals => 150,
+        TxKind::Withdraw | TxKind::Transfer => 200,
+    }
+}
+
+/// Estimate the fee of a tx of `kind` and `size` encoded bytes (or the
+/// kind's typical size). `throttle_min_fee` is the smallest fee the queue
+/// currently accepts, 0 when it isn't throttling.
+pub fn estimate_fee(
+    curve: &FeeCurve,
+    fee_policy: Option<&FeePolicy>,
+    kind: &TxKind,
+    size: Option<usize>,
+    pressure: f32,
+    throttle_min_fee: u128,
+) -> FeeEstimate {
+    let size = size.unwrap_or_else(|| default_tx_size(kind));
+    let priority_fee = curve
+        .priority_fee(size, pressure)
+        .max(curve.min_fee)
+        .max(throttle_min_fee);
+
+    FeeEstimate {
+        priority_fee,
+        fee_amount: fee_policy
+            .map_or(0, |fee| fee.amount)
+            .saturating_add(priority_fee),
+        asset_id: fee_policy.map(|fee| fee.asset_id),
+        chain_id: fee_policy.map(|fee| fee.chain_id),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zkclear_types::chain_ids;
+
+    const FEE_POLICY: FeePolicy = FeePolicy {
+        asset_id: 1,
+        chain_id: chain_ids::ETHEREUM,
+        amount: 50,
+    };
+
+    #[test]
+    fn test_estimate_rises_with_queue_pressure() {
+        let curve = FeeCurve::default();
+        let estimates: Vec<u128> = [0.0, 0.1, 0.25, 0.5, 0.75, 0.9, 1.0, 2.0]
+            .into_iter()
+            .map(|pressure| {
+                estimate_fee(
+                    &curve,
+                    Some(&FEE_POLICY),
+                    &TxKind::Withdraw,
+                    Some(320),
+                    pressure,
+                    0,
+                )
+                .fee_amount
+            })
+            .collect();
+
+        assert!(estimates.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(estimates[0] < estimates[3]);
+        assert_eq!(estimates[6], 50 + 320 * 10);
+        // Pressure past full is treated as full
+        assert_eq!(estimates[7], estimates[6]);
+    }
+
+    #[test]
+    fn test_empty_queue_estimate_has_floor() {
+        let curve = FeeCurve {
+            base_fee_per_byte: 0,
+            min_fee: 7,
+            max_multiplier: 10,
+        };
+        let estimate = estimate_fee(&curve, Some(&FEE_POLICY), &TxKind::Deposit, None, 0.0, 0);
+        assert_eq!(
+            estimate,
+            FeeEstimate {
+                priority_fee: 7,
+                fee_amount: 57,
+                asset_id: Some(1),
+                chain_id: Some(chain_ids::ETHEREUM),
+            }
+        );
+
+        let default = estimate_fee(&FeeCurve::default(), None, &TxKind::Deposit, None, 0.0, 0);
+        assert_eq!(default.priority_fee, 200);
+        assert_eq!(default.fee_amount, 200);
+        assert_eq!(default.asset_id, None);
+
+        // While throttling, never below what the queue accepts
+        let throttled = estimate_fee(&curve, None, &TxKind::Deposit, None, 0.9, 1_000);
+        assert_eq!(throttled.priority_fee, 1_000);
+    }
+}
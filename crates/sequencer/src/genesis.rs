@@ -0,0 +1,83 @@
+//! Initial assets, accounts and balances for a new chain, applied before
+//! the first block so test and devnet setups don't have to script deposits.
+
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+use zkclear_state::State;
+use zkclear_types::{Address, Asset, AssetId, ChainId};
+
+use crate::SequencerError;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GenesisConfig {
+    /// Registered before any balance is credited
+    #[serde(default)]
+    pub assets: Vec<Asset>,
+    /// Created in this order, which fixes the account ids and so the
+    /// genesis state root
+    #[serde(default)]
+    pub accounts: Vec<GenesisAccount>,
+    /// Credited with tx fees; the zero address when unset
+    #[serde(default)]
+    pub fee_collector: Option<Address>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GenesisAccount {
+    pub owner: Address,
+    #[serde(default)]
+    pub balances: Vec<GenesisBalance>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GenesisBalance {
+    pub asset_id: AssetId,
+    pub chain_id: ChainId,
+    pub amount: u128,
+}
+
+impl GenesisConfig {
+    /// Build the genesis state. The same config always yields the same
+    /// state and root. An owner listed twice is rejected rather than
+    /// merged, as it is almost certainly a mistake in the config.
+    pub fn build_state(&self) -> Result<State, SequencerError> {
+        let mut state = State::new();
+        if let Some(fee_collector) = self.fee_collector {
+            state.fee_collector = fee_collector;
+        }
+        for asset in &self.assets {
+            state.register_asset(asset.clone());
+        }
+
+        let mut owners = HashSet::new();
+        for genesis_account in &self.accounts {
+            if !owners.insert(genesis_account.owner) {
+                return Err(SequencerError::InvalidGenesis(format!(
+                    "account 0x{} is listed more than once",
+                    hex_address(&genesis_account.owner)
+                )));
+            }
+
+            let account = state.get_or_create_account_by_owner(genesis_account.owner);
+            for balance in &genesis_account.balances {
+                account
+                    .credit(balance.asset_id, balance.chain_id, balance.amount)
+                    .ok_or_else(|| {
+                        SequencerError::InvalidGenesis(format!(
+                            "balance of asset {} on chain {} overflows for 0x{}",
+                            balance.asset_id,
+                            balance.chain_id,
+                            hex_address(&genesis_account.owner)
+                        ))
+                    })?;
+            }
+        }
+
+        Ok(state)
+    }
+}
+
+fn hex_address(address: &Address) -> String {
+    address.iter().map(|b| format!("{:02x}", b)).collect()
+}
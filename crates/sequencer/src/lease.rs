@@ -0,0 +1,67 @@
+//! Block builder leases for running several sequencer instances against
+//! shared storage. Only the instance holding the lease for a block id may
+//! build that block.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use zkclear_storage::{BuilderClaim, Storage};
+use zkclear_types::BlockId;
+
+use crate::clock::{Clock, SystemClock};
+use crate::config::DEFAULT_BLOCK_BUILDER_LEASE_TTL;
+use crate::SequencerError;
+
+pub trait BlockBuilderLease: Send + Sync {
+    /// Try to acquire the right to build `block_id`. Returns false if another
+    /// instance already holds it.
+    fn try_acquire(&self, block_id: BlockId) -> Result<bool, SequencerError>;
+}
+
+/// Lease backed by an atomic claim on the block id in shared storage. A
+/// claim lasts `ttl` from when it was last renewed, after which another
+/// holder may take the block over, so a crashed holder doesn't stall the
+/// chain.
+pub struct StorageBlockBuilderLease {
+    storage: Arc<dyn Storage>,
+    holder_id: String,
+    ttl: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+impl StorageBlockBuilderLease {
+    pub fn new(storage: Arc<dyn Storage>, holder_id: impl Into<String>) -> Self {
+        Self {
+            storage,
+            holder_id: holder_id.into(),
+            ttl: DEFAULT_BLOCK_BUILDER_LEASE_TTL,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+}
+
+impl BlockBuilderLease for StorageBlockBuilderLease {
+    fn try_acquire(&self, block_id: BlockId) -> Result<bool, SequencerError> {
+        let now = self.clock.now();
+        let claim = BuilderClaim {
+            holder: self.holder_id.clone(),
+            claimed_at: now,
+            expires_at: now.saturating_add(self.ttl.as_secs()),
+        };
+        self.storage
+            .claim_block_builder(block_id, &claim)
+            .map_err(|e| {
+                SequencerError::StorageError(format!("Failed to claim block {}: {:?}", block_id, e))
+            })
+    }
+}
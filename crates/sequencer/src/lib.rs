@@ -1,19 +1,93 @@
+pub mod clock;
 pub mod config;
+pub mod envelope;
+pub mod events;
+pub mod fee;
+pub mod genesis;
+pub mod lease;
+pub mod mempool;
+pub mod metrics;
+pub mod observer;
+pub mod request_ids;
 pub mod security;
 mod validation;
 
-use std::collections::VecDeque;
-use std::sync::{Arc, Mutex};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, Notify};
+use tracing::{error, info, info_span, warn};
 use zkclear_prover::{Prover, ProverConfig, ProverError};
-use zkclear_state::State;
-use zkclear_stf::{apply_block, StfError};
+use zkclear_state::{AccountExport, HashAlgo, ImportError, State, StateDelta};
+use zkclear_stf::apply_block_with_config;
+pub use zkclear_stf::{
+    FeePolicy, QuoteRounding, StfConfig, StfError, WithdrawalDestinationPolicy,
+    DEFAULT_MAX_BATCH_DEALS, DEFAULT_MAX_CANCEL_ALL_DEALS, DEFAULT_MAX_EXTERNAL_REF_LEN,
+    DEFAULT_SETTLEMENT_TIMEOUT_SECONDS,
+};
 use zkclear_storage::Storage;
-use zkclear_types::{Block, BlockId, Tx};
+use zkclear_types::{
+    AccountId, Address, Block, BlockId, Checkpoint, Deal, Eip712Domain, Tx, TxKind,
+};
 
-use config::{DEFAULT_MAX_QUEUE_SIZE, DEFAULT_MAX_TXS_PER_BLOCK, DEFAULT_SNAPSHOT_INTERVAL};
-use security::{validate_address, validate_nonce_gap, validate_tx_size};
+use clock::{Clock, SystemClock};
+use config::{
+    DEFAULT_CHECKPOINT_INTERVAL, DEFAULT_EVENT_CHANNEL_CAPACITY, DEFAULT_MAX_NONCE_GAP,
+    DEFAULT_MAX_QUEUE_SIZE, DEFAULT_MAX_TXS_PER_BLOCK, DEFAULT_NETWORK_ID,
+    DEFAULT_NONCE_BUFFER_TTL, DEFAULT_PROOF_TIMEOUT, DEFAULT_REQUEST_ID_CACHE_SIZE,
+    DEFAULT_SNAPSHOT_INTERVAL, DEFAULT_SNAPSHOT_RETENTION, DEFAULT_THROTTLE_HIGH_WATER_MARK,
+    MAX_READ_SNAPSHOT_DELTAS,
+};
+use events::SequencerEvent;
+use fee::{FeeCurve, FeeEstimate};
+pub use genesis::GenesisConfig;
+use lease::BlockBuilderLease;
+pub use mempool::OrderingPolicy;
+use mempool::{EvictionReason, Mempool};
+use metrics::Metrics;
+use observer::{SequencerObserver, StateDiff};
+use request_ids::RequestIdCache;
+use security::validate_address;
+pub use security::TxLimits;
+pub use validation::Erc1271Verifier;
 use validation::{validate_tx, ValidationError};
 
+/// Result of an idempotent submission
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubmitOutcome {
+    Queued,
+    /// The request id was already submitted; carries the tx hash recorded
+    /// for it
+    Duplicate(String),
+}
+
+/// Read-only copy of the committed state used for analytical queries,
+/// so heavy scans don't contend with block production on the live state lock
+#[derive(Debug, Default, Clone)]
+pub struct ReadSnapshot {
+    /// Last executed block reflected in this snapshot; `None` before the
+    /// first block
+    pub block_id: Option<BlockId>,
+    pub state: State,
+}
+
+/// Point-in-time summary of the sequencer, returned by `Sequencer::stats`.
+/// Each figure is read under its own lock, so the values are individually
+/// accurate but may straddle a concurrent block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SequencerStats {
+    /// Id the next block will get
+    pub current_block_id: BlockId,
+    pub queue_length: usize,
+    pub max_queue_size: usize,
+    /// Block of the latest state snapshot; 0 when none was taken
+    pub last_snapshot_block_id: BlockId,
+    pub accounts: usize,
+    pub deals: usize,
+    pub has_prover: bool,
+}
+
 #[derive(Debug)]
 pub enum SequencerError {
     QueueFull,
@@ -25,18 +99,101 @@ pub enum SequencerError {
     ValidationFailed,
     StorageError(String),
     ProverError(String),
+    TxKindDisabled,
+    NotLeader,
+    /// Tx `domain` does not match the sequencer's network id
+    WrongDomain,
+    /// Queue is past its high-water mark and the tx's fee is below the
+    /// throttle threshold
+    Throttled,
+    /// Startup consistency check found the loaded state or proof invalid
+    SelfCheckFailed(String),
+    /// Genesis config can't be applied
+    InvalidGenesis(String),
+    /// Tx is larger than `TxLimits` allow, in encoded size or in the
+    /// length of its `external_ref`
+    TxTooLarge,
+    /// Accounts can only be imported before the first block
+    ChainStarted,
+    /// The account export was rejected by the state
+    ImportFailed(ImportError),
 }
 
 pub struct Sequencer {
     state: Arc<Mutex<State>>,
-    tx_queue: Arc<Mutex<VecDeque<Tx>>>,
+    tx_queue: Arc<Mutex<Mempool>>,
     max_queue_size: usize,
+    /// Longest a tx may wait in the queue before it is evicted; `None`
+    /// keeps it until it is included or its nonce goes stale
+    mempool_ttl: Option<Duration>,
+    /// Queue pressure from which txs paying less than `throttle_min_fee`
+    /// are rejected
+    throttle_high_water_mark: f32,
+    throttle_min_fee: u128,
+    /// Curve fee estimates are read from
+    fee_curve: FeeCurve,
+    /// Network id txs must carry in their `domain` field
+    network_id: u64,
+    /// Domain in which EIP-712 typed-data signatures are accepted alongside
+    /// raw-hash ones; `None` accepts raw-hash signatures only
+    eip712_domain: Option<Eip712Domain>,
+    /// Checks `SignatureScheme::Erc1271` signatures; without one they are
+    /// rejected
+    erc1271_verifier: Option<Arc<dyn Erc1271Verifier>>,
+    /// Validated txs that arrived ahead of their sender's next nonce, held
+    /// until the gap is filled. They count against `max_queue_size`.
+    nonce_buffer: Arc<Mutex<NonceBuffer>>,
+    max_nonce_gap: u64,
+    /// Longest a tx may wait in the nonce buffer for its gap to be filled
+    nonce_buffer_ttl: Duration,
+    /// Client request ids of recent submissions, so retries aren't enqueued
+    /// twice
+    request_ids: Arc<Mutex<RequestIdCache>>,
     current_block_id: Arc<Mutex<BlockId>>,
+    /// Timestamp of the last executed block; 0 before the first
+    last_block_timestamp: AtomicU64,
     max_txs_per_block: usize,
     storage: Option<Arc<dyn Storage>>,
     snapshot_interval: BlockId,
+    /// Number of interval snapshots kept in storage; 0 keeps all of them
+    snapshot_retention: usize,
     last_snapshot_block_id: Arc<Mutex<BlockId>>,
     prover: Option<Arc<Prover>>,
+    /// Longest a block proof may take before the block is built without one
+    proof_timeout: Duration,
+    checkpoint_interval: BlockId,
+    withdrawals_root_accumulator: Arc<Mutex<[u8; 32]>>,
+    last_checkpoint: Arc<Mutex<Option<Checkpoint>>>,
+    skip_nonce_conflicts: bool,
+    max_txs_per_sender: Option<usize>,
+    stf_config: StfConfig,
+    tx_limits: TxLimits,
+    /// Hash of the state and withdrawals Merkle trees
+    hash_algo: HashAlgo,
+    read_snapshot: Arc<RwLock<Arc<ReadSnapshot>>>,
+    /// Changes of the blocks committed since the read snapshot was taken,
+    /// applied to a copy of it at the next refresh. `None` once the live
+    /// state changed outside a block, or more than
+    /// `MAX_READ_SNAPSHOT_DELTAS` blocks went by, so the next refresh
+    /// copies the live state instead.
+    read_snapshot_deltas: Mutex<Option<Vec<(BlockId, StateDelta)>>>,
+    /// Held across a refresh, so two refreshes don't apply the same deltas
+    read_snapshot_refresh: Mutex<()>,
+    observers: Vec<Arc<dyn SequencerObserver>>,
+    events: broadcast::Sender<SequencerEvent>,
+    block_builder_lease: Option<Arc<dyn BlockBuilderLease>>,
+    self_check_verify_proof: bool,
+    metrics: Arc<Metrics>,
+    /// Source of block and checkpoint timestamps
+    clock: Arc<dyn Clock>,
+    /// Held for a whole build-and-execute, so concurrent callers (the block
+    /// timer, the admin flush) build blocks one after the other instead of
+    /// both building on the same block id
+    build_lock: Mutex<()>,
+    /// Queue length at which a block is built without waiting for the timer
+    build_threshold: Option<usize>,
+    /// Pulsed when a submission brings the queue to `build_threshold`
+    build_notify: Notify,
 }
 
 impl Sequencer {
@@ -47,14 +204,50 @@ impl Sequencer {
     pub fn with_config(max_queue_size: usize, max_txs_per_block: usize) -> Self {
         Self {
             state: Arc::new(Mutex::new(State::new())),
-            tx_queue: Arc::new(Mutex::new(VecDeque::new())),
+            tx_queue: Arc::new(Mutex::new(Mempool::default())),
             max_queue_size,
+            mempool_ttl: None,
+            throttle_high_water_mark: DEFAULT_THROTTLE_HIGH_WATER_MARK,
+            throttle_min_fee: 0,
+            fee_curve: FeeCurve::default(),
+            eip712_domain: None,
+            erc1271_verifier: None,
+            network_id: DEFAULT_NETWORK_ID,
+            nonce_buffer: Arc::new(Mutex::new(HashMap::new())),
+            max_nonce_gap: DEFAULT_MAX_NONCE_GAP,
+            nonce_buffer_ttl: DEFAULT_NONCE_BUFFER_TTL,
+            request_ids: Arc::new(Mutex::new(RequestIdCache::new(
+                DEFAULT_REQUEST_ID_CACHE_SIZE,
+            ))),
             current_block_id: Arc::new(Mutex::new(0)),
+            last_block_timestamp: AtomicU64::new(0),
             max_txs_per_block,
             storage: None,
             snapshot_interval: DEFAULT_SNAPSHOT_INTERVAL,
+            snapshot_retention: DEFAULT_SNAPSHOT_RETENTION,
             last_snapshot_block_id: Arc::new(Mutex::new(0)),
             prover: None,
+            proof_timeout: DEFAULT_PROOF_TIMEOUT,
+            checkpoint_interval: DEFAULT_CHECKPOINT_INTERVAL,
+            withdrawals_root_accumulator: Arc::new(Mutex::new([0u8; 32])),
+            last_checkpoint: Arc::new(Mutex::new(None)),
+            skip_nonce_conflicts: true,
+            max_txs_per_sender: None,
+            stf_config: StfConfig::default(),
+            tx_limits: TxLimits::default(),
+            hash_algo: HashAlgo::Sha256,
+            read_snapshot: Arc::new(RwLock::new(Arc::new(ReadSnapshot::default()))),
+            read_snapshot_deltas: Mutex::new(Some(Vec::new())),
+            read_snapshot_refresh: Mutex::new(()),
+            observers: Vec::new(),
+            events: broadcast::channel(DEFAULT_EVENT_CHANNEL_CAPACITY).0,
+            block_builder_lease: None,
+            self_check_verify_proof: false,
+            metrics: Arc::new(Metrics::new()),
+            clock: Arc::new(SystemClock),
+            build_lock: Mutex::new(()),
+            build_threshold: None,
+            build_notify: Notify::new(),
         }
     }
 
@@ -63,12 +256,212 @@ impl Sequencer {
         self
     }
 
+    /// Once the queue is at least `high_water_mark` full (0.0–1.0), reject
+    /// txs whose fee is below `min_fee` so the remaining room goes to
+    /// higher-fee txs. A `min_fee` of 0 disables throttling.
+    pub fn with_throttling(mut self, high_water_mark: f32, min_fee: u128) -> Self {
+        self.throttle_high_water_mark = high_water_mark;
+        self.throttle_min_fee = min_fee;
+        self
+    }
+
+    pub fn with_fee_curve(mut self, fee_curve: FeeCurve) -> Self {
+        self.fee_curve = fee_curve;
+        self
+    }
+
+    /// Only accept txs whose `domain` is `network_id`, so txs signed for
+    /// another deployment cannot be replayed here
+    pub fn with_network_id(mut self, network_id: u64) -> Self {
+        self.network_id = network_id;
+        self
+    }
+
+    /// Also accept signatures over the EIP-712 typed-data hash of a tx in
+    /// `domain`, for wallets that sign with `eth_signTypedData_v4`
+    pub fn with_eip712_domain(mut self, domain: Eip712Domain) -> Self {
+        self.eip712_domain = Some(domain);
+        self
+    }
+
+    /// Accept `SignatureScheme::Erc1271` txs whose contract wallet accepts
+    /// the signature according to `verifier`
+    pub fn with_erc1271_verifier(mut self, verifier: Arc<dyn Erc1271Verifier>) -> Self {
+        self.erc1271_verifier = Some(verifier);
+        self
+    }
+
+    /// Set how many of the most recent interval snapshots are kept in
+    /// storage. The latest snapshot is never pruned; 0 disables pruning.
+    pub fn with_snapshot_retention(mut self, retention: usize) -> Self {
+        self.snapshot_retention = retention;
+        self
+    }
+
+    /// Set how often (in blocks) a checkpoint is exported automatically.
+    /// An interval of 0 disables automatic export.
+    pub fn with_checkpoint_interval(mut self, interval: BlockId) -> Self {
+        self.checkpoint_interval = interval;
+        self
+    }
+
+    /// Set whether block building skips txs whose nonce conflicts with another
+    /// tx from the same sender instead of failing the whole block
+    pub fn with_skip_nonce_conflicts(mut self, skip: bool) -> Self {
+        self.skip_nonce_conflicts = skip;
+        self
+    }
+
+    /// Cap how many txs a single sender may have in one block, so a
+    /// high-volume sender cannot monopolize block space. Slots a capped
+    /// sender cannot use go to other senders' txs further back in the queue.
+    pub fn with_max_txs_per_sender(mut self, max: usize) -> Self {
+        self.max_txs_per_sender = Some(max.max(1));
+        self
+    }
+
+    /// Build a block as soon as `threshold` txs are queued, rather than
+    /// only when the block timer fires. See `build_threshold_reached`.
+    pub fn with_build_threshold(mut self, threshold: usize) -> Self {
+        self.build_threshold = Some(threshold.max(1));
+        self
+    }
+
+    /// Set how far ahead of a sender's next nonce a validated tx may be.
+    /// Such txs are buffered until the missing nonces arrive; 0 rejects any
+    /// out-of-order tx.
+    pub fn with_max_nonce_gap(mut self, gap: u64) -> Self {
+        self.max_nonce_gap = gap;
+        self
+    }
+
+    /// Evict buffered txs whose nonce gap is still open after `ttl`
+    pub fn with_nonce_buffer_ttl(mut self, ttl: Duration) -> Self {
+        self.nonce_buffer_ttl = ttl;
+        self
+    }
+
+    /// Evict txs that have waited in the queue longer than `ttl`
+    pub fn with_mempool_ttl(mut self, ttl: Duration) -> Self {
+        self.mempool_ttl = Some(ttl);
+        self
+    }
+
+    /// Set how many recent client request ids are remembered for
+    /// `submit_tx_with_request_id`
+    pub fn with_request_id_cache_size(self, size: usize) -> Self {
+        *self.request_ids.lock().unwrap() = RequestIdCache::new(size);
+        self
+    }
+
+    /// Set the state transition configuration used for building, executing
+    /// and replaying blocks
+    pub fn with_stf_config(mut self, config: StfConfig) -> Self {
+        self.tx_queue.lock().unwrap().set_fee_policy(config.fee);
+        self.tx_limits.max_external_ref_len = config.max_external_ref_len;
+        self.stf_config = config;
+        self
+    }
+
+    /// Set the size limits submissions are checked against. Its
+    /// `max_external_ref_len` also becomes the STF config's, so a deal
+    /// accepted here is not rejected for its ref when the block executes.
+    pub fn with_tx_limits(mut self, limits: TxLimits) -> Self {
+        self.stf_config.max_external_ref_len = limits.max_external_ref_len;
+        self.tx_limits = limits;
+        self
+    }
+
+    /// Set the order in which queued txs are included in blocks. Fee
+    /// priority ranks txs by the fee they pay under the STF config's fee
+    /// policy; without one it behaves like FIFO.
+    pub fn with_ordering_policy(self, ordering: OrderingPolicy) -> Self {
+        self.tx_queue.lock().unwrap().set_ordering(ordering);
+        self
+    }
+
+    /// Register an observer notified of executed blocks and rejected txs
+    pub fn with_observer(mut self, observer: Arc<dyn SequencerObserver>) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    /// Receive an event for every block executed from now on, followed by
+    /// the deals and accounts it changed
+    pub fn subscribe_events(&self) -> broadcast::Receiver<SequencerEvent> {
+        self.events.subscribe()
+    }
+
+    /// Record into a shared registry instead of the sequencer's own
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    /// Read the time for new blocks and checkpoints from `clock` instead
+    /// of the system clock, e.g. a `MockClock` in tests and simulations
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Block time of the last executed block, 0 before the first. Deals
+    /// past their expiry at this time are expired even before the next
+    /// block sweeps them.
+    pub fn last_block_timestamp(&self) -> u64 {
+        self.last_block_timestamp.load(Ordering::Relaxed)
+    }
+
+    pub fn stf_config(&self) -> &StfConfig {
+        &self.stf_config
+    }
+
+    pub fn tx_limits(&self) -> &TxLimits {
+        &self.tx_limits
+    }
+
+    /// Require a lease before building each block, so that only one of
+    /// several instances sharing storage produces a given block id
+    pub fn with_block_builder_lease(mut self, lease: Arc<dyn BlockBuilderLease>) -> Self {
+        self.block_builder_lease = Some(lease);
+        self
+    }
+
+    /// Also re-verify the latest block's proof in `self_check`.
+    /// Requires a prover and replays all stored blocks to rebuild the
+    /// previous state, so it can be slow on long chains.
+    pub fn with_self_check_proof_verification(mut self, verify: bool) -> Self {
+        self.self_check_verify_proof = verify;
+        self
+    }
+
+    /// Compute state and withdrawals roots with `hash_algo` instead of
+    /// SHA-256, e.g. Keccak-256 to match the destination contract. The
+    /// prover must be configured with the same algorithm.
+    pub fn with_hash_algo(mut self, hash_algo: HashAlgo) -> Self {
+        self.hash_algo = hash_algo;
+        self
+    }
+
+    pub fn hash_algo(&self) -> HashAlgo {
+        self.hash_algo
+    }
+
     /// Set prover for automatic proof generation
     pub fn with_prover(mut self, prover: Arc<Prover>) -> Self {
         self.prover = Some(prover);
         self
     }
 
+    pub fn with_proof_timeout(mut self, timeout: Duration) -> Self {
+        self.proof_timeout = timeout;
+        self
+    }
+
     /// Set prover configuration (will create prover internally)
     pub fn with_prover_config(mut self, config: ProverConfig) -> Result<Self, SequencerError> {
         let prover = Prover::new(config).map_err(|e| {
@@ -90,6 +483,94 @@ impl Sequencer {
         Ok(sequencer)
     }
 
+    /// Like `with_storage_arc`, but applies the STF config and hash
+    /// algorithm before replaying stored blocks, so blocks built under a
+    /// non-default config replay the same way on restart
+    pub fn with_storage_and_config(
+        storage: Arc<dyn Storage>,
+        stf_config: StfConfig,
+        hash_algo: HashAlgo,
+    ) -> Result<Self, SequencerError> {
+        let mut sequencer = Self::with_config(DEFAULT_MAX_QUEUE_SIZE, DEFAULT_MAX_TXS_PER_BLOCK)
+            .with_stf_config(stf_config)
+            .with_hash_algo(hash_algo);
+        sequencer.load_state_from_storage(storage)?;
+        Ok(sequencer)
+    }
+
+    /// Start the chain from `genesis` instead of an empty state. Only a
+    /// fresh chain is initialized: state already loaded from storage is
+    /// kept, so the same genesis can be passed on every start. With storage,
+    /// the genesis state is saved as the snapshot of block 0, so a restart
+    /// loads it like any other snapshot.
+    pub fn with_genesis(self, genesis: GenesisConfig) -> Result<Self, SequencerError> {
+        let has_history = {
+            let state = self.lock_state();
+            !state.accounts.is_empty() || !state.deals.is_empty()
+        };
+        if has_history || self.has_committed_blocks()? {
+            info!("chain already initialized, skipping genesis");
+            return Ok(self);
+        }
+
+        let state = genesis.build_state()?;
+        if let Some(ref storage) = self.storage {
+            storage.save_state_snapshot(&state, 0).map_err(|e| {
+                SequencerError::StorageError(format!("Failed to save genesis snapshot: {:?}", e))
+            })?;
+        }
+        *self.lock_state() = state;
+        *self.last_snapshot_block_id.lock().unwrap() = 0;
+        info!(
+            accounts = genesis.accounts.len(),
+            assets = genesis.assets.len(),
+            "applied genesis"
+        );
+        Ok(self)
+    }
+
+    fn has_committed_blocks(&self) -> Result<bool, SequencerError> {
+        match self.storage {
+            Some(ref storage) => Ok(storage
+                .get_latest_block_id()
+                .map_err(|e| {
+                    SequencerError::StorageError(format!("Failed to get latest block ID: {:?}", e))
+                })?
+                .is_some_and(|id| id > 0)),
+            None => Ok(self.get_current_block_id() > 0),
+        }
+    }
+
+    /// Upsert an exported account into the state of a chain that has no
+    /// blocks yet. Once blocks exist, state only changes through them, so
+    /// the import is rejected with `ChainStarted`. With storage, the result
+    /// is saved as the snapshot of block 0, like a genesis state.
+    pub fn import_account(
+        &self,
+        owner: Address,
+        export: AccountExport,
+    ) -> Result<AccountId, SequencerError> {
+        let _build = self.build_lock.lock().unwrap_or_else(|e| e.into_inner());
+        if self.has_committed_blocks()? {
+            return Err(SequencerError::ChainStarted);
+        }
+
+        let mut state = self.lock_state();
+        let account_id = state
+            .import_account(owner, export)
+            .map_err(SequencerError::ImportFailed)?;
+        if let Some(ref storage) = self.storage {
+            storage.save_state_snapshot(&state, 0).map_err(|e| {
+                SequencerError::StorageError(format!("Failed to save state snapshot: {:?}", e))
+            })?;
+            *self.last_snapshot_block_id.lock().unwrap() = 0;
+        }
+        drop(state);
+
+        self.reset_read_snapshot();
+        Ok(account_id)
+    }
+
     pub fn set_storage<S: Storage + 'static>(&mut self, storage: S) -> Result<(), SequencerError> {
         self.load_state_from_storage(Arc::new(storage))?;
         Ok(())
@@ -104,12 +585,14 @@ impl Sequencer {
             .unwrap_or(0);
 
         match storage.get_latest_state_snapshot() {
-            Ok(Some((snapshot_state, snapshot_block_id))) => {
-                *self.state.lock().unwrap() = snapshot_state;
+            Ok(Some((mut snapshot_state, snapshot_block_id))) => {
+                snapshot_state.rebuild_derived_indexes();
+                *self.lock_state() = snapshot_state;
                 *self.last_snapshot_block_id.lock().unwrap() = snapshot_block_id;
 
                 if latest_block_id > snapshot_block_id {
                     self.replay_blocks_from_storage(
+                        &mut self.lock_state(),
                         &*storage,
                         snapshot_block_id + 1,
                         latest_block_id,
@@ -124,26 +607,16 @@ impl Sequencer {
                 if latest_block_id > 0 {
                     // Try to find the first existing block (could be 1, 2, etc.)
                     // Start from block 1 since blocks are numbered from 1
-                    let mut first_block_found = None;
-                    for block_id in 1..=latest_block_id {
-                        match storage.get_block(block_id) {
-                            Ok(Some(_)) => {
-                                first_block_found = Some(block_id);
-                                break;
-                            }
-                            Ok(None) => continue,
-                            Err(e) => {
-                                return Err(SequencerError::StorageError(format!(
-                                    "Failed to check block {}: {:?}",
-                                    block_id, e
-                                )));
-                            }
-                        }
-                    }
-                    
+                    let first_block_found = first_stored_block_id(&*storage, 1, latest_block_id)?;
+
                     if let Some(first_block) = first_block_found {
                         // Found first block, replay from there
-                        self.replay_blocks_from_storage(&*storage, first_block, latest_block_id)?;
+                        self.replay_blocks_from_storage(
+                            &mut self.lock_state(),
+                            &*storage,
+                            first_block,
+                            latest_block_id,
+                        )?;
                     } else {
                         // No blocks found despite latest_block_id > 0
                         // This indicates data inconsistency - treat as empty storage
@@ -162,12 +635,253 @@ impl Sequencer {
             }
         }
 
+        self.rebuild_withdrawals_accumulator(&*storage, latest_block_id)?;
+
+        let latest_block = storage.get_block(latest_block_id).map_err(|e| {
+            SequencerError::StorageError(format!("Failed to load latest block: {:?}", e))
+        })?;
+        if let Some(block) = latest_block {
+            self.last_block_timestamp
+                .store(block.timestamp, Ordering::Relaxed);
+        }
+
+        let last_checkpoint = storage.get_latest_checkpoint().map_err(|e| {
+            SequencerError::StorageError(format!("Failed to load checkpoint: {:?}", e))
+        })?;
+        *self.last_checkpoint.lock().unwrap() = last_checkpoint;
+
         self.storage = Some(storage);
+        self.reset_read_snapshot();
+        Ok(())
+    }
+
+    /// Roll the chain back so that `block_id` is the latest block, e.g. to
+    /// recover from a block executed by a buggy build. The state is rebuilt
+    /// from the latest snapshot at or before `block_id` by replaying stored
+    /// blocks up to and including it, checked against the block's
+    /// `state_root`, and every later block is deleted from storage. Txs of
+    /// the deleted blocks are dropped rather than re-queued; txs already
+    /// queued stay queued.
+    ///
+    /// Requires storage, and refuses to go below the last exported
+    /// checkpoint, which may already be anchored on L1.
+    pub fn rollback_to(&self, block_id: BlockId) -> Result<(), SequencerError> {
+        let storage = self
+            .storage
+            .clone()
+            .ok_or_else(|| SequencerError::StorageError("Rollback requires storage".to_string()))?;
+        // No block may be built while the chain is rewritten
+        let _build = self.build_lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        if block_id >= self.get_current_block_id() || block_id < self.last_checkpoint_block_id() {
+            return Err(SequencerError::InvalidBlockId);
+        }
+        let block = storage
+            .get_block(block_id)
+            .map_err(|e| {
+                SequencerError::StorageError(format!("Failed to load block {}: {:?}", block_id, e))
+            })?
+            .ok_or_else(|| SequencerError::StorageError(format!("Block {} not found", block_id)))?;
+
+        let (mut state, snapshot_block_id) = self.replay_state_at(&*storage, block_id)?;
+
+        let state_root = self.compute_state_root(&mut state);
+        if state_root != block.state_root {
+            return Err(SequencerError::SelfCheckFailed(format!(
+                "state root mismatch at block {}: replayed 0x{}, block has 0x{}",
+                block_id,
+                to_hex(&state_root),
+                to_hex(&block.state_root)
+            )));
+        }
+
+        // Truncation deletes the deals the later blocks changed; the ones
+        // that existed at `block_id` are saved back from the replayed state
+        let mut changed_deals = BTreeSet::new();
+        for later_block_id in block_id + 1..self.get_current_block_id() {
+            let diff = storage.get_state_diff(later_block_id).map_err(|e| {
+                SequencerError::StorageError(format!("Failed to load state diff: {:?}", e))
+            })?;
+            if let Some(diff) = diff {
+                changed_deals.extend(diff.deals.iter().map(|deal| deal.id));
+                changed_deals.extend(diff.removed_deals);
+            }
+        }
+
+        storage.truncate_after(block_id).map_err(|e| {
+            SequencerError::StorageError(format!("Failed to delete later blocks: {:?}", e))
+        })?;
+        for deal in changed_deals.iter().filter_map(|id| state.get_deal(*id)) {
+            storage.save_deal(deal).map_err(|e| {
+                SequencerError::StorageError(format!("Failed to save deal: {:?}", e))
+            })?;
+        }
+
+        *self.lock_state() = state;
+        *self.current_block_id.lock().unwrap() = block_id + 1;
+        *self.last_snapshot_block_id.lock().unwrap() = snapshot_block_id;
+        // Retries of txs in the deleted blocks must be accepted again
+        self.request_ids.lock().unwrap().clear();
+        self.last_block_timestamp
+            .store(block.timestamp, Ordering::Relaxed);
+        self.rebuild_withdrawals_accumulator(&*storage, block_id)?;
+        self.reset_read_snapshot();
+
+        Ok(())
+    }
+
+    /// Catch up with blocks another sequencer has written to the shared
+    /// storage, as a read-only replica does. Blocks past this sequencer's
+    /// head are replayed and published to event subscribers. If the stored
+    /// chain no longer holds this sequencer's head, e.g. after a rollback
+    /// or a replay that failed halfway, the state is rebuilt from the
+    /// latest snapshot at or before the stored head instead. Returns the
+    /// id of the latest block now applied.
+    pub fn sync_from_storage(&self) -> Result<BlockId, SequencerError> {
+        let storage = self
+            .storage
+            .clone()
+            .ok_or_else(|| SequencerError::StorageError("Syncing requires storage".to_string()))?;
+        let _build = self.build_lock.lock().unwrap_or_else(|e| e.into_inner());
+        storage.catch_up_with_primary().map_err(|e| {
+            SequencerError::StorageError(format!("Failed to catch up with primary: {:?}", e))
+        })?;
+
+        let latest_block_id = storage
+            .get_latest_block_id()
+            .map_err(|e| {
+                SequencerError::StorageError(format!("Failed to get latest block ID: {:?}", e))
+            })?
+            .unwrap_or(0);
+        let head = self.get_current_block_id().saturating_sub(1);
+
+        let mut state = self.lock_state();
+        let head_block = storage.get_block(head).map_err(|e| {
+            SequencerError::StorageError(format!("Failed to load block {}: {:?}", head, e))
+        })?;
+        let on_stored_chain = latest_block_id >= head
+            && match head_block {
+                Some(block) => block.state_root == self.compute_state_root(&mut state),
+                None => head == 0,
+            };
+        if on_stored_chain && latest_block_id == head {
+            return Ok(head);
+        }
+
+        let replay_from = if on_stored_chain {
+            head + 1
+        } else {
+            let snapshot = storage
+                .get_state_snapshot_at_or_before(latest_block_id)
+                .map_err(|e| {
+                    SequencerError::StorageError(format!("Failed to load state snapshot: {:?}", e))
+                })?;
+            let (snapshot_state, snapshot_block_id) = match snapshot {
+                Some((mut snapshot_state, snapshot_block_id)) => {
+                    snapshot_state.rebuild_derived_indexes();
+                    (snapshot_state, snapshot_block_id)
+                }
+                None => (State::new(), 0),
+            };
+            *state = snapshot_state;
+            // The read snapshot can't be brought onto the reloaded chain
+            *self.read_snapshot_deltas.lock().unwrap() = None;
+            *self.current_block_id.lock().unwrap() = snapshot_block_id + 1;
+            *self.last_snapshot_block_id.lock().unwrap() = snapshot_block_id;
+            self.rebuild_withdrawals_accumulator(&*storage, snapshot_block_id)?;
+            snapshot_block_id + 1
+        };
+
+        let mut events = Vec::new();
+        let blocks = storage.iter_blocks(replay_from, latest_block_id);
+        for (block_id, block) in (replay_from..=latest_block_id).zip(blocks) {
+            let block = block.map_err(|e| {
+                SequencerError::StorageError(format!("Failed to load block {}: {:?}", block_id, e))
+            })?;
+            state.begin_journal();
+            let applied = apply_block_with_config(
+                &mut state,
+                &block.transactions,
+                block.timestamp,
+                &self.stf_config,
+            );
+            let delta = state.journal_delta();
+            state.commit_journal();
+            applied.map_err(SequencerError::ExecutionFailed)?;
+            self.record_read_snapshot_delta(block_id, delta);
+
+            *self.current_block_id.lock().unwrap() = block_id + 1;
+            self.last_block_timestamp
+                .store(block.timestamp, Ordering::Relaxed);
+            let mut accumulator = self.withdrawals_root_accumulator.lock().unwrap();
+            *accumulator = accumulate_withdrawals_root(&accumulator, &block.withdrawals_root);
+            drop(accumulator);
+
+            if let Ok(Some(diff)) = storage.get_state_diff(block_id) {
+                events.extend(SequencerEvent::for_block(&block, diff));
+            }
+        }
+        drop(state);
+
+        *self.last_checkpoint.lock().unwrap() = storage.get_latest_checkpoint().map_err(|e| {
+            SequencerError::StorageError(format!("Failed to load checkpoint: {:?}", e))
+        })?;
+        self.refresh_read_snapshot();
+        for event in events {
+            // Only fails once every subscriber has gone away
+            let _ = self.events.send(event);
+        }
+        Ok(latest_block_id)
+    }
+
+    /// Restore the withdrawals root accumulator after `latest_block_id`
+    /// from the latest accumulator recorded at or before it, folding in
+    /// the blocks after that one. Stores written before accumulators were
+    /// recorded fold every stored block.
+    fn rebuild_withdrawals_accumulator(
+        &self,
+        storage: &dyn Storage,
+        latest_block_id: BlockId,
+    ) -> Result<(), SequencerError> {
+        let mut accumulator = [0u8; 32];
+        let mut fold_from = 0;
+        for block_id in (0..=latest_block_id).rev() {
+            let recorded = storage.get_withdrawals_accumulator(block_id).map_err(|e| {
+                SequencerError::StorageError(format!(
+                    "Failed to load withdrawals accumulator {}: {:?}",
+                    block_id, e
+                ))
+            })?;
+            if let Some(recorded) = recorded {
+                accumulator = recorded;
+                fold_from = block_id + 1;
+                break;
+            }
+        }
+
+        for block_id in fold_from..=latest_block_id {
+            match storage.get_block(block_id) {
+                Ok(Some(block)) => {
+                    accumulator =
+                        accumulate_withdrawals_root(&accumulator, &block.withdrawals_root);
+                }
+                Ok(None) => continue,
+                Err(e) => {
+                    return Err(SequencerError::StorageError(format!(
+                        "Failed to load block {}: {:?}",
+                        block_id, e
+                    )));
+                }
+            }
+        }
+
+        *self.withdrawals_root_accumulator.lock().unwrap() = accumulator;
         Ok(())
     }
 
     fn replay_blocks_from_storage(
         &self,
+        state: &mut State,
         storage: &dyn Storage,
         from_block: BlockId,
         to_block: BlockId,
@@ -177,15 +891,20 @@ impl Sequencer {
             return Ok(());
         }
 
-        let mut state = self.state.lock().unwrap();
+        let blocks = storage.iter_blocks(from_block, to_block);
 
-        for block_id in from_block..=to_block {
-            match storage.get_block(block_id) {
-                Ok(Some(block)) => {
-                    apply_block(&mut state, &block.transactions, block.timestamp)
-                        .map_err(SequencerError::ExecutionFailed)?;
+        for (block_id, block) in (from_block..=to_block).zip(blocks) {
+            match block {
+                Ok(block) => {
+                    apply_block_with_config(
+                        state,
+                        &block.transactions,
+                        block.timestamp,
+                        &self.stf_config,
+                    )
+                    .map_err(SequencerError::ExecutionFailed)?;
                 }
-                Ok(None) => {
+                Err(zkclear_storage::StorageError::NotFound) => {
                     return Err(SequencerError::StorageError(format!(
                         "Block {} not found",
                         block_id
@@ -203,31 +922,130 @@ impl Sequencer {
         Ok(())
     }
 
+    /// The state after block `block_id`, rebuilt from the latest snapshot
+    /// at or before it, or from the first stored block without one, by
+    /// replaying the stored blocks up to and including `block_id`. Returns
+    /// it with the id of the snapshot used, 0 for none.
+    fn replay_state_at(
+        &self,
+        storage: &dyn Storage,
+        block_id: BlockId,
+    ) -> Result<(State, BlockId), SequencerError> {
+        let snapshot = storage
+            .get_state_snapshot_at_or_before(block_id)
+            .map_err(|e| {
+                SequencerError::StorageError(format!("Failed to load state snapshot: {:?}", e))
+            })?;
+        let (mut state, snapshot_block_id, replay_from) = match snapshot {
+            Some((mut state, snapshot_block_id)) => {
+                state.rebuild_derived_indexes();
+                (state, snapshot_block_id, snapshot_block_id + 1)
+            }
+            None => {
+                let first_block = first_stored_block_id(storage, 0, block_id)?.unwrap_or(0);
+                (State::new(), 0, first_block)
+            }
+        };
+        self.replay_blocks_from_storage(&mut state, storage, replay_from, block_id)?;
+        Ok((state, snapshot_block_id))
+    }
+
     pub fn submit_tx(&self, tx: Tx) -> Result<(), SequencerError> {
         self.submit_tx_with_validation(tx, true)
     }
 
     pub fn submit_tx_with_validation(&self, tx: Tx, validate: bool) -> Result<(), SequencerError> {
-        if validate {
-            // Security checks: validate transaction size and address format
-            if let Err(_) = validate_tx_size(&tx) {
-                return Err(SequencerError::InvalidSignature); // Reuse error type
+        if self.observers.is_empty() {
+            return self
+                .enqueue_tx(tx, validate)
+                .inspect(|()| self.check_build_threshold())
+                .inspect_err(|e| self.metrics.record_rejection(e));
+        }
+
+        let result = self.enqueue_tx(tx.clone(), validate);
+        match result {
+            Ok(()) => self.check_build_threshold(),
+            Err(ref e) => {
+                self.metrics.record_rejection(e);
+                self.notify_tx_rejected(&tx, e);
             }
-            
+        }
+        result
+    }
+
+    fn check_build_threshold(&self) {
+        if let Some(threshold) = self.build_threshold {
+            if self.queue_length() >= threshold {
+                self.build_notify.notify_one();
+            }
+        }
+    }
+
+    /// Resolves once a submission has brought the queue to the build
+    /// threshold, and never without one. A pulse with no task waiting is
+    /// kept for the next call, so the block production task can select on
+    /// this alongside its timer without missing one.
+    pub async fn build_threshold_reached(&self) {
+        self.build_notify.notified().await;
+    }
+
+    /// Submit `tx` at most once per client `request_id` of its sender. A
+    /// retry with an id still in the cache is not enqueued again and gets
+    /// back the tx hash recorded by the first submission. Rejected
+    /// submissions aren't recorded, so they can be retried under the same
+    /// id.
+    pub fn submit_tx_with_request_id(
+        &self,
+        tx: Tx,
+        request_id: &str,
+        tx_hash: String,
+        validate: bool,
+    ) -> Result<SubmitOutcome, SequencerError> {
+        // Held across the submission so concurrent retries can't both miss
+        let mut request_ids = self.request_ids.lock().unwrap();
+        let from = tx.from;
+        if let Some(tx_hash) = request_ids.get(from, request_id) {
+            return Ok(SubmitOutcome::Duplicate(tx_hash));
+        }
+
+        self.submit_tx_with_validation(tx, validate)?;
+        request_ids.insert(from, request_id.to_string(), tx_hash);
+        Ok(SubmitOutcome::Queued)
+    }
+
+    fn enqueue_tx(&self, tx: Tx, validate: bool) -> Result<(), SequencerError> {
+        if tx.domain != self.network_id {
+            return Err(SequencerError::WrongDomain);
+        }
+
+        // Checked with or without validation, as the STF would reject a
+        // tx whose kind doesn't match its payload anyway
+        if tx.kind != tx.payload.kind() {
+            return Err(SequencerError::ValidationFailed);
+        }
+        if !self.stf_config.is_tx_kind_enabled(&tx.kind) {
+            return Err(SequencerError::TxKindDisabled);
+        }
+
+        // Checked with or without validation, as trusted callers skip it
+        if self.tx_limits.check_tx(&tx).is_err() {
+            return Err(SequencerError::TxTooLarge);
+        }
+
+        if validate {
+            // Security checks: validate address format
             if !validate_address(&tx.from) {
                 return Err(SequencerError::InvalidSignature);
             }
             
-            let state = self.state.lock().unwrap();
-            
-            // Validate nonce gap
-            let account = state.get_account_by_address(tx.from);
-            let current_nonce = account.map(|a| a.nonce).unwrap_or(0);
-            if let Err(_) = validate_nonce_gap(current_nonce, tx.nonce) {
-                return Err(SequencerError::InvalidNonce);
-            }
+            let state = self.lock_state();
 
-            match validate_tx(&state, &tx) {
+            match validate_tx(
+                &state,
+                &tx,
+                self.eip712_domain.as_ref(),
+                self.erc1271_verifier.as_deref(),
+            ) {
                 Ok(()) => {}
                 Err(ValidationError::InvalidSignature) => {
                     return Err(SequencerError::InvalidSignature)
@@ -236,68 +1054,163 @@ impl Sequencer {
                 Err(ValidationError::SignatureRecoveryFailed) => {
                     return Err(SequencerError::InvalidSignature)
                 }
+                Err(ValidationError::KindMismatch) => return Err(SequencerError::ValidationFailed),
+                Err(ValidationError::SignatureLengthMismatch) => {
+                    return Err(SequencerError::InvalidSignature)
+                }
+                Err(ValidationError::TxTooLarge) => return Err(SequencerError::TxTooLarge),
+            }
+
+            // Lock order: state, queue, nonce buffer
+            let mut queue = self.tx_queue.lock().unwrap();
+            self.check_throttle(&queue, &tx)?;
+            let next_nonce = next_sender_nonce(&state, &queue, tx.from);
+            if tx.nonce < next_nonce || tx.nonce - next_nonce > self.max_nonce_gap {
+                return Err(SequencerError::InvalidNonce);
+            }
+
+            let mut buffer = self.nonce_buffer.lock().unwrap();
+            if queue.len() + buffered_len(&buffer) >= self.max_queue_size {
+                return Err(SequencerError::QueueFull);
+            }
+
+            if tx.nonce > next_nonce {
+                buffer
+                    .entry(tx.from)
+                    .or_default()
+                    .insert(tx.nonce, (tx, Instant::now()));
+                return Ok(());
             }
 
-            drop(state);
+            let from = tx.from;
+            queue.push_back(tx);
+            let after = next_nonce.saturating_add(1);
+            promote_buffered(&mut queue, &mut buffer, from, after, self.max_queue_size);
+            return Ok(());
         }
 
+        // Lock order: queue, nonce buffer
         let mut queue = self.tx_queue.lock().unwrap();
+        let buffered = buffered_len(&self.nonce_buffer.lock().unwrap());
 
-        if queue.len() >= self.max_queue_size {
+        if queue.len() + buffered >= self.max_queue_size {
             return Err(SequencerError::QueueFull);
         }
+        self.check_throttle(&queue, &tx)?;
 
         queue.push_back(tx);
         Ok(())
     }
 
-    /// Build a block with transactions from the queue
-    /// This is a synchronous version that doesn't generate proofs
-    pub fn build_block(&self) -> Result<Block, SequencerError> {
-        self.build_block_with_proof(false)
+    fn check_throttle(&self, queue: &Mempool, tx: &Tx) -> Result<(), SequencerError> {
+        if tx.fee < self.throttle_min_fee
+            && self.pressure_at(queue.len()) >= self.throttle_high_water_mark
+        {
+            return Err(SequencerError::Throttled);
+        }
+        Ok(())
     }
 
-    /// Build a block with optional proof generation
-    /// If generate_proof is true and prover is available, generates ZK proof
-    pub fn build_block_with_proof(&self, generate_proof: bool) -> Result<Block, SequencerError> {
-        let mut queue = self.tx_queue.lock().unwrap();
-        let block_id = *self.current_block_id.lock().unwrap();
-
-        if queue.is_empty() {
-            return Err(SequencerError::NoTransactions);
+    fn pressure_at(&self, queue_length: usize) -> f32 {
+        if self.max_queue_size == 0 {
+            return 1.0;
         }
+        (queue_length as f32 / self.max_queue_size as f32).min(1.0)
+    }
 
-        let mut transactions = Vec::new();
-        let count = queue.len().min(self.max_txs_per_block);
+    /// Number of txs from `address` buffered while waiting for an earlier nonce
+    pub fn buffered_tx_count(&self, address: Address) -> usize {
+        self.nonce_buffer
+            .lock()
+            .unwrap()
+            .get(&address)
+            .map_or(0, BTreeMap::len)
+    }
 
-        for _ in 0..count {
-            if let Some(tx) = queue.pop_front() {
-                transactions.push(tx);
-            } else {
-                break;
+    /// Nonce the next tx from `address` should use: the committed account
+    /// nonce advanced past the sender's queued txs, and 0 for an unknown
+    /// account. Buffered txs don't count, as they wait on this nonce.
+    pub fn next_nonce(&self, address: Address) -> u64 {
+        // Lock order: state, queue
+        let state = self.lock_state();
+        let queue = self.tx_queue.lock().unwrap();
+        next_sender_nonce(&state, &queue, address)
+    }
+
+    /// Move buffered txs whose gap has been closed into the queue. Called
+    /// after a block executes, since a sender's next nonce may then be
+    /// determined by the state rather than by queued txs.
+    fn promote_all_buffered(&self, state: &State) {
+        let mut queue = self.tx_queue.lock().unwrap();
+        let mut buffer = self.nonce_buffer.lock().unwrap();
+        let senders: Vec<Address> = buffer.keys().copied().collect();
+
+        for sender in senders {
+            let next_nonce = next_sender_nonce(state, &queue, sender);
+            promote_buffered(
+                &mut queue,
+                &mut buffer,
+                sender,
+                next_nonce,
+                self.max_queue_size,
+            );
+        }
+    }
+
+    /// Build a block with transactions from the queue
+    /// This is a synchronous version that doesn't generate proofs
+    pub fn build_block(&self) -> Result<Block, SequencerError> {
+        self.build_block_with_proof(false)
+    }
+
+    /// Build a block with optional proof generation
+    /// If generate_proof is true and prover is available, generates ZK proof
+    pub fn build_block_with_proof(&self, generate_proof: bool) -> Result<Block, SequencerError> {
+        self.evict_stale_txs();
+
+        let mut queue = self.tx_queue.lock().unwrap();
+        let block_id = *self.current_block_id.lock().unwrap();
+        let span = info_span!("build_block", block_id, tx_count = tracing::field::Empty);
+        let _span = span.enter();
+
+        if queue.is_empty() {
+            return Err(SequencerError::NoTransactions);
+        }
+
+        if let Some(ref lease) = self.block_builder_lease {
+            if !lease.try_acquire(block_id)? {
+                return Err(SequencerError::NotLeader);
             }
         }
+
+        let mut transactions = self.take_block_transactions(&mut queue);
         drop(queue);
 
-        // Get current state (before applying transactions)
-        let prev_state = self.state.lock().unwrap().clone();
-        drop(self.state.lock().unwrap());
+        // Get current state (before applying transactions). Bringing the live
+        // state's cached root up to date first means the copies below only
+        // rehash the leaves this block touches.
+        let prev_state = {
+            let mut state = self.lock_state();
+            state.root_with(self.hash_algo);
+            state.clone()
+        };
 
-        // Calculate state roots and withdrawals root
-        // Note: prev_state_root is computed but not used directly here (used in proof generation)
-        let _prev_state_root = self.compute_state_root(&prev_state)?;
+        if self.skip_nonce_conflicts {
+            transactions = self.filter_nonce_conflicts(&prev_state, transactions);
+            if transactions.is_empty() {
+                return Err(SequencerError::NoTransactions);
+            }
+        }
+        span.record("tx_count", transactions.len());
 
         // Apply transactions to a copy of state to get new state
         let mut new_state = prev_state.clone();
-        let timestamp = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
+        let timestamp = self.clock.now();
 
-        apply_block(&mut new_state, &transactions, timestamp)
+        apply_block_with_config(&mut new_state, &transactions, timestamp, &self.stf_config)
             .map_err(SequencerError::ExecutionFailed)?;
 
-        let new_state_root = self.compute_state_root(&new_state)?;
+        let new_state_root = self.compute_state_root(&mut new_state);
         let withdrawals_root = self.compute_withdrawals_root(&transactions)?;
 
         // Generate proof if requested and prover is available
@@ -318,7 +1231,7 @@ impl Sequencer {
                 match self.generate_block_proof(prover, &temp_block, &prev_state, &new_state) {
                     Ok(proof) => proof,
                     Err(e) => {
-                        eprintln!("Warning: Failed to generate proof: {:?}", e);
+                        warn!(error = ?e, "block proof failed, building block without proof");
                         Vec::new() // Fallback to empty proof
                     }
                 }
@@ -341,9 +1254,138 @@ impl Sequencer {
         Ok(block)
     }
 
-    /// Generate block proof using prover (blocking call)
-    /// This is called from spawn_blocking, so we try to use Handle::current() if available
-    /// Otherwise create a new runtime in a separate thread to avoid deadlocks
+    /// Drop queued txs that can never be included because their sender's
+    /// committed nonce has moved past them, and, with a mempool TTL, txs
+    /// that have waited longer than it. Buffered txs are dropped once they
+    /// have waited longer than the nonce buffer TTL for their gap to be
+    /// filled. Runs before each block is built; returns how many txs were
+    /// evicted.
+    pub fn evict_stale_txs(&self) -> usize {
+        // Lock order: state, queue, nonce buffer
+        let state = self.lock_state();
+        let mut queue = self.tx_queue.lock().unwrap();
+        let stale = queue.remove_where(|tx, _| {
+            state
+                .get_account_by_address(tx.from)
+                .is_some_and(|account| tx.nonce < account.nonce)
+        });
+        let mut expired = match self.mempool_ttl {
+            Some(ttl) => queue.remove_where(|_, waited| waited > ttl),
+            None => Vec::new(),
+        };
+        drop(queue);
+        drop(state);
+
+        let mut buffer = self.nonce_buffer.lock().unwrap();
+        for pending in buffer.values_mut() {
+            let aged: Vec<u64> = pending
+                .iter()
+                .filter(|(_, (_, buffered_at))| buffered_at.elapsed() > self.nonce_buffer_ttl)
+                .map(|(nonce, _)| *nonce)
+                .collect();
+            expired.extend(
+                aged.iter()
+                    .filter_map(|nonce| pending.remove(nonce))
+                    .map(|(tx, _)| tx),
+            );
+        }
+        buffer.retain(|_, pending| !pending.is_empty());
+        drop(buffer);
+
+        for tx in &stale {
+            self.metrics.record_eviction(EvictionReason::StaleNonce);
+            self.notify_tx_rejected(tx, &SequencerError::InvalidNonce);
+        }
+        for _ in &expired {
+            self.metrics.record_eviction(EvictionReason::Expired);
+        }
+        let evicted = stale.len() + expired.len();
+        if evicted > 0 {
+            info!(
+                stale = stale.len(),
+                expired = expired.len(),
+                "evicted queued txs"
+            );
+        }
+        evicted
+    }
+
+    /// Pop the txs for the next block from the queue in the mempool's order,
+    /// respecting the per-sender cap. Skipped txs keep their queue order.
+    fn take_block_transactions(&self, queue: &mut Mempool) -> Vec<Tx> {
+        let Some(max_per_sender) = self.max_txs_per_sender else {
+            let count = queue.len().min(self.max_txs_per_block);
+            return (0..count).filter_map(|_| queue.pop()).collect();
+        };
+
+        let mut per_sender: HashMap<Address, usize> = HashMap::new();
+        let mut selected = Vec::new();
+        let mut skipped = Vec::new();
+
+        while selected.len() < self.max_txs_per_block {
+            let Some(tx) = queue.pop() else {
+                break;
+            };
+
+            let count = per_sender.entry(tx.from).or_insert(0);
+            if *count < max_per_sender {
+                *count += 1;
+                selected.push(tx);
+            } else {
+                skipped.push(tx);
+            }
+        }
+
+        for tx in skipped.into_iter().rev() {
+            queue.push_front(tx);
+        }
+        selected
+    }
+
+    /// Keep at most one tx per (sender, nonce), in nonce order per sender.
+    /// Txs with a stale or duplicate nonce are dropped; txs with a future
+    /// nonce are returned to the front of the queue for a later block.
+    fn filter_nonce_conflicts(&self, state: &State, transactions: Vec<Tx>) -> Vec<Tx> {
+        let mut next_nonces: HashMap<Address, u64> = HashMap::new();
+        let mut selected = Vec::with_capacity(transactions.len());
+        let mut deferred = Vec::new();
+
+        for tx in transactions {
+            let next_nonce = next_nonces.entry(tx.from).or_insert_with(|| {
+                state
+                    .get_account_by_address(tx.from)
+                    .map(|a| a.nonce)
+                    .unwrap_or(0)
+            });
+
+            if tx.nonce == *next_nonce {
+                *next_nonce = next_nonce.saturating_add(1);
+                selected.push(tx);
+            } else if tx.nonce > *next_nonce {
+                deferred.push(tx);
+            } else {
+                self.notify_tx_rejected(&tx, &SequencerError::InvalidNonce);
+            }
+        }
+
+        if !deferred.is_empty() {
+            let mut queue = self.tx_queue.lock().unwrap();
+            for tx in deferred.into_iter().rev() {
+                queue.push_front(tx);
+            }
+        }
+
+        selected
+    }
+
+    /// Generate block proof using prover (blocking call), giving up with
+    /// `ProverError("timeout")` after `proof_timeout`.
+    ///
+    /// The proof future runs on a current-thread runtime in a worker thread,
+    /// since callers may already be on a tokio runtime where blocking on
+    /// another one would panic. The timeout wraps the future itself, so on
+    /// expiry it is dropped at its next await point and the worker exits
+    /// and is joined rather than left running.
     fn generate_block_proof(
         &self,
         prover: &Arc<Prover>,
@@ -351,45 +1393,63 @@ impl Sequencer {
         prev_state: &State,
         new_state: &State,
     ) -> Result<Vec<u8>, SequencerError> {
-        // We're in spawn_blocking, so we can't use Handle::current() directly
-        // Create runtime in a separate thread to avoid deadlocks
+        let span = info_span!(
+            "generate_block_proof",
+            block_id = block.id,
+            proof_ms = tracing::field::Empty
+        );
+        let _span = span.enter();
+        let started = std::time::Instant::now();
 
         // Clone data needed for proof generation
         let prover_clone = Arc::clone(prover);
         let block_clone = block.clone();
         let prev_state_clone = prev_state.clone();
         let new_state_clone = new_state.clone();
+        let timeout = self.proof_timeout;
 
-        // Create runtime in a separate thread to avoid deadlocks
-        // This is necessary because we're already in spawn_blocking
         let handle = std::thread::spawn(move || {
-            // Use current_thread runtime to avoid spawning new threads
-            let rt = tokio::runtime::Builder::new_current_thread()
+            let runtime = match tokio::runtime::Builder::new_current_thread()
                 .enable_all()
-                .build();
-            
-            match rt {
-                Ok(runtime) => {
-                    runtime.block_on(
-                        prover_clone.prove_block(&block_clone, &prev_state_clone, &new_state_clone)
-                    )
-                }
+                .build()
+            {
+                Ok(runtime) => runtime,
                 Err(e) => {
-                    Err(ProverError::StarkProof(format!("Failed to create runtime: {:?}", e)))
+                    return Some(Err(ProverError::StarkProof(format!(
+                        "Failed to create runtime: {:?}",
+                        e
+                    ))))
                 }
-            }
+            };
+
+            // The timer has to be created inside the runtime. `None` once it
+            // expires.
+            runtime
+                .block_on(async {
+                    let proof = prover_clone.prove_block(
+                        &block_clone,
+                        &prev_state_clone,
+                        &new_state_clone,
+                    );
+                    tokio::time::timeout(timeout, proof).await
+                })
+                .ok()
         });
 
-        // Wait for result - join will block until thread completes
-        // For placeholder proofs, this should be very fast (< 1ms)
-        // For real proofs, this may take longer, but timeout is handled in demo
         match handle.join() {
-            Ok(Ok(block_proof)) => {
+            Ok(Some(Ok(block_proof))) => {
+                let elapsed = started.elapsed();
+                self.metrics.record_proof(elapsed);
+                let proof_ms = elapsed.as_millis() as u64;
+                span.record("proof_ms", proof_ms);
+                info!(proof_ms, "block_proved");
+
                 // Serialize the proof
                 bincode::serialize(&block_proof.zk_proof)
                     .map_err(|e| SequencerError::ProverError(format!("Failed to serialize proof: {}", e)))
             }
-            Ok(Err(e)) => {
+            Ok(None) => Err(SequencerError::ProverError("timeout".to_string())),
+            Ok(Some(Err(e))) => {
                 Err(SequencerError::ProverError(format!("Proof generation failed: {:?}", e)))
             }
             Err(_) => {
@@ -398,72 +1458,112 @@ impl Sequencer {
         }
     }
 
-    /// Compute state root from state
-    fn compute_state_root(&self, _state: &State) -> Result<[u8; 32], SequencerError> {
-        // Use prover's compute_state_root if available, otherwise use simple hash
-        // For now, use simple hash (same logic as Prover's placeholder)
-        let state_bytes = bincode::serialize(_state).map_err(|e| {
-            SequencerError::StorageError(format!("Failed to serialize state: {}", e))
-        })?;
-
-        use sha2::{Digest, Sha256};
-        let mut hasher = Sha256::new();
-        hasher.update(&state_bytes);
-        Ok(hasher.finalize().into())
+    /// Compute state root from state. Uses the state's incrementally
+    /// maintained tree, which matches the prover's from-scratch root.
+    fn compute_state_root(&self, state: &mut State) -> [u8; 32] {
+        state.root_with(self.hash_algo)
     }
 
-    /// Compute withdrawals root from transactions
+    /// Compute withdrawals root from transactions, over the withdrawals in
+    /// canonical rather than tx order
     fn compute_withdrawals_root(&self, transactions: &[Tx]) -> Result<[u8; 32], SequencerError> {
-        use zkclear_prover::merkle::{hash_withdrawal, MerkleTree};
-
-        let mut tree = MerkleTree::new();
+        zkclear_prover::merkle::compute_withdrawals_root_with(self.hash_algo, transactions)
+            .map_err(|e| {
+                SequencerError::ProverError(format!("Failed to compute withdrawals root: {:?}", e))
+            })
+    }
 
-        for tx in transactions {
-            if let zkclear_types::TxPayload::Withdraw(w) = &tx.payload {
-                let leaf = hash_withdrawal(tx.from, w.asset_id, w.amount, w.chain_id);
-                tree.add_leaf(leaf);
+    pub fn execute_block(&self, block: Block) -> Result<(), SequencerError> {
+        let tx_count = block.transactions.len();
+        let _span = info_span!("execute_block", block_id = block.id, tx_count).entered();
+        let diff = self.commit_block(&block).inspect_err(|e| {
+            warn!(block_id = block.id, error = ?e, "block execution failed");
+        })?;
+        self.metrics.record_block(&block);
+        info!(block_id = block.id, tx_count, "block_executed");
+        if let Some(diff) = diff {
+            for observer in &self.observers {
+                observer.on_block_executed(&block, &diff);
+            }
+            for event in SequencerEvent::for_block(&block, diff) {
+                // Only fails once every subscriber has gone away
+                let _ = self.events.send(event);
             }
         }
-
-        tree.root().map_err(|e| {
-            SequencerError::ProverError(format!("Failed to compute withdrawals root: {:?}", e))
-        })
+        Ok(())
     }
 
-    pub fn execute_block(&self, block: Block) -> Result<(), SequencerError> {
+    /// Apply and persist a block, saving its state diff with the block.
+    /// Returns the diff when storage, observers or event subscribers need
+    /// it, so observers can be notified once all locks are released.
+    fn commit_block(&self, block: &Block) -> Result<Option<StateDiff>, SequencerError> {
         let expected_id = *self.current_block_id.lock().unwrap();
         if block.id != expected_id {
             return Err(SequencerError::InvalidBlockId);
         }
 
-        let mut state = self.state.lock().unwrap();
+        let mut state = self.lock_state();
+        let wants_diff = self.storage.is_some()
+            || !self.observers.is_empty()
+            || self.events.receiver_count() > 0;
+        // Journaled so a failed transaction or write can be undone in place
+        state.begin_journal();
 
-        match apply_block(&mut state, &block.transactions, block.timestamp) {
+        match apply_block_with_config(&mut state, &block.transactions, block.timestamp, &self.stf_config) {
             Ok(()) => {
+                let delta = state.journal_delta();
+                let diff = wants_diff.then(|| delta.diff.clone());
+
+                // Nothing advances until the block is durable, so a failed
+                // write leaves the sequencer ready to execute it again
+                if let Some(ref storage) = self.storage {
+                    let deals: Vec<&Deal> = diff.iter().flat_map(|d| &d.deals).collect();
+                    if let Err(e) = storage.save_block_atomic(block, diff.as_ref(), &deals) {
+                        drop(deals);
+                        state.revert_journal();
+                        return Err(SequencerError::StorageError(format!(
+                            "Failed to save block: {:?}",
+                            e
+                        )));
+                    }
+                }
+                state.commit_journal();
+                self.record_read_snapshot_delta(block.id, delta);
+
+                self.promote_all_buffered(&state);
+
                 let mut block_id = self.current_block_id.lock().unwrap();
                 *block_id += 1;
                 drop(block_id);
+                self.last_block_timestamp
+                    .store(block.timestamp, Ordering::Relaxed);
 
+                let withdrawals_root_accumulator = {
+                    let mut accumulator = self.withdrawals_root_accumulator.lock().unwrap();
+                    *accumulator = accumulate_withdrawals_root(&accumulator, &block.withdrawals_root);
+                    *accumulator
+                };
                 if let Some(ref storage) = self.storage {
-                    storage.save_block(&block).map_err(|e| {
-                        SequencerError::StorageError(format!("Failed to save block: {:?}", e))
-                    })?;
-
-                    for (index, tx) in block.transactions.iter().enumerate() {
-                        storage.save_transaction(tx, block.id, index).map_err(|e| {
+                    storage
+                        .save_withdrawals_accumulator(block.id, &withdrawals_root_accumulator)
+                        .map_err(|e| {
                             SequencerError::StorageError(format!(
-                                "Failed to save transaction: {:?}",
+                                "Failed to save withdrawals accumulator: {:?}",
                                 e
                             ))
                         })?;
-                    }
+                }
 
-                    for deal in state.deals.values() {
-                        storage.save_deal(deal).map_err(|e| {
-                            SequencerError::StorageError(format!("Failed to save deal: {:?}", e))
-                        })?;
-                    }
+                let checkpoint_due = self.checkpoint_interval > 0
+                    && block.id.saturating_sub(self.last_checkpoint_block_id())
+                        >= self.checkpoint_interval;
+                let checkpoint = if checkpoint_due {
+                    Some(self.build_checkpoint(&mut state, block.id, withdrawals_root_accumulator)?)
+                } else {
+                    None
+                };
 
+                if let Some(ref storage) = self.storage {
                     let last_snapshot = *self.last_snapshot_block_id.lock().unwrap();
                     let blocks_since_snapshot = block.id.saturating_sub(last_snapshot);
 
@@ -481,12 +1581,142 @@ impl Sequencer {
                             })?;
 
                         *self.last_snapshot_block_id.lock().unwrap() = block.id;
+
+                        if self.snapshot_retention > 0 {
+                            // Interval snapshots are `snapshot_interval` blocks
+                            // apart, so the last N start this many blocks back
+                            let window = self
+                                .snapshot_interval
+                                .saturating_mul(self.snapshot_retention as BlockId - 1);
+                            storage
+                                .prune_snapshots_before(block.id.saturating_sub(window))
+                                .map_err(|e| {
+                                    SequencerError::StorageError(format!(
+                                        "Failed to prune state snapshots: {:?}",
+                                        e
+                                    ))
+                                })?;
+                        }
                     }
                 }
 
-                Ok(())
+                if let Some(checkpoint) = checkpoint {
+                    self.record_checkpoint(checkpoint)?;
+                }
+
+                Ok(diff)
+            }
+            Err(e) => {
+                state.revert_journal();
+                Err(SequencerError::ExecutionFailed(e))
             }
-            Err(e) => Err(SequencerError::ExecutionFailed(e)),
+        }
+    }
+
+    /// Check that the loaded state matches the latest stored block's
+    /// `state_root`, and optionally re-verify that block's proof.
+    /// Meant to run once at startup, before accepting transactions.
+    pub fn self_check(&self) -> Result<(), SequencerError> {
+        let Some(ref storage) = self.storage else {
+            return Ok(());
+        };
+
+        let latest_block_id = storage.get_latest_block_id().map_err(|e| {
+            SequencerError::StorageError(format!("Failed to get latest block ID: {:?}", e))
+        })?;
+        let Some(latest_block_id) = latest_block_id else {
+            return Ok(());
+        };
+        let block = storage
+            .get_block(latest_block_id)
+            .map_err(|e| {
+                SequencerError::StorageError(format!(
+                    "Failed to load block {}: {:?}",
+                    latest_block_id, e
+                ))
+            })?
+            .ok_or_else(|| {
+                SequencerError::SelfCheckFailed(format!(
+                    "latest block {} not found in storage",
+                    latest_block_id
+                ))
+            })?;
+
+        let mut state = self.lock_state().clone();
+        let state_root = self.compute_state_root(&mut state);
+        if state_root != block.state_root {
+            return Err(SequencerError::SelfCheckFailed(format!(
+                "state root mismatch at block {}: computed 0x{}, block has 0x{}",
+                block.id,
+                to_hex(&state_root),
+                to_hex(&block.state_root)
+            )));
+        }
+
+        if self.self_check_verify_proof {
+            self.verify_stored_block_proof(&**storage, &block)?;
+        }
+
+        Ok(())
+    }
+
+    /// Re-verify a stored block's proof. The public inputs include the
+    /// previous state root, so that state is recovered from the nearest
+    /// snapshot and the stored blocks after it.
+    fn verify_stored_block_proof(
+        &self,
+        storage: &dyn Storage,
+        block: &Block,
+    ) -> Result<(), SequencerError> {
+        let prover = self.prover.as_ref().ok_or_else(|| {
+            SequencerError::SelfCheckFailed(
+                "proof verification requested but no prover is configured".to_string(),
+            )
+        })?;
+
+        if block.block_proof.is_empty() {
+            return Err(SequencerError::SelfCheckFailed(format!(
+                "block {} has no proof",
+                block.id
+            )));
+        }
+
+        let mut prev_state = match block.id.checked_sub(1) {
+            Some(prev_block_id) => self.replay_state_at(storage, prev_block_id)?.0,
+            None => State::new(),
+        };
+
+        let prev_state_root = self.compute_state_root(&mut prev_state);
+
+        // Same runtime-in-a-thread approach as `generate_block_proof`
+        let prover_clone = Arc::clone(prover);
+        let block_clone = block.clone();
+        let handle = std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .map_err(|e| {
+                    ProverError::SnarkProof(format!("Failed to create runtime: {:?}", e))
+                })?;
+            runtime.block_on(prover_clone.verify_block(&block_clone, &prev_state_root))
+        });
+
+        match handle.join() {
+            Ok(Ok(true)) => Ok(()),
+            Ok(Ok(false)) => Err(SequencerError::SelfCheckFailed(format!(
+                "proof for block {} does not verify",
+                block.id
+            ))),
+            Ok(Err(ProverError::Serialization(e))) => Err(SequencerError::SelfCheckFailed(
+                format!("block {} proof is malformed: {}", block.id, e),
+            )),
+            Ok(Err(e)) => Err(SequencerError::ProverError(format!(
+                "Proof verification failed: {:?}",
+                e
+            ))),
+            Err(_) => Err(SequencerError::ProverError(
+                "Thread panicked during proof verification".to_string(),
+            )),
         }
     }
 
@@ -499,15 +1729,123 @@ impl Sequencer {
         &self,
         generate_proof: bool,
     ) -> Result<Block, SequencerError> {
+        let _build = self.build_lock.lock().unwrap_or_else(|e| e.into_inner());
         let block = self.build_block_with_proof(generate_proof)?;
         self.execute_block(block.clone())?;
         Ok(block)
     }
 
+    /// Shared handle to the live state. The sequencer clears the lock's
+    /// poison flag the next time it takes the lock, so a handle that finds
+    /// it poisoned only needs to retry later.
     pub fn get_state(&self) -> Arc<Mutex<State>> {
         Arc::clone(&self.state)
     }
 
+    /// Lock the live state, recovering it if a thread panicked while holding
+    /// the lock. Without this, one panic would poison the mutex and make
+    /// every later lock fail, taking the node down for good. Blocks are
+    /// applied under a journal, so changes a panic left behind halfway
+    /// through a block are undone, back to the last committed state.
+    fn lock_state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|poisoned| {
+            error!("state lock poisoned by a panic, rolling back to the last committed state");
+            self.state.clear_poison();
+            let mut state = poisoned.into_inner();
+            state.revert_journal();
+            state
+        })
+    }
+
+    /// Get the current read-only snapshot. It may lag the live state until
+    /// the next `refresh_read_snapshot` call.
+    pub fn get_read_snapshot(&self) -> Arc<ReadSnapshot> {
+        Arc::clone(&self.read_snapshot.read().unwrap())
+    }
+
+    /// Bring the read-only snapshot up to the last committed block. The
+    /// changes of the blocks committed since the last refresh are applied
+    /// to a copy of the current snapshot, so the live state lock is only
+    /// taken when the live state has to be copied instead.
+    pub fn refresh_read_snapshot(&self) -> Option<BlockId> {
+        let _refresh = self.read_snapshot_refresh.lock().unwrap();
+        let pending = self
+            .read_snapshot_deltas
+            .lock()
+            .unwrap()
+            .as_mut()
+            .map(std::mem::take);
+        let Some(pending) = pending else {
+            return self.copy_live_state_to_read_snapshot();
+        };
+
+        let current = self.get_read_snapshot();
+        if pending.is_empty() {
+            return current.block_id;
+        }
+        let mut state = current.state.clone();
+        let mut block_id = current.block_id;
+        for (id, delta) in &pending {
+            state.apply_delta(delta);
+            block_id = Some(*id);
+        }
+        state.rebuild_derived_indexes();
+
+        *self.read_snapshot.write().unwrap() = Arc::new(ReadSnapshot { block_id, state });
+        block_id
+    }
+
+    /// Replace the read-only snapshot with a copy of the live state. Needed
+    /// after changing the live state through `get_state` outside a block,
+    /// which `refresh_read_snapshot` does not pick up.
+    pub fn reset_read_snapshot(&self) -> Option<BlockId> {
+        let _refresh = self.read_snapshot_refresh.lock().unwrap();
+        self.copy_live_state_to_read_snapshot()
+    }
+
+    /// Caller holds `read_snapshot_refresh`
+    fn copy_live_state_to_read_snapshot(&self) -> Option<BlockId> {
+        let state = self.lock_state();
+        // Blocks commit under the state lock, so no delta can slip in
+        // between the copy and the reset
+        *self.read_snapshot_deltas.lock().unwrap() = Some(Vec::new());
+        let block_id = self.get_current_block_id().checked_sub(1);
+        let snapshot = Arc::new(ReadSnapshot {
+            block_id,
+            state: state.clone(),
+        });
+        drop(state);
+
+        *self.read_snapshot.write().unwrap() = snapshot;
+        block_id
+    }
+
+    /// Keep a committed block's changes for the next read snapshot refresh
+    fn record_read_snapshot_delta(&self, block_id: BlockId, delta: StateDelta) {
+        let mut deltas = self.read_snapshot_deltas.lock().unwrap();
+        match deltas.as_mut() {
+            Some(pending) if pending.len() < MAX_READ_SNAPSHOT_DELTAS => {
+                pending.push((block_id, delta))
+            }
+            _ => *deltas = None,
+        }
+    }
+
+    /// Merkle root of the committed state, as committed in block headers
+    pub fn current_state_root(&self) -> Result<[u8; 32], SequencerError> {
+        self.current_state_root_with_block_id()
+            .map(|(_, root)| root)
+    }
+
+    /// Last executed block id together with the state root after it,
+    /// read under one state lock so the pair is consistent
+    pub fn current_state_root_with_block_id(&self) -> Result<(BlockId, [u8; 32]), SequencerError> {
+        let mut state = self.lock_state();
+        let block_id = self.get_current_block_id().saturating_sub(1);
+        let root = self.compute_state_root(&mut state);
+        Ok((block_id, root))
+    }
+
     pub fn get_current_block_id(&self) -> BlockId {
         *self.current_block_id.lock().unwrap()
     }
@@ -516,28 +1854,250 @@ impl Sequencer {
         self.tx_queue.lock().unwrap().len()
     }
 
+    pub fn network_id(&self) -> u64 {
+        self.network_id
+    }
+
+    pub fn max_queue_size(&self) -> usize {
+        self.max_queue_size
+    }
+
+    /// How full the queue is, from 0.0 (empty) to 1.0 (full)
+    pub fn queue_pressure(&self) -> f32 {
+        self.pressure_at(self.queue_length())
+    }
+
+    /// Recommended fee for a tx of `kind` at the current queue pressure.
+    /// `size` is the tx's encoded size in bytes, or the kind's typical
+    /// size when `None`.
+    pub fn estimate_fee(&self, kind: &TxKind, size: Option<usize>) -> FeeEstimate {
+        let pressure = self.queue_pressure();
+        let throttle_min_fee = if pressure >= self.throttle_high_water_mark {
+            self.throttle_min_fee
+        } else {
+            0
+        };
+        fee::estimate_fee(
+            &self.fee_curve,
+            self.stf_config.fee.as_ref(),
+            kind,
+            size,
+            pressure,
+            throttle_min_fee,
+        )
+    }
+
+    /// Whether a tx would currently be queued regardless of its fee: the
+    /// queue has room and low-fee txs are not being throttled
+    pub fn is_accepting(&self) -> bool {
+        let queue_length = self.queue_length();
+        queue_length < self.max_queue_size
+            && (self.throttle_min_fee == 0
+                || self.pressure_at(queue_length) < self.throttle_high_water_mark)
+    }
+
+    /// Snapshot of the sequencer's counters. The state lock is held only
+    /// long enough to count accounts and deals.
+    pub fn stats(&self) -> SequencerStats {
+        let (accounts, deals) = {
+            let state = self.lock_state();
+            (state.accounts.len(), state.deals.len())
+        };
+
+        SequencerStats {
+            current_block_id: self.get_current_block_id(),
+            queue_length: self.queue_length(),
+            max_queue_size: self.max_queue_size,
+            last_snapshot_block_id: *self.last_snapshot_block_id.lock().unwrap(),
+            accounts,
+            deals,
+            has_prover: self.prover.is_some(),
+        }
+    }
+
     pub fn has_pending_txs(&self) -> bool {
         !self.tx_queue.lock().unwrap().is_empty()
     }
 
-    pub fn create_state_snapshot(&self) -> Result<(), SequencerError> {
+    /// Export a checkpoint of the current committed state for L1 anchoring.
+    /// The checkpoint is persisted and becomes the latest anchored checkpoint.
+    pub fn export_checkpoint(&self) -> Result<Checkpoint, SequencerError> {
+        let mut state = self.lock_state();
+        let block_id = self.get_current_block_id().saturating_sub(1);
+        let withdrawals_root_accumulator = *self.withdrawals_root_accumulator.lock().unwrap();
+        let checkpoint =
+            self.build_checkpoint(&mut state, block_id, withdrawals_root_accumulator)?;
+        drop(state);
+
+        self.record_checkpoint(checkpoint.clone())?;
+        Ok(checkpoint)
+    }
+
+    /// Get the last exported checkpoint, if any
+    pub fn get_last_checkpoint(&self) -> Option<Checkpoint> {
+        self.last_checkpoint.lock().unwrap().clone()
+    }
+
+    fn notify_tx_rejected(&self, tx: &Tx, error: &SequencerError) {
+        for observer in &self.observers {
+            observer.on_tx_rejected(tx, error);
+        }
+    }
+
+    fn last_checkpoint_block_id(&self) -> BlockId {
+        self.last_checkpoint
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|c| c.block_id)
+            .unwrap_or(0)
+    }
+
+    fn build_checkpoint(
+        &self,
+        state: &mut State,
+        block_id: BlockId,
+        withdrawals_root_accumulator: [u8; 32],
+    ) -> Result<Checkpoint, SequencerError> {
+        let timestamp = self.clock.now();
+
+        Ok(Checkpoint {
+            block_id,
+            state_root: self.compute_state_root(state),
+            withdrawals_root_accumulator,
+            timestamp,
+        })
+    }
+
+    fn record_checkpoint(&self, checkpoint: Checkpoint) -> Result<(), SequencerError> {
         if let Some(ref storage) = self.storage {
-            let state = self.state.lock().unwrap();
-            let block_id = *self.current_block_id.lock().unwrap();
+            storage.save_checkpoint(&checkpoint).map_err(|e| {
+                SequencerError::StorageError(format!("Failed to save checkpoint: {:?}", e))
+            })?;
+        }
 
-            let state_clone = state.clone();
-            drop(state);
+        *self.last_checkpoint.lock().unwrap() = Some(checkpoint);
+        Ok(())
+    }
 
-            storage
-                .save_state_snapshot(&state_clone, block_id)
-                .map_err(|e| {
-                    SequencerError::StorageError(format!("Failed to save state snapshot: {:?}", e))
-                })?;
+    /// Snapshot the live state at the latest stored block, so a restart
+    /// loads it without replaying any blocks. Run on shutdown; a no-op
+    /// without storage, before the first block, or when the latest block
+    /// already has a snapshot.
+    pub fn create_state_snapshot(&self) -> Result<(), SequencerError> {
+        let Some(ref storage) = self.storage else {
+            return Ok(());
+        };
+        // The state must match the latest block, so no block may land meanwhile
+        let _build = self.build_lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        let latest_block_id = storage.get_latest_block_id().map_err(|e| {
+            SequencerError::StorageError(format!("Failed to get latest block ID: {:?}", e))
+        })?;
+        let Some(block_id) = latest_block_id else {
+            return Ok(());
+        };
+        if *self.last_snapshot_block_id.lock().unwrap() == block_id {
+            return Ok(());
         }
+
+        let state_clone = self.lock_state().clone();
+        storage
+            .save_state_snapshot(&state_clone, block_id)
+            .map_err(|e| {
+                SequencerError::StorageError(format!("Failed to save state snapshot: {:?}", e))
+            })?;
+        *self.last_snapshot_block_id.lock().unwrap() = block_id;
         Ok(())
     }
 }
 
+/// Next nonce `sender` may queue: the account nonce, advanced past any txs
+/// from the sender already waiting in the queue
+fn next_sender_nonce(state: &State, queue: &Mempool, sender: Address) -> u64 {
+    let account_nonce = state
+        .get_account_by_address(sender)
+        .map(|a| a.nonce)
+        .unwrap_or(0);
+
+    queue
+        .iter()
+        .filter(|tx| tx.from == sender)
+        .map(|tx| tx.nonce.saturating_add(1))
+        .fold(account_nonce, u64::max)
+}
+
+/// Txs buffered ahead of their sender's next nonce, by sender and nonce,
+/// with when each was buffered
+type NonceBuffer = HashMap<Address, BTreeMap<u64, (Tx, Instant)>>;
+
+/// Number of txs held in `buffer` across all senders
+fn buffered_len(buffer: &NonceBuffer) -> usize {
+    buffer.values().map(BTreeMap::len).sum()
+}
+
+/// Queue `sender`'s buffered txs in nonce order for as long as they continue
+/// the sequence from `next_nonce`. Buffered txs that no longer can be
+/// queued, because their nonce is already used, are dropped.
+fn promote_buffered(
+    queue: &mut Mempool,
+    buffer: &mut NonceBuffer,
+    sender: Address,
+    mut next_nonce: u64,
+    max_queue_size: usize,
+) {
+    let Some(pending) = buffer.get_mut(&sender) else {
+        return;
+    };
+
+    pending.retain(|&nonce, _| nonce >= next_nonce);
+    while queue.len() < max_queue_size {
+        let Some((tx, _)) = pending.remove(&next_nonce) else {
+            break;
+        };
+        queue.push_back(tx);
+        next_nonce += 1;
+    }
+
+    if pending.is_empty() {
+        buffer.remove(&sender);
+    }
+}
+
+/// Id of the first block stored in `from..=to`, if any
+fn first_stored_block_id(
+    storage: &dyn Storage,
+    from: BlockId,
+    to: BlockId,
+) -> Result<Option<BlockId>, SequencerError> {
+    for block_id in from..=to {
+        match storage.get_block(block_id) {
+            Ok(Some(_)) => return Ok(Some(block_id)),
+            Ok(None) => continue,
+            Err(e) => {
+                return Err(SequencerError::StorageError(format!(
+                    "Failed to check block {}: {:?}",
+                    block_id, e
+                )));
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Fold a block's withdrawals root into the running accumulator
+fn accumulate_withdrawals_root(accumulator: &[u8; 32], withdrawals_root: &[u8; 32]) -> [u8; 32] {
+    use sha2::{Digest, Sha256};
+    let mut hasher = Sha256::new();
+    hasher.update(accumulator);
+    hasher.update(withdrawals_root);
+    hasher.finalize().into()
+}
+
 impl Default for Sequencer {
     fn default() -> Self {
         Self::new()
@@ -547,7 +2107,10 @@ impl Default for Sequencer {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use zkclear_types::{Address, Deposit, Tx, TxKind, TxPayload};
+    use std::sync::atomic::AtomicBool;
+    use zkclear_storage::StorageError;
+    use zkclear_types::test_util::unique_tx_hash;
+    use zkclear_types::{Address, Deposit, SignatureScheme, Tx, TxKind, TxPayload};
 
     fn dummy_tx(id: u64, from: Address, nonce: u64) -> Tx {
         Tx {
@@ -556,22 +2119,211 @@ mod tests {
             nonce,
             kind: TxKind::Deposit,
             payload: TxPayload::Deposit(Deposit {
-                tx_hash: [0u8; 32],
+                tx_hash: unique_tx_hash(),
                 account: from,
                 asset_id: 0,
                 amount: 100,
                 chain_id: zkclear_types::chain_ids::ETHEREUM,
             }),
-            signature: [0u8; 65],
+            fee: 0,
+            domain: 0,
+            scheme: SignatureScheme::Secp256k1Recoverable,
+            signature: vec![0u8; 65],
         }
     }
 
-    #[test]
-    fn test_submit_and_build_block() {
-        let sequencer = Sequencer::with_config(100, 10);
-        let addr = [1u8; 20];
+    fn signing_key() -> k256::ecdsa::SigningKey {
+        k256::ecdsa::SigningKey::from_bytes(&[7u8; 32].into()).unwrap()
+    }
 
-        for i in 0..5 {
+    fn address_of(key: &k256::ecdsa::SigningKey) -> Address {
+        use k256::elliptic_curve::sec1::ToEncodedPoint;
+        use sha3::Digest;
+
+        let point = k256::PublicKey::from(key.verifying_key()).to_encoded_point(false);
+        let hash = sha3::Keccak256::digest(&point.as_bytes()[1..]);
+        let mut address = [0u8; 20];
+        address.copy_from_slice(&hash[12..]);
+        address
+    }
+
+    fn signed_tx(key: &k256::ecdsa::SigningKey, nonce: u64) -> Tx {
+        signed_tx_for_domain(key, nonce, 0)
+    }
+
+    fn signed_tx_for_domain(key: &k256::ecdsa::SigningKey, nonce: u64, domain: u64) -> Tx {
+        let mut tx = Tx {
+            domain,
+            ..dummy_tx(nonce, address_of(key), nonce)
+        };
+        let (signature, recovery_id) = key.sign_prehash_recoverable(&tx.signing_hash()).unwrap();
+        tx.signature[..64].copy_from_slice(&signature.to_bytes());
+        tx.signature[64] = recovery_id.to_byte() + 27;
+        tx
+    }
+
+    #[test]
+    fn test_withdrawals_root_independent_of_tx_order() {
+        let withdraw = |from: Address, amount: u128| Tx {
+            id: 0,
+            from,
+            nonce: 1,
+            kind: TxKind::Withdraw,
+            payload: TxPayload::Withdraw(zkclear_types::Withdraw {
+                asset_id: 0,
+                amount,
+                to: from,
+                chain_id: zkclear_types::chain_ids::ETHEREUM,
+            }),
+            fee: 0,
+            domain: 0,
+            scheme: SignatureScheme::Secp256k1Recoverable,
+            signature: vec![0u8; 65],
+        };
+        let (a, b) = ([1u8; 20], [2u8; 20]);
+
+        let roots: Vec<[u8; 32]> = [[a, b], [b, a]]
+            .into_iter()
+            .map(|order| {
+                let sequencer = Sequencer::with_config(100, 10);
+                for from in [a, b] {
+                    sequencer
+                        .submit_tx_with_validation(dummy_tx(0, from, 0), false)
+                        .unwrap();
+                }
+                sequencer.build_and_execute_block().unwrap();
+
+                for from in order {
+                    sequencer
+                        .submit_tx_with_validation(withdraw(from, 40), false)
+                        .unwrap();
+                }
+                sequencer.build_block().unwrap().withdrawals_root
+            })
+            .collect();
+
+        assert_eq!(roots[0], roots[1]);
+        assert_ne!(roots[0], [0u8; 32]);
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_execute_block_emits_block_executed_event() {
+        let sequencer = Sequencer::with_config(100, 10);
+        for nonce in 0..2 {
+            sequencer
+                .submit_tx_with_validation(dummy_tx(nonce, [1u8; 20], nonce), false)
+                .unwrap();
+        }
+        sequencer.build_and_execute_block().unwrap();
+
+        assert!(logs_contain("block_executed block_id=0 tx_count=2"));
+    }
+
+    #[test]
+    fn test_poisoned_state_lock_rolls_back_unfinished_block() {
+        let sequencer = Sequencer::with_config(100, 10);
+        sequencer
+            .submit_tx_with_validation(dummy_tx(0, [1u8; 20], 0), false)
+            .unwrap();
+        sequencer.build_and_execute_block().unwrap();
+        let root = sequencer.current_state_root().unwrap();
+
+        let state = sequencer.get_state();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let mut state = state.lock().unwrap();
+            state.begin_journal();
+            state.get_or_create_account_by_owner([2u8; 20]);
+            panic!("panic while applying a block");
+        }));
+        assert!(result.is_err());
+
+        assert_eq!(sequencer.current_state_root().unwrap(), root);
+        assert!(sequencer
+            .lock_state()
+            .get_account_by_address([2u8; 20])
+            .is_none());
+    }
+
+    #[test]
+    fn test_block_building_recovers_from_poisoned_state_lock() {
+        let sequencer = Sequencer::with_config(100, 10);
+        let state = sequencer.get_state();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _guard = state.lock().unwrap();
+            panic!("panic while holding the state lock");
+        }));
+        assert!(result.is_err());
+        assert!(state.is_poisoned());
+
+        let addr = [1u8; 20];
+        sequencer
+            .submit_tx_with_validation(dummy_tx(0, addr, 0), false)
+            .unwrap();
+        let block = sequencer.build_and_execute_block().unwrap();
+        assert_eq!(block.transactions.len(), 1);
+        assert_eq!(sequencer.get_current_block_id(), 1);
+
+        // The poison flag is cleared, so plain handles work again
+        assert!(!state.is_poisoned());
+        assert_eq!(
+            state
+                .lock()
+                .unwrap()
+                .get_account_by_address(addr)
+                .unwrap()
+                .nonce,
+            1
+        );
+    }
+
+    #[test]
+    fn test_concurrent_block_building_is_sequential() {
+        let sequencer = Arc::new(Sequencer::with_config(1000, 5));
+        for i in 0..100 {
+            sequencer
+                .submit_tx_with_validation(dummy_tx(i, [(i % 10) as u8 + 1; 20], i / 10), false)
+                .unwrap();
+        }
+
+        let builders: Vec<_> = (0..2)
+            .map(|_| {
+                let sequencer = sequencer.clone();
+                std::thread::spawn(move || {
+                    let mut blocks = Vec::new();
+                    loop {
+                        match sequencer.build_and_execute_block() {
+                            Ok(block) => blocks.push(block),
+                            Err(SequencerError::NoTransactions) => return blocks,
+                            Err(e) => panic!("block building failed: {:?}", e),
+                        }
+                    }
+                })
+            })
+            .collect();
+        let mut blocks: Vec<Block> = builders
+            .into_iter()
+            .flat_map(|builder| builder.join().unwrap())
+            .collect();
+        blocks.sort_by_key(|block| block.id);
+
+        let ids: Vec<BlockId> = blocks.iter().map(|block| block.id).collect();
+        assert_eq!(ids, (0..blocks.len() as BlockId).collect::<Vec<_>>());
+        let mut tx_ids: Vec<u64> = blocks
+            .iter()
+            .flat_map(|block| block.transactions.iter().map(|tx| tx.id))
+            .collect();
+        tx_ids.sort();
+        assert_eq!(tx_ids, (0..100).collect::<Vec<_>>());
+        assert_eq!(sequencer.get_current_block_id(), blocks.len() as BlockId);
+    }
+
+    #[test]
+    fn test_submit_and_build_block() {
+        let sequencer = Sequencer::with_config(100, 10);
+        let addr = [1u8; 20];
+
+        for i in 0..5 {
             sequencer
                 .submit_tx_with_validation(dummy_tx(i, addr, i), false)
                 .unwrap();
@@ -599,6 +2351,49 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_build_threshold_triggers_block_before_timer() {
+        let sequencer = Sequencer::new().with_build_threshold(3);
+        let addr = [1u8; 20];
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            // A timer that won't fire during the test
+            let mut timer = tokio::time::interval(Duration::from_secs(3600));
+            timer.tick().await;
+
+            for i in 0..2 {
+                sequencer
+                    .submit_tx_with_validation(dummy_tx(i, addr, i), false)
+                    .unwrap();
+            }
+            let wait = Duration::from_millis(50);
+            assert!(
+                tokio::time::timeout(wait, sequencer.build_threshold_reached())
+                    .await
+                    .is_err()
+            );
+
+            sequencer
+                .submit_tx_with_validation(dummy_tx(2, addr, 2), false)
+                .unwrap();
+            let triggered = async {
+                tokio::select! {
+                    _ = timer.tick() => false,
+                    _ = sequencer.build_threshold_reached() => true,
+                }
+            };
+            assert!(tokio::time::timeout(wait, triggered).await.unwrap());
+        });
+
+        let block = sequencer.build_and_execute_block().unwrap();
+        assert_eq!(block.transactions.len(), 3);
+        assert_eq!(sequencer.queue_length(), 0);
+    }
+
     #[test]
     fn test_execute_block() {
         let sequencer = Sequencer::new();
@@ -626,4 +2421,1819 @@ mod tests {
         assert_eq!(block.id, 0);
         assert_eq!(sequencer.get_current_block_id(), 1);
     }
+
+    #[test]
+    fn test_build_block_skips_same_nonce_conflict() {
+        let sequencer = Sequencer::with_config(100, 10);
+        let addr = [1u8; 20];
+
+        sequencer
+            .submit_tx_with_validation(dummy_tx(0, addr, 0), false)
+            .unwrap();
+        sequencer
+            .submit_tx_with_validation(dummy_tx(1, addr, 0), false)
+            .unwrap();
+        sequencer
+            .submit_tx_with_validation(dummy_tx(2, addr, 1), false)
+            .unwrap();
+
+        let block = sequencer.build_block().unwrap();
+        assert_eq!(block.transactions.len(), 2);
+        assert_eq!(block.transactions[0].id, 0);
+        assert_eq!(block.transactions[1].nonce, 1);
+
+        sequencer.execute_block(block).unwrap();
+        let state = sequencer.get_state();
+        let account = state.lock().unwrap().get_account_by_address(addr).cloned();
+        assert_eq!(account.unwrap().nonce, 2);
+    }
+
+    #[test]
+    fn test_build_block_defers_future_nonce() {
+        let sequencer = Sequencer::with_config(100, 10);
+        let addr = [1u8; 20];
+
+        sequencer
+            .submit_tx_with_validation(dummy_tx(0, addr, 1), false)
+            .unwrap();
+        sequencer
+            .submit_tx_with_validation(dummy_tx(1, addr, 0), false)
+            .unwrap();
+
+        let block = sequencer.build_and_execute_block().unwrap();
+        assert_eq!(block.transactions.len(), 1);
+        assert_eq!(block.transactions[0].nonce, 0);
+        assert_eq!(sequencer.queue_length(), 1);
+
+        let block = sequencer.build_and_execute_block().unwrap();
+        assert_eq!(block.transactions[0].nonce, 1);
+    }
+
+    #[test]
+    fn test_out_of_order_nonces_buffered_until_gap_filled() {
+        let sequencer = Sequencer::with_config(100, 10);
+        let key = signing_key();
+        let addr = address_of(&key);
+
+        sequencer.submit_tx(signed_tx(&key, 2)).unwrap();
+        sequencer.submit_tx(signed_tx(&key, 1)).unwrap();
+        assert_eq!(sequencer.buffered_tx_count(addr), 2);
+        assert_eq!(sequencer.queue_length(), 0);
+
+        sequencer.submit_tx(signed_tx(&key, 0)).unwrap();
+        assert_eq!(sequencer.buffered_tx_count(addr), 0);
+        assert_eq!(sequencer.queue_length(), 3);
+
+        // Buffered while nonce 3 is still in a block being built, then
+        // promoted once that block has executed
+        sequencer.submit_tx(signed_tx(&key, 3)).unwrap();
+        let block = sequencer.build_block().unwrap();
+        sequencer.submit_tx(signed_tx(&key, 5)).unwrap();
+        sequencer.submit_tx(signed_tx(&key, 4)).unwrap();
+        assert_eq!(sequencer.buffered_tx_count(addr), 2);
+        let nonces: Vec<u64> = block.transactions.iter().map(|tx| tx.nonce).collect();
+        assert_eq!(nonces, vec![0, 1, 2, 3]);
+
+        sequencer.execute_block(block).unwrap();
+        assert_eq!(sequencer.buffered_tx_count(addr), 0);
+
+        let block = sequencer.build_and_execute_block().unwrap();
+        let nonces: Vec<u64> = block.transactions.iter().map(|tx| tx.nonce).collect();
+        assert_eq!(nonces, vec![4, 5]);
+        let state = sequencer.get_state();
+        let nonce = state
+            .lock()
+            .unwrap()
+            .get_account_by_address(addr)
+            .unwrap()
+            .nonce;
+        assert_eq!(nonce, 6);
+    }
+
+    #[test]
+    fn test_nonce_gap_beyond_limit_rejected() {
+        let sequencer = Sequencer::with_config(100, 10).with_max_nonce_gap(3);
+        let key = signing_key();
+        let addr = address_of(&key);
+
+        assert!(matches!(
+            sequencer.submit_tx(signed_tx(&key, 4)),
+            Err(SequencerError::InvalidNonce)
+        ));
+        sequencer.submit_tx(signed_tx(&key, 3)).unwrap();
+        assert_eq!(sequencer.buffered_tx_count(addr), 1);
+
+        let strict = Sequencer::with_config(100, 10).with_max_nonce_gap(0);
+        assert!(matches!(
+            strict.submit_tx(signed_tx(&key, 1)),
+            Err(SequencerError::InvalidNonce)
+        ));
+        assert_eq!(strict.buffered_tx_count(addr), 0);
+    }
+
+    #[test]
+    fn test_buffered_txs_count_against_queue_size() {
+        let sequencer = Sequencer::with_config(2, 10);
+        let key = signing_key();
+
+        sequencer.submit_tx(signed_tx(&key, 1)).unwrap();
+        sequencer.submit_tx(signed_tx(&key, 2)).unwrap();
+        assert_eq!(sequencer.queue_length(), 0);
+        assert!(matches!(
+            sequencer.submit_tx(signed_tx(&key, 3)),
+            Err(SequencerError::QueueFull)
+        ));
+        assert!(matches!(
+            sequencer.submit_tx_with_validation(dummy_tx(0, [1u8; 20], 0), false),
+            Err(SequencerError::QueueFull)
+        ));
+    }
+
+    #[test]
+    fn test_buffered_txs_past_ttl_evicted() {
+        let sequencer =
+            Sequencer::with_config(100, 10).with_nonce_buffer_ttl(Duration::from_millis(20));
+        let key = signing_key();
+        let addr = address_of(&key);
+
+        sequencer.submit_tx(signed_tx(&key, 2)).unwrap();
+        assert_eq!(sequencer.evict_stale_txs(), 0);
+
+        std::thread::sleep(Duration::from_millis(40));
+        sequencer.submit_tx(signed_tx(&key, 1)).unwrap();
+        assert_eq!(sequencer.evict_stale_txs(), 1);
+        assert_eq!(sequencer.buffered_tx_count(addr), 1);
+
+        // The gap closes for the tx that is still buffered
+        sequencer.submit_tx(signed_tx(&key, 0)).unwrap();
+        assert_eq!(sequencer.queue_length(), 2);
+        assert_eq!(sequencer.buffered_tx_count(addr), 0);
+    }
+
+    #[test]
+    fn test_build_block_nonce_conflict_fails_when_not_skipping() {
+        let sequencer = Sequencer::with_config(100, 10).with_skip_nonce_conflicts(false);
+        let addr = [1u8; 20];
+
+        sequencer
+            .submit_tx_with_validation(dummy_tx(0, addr, 0), false)
+            .unwrap();
+        sequencer
+            .submit_tx_with_validation(dummy_tx(1, addr, 0), false)
+            .unwrap();
+
+        assert!(matches!(
+            sequencer.build_block(),
+            Err(SequencerError::ExecutionFailed(StfError::InvalidNonce))
+        ));
+    }
+
+    #[test]
+    fn test_stale_nonce_tx_evicted_instead_of_failing_builds() {
+        let sequencer = Sequencer::with_config(100, 10).with_skip_nonce_conflicts(false);
+        let addr = [1u8; 20];
+
+        sequencer
+            .submit_tx_with_validation(dummy_tx(0, addr, 0), false)
+            .unwrap();
+
+        // A competing nonce-0 tx lands in a block built elsewhere
+        sequencer
+            .execute_block(Block {
+                id: sequencer.get_current_block_id(),
+                transactions: vec![dummy_tx(1, addr, 0)],
+                timestamp: 1000,
+                state_root: [0u8; 32],
+                withdrawals_root: [0u8; 32],
+                block_proof: Vec::new(),
+            })
+            .unwrap();
+
+        assert!(matches!(
+            sequencer.build_block(),
+            Err(SequencerError::NoTransactions)
+        ));
+        assert_eq!(sequencer.queue_length(), 0);
+        assert!(sequencer
+            .metrics()
+            .encode(0, 0)
+            .contains("zkclear_tx_evicted_total{reason=\"stale_nonce\"} 1"));
+
+        sequencer
+            .submit_tx_with_validation(dummy_tx(2, addr, 1), false)
+            .unwrap();
+        let block = sequencer.build_block().unwrap();
+        assert_eq!(block.transactions[0].id, 2);
+    }
+
+    #[test]
+    fn test_failed_block_execution_leaves_state_unchanged() {
+        let sequencer = Sequencer::new();
+        let addr = [1u8; 20];
+        let root = sequencer.get_state().lock().unwrap().root();
+
+        // The first deposit applies before the second fails on its nonce
+        let result = sequencer.execute_block(Block {
+            id: sequencer.get_current_block_id(),
+            transactions: vec![dummy_tx(0, addr, 0), dummy_tx(1, addr, 0)],
+            timestamp: 1000,
+            state_root: [0u8; 32],
+            withdrawals_root: [0u8; 32],
+            block_proof: Vec::new(),
+        });
+        assert!(matches!(
+            result,
+            Err(SequencerError::ExecutionFailed(StfError::InvalidNonce))
+        ));
+
+        let state = sequencer.get_state();
+        let mut state = state.lock().unwrap();
+        assert_eq!(state.root(), root);
+        assert!(state.get_account_by_address(addr).is_none());
+        assert!(state.processed_deposits.is_empty());
+    }
+
+    #[test]
+    fn test_txs_past_mempool_ttl_evicted() {
+        let sequencer = Sequencer::with_config(100, 10).with_mempool_ttl(Duration::from_millis(20));
+
+        sequencer
+            .submit_tx_with_validation(dummy_tx(0, [1u8; 20], 0), false)
+            .unwrap();
+        assert_eq!(sequencer.evict_stale_txs(), 0);
+
+        std::thread::sleep(Duration::from_millis(40));
+        sequencer
+            .submit_tx_with_validation(dummy_tx(1, [2u8; 20], 0), false)
+            .unwrap();
+        assert_eq!(sequencer.evict_stale_txs(), 1);
+
+        let block = sequencer.build_block().unwrap();
+        assert_eq!(block.transactions.len(), 1);
+        assert_eq!(block.transactions[0].id, 1);
+    }
+
+    #[test]
+    fn test_withdrawals_accumulator_restored_from_storage() {
+        let storage: Arc<dyn Storage> = Arc::new(zkclear_storage::InMemoryStorage::new());
+        let sequencer = Sequencer::with_storage_arc(storage.clone()).unwrap();
+        let addr = [1u8; 20];
+        let mut accumulators = Vec::new();
+        for nonce in 0..3 {
+            sequencer
+                .submit_tx_with_validation(dummy_tx(nonce, addr, nonce), false)
+                .unwrap();
+            let block = sequencer.build_and_execute_block().unwrap();
+            let accumulator = storage.get_withdrawals_accumulator(block.id).unwrap();
+            assert_eq!(
+                accumulator,
+                Some(*sequencer.withdrawals_root_accumulator.lock().unwrap())
+            );
+            accumulators.push(accumulator.unwrap());
+        }
+
+        let restarted = Sequencer::with_storage_arc(storage.clone()).unwrap();
+        assert_eq!(
+            *restarted.withdrawals_root_accumulator.lock().unwrap(),
+            accumulators[2]
+        );
+
+        restarted.rollback_to(2).unwrap();
+        assert_eq!(
+            restarted
+                .export_checkpoint()
+                .unwrap()
+                .withdrawals_root_accumulator,
+            accumulators[1]
+        );
+        assert_eq!(storage.get_withdrawals_accumulator(3).unwrap(), None);
+    }
+
+    #[test]
+    fn test_export_checkpoint_tracks_state_root() {
+        let storage: Arc<dyn Storage> = Arc::new(zkclear_storage::InMemoryStorage::new());
+        let sequencer = Sequencer::with_storage_arc(storage.clone()).unwrap();
+        let addr = [1u8; 20];
+
+        sequencer
+            .submit_tx_with_validation(dummy_tx(0, addr, 0), false)
+            .unwrap();
+        let block = sequencer.build_and_execute_block().unwrap();
+
+        let first = sequencer.export_checkpoint().unwrap();
+        let expected_root = {
+            let state = sequencer.get_state();
+            let mut state = state.lock().unwrap();
+            sequencer.compute_state_root(&mut state)
+        };
+        assert_eq!(first.block_id, block.id);
+        assert_eq!(first.state_root, expected_root);
+        assert_eq!(first.state_root, block.state_root);
+        assert_eq!(
+            storage.get_latest_checkpoint().unwrap(),
+            Some(first.clone())
+        );
+
+        sequencer
+            .submit_tx_with_validation(dummy_tx(1, addr, 1), false)
+            .unwrap();
+        let block = sequencer.build_and_execute_block().unwrap();
+
+        let second = sequencer.export_checkpoint().unwrap();
+        assert_eq!(second.block_id, block.id);
+        assert!(second.block_id > first.block_id);
+        assert_eq!(second.state_root, block.state_root);
+        assert_ne!(second.state_root, first.state_root);
+        assert_ne!(
+            second.withdrawals_root_accumulator,
+            first.withdrawals_root_accumulator
+        );
+        assert_eq!(sequencer.get_last_checkpoint(), Some(second));
+    }
+
+    #[test]
+    fn test_block_timestamps_come_from_clock() {
+        let clock = Arc::new(clock::MockClock::new(1_700_000_000));
+        let sequencer = Sequencer::new().with_clock(clock.clone());
+        let addr = [1u8; 20];
+
+        sequencer
+            .submit_tx_with_validation(dummy_tx(0, addr, 0), false)
+            .unwrap();
+        let first = sequencer.build_and_execute_block().unwrap();
+        assert_eq!(first.timestamp, 1_700_000_000);
+
+        clock.advance(12);
+        sequencer
+            .submit_tx_with_validation(dummy_tx(1, addr, 1), false)
+            .unwrap();
+        let second = sequencer.build_and_execute_block().unwrap();
+        assert_eq!(second.timestamp, 1_700_000_012);
+        assert_eq!(sequencer.last_block_timestamp(), 1_700_000_012);
+        assert_eq!(
+            sequencer.export_checkpoint().unwrap().timestamp,
+            1_700_000_012
+        );
+    }
+
+    fn two_account_genesis() -> GenesisConfig {
+        use genesis::{GenesisAccount, GenesisBalance};
+
+        let balance = |asset_id, amount| GenesisBalance {
+            asset_id,
+            chain_id: zkclear_types::chain_ids::ETHEREUM,
+            amount,
+        };
+        GenesisConfig {
+            assets: Vec::new(),
+            accounts: vec![
+                GenesisAccount {
+                    owner: [1u8; 20],
+                    balances: vec![balance(0, 1_000), balance(1, 50_000)],
+                },
+                GenesisAccount {
+                    owner: [2u8; 20],
+                    balances: vec![balance(1, 7)],
+                },
+            ],
+            fee_collector: None,
+        }
+    }
+
+    #[test]
+    fn test_genesis_state_is_reproducible() {
+        let first = Sequencer::new()
+            .with_genesis(two_account_genesis())
+            .unwrap();
+        let second = Sequencer::new()
+            .with_genesis(two_account_genesis())
+            .unwrap();
+
+        {
+            let state_handle = first.get_state();
+            let state = state_handle.lock().unwrap();
+            let balance = |owner, asset_id| {
+                state
+                    .get_account_by_address(owner)
+                    .unwrap()
+                    .balance_of(asset_id, zkclear_types::chain_ids::ETHEREUM)
+            };
+            assert_eq!(balance([1u8; 20], 0), 1_000);
+            assert_eq!(balance([1u8; 20], 1), 50_000);
+            assert_eq!(balance([2u8; 20], 1), 7);
+        }
+        let root = first.current_state_root().unwrap();
+        assert_eq!(root, second.current_state_root().unwrap());
+        assert_ne!(root, Sequencer::new().current_state_root().unwrap());
+
+        let mut duplicated = two_account_genesis();
+        duplicated.accounts[1].owner = [1u8; 20];
+        assert!(matches!(
+            Sequencer::new().with_genesis(duplicated),
+            Err(SequencerError::InvalidGenesis(_))
+        ));
+    }
+
+    #[test]
+    fn test_genesis_persisted_and_not_reapplied_on_restart() {
+        let storage: Arc<dyn Storage> = Arc::new(zkclear_storage::InMemoryStorage::new());
+        let sequencer = Sequencer::with_storage_arc(storage.clone())
+            .unwrap()
+            .with_genesis(two_account_genesis())
+            .unwrap();
+        let genesis_root = sequencer.current_state_root().unwrap();
+        assert_eq!(storage.get_latest_state_snapshot().unwrap().unwrap().1, 0);
+
+        sequencer
+            .submit_tx_with_validation(dummy_tx(0, [3u8; 20], 0), false)
+            .unwrap();
+        sequencer.build_and_execute_block().unwrap();
+        let root = sequencer.current_state_root().unwrap();
+        assert_ne!(root, genesis_root);
+
+        let restarted = Sequencer::with_storage_arc(storage)
+            .unwrap()
+            .with_genesis(two_account_genesis())
+            .unwrap();
+        assert_eq!(restarted.current_state_root().unwrap(), root);
+    }
+
+    #[test]
+    fn test_restart_replays_under_configured_stf_and_hash_algo() {
+        let storage: Arc<dyn Storage> = Arc::new(zkclear_storage::InMemoryStorage::new());
+        let (watcher, user) = ([9u8; 20], [3u8; 20]);
+        let config = StfConfig {
+            deposit_watcher: Some(watcher),
+            ..StfConfig::default()
+        };
+        let sequencer = Sequencer::with_storage_and_config(
+            storage.clone(),
+            config.clone(),
+            HashAlgo::Keccak256,
+        )
+        .unwrap();
+
+        // The watcher credits another account, which the default config rejects
+        let mut deposit = dummy_tx(0, watcher, 0);
+        if let TxPayload::Deposit(ref mut d) = deposit.payload {
+            d.account = user;
+        }
+        sequencer.submit_tx_with_validation(deposit, false).unwrap();
+        sequencer.build_and_execute_block().unwrap();
+        let root = sequencer.current_state_root().unwrap();
+
+        let restarted =
+            Sequencer::with_storage_and_config(storage.clone(), config, HashAlgo::Keccak256)
+                .unwrap();
+        assert_eq!(restarted.current_state_root().unwrap(), root);
+        assert_eq!(
+            restarted
+                .get_state()
+                .lock()
+                .unwrap()
+                .get_account_by_address(user)
+                .unwrap()
+                .balance_of(0, zkclear_types::chain_ids::ETHEREUM),
+            100
+        );
+
+        let defaulted = Sequencer::with_storage_arc(storage).and_then(|s| s.current_state_root());
+        assert!(!matches!(defaulted, Ok(r) if r == root));
+    }
+
+    #[test]
+    fn test_genesis_fee_collector_and_assets_persist_across_restart() {
+        let storage: Arc<dyn Storage> = Arc::new(zkclear_storage::InMemoryStorage::new());
+        let mut genesis = two_account_genesis();
+        genesis.fee_collector = Some([8u8; 20]);
+        genesis.assets = vec![zkclear_types::Asset {
+            id: 1,
+            symbol: "USDC".to_string(),
+            decimals: 6,
+            chain_id: zkclear_types::chain_ids::ETHEREUM,
+            contract_address: None,
+            is_wrapped: false,
+            original_chain_id: None,
+        }];
+        let sequencer = Sequencer::with_storage_arc(storage.clone())
+            .unwrap()
+            .with_genesis(genesis)
+            .unwrap();
+        sequencer
+            .submit_tx_with_validation(dummy_tx(0, [3u8; 20], 0), false)
+            .unwrap();
+        sequencer.build_and_execute_block().unwrap();
+
+        let restarted = Sequencer::with_storage_arc(storage).unwrap();
+        let state_handle = restarted.get_state();
+        let state = state_handle.lock().unwrap();
+        assert_eq!(state.fee_collector, [8u8; 20]);
+        assert_eq!(state.assets.len(), 1);
+    }
+
+    #[test]
+    fn test_imported_account_snapshotted_and_rejected_after_first_block() {
+        let owner = [1u8; 20];
+        let source = Sequencer::new();
+        source
+            .submit_tx_with_validation(dummy_tx(0, owner, 0), false)
+            .unwrap();
+        source.build_and_execute_block().unwrap();
+        let export = source.lock_state().export_account(owner).unwrap();
+
+        let storage: Arc<dyn Storage> = Arc::new(zkclear_storage::InMemoryStorage::new());
+        let target = Sequencer::with_storage_arc(storage.clone()).unwrap();
+        target.import_account(owner, export.clone()).unwrap();
+        assert_eq!(storage.get_latest_state_snapshot().unwrap().unwrap().1, 0);
+
+        let restarted = Sequencer::with_storage_arc(storage).unwrap();
+        assert_eq!(
+            restarted.lock_state().export_account(owner).unwrap(),
+            export
+        );
+
+        restarted
+            .submit_tx_with_validation(dummy_tx(1, owner, 1), false)
+            .unwrap();
+        restarted.build_and_execute_block().unwrap();
+        assert!(matches!(
+            restarted.import_account(owner, export),
+            Err(SequencerError::ChainStarted)
+        ));
+    }
+
+    #[test]
+    fn test_stats_reflect_queue_and_state() {
+        let sequencer = Sequencer::with_config(100, 10);
+        let (a, b) = ([1u8; 20], [2u8; 20]);
+
+        sequencer
+            .submit_tx_with_validation(dummy_tx(0, a, 0), false)
+            .unwrap();
+        sequencer
+            .submit_tx_with_validation(dummy_tx(1, b, 0), false)
+            .unwrap();
+        let stats = sequencer.stats();
+        assert_eq!(stats.queue_length, 2);
+        assert_eq!(stats.max_queue_size, 100);
+        assert_eq!(stats.current_block_id, 0);
+        assert_eq!(stats.accounts, 0);
+        assert!(!stats.has_prover);
+
+        sequencer.build_and_execute_block().unwrap();
+        sequencer
+            .submit_tx_with_validation(dummy_tx(2, a, 1), false)
+            .unwrap();
+        assert_eq!(
+            sequencer.stats(),
+            SequencerStats {
+                current_block_id: 1,
+                queue_length: 1,
+                max_queue_size: 100,
+                last_snapshot_block_id: 0,
+                accounts: 2,
+                deals: 0,
+                has_prover: false,
+            }
+        );
+    }
+
+    #[test]
+    fn test_read_snapshot_lags_until_refresh() {
+        let sequencer = Sequencer::new();
+        let addr = [1u8; 20];
+
+        sequencer
+            .submit_tx_with_validation(dummy_tx(0, addr, 0), false)
+            .unwrap();
+        let block = sequencer.build_and_execute_block().unwrap();
+
+        let stale = sequencer.get_read_snapshot();
+        assert_eq!(stale.block_id, None);
+        assert!(stale.state.accounts.is_empty());
+
+        assert_eq!(sequencer.refresh_read_snapshot(), Some(block.id));
+        let fresh = sequencer.get_read_snapshot();
+        assert_eq!(fresh.block_id, Some(block.id));
+        assert_eq!(
+            fresh.state.accounts.len(),
+            sequencer.get_state().lock().unwrap().accounts.len()
+        );
+        // Previously handed-out snapshots are unaffected by the refresh
+        assert!(stale.state.accounts.is_empty());
+    }
+
+    #[test]
+    fn test_read_snapshot_refresh_applies_block_deltas() {
+        let sequencer = Sequencer::new();
+        sequencer
+            .submit_tx_with_validation(dummy_tx(0, [1u8; 20], 0), false)
+            .unwrap();
+        sequencer.build_and_execute_block().unwrap();
+        sequencer.refresh_read_snapshot();
+
+        let mut last = None;
+        for (id, from) in [(1, [1u8; 20]), (2, [2u8; 20]), (3, [3u8; 20])] {
+            let nonce = u64::from(from == [1u8; 20]);
+            sequencer
+                .submit_tx_with_validation(dummy_tx(id, from, nonce), false)
+                .unwrap();
+            last = Some(sequencer.build_and_execute_block().unwrap().id);
+        }
+
+        assert_eq!(sequencer.refresh_read_snapshot(), last);
+        let snapshot = sequencer.get_read_snapshot();
+        let mut copy = snapshot.state.clone();
+        let mut live = sequencer.get_state().lock().unwrap().clone();
+        assert_eq!(copy.root(), live.root());
+        assert_eq!(copy.account_index, live.account_index);
+        assert_eq!(copy.next_account_id, live.next_account_id);
+        assert_eq!(copy.processed_deposits, live.processed_deposits);
+
+        // Nothing new to apply, so the snapshot is kept as it is
+        assert_eq!(sequencer.refresh_read_snapshot(), last);
+        assert!(Arc::ptr_eq(&snapshot, &sequencer.get_read_snapshot()));
+    }
+
+    #[test]
+    fn test_tx_for_other_domain_rejected() {
+        let key = signing_key();
+        let network_a = Sequencer::new().with_network_id(1);
+        let network_b = Sequencer::new().with_network_id(2);
+
+        let tx = signed_tx_for_domain(&key, 0, 1);
+        assert!(matches!(
+            network_b.submit_tx_with_validation(tx.clone(), true),
+            Err(SequencerError::WrongDomain)
+        ));
+        network_a
+            .submit_tx_with_validation(tx.clone(), true)
+            .unwrap();
+
+        // The domain is signed, so relabelling the tx breaks its signature
+        let relabelled = Tx { domain: 2, ..tx };
+        assert!(matches!(
+            network_b.submit_tx_with_validation(relabelled, true),
+            Err(SequencerError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn test_tx_size_limit_applies_to_every_submission() {
+        let key = signing_key();
+        let tx = signed_tx_for_domain(&key, 0, 0);
+        let size = security::encoded_tx_size(&tx);
+        let limited = |max_tx_size| {
+            Sequencer::new().with_tx_limits(TxLimits {
+                max_tx_size,
+                ..Default::default()
+            })
+        };
+
+        limited(size).submit_tx(tx.clone()).unwrap();
+        assert!(matches!(
+            limited(size - 1).submit_tx(tx.clone()),
+            Err(SequencerError::TxTooLarge)
+        ));
+        // Unvalidated submissions, as made by the API, are limited too
+        assert!(matches!(
+            limited(size - 1).submit_tx_with_validation(tx, false),
+            Err(SequencerError::TxTooLarge)
+        ));
+    }
+
+    #[test]
+    fn test_tx_limits_external_ref_shared_with_stf() {
+        let sequencer = Sequencer::new().with_tx_limits(TxLimits {
+            max_external_ref_len: 4,
+            ..Default::default()
+        });
+        assert_eq!(sequencer.stf_config().max_external_ref_len, 4);
+
+        let deal_tx = |nonce, external_ref: &str| Tx {
+            kind: TxKind::CreateDeal,
+            payload: TxPayload::CreateDeal(zkclear_types::CreateDeal {
+                deal_id: nonce,
+                visibility: zkclear_types::DealVisibility::Public,
+                taker: None,
+                asset_base: 0,
+                asset_quote: 1,
+                chain_id_base: zkclear_types::chain_ids::ETHEREUM,
+                chain_id_quote: zkclear_types::chain_ids::ETHEREUM,
+                amount_base: 1,
+                price_quote_per_base: 1,
+                expires_at: None,
+                external_ref: Some(external_ref.to_string()),
+            }),
+            ..dummy_tx(nonce, [1u8; 20], nonce)
+        };
+        sequencer
+            .submit_tx_with_validation(deal_tx(0, "abcd"), false)
+            .unwrap();
+        assert!(matches!(
+            sequencer.submit_tx_with_validation(deal_tx(1, "abcde"), false),
+            Err(SequencerError::TxTooLarge)
+        ));
+
+        // A later STF config brings its own limit along
+        let sequencer = sequencer.with_stf_config(StfConfig {
+            max_external_ref_len: 8,
+            ..Default::default()
+        });
+        assert_eq!(sequencer.tx_limits().max_external_ref_len, 8);
+    }
+
+    #[test]
+    fn test_disabled_tx_kind_rejected_on_submit() {
+        let sequencer = Sequencer::new().with_stf_config(StfConfig {
+            enabled_tx_kinds: Some([TxKind::Deposit, TxKind::Withdraw].into_iter().collect()),
+            ..Default::default()
+        });
+        let addr = [1u8; 20];
+
+        let mut create_deal = dummy_tx(1, addr, 1);
+        create_deal.kind = TxKind::CreateDeal;
+        create_deal.payload = TxPayload::CreateDeal(zkclear_types::CreateDeal {
+            deal_id: 1,
+            visibility: zkclear_types::DealVisibility::Public,
+            taker: None,
+            asset_base: 0,
+            asset_quote: 1,
+            chain_id_base: zkclear_types::chain_ids::ETHEREUM,
+            chain_id_quote: zkclear_types::chain_ids::ETHEREUM,
+            amount_base: 10,
+            price_quote_per_base: 1,
+            expires_at: None,
+            external_ref: None,
+        });
+        let mut relabelled = create_deal.clone();
+        relabelled.kind = TxKind::Deposit;
+        assert!(matches!(
+            sequencer.submit_tx_with_validation(create_deal, false),
+            Err(SequencerError::TxKindDisabled)
+        ));
+        assert!(matches!(
+            sequencer.submit_tx_with_validation(relabelled, false),
+            Err(SequencerError::ValidationFailed)
+        ));
+        assert_eq!(sequencer.queue_length(), 0);
+
+        sequencer
+            .submit_tx_with_validation(dummy_tx(0, addr, 0), false)
+            .unwrap();
+        sequencer.build_and_execute_block().unwrap();
+    }
+
+    #[test]
+    fn test_block_saves_only_the_deals_it_changed() {
+        let storage = Arc::new(zkclear_storage::InMemoryStorage::new());
+        let sequencer = Sequencer::with_storage_arc(storage.clone()).unwrap();
+        let addr = [1u8; 20];
+
+        let mut create_deal = dummy_tx(1, addr, 1);
+        create_deal.kind = TxKind::CreateDeal;
+        create_deal.payload = TxPayload::CreateDeal(zkclear_types::CreateDeal {
+            deal_id: 1,
+            visibility: zkclear_types::DealVisibility::Public,
+            taker: None,
+            asset_base: 0,
+            asset_quote: 1,
+            chain_id_base: zkclear_types::chain_ids::ETHEREUM,
+            chain_id_quote: zkclear_types::chain_ids::ETHEREUM,
+            amount_base: 10,
+            price_quote_per_base: 1,
+            expires_at: None,
+            external_ref: None,
+        });
+        sequencer
+            .submit_tx_with_validation(dummy_tx(0, addr, 0), false)
+            .unwrap();
+        sequencer
+            .submit_tx_with_validation(create_deal, false)
+            .unwrap();
+        sequencer.build_and_execute_block().unwrap();
+        let deal = storage.get_deal(1).unwrap().unwrap();
+
+        // A marker only a rewrite of the deal would clear
+        let mut marked = deal.clone();
+        marked.external_ref = Some("marker".to_string());
+        storage.save_deal(&marked).unwrap();
+
+        sequencer
+            .submit_tx_with_validation(dummy_tx(2, addr, 2), false)
+            .unwrap();
+        sequencer.build_and_execute_block().unwrap();
+        assert_eq!(storage.get_deal(1).unwrap(), Some(marked));
+    }
+
+    #[derive(Default)]
+    struct RecordingObserver {
+        executed: Mutex<Vec<(BlockId, StateDiff)>>,
+        rejected: Mutex<Vec<(u64, String)>>,
+    }
+
+    impl SequencerObserver for RecordingObserver {
+        fn on_block_executed(&self, block: &Block, diff: &StateDiff) {
+            self.executed.lock().unwrap().push((block.id, diff.clone()));
+        }
+
+        fn on_tx_rejected(&self, tx: &Tx, error: &SequencerError) {
+            self.rejected
+                .lock()
+                .unwrap()
+                .push((tx.id, format!("{:?}", error)));
+        }
+    }
+
+    #[test]
+    fn test_observer_callbacks() {
+        let observer = Arc::new(RecordingObserver::default());
+        let sequencer = Sequencer::with_config(1, 10).with_observer(observer.clone());
+        let addr = [1u8; 20];
+
+        sequencer
+            .submit_tx_with_validation(dummy_tx(0, addr, 0), false)
+            .unwrap();
+        assert!(sequencer
+            .submit_tx_with_validation(dummy_tx(1, addr, 1), false)
+            .is_err());
+        assert_eq!(
+            *observer.rejected.lock().unwrap(),
+            vec![(1, "QueueFull".to_string())]
+        );
+
+        let block = sequencer.build_and_execute_block().unwrap();
+
+        let executed = observer.executed.lock().unwrap();
+        assert_eq!(executed.len(), 1);
+        let (block_id, diff) = &executed[0];
+        assert_eq!(*block_id, block.id);
+        assert!(diff.deals.is_empty());
+        assert_eq!(diff.accounts.len(), 1);
+        assert_eq!(diff.accounts[0].owner, addr);
+        assert_eq!(diff.accounts[0].nonce, 1);
+        assert_eq!(
+            diff.accounts[0].balance_of(0, zkclear_types::chain_ids::ETHEREUM),
+            100
+        );
+    }
+
+    #[test]
+    fn test_request_id_deduplicates_retries_until_evicted() {
+        let sequencer = Sequencer::new().with_request_id_cache_size(2);
+        let addr = [1u8; 20];
+        let submit = |nonce: u64, request_id: &str| {
+            sequencer.submit_tx_with_request_id(
+                dummy_tx(nonce, addr, nonce),
+                request_id,
+                format!("0x{:02x}", nonce),
+                false,
+            )
+        };
+
+        assert_eq!(submit(0, "req-0").unwrap(), SubmitOutcome::Queued);
+        assert_eq!(sequencer.queue_length(), 1);
+
+        // A retry returns the first submission's hash and enqueues nothing
+        assert_eq!(
+            submit(5, "req-0").unwrap(),
+            SubmitOutcome::Duplicate("0x00".to_string())
+        );
+        assert_eq!(sequencer.queue_length(), 1);
+
+        // Two newer ids push "req-0" out of the cache
+        assert_eq!(submit(1, "req-1").unwrap(), SubmitOutcome::Queued);
+        assert_eq!(submit(2, "req-2").unwrap(), SubmitOutcome::Queued);
+        assert_eq!(submit(3, "req-0").unwrap(), SubmitOutcome::Queued);
+        assert_eq!(sequencer.queue_length(), 4);
+
+        // Another sender's id is its own
+        assert_eq!(
+            sequencer
+                .submit_tx_with_request_id(
+                    dummy_tx(4, [2u8; 20], 0),
+                    "req-0",
+                    "0x04".to_string(),
+                    false,
+                )
+                .unwrap(),
+            SubmitOutcome::Queued
+        );
+        assert_eq!(sequencer.queue_length(), 5);
+    }
+
+    #[test]
+    fn test_event_subscribers_receive_block_and_changes() {
+        let sequencer = Sequencer::new();
+        let addr = [1u8; 20];
+        let mut events = sequencer.subscribe_events();
+
+        sequencer
+            .submit_tx_with_validation(dummy_tx(0, addr, 0), false)
+            .unwrap();
+        let block = sequencer.build_and_execute_block().unwrap();
+
+        assert_eq!(
+            events.try_recv().unwrap(),
+            SequencerEvent::BlockExecuted {
+                block_id: block.id,
+                state_root: block.state_root,
+                timestamp: block.timestamp,
+                transaction_count: 1,
+            }
+        );
+        match events.try_recv().unwrap() {
+            SequencerEvent::AccountUpdated(account) => {
+                assert_eq!(account.owner, addr);
+                assert_eq!(account.nonce, 1);
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_only_lease_holder_builds_block() {
+        let storage: Arc<dyn Storage> = Arc::new(zkclear_storage::InMemoryStorage::new());
+        let leader = Sequencer::with_storage_arc(storage.clone())
+            .unwrap()
+            .with_block_builder_lease(Arc::new(lease::StorageBlockBuilderLease::new(
+                storage.clone(),
+                "leader",
+            )));
+        let follower = Sequencer::with_storage_arc(storage.clone())
+            .unwrap()
+            .with_block_builder_lease(Arc::new(lease::StorageBlockBuilderLease::new(
+                storage.clone(),
+                "follower",
+            )));
+
+        leader
+            .submit_tx_with_validation(dummy_tx(0, [1u8; 20], 0), false)
+            .unwrap();
+        follower
+            .submit_tx_with_validation(dummy_tx(1, [2u8; 20], 0), false)
+            .unwrap();
+
+        let block = leader.build_and_execute_block().unwrap();
+        assert_eq!(block.id, follower.get_current_block_id());
+
+        assert!(matches!(
+            follower.build_and_execute_block(),
+            Err(SequencerError::NotLeader)
+        ));
+        assert_eq!(follower.queue_length(), 1);
+
+        let stored = storage.get_block(block.id).unwrap().unwrap();
+        assert_eq!(stored.transactions.len(), 1);
+        assert_eq!(stored.transactions[0].from, [1u8; 20]);
+    }
+
+    #[test]
+    fn test_expired_lease_taken_over() {
+        let storage: Arc<dyn Storage> = Arc::new(zkclear_storage::InMemoryStorage::new());
+        let clock = Arc::new(clock::MockClock::new(1_000));
+        let lease = |holder: &str| {
+            lease::StorageBlockBuilderLease::new(storage.clone(), holder)
+                .with_ttl(Duration::from_secs(10))
+                .with_clock(clock.clone())
+        };
+        let (leader, follower) = (lease("leader"), lease("follower"));
+
+        assert!(leader.try_acquire(1).unwrap());
+        clock.advance(9);
+        assert!(!follower.try_acquire(1).unwrap());
+        // Renewing moves the expiry to 1_019
+        assert!(leader.try_acquire(1).unwrap());
+        clock.advance(9);
+        assert!(!follower.try_acquire(1).unwrap());
+
+        clock.advance(1);
+        assert!(follower.try_acquire(1).unwrap());
+        assert!(!leader.try_acquire(1).unwrap());
+    }
+
+    #[test]
+    fn test_replica_syncs_blocks_from_shared_storage() {
+        let storage: Arc<dyn Storage> = Arc::new(zkclear_storage::InMemoryStorage::new());
+        let primary = Sequencer::with_storage_arc(storage.clone()).unwrap();
+        let replica = Sequencer::with_storage_arc(storage.clone()).unwrap();
+        let mut events = replica.subscribe_events();
+        let addr = [1u8; 20];
+
+        for nonce in 0..2 {
+            primary
+                .submit_tx_with_validation(dummy_tx(nonce, addr, nonce), false)
+                .unwrap();
+            primary.build_and_execute_block().unwrap();
+        }
+        assert_eq!(replica.sync_from_storage().unwrap(), 2);
+        assert_eq!(
+            replica.get_current_block_id(),
+            primary.get_current_block_id()
+        );
+        assert_eq!(
+            replica.current_state_root().unwrap(),
+            primary.current_state_root().unwrap()
+        );
+        assert!(matches!(
+            events.try_recv().unwrap(),
+            SequencerEvent::BlockExecuted { block_id: 1, .. }
+        ));
+
+        // A rollback on the primary is followed too
+        primary.rollback_to(1).unwrap();
+        primary
+            .submit_tx_with_validation(dummy_tx(5, [2u8; 20], 0), false)
+            .unwrap();
+        primary.build_and_execute_block().unwrap();
+        assert_eq!(replica.sync_from_storage().unwrap(), 2);
+        assert_eq!(
+            replica.current_state_root().unwrap(),
+            primary.current_state_root().unwrap()
+        );
+        assert_eq!(replica.sync_from_storage().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_per_sender_cap_keeps_blocks_fair() {
+        let sequencer = Sequencer::with_config(100, 4).with_max_txs_per_sender(2);
+        let flooder = [1u8; 20];
+        let alice = [2u8; 20];
+        let bob = [3u8; 20];
+
+        for nonce in 0..10 {
+            sequencer
+                .submit_tx_with_validation(dummy_tx(nonce, flooder, nonce), false)
+                .unwrap();
+        }
+        sequencer
+            .submit_tx_with_validation(dummy_tx(100, alice, 0), false)
+            .unwrap();
+        sequencer
+            .submit_tx_with_validation(dummy_tx(200, bob, 0), false)
+            .unwrap();
+
+        let block = sequencer.build_and_execute_block().unwrap();
+        let ids: Vec<u64> = block.transactions.iter().map(|tx| tx.id).collect();
+        assert_eq!(ids, vec![0, 1, 100, 200]);
+
+        let block = sequencer.build_and_execute_block().unwrap();
+        let ids: Vec<u64> = block.transactions.iter().map(|tx| tx.id).collect();
+        assert_eq!(ids, vec![2, 3]);
+        assert_eq!(sequencer.queue_length(), 6);
+    }
+
+    /// Ids of the txs in the next `blocks` blocks, in inclusion order
+    fn included_ids(sequencer: &Sequencer, blocks: usize) -> Vec<u64> {
+        (0..blocks)
+            .flat_map(|_| sequencer.build_and_execute_block().unwrap().transactions)
+            .map(|tx| tx.id)
+            .collect()
+    }
+
+    #[test]
+    fn test_fee_priority_includes_late_high_fee_first() {
+        let fee_policy = FeePolicy {
+            asset_id: 0,
+            chain_id: zkclear_types::chain_ids::ETHEREUM,
+            amount: 1,
+        };
+        let build = |ordering| {
+            let sequencer = Sequencer::with_config(100, 1)
+                .with_stf_config(StfConfig {
+                    fee: Some(fee_policy),
+                    ..StfConfig::default()
+                })
+                .with_ordering_policy(ordering);
+            {
+                let state_handle = sequencer.get_state();
+                let mut state = state_handle.lock().unwrap();
+                for from in [[1u8; 20], [2u8; 20]] {
+                    state
+                        .get_or_create_account_by_owner(from)
+                        .credit(0, zkclear_types::chain_ids::ETHEREUM, 100)
+                        .unwrap();
+                }
+            }
+
+            let early_low_fee = dummy_tx(1, [1u8; 20], 0);
+            let mut late_high_fee = dummy_tx(2, [2u8; 20], 0);
+            late_high_fee.fee = 10;
+            for tx in [early_low_fee, late_high_fee] {
+                sequencer.submit_tx_with_validation(tx, false).unwrap();
+            }
+            sequencer
+        };
+
+        assert_eq!(included_ids(&build(OrderingPolicy::Fifo), 2), vec![1, 2]);
+
+        let sequencer = build(OrderingPolicy::FeePriority);
+        assert_eq!(included_ids(&sequencer, 2), vec![2, 1]);
+        // The priority fee is charged on top of the flat fee
+        let state_handle = sequencer.get_state();
+        let state = state_handle.lock().unwrap();
+        let balance = |owner| {
+            state
+                .get_account_by_address(owner)
+                .unwrap()
+                .balance_of(0, zkclear_types::chain_ids::ETHEREUM)
+        };
+        assert_eq!(balance([2u8; 20]), 100 + 100 - 11);
+        assert_eq!(balance([1u8; 20]), 100 + 100 - 1);
+    }
+
+    #[test]
+    fn test_low_fee_txs_throttled_past_high_water_mark() {
+        let sequencer = Sequencer::with_config(10, 10).with_throttling(0.8, 10);
+        let fee_tx = |id: u64, fee: u128| Tx {
+            fee,
+            ..dummy_tx(id, [id as u8 + 1; 20], 0)
+        };
+
+        for id in 0..8 {
+            sequencer
+                .submit_tx_with_validation(fee_tx(id, 0), false)
+                .unwrap();
+        }
+        assert_eq!(sequencer.queue_pressure(), 0.8);
+        assert!(!sequencer.is_accepting());
+
+        assert!(matches!(
+            sequencer.submit_tx_with_validation(fee_tx(8, 9), false),
+            Err(SequencerError::Throttled)
+        ));
+        sequencer
+            .submit_tx_with_validation(fee_tx(8, 10), false)
+            .unwrap();
+        sequencer
+            .submit_tx_with_validation(fee_tx(9, 50), false)
+            .unwrap();
+        assert_eq!(sequencer.queue_pressure(), 1.0);
+
+        assert!(matches!(
+            sequencer.submit_tx_with_validation(fee_tx(10, 50), false),
+            Err(SequencerError::QueueFull)
+        ));
+    }
+
+    #[test]
+    fn test_old_snapshots_pruned_and_restart_replays() {
+        let storage: Arc<dyn Storage> = Arc::new(zkclear_storage::InMemoryStorage::new());
+        let sequencer = Sequencer::with_storage_arc(storage.clone())
+            .unwrap()
+            .with_snapshot_interval(2)
+            .with_snapshot_retention(2);
+        let addr = [1u8; 20];
+
+        // Blocks 1..=9 snapshot at 2, 4, 6 and 8; the first two are pruned
+        for nonce in 0..9 {
+            sequencer
+                .submit_tx_with_validation(dummy_tx(nonce, addr, nonce), false)
+                .unwrap();
+            sequencer.build_and_execute_block().unwrap();
+        }
+
+        let (_, snapshot_block_id) = storage.get_latest_state_snapshot().unwrap().unwrap();
+        assert_eq!(snapshot_block_id, 8);
+
+        // Restarting loads the snapshot at 8 and replays block 9
+        let restarted = Sequencer::with_storage_arc(storage.clone()).unwrap();
+        assert_eq!(
+            restarted.get_state().lock().unwrap().root(),
+            sequencer.get_state().lock().unwrap().root()
+        );
+        restarted.self_check().unwrap();
+
+        // Only the snapshot at 6 remains besides the latest
+        assert_eq!(storage.prune_snapshots_before(BlockId::MAX).unwrap(), 1);
+    }
+
+    /// In-memory storage that can be told to drop the next block batch,
+    /// as when the process dies before the batch commits
+    struct CrashingStorage {
+        inner: zkclear_storage::InMemoryStorage,
+        crash_next_block: AtomicBool,
+    }
+
+    impl Storage for CrashingStorage {
+        fn version(&self) -> u32 {
+            self.inner.version()
+        }
+        fn set_version(&self, version: u32) -> Result<(), StorageError> {
+            self.inner.set_version(version)
+        }
+        fn save_block(&self, block: &Block) -> Result<(), StorageError> {
+            self.inner.save_block(block)
+        }
+        fn save_block_atomic(
+            &self,
+            block: &Block,
+            diff: Option<&zkclear_state::StateDiff>,
+            deals: &[&Deal],
+        ) -> Result<(), StorageError> {
+            if self.crash_next_block.swap(false, Ordering::SeqCst) {
+                return Err(StorageError::DatabaseError("crashed mid-batch".to_string()));
+            }
+            self.inner.save_block_atomic(block, diff, deals)
+        }
+        fn get_block(&self, block_id: BlockId) -> Result<Option<Block>, StorageError> {
+            self.inner.get_block(block_id)
+        }
+        fn get_latest_block_id(&self) -> Result<Option<BlockId>, StorageError> {
+            self.inner.get_latest_block_id()
+        }
+        fn iter_blocks(&self, from: BlockId, to: BlockId) -> zkclear_storage::BlockIter {
+            self.inner.iter_blocks(from, to)
+        }
+        fn save_transaction(
+            &self,
+            tx: &Tx,
+            block_id: BlockId,
+            index: usize,
+        ) -> Result<(), StorageError> {
+            self.inner.save_transaction(tx, block_id, index)
+        }
+        fn get_transaction(
+            &self,
+            block_id: BlockId,
+            index: usize,
+        ) -> Result<Option<Tx>, StorageError> {
+            self.inner.get_transaction(block_id, index)
+        }
+        fn get_transactions_by_block(&self, block_id: BlockId) -> Result<Vec<Tx>, StorageError> {
+            self.inner.get_transactions_by_block(block_id)
+        }
+        fn get_transaction_by_hash(
+            &self,
+            tx_hash: &zkclear_storage::TxHash,
+        ) -> Result<Option<(Tx, BlockId, usize)>, StorageError> {
+            self.inner.get_transaction_by_hash(tx_hash)
+        }
+        fn save_deal(&self, deal: &Deal) -> Result<(), StorageError> {
+            self.inner.save_deal(deal)
+        }
+        fn get_deal(&self, deal_id: zkclear_types::DealId) -> Result<Option<Deal>, StorageError> {
+            self.inner.get_deal(deal_id)
+        }
+        fn get_all_deals(&self) -> Result<Vec<Deal>, StorageError> {
+            self.inner.get_all_deals()
+        }
+        fn save_state_snapshot(
+            &self,
+            state: &State,
+            block_id: BlockId,
+        ) -> Result<(), StorageError> {
+            self.inner.save_state_snapshot(state, block_id)
+        }
+        fn get_latest_state_snapshot(&self) -> Result<Option<(State, BlockId)>, StorageError> {
+            self.inner.get_latest_state_snapshot()
+        }
+        fn get_state_snapshot_at_or_before(
+            &self,
+            block_id: BlockId,
+        ) -> Result<Option<(State, BlockId)>, StorageError> {
+            self.inner.get_state_snapshot_at_or_before(block_id)
+        }
+        fn prune_snapshots_before(&self, block_id: BlockId) -> Result<usize, StorageError> {
+            self.inner.prune_snapshots_before(block_id)
+        }
+        fn save_state_diff(
+            &self,
+            block_id: BlockId,
+            diff: &zkclear_state::StateDiff,
+        ) -> Result<(), StorageError> {
+            self.inner.save_state_diff(block_id, diff)
+        }
+        fn get_state_diff(
+            &self,
+            block_id: BlockId,
+        ) -> Result<Option<zkclear_state::StateDiff>, StorageError> {
+            self.inner.get_state_diff(block_id)
+        }
+        fn save_withdrawals_accumulator(
+            &self,
+            block_id: BlockId,
+            accumulator: &[u8; 32],
+        ) -> Result<(), StorageError> {
+            self.inner
+                .save_withdrawals_accumulator(block_id, accumulator)
+        }
+        fn get_withdrawals_accumulator(
+            &self,
+            block_id: BlockId,
+        ) -> Result<Option<[u8; 32]>, StorageError> {
+            self.inner.get_withdrawals_accumulator(block_id)
+        }
+        fn truncate_after(&self, block_id: BlockId) -> Result<usize, StorageError> {
+            self.inner.truncate_after(block_id)
+        }
+        fn save_checkpoint(&self, checkpoint: &Checkpoint) -> Result<(), StorageError> {
+            self.inner.save_checkpoint(checkpoint)
+        }
+        fn get_latest_checkpoint(&self) -> Result<Option<Checkpoint>, StorageError> {
+            self.inner.get_latest_checkpoint()
+        }
+        fn claim_block_builder(
+            &self,
+            block_id: BlockId,
+            claim: &zkclear_storage::BuilderClaim,
+        ) -> Result<bool, StorageError> {
+            self.inner.claim_block_builder(block_id, claim)
+        }
+        fn flush(&self) -> Result<(), StorageError> {
+            self.inner.flush()
+        }
+    }
+
+    #[test]
+    fn test_failed_block_write_leaves_nothing_behind() {
+        let storage = Arc::new(CrashingStorage {
+            inner: zkclear_storage::InMemoryStorage::new(),
+            crash_next_block: AtomicBool::new(false),
+        });
+        let sequencer = Sequencer::with_storage_arc(storage.clone()).unwrap();
+        let addr = [1u8; 20];
+
+        sequencer
+            .submit_tx_with_validation(dummy_tx(0, addr, 0), false)
+            .unwrap();
+        sequencer.build_and_execute_block().unwrap();
+        let committed_id = sequencer.get_current_block_id();
+        let committed_root = sequencer.get_state().lock().unwrap().root();
+
+        sequencer
+            .submit_tx_with_validation(dummy_tx(1, addr, 1), false)
+            .unwrap();
+        let block = sequencer.build_block().unwrap();
+        storage.crash_next_block.store(true, Ordering::SeqCst);
+        assert!(matches!(
+            sequencer.execute_block(block.clone()),
+            Err(SequencerError::StorageError(_))
+        ));
+
+        // Neither the sequencer nor storage moved past the committed block
+        assert_eq!(sequencer.get_current_block_id(), committed_id);
+        assert_eq!(sequencer.get_state().lock().unwrap().root(), committed_root);
+        assert_eq!(
+            storage.get_latest_block_id().unwrap(),
+            Some(committed_id - 1)
+        );
+        assert!(storage.get_block(block.id).unwrap().is_none());
+        assert!(storage
+            .get_transactions_by_block(block.id)
+            .unwrap()
+            .is_empty());
+
+        // A restart replays exactly the committed blocks
+        let restarted = Sequencer::with_storage_arc(storage.clone()).unwrap();
+        assert_eq!(restarted.get_current_block_id(), committed_id);
+        assert_eq!(restarted.get_state().lock().unwrap().root(), committed_root);
+
+        // And the dropped block can simply be executed again
+        sequencer.execute_block(block.clone()).unwrap();
+        assert_eq!(sequencer.get_current_block_id(), committed_id + 1);
+        assert_eq!(storage.get_latest_block_id().unwrap(), Some(block.id));
+        assert_eq!(
+            storage.get_transactions_by_block(block.id).unwrap().len(),
+            1
+        );
+        let restarted = Sequencer::with_storage_arc(storage.clone()).unwrap();
+        assert_eq!(
+            restarted.get_state().lock().unwrap().root(),
+            sequencer.get_state().lock().unwrap().root()
+        );
+        restarted.self_check().unwrap();
+    }
+
+    #[test]
+    fn test_shutdown_snapshot_skips_replay_on_restart() {
+        let storage: Arc<dyn Storage> = Arc::new(zkclear_storage::InMemoryStorage::new());
+        let sequencer = Sequencer::with_storage_arc(storage.clone())
+            .unwrap()
+            .with_snapshot_interval(3);
+        let addr = [1u8; 20];
+
+        for nonce in 0..5 {
+            sequencer
+                .submit_tx_with_validation(dummy_tx(nonce, addr, nonce), false)
+                .unwrap();
+            sequencer.build_and_execute_block().unwrap();
+        }
+        assert_eq!(storage.get_latest_state_snapshot().unwrap().unwrap().1, 3);
+
+        sequencer.create_state_snapshot().unwrap();
+        assert_eq!(storage.get_latest_state_snapshot().unwrap().unwrap().1, 5);
+
+        // Replaying the emptied block 5 would lose its deposit, so matching
+        // roots show the restart loaded the snapshot alone
+        let mut block = storage.get_block(5).unwrap().unwrap();
+        block.transactions.clear();
+        storage.save_block(&block).unwrap();
+
+        let restarted = Sequencer::with_storage_arc(storage.clone()).unwrap();
+        assert_eq!(
+            restarted.get_state().lock().unwrap().root(),
+            sequencer.get_state().lock().unwrap().root()
+        );
+        assert_eq!(restarted.get_current_block_id(), 6);
+
+        // Without storage there is nothing to write
+        Sequencer::new().create_state_snapshot().unwrap();
+    }
+
+    #[test]
+    fn test_replay_state_at_starts_from_nearest_snapshot() {
+        let storage: Arc<dyn Storage> = Arc::new(zkclear_storage::InMemoryStorage::new());
+        let sequencer = Sequencer::with_storage_arc(storage.clone())
+            .unwrap()
+            .with_snapshot_interval(3);
+        let addr = [1u8; 20];
+        for nonce in 0..5 {
+            sequencer
+                .submit_tx_with_validation(dummy_tx(nonce, addr, nonce), false)
+                .unwrap();
+            sequencer.build_and_execute_block().unwrap();
+        }
+
+        for (block_id, snapshot_block_id) in [(2, 0), (3, 3), (5, 3)] {
+            let (mut state, used) = sequencer.replay_state_at(&*storage, block_id).unwrap();
+            assert_eq!(used, snapshot_block_id);
+            assert_eq!(
+                sequencer.compute_state_root(&mut state),
+                storage.get_block(block_id).unwrap().unwrap().state_root
+            );
+        }
+    }
+
+    #[test]
+    fn test_rollback_matches_rederived_state() {
+        let storage: Arc<dyn Storage> = Arc::new(zkclear_storage::InMemoryStorage::new());
+        let sequencer = Sequencer::with_storage_arc(storage.clone())
+            .unwrap()
+            .with_snapshot_interval(3);
+        let rederived =
+            Sequencer::with_storage_arc(Arc::new(zkclear_storage::InMemoryStorage::new())).unwrap();
+        let addr = [1u8; 20];
+
+        // Blocks 1..=5, with a snapshot after block 3
+        for nonce in 0..5 {
+            sequencer
+                .submit_tx_with_validation(dummy_tx(nonce, addr, nonce), false)
+                .unwrap();
+            sequencer.build_and_execute_block().unwrap();
+            if nonce < 2 {
+                rederived
+                    .submit_tx_with_validation(dummy_tx(nonce, addr, nonce), false)
+                    .unwrap();
+                rederived.build_and_execute_block().unwrap();
+            }
+        }
+        assert_eq!(sequencer.get_current_block_id(), 6);
+
+        // From the snapshot at 3, replaying block 4
+        sequencer.rollback_to(4).unwrap();
+        assert_eq!(sequencer.get_current_block_id(), 5);
+        sequencer.self_check().unwrap();
+
+        // From scratch, as the snapshot at 3 is past block 2
+        sequencer.rollback_to(2).unwrap();
+        assert_eq!(sequencer.get_current_block_id(), 3);
+        assert_eq!(
+            sequencer.get_current_block_id(),
+            rederived.get_current_block_id()
+        );
+        assert_eq!(
+            sequencer.get_state().lock().unwrap().root(),
+            rederived.get_state().lock().unwrap().root()
+        );
+        assert_eq!(storage.get_latest_block_id().unwrap(), Some(2));
+        assert!(storage.get_block(3).unwrap().is_none());
+        assert!(storage.get_latest_state_snapshot().unwrap().is_none());
+
+        // The chain continues from block 3, and a restart sees the same state
+        sequencer
+            .submit_tx_with_validation(dummy_tx(2, addr, 2), false)
+            .unwrap();
+        assert_eq!(sequencer.build_and_execute_block().unwrap().id, 3);
+        let restarted = Sequencer::with_storage_arc(storage).unwrap();
+        restarted.self_check().unwrap();
+        assert_eq!(
+            restarted.get_state().lock().unwrap().root(),
+            sequencer.get_state().lock().unwrap().root()
+        );
+
+        assert!(matches!(
+            sequencer.rollback_to(4),
+            Err(SequencerError::InvalidBlockId)
+        ));
+        assert!(matches!(
+            Sequencer::new().rollback_to(0),
+            Err(SequencerError::StorageError(_))
+        ));
+    }
+
+    #[test]
+    fn test_rollback_forgets_request_ids() {
+        let storage: Arc<dyn Storage> = Arc::new(zkclear_storage::InMemoryStorage::new());
+        let sequencer = Sequencer::with_storage_arc(storage).unwrap();
+        let addr = [1u8; 20];
+        let submit = || {
+            sequencer.submit_tx_with_request_id(
+                dummy_tx(1, addr, 1),
+                "req-1",
+                "0x01".to_string(),
+                false,
+            )
+        };
+
+        sequencer
+            .submit_tx_with_validation(dummy_tx(0, addr, 0), false)
+            .unwrap();
+        sequencer.build_and_execute_block().unwrap();
+        assert_eq!(submit().unwrap(), SubmitOutcome::Queued);
+        sequencer.build_and_execute_block().unwrap();
+
+        // The tx behind "req-1" is gone with block 2, so a retry goes in
+        sequencer.rollback_to(1).unwrap();
+        assert_eq!(submit().unwrap(), SubmitOutcome::Queued);
+        assert_eq!(sequencer.queue_length(), 1);
+    }
+
+    #[test]
+    fn test_rollback_restores_stored_deals() {
+        let storage = Arc::new(zkclear_storage::InMemoryStorage::new());
+        let sequencer = Sequencer::with_storage_arc(storage.clone()).unwrap();
+        let addr = [1u8; 20];
+        let create_deal = |nonce, deal_id| Tx {
+            kind: TxKind::CreateDeal,
+            payload: TxPayload::CreateDeal(zkclear_types::CreateDeal {
+                deal_id,
+                visibility: zkclear_types::DealVisibility::Public,
+                taker: None,
+                asset_base: 0,
+                asset_quote: 1,
+                chain_id_base: zkclear_types::chain_ids::ETHEREUM,
+                chain_id_quote: zkclear_types::chain_ids::ETHEREUM,
+                amount_base: 10,
+                price_quote_per_base: 1,
+                expires_at: None,
+                external_ref: None,
+            }),
+            ..dummy_tx(nonce, addr, nonce)
+        };
+        let cancel_deal = Tx {
+            kind: TxKind::CancelDeal,
+            payload: TxPayload::CancelDeal(zkclear_types::CancelDeal { deal_id: 1 }),
+            ..dummy_tx(2, addr, 2)
+        };
+
+        for tx in [dummy_tx(0, addr, 0), create_deal(1, 1)] {
+            sequencer.submit_tx_with_validation(tx, false).unwrap();
+        }
+        sequencer.build_and_execute_block().unwrap();
+        for tx in [cancel_deal, create_deal(3, 2)] {
+            sequencer.submit_tx_with_validation(tx, false).unwrap();
+        }
+        sequencer.build_and_execute_block().unwrap();
+        assert_eq!(
+            storage.get_deal(1).unwrap().unwrap().status,
+            zkclear_types::DealStatus::Cancelled
+        );
+
+        sequencer.rollback_to(1).unwrap();
+        assert_eq!(
+            storage.get_deal(1).unwrap().unwrap().status,
+            zkclear_types::DealStatus::Pending
+        );
+        assert!(storage.get_deal(2).unwrap().is_none());
+        assert_eq!(
+            storage.get_all_deals().unwrap().len(),
+            sequencer.get_state().lock().unwrap().deals.len()
+        );
+    }
+
+    #[test]
+    fn test_self_check_detects_tampered_snapshot() {
+        let storage: Arc<dyn Storage> = Arc::new(zkclear_storage::InMemoryStorage::new());
+        let sequencer = Sequencer::with_storage_arc(storage.clone()).unwrap();
+        let addr = [1u8; 20];
+
+        sequencer
+            .submit_tx_with_validation(dummy_tx(0, addr, 0), false)
+            .unwrap();
+        let block = sequencer.build_and_execute_block().unwrap();
+        sequencer.self_check().unwrap();
+
+        // A restart replaying the stored block reaches the same root
+        let restarted = Sequencer::with_storage_arc(storage.clone()).unwrap();
+        restarted.self_check().unwrap();
+
+        let mut tampered = sequencer.get_state().lock().unwrap().clone();
+        let account = tampered.get_or_create_account_by_owner(addr);
+        account.credit(0, zkclear_types::chain_ids::ETHEREUM, 1);
+        storage.save_state_snapshot(&tampered, block.id).unwrap();
+
+        let restarted = Sequencer::with_storage_arc(storage).unwrap();
+        assert!(matches!(
+            restarted.self_check(),
+            Err(SequencerError::SelfCheckFailed(_))
+        ));
+    }
+
+    #[test]
+    fn test_self_check_verifies_latest_proof() {
+        let storage: Arc<dyn Storage> = Arc::new(zkclear_storage::InMemoryStorage::new());
+        let sequencer = Sequencer::with_storage_arc(storage.clone())
+            .unwrap()
+            .with_prover_config(ProverConfig::default())
+            .unwrap();
+        let addr = [1u8; 20];
+
+        sequencer
+            .submit_tx_with_validation(dummy_tx(0, addr, 0), false)
+            .unwrap();
+        sequencer.build_and_execute_block().unwrap();
+
+        // The latest block was built without a proof
+        let unproven = sequencer.with_self_check_proof_verification(true);
+        assert!(matches!(
+            unproven.self_check(),
+            Err(SequencerError::SelfCheckFailed(_))
+        ));
+
+        unproven
+            .submit_tx_with_validation(dummy_tx(1, addr, 1), false)
+            .unwrap();
+        let block = unproven.build_and_execute_block_with_proof(true).unwrap();
+        assert!(!block.block_proof.is_empty());
+        unproven.self_check().unwrap();
+    }
+
+    /// STARK backend that never finishes within any reasonable timeout
+    struct SlowStarkProver;
+
+    #[async_trait::async_trait]
+    impl zkclear_prover::stark::StarkProver for SlowStarkProver {
+        async fn prove_block_transition(
+            &self,
+            _: &[u8; 32],
+            _: &[u8; 32],
+            _: &[u8; 32],
+            _: &[u8],
+        ) -> Result<Vec<u8>, ProverError> {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            Ok(Vec::new())
+        }
+
+        async fn verify_stark_proof(&self, _: &[u8], _: &[u8]) -> Result<bool, ProverError> {
+            Ok(true)
+        }
+    }
+
+    #[test]
+    fn test_proof_timeout_falls_back_to_empty_proof() {
+        let prover = Prover::from_provers(
+            Box::new(SlowStarkProver),
+            Box::new(zkclear_prover::snark::PlaceholderSnarkProver),
+        );
+        let sequencer = Sequencer::new()
+            .with_prover(Arc::new(prover))
+            .with_proof_timeout(Duration::from_millis(50));
+        let addr = [1u8; 20];
+        let started = std::time::Instant::now();
+
+        let prev_state = sequencer.get_state().lock().unwrap().clone();
+        // The state must change, or the prover skips the backend entirely
+        let mut new_state = prev_state.clone();
+        new_state.get_or_create_account_by_owner(addr);
+        let block = Block {
+            id: 0,
+            transactions: Vec::new(),
+            timestamp: 0,
+            state_root: [0u8; 32],
+            withdrawals_root: [0u8; 32],
+            block_proof: Vec::new(),
+        };
+        assert!(matches!(
+            sequencer.generate_block_proof(
+                sequencer.prover.as_ref().unwrap(),
+                &block,
+                &prev_state,
+                &new_state
+            ),
+            Err(SequencerError::ProverError(ref e)) if e == "timeout"
+        ));
+
+        sequencer
+            .submit_tx_with_validation(dummy_tx(0, addr, 0), false)
+            .unwrap();
+        let block = sequencer.build_and_execute_block_with_proof(true).unwrap();
+        assert!(block.block_proof.is_empty());
+        assert_eq!(sequencer.get_current_block_id(), 1);
+        assert!(started.elapsed() < Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_built_block_round_trips_through_types_crate() {
+        let storage: Arc<dyn Storage> = Arc::new(zkclear_storage::InMemoryStorage::new());
+        let sequencer = Sequencer::with_storage_arc(storage.clone())
+            .unwrap()
+            .with_prover_config(ProverConfig::default())
+            .unwrap();
+
+        sequencer
+            .submit_tx_with_validation(dummy_tx(0, [1u8; 20], 0), false)
+            .unwrap();
+        let block = sequencer.build_and_execute_block_with_proof(true).unwrap();
+        assert!(!block.block_proof.is_empty());
+
+        let bytes = bincode::serialize(&block).unwrap();
+        let decoded: zkclear_types::Block = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(decoded.id, block.id);
+        assert_eq!(decoded.timestamp, block.timestamp);
+        assert_eq!(decoded.state_root, block.state_root);
+        assert_eq!(decoded.withdrawals_root, block.withdrawals_root);
+        assert_eq!(decoded.block_proof, block.block_proof);
+        assert_eq!(bincode::serialize(&decoded).unwrap(), bytes);
+
+        let stored = storage.get_block(block.id).unwrap().unwrap();
+        assert_eq!(bincode::serialize(&stored).unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_state_root_matches_prover() {
+        let sequencer = Sequencer::new();
+        assert_eq!(
+            sequencer.current_state_root().unwrap(),
+            Prover::compute_state_root_static(&State::new()).unwrap()
+        );
+
+        sequencer
+            .submit_tx_with_validation(dummy_tx(0, [1u8; 20], 0), false)
+            .unwrap();
+        sequencer
+            .submit_tx_with_validation(dummy_tx(1, [2u8; 20], 0), false)
+            .unwrap();
+        let block = sequencer.build_and_execute_block().unwrap();
+
+        let expected = {
+            let state = sequencer.get_state();
+            let state = state.lock().unwrap();
+            Prover::compute_state_root_static(&state).unwrap()
+        };
+        let (block_id, root) = sequencer.current_state_root_with_block_id().unwrap();
+        assert_eq!(block_id, block.id);
+        assert_eq!(root, expected);
+        assert_eq!(block.state_root, expected);
+    }
+
+    #[test]
+    fn test_keccak_roots_match_prover() {
+        use zkclear_prover::merkle::{compute_state_root_with, compute_withdrawals_root_with};
+
+        let sequencer = Sequencer::new().with_hash_algo(HashAlgo::Keccak256);
+        let owner = [1u8; 20];
+        sequencer
+            .submit_tx_with_validation(dummy_tx(0, owner, 0), false)
+            .unwrap();
+        sequencer.build_and_execute_block().unwrap();
+        sequencer
+            .submit_tx_with_validation(
+                Tx {
+                    id: 1,
+                    from: owner,
+                    nonce: 1,
+                    kind: TxKind::Withdraw,
+                    payload: TxPayload::Withdraw(zkclear_types::Withdraw {
+                        asset_id: 0,
+                        amount: 40,
+                        to: owner,
+                        chain_id: zkclear_types::chain_ids::ETHEREUM,
+                    }),
+                    fee: 0,
+                    domain: 0,
+                    scheme: SignatureScheme::Secp256k1Recoverable,
+                    signature: vec![0u8; 65],
+                },
+                false,
+            )
+            .unwrap();
+        let block = sequencer.build_and_execute_block().unwrap();
+
+        let state = sequencer.get_state().lock().unwrap().clone();
+        assert_eq!(
+            block.state_root,
+            compute_state_root_with(HashAlgo::Keccak256, &state).unwrap()
+        );
+        assert_ne!(
+            block.state_root,
+            Prover::compute_state_root_static(&state).unwrap()
+        );
+        assert_eq!(
+            block.withdrawals_root,
+            compute_withdrawals_root_with(HashAlgo::Keccak256, &block.transactions).unwrap()
+        );
+        assert_eq!(sequencer.current_state_root().unwrap(), block.state_root);
+    }
 }
@@ -0,0 +1,251 @@
+//! Pending transaction pool
+//!
+//! Txs are kept in a max-heap keyed by `(priority, Reverse(seq))`, where
+//! `seq` is the arrival order. Under `OrderingPolicy::Fifo` every tx has
+//! priority 0, so txs leave in arrival order. Under
+//! `OrderingPolicy::FeePriority` the priority is the fee paid per encoded
+//! byte, and arrival order only breaks ties, keeping selection
+//! deterministic.
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::time::{Duration, Instant};
+
+use zkclear_stf::FeePolicy;
+use zkclear_types::Tx;
+
+/// Order in which queued txs are included in blocks
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OrderingPolicy {
+    /// Arrival order
+    #[default]
+    Fifo,
+    /// Highest fee per byte first, earliest arrival among equal fees
+    FeePriority,
+}
+
+/// Why a queued tx was dropped without being included
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvictionReason {
+    /// The sender's committed nonce has moved past the tx's nonce
+    StaleNonce,
+    /// The tx waited in the queue longer than the mempool TTL
+    Expired,
+}
+
+/// Fees per byte are ranked in thousandths, so small fees on large txs
+/// still order correctly
+const FEE_PER_BYTE_SCALE: u128 = 1_000;
+
+/// Sequence number of the first tx pushed to either end. Txs pushed to the
+/// front count down from here, txs pushed to the back count up.
+const FIRST_SEQ: u64 = 1 << 63;
+
+struct Entry {
+    priority: u128,
+    seq: u64,
+    /// When the tx was last put in the queue
+    queued_at: Instant,
+    tx: Tx,
+}
+
+impl Entry {
+    fn key(&self) -> (u128, Reverse<u64>) {
+        (self.priority, Reverse(self.seq))
+    }
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Entry {}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+pub struct Mempool {
+    heap: BinaryHeap<Entry>,
+    ordering: OrderingPolicy,
+    /// Flat fee the STF charges every tx, added to each tx's own fee
+    fee_policy: Option<FeePolicy>,
+    next_back_seq: u64,
+    next_front_seq: u64,
+}
+
+impl Mempool {
+    pub fn new(ordering: OrderingPolicy, fee_policy: Option<FeePolicy>) -> Self {
+        Self {
+            heap: BinaryHeap::new(),
+            ordering,
+            fee_policy,
+            next_back_seq: FIRST_SEQ,
+            next_front_seq: FIRST_SEQ - 1,
+        }
+    }
+
+    pub fn ordering(&self) -> OrderingPolicy {
+        self.ordering
+    }
+
+    pub fn set_ordering(&mut self, ordering: OrderingPolicy) {
+        self.ordering = ordering;
+        self.reprioritize();
+    }
+
+    pub fn set_fee_policy(&mut self, fee_policy: Option<FeePolicy>) {
+        self.fee_policy = fee_policy;
+        self.reprioritize();
+    }
+
+    /// Rank of `tx` under the current policy. Without a fee policy the STF
+    /// charges nothing, so every tx ranks equally and arrival order decides.
+    pub fn priority(&self, tx: &Tx) -> u128 {
+        let (OrderingPolicy::FeePriority, Some(fee_policy)) = (self.ordering, self.fee_policy)
+        else {
+            return 0;
+        };
+
+        let fee = fee_policy.amount.saturating_add(tx.fee);
+        let size = bincode::serialized_size(tx).unwrap_or(1).max(1) as u128;
+        fee.saturating_mul(FEE_PER_BYTE_SCALE) / size
+    }
+
+    /// Queue a newly arrived tx
+    pub fn push_back(&mut self, tx: Tx) {
+        let seq = self.next_back_seq;
+        self.next_back_seq += 1;
+        self.push(tx, seq, Instant::now());
+    }
+
+    /// Return a tx ahead of everything of equal priority, e.g. one taken
+    /// for a block but deferred to a later one
+    pub fn push_front(&mut self, tx: Tx) {
+        let seq = self.next_front_seq;
+        self.next_front_seq -= 1;
+        self.push(tx, seq, Instant::now());
+    }
+
+    /// Remove the tx that should be included next
+    pub fn pop(&mut self) -> Option<Tx> {
+        self.heap.pop().map(|entry| entry.tx)
+    }
+
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+
+    /// Queued txs in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = &Tx> {
+        self.heap.iter().map(|entry| &entry.tx)
+    }
+
+    /// Remove and return the txs for which `evict` returns true. It is
+    /// given each tx and how long it has waited since it was last queued.
+    pub fn remove_where(&mut self, mut evict: impl FnMut(&Tx, Duration) -> bool) -> Vec<Tx> {
+        let now = Instant::now();
+        let (removed, kept): (Vec<Entry>, Vec<Entry>) = std::mem::take(&mut self.heap)
+            .into_vec()
+            .into_iter()
+            .partition(|entry| evict(&entry.tx, now.duration_since(entry.queued_at)));
+        self.heap = BinaryHeap::from(kept);
+        removed.into_iter().map(|entry| entry.tx).collect()
+    }
+
+    fn push(&mut self, tx: Tx, seq: u64, queued_at: Instant) {
+        let priority = self.priority(&tx);
+        self.heap.push(Entry {
+            priority,
+            seq,
+            queued_at,
+            tx,
+        });
+    }
+
+    fn reprioritize(&mut self) {
+        let entries = std::mem::take(&mut self.heap).into_vec();
+        for entry in entries {
+            self.push(entry.tx, entry.seq, entry.queued_at);
+        }
+    }
+}
+
+impl Default for Mempool {
+    fn default() -> Self {
+        Self::new(OrderingPolicy::default(), None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zkclear_types::{SignatureScheme, TxKind, TxPayload, Withdraw};
+
+    fn withdraw_tx(id: u64, fee: u128) -> Tx {
+        Tx {
+            id,
+            from: [1u8; 20],
+            nonce: id,
+            kind: TxKind::Withdraw,
+            payload: TxPayload::Withdraw(Withdraw {
+                asset_id: 0,
+                amount: 1,
+                to: [1u8; 20],
+                chain_id: zkclear_types::chain_ids::ETHEREUM,
+            }),
+            fee,
+            domain: 0,
+            scheme: SignatureScheme::Secp256k1Recoverable,
+            signature: vec![0u8; 65],
+        }
+    }
+
+    fn drain(pool: &mut Mempool) -> Vec<u64> {
+        std::iter::from_fn(|| pool.pop()).map(|tx| tx.id).collect()
+    }
+
+    #[test]
+    fn test_fifo_and_fee_priority_order() {
+        let fee_policy = FeePolicy {
+            asset_id: 0,
+            chain_id: zkclear_types::chain_ids::ETHEREUM,
+            amount: 1,
+        };
+        let fees = [0, 50, 0, 50, 10];
+
+        let mut fifo = Mempool::new(OrderingPolicy::Fifo, Some(fee_policy));
+        let mut priority = Mempool::new(OrderingPolicy::FeePriority, Some(fee_policy));
+        for (id, fee) in fees.into_iter().enumerate() {
+            fifo.push_back(withdraw_tx(id as u64, fee));
+            priority.push_back(withdraw_tx(id as u64, fee));
+        }
+
+        assert_eq!(drain(&mut fifo), vec![0, 1, 2, 3, 4]);
+        assert_eq!(drain(&mut priority), vec![1, 3, 4, 0, 2]);
+    }
+
+    #[test]
+    fn test_push_front_goes_ahead_of_equal_priority() {
+        let mut pool = Mempool::default();
+        pool.push_back(withdraw_tx(1, 0));
+        pool.push_back(withdraw_tx(2, 0));
+        pool.push_front(withdraw_tx(0, 0));
+
+        assert_eq!(drain(&mut pool), vec![0, 1, 2]);
+    }
+}
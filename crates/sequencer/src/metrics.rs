@@ -0,0 +1,250 @@
+//! Counters and histograms exported at the API's `/metrics` endpoint
+//!
+//! A small registry rather than a metrics crate: the sequencer records into
+//! it as blocks execute, submissions are rejected and proofs finish, and
+//! `encode` renders everything in the Prometheus text exposition format.
+//! Gauges that mirror sequencer state, like the queue length, are passed in
+//! at scrape time instead of being tracked here.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use zkclear_types::{Block, BlockId, TxKind};
+
+use crate::mempool::EvictionReason;
+use crate::SequencerError;
+
+/// Upper bounds of the `zkclear_proof_seconds` buckets
+pub const PROOF_SECONDS_BUCKETS: [f64; 10] =
+    [0.1, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0];
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    blocks_total: AtomicU64,
+    txs_total: Mutex<BTreeMap<&'static str, u64>>,
+    tx_rejected_total: Mutex<BTreeMap<&'static str, u64>>,
+    tx_evicted_total: Mutex<BTreeMap<&'static str, u64>>,
+    proof_seconds: Mutex<Histogram>,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Observations per bucket, not yet cumulative
+    buckets: [u64; PROOF_SECONDS_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count an executed block and its txs by kind
+    pub fn record_block(&self, block: &Block) {
+        self.blocks_total.fetch_add(1, Ordering::Relaxed);
+        let mut txs_total = self.txs_total.lock().unwrap();
+        for tx in &block.transactions {
+            *txs_total.entry(kind_label(&tx.kind)).or_default() += 1;
+        }
+    }
+
+    pub fn record_rejection(&self, error: &SequencerError) {
+        *self
+            .tx_rejected_total
+            .lock()
+            .unwrap()
+            .entry(rejection_reason(error))
+            .or_default() += 1;
+    }
+
+    pub fn record_eviction(&self, reason: EvictionReason) {
+        *self
+            .tx_evicted_total
+            .lock()
+            .unwrap()
+            .entry(eviction_reason(reason))
+            .or_default() += 1;
+    }
+
+    pub fn record_proof(&self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        let mut histogram = self.proof_seconds.lock().unwrap();
+        if let Some(bucket) = PROOF_SECONDS_BUCKETS.iter().position(|&le| seconds <= le) {
+            histogram.buckets[bucket] += 1;
+        }
+        histogram.count += 1;
+        histogram.sum += seconds;
+    }
+
+    pub fn blocks_total(&self) -> u64 {
+        self.blocks_total.load(Ordering::Relaxed)
+    }
+
+    /// Render all metrics in the Prometheus text format
+    pub fn encode(&self, queue_length: usize, current_block_id: BlockId) -> String {
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "zkclear_queue_length",
+            "gauge",
+            "Txs waiting in the queue",
+        );
+        let _ = writeln!(out, "zkclear_queue_length {}", queue_length);
+        header(
+            &mut out,
+            "zkclear_current_block_id",
+            "gauge",
+            "Id of the next block to execute",
+        );
+        let _ = writeln!(out, "zkclear_current_block_id {}", current_block_id);
+
+        header(
+            &mut out,
+            "zkclear_blocks_total",
+            "counter",
+            "Blocks executed",
+        );
+        let _ = writeln!(out, "zkclear_blocks_total {}", self.blocks_total());
+
+        header(
+            &mut out,
+            "zkclear_txs_total",
+            "counter",
+            "Txs executed in blocks",
+        );
+        for (kind, count) in self.txs_total.lock().unwrap().iter() {
+            let _ = writeln!(out, "zkclear_txs_total{{kind=\"{}\"}} {}", kind, count);
+        }
+
+        header(
+            &mut out,
+            "zkclear_tx_rejected_total",
+            "counter",
+            "Submissions rejected before reaching the queue",
+        );
+        for (reason, count) in self.tx_rejected_total.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "zkclear_tx_rejected_total{{reason=\"{}\"}} {}",
+                reason, count
+            );
+        }
+
+        header(
+            &mut out,
+            "zkclear_tx_evicted_total",
+            "counter",
+            "Queued txs dropped without being included",
+        );
+        for (reason, count) in self.tx_evicted_total.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "zkclear_tx_evicted_total{{reason=\"{}\"}} {}",
+                reason, count
+            );
+        }
+
+        header(
+            &mut out,
+            "zkclear_proof_seconds",
+            "histogram",
+            "Time spent generating block proofs",
+        );
+        let histogram = self.proof_seconds.lock().unwrap();
+        let mut cumulative = 0;
+        for (le, count) in PROOF_SECONDS_BUCKETS.iter().zip(histogram.buckets) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "zkclear_proof_seconds_bucket{{le=\"{}\"}} {}",
+                le, cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "zkclear_proof_seconds_bucket{{le=\"+Inf\"}} {}",
+            histogram.count
+        );
+        let _ = writeln!(out, "zkclear_proof_seconds_sum {}", histogram.sum);
+        let _ = writeln!(out, "zkclear_proof_seconds_count {}", histogram.count);
+
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn kind_label(kind: &TxKind) -> &'static str {
+    match kind {
+        TxKind::Deposit => "deposit",
+        TxKind::Withdraw => "withdraw",
+        TxKind::CreateDeal => "create_deal",
+        TxKind::AcceptDeal => "accept_deal",
+        TxKind::CancelDeal => "cancel_deal",
+        TxKind::Transfer => "transfer",
+        TxKind::DeclineDeal => "decline_deal",
+        TxKind::CreateFundedDeal => "create_funded_deal",
+        TxKind::ConfirmSettlement => "confirm_settlement",
+        TxKind::BatchCreateDeal => "batch_create_deal",
+        TxKind::CancelAllDeals => "cancel_all_deals",
+    }
+}
+
+fn rejection_reason(error: &SequencerError) -> &'static str {
+    match error {
+        SequencerError::QueueFull => "queue_full",
+        SequencerError::ExecutionFailed(_) => "execution_failed",
+        SequencerError::NoTransactions => "no_transactions",
+        SequencerError::InvalidBlockId => "invalid_block_id",
+        SequencerError::InvalidSignature => "invalid_signature",
+        SequencerError::InvalidNonce => "invalid_nonce",
+        SequencerError::ValidationFailed => "validation_failed",
+        SequencerError::StorageError(_) => "storage_error",
+        SequencerError::ProverError(_) => "prover_error",
+        SequencerError::TxKindDisabled => "tx_kind_disabled",
+        SequencerError::NotLeader => "not_leader",
+        SequencerError::WrongDomain => "wrong_domain",
+        SequencerError::Throttled => "throttled",
+        SequencerError::SelfCheckFailed(_) => "self_check_failed",
+        SequencerError::InvalidGenesis(_) => "invalid_genesis",
+        SequencerError::TxTooLarge => "tx_too_large",
+        SequencerError::ChainStarted => "chain_started",
+        SequencerError::ImportFailed(_) => "import_failed",
+    }
+}
+
+fn eviction_reason(reason: EvictionReason) -> &'static str {
+    match reason {
+        EvictionReason::StaleNonce => "stale_nonce",
+        EvictionReason::Expired => "expired",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_proof_histogram_buckets_are_cumulative() {
+        let metrics = Metrics::new();
+        metrics.record_proof(Duration::from_millis(50));
+        metrics.record_proof(Duration::from_secs(3));
+        metrics.record_proof(Duration::from_secs(600));
+
+        let text = metrics.encode(0, 0);
+        assert!(text.contains("zkclear_proof_seconds_bucket{le=\"0.1\"} 1\n"));
+        assert!(text.contains("zkclear_proof_seconds_bucket{le=\"2.5\"} 1\n"));
+        assert!(text.contains("zkclear_proof_seconds_bucket{le=\"5\"} 2\n"));
+        assert!(text.contains("zkclear_proof_seconds_bucket{le=\"300\"} 2\n"));
+        assert!(text.contains("zkclear_proof_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(text.contains("zkclear_proof_seconds_count 3\n"));
+    }
+}
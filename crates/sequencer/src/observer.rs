@@ -0,0 +1,17 @@
+//! In-process hooks for applications embedding the sequencer
+
+pub use zkclear_state::StateDiff;
+use zkclear_types::{Block, Tx};
+
+use crate::SequencerError;
+
+/// Callbacks invoked by the sequencer. Both methods default to no-ops so
+/// implementors only override what they need.
+///
+/// Callbacks run synchronously on the sequencer's thread after its locks are
+/// released, so they should return quickly.
+pub trait SequencerObserver: Send + Sync {
+    fn on_block_executed(&self, _block: &Block, _diff: &StateDiff) {}
+
+    fn on_tx_rejected(&self, _tx: &Tx, _error: &SequencerError) {}
+}
@@ -0,0 +1,123 @@
+//! Recently seen client request ids, for idempotent submission
+//!
+//! A bounded least-recently-used map from a sender's request id to the tx
+//! hash its first submission returned. Ids are scoped to the sender, so
+//! one client can't claim another's id. Each entry carries the tick of its
+//! last use, and `recency` orders entries by that tick so the stalest one
+//! is evicted when the cache is full.
+
+use std::collections::{BTreeMap, HashMap};
+
+use zkclear_types::Address;
+
+/// A request id as sent by `from`
+type RequestKey = (Address, String);
+
+pub struct RequestIdCache {
+    capacity: usize,
+    entries: HashMap<RequestKey, (u64, String)>,
+    /// Request key by last-use tick, oldest first
+    recency: BTreeMap<u64, RequestKey>,
+    next_tick: u64,
+}
+
+impl RequestIdCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            next_tick: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Tx hash recorded for `from`'s `request_id`, marking it as recently
+    /// used
+    pub fn get(&mut self, from: Address, request_id: &str) -> Option<String> {
+        let tick = self.tick();
+        let key = (from, request_id.to_string());
+        let (last_used, tx_hash) = self.entries.get_mut(&key)?;
+        self.recency.remove(last_used);
+        self.recency.insert(tick, key);
+        *last_used = tick;
+        Some(tx_hash.clone())
+    }
+
+    /// Record the tx hash of `from`'s request, evicting the least recently
+    /// used entry if the cache is over capacity
+    pub fn insert(&mut self, from: Address, request_id: String, tx_hash: String) {
+        let tick = self.tick();
+        let key = (from, request_id);
+        if let Some((last_used, _)) = self.entries.insert(key.clone(), (tick, tx_hash)) {
+            self.recency.remove(&last_used);
+        }
+        self.recency.insert(tick, key);
+        self.evict();
+    }
+
+    /// Forget every request, e.g. once the txs they point at were rolled
+    /// back
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.next_tick += 1;
+        self.next_tick
+    }
+
+    fn evict(&mut self) {
+        while self.entries.len() > self.capacity {
+            let Some((_, key)) = self.recency.pop_first() else {
+                break;
+            };
+            self.entries.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_least_recently_used_entry_evicted() {
+        let mut cache = RequestIdCache::new(2);
+        let from = [1u8; 20];
+        cache.insert(from, "a".to_string(), "0xa".to_string());
+        cache.insert(from, "b".to_string(), "0xb".to_string());
+
+        // Touching "a" makes "b" the oldest
+        assert_eq!(cache.get(from, "a"), Some("0xa".to_string()));
+        cache.insert(from, "c".to_string(), "0xc".to_string());
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(from, "b"), None);
+        assert_eq!(cache.get(from, "a"), Some("0xa".to_string()));
+        assert_eq!(cache.get(from, "c"), Some("0xc".to_string()));
+    }
+
+    #[test]
+    fn test_request_ids_scoped_to_sender() {
+        let mut cache = RequestIdCache::new(4);
+        cache.insert([1u8; 20], "a".to_string(), "0xa".to_string());
+
+        assert_eq!(cache.get([2u8; 20], "a"), None);
+        cache.insert([2u8; 20], "a".to_string(), "0xb".to_string());
+        assert_eq!(cache.get([1u8; 20], "a"), Some("0xa".to_string()));
+        assert_eq!(cache.get([2u8; 20], "a"), Some("0xb".to_string()));
+
+        cache.clear();
+        assert!(cache.is_empty());
+        assert_eq!(cache.get([1u8; 20], "a"), None);
+    }
+}
@@ -6,10 +6,12 @@
 //! - Overflow/underflow protection
 //! - Replay attack prevention
 
+use crate::config::DEFAULT_MAX_BATCH_SIZE;
+use crate::envelope::TX_ENVELOPE_HEADER_SIZE;
 use crate::validation::ValidationError;
-use zkclear_types::Tx;
+use zkclear_types::{CreateDeal, Tx, TxPayload};
 
-/// Maximum allowed transaction size (in bytes)
+/// Maximum allowed encoded transaction size (in bytes)
 /// Prevents DoS attacks via oversized transactions
 pub const MAX_TX_SIZE: usize = 10_000;
 
@@ -17,26 +19,66 @@ pub const MAX_TX_SIZE: usize = 10_000;
 /// Prevents potential issues with very large nonce jumps
 pub const MAX_NONCE_GAP: u64 = 1_000_000;
 
+/// Size limits on submitted transactions. The sequencer checks every
+/// submission against them and the API reads them for its own early
+/// checks, so the two always agree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxLimits {
+    /// Largest tx accepted, as encoded in a tx envelope
+    pub max_tx_size: usize,
+    /// Longest deal `external_ref` accepted, in bytes. The sequencer keeps
+    /// it equal to `StfConfig::max_external_ref_len`.
+    pub max_external_ref_len: usize,
+    /// Most txs accepted in one batch submission
+    pub max_batch_size: usize,
+}
+
+impl Default for TxLimits {
+    fn default() -> Self {
+        Self {
+            max_tx_size: MAX_TX_SIZE,
+            max_external_ref_len: zkclear_stf::DEFAULT_MAX_EXTERNAL_REF_LEN,
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+        }
+    }
+}
+
+impl TxLimits {
+    /// Check the tx's encoded size and, for deals, its `external_ref`
+    pub fn check_tx(&self, tx: &Tx) -> Result<(), ValidationError> {
+        validate_tx_size(tx, self.max_tx_size)?;
+
+        let deals: &[CreateDeal] = match &tx.payload {
+            TxPayload::CreateDeal(deal) => std::slice::from_ref(deal),
+            TxPayload::CreateFundedDeal(funded) => std::slice::from_ref(&funded.deal),
+            TxPayload::BatchCreateDeal(batch) => &batch.deals,
+            _ => &[],
+        };
+        if deals
+            .iter()
+            .filter_map(|deal| deal.external_ref.as_deref())
+            .any(|external_ref| external_ref.len() > self.max_external_ref_len)
+        {
+            return Err(ValidationError::TxTooLarge);
+        }
+
+        Ok(())
+    }
+}
+
+/// Size of `tx` wrapped in a tx envelope
+pub fn encoded_tx_size(tx: &Tx) -> usize {
+    bincode::serialized_size(tx)
+        .map(|size| TX_ENVELOPE_HEADER_SIZE + size as usize)
+        .unwrap_or(usize::MAX)
+}
+
 /// Validate transaction size to prevent DoS attacks
-pub fn validate_tx_size(tx: &Tx) -> Result<(), ValidationError> {
-    // Estimate transaction size
-    let size = std::mem::size_of::<Tx>();
-    
-    // Check payload size (rough estimate)
-    let payload_size = match &tx.payload {
-        zkclear_types::TxPayload::Deposit(_) => 100,
-        zkclear_types::TxPayload::Withdraw(_) => 100,
-        zkclear_types::TxPayload::CreateDeal(_) => 500,
-        zkclear_types::TxPayload::AcceptDeal(_) => 50,
-        zkclear_types::TxPayload::CancelDeal(_) => 50,
-    };
-    
-    let total_size = size + payload_size;
-    
-    if total_size > MAX_TX_SIZE {
-        return Err(ValidationError::InvalidSignature); // Reuse error type for now
+pub fn validate_tx_size(tx: &Tx, max_tx_size: usize) -> Result<(), ValidationError> {
+    if encoded_tx_size(tx) > max_tx_size {
+        return Err(ValidationError::TxTooLarge);
     }
-    
+
     Ok(())
 }
 
@@ -161,4 +203,3 @@ mod tests {
         assert!(!sanitized.contains('\x00'));
     }
 }
-
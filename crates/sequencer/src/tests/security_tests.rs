@@ -1,7 +1,7 @@
 //! Security tests for sequencer validation and security checks
 
 use crate::security::*;
-use zkclear_types::{Address, Tx, TxKind, TxPayload, Deposit};
+use zkclear_types::{Address, Deposit, SignatureScheme, Tx, TxKind, TxPayload};
 
 #[test]
 fn test_validate_address_rejects_zero() {
@@ -54,7 +54,7 @@ fn test_validate_nonce_gap_rejects_large_gap() {
 #[test]
 fn test_validate_tx_size_accepts_normal() {
     let tx = create_test_tx();
-    assert!(validate_tx_size(&tx).is_ok());
+    assert!(validate_tx_size(&tx, MAX_TX_SIZE).is_ok());
 }
 
 #[test]
@@ -89,7 +89,9 @@ fn create_test_tx() -> Tx {
             amount: 100,
             chain_id: 1,
         }),
-        signature: [0u8; 65],
+        fee: 0,
+        domain: 0,
+        scheme: SignatureScheme::Secp256k1Recoverable,
+        signature: vec![0u8; 65],
     }
 }
-
@@ -5,36 +5,94 @@ use k256::{
 };
 use sha3::{Digest, Keccak256};
 use zkclear_state::State;
-use zkclear_types::{Address, Tx, TxKind};
+use zkclear_types::{
+    signature::ED25519_PUBLIC_KEY_SIZE, Address, Eip712Domain, SignatureScheme, Tx,
+};
 
 #[derive(Debug)]
 pub enum ValidationError {
     InvalidSignature,
     InvalidNonce,
     SignatureRecoveryFailed,
+    /// `kind` does not match the payload variant
+    KindMismatch,
+    /// The signature's length doesn't fit the tx's signature scheme
+    SignatureLengthMismatch,
+    /// The tx exceeds the sequencer's `TxLimits`
+    TxTooLarge,
+}
+
+/// Checks signatures of ERC-1271 contract wallets, usually by calling the
+/// wallet's `isValidSignature` through an RPC provider. Called while the
+/// sequencer validates a submission, so it should answer promptly.
+pub trait Erc1271Verifier: Send + Sync {
+    /// Whether the wallet at `wallet` accepts `signature` over `hash`
+    fn is_valid_signature(&self, wallet: Address, hash: &[u8; 32], signature: &[u8]) -> bool;
 }
 
-pub fn validate_tx(state: &State, tx: &Tx) -> Result<(), ValidationError> {
-    verify_signature(tx)?;
+/// Check that the tx's kind matches its payload, then its signature and
+/// nonce. A secp256k1 signature may cover the raw `signing_hash`, or, when
+/// `eip712` is set, the EIP-712 typed-data hash of the tx in that domain;
+/// other schemes sign `signing_hash`. ERC-1271 signatures are rejected
+/// unless an `erc1271` verifier is given.
+pub fn validate_tx(
+    state: &State,
+    tx: &Tx,
+    eip712: Option<&Eip712Domain>,
+    erc1271: Option<&dyn Erc1271Verifier>,
+) -> Result<(), ValidationError> {
+    if tx.kind != tx.payload.kind() {
+        return Err(ValidationError::KindMismatch);
+    }
+    verify_signature(tx, eip712, erc1271)?;
     check_nonce(state, tx)?;
     Ok(())
 }
 
-fn verify_signature(tx: &Tx) -> Result<(), ValidationError> {
-    let recovered_address = recover_address(tx)?;
+fn verify_signature(
+    tx: &Tx,
+    eip712: Option<&Eip712Domain>,
+    erc1271: Option<&dyn Erc1271Verifier>,
+) -> Result<(), ValidationError> {
+    if tx
+        .scheme
+        .signature_len()
+        .is_some_and(|len| tx.signature.len() != len)
+    {
+        return Err(ValidationError::SignatureLengthMismatch);
+    }
 
-    if recovered_address != tx.from {
+    match tx.scheme {
+        SignatureScheme::Secp256k1Recoverable => verify_secp256k1(tx, eip712),
+        SignatureScheme::Ed25519 => verify_ed25519(tx),
+        SignatureScheme::Erc1271 => {
+            let verifier = erc1271.ok_or(ValidationError::InvalidSignature)?;
+            if !verifier.is_valid_signature(tx.from, &tx.signing_hash(), &tx.signature) {
+                return Err(ValidationError::InvalidSignature);
+            }
+            Ok(())
+        }
+    }
+}
+
+fn verify_secp256k1(tx: &Tx, eip712: Option<&Eip712Domain>) -> Result<(), ValidationError> {
+    let raw = recover_address(tx, &tx.signing_hash());
+    if matches!(raw, Ok(address) if address == tx.from) {
+        return Ok(());
+    }
+
+    let Some(domain) = eip712 else {
+        return raw.and(Err(ValidationError::InvalidSignature));
+    };
+    if recover_address(tx, &domain.typed_data_hash(tx))? != tx.from {
         return Err(ValidationError::InvalidSignature);
     }
 
     Ok(())
 }
 
-fn recover_address(tx: &Tx) -> Result<Address, ValidationError> {
-    let message = tx_hash(tx);
-    let message_hash = Keccak256::digest(&message);
-
-    let sig_bytes = tx.signature;
+fn recover_address(tx: &Tx, message_hash: &[u8; 32]) -> Result<Address, ValidationError> {
+    let sig_bytes = &tx.signature;
 
     let mut r_bytes = [0u8; 32];
     r_bytes.copy_from_slice(&sig_bytes[0..32]);
@@ -52,93 +110,49 @@ fn recover_address(tx: &Tx) -> Result<Address, ValidationError> {
     let signature =
         Signature::from_scalars(r, s).map_err(|_| ValidationError::SignatureRecoveryFailed)?;
 
-    let verifying_key = VerifyingKey::recover_from_prehash(&message_hash, &signature, recovery_id)
+    let verifying_key = VerifyingKey::recover_from_prehash(message_hash, &signature, recovery_id)
         .map_err(|_| ValidationError::SignatureRecoveryFailed)?;
 
     let public_key = PublicKey::from(&verifying_key);
     let encoded_point = public_key.to_encoded_point(false);
-    let public_key_bytes = encoded_point.as_bytes();
-
-    let hash = Keccak256::digest(&public_key_bytes[1..]);
-    let mut address = [0u8; 20];
-    address.copy_from_slice(&hash[12..]);
 
-    Ok(address)
+    Ok(address_of_public_key(&encoded_point.as_bytes()[1..]))
 }
 
-fn tx_hash(tx: &Tx) -> Vec<u8> {
-    let mut data = Vec::new();
-    data.extend_from_slice(&tx.from);
-    data.extend_from_slice(&tx.nonce.to_le_bytes());
-
-    let kind_byte = match tx.kind {
-        TxKind::Deposit => 0u8,
-        TxKind::Withdraw => 1u8,
-        TxKind::CreateDeal => 2u8,
-        TxKind::AcceptDeal => 3u8,
-        TxKind::CancelDeal => 4u8,
-    };
-    data.push(kind_byte);
-
-    match &tx.payload {
-        zkclear_types::TxPayload::Deposit(p) => {
-            data.extend_from_slice(&p.tx_hash);
-            data.extend_from_slice(&p.account);
-            data.extend_from_slice(&p.asset_id.to_le_bytes());
-            data.extend_from_slice(&p.amount.to_le_bytes());
-            data.extend_from_slice(&p.chain_id.to_le_bytes());
-        }
-        zkclear_types::TxPayload::Withdraw(p) => {
-            data.extend_from_slice(&p.asset_id.to_le_bytes());
-            data.extend_from_slice(&p.amount.to_le_bytes());
-            data.extend_from_slice(&p.to);
-            data.extend_from_slice(&p.chain_id.to_le_bytes());
-        }
-        zkclear_types::TxPayload::CreateDeal(p) => {
-            data.extend_from_slice(&p.deal_id.to_le_bytes());
-            data.push(p.visibility as u8);
-            if let Some(taker) = p.taker {
-                data.push(1);
-                data.extend_from_slice(&taker);
-            } else {
-                data.push(0);
-            }
-            data.extend_from_slice(&p.asset_base.to_le_bytes());
-            data.extend_from_slice(&p.asset_quote.to_le_bytes());
-            data.extend_from_slice(&p.chain_id_base.to_le_bytes());
-            data.extend_from_slice(&p.chain_id_quote.to_le_bytes());
-            data.extend_from_slice(&p.amount_base.to_le_bytes());
-            data.extend_from_slice(&p.price_quote_per_base.to_le_bytes());
-        }
-        zkclear_types::TxPayload::AcceptDeal(p) => {
-            data.extend_from_slice(&p.deal_id.to_le_bytes());
-            if let Some(amount) = p.amount {
-                data.push(1);
-                data.extend_from_slice(&amount.to_le_bytes());
-            } else {
-                data.push(0);
-            }
-        }
-        zkclear_types::TxPayload::CancelDeal(p) => {
-            data.extend_from_slice(&p.deal_id.to_le_bytes());
-        }
+fn verify_ed25519(tx: &Tx) -> Result<(), ValidationError> {
+    let (public_key, signature) = tx.signature.split_at(ED25519_PUBLIC_KEY_SIZE);
+    if address_of_public_key(public_key) != tx.from {
+        return Err(ValidationError::InvalidSignature);
     }
 
-    let prefix = b"\x19Ethereum Signed Message:\n";
-    let message_len = data.len();
-    let mut prefixed = Vec::new();
-    prefixed.extend_from_slice(prefix);
-    prefixed.extend_from_slice(message_len.to_string().as_bytes());
-    prefixed.extend_from_slice(&data);
+    let public_key: [u8; ED25519_PUBLIC_KEY_SIZE] = public_key
+        .try_into()
+        .map_err(|_| ValidationError::SignatureRecoveryFailed)?;
+    let verifying_key = ed25519_dalek::VerifyingKey::from_bytes(&public_key)
+        .map_err(|_| ValidationError::SignatureRecoveryFailed)?;
+    let signature = ed25519_dalek::Signature::from_slice(signature)
+        .map_err(|_| ValidationError::SignatureRecoveryFailed)?;
 
-    prefixed
+    verifying_key
+        .verify_strict(&tx.signing_hash(), &signature)
+        .map_err(|_| ValidationError::InvalidSignature)
 }
 
+/// Last 20 bytes of the keccak256 of a public key's encoding
+fn address_of_public_key(public_key: &[u8]) -> Address {
+    let hash = Keccak256::digest(public_key);
+    let mut address = [0u8; 20];
+    address.copy_from_slice(&hash[12..]);
+    address
+}
+
+/// Reject nonces the account has already used. Future nonces pass; the
+/// sequencer decides whether to queue or buffer them.
 fn check_nonce(state: &State, tx: &Tx) -> Result<(), ValidationError> {
     let account = state.get_account_by_address(tx.from);
     let expected_nonce = account.map(|a| a.nonce).unwrap_or(0);
 
-    if tx.nonce != expected_nonce {
+    if tx.nonce < expected_nonce {
         return Err(ValidationError::InvalidNonce);
     }
 
@@ -167,10 +181,203 @@ mod tests {
                 amount: 100,
                 chain_id: 1,
             }),
-            signature: [0u8; 65],
+            fee: 0,
+            domain: 0,
+            scheme: SignatureScheme::Secp256k1Recoverable,
+            signature: vec![0u8; 65],
         }
     }
 
+    fn address_of(key: &k256::ecdsa::SigningKey) -> Address {
+        let point = PublicKey::from(key.verifying_key()).to_encoded_point(false);
+        let hash = Keccak256::digest(&point.as_bytes()[1..]);
+        let mut address = [0u8; 20];
+        address.copy_from_slice(&hash[12..]);
+        address
+    }
+
+    fn sign(tx: &mut Tx, key: &k256::ecdsa::SigningKey) {
+        let hash = tx.signing_hash();
+        sign_hash(tx, key, hash);
+    }
+
+    fn sign_hash(tx: &mut Tx, key: &k256::ecdsa::SigningKey, hash: [u8; 32]) {
+        let (signature, recovery_id) = key.sign_prehash_recoverable(&hash).unwrap();
+        tx.signature[..64].copy_from_slice(&signature.to_bytes());
+        tx.signature[64] = recovery_id.to_byte() + 27;
+    }
+
+    fn test_key() -> k256::ecdsa::SigningKey {
+        k256::ecdsa::SigningKey::from_bytes(&[7u8; 32].into()).unwrap()
+    }
+
+    #[test]
+    fn test_validate_signed_tx() {
+        let key = test_key();
+        let mut tx = dummy_tx_with_nonce(address_of(&key), 0);
+        sign(&mut tx, &key);
+
+        assert!(validate_tx(&State::new(), &tx, None, None).is_ok());
+    }
+
+    #[test]
+    fn test_eip712_signature_accepted_only_when_enabled() {
+        let key = test_key();
+        let domain = Eip712Domain::new(1337, [0xCC; 20]);
+        let mut tx = dummy_tx_with_nonce(address_of(&key), 0);
+        let hash = domain.typed_data_hash(&tx);
+        sign_hash(&mut tx, &key, hash);
+
+        assert!(validate_tx(&State::new(), &tx, Some(&domain), None).is_ok());
+        assert!(matches!(
+            validate_tx(&State::new(), &tx, None, None),
+            Err(ValidationError::InvalidSignature)
+        ));
+        // A signature for another rollup's domain does not carry over
+        let other = Eip712Domain::new(1, [0xCC; 20]);
+        assert!(validate_tx(&State::new(), &tx, Some(&other), None).is_err());
+
+        // Raw-hash signatures keep working with EIP-712 enabled
+        sign(&mut tx, &key);
+        assert!(validate_tx(&State::new(), &tx, Some(&domain), None).is_ok());
+    }
+
+    #[test]
+    fn test_tampered_payload_rejected() {
+        let key = test_key();
+        let mut tx = dummy_tx_with_nonce(address_of(&key), 0);
+        sign(&mut tx, &key);
+
+        if let TxPayload::Deposit(ref mut deposit) = tx.payload {
+            deposit.amount += 1;
+        }
+
+        assert!(matches!(
+            validate_tx(&State::new(), &tx, None, None),
+            Err(ValidationError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn test_kind_mismatch_rejected() {
+        let key = test_key();
+        let mut tx = dummy_tx_with_nonce(address_of(&key), 0);
+        tx.kind = TxKind::Withdraw;
+        sign(&mut tx, &key);
+
+        assert!(matches!(
+            validate_tx(&State::new(), &tx, None, None),
+            Err(ValidationError::KindMismatch)
+        ));
+    }
+
+    #[test]
+    fn test_signature_from_other_key_rejected() {
+        let key = test_key();
+        let mut tx = dummy_tx_with_nonce(dummy_address(1), 0);
+        sign(&mut tx, &key);
+
+        assert!(matches!(
+            validate_tx(&State::new(), &tx, None, None),
+            Err(ValidationError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn test_zero_signature_rejected() {
+        let tx = dummy_tx_with_nonce(dummy_address(1), 0);
+
+        assert!(validate_tx(&State::new(), &tx, None, None).is_err());
+    }
+
+    fn sign_ed25519(tx: &mut Tx, key: &ed25519_dalek::SigningKey) {
+        use ed25519_dalek::Signer;
+
+        let public_key = key.verifying_key().to_bytes();
+        tx.from = address_of_public_key(&public_key);
+        tx.scheme = SignatureScheme::Ed25519;
+        let signature = key.sign(&tx.signing_hash());
+        tx.signature = [public_key.as_slice(), &signature.to_bytes()].concat();
+    }
+
+    #[test]
+    fn test_validate_ed25519_signed_tx() {
+        let key = ed25519_dalek::SigningKey::from_bytes(&[9u8; 32]);
+        let mut tx = dummy_tx_with_nonce(dummy_address(1), 0);
+        sign_ed25519(&mut tx, &key);
+        assert!(validate_tx(&State::new(), &tx, None, None).is_ok());
+
+        // Bound to the key's address and to the signed fields
+        let mut other = tx.clone();
+        other.from = dummy_address(2);
+        assert!(matches!(
+            validate_tx(&State::new(), &other, None, None),
+            Err(ValidationError::InvalidSignature)
+        ));
+        let mut tampered = tx.clone();
+        tampered.nonce = 1;
+        assert!(matches!(
+            validate_tx(&State::new(), &tampered, None, None),
+            Err(ValidationError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn test_signature_length_must_fit_scheme() {
+        let key = ed25519_dalek::SigningKey::from_bytes(&[9u8; 32]);
+        let mut tx = dummy_tx_with_nonce(dummy_address(1), 0);
+        sign_ed25519(&mut tx, &key);
+
+        let mut as_secp256k1 = tx.clone();
+        as_secp256k1.scheme = SignatureScheme::Secp256k1Recoverable;
+        assert!(matches!(
+            validate_tx(&State::new(), &as_secp256k1, None, None),
+            Err(ValidationError::SignatureLengthMismatch)
+        ));
+
+        let mut secp256k1 = dummy_tx_with_nonce(address_of(&test_key()), 0);
+        sign(&mut secp256k1, &test_key());
+        secp256k1.scheme = SignatureScheme::Ed25519;
+        assert!(matches!(
+            validate_tx(&State::new(), &secp256k1, None, None),
+            Err(ValidationError::SignatureLengthMismatch)
+        ));
+    }
+
+    struct FixedWallet {
+        wallet: Address,
+        signature: Vec<u8>,
+    }
+
+    impl Erc1271Verifier for FixedWallet {
+        fn is_valid_signature(&self, wallet: Address, _hash: &[u8; 32], signature: &[u8]) -> bool {
+            wallet == self.wallet && signature == self.signature
+        }
+    }
+
+    #[test]
+    fn test_erc1271_signature_checked_by_verifier() {
+        let verifier = FixedWallet {
+            wallet: dummy_address(1),
+            signature: vec![0xAB; 3],
+        };
+        let mut tx = dummy_tx_with_nonce(dummy_address(1), 0);
+        tx.scheme = SignatureScheme::Erc1271;
+        tx.signature = vec![0xAB; 3];
+
+        assert!(validate_tx(&State::new(), &tx, None, Some(&verifier)).is_ok());
+        // Without a verifier contract wallets can't be checked at all
+        assert!(matches!(
+            validate_tx(&State::new(), &tx, None, None),
+            Err(ValidationError::InvalidSignature)
+        ));
+        tx.signature = vec![0xAB; 4];
+        assert!(matches!(
+            validate_tx(&State::new(), &tx, None, Some(&verifier)),
+            Err(ValidationError::InvalidSignature)
+        ));
+    }
+
     #[test]
     fn test_validate_nonce_new_account() {
         let state = State::new();
@@ -208,7 +415,7 @@ mod tests {
     }
 
     #[test]
-    fn test_validate_nonce_too_high() {
+    fn test_validate_nonce_future_accepted() {
         let mut state = State::new();
         let addr = dummy_address(1);
 
@@ -216,8 +423,7 @@ mod tests {
         account.nonce = 5;
 
         let tx = dummy_tx_with_nonce(addr, 10);
-        let result = check_nonce(&state, &tx);
-        assert!(matches!(result, Err(ValidationError::InvalidNonce)));
+        assert!(check_nonce(&state, &tx).is_ok());
     }
 
     #[test]
@@ -0,0 +1,90 @@
+use zkclear_types::{Account, AccountId, Deal, DealId};
+
+use crate::State;
+
+/// Accounts and deals that differ between two states, for clients following
+/// state changes block by block without downloading the full state
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct StateDiff {
+    /// New or changed accounts, by ascending id
+    pub accounts: Vec<Account>,
+    /// New or changed deals, by ascending id
+    pub deals: Vec<Deal>,
+    /// Ids of accounts present before but not after
+    pub removed_accounts: Vec<AccountId>,
+    /// Ids of deals present before but not after
+    pub removed_deals: Vec<DealId>,
+}
+
+impl State {
+    /// Changes that turn `prev` into this state
+    pub fn diff(&self, prev: &State) -> StateDiff {
+        let mut accounts: Vec<Account> = self
+            .accounts
+            .iter()
+            .filter(|(id, account)| prev.accounts.get(id) != Some(account))
+            .map(|(_, account)| account.clone())
+            .collect();
+        accounts.sort_by_key(|a| a.id);
+
+        let mut deals: Vec<Deal> = self
+            .deals
+            .iter()
+            .filter(|(id, deal)| prev.deals.get(id) != Some(deal))
+            .map(|(_, deal)| deal.clone())
+            .collect();
+        deals.sort_by_key(|d| d.id);
+
+        let mut removed_accounts: Vec<AccountId> = prev
+            .accounts
+            .keys()
+            .filter(|id| !self.accounts.contains_key(id))
+            .copied()
+            .collect();
+        removed_accounts.sort();
+
+        let mut removed_deals: Vec<DealId> = prev
+            .deals
+            .keys()
+            .filter(|id| !self.deals.contains_key(id))
+            .copied()
+            .collect();
+        removed_deals.sort();
+
+        StateDiff {
+            accounts,
+            deals,
+            removed_accounts,
+            removed_deals,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_lists_only_touched_entries() {
+        let mut prev = State::new();
+        prev.get_or_create_account_by_owner([1u8; 20]);
+        prev.get_or_create_account_by_owner([2u8; 20]);
+        prev.get_or_create_account_by_owner([3u8; 20]);
+
+        let mut next = prev.clone();
+        next.get_or_create_account_by_owner([2u8; 20]).nonce = 1;
+        next.get_or_create_account_by_owner([4u8; 20]);
+        next.accounts.remove(&2);
+
+        let diff = next.diff(&prev);
+        assert_eq!(
+            diff.accounts.iter().map(|a| a.id).collect::<Vec<_>>(),
+            vec![1, 3]
+        );
+        assert_eq!(diff.accounts[0].nonce, 1);
+        assert_eq!(diff.removed_accounts, vec![2]);
+        assert!(diff.deals.is_empty());
+        assert!(diff.removed_deals.is_empty());
+        assert_eq!(prev.diff(&prev), StateDiff::default());
+    }
+}
@@ -0,0 +1,182 @@
+use zkclear_types::{Account, AccountId, Address, Deal, DealId, DealStatus};
+
+use crate::State;
+
+/// One account's full state, for moving it between instances
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct AccountExport {
+    pub account: Account,
+    /// Pending deals made by the account, by ascending id. Their amounts
+    /// are what the account's `reserved` balances hold.
+    pub open_deals: Vec<Deal>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ImportError {
+    /// The export's account or one of its deals belongs to another address
+    OwnerMismatch,
+    /// The existing account has a higher nonce than the export; importing
+    /// would roll it back and let old txs be replayed
+    StaleNonce { existing: u64, imported: u64 },
+    /// A deal with this id already exists and differs from the exported one
+    DealIdTaken(DealId),
+}
+
+impl State {
+    pub fn export_account(&self, owner: Address) -> Option<AccountExport> {
+        let account = self.get_account_by_address(owner)?.clone();
+        let mut open_deals: Vec<Deal> = self
+            .deals
+            .values()
+            .filter(|deal| deal.maker == owner && deal.status == DealStatus::Pending)
+            .cloned()
+            .collect();
+        open_deals.sort_by_key(|deal| deal.id);
+
+        Some(AccountExport {
+            account,
+            open_deals,
+        })
+    }
+
+    /// Upsert an exported account as the account of `owner`, together with
+    /// its open deals. An existing account of `owner` keeps its id and is
+    /// replaced; a new one keeps the exported id unless another account
+    /// holds it. Nothing changes if the import is rejected.
+    pub fn import_account(
+        &mut self,
+        owner: Address,
+        export: AccountExport,
+    ) -> Result<AccountId, ImportError> {
+        let AccountExport {
+            mut account,
+            open_deals,
+        } = export;
+
+        if account.owner != owner || open_deals.iter().any(|deal| deal.maker != owner) {
+            return Err(ImportError::OwnerMismatch);
+        }
+        if let Some(existing) = self.get_account_by_address(owner) {
+            if existing.nonce > account.nonce {
+                return Err(ImportError::StaleNonce {
+                    existing: existing.nonce,
+                    imported: account.nonce,
+                });
+            }
+        }
+        for deal in &open_deals {
+            if self
+                .get_deal(deal.id)
+                .is_some_and(|existing| existing != deal)
+            {
+                return Err(ImportError::DealIdTaken(deal.id));
+            }
+        }
+
+        account.id = match self.account_index.get(&owner) {
+            Some(&id) => id,
+            None if self.accounts.contains_key(&account.id) => self.next_account_id,
+            None => account.id,
+        };
+        let id = account.id;
+        self.upsert_account(account);
+
+        for deal in open_deals {
+            if self.get_deal(deal.id).is_none() {
+                self.record_deal_opened(owner, deal.id);
+                self.upsert_deal(deal);
+            }
+        }
+
+        Ok(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zkclear_types::{chain_ids, DealVisibility};
+
+    fn open_deal(id: DealId, maker: Address) -> Deal {
+        Deal {
+            id,
+            maker,
+            taker: None,
+            asset_base: 0,
+            asset_quote: 1,
+            chain_id_base: chain_ids::ETHEREUM,
+            chain_id_quote: chain_ids::ETHEREUM,
+            amount_base: 40,
+            amount_remaining: 40,
+            price_quote_per_base: 100,
+            status: DealStatus::Pending,
+            visibility: DealVisibility::Public,
+            created_at: 0,
+            updated_at: 0,
+            expires_at: None,
+            external_ref: None,
+            is_cross_chain: false,
+            escrows: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_account_round_trips_through_export() {
+        let owner = [7u8; 20];
+        let mut source = State::new();
+        source.get_or_create_account_by_owner([1u8; 20]);
+        let account = source.get_or_create_account_by_owner(owner);
+        account.credit(0, chain_ids::ETHEREUM, 60).unwrap();
+        account.credit(1, chain_ids::ETHEREUM, 500).unwrap();
+        account.credit(0, chain_ids::BASE, 25).unwrap();
+        account.reserved.set(0, chain_ids::ETHEREUM, 40);
+        account.nonce = 9;
+        source.upsert_deal(open_deal(3, owner));
+
+        let export = source.export_account(owner).unwrap();
+        let bytes = bincode::serialize(&export).unwrap();
+        let export: AccountExport = bincode::deserialize(&bytes).unwrap();
+
+        // The exported id 1 is taken in the target, so a fresh one is used
+        let mut target = State::new();
+        target.get_or_create_account_by_owner([2u8; 20]);
+        target.get_or_create_account_by_owner([3u8; 20]);
+        let id = target.import_account(owner, export.clone()).unwrap();
+        assert_eq!(id, 2);
+
+        let imported = target.get_account_by_address(owner).unwrap();
+        assert_eq!(imported.balances, export.account.balances);
+        assert_eq!(imported.reserved, export.account.reserved);
+        assert_eq!(imported.nonce, 9);
+        assert_eq!(target.get_deal(3), Some(&open_deal(3, owner)));
+        assert_eq!(target.open_deal_count(owner), 1);
+
+        // Re-importing the same export is a no-op
+        assert_eq!(target.import_account(owner, export.clone()), Ok(2));
+        assert_eq!(target.open_deal_count(owner), 1);
+    }
+
+    #[test]
+    fn test_import_rejects_wrong_owner_and_nonce_rollback() {
+        let owner = [7u8; 20];
+        let mut source = State::new();
+        source.get_or_create_account_by_owner(owner).nonce = 3;
+        let export = source.export_account(owner).unwrap();
+
+        let mut target = State::new();
+        assert_eq!(
+            target.import_account([8u8; 20], export.clone()),
+            Err(ImportError::OwnerMismatch)
+        );
+
+        target.get_or_create_account_by_owner(owner).nonce = 5;
+        assert_eq!(
+            target.import_account(owner, export),
+            Err(ImportError::StaleNonce {
+                existing: 5,
+                imported: 3
+            })
+        );
+        assert_eq!(target.get_account_by_address(owner).unwrap().nonce, 5);
+    }
+}
@@ -0,0 +1,379 @@
+//! Undo log for applying a block to the live state in place. While a
+//! journal is open, the first change to each account and deal keeps its
+//! previous value, alongside the fills and deposit records added or pruned,
+//! so the block can be diffed or reverted in time proportional to what it
+//! touched instead of against a copy of the whole state.
+
+use std::collections::HashMap;
+
+use zkclear_types::{Account, AccountId, Deal, DealId, Fill};
+
+use crate::{State, StateDiff};
+
+#[derive(Debug, Default, Clone)]
+pub struct Journal {
+    /// Value before the first change, `None` if the entry was created
+    accounts: HashMap<AccountId, Option<Account>>,
+    deals: HashMap<DealId, Option<Deal>>,
+    /// Number of fills a deal had before the first fill recorded
+    fill_counts: HashMap<DealId, usize>,
+    recorded_deposits: Vec<(u64, [u8; 32])>,
+    pruned_deposits: Vec<(u64, [u8; 32])>,
+    next_account_id: AccountId,
+}
+
+/// Everything a journaled block changed, with the new values, so the
+/// block can be brought onto an older copy of the state without running it
+#[derive(Debug, Default, Clone)]
+pub struct StateDelta {
+    pub diff: StateDiff,
+    /// Fills a deal had before the block, followed by the ones it added
+    fills: Vec<(DealId, usize, Vec<Fill>)>,
+    recorded_deposits: Vec<(u64, [u8; 32])>,
+    pruned_deposits: Vec<(u64, [u8; 32])>,
+    next_account_id: AccountId,
+}
+
+impl State {
+    /// Start recording changes, replacing any journal already open. The
+    /// accounts and deals recorded are the ones the Merkle tree marks
+    /// dirty, so every change made through `State`'s methods is covered.
+    pub fn begin_journal(&mut self) {
+        self.journal = Some(Journal {
+            next_account_id: self.next_account_id,
+            ..Journal::default()
+        });
+    }
+
+    /// Accounts and deals changed since `begin_journal`, as `diff` against
+    /// the state at that point would report them
+    pub fn journal_diff(&self) -> StateDiff {
+        let Some(ref journal) = self.journal else {
+            return StateDiff::default();
+        };
+
+        let mut diff = StateDiff::default();
+        for (id, prev) in &journal.accounts {
+            match self.accounts.get(id) {
+                Some(account) if prev.as_ref() != Some(account) => {
+                    diff.accounts.push(account.clone())
+                }
+                None if prev.is_some() => diff.removed_accounts.push(*id),
+                _ => {}
+            }
+        }
+        for (id, prev) in &journal.deals {
+            match self.deals.get(id) {
+                Some(deal) if prev.as_ref() != Some(deal) => diff.deals.push(deal.clone()),
+                None if prev.is_some() => diff.removed_deals.push(*id),
+                _ => {}
+            }
+        }
+        diff.accounts.sort_by_key(|a| a.id);
+        diff.deals.sort_by_key(|d| d.id);
+        diff.removed_accounts.sort();
+        diff.removed_deals.sort();
+        diff
+    }
+
+    /// Changes since `begin_journal`, for `apply_delta` on a copy of the
+    /// state as it was then
+    pub fn journal_delta(&self) -> StateDelta {
+        let Some(ref journal) = self.journal else {
+            return StateDelta {
+                next_account_id: self.next_account_id,
+                ..StateDelta::default()
+            };
+        };
+
+        StateDelta {
+            diff: self.journal_diff(),
+            fills: journal
+                .fill_counts
+                .iter()
+                .map(|(&deal_id, &count)| {
+                    let added = self
+                        .get_deal_fills(deal_id)
+                        .get(count..)
+                        .unwrap_or_default();
+                    (deal_id, count, added.to_vec())
+                })
+                .collect(),
+            recorded_deposits: journal.recorded_deposits.clone(),
+            pruned_deposits: journal.pruned_deposits.clone(),
+            next_account_id: self.next_account_id,
+        }
+    }
+
+    /// Bring the changes of `delta` onto this copy of the state. Deltas
+    /// must be applied in the order their blocks ran. The derived indexes
+    /// are left to `rebuild_derived_indexes`, so a run of deltas rebuilds
+    /// them once.
+    pub fn apply_delta(&mut self, delta: &StateDelta) {
+        for account in &delta.diff.accounts {
+            self.account_index.insert(account.owner, account.id);
+            self.accounts.insert(account.id, account.clone());
+            self.merkle.mark_account(account.id);
+        }
+        for id in &delta.diff.removed_accounts {
+            if let Some(account) = self.accounts.remove(id) {
+                self.account_index.remove(&account.owner);
+            }
+            self.merkle.mark_account(*id);
+        }
+        for deal in &delta.diff.deals {
+            self.deals.insert(deal.id, deal.clone());
+            self.merkle.mark_deal(deal.id);
+        }
+        for id in &delta.diff.removed_deals {
+            self.deals.remove(id);
+            self.merkle.mark_deal(*id);
+        }
+        for (deal_id, count, added) in &delta.fills {
+            let fills = self.fills.entry(*deal_id).or_default();
+            fills.truncate(*count);
+            fills.extend_from_slice(added);
+        }
+        for entry in &delta.pruned_deposits {
+            self.processed_deposits.remove(&entry.1);
+            self.processed_deposits_by_time.remove(entry);
+        }
+        for entry in &delta.recorded_deposits {
+            self.processed_deposits.insert(entry.1);
+            self.processed_deposits_by_time.insert(*entry);
+        }
+        self.next_account_id = delta.next_account_id;
+    }
+
+    /// Close the journal, keeping every change
+    pub fn commit_journal(&mut self) {
+        self.journal = None;
+    }
+
+    /// Close the journal and undo every change made since `begin_journal`.
+    /// The derived indexes are rebuilt rather than journaled.
+    pub fn revert_journal(&mut self) {
+        let Some(journal) = self.journal.take() else {
+            return;
+        };
+
+        for (id, prev) in journal.accounts {
+            if let Some(current) = self.accounts.remove(&id) {
+                self.account_index.remove(&current.owner);
+            }
+            if let Some(account) = prev {
+                self.account_index.insert(account.owner, id);
+                self.accounts.insert(id, account);
+            }
+            self.merkle.mark_account(id);
+        }
+        for (id, prev) in journal.deals {
+            match prev {
+                Some(deal) => self.deals.insert(id, deal),
+                None => self.deals.remove(&id),
+            };
+            self.merkle.mark_deal(id);
+        }
+        for (deal_id, count) in journal.fill_counts {
+            match count {
+                0 => {
+                    self.fills.remove(&deal_id);
+                }
+                _ => {
+                    if let Some(fills) = self.fills.get_mut(&deal_id) {
+                        fills.truncate(count);
+                    }
+                }
+            }
+        }
+        for entry in journal.recorded_deposits {
+            self.processed_deposits.remove(&entry.1);
+            self.processed_deposits_by_time.remove(&entry);
+        }
+        for entry in journal.pruned_deposits {
+            self.processed_deposits.insert(entry.1);
+            self.processed_deposits_by_time.insert(entry);
+        }
+        self.next_account_id = journal.next_account_id;
+
+        self.rebuild_derived_indexes();
+    }
+
+    /// Mark an account dirty and journal its value before the first change
+    pub(crate) fn touch_account(&mut self, id: AccountId) {
+        self.merkle.mark_account(id);
+        if let Some(ref mut journal) = self.journal {
+            journal
+                .accounts
+                .entry(id)
+                .or_insert_with(|| self.accounts.get(&id).cloned());
+        }
+    }
+
+    /// Mark a deal dirty and journal its value before the first change
+    pub(crate) fn touch_deal(&mut self, id: DealId) {
+        self.merkle.mark_deal(id);
+        if let Some(ref mut journal) = self.journal {
+            journal
+                .deals
+                .entry(id)
+                .or_insert_with(|| self.deals.get(&id).cloned());
+        }
+    }
+
+    pub(crate) fn journal_fill(&mut self, deal_id: DealId) {
+        if let Some(ref mut journal) = self.journal {
+            journal
+                .fill_counts
+                .entry(deal_id)
+                .or_insert_with(|| self.fills.get(&deal_id).map_or(0, Vec::len));
+        }
+    }
+
+    pub(crate) fn journal_deposits(
+        &mut self,
+        recorded: &[(u64, [u8; 32])],
+        pruned: &[(u64, [u8; 32])],
+    ) {
+        if let Some(ref mut journal) = self.journal {
+            journal.recorded_deposits.extend_from_slice(recorded);
+            journal.pruned_deposits.extend_from_slice(pruned);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zkclear_types::{DealStatus, DealVisibility, Fill};
+
+    fn deal(id: DealId, maker: [u8; 20]) -> Deal {
+        Deal {
+            id,
+            maker,
+            taker: None,
+            visibility: DealVisibility::Public,
+            asset_base: 0,
+            asset_quote: 1,
+            chain_id_base: 1,
+            chain_id_quote: 1,
+            amount_base: 100,
+            amount_remaining: 100,
+            price_quote_per_base: 1,
+            status: DealStatus::Pending,
+            created_at: 0,
+            updated_at: 0,
+            expires_at: Some(50),
+            external_ref: Some("order-1".to_string()),
+            is_cross_chain: false,
+            escrows: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_revert_restores_state_and_root() {
+        let mut state = State::new();
+        state.get_or_create_account_by_owner([1u8; 20]).nonce = 3;
+        state.upsert_deal(deal(1, [1u8; 20]));
+        state.record_deposit([9u8; 32], 10);
+        let before = state.clone();
+        let root = state.root();
+
+        state.begin_journal();
+        state.get_or_create_account_by_owner([1u8; 20]).nonce = 4;
+        state.get_or_create_account_by_owner([2u8; 20]);
+        state.get_deal_mut(1).unwrap().status = DealStatus::Cancelled;
+        state.upsert_deal(deal(2, [2u8; 20]));
+        state.record_fill(Fill {
+            deal_id: 1,
+            taker: [2u8; 20],
+            amount_base: 10,
+            amount_quote: 10,
+            block_timestamp: 20,
+        });
+        state.prune_processed_deposits(20);
+        state.record_deposit([8u8; 32], 20);
+
+        let diff = state.journal_diff();
+        assert_eq!(
+            diff.accounts.iter().map(|a| a.id).collect::<Vec<_>>(),
+            vec![0, 1]
+        );
+        assert_eq!(
+            diff.deals.iter().map(|d| d.id).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+
+        state.revert_journal();
+        assert_eq!(state.root(), root);
+        assert_eq!(state.diff(&before), StateDiff::default());
+        assert_eq!(state.account_index, before.account_index);
+        assert_eq!(state.next_account_id, before.next_account_id);
+        assert!(state.fills.is_empty());
+        assert_eq!(state.processed_deposits, before.processed_deposits);
+        assert_eq!(
+            state.processed_deposits_by_time,
+            before.processed_deposits_by_time
+        );
+        assert_eq!(state.external_ref_index, before.external_ref_index);
+    }
+
+    #[test]
+    fn test_delta_brings_copy_up_to_date() {
+        let mut state = State::new();
+        state.upsert_deal(deal(1, [1u8; 20]));
+        state.record_deposit([9u8; 32], 10);
+        let mut copy = state.clone();
+
+        state.begin_journal();
+        state.get_or_create_account_by_owner([1u8; 20]).nonce = 4;
+        state.get_deal_mut(1).unwrap().amount_remaining = 90;
+        state.upsert_deal(Deal {
+            external_ref: Some("order-2".to_string()),
+            ..deal(2, [2u8; 20])
+        });
+        state.record_fill(Fill {
+            deal_id: 1,
+            taker: [2u8; 20],
+            amount_base: 10,
+            amount_quote: 10,
+            block_timestamp: 20,
+        });
+        state.prune_processed_deposits(20);
+        state.record_deposit([8u8; 32], 20);
+        let delta = state.journal_delta();
+        state.commit_journal();
+
+        copy.apply_delta(&delta);
+        copy.rebuild_derived_indexes();
+        assert_eq!(copy.root(), state.root());
+        assert_eq!(copy.diff(&state), StateDiff::default());
+        assert_eq!(copy.account_index, state.account_index);
+        assert_eq!(copy.next_account_id, state.next_account_id);
+        assert_eq!(copy.fills, state.fills);
+        assert_eq!(copy.processed_deposits, state.processed_deposits);
+        assert_eq!(
+            copy.processed_deposits_by_time,
+            state.processed_deposits_by_time
+        );
+        assert_eq!(copy.external_ref_index, state.external_ref_index);
+    }
+
+    #[test]
+    fn test_journal_diff_matches_full_diff() {
+        let mut state = State::new();
+        state.get_or_create_account_by_owner([1u8; 20]);
+        state.get_or_create_account_by_owner([2u8; 20]);
+        let before = state.clone();
+
+        state.begin_journal();
+        // Touched without a change, so left out of the diff
+        state.get_account_mut(0);
+        state.get_account_mut(1).unwrap().nonce = 1;
+        state.get_or_create_account_by_owner([3u8; 20]);
+
+        assert_eq!(state.journal_diff(), state.diff(&before));
+        state.commit_journal();
+        assert_eq!(state.journal_diff(), StateDiff::default());
+    }
+}
@@ -1,12 +1,78 @@
-use std::collections::HashMap;
-use zkclear_types::{Account, AccountId, Address, Deal, DealId};
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+mod diff;
+mod export;
+mod journal;
+mod merkle;
+mod snapshot;
+
+pub use diff::StateDiff;
+pub use export::{AccountExport, ImportError};
+pub use journal::StateDelta;
+pub use merkle::{HashAlgo, StateMerkle};
+pub use snapshot::{SnapshotError, SnapshotReader, DEFAULT_SNAPSHOT_CHUNK_SIZE};
+use zkclear_types::{
+    Account, AccountId, Address, Asset, AssetId, Balances, ChainId, Deal, DealId, DealStatus,
+    DealVisibility, Fill, ZERO_ADDRESS,
+};
+
+/// A deal's market: `(asset_base, asset_quote, chain_id_base, chain_id_quote)`
+pub type DealPair = (AssetId, AssetId, ChainId, ChainId);
 
 #[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
 pub struct State {
     pub accounts: HashMap<AccountId, Account>,
     pub deals: HashMap<DealId, Deal>,
     pub account_index: HashMap<Address, AccountId>,
+    /// Id given to the next new account; always greater than every id in
+    /// `accounts`. Serialized with the rest of the state, so ids assigned
+    /// after a snapshot reload continue where they left off.
     pub next_account_id: AccountId,
+    /// Fill history per deal, in execution order
+    pub fills: HashMap<DealId, Vec<Fill>>,
+    /// Account credited with transaction fees
+    pub fee_collector: Address,
+    /// L1 tx hashes of applied deposits, so a re-submitted deposit is not
+    /// credited twice
+    pub processed_deposits: HashSet<[u8; 32]>,
+    /// `processed_deposits` ordered by the block timestamp they were applied
+    /// at, for pruning
+    pub processed_deposits_by_time: BTreeSet<(u64, [u8; 32])>,
+    /// Assets that settle on each chain. While empty, any asset may be
+    /// traded; once populated, deals may only use registered pairs.
+    pub assets: HashMap<(AssetId, ChainId), Asset>,
+    /// Lookup from a deal's maker and `external_ref` to its id, so each
+    /// maker has refs of their own. Derived from `deals`, so it is not
+    /// serialized; call `rebuild_derived_indexes` after loading.
+    #[serde(skip)]
+    pub external_ref_index: HashMap<(Address, String), DealId>,
+    /// Pending deals ordered by `(expires_at, deal_id)`, and deals with
+    /// open escrows by their earliest refund time, so deals due for the
+    /// expiry sweep can be found without scanning. Entries of deals that
+    /// moved on are left in place, so callers must re-check the deal. Not
+    /// serialized; call `rebuild_derived_indexes` after loading.
+    #[serde(skip)]
+    pub expiry_index: BTreeSet<(u64, DealId)>,
+    /// Ids of each maker's pending or settling deals, in id order. Kept up
+    /// to date by the STF as deals open and close; derived from `deals`, so
+    /// it is not serialized; call `rebuild_derived_indexes` after loading.
+    #[serde(skip)]
+    pub open_deals: HashMap<Address, BTreeSet<DealId>>,
+    /// Public pending deals per pair, ordered by `(price_quote_per_base,
+    /// deal_id)` so the cheapest is found without scanning every deal.
+    /// Entries of deals that close are left in place, so callers must
+    /// re-check the deal. Not serialized; call
+    /// `rebuild_derived_indexes` after loading.
+    #[serde(skip)]
+    pub pair_index: HashMap<DealPair, BTreeSet<(u128, DealId)>>,
+    /// Cached Merkle tree behind `root`. Accounts and deals touched through
+    /// the accessors below are marked dirty; code that mutates `accounts` or
+    /// `deals` directly must call `invalidate_root` afterwards.
+    #[serde(skip)]
+    pub merkle: StateMerkle,
+    /// Open while a block is applied in place, so it can be reverted
+    #[serde(skip)]
+    journal: Option<journal::Journal>,
 }
 
 impl State {
@@ -16,6 +82,17 @@ impl State {
             deals: HashMap::new(),
             account_index: HashMap::new(),
             next_account_id: 0,
+            fills: HashMap::new(),
+            fee_collector: ZERO_ADDRESS,
+            processed_deposits: HashSet::new(),
+            processed_deposits_by_time: BTreeSet::new(),
+            assets: HashMap::new(),
+            external_ref_index: HashMap::new(),
+            expiry_index: BTreeSet::new(),
+            open_deals: HashMap::new(),
+            pair_index: HashMap::new(),
+            merkle: StateMerkle::default(),
+            journal: None,
         }
     }
 
@@ -24,10 +101,19 @@ impl State {
     }
 
     pub fn get_account_mut(&mut self, id: AccountId) -> Option<&mut Account> {
+        self.touch_account(id);
         self.accounts.get_mut(&id)
     }
 
+    /// Insert or replace an account, keeping `next_account_id` past its id
     pub fn upsert_account(&mut self, account: Account) {
+        if account.id >= self.next_account_id {
+            self.next_account_id = account
+                .id
+                .checked_add(1)
+                .expect("account id space exhausted");
+        }
+        self.touch_account(account.id);
         self.account_index.insert(account.owner, account.id);
         self.accounts.insert(account.id, account);
     }
@@ -37,29 +123,284 @@ impl State {
     }
 
     pub fn get_deal_mut(&mut self, id: DealId) -> Option<&mut Deal> {
+        self.touch_deal(id);
         self.deals.get_mut(&id)
     }
 
+    /// Put a deal back in the expiry and pair indexes after its status or
+    /// escrow changed. Stale entries are skipped by readers, so nothing is
+    /// removed.
+    pub fn reindex_deal(&mut self, id: DealId) {
+        let Some(deal) = self.deals.get(&id) else {
+            return;
+        };
+        if let Some(expires_at) = expiry_of(deal) {
+            self.expiry_index.insert((expires_at, id));
+        }
+        if is_matchable(deal) {
+            self.pair_index
+                .entry(pair_of(deal))
+                .or_default()
+                .insert((deal.price_quote_per_base, id));
+        }
+    }
+
     pub fn upsert_deal(&mut self, deal: Deal) {
+        if let Some(ref external_ref) = deal.external_ref {
+            self.external_ref_index
+                .insert((deal.maker, external_ref.clone()), deal.id);
+        }
+        if let Some(expires_at) = expiry_of(&deal) {
+            self.expiry_index.insert((expires_at, deal.id));
+        }
+        if is_matchable(&deal) {
+            self.pair_index
+                .entry(pair_of(&deal))
+                .or_default()
+                .insert((deal.price_quote_per_base, deal.id));
+        }
+        self.touch_deal(deal.id);
         self.deals.insert(deal.id, deal);
     }
 
+    /// Merkle root over all accounts and deals, matching the prover's state
+    /// root. Only leaves changed since the last call are rehashed.
+    pub fn root(&mut self) -> [u8; 32] {
+        self.merkle.root(&self.accounts, &self.deals)
+    }
+
+    /// `root` with the tree hashing under `algo` from now on. Switching
+    /// algorithms rebuilds the tree once.
+    pub fn root_with(&mut self, algo: HashAlgo) -> [u8; 32] {
+        self.merkle.set_algo(algo);
+        self.root()
+    }
+
+    /// Discard the cached tree after mutating `accounts` or `deals` directly
+    pub fn invalidate_root(&mut self) {
+        self.merkle.invalidate();
+    }
+
+    pub fn record_fill(&mut self, fill: Fill) {
+        self.journal_fill(fill.deal_id);
+        self.fills.entry(fill.deal_id).or_default().push(fill);
+    }
+
+    pub fn get_deal_fills(&self, deal_id: DealId) -> &[Fill] {
+        self.fills
+            .get(&deal_id)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    pub fn is_deposit_processed(&self, tx_hash: &[u8; 32]) -> bool {
+        self.processed_deposits.contains(tx_hash)
+    }
+
+    /// Remember a deposit applied at `timestamp`. Returns false if it was
+    /// already recorded.
+    pub fn record_deposit(&mut self, tx_hash: [u8; 32], timestamp: u64) -> bool {
+        if !self.processed_deposits.insert(tx_hash) {
+            return false;
+        }
+        self.processed_deposits_by_time.insert((timestamp, tx_hash));
+        self.journal_deposits(&[(timestamp, tx_hash)], &[]);
+        true
+    }
+
+    /// Forget deposits applied before `timestamp`, returning how many were
+    /// dropped. A pruned deposit would be credited again if re-submitted.
+    pub fn prune_processed_deposits(&mut self, timestamp: u64) -> usize {
+        let kept = self
+            .processed_deposits_by_time
+            .split_off(&(timestamp, [0u8; 32]));
+        let pruned = std::mem::replace(&mut self.processed_deposits_by_time, kept);
+        for (_, tx_hash) in &pruned {
+            self.processed_deposits.remove(tx_hash);
+        }
+        if self.journal.is_some() {
+            let pruned: Vec<_> = pruned.iter().copied().collect();
+            self.journal_deposits(&[], &pruned);
+        }
+        pruned.len()
+    }
+
+    pub fn register_asset(&mut self, asset: Asset) {
+        self.assets.insert((asset.id, asset.chain_id), asset);
+    }
+
+    pub fn get_asset(&self, asset_id: AssetId, chain_id: ChainId) -> Option<&Asset> {
+        self.assets.get(&(asset_id, chain_id))
+    }
+
+    pub fn get_deal_by_external_ref(&self, maker: Address, external_ref: &str) -> Option<&Deal> {
+        self.external_ref_index
+            .get(&(maker, external_ref.to_string()))
+            .and_then(|id| self.deals.get(id))
+    }
+
+    /// Rebuild every index derived from `deals`, as needed after loading
+    /// a state or replacing its deals wholesale
+    pub fn rebuild_derived_indexes(&mut self) {
+        self.rebuild_external_ref_index();
+        self.rebuild_expiry_index();
+        self.rebuild_open_deal_index();
+        self.rebuild_pair_index();
+    }
+
+    pub fn rebuild_external_ref_index(&mut self) {
+        self.external_ref_index = self
+            .deals
+            .values()
+            .filter_map(|deal| {
+                let external_ref = deal.external_ref.clone()?;
+                Some(((deal.maker, external_ref), deal.id))
+            })
+            .collect();
+    }
+
+    pub fn rebuild_expiry_index(&mut self) {
+        self.expiry_index = self
+            .deals
+            .values()
+            .filter_map(|deal| expiry_of(deal).map(|expires_at| (expires_at, deal.id)))
+            .collect();
+    }
+
+    pub fn rebuild_pair_index(&mut self) {
+        self.pair_index.clear();
+        for deal in self.deals.values().filter(|deal| is_matchable(deal)) {
+            self.pair_index
+                .entry(pair_of(deal))
+                .or_default()
+                .insert((deal.price_quote_per_base, deal.id));
+        }
+    }
+
+    /// Cheapest public pending deal on `pair` with at least `min_amount` of
+    /// base left and a price of at most `max_price`, skipping deals expired
+    /// at `now`. Ties go to the older deal.
+    pub fn find_matching_deal(
+        &self,
+        pair: DealPair,
+        min_amount: u128,
+        max_price: u128,
+        now: u64,
+    ) -> Option<DealId> {
+        self.pair_index
+            .get(&pair)?
+            .range(..=(max_price, DealId::MAX))
+            .find(|(price, deal_id)| {
+                self.deals.get(deal_id).is_some_and(|deal| {
+                    is_matchable(deal)
+                        && deal.price_quote_per_base == *price
+                        && deal.amount_remaining >= min_amount.max(1)
+                        && !deal.is_expired_at(now)
+                })
+            })
+            .map(|&(_, deal_id)| deal_id)
+    }
+
+    /// Order book of `pair`: the summed `amount_remaining` of live public
+    /// pending deals at each price, cheapest first. Deals expired at `now`
+    /// are left out.
+    pub fn book_depth(&self, pair: DealPair, now: u64) -> Vec<(u128, u128)> {
+        let mut levels: Vec<(u128, u128)> = Vec::new();
+        let Some(entries) = self.pair_index.get(&pair) else {
+            return levels;
+        };
+
+        for (price, deal_id) in entries {
+            let Some(deal) = self.deals.get(deal_id).filter(|deal| {
+                is_matchable(deal)
+                    && deal.price_quote_per_base == *price
+                    && deal.amount_remaining > 0
+                    && !deal.is_expired_at(now)
+            }) else {
+                continue;
+            };
+            match levels.last_mut() {
+                Some((level_price, amount)) if level_price == price => {
+                    *amount = amount.saturating_add(deal.amount_remaining);
+                }
+                _ => levels.push((*price, deal.amount_remaining)),
+            }
+        }
+        levels
+    }
+
+    pub fn open_deal_count(&self, maker: Address) -> usize {
+        self.open_deals.get(&maker).map_or(0, BTreeSet::len)
+    }
+
+    /// Ids of `maker`'s pending or settling deals, lowest first
+    pub fn open_deal_ids(&self, maker: Address) -> impl Iterator<Item = DealId> + '_ {
+        self.open_deals.get(&maker).into_iter().flatten().copied()
+    }
+
+    /// Index a newly opened deal of `maker`
+    pub fn record_deal_opened(&mut self, maker: Address, deal_id: DealId) {
+        self.open_deals.entry(maker).or_default().insert(deal_id);
+    }
+
+    /// Drop a deal of `maker` closing for good from the index
+    pub fn record_deal_closed(&mut self, maker: Address, deal_id: DealId) {
+        if let Some(deal_ids) = self.open_deals.get_mut(&maker) {
+            deal_ids.remove(&deal_id);
+            if deal_ids.is_empty() {
+                self.open_deals.remove(&maker);
+            }
+        }
+    }
+
+    pub fn rebuild_open_deal_index(&mut self) {
+        self.open_deals.clear();
+        for deal in self.deals.values() {
+            if matches!(deal.status, DealStatus::Pending | DealStatus::Settling) {
+                self.open_deals
+                    .entry(deal.maker)
+                    .or_default()
+                    .insert(deal.id);
+            }
+        }
+    }
+
+    /// Remove and return the ids of indexed deals with `expires_at < now`,
+    /// earliest first
+    pub fn take_expired_deals(&mut self, now: u64) -> Vec<DealId> {
+        let mut expired = Vec::new();
+        while let Some(&(expires_at, deal_id)) = self.expiry_index.first() {
+            if expires_at >= now {
+                break;
+            }
+            self.expiry_index.pop_first();
+            expired.push(deal_id);
+        }
+        expired
+    }
+
     pub fn get_or_create_account_by_owner(&mut self, owner: Address) -> &mut Account {
         if let Some(id) = self.account_index.get(&owner).cloned() {
+            self.touch_account(id);
             return self.accounts.get_mut(&id).expect("inconsistent state");
         }
 
         let id = self.next_account_id;
-        self.next_account_id = self.next_account_id.wrapping_add(1);
+        self.next_account_id = self
+            .next_account_id
+            .checked_add(1)
+            .expect("account id space exhausted");
 
         let account = Account {
             id,
             owner,
-            balances: Vec::new(),
+            balances: Balances::new(),
+            reserved: Balances::new(),
             nonce: 0,
             created_at: 0,
         };
 
+        self.touch_account(id);
         self.accounts.insert(id, account);
         self.account_index.insert(owner, id);
         self.accounts.get_mut(&id).expect("just inserted")
@@ -72,6 +413,35 @@ impl State {
     }
 }
 
+fn pair_of(deal: &Deal) -> DealPair {
+    (
+        deal.asset_base,
+        deal.asset_quote,
+        deal.chain_id_base,
+        deal.chain_id_quote,
+    )
+}
+
+/// Whether any taker may accept the deal
+fn is_matchable(deal: &Deal) -> bool {
+    deal.status == DealStatus::Pending && deal.visibility == DealVisibility::Public
+}
+
+/// When the expiry sweep next has to look at a deal: the expiry time of a
+/// pending deal that can expire, or the earliest refund time of its open
+/// escrows, whichever comes first. An `expires_at` of 0 means the deal
+/// never expires.
+fn expiry_of(deal: &Deal) -> Option<u64> {
+    let expires_at = match deal.status {
+        DealStatus::Pending => deal.expires_at.filter(|&t| t > 0),
+        _ => None,
+    };
+    match (expires_at, deal.next_refund_at()) {
+        (Some(expires_at), Some(refund_at)) => Some(expires_at.min(refund_at)),
+        (expires_at, refund_at) => expires_at.or(refund_at),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -128,11 +498,14 @@ mod tests {
         let account = Account {
             id: 0,
             owner: addr,
-            balances: vec![Balance {
+            balances: [Balance {
                 asset_id: 0,
                 amount: 100,
                 chain_id: zkclear_types::chain_ids::ETHEREUM,
-            }],
+            }]
+            .into_iter()
+            .collect(),
+            reserved: Balances::new(),
             nonce: 5,
             created_at: 1000,
         };
@@ -145,6 +518,35 @@ mod tests {
         assert_eq!(retrieved.unwrap().balances.len(), 1);
     }
 
+    #[test]
+    fn test_account_ids_continue_after_snapshot_reload() {
+        let mut state = State::new();
+        for byte in 1..=3 {
+            state.get_or_create_account_by_owner(dummy_address(byte));
+        }
+        // An account inserted directly with an id beyond the counter
+        let mut imported = state
+            .get_account_by_address(dummy_address(1))
+            .unwrap()
+            .clone();
+        imported.id = 10;
+        imported.owner = dummy_address(4);
+        state.upsert_account(imported);
+
+        let mut reloaded: State =
+            bincode::deserialize(&bincode::serialize(&state).unwrap()).unwrap();
+        assert_eq!(reloaded.next_account_id, 11);
+        assert_eq!(reloaded.account_index, state.account_index);
+
+        let id = reloaded.get_or_create_account_by_owner(dummy_address(5)).id;
+        assert!(state.accounts.keys().all(|existing| id > *existing));
+        assert_eq!(reloaded.accounts.len(), 5);
+        assert_eq!(reloaded.account_index.len(), 5);
+        for (owner, id) in &reloaded.account_index {
+            assert_eq!(reloaded.accounts[id].owner, *owner);
+        }
+    }
+
     #[test]
     fn test_upsert_deal() {
         let mut state = State::new();
@@ -164,9 +566,11 @@ mod tests {
             status: DealStatus::Pending,
             visibility: DealVisibility::Public,
             created_at: 1000,
+            updated_at: 1000,
             expires_at: None,
             external_ref: None,
             is_cross_chain: false,
+            escrows: Vec::new(),
         };
 
         state.upsert_deal(deal);
@@ -177,6 +581,244 @@ mod tests {
         assert_eq!(retrieved.unwrap().amount_base, 1000);
     }
 
+    #[test]
+    fn test_external_ref_index_rebuild() {
+        let mut state = State::new();
+
+        let deal = Deal {
+            id: 7,
+            maker: dummy_address(1),
+            taker: None,
+            asset_base: 0,
+            asset_quote: 1,
+            chain_id_base: zkclear_types::chain_ids::ETHEREUM,
+            chain_id_quote: zkclear_types::chain_ids::ETHEREUM,
+            amount_base: 1000,
+            amount_remaining: 1000,
+            price_quote_per_base: 100,
+            status: DealStatus::Pending,
+            visibility: DealVisibility::Public,
+            created_at: 1000,
+            updated_at: 1000,
+            expires_at: None,
+            external_ref: Some("client-order-7".to_string()),
+            is_cross_chain: false,
+            escrows: Vec::new(),
+        };
+        state.upsert_deal(deal);
+        assert_eq!(
+            state.get_deal_by_external_ref(dummy_address(1), "client-order-7").unwrap().id,
+            7
+        );
+        // Refs are scoped to their maker
+        assert!(state
+            .get_deal_by_external_ref(dummy_address(2), "client-order-7")
+            .is_none());
+
+        // The index is not serialized; it must be restored from the deals
+        state.external_ref_index.clear();
+        assert!(state
+            .get_deal_by_external_ref(dummy_address(1), "client-order-7")
+            .is_none());
+        state.rebuild_external_ref_index();
+        assert_eq!(
+            state.get_deal_by_external_ref(dummy_address(1), "client-order-7").unwrap().id,
+            7
+        );
+    }
+
+    #[test]
+    fn test_take_expired_deals() {
+        let mut state = State::new();
+        for (id, expires_at) in [(1, Some(300)), (2, Some(100)), (3, None), (4, Some(0))] {
+            state.upsert_deal(Deal {
+                id,
+                maker: dummy_address(1),
+                taker: None,
+                asset_base: 0,
+                asset_quote: 1,
+                chain_id_base: zkclear_types::chain_ids::ETHEREUM,
+                chain_id_quote: zkclear_types::chain_ids::ETHEREUM,
+                amount_base: 1000,
+                amount_remaining: 1000,
+                price_quote_per_base: 100,
+                status: DealStatus::Pending,
+                visibility: DealVisibility::Public,
+                created_at: 0,
+                updated_at: 0,
+                expires_at,
+                external_ref: None,
+                is_cross_chain: false,
+                escrows: Vec::new(),
+            });
+        }
+
+        assert!(state.take_expired_deals(100).is_empty());
+        assert_eq!(state.take_expired_deals(301), vec![2, 1]);
+        assert!(state.take_expired_deals(u64::MAX).is_empty());
+
+        state.rebuild_expiry_index();
+        assert_eq!(state.take_expired_deals(u64::MAX), vec![2, 1]);
+    }
+
+    #[test]
+    fn test_find_matching_deal_picks_cheapest() {
+        let eth = zkclear_types::chain_ids::ETHEREUM;
+        let pair = (0, 1, eth, eth);
+        let mut state = State::new();
+        // (id, price, remaining, visibility, expires_at)
+        for (id, price, remaining, visibility, expires_at) in [
+            (1, 120, 1000, DealVisibility::Public, None),
+            (2, 90, 1000, DealVisibility::Direct, None),
+            (3, 100, 50, DealVisibility::Public, None),
+            (4, 100, 1000, DealVisibility::Public, None),
+            (5, 95, 1000, DealVisibility::Public, Some(500)),
+            (6, 100, 1000, DealVisibility::Public, None),
+        ] {
+            state.upsert_deal(Deal {
+                id,
+                maker: dummy_address(1),
+                taker: None,
+                asset_base: 0,
+                asset_quote: 1,
+                chain_id_base: eth,
+                chain_id_quote: eth,
+                amount_base: 1000,
+                amount_remaining: remaining,
+                price_quote_per_base: price,
+                status: DealStatus::Pending,
+                visibility,
+                created_at: 0,
+                updated_at: 0,
+                expires_at,
+                external_ref: None,
+                is_cross_chain: false,
+                escrows: Vec::new(),
+            });
+        }
+
+        assert_eq!(state.find_matching_deal(pair, 100, 200, 400), Some(5));
+        // Once 5 has expired, the older of the two at 100
+        assert_eq!(state.find_matching_deal(pair, 100, 200, 600), Some(4));
+        assert_eq!(state.find_matching_deal(pair, 10, 100, 600), Some(3));
+        assert_eq!(state.find_matching_deal(pair, 100, 99, 600), None);
+        assert_eq!(state.find_matching_deal((1, 0, eth, eth), 1, 200, 0), None);
+
+        state.get_deal_mut(4).unwrap().status = DealStatus::Settled;
+        state.rebuild_pair_index();
+        assert_eq!(state.find_matching_deal(pair, 100, 200, 600), Some(6));
+    }
+
+    #[test]
+    fn test_incremental_root_matches_rebuild() {
+        let mut state = State::new();
+        assert_eq!(state.root(), [0u8; 32]);
+
+        let deal = |id: DealId| Deal {
+            id,
+            maker: dummy_address(1),
+            taker: None,
+            asset_base: 0,
+            asset_quote: 1,
+            chain_id_base: zkclear_types::chain_ids::ETHEREUM,
+            chain_id_quote: zkclear_types::chain_ids::ETHEREUM,
+            amount_base: 1000,
+            amount_remaining: 1000,
+            price_quote_per_base: 100,
+            status: DealStatus::Pending,
+            visibility: DealVisibility::Public,
+            created_at: 0,
+            updated_at: 0,
+            expires_at: None,
+            external_ref: None,
+            is_cross_chain: false,
+            escrows: Vec::new(),
+        };
+
+        for round in 0u8..12 {
+            // New accounts and balance changes on existing ones
+            for byte in 0..=round % 4 {
+                let account = state.get_or_create_account_by_owner(dummy_address(byte));
+                account.credit(
+                    byte as zkclear_types::AssetId,
+                    zkclear_types::chain_ids::ETHEREUM,
+                    round as u128 + 1,
+                );
+                account.nonce += 1;
+            }
+            // Deals inserted out of id order shift the leaves after them
+            state.upsert_deal(deal(100 - round as DealId * 7 % 50));
+            if let Some(deal) = state.get_deal_mut(100) {
+                deal.amount_remaining -= 10;
+            }
+            // An account replaced wholesale
+            if round % 3 == 0 {
+                let mut account = state.get_account(0).unwrap().clone();
+                account.created_at = round as u64;
+                state.upsert_account(account);
+            }
+            // Touched but unchanged leaves leave the root alone
+            let _ = state.get_account_mut(1);
+
+            assert_eq!(
+                state.root(),
+                StateMerkle::compute_root(&state.accounts, &state.deals),
+                "round {}",
+                round
+            );
+        }
+
+        // A deserialized state rebuilds its tree from scratch
+        let root = state.root();
+        let mut restored: State =
+            bincode::deserialize(&bincode::serialize(&state).unwrap()).unwrap();
+        assert_eq!(restored.root(), root);
+    }
+
+    #[test]
+    fn test_root_with_keccak() {
+        let hex = |bytes: [u8; 32]| {
+            bytes
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<String>()
+        };
+        assert_eq!(
+            hex(HashAlgo::Sha256.hash(b"")),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hex(HashAlgo::Keccak256.hash(b"")),
+            "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"
+        );
+        assert_eq!("keccak256".parse(), Ok(HashAlgo::Keccak256));
+        assert_eq!("SHA-256".parse(), Ok(HashAlgo::Sha256));
+
+        let mut state = State::new();
+        for byte in 0..5 {
+            state
+                .get_or_create_account_by_owner(dummy_address(byte))
+                .nonce = byte as u64;
+        }
+        let sha_root = state.root();
+        let keccak_root = state.root_with(HashAlgo::Keccak256);
+        assert_ne!(keccak_root, sha_root);
+
+        // Incremental updates keep hashing with Keccak-256
+        state.get_or_create_account_by_owner(dummy_address(2)).nonce = 9;
+        state.get_or_create_account_by_owner(dummy_address(9));
+        assert_eq!(
+            state.root(),
+            StateMerkle::compute_root_with(HashAlgo::Keccak256, &state.accounts, &state.deals)
+        );
+        state.invalidate_root();
+        assert_eq!(state.merkle.algo(), HashAlgo::Keccak256);
+        assert_eq!(
+            state.root_with(HashAlgo::Sha256),
+            StateMerkle::compute_root(&state.accounts, &state.deals)
+        );
+    }
+
     #[test]
     fn test_multiple_accounts() {
         let mut state = State::new();
@@ -0,0 +1,317 @@
+//! Incrementally maintained Merkle root over the state
+//!
+//! Leaves are `H(bincode(account))` for every account in id order,
+//! followed by `H(bincode(deal))` for every deal in id order. Interior
+//! nodes are `H(left || right)`, with an odd trailing node paired with
+//! itself. `H` is the tree's `HashAlgo`, SHA-256 unless configured
+//! otherwise. This is the same tree the prover builds from scratch.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use sha2::{Digest, Sha256};
+use sha3::Keccak256;
+use zkclear_types::{Account, AccountId, Deal, DealId};
+
+/// Hash function of a Merkle tree. Roots checked on chain should use the
+/// hash that is cheapest for the destination contract.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize,
+)]
+pub enum HashAlgo {
+    #[default]
+    Sha256,
+    Keccak256,
+}
+
+impl HashAlgo {
+    pub fn hash(self, data: &[u8]) -> [u8; 32] {
+        match self {
+            HashAlgo::Sha256 => Sha256::digest(data).into(),
+            HashAlgo::Keccak256 => Keccak256::digest(data).into(),
+        }
+    }
+
+    /// Interior node over `left || right`
+    pub fn hash_pair(self, left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+        let mut data = [0u8; 64];
+        data[..32].copy_from_slice(left);
+        data[32..].copy_from_slice(right);
+        self.hash(&data)
+    }
+}
+
+impl std::str::FromStr for HashAlgo {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().replace(['-', '_'], "").as_str() {
+            "sha256" => Ok(HashAlgo::Sha256),
+            "keccak256" | "keccak" => Ok(HashAlgo::Keccak256),
+            _ => Err(format!("unknown hash algorithm {:?}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct StateMerkle {
+    algo: HashAlgo,
+    account_leaves: BTreeMap<AccountId, [u8; 32]>,
+    deal_leaves: BTreeMap<DealId, [u8; 32]>,
+    /// `levels[0]` holds the leaves in tree order, the last level the root
+    levels: Vec<Vec<[u8; 32]>>,
+    dirty_accounts: BTreeSet<AccountId>,
+    dirty_deals: BTreeSet<DealId>,
+    /// Cleared for a fresh or deserialized state, forcing a full build
+    initialized: bool,
+}
+
+impl StateMerkle {
+    pub fn mark_account(&mut self, id: AccountId) {
+        self.dirty_accounts.insert(id);
+    }
+
+    pub fn mark_deal(&mut self, id: DealId) {
+        self.dirty_deals.insert(id);
+    }
+
+    /// Drop all cached hashes so the next `root` rebuilds from scratch
+    pub fn invalidate(&mut self) {
+        *self = Self::with_algo(self.algo);
+    }
+
+    /// Empty tree hashing with `algo`
+    pub fn with_algo(algo: HashAlgo) -> Self {
+        Self {
+            algo,
+            ..Self::default()
+        }
+    }
+
+    pub fn algo(&self) -> HashAlgo {
+        self.algo
+    }
+
+    /// Switch the hash function; cached hashes are dropped if it changes
+    pub fn set_algo(&mut self, algo: HashAlgo) {
+        if algo != self.algo {
+            *self = Self::with_algo(algo);
+        }
+    }
+
+    /// Bring the tree up to date and return the root. Only dirty leaves are
+    /// rehashed. A changed leaf updates its path to the root; an added or
+    /// removed leaf shifts the ones after it, so interior nodes from that
+    /// position onwards are recomputed.
+    pub fn root(
+        &mut self,
+        accounts: &HashMap<AccountId, Account>,
+        deals: &HashMap<DealId, Deal>,
+    ) -> [u8; 32] {
+        if !self.initialized {
+            self.build(accounts, deals);
+        } else if !self.dirty_accounts.is_empty() || !self.dirty_deals.is_empty() {
+            self.update(accounts, deals);
+        }
+
+        match self.levels.last() {
+            Some(top) if !top.is_empty() => top[0],
+            _ => [0u8; 32],
+        }
+    }
+
+    /// SHA-256 root computed from scratch, without touching any cache
+    pub fn compute_root(
+        accounts: &HashMap<AccountId, Account>,
+        deals: &HashMap<DealId, Deal>,
+    ) -> [u8; 32] {
+        Self::compute_root_with(HashAlgo::Sha256, accounts, deals)
+    }
+
+    /// Root under `algo` computed from scratch, without touching any cache
+    pub fn compute_root_with(
+        algo: HashAlgo,
+        accounts: &HashMap<AccountId, Account>,
+        deals: &HashMap<DealId, Deal>,
+    ) -> [u8; 32] {
+        Self::with_algo(algo).root(accounts, deals)
+    }
+
+    fn build(&mut self, accounts: &HashMap<AccountId, Account>, deals: &HashMap<DealId, Deal>) {
+        self.account_leaves = accounts
+            .iter()
+            .map(|(id, account)| (*id, account_leaf(self.algo, account)))
+            .collect();
+        self.deal_leaves = deals
+            .iter()
+            .map(|(id, deal)| (*id, deal_leaf(self.algo, deal)))
+            .collect();
+        self.dirty_accounts.clear();
+        self.dirty_deals.clear();
+        self.levels.clear();
+        self.rebuild_from(0);
+        self.initialized = true;
+    }
+
+    fn update(&mut self, accounts: &HashMap<AccountId, Account>, deals: &HashMap<DealId, Deal>) {
+        let mut changed_accounts = Vec::new();
+        let mut changed_deals = Vec::new();
+        // Leaf position from which the layout changed, if any
+        let mut shifted_from: Option<usize> = None;
+
+        for id in std::mem::take(&mut self.dirty_accounts) {
+            let leaf = accounts.get(&id).map(|a| account_leaf(self.algo, a));
+            match apply_leaf(&mut self.account_leaves, id, leaf) {
+                LeafChange::Unchanged => {}
+                LeafChange::Updated => changed_accounts.push(id),
+                LeafChange::Shifted => {
+                    let pos = self.account_leaves.range(..id).count();
+                    shifted_from = Some(shifted_from.map_or(pos, |p| p.min(pos)));
+                }
+            }
+        }
+
+        for id in std::mem::take(&mut self.dirty_deals) {
+            let leaf = deals.get(&id).map(|d| deal_leaf(self.algo, d));
+            match apply_leaf(&mut self.deal_leaves, id, leaf) {
+                LeafChange::Unchanged => {}
+                LeafChange::Updated => changed_deals.push(id),
+                LeafChange::Shifted => {
+                    let pos = self.account_leaves.len() + self.deal_leaves.range(..id).count();
+                    shifted_from = Some(shifted_from.map_or(pos, |p| p.min(pos)));
+                }
+            }
+        }
+
+        let mut positions = Vec::with_capacity(changed_accounts.len() + changed_deals.len());
+        positions.extend(
+            changed_accounts
+                .iter()
+                .map(|id| self.account_leaves.range(..id).count()),
+        );
+        positions.extend(
+            changed_deals
+                .iter()
+                .map(|id| self.account_leaves.len() + self.deal_leaves.range(..id).count()),
+        );
+
+        if let Some(shifted_from) = shifted_from {
+            let from = positions.iter().copied().fold(shifted_from, usize::min);
+            self.rebuild_from(from);
+            return;
+        }
+
+        for pos in positions {
+            self.levels[0][pos] = self.leaf_at(pos);
+            self.update_path(pos);
+        }
+    }
+
+    fn leaf_at(&self, pos: usize) -> [u8; 32] {
+        let accounts = self.account_leaves.len();
+        if pos < accounts {
+            *self
+                .account_leaves
+                .values()
+                .nth(pos)
+                .expect("position in range")
+        } else {
+            *self
+                .deal_leaves
+                .values()
+                .nth(pos - accounts)
+                .expect("position in range")
+        }
+    }
+
+    /// Recompute the ancestors of the leaf at `pos`
+    fn update_path(&mut self, pos: usize) {
+        let mut index = pos;
+        for level in 0..self.levels.len() - 1 {
+            let nodes = &self.levels[level];
+            let left = index & !1;
+            let right = if left + 1 < nodes.len() {
+                left + 1
+            } else {
+                left
+            };
+            let parent = self.algo.hash_pair(&nodes[left], &nodes[right]);
+            index /= 2;
+            self.levels[level + 1][index] = parent;
+        }
+    }
+
+    /// Reload the leaves and recompute every node that covers a leaf at or
+    /// after `from`; nodes entirely to its left are kept
+    fn rebuild_from(&mut self, from: usize) {
+        let leaves: Vec<[u8; 32]> = self
+            .account_leaves
+            .values()
+            .chain(self.deal_leaves.values())
+            .copied()
+            .collect();
+
+        if self.levels.is_empty() {
+            self.levels.push(Vec::new());
+        }
+        self.levels[0] = leaves;
+
+        let algo = self.algo;
+        let mut level = 0;
+        let mut start = from;
+        while self.levels[level].len() > 1 {
+            let len = self.levels[level].len();
+            let parent_start = start / 2;
+
+            if self.levels.len() == level + 1 {
+                self.levels.push(Vec::new());
+            }
+            let (lower, upper) = self.levels.split_at_mut(level + 1);
+            let nodes = &lower[level];
+            let parents = &mut upper[0];
+
+            parents.truncate(parent_start);
+            for i in (parent_start * 2..len).step_by(2) {
+                let right = if i + 1 < len { i + 1 } else { i };
+                parents.push(algo.hash_pair(&nodes[i], &nodes[right]));
+            }
+
+            level += 1;
+            start = parent_start;
+        }
+
+        self.levels.truncate(level + 1);
+    }
+}
+
+enum LeafChange {
+    Unchanged,
+    Updated,
+    /// Leaf inserted or removed, moving every leaf after it
+    Shifted,
+}
+
+fn apply_leaf<K: Ord>(
+    leaves: &mut BTreeMap<K, [u8; 32]>,
+    id: K,
+    leaf: Option<[u8; 32]>,
+) -> LeafChange {
+    match leaf {
+        Some(leaf) => match leaves.insert(id, leaf) {
+            None => LeafChange::Shifted,
+            Some(old) if old == leaf => LeafChange::Unchanged,
+            Some(_) => LeafChange::Updated,
+        },
+        None => match leaves.remove(&id) {
+            Some(_) => LeafChange::Shifted,
+            None => LeafChange::Unchanged,
+        },
+    }
+}
+
+fn account_leaf(algo: HashAlgo, account: &Account) -> [u8; 32] {
+    algo.hash(&bincode::serialize(account).expect("account serialization cannot fail"))
+}
+
+fn deal_leaf(algo: HashAlgo, deal: &Deal) -> [u8; 32] {
+    algo.hash(&bincode::serialize(deal).expect("deal serialization cannot fail"))
+}
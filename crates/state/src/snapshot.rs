@@ -0,0 +1,220 @@
+//! Chunked snapshot encoding, so a large state is written and read a few
+//! thousand entries at a time instead of as one multi-hundred-MB blob.
+//!
+//! A snapshot is a manifest holding everything but the accounts and deals,
+//! followed by `chunk_count` chunks of at most `chunk_size` accounts or
+//! deals each, in ascending id order.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use zkclear_types::{Account, AccountId, Address, Asset, AssetId, ChainId, Deal, DealId, Fill};
+
+use crate::State;
+
+/// Accounts or deals per snapshot chunk
+pub const DEFAULT_SNAPSHOT_CHUNK_SIZE: usize = 10_000;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SnapshotError {
+    Serialization,
+    Deserialization,
+    /// The number of chunks read does not match the manifest
+    ChunkCountMismatch {
+        expected: u32,
+        found: u32,
+    },
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Manifest {
+    next_account_id: AccountId,
+    fills: HashMap<DealId, Vec<Fill>>,
+    fee_collector: Address,
+    processed_deposits: HashSet<[u8; 32]>,
+    processed_deposits_by_time: BTreeSet<(u64, [u8; 32])>,
+    assets: HashMap<(AssetId, ChainId), Asset>,
+    chunk_count: u32,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+enum Chunk {
+    Accounts(Vec<Account>),
+    Deals(Vec<Deal>),
+}
+
+impl State {
+    /// Encoded manifest of a snapshot whose chunks come from
+    /// `snapshot_chunks` with the same `chunk_size`
+    pub fn snapshot_manifest(&self, chunk_size: usize) -> Result<Vec<u8>, SnapshotError> {
+        let chunk_size = chunk_size.max(1);
+        let chunk_count =
+            self.accounts.len().div_ceil(chunk_size) + self.deals.len().div_ceil(chunk_size);
+        let manifest = Manifest {
+            next_account_id: self.next_account_id,
+            fills: self.fills.clone(),
+            fee_collector: self.fee_collector,
+            processed_deposits: self.processed_deposits.clone(),
+            processed_deposits_by_time: self.processed_deposits_by_time.clone(),
+            assets: self.assets.clone(),
+            chunk_count: u32::try_from(chunk_count).map_err(|_| SnapshotError::Serialization)?,
+        };
+        bincode::serialize(&manifest).map_err(|_| SnapshotError::Serialization)
+    }
+
+    /// Encoded snapshot chunks, each serialized only when the iterator
+    /// reaches it
+    pub fn snapshot_chunks(
+        &self,
+        chunk_size: usize,
+    ) -> impl Iterator<Item = Result<Vec<u8>, SnapshotError>> + '_ {
+        let chunk_size = chunk_size.max(1);
+        let mut account_ids: Vec<AccountId> = self.accounts.keys().copied().collect();
+        account_ids.sort_unstable();
+        let mut deal_ids: Vec<DealId> = self.deals.keys().copied().collect();
+        deal_ids.sort_unstable();
+
+        let accounts = (0..account_ids.len())
+            .step_by(chunk_size)
+            .map(move |start| {
+                let ids = &account_ids[start..(start + chunk_size).min(account_ids.len())];
+                Chunk::Accounts(ids.iter().map(|id| self.accounts[id].clone()).collect())
+            });
+        let deals = (0..deal_ids.len()).step_by(chunk_size).map(move |start| {
+            let ids = &deal_ids[start..(start + chunk_size).min(deal_ids.len())];
+            Chunk::Deals(ids.iter().map(|id| self.deals[id].clone()).collect())
+        });
+
+        accounts
+            .chain(deals)
+            .map(|chunk| bincode::serialize(&chunk).map_err(|_| SnapshotError::Serialization))
+    }
+}
+
+/// Rebuilds a state from a manifest and its chunks, one chunk at a time
+pub struct SnapshotReader {
+    state: State,
+    chunk_count: u32,
+    chunks_read: u32,
+}
+
+impl SnapshotReader {
+    pub fn new(manifest: &[u8]) -> Result<Self, SnapshotError> {
+        let manifest: Manifest =
+            bincode::deserialize(manifest).map_err(|_| SnapshotError::Deserialization)?;
+        let state = State {
+            next_account_id: manifest.next_account_id,
+            fills: manifest.fills,
+            fee_collector: manifest.fee_collector,
+            processed_deposits: manifest.processed_deposits,
+            processed_deposits_by_time: manifest.processed_deposits_by_time,
+            assets: manifest.assets,
+            ..State::new()
+        };
+        Ok(Self {
+            state,
+            chunk_count: manifest.chunk_count,
+            chunks_read: 0,
+        })
+    }
+
+    /// Number of chunks the manifest says follow it
+    pub fn chunk_count(&self) -> u32 {
+        self.chunk_count
+    }
+
+    pub fn push_chunk(&mut self, bytes: &[u8]) -> Result<(), SnapshotError> {
+        let chunk: Chunk =
+            bincode::deserialize(bytes).map_err(|_| SnapshotError::Deserialization)?;
+        match chunk {
+            Chunk::Accounts(accounts) => {
+                for account in accounts {
+                    self.state.account_index.insert(account.owner, account.id);
+                    self.state.accounts.insert(account.id, account);
+                }
+            }
+            Chunk::Deals(deals) => {
+                for deal in deals {
+                    self.state.deals.insert(deal.id, deal);
+                }
+            }
+        }
+        self.chunks_read += 1;
+        Ok(())
+    }
+
+    /// The rebuilt state. Like a state deserialized whole, its derived
+    /// indexes still need rebuilding.
+    pub fn finish(self) -> Result<State, SnapshotError> {
+        if self.chunks_read != self.chunk_count {
+            return Err(SnapshotError::ChunkCountMismatch {
+                expected: self.chunk_count,
+                found: self.chunks_read,
+            });
+        }
+        Ok(self.state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zkclear_types::chain_ids;
+
+    fn read_back(state: &State, chunk_size: usize) -> State {
+        let mut reader =
+            SnapshotReader::new(&state.snapshot_manifest(chunk_size).unwrap()).unwrap();
+        for chunk in state.snapshot_chunks(chunk_size) {
+            reader.push_chunk(&chunk.unwrap()).unwrap();
+        }
+        reader.finish().unwrap()
+    }
+
+    #[test]
+    fn test_chunked_snapshot_round_trips_large_state() {
+        let mut state = State::new();
+        for i in 0..100_000u32 {
+            let mut owner = [0u8; 20];
+            owner[..4].copy_from_slice(&i.to_le_bytes());
+            state
+                .get_or_create_account_by_owner(owner)
+                .credit(0, chain_ids::ETHEREUM, i as u128 + 1)
+                .unwrap();
+        }
+        state.record_deposit([7u8; 32], 1000);
+        let root = state.root();
+
+        let manifest = state
+            .snapshot_manifest(DEFAULT_SNAPSHOT_CHUNK_SIZE)
+            .unwrap();
+        assert_eq!(SnapshotReader::new(&manifest).unwrap().chunk_count(), 10);
+
+        let mut loaded = read_back(&state, DEFAULT_SNAPSHOT_CHUNK_SIZE);
+        assert_eq!(loaded.accounts.len(), 100_000);
+        assert_eq!(loaded.next_account_id, state.next_account_id);
+        assert!(loaded.is_deposit_processed(&[7u8; 32]));
+        assert_eq!(loaded.root(), root);
+
+        // New accounts continue from the snapshot's ids
+        let id = loaded.get_or_create_account_by_owner([0xFF; 20]).id;
+        assert_eq!(id, 100_000);
+    }
+
+    #[test]
+    fn test_missing_chunk_rejected() {
+        let mut state = State::new();
+        for byte in 0..3 {
+            state.get_or_create_account_by_owner([byte; 20]);
+        }
+
+        let mut reader = SnapshotReader::new(&state.snapshot_manifest(2).unwrap()).unwrap();
+        let first = state.snapshot_chunks(2).next().unwrap().unwrap();
+        reader.push_chunk(&first).unwrap();
+        assert_eq!(
+            reader.finish().err(),
+            Some(SnapshotError::ChunkCountMismatch {
+                expected: 2,
+                found: 1
+            })
+        );
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::HashSet;
+use zkclear_types::{Address, Amount, AssetId, ChainId, TxKind};
+
+use crate::StfError;
+
+/// Which destinations a withdrawal may pay out to.
+/// The zero address is always rejected regardless of policy.
+#[derive(Debug, Clone, Default)]
+pub enum WithdrawalDestinationPolicy {
+    /// Any non-zero address
+    #[default]
+    AnyNonZero,
+    /// Only the withdrawing account itself
+    SenderOnly,
+    /// Only addresses in the allowlist
+    Allowlist(HashSet<Address>),
+}
+
+/// How a fill's quote amount is rounded when `amount * price` is not a
+/// multiple of `StfConfig::price_scale`. The same quote amount is debited
+/// from the taker and credited to the maker, so rounding only decides who
+/// bears the dust; value is always conserved.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QuoteRounding {
+    /// Round up: the taker pays the dust, the maker never receives less than
+    /// the exact price
+    #[default]
+    QuoteUp,
+    /// Round down: the maker forgoes the dust, the taker never pays more than
+    /// the exact price
+    QuoteDown,
+    /// Round half to even, splitting dust between both sides over many fills
+    Banker,
+}
+
+impl QuoteRounding {
+    /// Compute `numerator / denominator` rounded according to the policy
+    pub fn divide(self, numerator: u128, denominator: u128) -> u128 {
+        let quotient = numerator / denominator;
+        let remainder = numerator % denominator;
+        if remainder == 0 {
+            return quotient;
+        }
+
+        let round_up = match self {
+            QuoteRounding::QuoteUp => true,
+            QuoteRounding::QuoteDown => false,
+            QuoteRounding::Banker => {
+                // Compare remainder with denominator / 2 without overflowing
+                let rest = denominator - remainder;
+                remainder > rest || (remainder == rest && quotient % 2 == 1)
+            }
+        };
+
+        if round_up {
+            quotient + 1
+        } else {
+            quotient
+        }
+    }
+}
+
+/// `10^decimals`, or `None` past the range of `u128`
+fn pow10(decimals: u8) -> Option<u128> {
+    10u128.checked_pow(decimals as u32)
+}
+
+/// Convert `amount` from one decimal precision to another, e.g. 1_500_000
+/// at 6 decimals is 150_000_000 at 8 decimals. Scaling down truncates;
+/// `None` if scaling up overflows.
+pub fn normalize_amount(amount: u128, from_decimals: u8, to_decimals: u8) -> Option<u128> {
+    if to_decimals >= from_decimals {
+        amount.checked_mul(pow10(to_decimals - from_decimals)?)
+    } else {
+        // Past 10^38 every u128 truncates to zero
+        Some(pow10(from_decimals - to_decimals).map_or(0, |divisor| amount / divisor))
+    }
+}
+
+/// Flat fee charged on every transaction, paid by `tx.from` to the state's
+/// `fee_collector`. A tx's own `fee` is charged on top, in the same asset.
+///
+/// The fee is debited before the payload executes, so the payload sees the
+/// reduced balance (a withdrawal of the whole balance in the fee asset fails).
+/// A tx that fails for any reason, including a rejected deal acceptance, is
+/// rolled back together with its fee: only successful txs pay, and a failed
+/// tx leaves no trace in the state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeePolicy {
+    pub asset_id: AssetId,
+    pub chain_id: ChainId,
+    pub amount: u128,
+}
+
+/// Default for `StfConfig::max_external_ref_len`
+pub const DEFAULT_MAX_EXTERNAL_REF_LEN: usize = 256;
+
+/// Default for `StfConfig::max_batch_deals`
+pub const DEFAULT_MAX_BATCH_DEALS: usize = 32;
+
+/// Default for `StfConfig::max_cancel_all_deals`
+pub const DEFAULT_MAX_CANCEL_ALL_DEALS: usize = 64;
+
+/// Default for `StfConfig::settlement_timeout_seconds`
+pub const DEFAULT_SETTLEMENT_TIMEOUT_SECONDS: u64 = 3600;
+
+/// Deployment-level configuration for the state transition function
+#[derive(Debug, Clone)]
+pub struct StfConfig {
+    pub withdrawal_destination_policy: WithdrawalDestinationPolicy,
+    /// Tx kinds this deployment handles; `None` enables every kind
+    pub enabled_tx_kinds: Option<HashSet<TxKind>>,
+    /// Fixed-point scale of `price_quote_per_base`: a fill of `amount` base
+    /// units costs `amount * price / price_scale` quote units. 1 (the
+    /// default) means prices are exact integers and no rounding happens.
+    pub price_scale: u128,
+    pub quote_rounding: QuoteRounding,
+    /// Read prices as whole quote tokens per whole base token (still
+    /// scaled by `price_scale`) and convert between the registered decimals
+    /// of both assets, so a price means the same for any pair of
+    /// precisions. Off (the default), prices are quote units per base unit
+    /// and decimals are ignored.
+    pub decimal_aware_prices: bool,
+    /// Per-tx fee; `None` (the default) charges nothing
+    pub fee: Option<FeePolicy>,
+    /// How long, in seconds of block time, applied deposit hashes are kept
+    /// to reject replays. Must exceed the longest window in which the
+    /// watcher may re-submit a deposit. `None` (the default) keeps them
+    /// forever.
+    pub deposit_dedup_retention_seconds: Option<u64>,
+    /// Smallest total quote value, `quote_amount(amount_base, price)`, a new
+    /// deal may have. 0 (the default) only rejects zero-value deals.
+    pub min_notional: u128,
+    /// Most pending deals one maker may have at a time; `None` (the
+    /// default) sets no limit
+    pub max_open_deals_per_account: Option<usize>,
+    /// Longest `external_ref`, in bytes, a new deal may carry. Refs are
+    /// stored with the deal and hashed into the state root.
+    pub max_external_ref_len: usize,
+    /// Sender whose `ConfirmSettlement` txs finalize cross-chain fills held
+    /// in escrow, i.e. the watcher. `None` (the default) settles cross-chain
+    /// fills immediately, like same-chain ones, and accepts no
+    /// confirmations.
+    pub settlement_confirmer: Option<Address>,
+    /// Seconds of block time a cross-chain fill may wait in escrow for its
+    /// confirmation before both sides are refunded
+    pub settlement_timeout_seconds: u64,
+    /// Sender the chain watcher submits observed deposits from. Its
+    /// deposits may credit any account; everyone else can only deposit to
+    /// their own address.
+    pub deposit_watcher: Option<Address>,
+    /// Most deals a single `BatchCreateDeal` may open
+    pub max_batch_deals: usize,
+    /// Most deals a single `CancelAllDeals` cancels
+    pub max_cancel_all_deals: usize,
+}
+
+impl Default for StfConfig {
+    fn default() -> Self {
+        Self {
+            withdrawal_destination_policy: WithdrawalDestinationPolicy::default(),
+            enabled_tx_kinds: None,
+            price_scale: 1,
+            quote_rounding: QuoteRounding::default(),
+            decimal_aware_prices: false,
+            fee: None,
+            deposit_dedup_retention_seconds: None,
+            min_notional: 0,
+            max_open_deals_per_account: None,
+            max_external_ref_len: DEFAULT_MAX_EXTERNAL_REF_LEN,
+            settlement_confirmer: None,
+            settlement_timeout_seconds: DEFAULT_SETTLEMENT_TIMEOUT_SECONDS,
+            deposit_watcher: None,
+            max_batch_deals: DEFAULT_MAX_BATCH_DEALS,
+            max_cancel_all_deals: DEFAULT_MAX_CANCEL_ALL_DEALS,
+        }
+    }
+}
+
+impl StfConfig {
+    pub fn is_tx_kind_enabled(&self, kind: &TxKind) -> bool {
+        self.enabled_tx_kinds
+            .as_ref()
+            .is_none_or(|kinds| kinds.contains(kind))
+    }
+
+    /// Check a deal's `external_ref` against the length limit. Refs with
+    /// control characters, null bytes included, are rejected rather than
+    /// sanitized: the ref is covered by the maker's signature and used as a
+    /// lookup key, so it must be stored exactly as signed.
+    pub fn check_external_ref(&self, external_ref: &str) -> Result<(), StfError> {
+        if external_ref.len() > self.max_external_ref_len {
+            return Err(StfError::ExternalRefTooLong);
+        }
+        if external_ref.chars().any(char::is_control) {
+            return Err(StfError::InvalidExternalRef);
+        }
+        Ok(())
+    }
+
+    /// Quote amount owed for filling `amount_base` at `price_quote_per_base`.
+    /// With `decimals` given as `(base, quote)`, the price is per whole token
+    /// and the result is rescaled from base to quote precision:
+    /// `amount * price * 10^quote / (price_scale * 10^base)`. The exact value
+    /// is divided once, so `quote_rounding` alone decides the rounding.
+    pub fn quote_amount(
+        &self,
+        amount_base: Amount,
+        price_quote_per_base: u128,
+        decimals: Option<(u8, u8)>,
+    ) -> Option<Amount> {
+        let mut numerator = amount_base.checked_mul(price_quote_per_base)?;
+        let mut denominator = self.price_scale.max(1);
+        if let Some((base, quote)) = decimals {
+            if quote >= base {
+                numerator = numerator.checked_mul(pow10(quote - base)?)?;
+            } else {
+                denominator = denominator.checked_mul(pow10(base - quote)?)?;
+            }
+        }
+        Some(Amount(
+            self.quote_rounding.divide(numerator.raw(), denominator),
+        ))
+    }
+}
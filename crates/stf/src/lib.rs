@@ -1,7 +1,21 @@
+mod config;
+#[cfg(test)]
+mod property_tests;
+
+pub use config::{
+    normalize_amount, FeePolicy, QuoteRounding, StfConfig, WithdrawalDestinationPolicy,
+    DEFAULT_MAX_BATCH_DEALS, DEFAULT_MAX_CANCEL_ALL_DEALS, DEFAULT_MAX_EXTERNAL_REF_LEN,
+    DEFAULT_SETTLEMENT_TIMEOUT_SECONDS,
+};
+
+use std::collections::{HashMap, HashSet};
+
 use zkclear_state::State;
 use zkclear_types::{
-    AcceptDeal, Address, AssetId, Balance, CancelDeal, ChainId, CreateDeal, Deal, DealStatus,
-    DealVisibility, Deposit, Tx, TxPayload, Withdraw,
+    AcceptDeal, Address, Amount, AssetId, BatchCreateDeal, CancelAllDeals, CancelDeal, ChainId,
+    ConfirmSettlement, CreateDeal, CreateFundedDeal, Deal, DealId, DealStatus, DealVisibility,
+    DeclineDeal, Deposit, Escrow, Fill, InvalidDealTransition, Transfer, Tx, TxPayload, Withdraw,
+    ZERO_ADDRESS,
 };
 
 #[derive(Debug)]
@@ -16,50 +30,363 @@ pub enum StfError {
     Overflow,
     InvalidNonce,
     DealExpired,
+    InvalidWithdrawalDestination,
+    TxKindDisabled,
+    DuplicateExternalRef,
+    InsufficientFee,
+    DuplicateDeposit,
+    InvalidTransferRecipient,
+    UnknownAsset,
+    InvalidDealParams,
+    /// The fill is so small its quote amount rounds down to zero
+    FillTooSmall,
+    /// The deal's price is above the taker's `max_price_quote_per_base`
+    PriceExceeded,
+    /// The maker already has `max_open_deals_per_account` pending deals
+    TooManyOpenDeals,
+    /// The deal's `external_ref` is longer than `max_external_ref_len`
+    ExternalRefTooLong,
+    /// The deal's `external_ref` contains control characters
+    InvalidExternalRef,
+    /// The deal has no cross-chain fill waiting in escrow
+    NotSettling,
+    /// The deal's current status can't move to the requested one
+    InvalidDealTransition,
+    /// A `BatchCreateDeal` with no deals
+    EmptyBatch,
+    /// A `BatchCreateDeal` with more than `max_batch_deals` deals
+    BatchTooLarge,
+    /// The sender's nonce is `u64::MAX`, so it can't be incremented. The
+    /// account can send no further txs, and whatever it holds stays there.
+    NonceOverflow,
+    /// `Tx::kind` names a different kind than the payload carries
+    KindMismatch,
+}
+
+impl From<InvalidDealTransition> for StfError {
+    fn from(_: InvalidDealTransition) -> Self {
+        StfError::InvalidDealTransition
+    }
+}
+
+/// What an applied tx did that its state changes don't show directly
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TxReceipt {
+    /// Deals a `CancelAllDeals` cancelled
+    pub deals_cancelled: usize,
+    /// Deals a `CancelAllDeals` matched but left pending because of
+    /// `max_cancel_all_deals`; another `CancelAllDeals` picks them up
+    pub deals_remaining: usize,
 }
 
 pub fn apply_tx(state: &mut State, tx: &Tx, block_timestamp: u64) -> Result<(), StfError> {
-    validate_nonce(state, tx.from, tx.nonce)?;
+    apply_tx_with_config(state, tx, block_timestamp, &StfConfig::default())
+}
+
+pub fn apply_tx_with_config(
+    state: &mut State,
+    tx: &Tx,
+    block_timestamp: u64,
+    config: &StfConfig,
+) -> Result<(), StfError> {
+    apply_tx_with_receipt(state, tx, block_timestamp, config)?;
+    Ok(())
+}
+
+/// `apply_tx_with_config`, returning the tx's receipt
+pub fn apply_tx_with_receipt(
+    state: &mut State,
+    tx: &Tx,
+    block_timestamp: u64,
+    config: &StfConfig,
+) -> Result<TxReceipt, StfError> {
+    // The payload is what executes, so that is the kind to check
+    if tx.kind != tx.payload.kind() {
+        return Err(StfError::KindMismatch);
+    }
+    if !config.is_tx_kind_enabled(&tx.kind) {
+        return Err(StfError::TxKindDisabled);
+    }
+
+    let next_nonce = validate_nonce(state, tx.from, tx.nonce)?;
+
+    let fee = match config.fee {
+        Some(ref fee) => Some((fee, tx_fee(fee, tx)?)),
+        None => None,
+    };
+    // A deposit pays its fee once it is credited, so a first deposit can
+    // cover it; every other kind pays up front
+    let upfront_fee = match tx.payload {
+        TxPayload::Deposit(_) => None,
+        _ => fee,
+    };
+    if let Some((fee, amount)) = upfront_fee {
+        charge_fee(state, tx.from, fee, amount)?;
+    }
 
+    let mut receipt = TxReceipt::default();
     let result = match &tx.payload {
-        TxPayload::Deposit(p) => apply_deposit(state, p),
-        TxPayload::Withdraw(p) => apply_withdraw(state, tx.from, p),
-        TxPayload::CreateDeal(p) => apply_create_deal(state, tx.from, p, block_timestamp),
-        TxPayload::AcceptDeal(p) => apply_accept_deal(state, tx.from, p, block_timestamp),
-        TxPayload::CancelDeal(p) => apply_cancel_deal(state, tx.from, p),
+        TxPayload::Deposit(p) => apply_deposit(state, tx.from, p, block_timestamp, config, fee),
+        TxPayload::Withdraw(p) => apply_withdraw(state, tx.from, p, config),
+        TxPayload::CreateDeal(p) => apply_create_deal(state, tx.from, p, block_timestamp, config),
+        TxPayload::AcceptDeal(p) => apply_accept_deal(state, tx.from, p, block_timestamp, config),
+        TxPayload::CancelDeal(p) => apply_cancel_deal(state, tx.from, p, block_timestamp),
+        TxPayload::Transfer(p) => apply_transfer(state, tx.from, p),
+        TxPayload::DeclineDeal(p) => apply_decline_deal(state, tx.from, p, block_timestamp),
+        TxPayload::CreateFundedDeal(p) => {
+            apply_create_funded_deal(state, tx.from, p, block_timestamp, config)
+        }
+        TxPayload::ConfirmSettlement(p) => {
+            apply_confirm_settlement(state, tx.from, p, block_timestamp, config)
+        }
+        TxPayload::BatchCreateDeal(p) => {
+            apply_batch_create_deal(state, tx.from, p, block_timestamp, config)
+        }
+        TxPayload::CancelAllDeals(p) => {
+            apply_cancel_all_deals(state, tx.from, p, block_timestamp, config)
+                .map(|cancelled| receipt = cancelled)
+        }
     };
 
-    if result.is_ok() {
-        increment_nonce(state, tx.from);
+    match result {
+        Ok(()) => set_nonce(state, tx.from, next_nonce),
+        Err(_) => {
+            if let Some((fee, amount)) = upfront_fee {
+                refund_fee(state, tx.from, fee, amount);
+            }
+        }
+    }
+
+    result.map(|()| receipt)
+}
+
+/// Total fee for `tx`: the flat amount plus the tx's own priority fee
+fn tx_fee(fee: &FeePolicy, tx: &Tx) -> Result<u128, StfError> {
+    fee.amount.checked_add(tx.fee).ok_or(StfError::Overflow)
+}
+
+/// Move `amount` of the fee asset from `payer` to the fee collector.
+/// Nothing changes if the payer can't cover it.
+fn charge_fee(
+    state: &mut State,
+    payer: Address,
+    fee: &FeePolicy,
+    amount: u128,
+) -> Result<(), StfError> {
+    let collector = state.fee_collector;
+    let amount = Amount(amount);
+    sub_balance(state, payer, fee.asset_id, amount, fee.chain_id)
+        .map_err(|_| StfError::InsufficientFee)?;
+
+    if let Err(e) = add_balance(state, collector, fee.asset_id, amount, fee.chain_id) {
+        // The payer held this much a moment ago, so it can't overflow
+        let _ = add_balance(state, payer, fee.asset_id, amount, fee.chain_id);
+        return Err(e);
     }
 
-    result
+    Ok(())
+}
+
+/// Undo `charge_fee` after the payload failed. Failed payloads leave the
+/// state untouched, so both balances are exactly as `charge_fee` left them.
+fn refund_fee(state: &mut State, payer: Address, fee: &FeePolicy, amount: u128) {
+    let collector = state.fee_collector;
+    let amount = Amount(amount);
+    if sub_balance(state, collector, fee.asset_id, amount, fee.chain_id).is_ok() {
+        let _ = add_balance(state, payer, fee.asset_id, amount, fee.chain_id);
+    }
 }
 
-fn apply_deposit(state: &mut State, payload: &Deposit) -> Result<(), StfError> {
+/// Credit an L1 deposit, then charge the credited account the tx's fee, if
+/// any. Users may only deposit to their own account; the configured watcher
+/// relays deposits for any account without paying their fees.
+fn apply_deposit(
+    state: &mut State,
+    from: Address,
+    payload: &Deposit,
+    block_timestamp: u64,
+    config: &StfConfig,
+    fee: Option<(&FeePolicy, u128)>,
+) -> Result<(), StfError> {
+    if payload.account != from && config.deposit_watcher != Some(from) {
+        return Err(StfError::Unauthorized);
+    }
+    if state.is_deposit_processed(&payload.tx_hash) {
+        return Err(StfError::DuplicateDeposit);
+    }
+
     add_balance(
         state,
         payload.account,
         payload.asset_id,
-        payload.amount,
+        Amount(payload.amount),
         payload.chain_id,
-    );
+    )?;
+    if let Some((fee, amount)) = fee {
+        if let Err(e) = charge_fee(state, payload.account, fee, amount) {
+            // A failed charge writes nothing, so the credit above is still there
+            let _ = sub_balance(
+                state,
+                payload.account,
+                payload.asset_id,
+                Amount(payload.amount),
+                payload.chain_id,
+            );
+            return Err(e);
+        }
+    }
+    state.record_deposit(payload.tx_hash, block_timestamp);
+    Ok(())
+}
+
+/// Credit the deposit to `maker`, then open the deal against the credited
+/// balance. The deposit is only marked processed once the deal is open, and
+/// a failed deal takes the credit back, so either both halves apply or
+/// neither does.
+fn apply_create_funded_deal(
+    state: &mut State,
+    maker: Address,
+    payload: &CreateFundedDeal,
+    block_timestamp: u64,
+    config: &StfConfig,
+) -> Result<(), StfError> {
+    let deposit = &payload.deposit;
+    if deposit.account != maker {
+        return Err(StfError::Unauthorized);
+    }
+    if state.is_deposit_processed(&deposit.tx_hash) {
+        return Err(StfError::DuplicateDeposit);
+    }
+
+    let amount = Amount(deposit.amount);
+    add_balance(state, maker, deposit.asset_id, amount, deposit.chain_id)?;
+    if let Err(e) = apply_create_deal(state, maker, &payload.deal, block_timestamp, config) {
+        // A failed deal writes nothing, so the credit above is still there
+        let _ = sub_balance(state, maker, deposit.asset_id, amount, deposit.chain_id);
+        return Err(e);
+    }
+    state.record_deposit(deposit.tx_hash, block_timestamp);
     Ok(())
 }
 
-fn apply_withdraw(state: &mut State, from: Address, payload: &Withdraw) -> Result<(), StfError> {
+fn apply_withdraw(
+    state: &mut State,
+    from: Address,
+    payload: &Withdraw,
+    config: &StfConfig,
+) -> Result<(), StfError> {
+    validate_withdrawal_destination(from, payload.to, config)?;
+
     sub_balance(
         state,
         from,
         payload.asset_id,
-        payload.amount,
+        Amount(payload.amount),
         payload.chain_id,
     )
 }
 
+/// Move a free balance from `from` to `payload.to`. Both balances are
+/// checked before either is written, so a failed transfer changes nothing.
+/// Transfers to the sender itself or to the zero address are rejected: they
+/// would move no value while still consuming a nonce.
+fn apply_transfer(state: &mut State, from: Address, payload: &Transfer) -> Result<(), StfError> {
+    if payload.to == from || payload.to == ZERO_ADDRESS {
+        return Err(StfError::InvalidTransferRecipient);
+    }
+
+    let amount = Amount(payload.amount);
+    let sender_balance = balance_of(state, from, payload.asset_id, payload.chain_id)
+        .checked_sub(amount)
+        .ok_or(StfError::BalanceTooLow)?;
+    let recipient_balance = balance_of(state, payload.to, payload.asset_id, payload.chain_id)
+        .checked_add(amount)
+        .ok_or(StfError::Overflow)?;
+
+    set_balance(
+        state,
+        from,
+        payload.asset_id,
+        payload.chain_id,
+        sender_balance,
+    );
+    set_balance(
+        state,
+        payload.to,
+        payload.asset_id,
+        payload.chain_id,
+        recipient_balance,
+    );
+    Ok(())
+}
+
+fn validate_withdrawal_destination(
+    from: Address,
+    to: Address,
+    config: &StfConfig,
+) -> Result<(), StfError> {
+    if to == ZERO_ADDRESS {
+        return Err(StfError::InvalidWithdrawalDestination);
+    }
+
+    let allowed = match &config.withdrawal_destination_policy {
+        WithdrawalDestinationPolicy::AnyNonZero => true,
+        WithdrawalDestinationPolicy::SenderOnly => to == from,
+        WithdrawalDestinationPolicy::Allowlist(allowlist) => allowlist.contains(&to),
+    };
+
+    if !allowed {
+        return Err(StfError::InvalidWithdrawalDestination);
+    }
+
+    Ok(())
+}
+
 pub fn apply_block(state: &mut State, txs: &[Tx], block_timestamp: u64) -> Result<(), StfError> {
+    apply_block_with_config(state, txs, block_timestamp, &StfConfig::default())
+}
+
+pub fn apply_block_with_config(
+    state: &mut State,
+    txs: &[Tx],
+    block_timestamp: u64,
+    config: &StfConfig,
+) -> Result<(), StfError> {
+    expire_deals(state, block_timestamp)?;
+    if let Some(retention) = config.deposit_dedup_retention_seconds {
+        state.prune_processed_deposits(block_timestamp.saturating_sub(retention));
+    }
+
     for tx in txs {
-        apply_tx(state, tx, block_timestamp)?;
+        apply_tx_with_config(state, tx, block_timestamp, config)?;
+    }
+    Ok(())
+}
+
+/// Refund cross-chain fills whose settlement was not confirmed in time, and
+/// mark pending deals whose expiry has passed as `Expired`, releasing their
+/// makers' reserves. Uses the state's expiry index, so only deals that are
+/// actually due are visited.
+fn expire_deals(state: &mut State, block_timestamp: u64) -> Result<(), StfError> {
+    for deal_id in state.take_expired_deals(block_timestamp) {
+        let Some(deal) = state.get_deal(deal_id) else {
+            continue;
+        };
+        if deal
+            .next_refund_at()
+            .is_some_and(|refund_at| refund_at < block_timestamp)
+        {
+            refund_escrows(state, deal_id, block_timestamp)?;
+        }
+
+        let Some(deal) = state.get_deal(deal_id) else {
+            continue;
+        };
+        if deal.status == DealStatus::Pending && deal.is_expired_at(block_timestamp) {
+            close_deal(state, deal_id, DealStatus::Expired, block_timestamp)?;
+        }
+        // Escrows still open are due again later
+        state.reindex_deal(deal_id);
     }
     Ok(())
 }
@@ -69,11 +396,115 @@ fn apply_create_deal(
     maker: Address,
     payload: &CreateDeal,
     block_timestamp: u64,
+    config: &StfConfig,
+) -> Result<(), StfError> {
+    validate_new_deal(state, maker, payload, config)?;
+
+    if let Some(max) = config.max_open_deals_per_account {
+        if state.open_deal_count(maker) >= max {
+            return Err(StfError::TooManyOpenDeals);
+        }
+    }
+
+    open_deal(state, maker, payload, block_timestamp)
+}
+
+/// Open every deal in the batch, or none of them. All checks, including
+/// the combined reserve each asset needs and clashes between deals of the
+/// same batch, run before the first deal is written, so a failing batch
+/// leaves the state untouched.
+fn apply_batch_create_deal(
+    state: &mut State,
+    maker: Address,
+    payload: &BatchCreateDeal,
+    block_timestamp: u64,
+    config: &StfConfig,
+) -> Result<(), StfError> {
+    if payload.deals.is_empty() {
+        return Err(StfError::EmptyBatch);
+    }
+    if payload.deals.len() > config.max_batch_deals {
+        return Err(StfError::BatchTooLarge);
+    }
+
+    let mut deal_ids = HashSet::new();
+    let mut external_refs = HashSet::new();
+    let mut locked: HashMap<(AssetId, ChainId), Amount> = HashMap::new();
+    for deal in &payload.deals {
+        validate_new_deal(state, maker, deal, config)?;
+        if !deal_ids.insert(deal.deal_id) {
+            return Err(StfError::DealAlreadyExists);
+        }
+        if let Some(ref external_ref) = deal.external_ref {
+            if !external_refs.insert(external_ref.as_str()) {
+                return Err(StfError::DuplicateExternalRef);
+            }
+        }
+        let total = locked
+            .entry((deal.asset_base, deal.chain_id_base))
+            .or_insert(Amount::ZERO);
+        *total = total
+            .checked_add(Amount(deal.amount_base))
+            .ok_or(StfError::Overflow)?;
+    }
+
+    if let Some(max) = config.max_open_deals_per_account {
+        if state.open_deal_count(maker) + payload.deals.len() > max {
+            return Err(StfError::TooManyOpenDeals);
+        }
+    }
+
+    for (&(asset_id, chain_id), &total) in &locked {
+        balance_of(state, maker, asset_id, chain_id)
+            .checked_sub(total)
+            .ok_or(StfError::BalanceTooLow)?;
+        reserved_of(state, maker, asset_id, chain_id)
+            .checked_add(total)
+            .ok_or(StfError::Overflow)?;
+    }
+
+    for deal in &payload.deals {
+        // Can't fail: the combined reserve was checked above
+        open_deal(state, maker, deal, block_timestamp)?;
+    }
+    Ok(())
+}
+
+/// Checks a new deal must pass regardless of the maker's holdings: an
+/// unused id, an external ref the maker hasn't used, sensible params and
+/// registered assets
+fn validate_new_deal(
+    state: &State,
+    maker: Address,
+    payload: &CreateDeal,
+    config: &StfConfig,
 ) -> Result<(), StfError> {
     if state.get_deal(payload.deal_id).is_some() {
         return Err(StfError::DealAlreadyExists);
     }
 
+    validate_deal_params(state, payload, config)?;
+
+    if let Some(ref external_ref) = payload.external_ref {
+        config.check_external_ref(external_ref)?;
+        if state.get_deal_by_external_ref(maker, external_ref).is_some() {
+            return Err(StfError::DuplicateExternalRef);
+        }
+    }
+
+    ensure_asset_registered(state, payload.asset_base, payload.chain_id_base)?;
+    ensure_asset_registered(state, payload.asset_quote, payload.chain_id_quote)
+}
+
+/// Reserve the deal's base amount from `maker` and store it as pending.
+/// Fails only if the maker can't cover the reserve, before anything is
+/// written.
+fn open_deal(
+    state: &mut State,
+    maker: Address,
+    payload: &CreateDeal,
+    block_timestamp: u64,
+) -> Result<(), StfError> {
     let is_cross_chain = payload.chain_id_base != payload.chain_id_quote;
 
     let expires_at = payload.expires_at.map(|exp| {
@@ -82,6 +513,16 @@ fn apply_create_deal(
         exp.min(max_expiry)
     });
 
+    // Lock the base amount up front so overlapping deals can't sell the
+    // same funds twice
+    let amount_base = Amount(payload.amount_base);
+    let free = balance_of(state, maker, payload.asset_base, payload.chain_id_base)
+        .checked_sub(amount_base)
+        .ok_or(StfError::BalanceTooLow)?;
+    let reserved = reserved_of(state, maker, payload.asset_base, payload.chain_id_base)
+        .checked_add(amount_base)
+        .ok_or(StfError::Overflow)?;
+
     let deal = Deal {
         id: payload.deal_id,
         maker,
@@ -96,21 +537,87 @@ fn apply_create_deal(
         price_quote_per_base: payload.price_quote_per_base,
         status: DealStatus::Pending,
         created_at: block_timestamp,
+        updated_at: block_timestamp,
         expires_at,
         external_ref: payload.external_ref.clone(),
         is_cross_chain,
+        escrows: Vec::new(),
     };
 
+    set_balance(
+        state,
+        maker,
+        payload.asset_base,
+        payload.chain_id_base,
+        free,
+    );
+    set_reserved(
+        state,
+        maker,
+        payload.asset_base,
+        payload.chain_id_base,
+        reserved,
+    );
     state.upsert_deal(deal);
+    state.record_deal_opened(maker, payload.deal_id);
+
+    Ok(())
+}
+
+/// Reject deals that could never be filled sensibly: a zero amount or
+/// price, a total quote value that doesn't fit in a `u128` or rounds to
+/// zero, or one below the configured minimum notional
+fn validate_deal_params(
+    state: &State,
+    payload: &CreateDeal,
+    config: &StfConfig,
+) -> Result<(), StfError> {
+    if payload.amount_base == 0 || payload.price_quote_per_base == 0 {
+        return Err(StfError::InvalidDealParams);
+    }
+
+    let decimals = price_decimals(
+        state,
+        config,
+        (payload.asset_base, payload.chain_id_base),
+        (payload.asset_quote, payload.chain_id_quote),
+    );
+    let notional = config
+        .quote_amount(
+            Amount(payload.amount_base),
+            payload.price_quote_per_base,
+            decimals,
+        )
+        .ok_or(StfError::Overflow)?;
+    if notional.is_zero() || notional < Amount(config.min_notional) {
+        return Err(StfError::InvalidDealParams);
+    }
 
     Ok(())
 }
 
+/// `(base, quote)` decimals to price a pair with, when the config asks for
+/// decimal-aware prices and both assets are registered
+fn price_decimals(
+    state: &State,
+    config: &StfConfig,
+    (asset_base, chain_id_base): (AssetId, ChainId),
+    (asset_quote, chain_id_quote): (AssetId, ChainId),
+) -> Option<(u8, u8)> {
+    if !config.decimal_aware_prices {
+        return None;
+    }
+    let base = state.get_asset(asset_base, chain_id_base)?;
+    let quote = state.get_asset(asset_quote, chain_id_quote)?;
+    Some((base.decimals, quote.decimals))
+}
+
 fn apply_accept_deal(
     state: &mut State,
     taker: Address,
     payload: &AcceptDeal,
     block_timestamp: u64,
+    config: &StfConfig,
 ) -> Result<(), StfError> {
     let (
         maker_addr,
@@ -120,7 +627,7 @@ fn apply_accept_deal(
         chain_id_quote,
         amount_remaining,
         price_quote_per_base,
-        _expires_at,
+        is_cross_chain,
         _visibility,
         _expected_taker,
     ) = {
@@ -132,10 +639,8 @@ fn apply_accept_deal(
             return Err(StfError::DealAlreadyClosed);
         }
 
-        if let Some(exp) = deal.expires_at {
-            if exp > 0 && exp < block_timestamp {
-                return Err(StfError::DealExpired);
-            }
+        if deal.is_expired_at(block_timestamp) {
+            return Err(StfError::DealExpired);
         }
 
         match deal.visibility {
@@ -155,6 +660,16 @@ fn apply_accept_deal(
             return Err(StfError::Unauthorized);
         }
 
+        if payload
+            .max_price_quote_per_base
+            .is_some_and(|max_price| deal.price_quote_per_base > max_price)
+        {
+            return Err(StfError::PriceExceeded);
+        }
+
+        ensure_asset_registered(state, deal.asset_base, deal.chain_id_base)?;
+        ensure_asset_registered(state, deal.asset_quote, deal.chain_id_quote)?;
+
         (
             deal.maker,
             deal.asset_base,
@@ -163,130 +678,493 @@ fn apply_accept_deal(
             deal.chain_id_quote,
             deal.amount_remaining,
             deal.price_quote_per_base,
-            deal.expires_at,
+            deal.is_cross_chain,
             deal.visibility,
             deal.taker,
         )
     };
 
-    let amount_to_fill = payload.amount.unwrap_or(amount_remaining);
-    if amount_to_fill == 0 || amount_to_fill > amount_remaining {
+    let amount_to_fill = Amount(payload.amount.unwrap_or(amount_remaining));
+    if amount_to_fill.is_zero() || amount_to_fill > Amount(amount_remaining) {
         return Err(StfError::BalanceTooLow);
     }
 
-    let amount_quote = amount_to_fill
-        .checked_mul(price_quote_per_base)
+    let decimals = price_decimals(
+        state,
+        config,
+        (asset_base, chain_id_base),
+        (asset_quote, chain_id_quote),
+    );
+    let amount_quote = config
+        .quote_amount(amount_to_fill, price_quote_per_base, decimals)
         .ok_or(StfError::Overflow)?;
+    if amount_quote.is_zero() {
+        return Err(StfError::FillTooSmall);
+    }
 
-    ensure_balance(state, maker_addr, asset_base, amount_to_fill, chain_id_base)?;
     ensure_balance(state, taker, asset_quote, amount_quote, chain_id_quote)?;
 
-    sub_balance(state, maker_addr, asset_base, amount_to_fill, chain_id_base)?;
-    sub_balance(state, taker, asset_quote, amount_quote, chain_id_quote)?;
+    // The maker's side is drawn from the reserve taken at deal creation
+    let maker_reserved = reserved_of(state, maker_addr, asset_base, chain_id_base)
+        .checked_sub(amount_to_fill)
+        .ok_or(StfError::BalanceTooLow)?;
+
+    // A cross-chain swap can't settle atomically here, so both sides of the
+    // fill are held in escrow until the watcher confirms the destination
+    // chain leg. The rest of the deal stays open to other fills.
+    if is_cross_chain && config.settlement_confirmer.is_some() {
+        let taker_quote = balance_of(state, taker, asset_quote, chain_id_quote)
+            .checked_sub(amount_quote)
+            .ok_or(StfError::BalanceTooLow)?;
+        let refund_at = block_timestamp
+            .checked_add(config.settlement_timeout_seconds)
+            .ok_or(StfError::Overflow)?;
+
+        let deal = state
+            .get_deal_mut(payload.deal_id)
+            .ok_or(StfError::DealNotFound)?;
+        deal.amount_remaining -= amount_to_fill.raw();
+        if deal.amount_remaining == 0 {
+            deal.transition(DealStatus::Settling)?;
+        }
+        deal.updated_at = block_timestamp;
+        deal.escrows.push(Escrow {
+            taker,
+            amount_base: amount_to_fill.raw(),
+            amount_quote: amount_quote.raw(),
+            refund_at,
+        });
+        set_balance(state, taker, asset_quote, chain_id_quote, taker_quote);
+        set_reserved(state, maker_addr, asset_base, chain_id_base, maker_reserved);
+        state.reindex_deal(payload.deal_id);
+        return Ok(());
+    }
+
+    // Compute every resulting balance before touching state so that
+    // settlement is all-or-nothing: (owner, asset, chain, debit, credit)
+    let legs = [
+        (
+            taker,
+            asset_quote,
+            chain_id_quote,
+            amount_quote,
+            Amount::ZERO,
+        ),
+        (
+            maker_addr,
+            asset_quote,
+            chain_id_quote,
+            Amount::ZERO,
+            amount_quote,
+        ),
+        (
+            taker,
+            asset_base,
+            chain_id_base,
+            Amount::ZERO,
+            amount_to_fill,
+        ),
+    ];
+
+    let mut new_balances: Vec<(Address, AssetId, ChainId, Amount)> = Vec::with_capacity(legs.len());
+    for (owner, asset_id, chain_id, debit, credit) in legs {
+        let existing = new_balances
+            .iter_mut()
+            .find(|(o, a, c, _)| *o == owner && *a == asset_id && *c == chain_id);
+        let current = match &existing {
+            Some((_, _, _, amount)) => *amount,
+            None => balance_of(state, owner, asset_id, chain_id),
+        };
+
+        let updated = current
+            .checked_sub(debit)
+            .ok_or(StfError::BalanceTooLow)?
+            .checked_add(credit)
+            .ok_or(StfError::Overflow)?;
+
+        match existing {
+            Some((_, _, _, amount)) => *amount = updated,
+            None => new_balances.push((owner, asset_id, chain_id, updated)),
+        }
+    }
 
-    add_balance(state, maker_addr, asset_quote, amount_quote, chain_id_quote);
-    add_balance(state, taker, asset_base, amount_to_fill, chain_id_base);
+    for (owner, asset_id, chain_id, amount) in new_balances {
+        set_balance(state, owner, asset_id, chain_id, amount);
+    }
+    set_reserved(state, maker_addr, asset_base, chain_id_base, maker_reserved);
 
     let deal = state
         .get_deal_mut(payload.deal_id)
         .ok_or(StfError::DealNotFound)?;
-    deal.amount_remaining -= amount_to_fill;
+    deal.amount_remaining -= amount_to_fill.raw();
+    deal.updated_at = block_timestamp;
     if deal.amount_remaining == 0 {
-        deal.status = DealStatus::Settled;
+        deal.transition(DealStatus::Settled)?;
+        state.record_deal_closed(maker_addr, payload.deal_id);
     }
 
+    state.record_fill(Fill {
+        deal_id: payload.deal_id,
+        taker,
+        amount_base: amount_to_fill.raw(),
+        amount_quote: amount_quote.raw(),
+        block_timestamp,
+    });
+
     Ok(())
 }
 
-fn apply_cancel_deal(
+/// Finalize the oldest escrowed fill of a deal: the taker receives the
+/// base, the maker the quote, and the fill is recorded. A settling deal is
+/// settled once its last escrow is.
+fn apply_confirm_settlement(
     state: &mut State,
     caller: Address,
-    payload: &CancelDeal,
+    payload: &ConfirmSettlement,
+    block_timestamp: u64,
+    config: &StfConfig,
 ) -> Result<(), StfError> {
+    if config.settlement_confirmer != Some(caller) {
+        return Err(StfError::Unauthorized);
+    }
+
     let deal = state
-        .get_deal_mut(payload.deal_id)
+        .get_deal(payload.deal_id)
         .ok_or(StfError::DealNotFound)?;
+    let escrow = deal.escrows.first().cloned().ok_or(StfError::NotSettling)?;
+    let maker = deal.maker;
+    let (asset_base, chain_id_base) = (deal.asset_base, deal.chain_id_base);
+    let (asset_quote, chain_id_quote) = (deal.asset_quote, deal.chain_id_quote);
 
-    if deal.status != DealStatus::Pending {
-        return Err(StfError::DealAlreadyClosed);
-    }
+    let taker_base = balance_of(state, escrow.taker, asset_base, chain_id_base)
+        .checked_add(Amount(escrow.amount_base))
+        .ok_or(StfError::Overflow)?;
+    let maker_quote = balance_of(state, maker, asset_quote, chain_id_quote)
+        .checked_add(Amount(escrow.amount_quote))
+        .ok_or(StfError::Overflow)?;
 
-    if deal.maker != caller {
-        return Err(StfError::Unauthorized);
+    set_balance(state, escrow.taker, asset_base, chain_id_base, taker_base);
+    set_balance(state, maker, asset_quote, chain_id_quote, maker_quote);
+
+    let deal = state
+        .get_deal_mut(payload.deal_id)
+        .ok_or(StfError::DealNotFound)?;
+    deal.escrows.remove(0);
+    deal.updated_at = block_timestamp;
+    if deal.status == DealStatus::Settling && deal.escrows.is_empty() {
+        deal.transition(DealStatus::Settled)?;
+        state.record_deal_closed(maker, payload.deal_id);
     }
 
-    deal.status = DealStatus::Cancelled;
+    state.record_fill(Fill {
+        deal_id: payload.deal_id,
+        taker: escrow.taker,
+        amount_base: escrow.amount_base,
+        amount_quote: escrow.amount_quote,
+        block_timestamp,
+    });
 
     Ok(())
 }
 
-fn add_balance(
+/// Undo the escrowed fills of a deal whose confirmation is overdue at
+/// `block_timestamp`: each taker gets their quote back, and the base returns
+/// to the maker's reserve, reopening a settling deal. The base of a deal
+/// that was closed in the meantime goes back to the maker's free balance.
+fn refund_escrows(
     state: &mut State,
-    owner: Address,
-    asset_id: AssetId,
-    amount: u128,
-    chain_id: ChainId,
-) {
-    let account = state.get_or_create_account_by_owner(owner);
+    deal_id: DealId,
+    block_timestamp: u64,
+) -> Result<(), StfError> {
+    let deal = state.get_deal(deal_id).ok_or(StfError::DealNotFound)?;
+    let (due, kept): (Vec<Escrow>, Vec<Escrow>) = deal
+        .escrows
+        .iter()
+        .cloned()
+        .partition(|escrow| escrow.refund_at < block_timestamp);
+    let is_open = matches!(deal.status, DealStatus::Pending | DealStatus::Settling);
+    let maker = deal.maker;
+    let (asset_base, chain_id_base) = (deal.asset_base, deal.chain_id_base);
+    let (asset_quote, chain_id_quote) = (deal.asset_quote, deal.chain_id_quote);
+
+    let mut refunded_base = Amount::ZERO;
+    for escrow in &due {
+        refunded_base = refunded_base
+            .checked_add(Amount(escrow.amount_base))
+            .ok_or(StfError::Overflow)?;
+        add_balance(
+            state,
+            escrow.taker,
+            asset_quote,
+            Amount(escrow.amount_quote),
+            chain_id_quote,
+        )?;
+    }
+    if is_open {
+        let maker_reserved = reserved_of(state, maker, asset_base, chain_id_base)
+            .checked_add(refunded_base)
+            .ok_or(StfError::Overflow)?;
+        set_reserved(state, maker, asset_base, chain_id_base, maker_reserved);
+    } else {
+        add_balance(state, maker, asset_base, refunded_base, chain_id_base)?;
+    }
 
-    for b in &mut account.balances {
-        if b.asset_id == asset_id && b.chain_id == chain_id {
-            b.amount = b.amount.saturating_add(amount);
-            return;
+    let deal = state.get_deal_mut(deal_id).ok_or(StfError::DealNotFound)?;
+    deal.escrows = kept;
+    deal.updated_at = block_timestamp;
+    if is_open {
+        deal.amount_remaining += refunded_base.raw();
+        if deal.status == DealStatus::Settling {
+            deal.transition(DealStatus::Pending)?;
         }
     }
-
-    account.balances.push(Balance {
-        asset_id,
-        amount,
-        chain_id,
-    });
+    Ok(())
 }
 
-fn sub_balance(
+fn apply_cancel_deal(
     state: &mut State,
-    owner: Address,
-    asset_id: AssetId,
-    amount: u128,
-    chain_id: ChainId,
+    caller: Address,
+    payload: &CancelDeal,
+    block_timestamp: u64,
 ) -> Result<(), StfError> {
-    let account = state.get_or_create_account_by_owner(owner);
+    let deal = state
+        .get_deal(payload.deal_id)
+        .ok_or(StfError::DealNotFound)?;
 
-    for b in &mut account.balances {
-        if b.asset_id == asset_id && b.chain_id == chain_id {
-            if b.amount < amount {
-                return Err(StfError::BalanceTooLow);
-            }
-            b.amount -= amount;
-            return Ok(());
-        }
+    if deal.status != DealStatus::Pending {
+        return Err(StfError::DealAlreadyClosed);
     }
 
-    Err(StfError::BalanceTooLow)
-}
+    if deal.maker != caller {
+        return Err(StfError::Unauthorized);
+    }
 
-fn ensure_balance(
+    close_deal(
+        state,
+        payload.deal_id,
+        DealStatus::Cancelled,
+        block_timestamp,
+    )
+}
+
+/// Cancel up to `max_cancel_all_deals` of the caller's pending deals,
+/// lowest id first so every node picks the same ones. Returns how many
+/// were cancelled and how many matching deals are still pending. Every
+/// reserve release is checked before the first deal closes, so the deals
+/// are cancelled all together or not at all.
+fn apply_cancel_all_deals(
+    state: &mut State,
+    caller: Address,
+    payload: &CancelAllDeals,
+    block_timestamp: u64,
+    config: &StfConfig,
+) -> Result<TxReceipt, StfError> {
+    let deal_ids: Vec<DealId> = state
+        .open_deal_ids(caller)
+        .filter(|deal_id| {
+            state.get_deal(*deal_id).is_some_and(|deal| {
+                deal.status == DealStatus::Pending
+                    && payload
+                        .asset_base
+                        .is_none_or(|asset_id| deal.asset_base == asset_id)
+            })
+        })
+        .collect();
+    let cancelled = deal_ids.len().min(config.max_cancel_all_deals);
+    let (deal_ids, remaining) = deal_ids.split_at(cancelled);
+
+    let mut released: HashMap<(AssetId, ChainId), Amount> = HashMap::new();
+    for deal_id in deal_ids {
+        let deal = state.get_deal(*deal_id).ok_or(StfError::DealNotFound)?;
+        let total = released
+            .entry((deal.asset_base, deal.chain_id_base))
+            .or_insert(Amount::ZERO);
+        *total = total
+            .checked_add(Amount(deal.amount_remaining))
+            .ok_or(StfError::Overflow)?;
+    }
+    for (&(asset_id, chain_id), &total) in &released {
+        reserved_of(state, caller, asset_id, chain_id)
+            .checked_sub(total)
+            .ok_or(StfError::BalanceTooLow)?;
+        balance_of(state, caller, asset_id, chain_id)
+            .checked_add(total)
+            .ok_or(StfError::Overflow)?;
+    }
+
+    for deal_id in deal_ids {
+        close_deal(state, *deal_id, DealStatus::Cancelled, block_timestamp)?;
+    }
+    Ok(TxReceipt {
+        deals_cancelled: cancelled,
+        deals_remaining: remaining.len(),
+    })
+}
+
+/// Let the designated taker of a direct deal turn it down. The deal is
+/// cancelled just as if its maker had cancelled it.
+fn apply_decline_deal(
+    state: &mut State,
+    caller: Address,
+    payload: &DeclineDeal,
+    block_timestamp: u64,
+) -> Result<(), StfError> {
+    let deal = state
+        .get_deal(payload.deal_id)
+        .ok_or(StfError::DealNotFound)?;
+
+    if deal.status != DealStatus::Pending {
+        return Err(StfError::DealAlreadyClosed);
+    }
+
+    // Public deals have no designated taker to decline them
+    if deal.visibility != DealVisibility::Direct || deal.taker != Some(caller) {
+        return Err(StfError::Unauthorized);
+    }
+
+    close_deal(
+        state,
+        payload.deal_id,
+        DealStatus::Cancelled,
+        block_timestamp,
+    )
+}
+
+/// Move a pending deal to a final `status` at `block_timestamp`, returning
+/// its unfilled base amount from the maker's reserve to their free balance.
+/// The transition is checked before any balance moves.
+fn close_deal(
+    state: &mut State,
+    deal_id: DealId,
+    status: DealStatus,
+    block_timestamp: u64,
+) -> Result<(), StfError> {
+    let deal = state.get_deal(deal_id).ok_or(StfError::DealNotFound)?;
+    if !deal.status.can_transition_to(status) {
+        return Err(StfError::InvalidDealTransition);
+    }
+    let (maker, asset_id, chain_id, remaining) = (
+        deal.maker,
+        deal.asset_base,
+        deal.chain_id_base,
+        Amount(deal.amount_remaining),
+    );
+
+    let reserved = reserved_of(state, maker, asset_id, chain_id)
+        .checked_sub(remaining)
+        .ok_or(StfError::BalanceTooLow)?;
+    let free = balance_of(state, maker, asset_id, chain_id)
+        .checked_add(remaining)
+        .ok_or(StfError::Overflow)?;
+
+    set_reserved(state, maker, asset_id, chain_id, reserved);
+    set_balance(state, maker, asset_id, chain_id, free);
+    let deal = state.get_deal_mut(deal_id).ok_or(StfError::DealNotFound)?;
+    deal.transition(status)?;
+    deal.updated_at = block_timestamp;
+    state.record_deal_closed(maker, deal_id);
+
+    Ok(())
+}
+
+fn add_balance(
     state: &mut State,
     owner: Address,
     asset_id: AssetId,
-    amount: u128,
+    amount: Amount,
     chain_id: ChainId,
 ) -> Result<(), StfError> {
     let account = state.get_or_create_account_by_owner(owner);
+    account
+        .credit(asset_id, chain_id, amount.raw())
+        .ok_or(StfError::Overflow)?;
+    Ok(())
+}
+
+fn balance_of(state: &State, owner: Address, asset_id: AssetId, chain_id: ChainId) -> Amount {
+    state
+        .get_account_by_address(owner)
+        .map_or(Amount::ZERO, |account| {
+            Amount(account.balance_of(asset_id, chain_id))
+        })
+}
+
+fn set_balance(
+    state: &mut State,
+    owner: Address,
+    asset_id: AssetId,
+    chain_id: ChainId,
+    amount: Amount,
+) {
+    let account = state.get_or_create_account_by_owner(owner);
+    account.balances.set(asset_id, chain_id, amount.raw());
+}
 
-    for b in &account.balances {
-        if b.asset_id == asset_id && b.chain_id == chain_id {
-            if b.amount < amount {
-                return Err(StfError::BalanceTooLow);
-            }
-            return Ok(());
-        }
+fn reserved_of(state: &State, owner: Address, asset_id: AssetId, chain_id: ChainId) -> Amount {
+    state
+        .get_account_by_address(owner)
+        .map_or(Amount::ZERO, |account| {
+            Amount(account.reserved.get(asset_id, chain_id))
+        })
+}
+
+fn set_reserved(
+    state: &mut State,
+    owner: Address,
+    asset_id: AssetId,
+    chain_id: ChainId,
+    amount: Amount,
+) {
+    let account = state.get_or_create_account_by_owner(owner);
+    account.reserved.set(asset_id, chain_id, amount.raw());
+}
+
+fn sub_balance(
+    state: &mut State,
+    owner: Address,
+    asset_id: AssetId,
+    amount: Amount,
+    chain_id: ChainId,
+) -> Result<(), StfError> {
+    let account = state.get_or_create_account_by_owner(owner);
+    account
+        .debit(asset_id, chain_id, amount.raw())
+        .ok_or(StfError::BalanceTooLow)?;
+    Ok(())
+}
+
+/// Reject an `(asset_id, chain_id)` pair missing from the state's asset
+/// registry, since a deal in it could never be withdrawn. An empty registry
+/// leaves assets unrestricted.
+fn ensure_asset_registered(
+    state: &State,
+    asset_id: AssetId,
+    chain_id: ChainId,
+) -> Result<(), StfError> {
+    if state.assets.is_empty() || state.get_asset(asset_id, chain_id).is_some() {
+        Ok(())
+    } else {
+        Err(StfError::UnknownAsset)
     }
+}
 
-    Err(StfError::BalanceTooLow)
+fn ensure_balance(
+    state: &State,
+    owner: Address,
+    asset_id: AssetId,
+    amount: Amount,
+    chain_id: ChainId,
+) -> Result<(), StfError> {
+    if balance_of(state, owner, asset_id, chain_id) < amount {
+        return Err(StfError::BalanceTooLow);
+    }
+    Ok(())
 }
 
-fn validate_nonce(state: &mut State, owner: Address, tx_nonce: u64) -> Result<(), StfError> {
+/// Check `tx_nonce` is the sender's next nonce and return the nonce that
+/// follows it. Checked before the payload runs, so a tx whose nonce can't
+/// be incremented never applies.
+fn validate_nonce(state: &mut State, owner: Address, tx_nonce: u64) -> Result<u64, StfError> {
     let account = state.get_or_create_account_by_owner(owner);
     let expected_nonce = account.nonce;
 
@@ -294,18 +1172,19 @@ fn validate_nonce(state: &mut State, owner: Address, tx_nonce: u64) -> Result<()
         return Err(StfError::InvalidNonce);
     }
 
-    Ok(())
+    expected_nonce.checked_add(1).ok_or(StfError::NonceOverflow)
 }
 
-fn increment_nonce(state: &mut State, owner: Address) {
+fn set_nonce(state: &mut State, owner: Address, nonce: u64) {
     let account = state.get_or_create_account_by_owner(owner);
-    account.nonce += 1;
+    account.nonce = nonce;
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use zkclear_types::{Tx, TxKind, TxPayload};
+    use zkclear_types::test_util::unique_tx_hash;
+    use zkclear_types::{SignatureScheme, Tx, TxKind, TxPayload};
 
     fn dummy_address(byte: u8) -> Address {
         [byte; 20]
@@ -320,18 +1199,57 @@ mod tests {
             id: 0,
             from,
             nonce,
-            kind: match &payload {
-                TxPayload::Deposit(_) => TxKind::Deposit,
-                TxPayload::Withdraw(_) => TxKind::Withdraw,
-                TxPayload::CreateDeal(_) => TxKind::CreateDeal,
-                TxPayload::AcceptDeal(_) => TxKind::AcceptDeal,
-                TxPayload::CancelDeal(_) => TxKind::CancelDeal,
-            },
+            kind: payload.kind(),
             payload,
-            signature: [0u8; 65],
+            fee: 0,
+            domain: 0,
+            scheme: SignatureScheme::Secp256k1Recoverable,
+            signature: vec![0u8; 65],
         }
     }
 
+    fn deposit_tx(who: Address, nonce: u64, asset_id: AssetId, amount: u128) -> Tx {
+        dummy_tx(
+            who,
+            nonce,
+            TxPayload::Deposit(Deposit {
+                tx_hash: unique_tx_hash(),
+                account: who,
+                asset_id,
+                amount,
+                chain_id: default_chain_id(),
+            }),
+        )
+    }
+
+    fn create_deal_tx(maker: Address, nonce: u64, deal_id: DealId, amount_base: u128) -> Tx {
+        dummy_tx(
+            maker,
+            nonce,
+            TxPayload::CreateDeal(CreateDeal {
+                deal_id,
+                visibility: DealVisibility::Public,
+                taker: None,
+                asset_base: 0,
+                asset_quote: 1,
+                chain_id_base: default_chain_id(),
+                chain_id_quote: default_chain_id(),
+                amount_base,
+                price_quote_per_base: 100,
+                expires_at: None,
+                external_ref: None,
+            }),
+        )
+    }
+
+    /// (free, reserved) amounts of asset 0 on the default chain
+    fn base_holdings(state: &State, who: Address) -> (u128, u128) {
+        (
+            balance_of(state, who, 0, default_chain_id()).raw(),
+            reserved_of(state, who, 0, default_chain_id()).raw(),
+        )
+    }
+
     #[test]
     fn test_deposit() {
         let mut state = State::new();
@@ -342,7 +1260,7 @@ mod tests {
             addr,
             0,
             TxPayload::Deposit(Deposit {
-                tx_hash: [0u8; 32],
+                tx_hash: unique_tx_hash(),
                 account: addr,
                 asset_id: 0,
                 amount: 1000,
@@ -354,12 +1272,59 @@ mod tests {
 
         let account = state.get_account_by_address(addr).unwrap();
         assert_eq!(account.balances.len(), 1);
-        assert_eq!(account.balances[0].asset_id, 0);
-        assert_eq!(account.balances[0].chain_id, default_chain_id());
-        assert_eq!(account.balances[0].amount, 1000);
+        assert_eq!(account.balance_of(0, default_chain_id()), 1000);
         assert_eq!(account.nonce, 1);
     }
 
+    #[test]
+    fn test_deposit_overflowing_balance_is_rejected() {
+        let mut state = State::new();
+        let addr = dummy_address(1);
+        apply_tx(&mut state, &deposit_tx(addr, 0, 0, u128::MAX - 5), 1000).unwrap();
+
+        assert!(matches!(
+            apply_tx(&mut state, &deposit_tx(addr, 1, 0, 6), 1000),
+            Err(StfError::Overflow)
+        ));
+        assert_eq!(base_holdings(&state, addr), (u128::MAX - 5, 0));
+        assert_eq!(state.get_account_by_address(addr).unwrap().nonce, 1);
+
+        apply_tx(&mut state, &deposit_tx(addr, 1, 0, 5), 1000).unwrap();
+        assert_eq!(base_holdings(&state, addr), (u128::MAX, 0));
+    }
+
+    #[test]
+    fn test_deposit_credits_only_sender_unless_from_watcher() {
+        let watcher = dummy_address(9);
+        let config = StfConfig {
+            deposit_watcher: Some(watcher),
+            ..Default::default()
+        };
+        let mut state = State::new();
+        let (alice, bob) = (dummy_address(1), dummy_address(2));
+
+        apply_tx_with_config(&mut state, &deposit_tx(alice, 0, 0, 100), 1000, &config).unwrap();
+        assert_eq!(base_holdings(&state, alice), (100, 0));
+
+        // Alice can't credit Bob with a deposit she signed
+        let mut to_bob = deposit_tx(alice, 1, 0, 50);
+        if let TxPayload::Deposit(ref mut p) = to_bob.payload {
+            p.account = bob;
+        }
+        let result = apply_tx_with_config(&mut state, &to_bob, 1000, &config);
+        assert!(matches!(result, Err(StfError::Unauthorized)));
+        assert_eq!(base_holdings(&state, bob), (0, 0));
+
+        // The watcher relays deposits for whoever made them on L1
+        let mut relayed = deposit_tx(watcher, 0, 0, 50);
+        if let TxPayload::Deposit(ref mut p) = relayed.payload {
+            p.account = bob;
+        }
+        apply_tx_with_config(&mut state, &relayed, 1000, &config).unwrap();
+        assert_eq!(base_holdings(&state, bob), (50, 0));
+        assert_eq!(base_holdings(&state, watcher), (0, 0));
+    }
+
     #[test]
     fn test_deposit_multiple_assets() {
         let mut state = State::new();
@@ -370,7 +1335,7 @@ mod tests {
             addr,
             0,
             TxPayload::Deposit(Deposit {
-                tx_hash: [0u8; 32],
+                tx_hash: unique_tx_hash(),
                 account: addr,
                 asset_id: 0,
                 amount: 1000,
@@ -407,7 +1372,7 @@ mod tests {
             addr,
             0,
             TxPayload::Deposit(Deposit {
-                tx_hash: [0u8; 32],
+                tx_hash: unique_tx_hash(),
                 account: addr,
                 asset_id: 0,
                 amount: 1000,
@@ -429,7 +1394,7 @@ mod tests {
         apply_tx(&mut state, &withdraw_tx, block_timestamp).unwrap();
 
         let account = state.get_account_by_address(addr).unwrap();
-        assert_eq!(account.balances[0].amount, 700);
+        assert_eq!(account.balance_of(0, default_chain_id()), 700);
     }
 
     #[test]
@@ -442,7 +1407,7 @@ mod tests {
             addr,
             0,
             TxPayload::Deposit(Deposit {
-                tx_hash: [0u8; 32],
+                tx_hash: unique_tx_hash(),
                 account: addr,
                 asset_id: 0,
                 amount: 100,
@@ -469,84 +1434,194 @@ mod tests {
     }
 
     #[test]
-    fn test_create_deal() {
+    fn test_withdraw_to_zero_address_rejected() {
         let mut state = State::new();
-        let maker = dummy_address(1);
+        let addr = dummy_address(1);
         let block_timestamp = 1000;
 
         let deposit_tx = dummy_tx(
-            maker,
+            addr,
             0,
             TxPayload::Deposit(Deposit {
-                tx_hash: [0u8; 32],
-                account: maker,
+                tx_hash: unique_tx_hash(),
+                account: addr,
                 asset_id: 0,
-                amount: 10000,
+                amount: 1000,
                 chain_id: default_chain_id(),
             }),
         );
         apply_tx(&mut state, &deposit_tx, block_timestamp).unwrap();
 
-        let create_deal_tx = dummy_tx(
-            maker,
+        let withdraw_tx = dummy_tx(
+            addr,
             1,
-            TxPayload::CreateDeal(CreateDeal {
-                deal_id: 42,
-                visibility: DealVisibility::Public,
-                taker: None,
-                asset_base: 0,
-                asset_quote: 1,
-                chain_id_base: default_chain_id(),
-                chain_id_quote: default_chain_id(),
-                amount_base: 1000,
-                price_quote_per_base: 100,
-                expires_at: None,
-                external_ref: None,
+            TxPayload::Withdraw(Withdraw {
+                asset_id: 0,
+                amount: 300,
+                to: ZERO_ADDRESS,
+                chain_id: default_chain_id(),
             }),
         );
-        apply_tx(&mut state, &create_deal_tx, block_timestamp).unwrap();
 
-        let deal = state.get_deal(42).unwrap();
-        assert_eq!(deal.maker, maker);
-        assert_eq!(deal.amount_base, 1000);
-        assert_eq!(deal.amount_remaining, 1000);
-        assert_eq!(deal.status, DealStatus::Pending);
+        assert!(matches!(
+            apply_tx(&mut state, &withdraw_tx, block_timestamp),
+            Err(StfError::InvalidWithdrawalDestination)
+        ));
+
+        let account = state.get_account_by_address(addr).unwrap();
+        assert_eq!(account.balance_of(0, default_chain_id()), 1000);
+        assert_eq!(account.nonce, 1);
     }
 
     #[test]
-    fn test_accept_deal() {
-        let mut state = State::new();
-        let maker = dummy_address(1);
-        let taker = dummy_address(2);
+    fn test_withdraw_destination_policies() {
+        let addr = dummy_address(1);
+        let other = dummy_address(2);
         let block_timestamp = 1000;
 
-        let maker_deposit = dummy_tx(
-            maker,
+        let deposit_tx = dummy_tx(
+            addr,
             0,
             TxPayload::Deposit(Deposit {
-                tx_hash: [0u8; 32],
-                account: maker,
+                tx_hash: unique_tx_hash(),
+                account: addr,
                 asset_id: 0,
-                amount: 10000,
+                amount: 1000,
                 chain_id: default_chain_id(),
             }),
         );
-        apply_tx(&mut state, &maker_deposit, block_timestamp).unwrap();
+        let withdraw_to = |to: Address| {
+            dummy_tx(
+                addr,
+                1,
+                TxPayload::Withdraw(Withdraw {
+                    asset_id: 0,
+                    amount: 300,
+                    to,
+                    chain_id: default_chain_id(),
+                }),
+            )
+        };
 
-        let taker_deposit = dummy_tx(
-            taker,
+        let sender_only = StfConfig {
+            withdrawal_destination_policy: WithdrawalDestinationPolicy::SenderOnly,
+            ..Default::default()
+        };
+        let mut state = State::new();
+        apply_tx(&mut state, &deposit_tx, block_timestamp).unwrap();
+        assert!(matches!(
+            apply_tx_with_config(
+                &mut state,
+                &withdraw_to(other),
+                block_timestamp,
+                &sender_only
+            ),
+            Err(StfError::InvalidWithdrawalDestination)
+        ));
+        apply_tx_with_config(
+            &mut state,
+            &withdraw_to(addr),
+            block_timestamp,
+            &sender_only,
+        )
+        .unwrap();
+
+        let allowlist = StfConfig {
+            withdrawal_destination_policy: WithdrawalDestinationPolicy::Allowlist(
+                [other].into_iter().collect(),
+            ),
+            ..Default::default()
+        };
+        let mut state = State::new();
+        apply_tx(&mut state, &deposit_tx, block_timestamp).unwrap();
+        assert!(matches!(
+            apply_tx_with_config(&mut state, &withdraw_to(addr), block_timestamp, &allowlist),
+            Err(StfError::InvalidWithdrawalDestination)
+        ));
+        apply_tx_with_config(&mut state, &withdraw_to(other), block_timestamp, &allowlist).unwrap();
+
+        let account = state.get_account_by_address(addr).unwrap();
+        assert_eq!(account.balance_of(0, default_chain_id()), 700);
+    }
+
+    fn transfer_tx(from: Address, nonce: u64, to: Address, amount: u128) -> Tx {
+        dummy_tx(
+            from,
+            nonce,
+            TxPayload::Transfer(Transfer {
+                to,
+                asset_id: 0,
+                amount,
+                chain_id: default_chain_id(),
+            }),
+        )
+    }
+
+    #[test]
+    fn test_transfer() {
+        let mut state = State::new();
+        let alice = dummy_address(1);
+        let bob = dummy_address(2);
+        apply_tx(&mut state, &deposit_tx(alice, 0, 0, 100), 1000).unwrap();
+
+        apply_tx(&mut state, &transfer_tx(alice, 1, bob, 30), 1000).unwrap();
+        assert_eq!(free_balance(&state, alice), 70);
+        assert_eq!(free_balance(&state, bob), 30);
+        assert_eq!(state.get_account_by_address(alice).unwrap().nonce, 2);
+    }
+
+    #[test]
+    fn test_transfer_to_self_or_zero_address_rejected() {
+        let mut state = State::new();
+        let alice = dummy_address(1);
+        apply_tx(&mut state, &deposit_tx(alice, 0, 0, 100), 1000).unwrap();
+
+        for to in [alice, ZERO_ADDRESS] {
+            let result = apply_tx(&mut state, &transfer_tx(alice, 1, to, 30), 1000);
+            assert!(matches!(result, Err(StfError::InvalidTransferRecipient)));
+        }
+        assert_eq!(free_balance(&state, alice), 100);
+        assert!(state.get_account_by_address(ZERO_ADDRESS).is_none());
+    }
+
+    #[test]
+    fn test_transfer_insufficient_balance() {
+        let mut state = State::new();
+        let alice = dummy_address(1);
+        let bob = dummy_address(2);
+        apply_tx(&mut state, &deposit_tx(alice, 0, 0, 100), 1000).unwrap();
+
+        let result = apply_tx(&mut state, &transfer_tx(alice, 1, bob, 101), 1000);
+        assert!(matches!(result, Err(StfError::BalanceTooLow)));
+        assert_eq!(free_balance(&state, alice), 100);
+        assert_eq!(free_balance(&state, bob), 0);
+        assert_eq!(state.get_account_by_address(alice).unwrap().nonce, 1);
+    }
+
+    #[test]
+    fn test_disabled_tx_kind_rejected() {
+        let mut state = State::new();
+        let maker = dummy_address(1);
+        let block_timestamp = 1000;
+        let config = StfConfig {
+            enabled_tx_kinds: Some([TxKind::Deposit, TxKind::Withdraw].into_iter().collect()),
+            ..Default::default()
+        };
+
+        let deposit_tx = dummy_tx(
+            maker,
             0,
             TxPayload::Deposit(Deposit {
-                tx_hash: [1u8; 32],
-                account: taker,
-                asset_id: 1,
-                amount: 100000,
+                tx_hash: unique_tx_hash(),
+                account: maker,
+                asset_id: 0,
+                amount: 10000,
                 chain_id: default_chain_id(),
             }),
         );
-        apply_tx(&mut state, &taker_deposit, block_timestamp).unwrap();
+        apply_tx_with_config(&mut state, &deposit_tx, block_timestamp, &config).unwrap();
 
-        let create_deal = dummy_tx(
+        let create_deal_tx = dummy_tx(
             maker,
             1,
             TxPayload::CreateDeal(CreateDeal {
@@ -563,40 +1638,1923 @@ mod tests {
                 external_ref: None,
             }),
         );
-        apply_tx(&mut state, &create_deal, block_timestamp).unwrap();
+        assert!(matches!(
+            apply_tx_with_config(&mut state, &create_deal_tx, block_timestamp, &config),
+            Err(StfError::TxKindDisabled)
+        ));
 
-        let accept_deal = dummy_tx(
-            taker,
-            1,
+        // Labelling the disabled payload with an enabled kind doesn't help
+        let mut relabelled = create_deal_tx.clone();
+        relabelled.kind = TxKind::Deposit;
+        assert!(matches!(
+            apply_tx_with_config(&mut state, &relabelled, block_timestamp, &config),
+            Err(StfError::KindMismatch)
+        ));
+        assert!(state.get_deal(42).is_none());
+        assert_eq!(state.get_account_by_address(maker).unwrap().nonce, 1);
+    }
+
+    #[test]
+    fn test_quote_rounding_policies() {
+        assert_eq!(QuoteRounding::QuoteUp.divide(4500, 1000), 5);
+        assert_eq!(QuoteRounding::QuoteDown.divide(4500, 1000), 4);
+        assert_eq!(QuoteRounding::Banker.divide(4500, 1000), 4);
+        assert_eq!(QuoteRounding::Banker.divide(7500, 1000), 8);
+        assert_eq!(QuoteRounding::Banker.divide(4501, 1000), 5);
+        assert_eq!(QuoteRounding::Banker.divide(4499, 1000), 4);
+        for policy in [
+            QuoteRounding::QuoteUp,
+            QuoteRounding::QuoteDown,
+            QuoteRounding::Banker,
+        ] {
+            assert_eq!(policy.divide(6000, 1000), 6);
+        }
+    }
+
+    #[test]
+    fn test_accept_deal_applies_rounding_policy() {
+        let maker = dummy_address(1);
+        let taker = dummy_address(2);
+        let block_timestamp = 1000;
+
+        // 3 base units at 1.5 quote per base (price 1500 at scale 1000)
+        // costs 4.5 quote units
+        for (policy, expected_quote) in [
+            (QuoteRounding::QuoteUp, 5),
+            (QuoteRounding::QuoteDown, 4),
+            (QuoteRounding::Banker, 4),
+        ] {
+            let config = StfConfig {
+                price_scale: 1000,
+                quote_rounding: policy,
+                ..Default::default()
+            };
+            let mut state = State::new();
+
+            let deposit = |who: Address, asset_id: AssetId, amount: u128| {
+                dummy_tx(
+                    who,
+                    0,
+                    TxPayload::Deposit(Deposit {
+                        tx_hash: unique_tx_hash(),
+                        account: who,
+                        asset_id,
+                        amount,
+                        chain_id: default_chain_id(),
+                    }),
+                )
+            };
+            apply_tx_with_config(&mut state, &deposit(maker, 0, 3), block_timestamp, &config)
+                .unwrap();
+            apply_tx_with_config(&mut state, &deposit(taker, 1, 10), block_timestamp, &config)
+                .unwrap();
+
+            let create_deal = dummy_tx(
+                maker,
+                1,
+                TxPayload::CreateDeal(CreateDeal {
+                    deal_id: 42,
+                    visibility: DealVisibility::Public,
+                    taker: None,
+                    asset_base: 0,
+                    asset_quote: 1,
+                    chain_id_base: default_chain_id(),
+                    chain_id_quote: default_chain_id(),
+                    amount_base: 3,
+                    price_quote_per_base: 1500,
+                    expires_at: None,
+                    external_ref: None,
+                }),
+            );
+            apply_tx_with_config(&mut state, &create_deal, block_timestamp, &config).unwrap();
+
+            let accept_deal = dummy_tx(
+                taker,
+                1,
+                TxPayload::AcceptDeal(AcceptDeal {
+                    deal_id: 42,
+                    amount: None,
+                    max_price_quote_per_base: None,
+                }),
+            );
+            apply_tx_with_config(&mut state, &accept_deal, block_timestamp, &config).unwrap();
+
+            let balance = |who: Address, asset_id: AssetId| {
+                state
+                    .get_account_by_address(who)
+                    .unwrap()
+                    .balances
+                    .iter()
+                    .find(|b| b.asset_id == asset_id)
+                    .map(|b| b.amount)
+                    .unwrap_or(0)
+            };
+            assert_eq!(balance(maker, 1), expected_quote, "{:?}", policy);
+            assert_eq!(balance(taker, 1), 10 - expected_quote, "{:?}", policy);
+            assert_eq!(balance(maker, 1) + balance(taker, 1), 10);
+            assert_eq!(balance(taker, 0), 3);
+            assert_eq!(balance(maker, 0), 0);
+        }
+    }
+
+    #[test]
+    fn test_accept_deal_failure_mid_settlement_leaves_balances_untouched() {
+        let mut state = State::new();
+        let maker = dummy_address(1);
+        let taker = dummy_address(2);
+        let block_timestamp = 1000;
+
+        let deposit = |who: Address, nonce: u64, asset_id: AssetId, amount: u128| {
+            dummy_tx(
+                who,
+                nonce,
+                TxPayload::Deposit(Deposit {
+                    tx_hash: unique_tx_hash(),
+                    account: who,
+                    asset_id,
+                    amount,
+                    chain_id: default_chain_id(),
+                }),
+            )
+        };
+        apply_tx(&mut state, &deposit(maker, 0, 0, 1000), block_timestamp).unwrap();
+        apply_tx(&mut state, &deposit(taker, 0, 1, 100000), block_timestamp).unwrap();
+        // Taker already holds the base asset at the limit, so crediting the
+        // fill (the last settlement step) overflows after the debits succeed
+        apply_tx(
+            &mut state,
+            &deposit(taker, 1, 0, u128::MAX),
+            block_timestamp,
+        )
+        .unwrap();
+
+        let create_deal = dummy_tx(
+            maker,
+            1,
+            TxPayload::CreateDeal(CreateDeal {
+                deal_id: 42,
+                visibility: DealVisibility::Public,
+                taker: None,
+                asset_base: 0,
+                asset_quote: 1,
+                chain_id_base: default_chain_id(),
+                chain_id_quote: default_chain_id(),
+                amount_base: 1000,
+                price_quote_per_base: 100,
+                expires_at: None,
+                external_ref: None,
+            }),
+        );
+        apply_tx(&mut state, &create_deal, block_timestamp).unwrap();
+
+        let balances = |state: &State, who: Address| {
+            let mut balances: Vec<_> = state
+                .get_account_by_address(who)
+                .unwrap()
+                .balances
+                .iter()
+                .map(|b| (b.asset_id, b.chain_id, b.amount))
+                .collect();
+            balances.sort();
+            balances
+        };
+        let maker_before = balances(&state, maker);
+        let taker_before = balances(&state, taker);
+
+        let accept_deal = dummy_tx(
+            taker,
+            2,
+            TxPayload::AcceptDeal(AcceptDeal {
+                deal_id: 42,
+                amount: None,
+                max_price_quote_per_base: None,
+            }),
+        );
+        assert!(matches!(
+            apply_tx(&mut state, &accept_deal, block_timestamp),
+            Err(StfError::Overflow)
+        ));
+
+        assert_eq!(balances(&state, maker), maker_before);
+        assert_eq!(balances(&state, taker), taker_before);
+        assert_eq!(base_holdings(&state, maker), (0, 1000));
+        let deal = state.get_deal(42).unwrap();
+        assert_eq!(deal.status, DealStatus::Pending);
+        assert_eq!(deal.amount_remaining, 1000);
+        assert_eq!(state.get_account_by_address(taker).unwrap().nonce, 2);
+    }
+
+    #[test]
+    fn test_accept_respects_max_price() {
+        let mut state = State::new();
+        let maker = dummy_address(1);
+        let taker = dummy_address(2);
+        apply_tx(&mut state, &deposit_tx(maker, 0, 0, 1000), 1000).unwrap();
+        apply_tx(&mut state, &deposit_tx(taker, 0, 1, 100_000), 1000).unwrap();
+        // Priced at 100 quote per base
+        apply_tx(&mut state, &create_deal_tx(maker, 1, 1, 1000), 1000).unwrap();
+
+        let accept = |nonce: u64, max_price: u128| {
+            dummy_tx(
+                taker,
+                nonce,
+                TxPayload::AcceptDeal(AcceptDeal {
+                    deal_id: 1,
+                    amount: Some(10),
+                    max_price_quote_per_base: Some(max_price),
+                }),
+            )
+        };
+
+        assert!(matches!(
+            apply_tx(&mut state, &accept(1, 99), 1000),
+            Err(StfError::PriceExceeded)
+        ));
+        assert_eq!(
+            balance_of(&state, taker, 1, default_chain_id()).raw(),
+            100_000
+        );
+        assert_eq!(balance_of(&state, taker, 0, default_chain_id()).raw(), 0);
+        assert_eq!(base_holdings(&state, maker), (0, 1000));
+        assert_eq!(state.get_deal(1).unwrap().amount_remaining, 1000);
+
+        // Failed txs don't consume the nonce
+        apply_tx(&mut state, &accept(1, 100), 1000).unwrap();
+        assert_eq!(
+            balance_of(&state, taker, 1, default_chain_id()).raw(),
+            99_000
+        );
+        assert_eq!(balance_of(&state, taker, 0, default_chain_id()).raw(), 10);
+        assert_eq!(state.get_deal(1).unwrap().amount_remaining, 990);
+    }
+
+    #[test]
+    fn test_create_deal() {
+        let mut state = State::new();
+        let maker = dummy_address(1);
+        let block_timestamp = 1000;
+
+        let deposit_tx = dummy_tx(
+            maker,
+            0,
+            TxPayload::Deposit(Deposit {
+                tx_hash: unique_tx_hash(),
+                account: maker,
+                asset_id: 0,
+                amount: 10000,
+                chain_id: default_chain_id(),
+            }),
+        );
+        apply_tx(&mut state, &deposit_tx, block_timestamp).unwrap();
+
+        let create_deal_tx = dummy_tx(
+            maker,
+            1,
+            TxPayload::CreateDeal(CreateDeal {
+                deal_id: 42,
+                visibility: DealVisibility::Public,
+                taker: None,
+                asset_base: 0,
+                asset_quote: 1,
+                chain_id_base: default_chain_id(),
+                chain_id_quote: default_chain_id(),
+                amount_base: 1000,
+                price_quote_per_base: 100,
+                expires_at: None,
+                external_ref: None,
+            }),
+        );
+        apply_tx(&mut state, &create_deal_tx, block_timestamp).unwrap();
+
+        let deal = state.get_deal(42).unwrap();
+        assert_eq!(deal.maker, maker);
+        assert_eq!(deal.amount_base, 1000);
+        assert_eq!(deal.amount_remaining, 1000);
+        assert_eq!(deal.status, DealStatus::Pending);
+    }
+
+    #[test]
+    fn test_external_ref_lookup_and_duplicate_rejection() {
+        let mut state = State::new();
+        let maker = dummy_address(1);
+        let block_timestamp = 1000;
+
+        let create_deal_from = |maker: Address, nonce: u64, deal_id: DealId, external_ref: &str| {
+            dummy_tx(
+                maker,
+                nonce,
+                TxPayload::CreateDeal(CreateDeal {
+                    deal_id,
+                    visibility: DealVisibility::Public,
+                    taker: None,
+                    asset_base: 0,
+                    asset_quote: 1,
+                    chain_id_base: default_chain_id(),
+                    chain_id_quote: default_chain_id(),
+                    amount_base: 1000,
+                    price_quote_per_base: 100,
+                    expires_at: None,
+                    external_ref: Some(external_ref.to_string()),
+                }),
+            )
+        };
+        let create_deal = |nonce: u64, deal_id: DealId, external_ref: &str| {
+            create_deal_from(maker, nonce, deal_id, external_ref)
+        };
+
+        apply_tx(&mut state, &deposit_tx(maker, 0, 0, 2000), block_timestamp).unwrap();
+        apply_tx(&mut state, &create_deal(1, 42, "order-1"), block_timestamp).unwrap();
+        assert_eq!(
+            state.get_deal_by_external_ref(maker, "order-1").unwrap().id,
+            42
+        );
+        assert!(state.get_deal_by_external_ref(maker, "order-2").is_none());
+
+        assert!(matches!(
+            apply_tx(&mut state, &create_deal(2, 43, "order-1"), block_timestamp),
+            Err(StfError::DuplicateExternalRef)
+        ));
+        assert!(state.get_deal(43).is_none());
+
+        apply_tx(&mut state, &create_deal(2, 43, "order-2"), block_timestamp).unwrap();
+        assert_eq!(
+            state.get_deal_by_external_ref(maker, "order-2").unwrap().id,
+            43
+        );
+
+        // Another maker can't claim the ref first, nor is blocked by it
+        let other = dummy_address(2);
+        apply_tx(&mut state, &deposit_tx(other, 0, 0, 1000), block_timestamp).unwrap();
+        apply_tx(
+            &mut state,
+            &create_deal_from(other, 1, 44, "order-1"),
+            block_timestamp,
+        )
+        .unwrap();
+        assert_eq!(
+            state.get_deal_by_external_ref(other, "order-1").unwrap().id,
+            44
+        );
+        assert_eq!(
+            state.get_deal_by_external_ref(maker, "order-1").unwrap().id,
+            42
+        );
+    }
+
+    #[test]
+    fn test_external_ref_length_and_characters_checked() {
+        let mut state = State::new();
+        let maker = dummy_address(1);
+        let config = StfConfig {
+            max_external_ref_len: 8,
+            ..StfConfig::default()
+        };
+        let create_deal = |nonce: u64, deal_id: DealId, external_ref: &str| {
+            let mut tx = create_deal_tx(maker, nonce, deal_id, 100);
+            if let TxPayload::CreateDeal(ref mut p) = tx.payload {
+                p.external_ref = Some(external_ref.to_string());
+            }
+            tx
+        };
+
+        apply_tx(&mut state, &deposit_tx(maker, 0, 0, 1000), 1000).unwrap();
+        apply_tx_with_config(&mut state, &create_deal(1, 1, "order-01"), 1000, &config).unwrap();
+        assert_eq!(
+            state
+                .get_deal_by_external_ref(maker, "order-01")
+                .unwrap()
+                .id,
+            1
+        );
+
+        assert!(matches!(
+            apply_tx_with_config(&mut state, &create_deal(2, 2, "order-002"), 1000, &config),
+            Err(StfError::ExternalRefTooLong)
+        ));
+        // Null bytes are rejected, not stripped
+        assert!(matches!(
+            apply_tx_with_config(&mut state, &create_deal(2, 2, "order\0"), 1000, &config),
+            Err(StfError::InvalidExternalRef)
+        ));
+        assert!(state.get_deal(2).is_none());
+    }
+
+    #[test]
+    fn test_accept_deal() {
+        let mut state = State::new();
+        let maker = dummy_address(1);
+        let taker = dummy_address(2);
+        let block_timestamp = 1000;
+
+        let maker_deposit = dummy_tx(
+            maker,
+            0,
+            TxPayload::Deposit(Deposit {
+                tx_hash: unique_tx_hash(),
+                account: maker,
+                asset_id: 0,
+                amount: 10000,
+                chain_id: default_chain_id(),
+            }),
+        );
+        apply_tx(&mut state, &maker_deposit, block_timestamp).unwrap();
+
+        let taker_deposit = dummy_tx(
+            taker,
+            0,
+            TxPayload::Deposit(Deposit {
+                tx_hash: [1u8; 32],
+                account: taker,
+                asset_id: 1,
+                amount: 100000,
+                chain_id: default_chain_id(),
+            }),
+        );
+        apply_tx(&mut state, &taker_deposit, block_timestamp).unwrap();
+
+        let create_deal = dummy_tx(
+            maker,
+            1,
+            TxPayload::CreateDeal(CreateDeal {
+                deal_id: 42,
+                visibility: DealVisibility::Public,
+                taker: None,
+                asset_base: 0,
+                asset_quote: 1,
+                chain_id_base: default_chain_id(),
+                chain_id_quote: default_chain_id(),
+                amount_base: 1000,
+                price_quote_per_base: 100,
+                expires_at: None,
+                external_ref: None,
+            }),
+        );
+        apply_tx(&mut state, &create_deal, block_timestamp).unwrap();
+
+        let accept_deal = dummy_tx(
+            taker,
+            1,
+            TxPayload::AcceptDeal(AcceptDeal {
+                deal_id: 42,
+                amount: None,
+                max_price_quote_per_base: None,
+            }),
+        );
+        apply_tx(&mut state, &accept_deal, block_timestamp).unwrap();
+
+        let deal = state.get_deal(42).unwrap();
+        assert_eq!(deal.status, DealStatus::Settled);
+        assert_eq!(deal.amount_remaining, 0);
+
+        let maker_account = state.get_account_by_address(maker).unwrap();
+        let taker_account = state.get_account_by_address(taker).unwrap();
+
+        let maker_quote_balance = maker_account
+            .balances
+            .iter()
+            .find(|b| b.asset_id == 1)
+            .map(|b| b.amount)
+            .unwrap_or(0);
+        assert_eq!(maker_quote_balance, 100000);
+
+        let taker_base_balance = taker_account
+            .balances
+            .iter()
+            .find(|b| b.asset_id == 0)
+            .map(|b| b.amount)
+            .unwrap_or(0);
+        assert_eq!(taker_base_balance, 1000);
+    }
+
+    #[test]
+    fn test_partial_fills_recorded() {
+        let mut state = State::new();
+        let maker = dummy_address(1);
+        let taker = dummy_address(2);
+
+        let deposits = [(maker, 0, 1000), (taker, 1, 100000)];
+        for (owner, asset_id, amount) in deposits {
+            let deposit = dummy_tx(
+                owner,
+                0,
+                TxPayload::Deposit(Deposit {
+                    tx_hash: unique_tx_hash(),
+                    account: owner,
+                    asset_id,
+                    amount,
+                    chain_id: default_chain_id(),
+                }),
+            );
+            apply_tx(&mut state, &deposit, 1000).unwrap();
+        }
+
+        let create_deal = dummy_tx(
+            maker,
+            1,
+            TxPayload::CreateDeal(CreateDeal {
+                deal_id: 7,
+                visibility: DealVisibility::Public,
+                taker: None,
+                asset_base: 0,
+                asset_quote: 1,
+                chain_id_base: default_chain_id(),
+                chain_id_quote: default_chain_id(),
+                amount_base: 1000,
+                price_quote_per_base: 100,
+                expires_at: None,
+                external_ref: None,
+            }),
+        );
+        apply_tx(&mut state, &create_deal, 1000).unwrap();
+
+        let accept = |nonce, amount| {
+            dummy_tx(
+                taker,
+                nonce,
+                TxPayload::AcceptDeal(AcceptDeal {
+                    deal_id: 7,
+                    amount: Some(amount),
+                    max_price_quote_per_base: None,
+                }),
+            )
+        };
+
+        apply_tx(&mut state, &accept(1, 400), 2000).unwrap();
+        let deal = state.get_deal(7).unwrap();
+        assert_eq!(deal.status, DealStatus::Pending);
+        assert_eq!(deal.amount_remaining, 600);
+
+        apply_tx(&mut state, &accept(2, 600), 3000).unwrap();
+        let deal = state.get_deal(7).unwrap();
+        assert_eq!(deal.status, DealStatus::Settled);
+
+        let fills = state.get_deal_fills(7);
+        assert_eq!(
+            fills,
+            [
+                Fill {
+                    deal_id: 7,
+                    taker,
+                    amount_base: 400,
+                    amount_quote: 40000,
+                    block_timestamp: 2000,
+                },
+                Fill {
+                    deal_id: 7,
+                    taker,
+                    amount_base: 600,
+                    amount_quote: 60000,
+                    block_timestamp: 3000,
+                },
+            ]
+        );
+        assert_eq!(fills.iter().map(|f| f.amount_base).sum::<u128>(), 1000);
+
+        // A rejected accept records nothing
+        assert!(apply_tx(&mut state, &accept(3, 1), 4000).is_err());
+        assert_eq!(state.get_deal_fills(7).len(), 2);
+    }
+
+    fn registered_asset(id: AssetId, symbol: &str, chain_id: ChainId) -> zkclear_types::Asset {
+        zkclear_types::Asset {
+            id,
+            symbol: symbol.to_string(),
+            decimals: 6,
+            chain_id,
+            contract_address: None,
+            is_wrapped: false,
+            original_chain_id: None,
+        }
+    }
+
+    fn quote_on_chain_tx(
+        maker: Address,
+        nonce: u64,
+        deal_id: DealId,
+        chain_id_quote: ChainId,
+    ) -> Tx {
+        dummy_tx(
+            maker,
+            nonce,
+            TxPayload::CreateDeal(CreateDeal {
+                deal_id,
+                visibility: DealVisibility::Public,
+                taker: None,
+                asset_base: 0,
+                asset_quote: 1,
+                chain_id_base: default_chain_id(),
+                chain_id_quote,
+                amount_base: 100,
+                price_quote_per_base: 1,
+                expires_at: None,
+                external_ref: None,
+            }),
+        )
+    }
+
+    #[test]
+    fn test_deal_with_unregistered_asset_rejected() {
+        use zkclear_types::chain_ids::{BASE, ETHEREUM, POLYGON};
+
+        let mut state = State::new();
+        state.register_asset(registered_asset(0, "WETH", ETHEREUM));
+        state.register_asset(registered_asset(1, "USDC", ETHEREUM));
+        state.register_asset(registered_asset(1, "USDC", BASE));
+        let maker = dummy_address(1);
+        apply_tx(&mut state, &deposit_tx(maker, 0, 0, 1000), 1000).unwrap();
+
+        apply_tx(&mut state, &quote_on_chain_tx(maker, 1, 1, BASE), 1000).unwrap();
+        assert!(state.get_deal(1).unwrap().is_cross_chain);
+
+        let result = apply_tx(&mut state, &quote_on_chain_tx(maker, 2, 2, POLYGON), 1000);
+        assert!(matches!(result, Err(StfError::UnknownAsset)));
+        assert!(state.get_deal(2).is_none());
+        assert_eq!(base_holdings(&state, maker), (900, 100));
+    }
+
+    #[test]
+    fn test_accept_deal_with_unregistered_asset_rejected() {
+        let mut state = State::new();
+        let maker = dummy_address(1);
+        let taker = dummy_address(2);
+        apply_tx(&mut state, &deposit_tx(maker, 0, 0, 1000), 1000).unwrap();
+        apply_tx(&mut state, &create_deal_tx(maker, 1, 1, 100), 1000).unwrap();
+
+        // Registering assets after the deal was created restricts settlement
+        state.register_asset(registered_asset(0, "WETH", default_chain_id()));
+        let accept = dummy_tx(
+            taker,
+            0,
+            TxPayload::AcceptDeal(AcceptDeal {
+                deal_id: 1,
+                amount: None,
+                max_price_quote_per_base: None,
+            }),
+        );
+        let result = apply_tx(&mut state, &accept, 1000);
+        assert!(matches!(result, Err(StfError::UnknownAsset)));
+        assert_eq!(state.get_deal(1).unwrap().status, DealStatus::Pending);
+    }
+
+    /// A cross-chain deal (quote on Base) created by `maker` at t=1000 whose
+    /// fills are held in escrow until `confirmer` signs off
+    fn escrowed_cross_chain_deal(maker: Address, taker: Address) -> (State, StfConfig) {
+        use zkclear_types::chain_ids::BASE;
+
+        let config = StfConfig {
+            settlement_confirmer: Some(dummy_address(9)),
+            settlement_timeout_seconds: 100,
+            ..Default::default()
+        };
+        let mut state = State::new();
+        apply_tx(&mut state, &deposit_tx(maker, 0, 0, 1000), 1000).unwrap();
+        let taker_deposit = dummy_tx(
+            taker,
+            0,
+            TxPayload::Deposit(Deposit {
+                tx_hash: unique_tx_hash(),
+                account: taker,
+                asset_id: 1,
+                amount: 500,
+                chain_id: BASE,
+            }),
+        );
+        apply_tx(&mut state, &taker_deposit, 1000).unwrap();
+        apply_tx(&mut state, &quote_on_chain_tx(maker, 1, 1, BASE), 1000).unwrap();
+        (state, config)
+    }
+
+    fn accept_tx(taker: Address, nonce: u64, deal_id: DealId, amount: Option<u128>) -> Tx {
+        dummy_tx(
+            taker,
+            nonce,
+            TxPayload::AcceptDeal(AcceptDeal {
+                deal_id,
+                amount,
+                max_price_quote_per_base: None,
+            }),
+        )
+    }
+
+    #[test]
+    fn test_cross_chain_fill_settles_on_confirmation() {
+        use zkclear_types::chain_ids::BASE;
+
+        let maker = dummy_address(1);
+        let taker = dummy_address(2);
+        let confirmer = dummy_address(9);
+        let (mut state, config) = escrowed_cross_chain_deal(maker, taker);
+
+        apply_tx_with_config(&mut state, &accept_tx(taker, 1, 1, None), 1010, &config).unwrap();
+        let deal = state.get_deal(1).unwrap();
+        assert_eq!(deal.status, DealStatus::Settling);
+        assert_eq!(deal.escrows[0].refund_at, 1110);
+        assert_eq!(base_holdings(&state, maker), (900, 0));
+        assert_eq!(base_holdings(&state, taker), (0, 0));
+        assert_eq!(balance_of(&state, taker, 1, BASE).raw(), 400);
+        assert!(state.get_deal_fills(1).is_empty());
+
+        // Only the configured confirmer may finalize the fill
+        let forged = dummy_tx(
+            taker,
+            2,
+            TxPayload::ConfirmSettlement(ConfirmSettlement { deal_id: 1 }),
+        );
+        let result = apply_tx_with_config(&mut state, &forged, 1020, &config);
+        assert!(matches!(result, Err(StfError::Unauthorized)));
+
+        let confirm = dummy_tx(
+            confirmer,
+            0,
+            TxPayload::ConfirmSettlement(ConfirmSettlement { deal_id: 1 }),
+        );
+        apply_tx_with_config(&mut state, &confirm, 1020, &config).unwrap();
+        let deal = state.get_deal(1).unwrap();
+        assert_eq!(deal.status, DealStatus::Settled);
+        assert!(deal.escrows.is_empty());
+        assert_eq!(base_holdings(&state, taker), (100, 0));
+        assert_eq!(balance_of(&state, maker, 1, BASE).raw(), 100);
+        assert_eq!(state.get_deal_fills(1).len(), 1);
+
+        let again = dummy_tx(
+            confirmer,
+            1,
+            TxPayload::ConfirmSettlement(ConfirmSettlement { deal_id: 1 }),
+        );
+        let result = apply_tx_with_config(&mut state, &again, 1030, &config);
+        assert!(matches!(result, Err(StfError::NotSettling)));
+    }
+
+    #[test]
+    fn test_unconfirmed_cross_chain_fill_refunded_after_timeout() {
+        use zkclear_types::chain_ids::BASE;
+
+        let maker = dummy_address(1);
+        let taker = dummy_address(2);
+        let (mut state, config) = escrowed_cross_chain_deal(maker, taker);
+
+        apply_block_with_config(
+            &mut state,
+            &[accept_tx(taker, 1, 1, Some(60))],
+            1010,
+            &config,
+        )
+        .unwrap();
+        let deal = state.get_deal(1).unwrap();
+        assert_eq!(deal.status, DealStatus::Pending);
+        assert_eq!(deal.amount_remaining, 40);
+        assert_eq!(deal.escrows.len(), 1);
+
+        // Only the filled amount is held, so the rest can still be filled
+        apply_block_with_config(&mut state, &[accept_tx(taker, 2, 1, None)], 1050, &config)
+            .unwrap();
+        let deal = state.get_deal(1).unwrap();
+        assert_eq!(deal.status, DealStatus::Settling);
+        assert_eq!(deal.amount_remaining, 0);
+        assert_eq!(deal.escrows.len(), 2);
+        assert_eq!(base_holdings(&state, maker), (900, 0));
+        assert_eq!(balance_of(&state, taker, 1, BASE).raw(), 400);
+
+        // Still waiting at exactly `refund_at`
+        apply_block_with_config(&mut state, &[], 1110, &config).unwrap();
+        assert_eq!(state.get_deal(1).unwrap().status, DealStatus::Settling);
+
+        // Each fill is refunded on its own timeout
+        apply_block_with_config(&mut state, &[], 1111, &config).unwrap();
+        let deal = state.get_deal(1).unwrap();
+        assert_eq!(deal.status, DealStatus::Pending);
+        assert_eq!(deal.amount_remaining, 60);
+        assert_eq!(deal.escrows.len(), 1);
+        assert_eq!(base_holdings(&state, maker), (900, 60));
+        assert_eq!(balance_of(&state, taker, 1, BASE).raw(), 460);
+
+        apply_block_with_config(&mut state, &[], 1151, &config).unwrap();
+        let deal = state.get_deal(1).unwrap();
+        assert_eq!(deal.status, DealStatus::Pending);
+        assert_eq!(deal.amount_remaining, 100);
+        assert!(deal.escrows.is_empty());
+        assert_eq!(base_holdings(&state, maker), (900, 100));
+        assert_eq!(balance_of(&state, taker, 1, BASE).raw(), 500);
+        assert!(state.get_deal_fills(1).is_empty());
+    }
+
+    #[test]
+    fn test_partial_cross_chain_fill_confirmed_while_deal_stays_open() {
+        use zkclear_types::chain_ids::BASE;
+
+        let maker = dummy_address(1);
+        let taker = dummy_address(2);
+        let confirmer = dummy_address(9);
+        let (mut state, config) = escrowed_cross_chain_deal(maker, taker);
+
+        apply_tx_with_config(&mut state, &accept_tx(taker, 1, 1, Some(60)), 1010, &config).unwrap();
+        let confirm = dummy_tx(
+            confirmer,
+            0,
+            TxPayload::ConfirmSettlement(ConfirmSettlement { deal_id: 1 }),
+        );
+        apply_tx_with_config(&mut state, &confirm, 1020, &config).unwrap();
+
+        let deal = state.get_deal(1).unwrap();
+        assert_eq!(deal.status, DealStatus::Pending);
+        assert_eq!(deal.amount_remaining, 40);
+        assert!(deal.escrows.is_empty());
+        assert_eq!(base_holdings(&state, taker), (60, 0));
+        assert_eq!(balance_of(&state, maker, 1, BASE).raw(), 60);
+        assert_eq!(state.get_deal_fills(1).len(), 1);
+
+        // The maker can still cancel what was never filled
+        let cancel = dummy_tx(maker, 2, TxPayload::CancelDeal(CancelDeal { deal_id: 1 }));
+        apply_tx_with_config(&mut state, &cancel, 1030, &config).unwrap();
+        assert_eq!(base_holdings(&state, maker), (940, 0));
+    }
+
+    #[test]
+    fn test_create_deal_rejects_overcommitment() {
+        let mut state = State::new();
+        let maker = dummy_address(1);
+
+        apply_tx(&mut state, &deposit_tx(maker, 0, 0, 1000), 1000).unwrap();
+        apply_tx(&mut state, &create_deal_tx(maker, 1, 1, 700), 1000).unwrap();
+        assert_eq!(base_holdings(&state, maker), (300, 700));
+
+        // Only 300 is still free, so a second deal for 700 must be refused
+        assert!(matches!(
+            apply_tx(&mut state, &create_deal_tx(maker, 2, 2, 700), 1000),
+            Err(StfError::BalanceTooLow)
+        ));
+        assert!(state.get_deal(2).is_none());
+        assert_eq!(base_holdings(&state, maker), (300, 700));
+
+        // Reserved funds can't be withdrawn either
+        let withdraw = dummy_tx(
+            maker,
+            2,
+            TxPayload::Withdraw(Withdraw {
+                asset_id: 0,
+                amount: 301,
+                to: maker,
+                chain_id: default_chain_id(),
+            }),
+        );
+        assert!(matches!(
+            apply_tx(&mut state, &withdraw, 1000),
+            Err(StfError::BalanceTooLow)
+        ));
+
+        apply_tx(&mut state, &create_deal_tx(maker, 2, 2, 300), 1000).unwrap();
+        assert_eq!(base_holdings(&state, maker), (0, 1000));
+    }
+
+    fn with_price(mut tx: Tx, price: u128) -> Tx {
+        if let TxPayload::CreateDeal(ref mut p) = tx.payload {
+            p.price_quote_per_base = price;
+        }
+        tx
+    }
+
+    #[test]
+    fn test_create_deal_rejects_invalid_params() {
+        let mut state = State::new();
+        let maker = dummy_address(1);
+        apply_tx(&mut state, &deposit_tx(maker, 0, 0, 1000), 1000).unwrap();
+
+        assert!(matches!(
+            apply_tx(&mut state, &create_deal_tx(maker, 1, 1, 0), 1000),
+            Err(StfError::InvalidDealParams)
+        ));
+        assert!(matches!(
+            apply_tx(
+                &mut state,
+                &with_price(create_deal_tx(maker, 1, 1, 100), 0),
+                1000
+            ),
+            Err(StfError::InvalidDealParams)
+        ));
+        // amount * price doesn't fit in a u128, so no fill could ever settle
+        assert!(matches!(
+            apply_tx(
+                &mut state,
+                &with_price(create_deal_tx(maker, 1, 1, 100), u128::MAX / 2),
+                1000
+            ),
+            Err(StfError::Overflow)
+        ));
+
+        assert!(state.get_deal(1).is_none());
+        assert_eq!(base_holdings(&state, maker), (1000, 0));
+        assert_eq!(state.get_account_by_address(maker).unwrap().nonce, 1);
+    }
+
+    fn funded_deal_tx(maker: Address, nonce: u64, tx_hash: [u8; 32], amount_base: u128) -> Tx {
+        let TxPayload::CreateDeal(deal) = create_deal_tx(maker, nonce, 1, amount_base).payload
+        else {
+            unreachable!()
+        };
+        dummy_tx(
+            maker,
+            nonce,
+            TxPayload::CreateFundedDeal(CreateFundedDeal {
+                deposit: Deposit {
+                    tx_hash,
+                    account: maker,
+                    asset_id: 0,
+                    amount: 1000,
+                    chain_id: default_chain_id(),
+                },
+                deal,
+            }),
+        )
+    }
+
+    #[test]
+    fn test_create_funded_deal_credits_and_reserves() {
+        let mut state = State::new();
+        let maker = dummy_address(1);
+        let tx_hash = unique_tx_hash();
+
+        apply_tx(&mut state, &funded_deal_tx(maker, 0, tx_hash, 600), 1000).unwrap();
+
+        assert_eq!(base_holdings(&state, maker), (400, 600));
+        assert_eq!(state.get_deal(1).unwrap().amount_remaining, 600);
+        assert!(state.is_deposit_processed(&tx_hash));
+        assert_eq!(state.get_account_by_address(maker).unwrap().nonce, 1);
+    }
+
+    #[test]
+    fn test_create_funded_deal_rejects_used_deposit() {
+        let mut state = State::new();
+        let maker = dummy_address(1);
+        let deposit = deposit_tx(maker, 0, 0, 1000);
+        let TxPayload::Deposit(ref d) = deposit.payload else {
+            unreachable!()
+        };
+        let tx_hash = d.tx_hash;
+        apply_tx(&mut state, &deposit, 1000).unwrap();
+
+        assert!(matches!(
+            apply_tx(&mut state, &funded_deal_tx(maker, 1, tx_hash, 600), 1000),
+            Err(StfError::DuplicateDeposit)
+        ));
+        assert!(state.get_deal(1).is_none());
+        assert_eq!(base_holdings(&state, maker), (1000, 0));
+        assert_eq!(state.get_account_by_address(maker).unwrap().nonce, 1);
+    }
+
+    #[test]
+    fn test_create_funded_deal_with_bad_params_skips_deposit() {
+        let mut state = State::new();
+        let maker = dummy_address(1);
+        let tx_hash = unique_tx_hash();
+
+        // More than the deposit brings in
+        assert!(matches!(
+            apply_tx(&mut state, &funded_deal_tx(maker, 0, tx_hash, 1500), 1000),
+            Err(StfError::BalanceTooLow)
+        ));
+        assert!(matches!(
+            apply_tx(&mut state, &funded_deal_tx(maker, 0, tx_hash, 0), 1000),
+            Err(StfError::InvalidDealParams)
+        ));
+
+        assert!(state.get_deal(1).is_none());
+        assert_eq!(base_holdings(&state, maker), (0, 0));
+        assert!(!state.is_deposit_processed(&tx_hash));
+
+        // The deposit is still usable once the deal is fixed
+        apply_tx(&mut state, &funded_deal_tx(maker, 0, tx_hash, 1000), 1000).unwrap();
+        assert_eq!(base_holdings(&state, maker), (0, 1000));
+    }
+
+    /// Batch of deals of asset 0 for 1, one per `(deal_id, amount_base,
+    /// price)`
+    fn batch_deal_tx(maker: Address, nonce: u64, deals: &[(DealId, u128, u128)]) -> Tx {
+        let deals = deals
+            .iter()
+            .map(|&(deal_id, amount_base, price)| {
+                let TxPayload::CreateDeal(mut deal) =
+                    create_deal_tx(maker, nonce, deal_id, amount_base).payload
+                else {
+                    unreachable!()
+                };
+                deal.price_quote_per_base = price;
+                deal
+            })
+            .collect();
+        dummy_tx(
+            maker,
+            nonce,
+            TxPayload::BatchCreateDeal(BatchCreateDeal { deals }),
+        )
+    }
+
+    #[test]
+    fn test_batch_create_deal_opens_ladder() {
+        let mut state = State::new();
+        let maker = dummy_address(1);
+        apply_tx(&mut state, &deposit_tx(maker, 0, 0, 1000), 1000).unwrap();
+
+        let ladder = [(1, 200, 100), (2, 300, 101), (3, 500, 102)];
+        apply_tx(&mut state, &batch_deal_tx(maker, 1, &ladder), 1000).unwrap();
+
+        for (deal_id, amount_base, price) in ladder {
+            let deal = state.get_deal(deal_id).unwrap();
+            assert_eq!(deal.status, DealStatus::Pending);
+            assert_eq!(deal.amount_remaining, amount_base);
+            assert_eq!(deal.price_quote_per_base, price);
+        }
+        assert_eq!(base_holdings(&state, maker), (0, 1000));
+        assert_eq!(state.open_deal_count(maker), 3);
+        assert_eq!(state.get_account_by_address(maker).unwrap().nonce, 2);
+    }
+
+    #[test]
+    fn test_batch_create_deal_rolls_back_on_any_failure() {
+        let mut state = State::new();
+        let maker = dummy_address(1);
+        apply_tx(&mut state, &deposit_tx(maker, 0, 0, 1000), 1000).unwrap();
+        apply_tx(&mut state, &create_deal_tx(maker, 1, 7, 100), 1000).unwrap();
+        let root = state.root();
+
+        // The third deal reuses an existing id
+        let clash = [(1, 100, 100), (2, 100, 101), (7, 100, 102)];
+        assert!(matches!(
+            apply_tx(&mut state, &batch_deal_tx(maker, 2, &clash), 1000),
+            Err(StfError::DealAlreadyExists)
+        ));
+        // Two deals of the same batch share an id
+        let repeated = [(1, 100, 100), (1, 100, 101)];
+        assert!(matches!(
+            apply_tx(&mut state, &batch_deal_tx(maker, 2, &repeated), 1000),
+            Err(StfError::DealAlreadyExists)
+        ));
+        // Each deal fits the free balance, but together they don't
+        let overcommitted = [(1, 500, 100), (2, 500, 101)];
+        assert!(matches!(
+            apply_tx(&mut state, &batch_deal_tx(maker, 2, &overcommitted), 1000),
+            Err(StfError::BalanceTooLow)
+        ));
+        let zero_price = [(1, 100, 100), (2, 100, 0)];
+        assert!(matches!(
+            apply_tx(&mut state, &batch_deal_tx(maker, 2, &zero_price), 1000),
+            Err(StfError::InvalidDealParams)
+        ));
+
+        assert!(state.get_deal(1).is_none() && state.get_deal(2).is_none());
+        assert_eq!(base_holdings(&state, maker), (900, 100));
+        assert_eq!(state.get_account_by_address(maker).unwrap().nonce, 2);
+        assert_eq!(state.root(), root);
+    }
+
+    #[test]
+    fn test_batch_create_deal_count_limit() {
+        let config = StfConfig {
+            max_batch_deals: 2,
+            ..Default::default()
+        };
+        let mut state = State::new();
+        let maker = dummy_address(1);
+        apply_tx(&mut state, &deposit_tx(maker, 0, 0, 1000), 1000).unwrap();
+
+        let three = [(1, 100, 100), (2, 100, 101), (3, 100, 102)];
+        let result =
+            apply_tx_with_config(&mut state, &batch_deal_tx(maker, 1, &three), 1000, &config);
+        assert!(matches!(result, Err(StfError::BatchTooLarge)));
+        let result = apply_tx_with_config(&mut state, &batch_deal_tx(maker, 1, &[]), 1000, &config);
+        assert!(matches!(result, Err(StfError::EmptyBatch)));
+        assert_eq!(base_holdings(&state, maker), (1000, 0));
+
+        apply_tx_with_config(
+            &mut state,
+            &batch_deal_tx(maker, 1, &three[..2]),
+            1000,
+            &config,
+        )
+        .unwrap();
+        assert_eq!(base_holdings(&state, maker), (800, 200));
+    }
+
+    fn cancel_all_tx(maker: Address, nonce: u64) -> Tx {
+        dummy_tx(
+            maker,
+            nonce,
+            TxPayload::CancelAllDeals(CancelAllDeals { asset_base: None }),
+        )
+    }
+
+    #[test]
+    fn test_cancel_all_deals_releases_every_reserve() {
+        let mut state = State::new();
+        let maker = dummy_address(1);
+        let other = dummy_address(2);
+        apply_tx(&mut state, &deposit_tx(maker, 0, 0, 1000), 1000).unwrap();
+        apply_tx(&mut state, &deposit_tx(other, 0, 0, 1000), 1000).unwrap();
+        let ladder = [(1, 200, 100), (2, 300, 101), (3, 500, 102)];
+        apply_tx(&mut state, &batch_deal_tx(maker, 1, &ladder), 1000).unwrap();
+        apply_tx(&mut state, &create_deal_tx(other, 1, 4, 400), 1000).unwrap();
+
+        // No deal of the maker sells asset 1
+        let quote_only = CancelAllDeals {
+            asset_base: Some(1),
+        };
+        let result =
+            apply_cancel_all_deals(&mut state, maker, &quote_only, 1000, &StfConfig::default());
+        assert_eq!(result.unwrap(), TxReceipt::default());
+
+        let receipt = apply_tx_with_receipt(
+            &mut state,
+            &cancel_all_tx(maker, 2),
+            1000,
+            &StfConfig::default(),
+        );
+        assert_eq!(
+            receipt.unwrap(),
+            TxReceipt {
+                deals_cancelled: 3,
+                deals_remaining: 0,
+            }
+        );
+
+        for (deal_id, _, _) in ladder {
+            assert_eq!(
+                state.get_deal(deal_id).unwrap().status,
+                DealStatus::Cancelled
+            );
+        }
+        assert_eq!(base_holdings(&state, maker), (1000, 0));
+        assert_eq!(state.open_deal_count(maker), 0);
+
+        assert_eq!(state.get_deal(4).unwrap().status, DealStatus::Pending);
+        assert_eq!(base_holdings(&state, other), (600, 400));
+        assert_eq!(state.open_deal_count(other), 1);
+    }
+
+    #[test]
+    fn test_cancel_all_deals_stops_at_cap() {
+        let config = StfConfig {
+            max_cancel_all_deals: 2,
+            ..Default::default()
+        };
+        let mut state = State::new();
+        let maker = dummy_address(1);
+        apply_tx(&mut state, &deposit_tx(maker, 0, 0, 1000), 1000).unwrap();
+        let ladder = [(3, 200, 100), (1, 300, 101), (2, 500, 102)];
+        apply_tx(&mut state, &batch_deal_tx(maker, 1, &ladder), 1000).unwrap();
+
+        let all = CancelAllDeals { asset_base: None };
+        let result = apply_cancel_all_deals(&mut state, maker, &all, 1000, &config);
+        assert_eq!(
+            result.unwrap(),
+            TxReceipt {
+                deals_cancelled: 2,
+                deals_remaining: 1,
+            }
+        );
+        assert_eq!(state.get_deal(1).unwrap().status, DealStatus::Cancelled);
+        assert_eq!(state.get_deal(2).unwrap().status, DealStatus::Cancelled);
+        assert_eq!(state.get_deal(3).unwrap().status, DealStatus::Pending);
+        assert_eq!(base_holdings(&state, maker), (800, 200));
+
+        let result = apply_cancel_all_deals(&mut state, maker, &all, 1000, &config);
+        assert_eq!(
+            result.unwrap(),
+            TxReceipt {
+                deals_cancelled: 1,
+                deals_remaining: 0,
+            }
+        );
+        assert_eq!(base_holdings(&state, maker), (1000, 0));
+    }
+
+    #[test]
+    fn test_cancel_all_deals_failure_cancels_nothing() {
+        let mut state = State::new();
+        let maker = dummy_address(1);
+        apply_tx(&mut state, &deposit_tx(maker, 0, 0, 1000), 1000).unwrap();
+        let ladder = [(1, 200, 100), (2, 300, 101), (3, 500, 102)];
+        apply_tx(&mut state, &batch_deal_tx(maker, 1, &ladder), 1000).unwrap();
+
+        // A reserve short of the last deal's remaining amount
+        let account = state.get_or_create_account_by_owner(maker);
+        account.reserved.set(0, default_chain_id(), 900);
+
+        let all = CancelAllDeals { asset_base: None };
+        let result = apply_cancel_all_deals(&mut state, maker, &all, 1000, &StfConfig::default());
+        assert!(matches!(result, Err(StfError::BalanceTooLow)));
+        for (deal_id, _, _) in ladder {
+            assert_eq!(state.get_deal(deal_id).unwrap().status, DealStatus::Pending);
+        }
+        assert_eq!(state.open_deal_count(maker), 3);
+    }
+
+    #[test]
+    fn test_create_deal_below_min_notional_rejected() {
+        let mut state = State::new();
+        let maker = dummy_address(1);
+        let config = StfConfig {
+            min_notional: 10_000,
+            ..StfConfig::default()
+        };
+        apply_tx(&mut state, &deposit_tx(maker, 0, 0, 1000), 1000).unwrap();
+
+        // 99 * 100 = 9_900 quote units
+        let result =
+            apply_tx_with_config(&mut state, &create_deal_tx(maker, 1, 1, 99), 1000, &config);
+        assert!(matches!(result, Err(StfError::InvalidDealParams)));
+        assert!(state.get_deal(1).is_none());
+
+        apply_tx_with_config(&mut state, &create_deal_tx(maker, 1, 1, 100), 1000, &config).unwrap();
+        assert_eq!(base_holdings(&state, maker), (900, 100));
+    }
+
+    #[test]
+    fn test_normalize_amount_between_precisions() {
+        // 1.5 USDC (6 decimals) as an 8-decimal amount and back
+        assert_eq!(normalize_amount(1_500_000, 6, 8), Some(150_000_000));
+        assert_eq!(normalize_amount(150_000_000, 8, 6), Some(1_500_000));
+        // Scaling down truncates sub-unit remainders
+        assert_eq!(normalize_amount(199, 8, 6), Some(1));
+        assert_eq!(normalize_amount(99, 8, 6), Some(0));
+        assert_eq!(normalize_amount(u128::MAX, 0, 40), None);
+        assert_eq!(normalize_amount(u128::MAX, 40, 0), Some(0));
+    }
+
+    /// State holding WBTC (8 decimals) and USDC (6 decimals) on the default
+    /// chain, with a maker and a taker funded in both
+    fn wbtc_usdc_state(wbtc: AssetId, usdc: AssetId) -> (State, Address, Address) {
+        let mut state = State::new();
+        for (id, symbol, decimals) in [(wbtc, "WBTC", 8), (usdc, "USDC", 6)] {
+            state.register_asset(zkclear_types::Asset {
+                decimals,
+                ..registered_asset(id, symbol, default_chain_id())
+            });
+        }
+        let maker = dummy_address(1);
+        let taker = dummy_address(2);
+        for (nonce, asset_id) in [(0, wbtc), (1, usdc)] {
+            apply_tx(
+                &mut state,
+                &deposit_tx(maker, nonce, asset_id, 10u128.pow(12)),
+                1000,
+            )
+            .unwrap();
+            apply_tx(
+                &mut state,
+                &deposit_tx(taker, nonce, asset_id, 10u128.pow(12)),
+                1000,
+            )
+            .unwrap();
+        }
+        (state, maker, taker)
+    }
+
+    fn pair_deal_tx(
+        maker: Address,
+        (asset_base, asset_quote): (AssetId, AssetId),
+        amount_base: u128,
+        price_quote_per_base: u128,
+    ) -> Tx {
+        let mut tx = create_deal_tx(maker, 2, 1, amount_base);
+        if let TxPayload::CreateDeal(ref mut p) = tx.payload {
+            p.asset_base = asset_base;
+            p.asset_quote = asset_quote;
+            p.price_quote_per_base = price_quote_per_base;
+        }
+        tx
+    }
+
+    #[test]
+    fn test_decimal_aware_price_scales_wbtc_usdc_fill() {
+        let (wbtc, usdc) = (0, 1);
+        let config = StfConfig {
+            decimal_aware_prices: true,
+            ..StfConfig::default()
+        };
+        let (mut state, maker, taker) = wbtc_usdc_state(wbtc, usdc);
+
+        // 0.01 WBTC at 65_000 USDC per WBTC costs 650 USDC
+        let deal = pair_deal_tx(maker, (wbtc, usdc), 1_000_000, 65_000);
+        apply_tx_with_config(&mut state, &deal, 1000, &config).unwrap();
+        let accept = dummy_tx(
+            taker,
+            2,
+            TxPayload::AcceptDeal(AcceptDeal {
+                deal_id: 1,
+                amount: None,
+                max_price_quote_per_base: None,
+            }),
+        );
+        apply_tx_with_config(&mut state, &accept, 1000, &config).unwrap();
+
+        let chain = default_chain_id();
+        assert_eq!(
+            balance_of(&state, maker, usdc, chain).raw(),
+            10u128.pow(12) + 650_000_000
+        );
+        assert_eq!(
+            balance_of(&state, taker, usdc, chain).raw(),
+            10u128.pow(12) - 650_000_000
+        );
+        assert_eq!(
+            balance_of(&state, taker, wbtc, chain).raw(),
+            10u128.pow(12) + 1_000_000
+        );
+    }
+
+    #[test]
+    fn test_decimal_aware_price_scales_usdc_wbtc_fill() {
+        let (wbtc, usdc) = (1, 0);
+        // Prices in WBTC per USDC, scaled by 10^8
+        let config = StfConfig {
+            decimal_aware_prices: true,
+            price_scale: 100_000_000,
+            quote_rounding: QuoteRounding::QuoteDown,
+            ..StfConfig::default()
+        };
+        let (mut state, maker, taker) = wbtc_usdc_state(wbtc, usdc);
+
+        // A single micro-USDC is worth far less than a satoshi
+        let dust = pair_deal_tx(maker, (usdc, wbtc), 1, 1538);
+        assert!(matches!(
+            apply_tx_with_config(&mut state, &dust, 1000, &config),
+            Err(StfError::InvalidDealParams)
+        ));
+
+        // 650 USDC at 0.00001538 WBTC per USDC costs 999_700 satoshi
+        let deal = pair_deal_tx(maker, (usdc, wbtc), 650_000_000, 1538);
+        apply_tx_with_config(&mut state, &deal, 1000, &config).unwrap();
+
+        // A partial fill whose quote rounds down to zero would be free
+        let accept = |amount: u128| {
+            dummy_tx(
+                taker,
+                2,
+                TxPayload::AcceptDeal(AcceptDeal {
+                    deal_id: 1,
+                    amount: Some(amount),
+                    max_price_quote_per_base: None,
+                }),
+            )
+        };
+        assert!(matches!(
+            apply_tx_with_config(&mut state, &accept(100), 1000, &config),
+            Err(StfError::FillTooSmall)
+        ));
+
+        apply_tx_with_config(&mut state, &accept(650_000_000), 1000, &config).unwrap();
+        assert_eq!(
+            balance_of(&state, maker, wbtc, default_chain_id()).raw(),
+            10u128.pow(12) + 999_700
+        );
+    }
+
+    #[test]
+    fn test_cancel_deal_releases_reserve() {
+        let mut state = State::new();
+        let maker = dummy_address(1);
+        let taker = dummy_address(2);
+
+        apply_tx(&mut state, &deposit_tx(maker, 0, 0, 1000), 1000).unwrap();
+        apply_tx(&mut state, &deposit_tx(taker, 0, 1, 100000), 1000).unwrap();
+        apply_tx(&mut state, &create_deal_tx(maker, 1, 1, 1000), 1000).unwrap();
+
+        let accept = dummy_tx(
+            taker,
+            1,
+            TxPayload::AcceptDeal(AcceptDeal {
+                deal_id: 1,
+                amount: Some(250),
+                max_price_quote_per_base: None,
+            }),
+        );
+        apply_tx(&mut state, &accept, 1000).unwrap();
+        assert_eq!(base_holdings(&state, maker), (0, 750));
+
+        let cancel = dummy_tx(maker, 2, TxPayload::CancelDeal(CancelDeal { deal_id: 1 }));
+        apply_tx(&mut state, &cancel, 1000).unwrap();
+
+        assert_eq!(state.get_deal(1).unwrap().status, DealStatus::Cancelled);
+        assert_eq!(base_holdings(&state, maker), (750, 0));
+        assert_eq!(base_holdings(&state, taker), (250, 0));
+    }
+
+    #[test]
+    fn test_open_deals_per_account_limit() {
+        let mut state = State::new();
+        let maker = dummy_address(1);
+        let taker = dummy_address(2);
+        let config = StfConfig {
+            max_open_deals_per_account: Some(3),
+            ..StfConfig::default()
+        };
+
+        apply_tx(&mut state, &deposit_tx(maker, 0, 0, 1000), 1000).unwrap();
+        apply_tx(&mut state, &deposit_tx(taker, 0, 1, 100000), 1000).unwrap();
+        for deal_id in 1..=3 {
+            let create = create_deal_tx(maker, deal_id, deal_id, 100);
+            apply_tx_with_config(&mut state, &create, 1000, &config).unwrap();
+        }
+        assert_eq!(state.open_deal_count(maker), 3);
+
+        let over = create_deal_tx(maker, 4, 4, 100);
+        assert!(matches!(
+            apply_tx_with_config(&mut state, &over, 1000, &config),
+            Err(StfError::TooManyOpenDeals)
+        ));
+
+        // A cancelled and a fully filled deal both free a slot
+        let cancel = dummy_tx(maker, 4, TxPayload::CancelDeal(CancelDeal { deal_id: 1 }));
+        apply_tx_with_config(&mut state, &cancel, 1000, &config).unwrap();
+        let accept = dummy_tx(
+            taker,
+            1,
+            TxPayload::AcceptDeal(AcceptDeal {
+                deal_id: 2,
+                amount: None,
+                max_price_quote_per_base: None,
+            }),
+        );
+        apply_tx_with_config(&mut state, &accept, 1000, &config).unwrap();
+        assert_eq!(state.open_deal_count(maker), 1);
+
+        for (nonce, deal_id) in [(5, 4), (6, 5)] {
+            let create = create_deal_tx(maker, nonce, deal_id, 100);
+            apply_tx_with_config(&mut state, &create, 1000, &config).unwrap();
+        }
+        assert_eq!(state.open_deal_count(maker), 3);
+
+        let counts = state.open_deals.clone();
+        state.rebuild_open_deal_index();
+        assert_eq!(state.open_deals, counts);
+    }
+
+    fn direct_deal_tx(maker: Address, nonce: u64, deal_id: DealId, taker: Address) -> Tx {
+        let mut tx = create_deal_tx(maker, nonce, deal_id, 1000);
+        if let TxPayload::CreateDeal(ref mut p) = tx.payload {
+            p.visibility = DealVisibility::Direct;
+            p.taker = Some(taker);
+        }
+        tx
+    }
+
+    #[test]
+    fn test_designated_taker_declines_direct_deal() {
+        let mut state = State::new();
+        let maker = dummy_address(1);
+        let taker = dummy_address(2);
+        let decline = |who: Address, nonce: u64, deal_id: DealId| {
+            dummy_tx(who, nonce, TxPayload::DeclineDeal(DeclineDeal { deal_id }))
+        };
+
+        apply_tx(&mut state, &deposit_tx(maker, 0, 0, 2000), 1000).unwrap();
+        apply_tx(&mut state, &direct_deal_tx(maker, 1, 1, taker), 1000).unwrap();
+        assert_eq!(base_holdings(&state, maker), (1000, 1000));
+
+        apply_tx(&mut state, &decline(taker, 0, 1), 1000).unwrap();
+        assert_eq!(state.get_deal(1).unwrap().status, DealStatus::Cancelled);
+        assert_eq!(base_holdings(&state, maker), (2000, 0));
+
+        // Nothing is left to decline once the deal is closed
+        assert!(matches!(
+            apply_tx(&mut state, &decline(taker, 1, 1), 1000),
+            Err(StfError::DealAlreadyClosed)
+        ));
+
+        // A public deal has no designated taker
+        apply_tx(&mut state, &create_deal_tx(maker, 2, 2, 1000), 1000).unwrap();
+        assert!(matches!(
+            apply_tx(&mut state, &decline(taker, 1, 2), 1000),
+            Err(StfError::Unauthorized)
+        ));
+        assert_eq!(state.get_deal(2).unwrap().status, DealStatus::Pending);
+    }
+
+    #[test]
+    fn test_decline_deal_rejects_other_addresses() {
+        let mut state = State::new();
+        let maker = dummy_address(1);
+        let taker = dummy_address(2);
+        let stranger = dummy_address(3);
+
+        apply_tx(&mut state, &deposit_tx(maker, 0, 0, 1000), 1000).unwrap();
+        apply_tx(&mut state, &direct_deal_tx(maker, 1, 1, taker), 1000).unwrap();
+
+        for (who, nonce) in [(stranger, 0), (maker, 2)] {
+            let decline = dummy_tx(
+                who,
+                nonce,
+                TxPayload::DeclineDeal(DeclineDeal { deal_id: 1 }),
+            );
+            assert!(matches!(
+                apply_tx(&mut state, &decline, 1000),
+                Err(StfError::Unauthorized)
+            ));
+        }
+        assert_eq!(state.get_deal(1).unwrap().status, DealStatus::Pending);
+        assert_eq!(base_holdings(&state, maker), (0, 1000));
+    }
+
+    #[test]
+    fn test_partial_fills_draw_down_reserve() {
+        let mut state = State::new();
+        let maker = dummy_address(1);
+        let taker = dummy_address(2);
+
+        apply_tx(&mut state, &deposit_tx(maker, 0, 0, 1500), 1000).unwrap();
+        apply_tx(&mut state, &deposit_tx(taker, 0, 1, 100000), 1000).unwrap();
+        apply_tx(&mut state, &create_deal_tx(maker, 1, 1, 1000), 1000).unwrap();
+        assert_eq!(base_holdings(&state, maker), (500, 1000));
+
+        for (nonce, amount, expected) in [(1, 300, (500, 700)), (2, 700, (500, 0))] {
+            let accept = dummy_tx(
+                taker,
+                nonce,
+                TxPayload::AcceptDeal(AcceptDeal {
+                    deal_id: 1,
+                    amount: Some(amount),
+                    max_price_quote_per_base: None,
+                }),
+            );
+            apply_tx(&mut state, &accept, 1000).unwrap();
+            // The free balance is never touched by fills
+            assert_eq!(base_holdings(&state, maker), expected);
+        }
+
+        assert_eq!(state.get_deal(1).unwrap().status, DealStatus::Settled);
+        assert_eq!(base_holdings(&state, taker), (1000, 0));
+    }
+
+    #[test]
+    fn test_updated_at_advances_on_fill_and_cancel() {
+        let mut state = State::new();
+        let maker = dummy_address(1);
+        let taker = dummy_address(2);
+
+        apply_tx(&mut state, &deposit_tx(maker, 0, 0, 1000), 1000).unwrap();
+        apply_tx(&mut state, &deposit_tx(taker, 0, 1, 100000), 1000).unwrap();
+        apply_tx(&mut state, &create_deal_tx(maker, 1, 1, 1000), 1000).unwrap();
+        let deal = state.get_deal(1).unwrap();
+        assert_eq!((deal.created_at, deal.updated_at), (1000, 1000));
+
+        apply_tx(&mut state, &accept_tx(taker, 1, 1, Some(300)), 2000).unwrap();
+        let deal = state.get_deal(1).unwrap();
+        assert_eq!((deal.created_at, deal.updated_at), (1000, 2000));
+
+        let cancel = dummy_tx(maker, 2, TxPayload::CancelDeal(CancelDeal { deal_id: 1 }));
+        apply_tx(&mut state, &cancel, 3000).unwrap();
+        let deal = state.get_deal(1).unwrap();
+        assert_eq!(deal.status, DealStatus::Cancelled);
+        assert_eq!((deal.created_at, deal.updated_at), (1000, 3000));
+    }
+
+    #[test]
+    fn test_apply_block_expires_pending_deals() {
+        let mut state = State::new();
+        let maker = dummy_address(1);
+
+        apply_tx(&mut state, &deposit_tx(maker, 0, 0, 1000), 1000).unwrap();
+        let mut expiring = create_deal_tx(maker, 1, 1, 600);
+        if let TxPayload::CreateDeal(ref mut p) = expiring.payload {
+            p.expires_at = Some(1100);
+        }
+        apply_block(
+            &mut state,
+            &[expiring, create_deal_tx(maker, 2, 2, 400)],
+            1000,
+        )
+        .unwrap();
+        assert_eq!(base_holdings(&state, maker), (0, 1000));
+
+        // Not expired yet at exactly `expires_at`
+        apply_block(&mut state, &[], 1100).unwrap();
+        assert_eq!(state.get_deal(1).unwrap().status, DealStatus::Pending);
+
+        apply_block(&mut state, &[], 1101).unwrap();
+        assert_eq!(state.get_deal(1).unwrap().status, DealStatus::Expired);
+        assert_eq!(state.get_deal(2).unwrap().status, DealStatus::Pending);
+        assert_eq!(base_holdings(&state, maker), (600, 400));
+
+        // Later blocks leave the already expired deal alone
+        apply_block(&mut state, &[], 5000).unwrap();
+        assert_eq!(base_holdings(&state, maker), (600, 400));
+    }
+
+    #[test]
+    fn test_deal_status_transitions() {
+        use DealStatus::*;
+
+        let mut state = State::new();
+        let maker = dummy_address(1);
+        apply_tx(&mut state, &deposit_tx(maker, 0, 0, 1000), 1000).unwrap();
+        apply_tx(&mut state, &create_deal_tx(maker, 1, 1, 100), 1000).unwrap();
+        let pending = state.get_deal(1).unwrap().clone();
+        let deal_in = |status| Deal {
+            status,
+            ..pending.clone()
+        };
+
+        let legal = [
+            (Pending, Settling),
+            (Pending, Settled),
+            (Pending, Cancelled),
+            (Pending, Expired),
+            (Settling, Pending),
+            (Settling, Settled),
+        ];
+        for (from, to) in legal {
+            let mut deal = deal_in(from);
+            deal.transition(to).unwrap();
+            assert_eq!(deal.status, to);
+        }
+
+        let illegal = [
+            (Settled, Pending),
+            (Cancelled, Settled),
+            (Expired, Pending),
+            (Settling, Cancelled),
+            (Settled, Settled),
+        ];
+        for (from, to) in illegal {
+            let mut deal = deal_in(from);
+            let result = deal.transition(to).map_err(StfError::from);
+            assert!(
+                matches!(result, Err(StfError::InvalidDealTransition)),
+                "{:?} -> {:?}",
+                from,
+                to
+            );
+            assert_eq!(deal.status, from);
+        }
+
+        // Closing an already cancelled deal is refused before any balance moves
+        let cancel = dummy_tx(maker, 2, TxPayload::CancelDeal(CancelDeal { deal_id: 1 }));
+        apply_tx(&mut state, &cancel, 1000).unwrap();
+        let result = close_deal(&mut state, 1, Expired, 1000);
+        assert!(matches!(result, Err(StfError::InvalidDealTransition)));
+        assert_eq!(state.get_deal(1).unwrap().status, Cancelled);
+        assert_eq!(base_holdings(&state, maker), (1000, 0));
+    }
+
+    #[test]
+    fn test_expiry_sweep_skips_closed_deals() {
+        let mut state = State::new();
+        let maker = dummy_address(1);
+
+        apply_tx(&mut state, &deposit_tx(maker, 0, 0, 1000), 1000).unwrap();
+        let mut create = create_deal_tx(maker, 1, 1, 1000);
+        if let TxPayload::CreateDeal(ref mut p) = create.payload {
+            p.expires_at = Some(1100);
+        }
+        let cancel = dummy_tx(maker, 2, TxPayload::CancelDeal(CancelDeal { deal_id: 1 }));
+        apply_block(&mut state, &[create, cancel], 1000).unwrap();
+
+        apply_block(&mut state, &[], 2000).unwrap();
+        assert_eq!(state.get_deal(1).unwrap().status, DealStatus::Cancelled);
+        assert_eq!(base_holdings(&state, maker), (1000, 0));
+    }
+
+    fn fee_config(amount: u128) -> StfConfig {
+        StfConfig {
+            fee: Some(FeePolicy {
+                asset_id: 0,
+                chain_id: default_chain_id(),
+                amount,
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn free_balance(state: &State, who: Address) -> u128 {
+        balance_of(state, who, 0, default_chain_id()).raw()
+    }
+
+    #[test]
+    fn test_fee_collected_on_deposit() {
+        let mut state = State::new();
+        let collector = dummy_address(9);
+        let user = dummy_address(1);
+        state.fee_collector = collector;
+
+        apply_tx(&mut state, &deposit_tx(user, 0, 0, 1000), 1000).unwrap();
+        apply_tx_with_config(
+            &mut state,
+            &deposit_tx(user, 1, 0, 500),
+            1000,
+            &fee_config(10),
+        )
+        .unwrap();
+
+        assert_eq!(free_balance(&state, user), 1490);
+        assert_eq!(free_balance(&state, collector), 10);
+        assert_eq!(state.get_account_by_address(user).unwrap().nonce, 2);
+    }
+
+    #[test]
+    fn test_first_deposit_pays_its_own_fee() {
+        let mut state = State::new();
+        let collector = dummy_address(9);
+        let user = dummy_address(1);
+        state.fee_collector = collector;
+
+        let mut deposit = deposit_tx(user, 0, 0, 500);
+        deposit.fee = 2;
+        apply_tx_with_config(&mut state, &deposit, 1000, &fee_config(10)).unwrap();
+
+        assert_eq!(free_balance(&state, user), 488);
+        assert_eq!(free_balance(&state, collector), 12);
+        assert_eq!(state.get_account_by_address(user).unwrap().nonce, 1);
+    }
+
+    #[test]
+    fn test_relayed_deposit_fee_is_paid_by_depositor() {
+        let watcher = dummy_address(8);
+        let collector = dummy_address(9);
+        let user = dummy_address(1);
+        let config = StfConfig {
+            deposit_watcher: Some(watcher),
+            ..fee_config(10)
+        };
+        let mut state = State::new();
+        state.fee_collector = collector;
+
+        // The watcher holds nothing, yet relays the deposit
+        let mut relayed = deposit_tx(watcher, 0, 0, 500);
+        if let TxPayload::Deposit(ref mut p) = relayed.payload {
+            p.account = user;
+        }
+        apply_block_with_config(&mut state, &[relayed], 1000, &config).unwrap();
+
+        assert_eq!(free_balance(&state, user), 490);
+        assert_eq!(free_balance(&state, collector), 10);
+        assert_eq!(free_balance(&state, watcher), 0);
+    }
+
+    #[test]
+    fn test_fee_overflow_rejected() {
+        let mut state = State::new();
+        let user = dummy_address(1);
+        apply_tx(&mut state, &deposit_tx(user, 0, 0, 5), 1000).unwrap();
+
+        let mut deposit = deposit_tx(user, 1, 0, 500);
+        deposit.fee = u128::MAX;
+        assert!(matches!(
+            apply_tx_with_config(&mut state, &deposit, 1000, &fee_config(10)),
+            Err(StfError::Overflow)
+        ));
+        assert_eq!(free_balance(&state, user), 5);
+    }
+
+    #[test]
+    fn test_fee_shortfall_rejected() {
+        let mut state = State::new();
+        let collector = dummy_address(9);
+        let user = dummy_address(1);
+        state.fee_collector = collector;
+        apply_tx(&mut state, &deposit_tx(user, 0, 0, 5), 1000).unwrap();
+
+        // A deposit of another asset can't pay the fee, so it is not credited
+        let deposit = deposit_tx(user, 1, 1, 500);
+        assert!(matches!(
+            apply_tx_with_config(&mut state, &deposit, 1000, &fee_config(10)),
+            Err(StfError::InsufficientFee)
+        ));
+        assert_eq!(free_balance(&state, user), 5);
+        assert_eq!(balance_of(&state, user, 1, default_chain_id()).raw(), 0);
+        assert_eq!(free_balance(&state, collector), 0);
+        assert_eq!(state.get_account_by_address(user).unwrap().nonce, 1);
+        let TxPayload::Deposit(ref payload) = deposit.payload else {
+            unreachable!()
+        };
+        assert!(!state.is_deposit_processed(&payload.tx_hash));
+
+        // A payload that fails after the fee was charged rolls the fee back
+        let accept = dummy_tx(
+            user,
+            1,
             TxPayload::AcceptDeal(AcceptDeal {
                 deal_id: 42,
                 amount: None,
+                max_price_quote_per_base: None,
             }),
         );
-        apply_tx(&mut state, &accept_deal, block_timestamp).unwrap();
+        assert!(matches!(
+            apply_tx_with_config(&mut state, &accept, 1000, &fee_config(5)),
+            Err(StfError::DealNotFound)
+        ));
+        assert_eq!(free_balance(&state, user), 5);
+        assert_eq!(free_balance(&state, collector), 0);
+    }
 
-        let deal = state.get_deal(42).unwrap();
-        assert_eq!(deal.status, DealStatus::Settled);
-        assert_eq!(deal.amount_remaining, 0);
+    #[test]
+    fn test_fees_accrue_across_blocks() {
+        let mut state = State::new();
+        let collector = dummy_address(9);
+        let maker = dummy_address(1);
+        let taker = dummy_address(2);
+        state.fee_collector = collector;
+        apply_block(
+            &mut state,
+            &[
+                deposit_tx(maker, 0, 0, 1000),
+                deposit_tx(taker, 0, 0, 100),
+                deposit_tx(taker, 1, 1, 100_000),
+            ],
+            1000,
+        )
+        .unwrap();
+
+        let config = fee_config(3);
+        apply_block_with_config(
+            &mut state,
+            &[
+                create_deal_tx(maker, 1, 1, 500),
+                deposit_tx(taker, 2, 0, 50),
+            ],
+            1000,
+            &config,
+        )
+        .unwrap();
+        assert_eq!(free_balance(&state, collector), 6);
 
-        let maker_account = state.get_account_by_address(maker).unwrap();
-        let taker_account = state.get_account_by_address(taker).unwrap();
+        let accept = dummy_tx(
+            taker,
+            3,
+            TxPayload::AcceptDeal(AcceptDeal {
+                deal_id: 1,
+                amount: Some(200),
+                max_price_quote_per_base: None,
+            }),
+        );
+        let cancel = dummy_tx(maker, 2, TxPayload::CancelDeal(CancelDeal { deal_id: 1 }));
+        apply_block_with_config(&mut state, &[accept, cancel], 1001, &config).unwrap();
+
+        assert_eq!(free_balance(&state, collector), 12);
+        // 1000 deposited, 200 sold, two fees paid
+        assert_eq!(base_holdings(&state, maker), (1000 - 200 - 6, 0));
+        // 150 deposited plus 200 bought, two fees paid
+        assert_eq!(base_holdings(&state, taker), (150 + 200 - 6, 0));
+    }
 
-        let maker_quote_balance = maker_account
-            .balances
-            .iter()
-            .find(|b| b.asset_id == 1)
-            .map(|b| b.amount)
-            .unwrap_or(0);
-        assert_eq!(maker_quote_balance, 100000);
+    /// Same deposit as `original`, resubmitted with the next nonce
+    fn replayed(original: &Tx, nonce: u64) -> Tx {
+        Tx {
+            nonce,
+            ..original.clone()
+        }
+    }
 
-        let taker_base_balance = taker_account
-            .balances
-            .iter()
-            .find(|b| b.asset_id == 0)
-            .map(|b| b.amount)
-            .unwrap_or(0);
-        assert_eq!(taker_base_balance, 1000);
+    #[test]
+    fn test_replayed_deposit_rejected() {
+        let mut state = State::new();
+        let user = dummy_address(1);
+        let deposit = deposit_tx(user, 0, 0, 100);
+        apply_tx(&mut state, &deposit, 1000).unwrap();
+
+        let result = apply_tx(&mut state, &replayed(&deposit, 1), 1001);
+        assert!(matches!(result, Err(StfError::DuplicateDeposit)));
+        assert_eq!(free_balance(&state, user), 100);
+        assert_eq!(state.get_account_by_address(user).unwrap().nonce, 1);
+    }
+
+    #[test]
+    fn test_processed_deposits_survive_snapshot() {
+        let mut state = State::new();
+        let user = dummy_address(1);
+        let deposit = deposit_tx(user, 0, 0, 100);
+        apply_tx(&mut state, &deposit, 1000).unwrap();
+
+        let bytes = bincode::serialize(&state).unwrap();
+        let mut restored: State = bincode::deserialize(&bytes).unwrap();
+
+        let result = apply_tx(&mut restored, &replayed(&deposit, 1), 1001);
+        assert!(matches!(result, Err(StfError::DuplicateDeposit)));
+        assert_eq!(free_balance(&restored, user), 100);
+    }
+
+    #[test]
+    fn test_processed_deposits_pruned_after_retention() {
+        let mut state = State::new();
+        let user = dummy_address(1);
+        let config = StfConfig {
+            deposit_dedup_retention_seconds: Some(60),
+            ..StfConfig::default()
+        };
+        let deposit = deposit_tx(user, 0, 0, 100);
+        apply_block_with_config(&mut state, std::slice::from_ref(&deposit), 1000, &config).unwrap();
+
+        // Still inside the retention window
+        apply_block_with_config(&mut state, &[], 1060, &config).unwrap();
+        assert!(state.is_deposit_processed(&deposit_hash(&deposit)));
+
+        apply_block_with_config(&mut state, &[], 1061, &config).unwrap();
+        assert!(!state.is_deposit_processed(&deposit_hash(&deposit)));
+    }
+
+    fn deposit_hash(tx: &Tx) -> [u8; 32] {
+        match &tx.payload {
+            TxPayload::Deposit(deposit) => deposit.tx_hash,
+            _ => panic!("not a deposit"),
+        }
     }
 
     #[test]
@@ -609,7 +3567,7 @@ mod tests {
             addr,
             0,
             TxPayload::Deposit(Deposit {
-                tx_hash: [0u8; 32],
+                tx_hash: unique_tx_hash(),
                 account: addr,
                 asset_id: 0,
                 amount: 1000,
@@ -636,6 +3594,22 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_nonce_at_max_rejected_without_panic() {
+        let mut state = State::new();
+        let addr = dummy_address(1);
+        apply_tx(&mut state, &deposit_tx(addr, 0, 0, 1000), 1000).unwrap();
+        state.get_or_create_account_by_owner(addr).nonce = u64::MAX;
+
+        let tx = deposit_tx(addr, u64::MAX, 0, 500);
+        assert!(matches!(
+            apply_tx(&mut state, &tx, 1000),
+            Err(StfError::NonceOverflow)
+        ));
+        assert_eq!(base_holdings(&state, addr), (1000, 0));
+        assert_eq!(state.get_account_by_address(addr).unwrap().nonce, u64::MAX);
+    }
+
     #[test]
     fn test_nonce_increment() {
         let mut state = State::new();
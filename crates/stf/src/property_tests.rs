@@ -0,0 +1,356 @@
+//! Property tests for value conservation across random tx sequences
+
+use std::collections::BTreeMap;
+
+use proptest::prelude::*;
+use zkclear_state::State;
+use zkclear_types::{
+    AcceptDeal, Address, AssetId, CancelDeal, ChainId, CreateDeal, DealId, DealVisibility, Deposit,
+    SignatureScheme, Transfer, Tx, TxKind, TxPayload, Withdraw,
+};
+
+use crate::{apply_tx, StfError};
+
+const ACTORS: u8 = 3;
+const ASSETS: AssetId = 2;
+const CHAINS: [ChainId; 2] = [
+    zkclear_types::chain_ids::ETHEREUM,
+    zkclear_types::chain_ids::BASE,
+];
+const DEAL_IDS: DealId = 4;
+
+#[derive(Debug, Clone)]
+enum Op {
+    Deposit {
+        who: u8,
+        asset: AssetId,
+        chain: usize,
+        amount: u128,
+    },
+    Withdraw {
+        who: u8,
+        asset: AssetId,
+        chain: usize,
+        amount: u128,
+    },
+    CreateDeal {
+        who: u8,
+        deal_id: DealId,
+        assets: (AssetId, AssetId),
+        chains: (usize, usize),
+        amount_base: u128,
+        price: u128,
+        direct_taker: Option<u8>,
+    },
+    AcceptDeal {
+        who: u8,
+        deal_id: DealId,
+        amount: Option<u128>,
+    },
+    CancelDeal {
+        who: u8,
+        deal_id: DealId,
+    },
+    Transfer {
+        who: u8,
+        to: u8,
+        asset: AssetId,
+        chain: usize,
+        amount: u128,
+    },
+}
+
+fn actor(who: u8) -> Address {
+    [who + 1; 20]
+}
+
+fn amount() -> impl Strategy<Value = u128> {
+    // Mostly small amounts, with occasional values near the top of the range
+    // to exercise overflow paths
+    prop_oneof![
+        8 => 1u128..1_000,
+        1 => (u128::MAX - 1_000)..=u128::MAX,
+    ]
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    let who = 0..ACTORS;
+    let asset = 0..ASSETS;
+    let chain = 0..CHAINS.len();
+    let deal_id = 0..DEAL_IDS;
+
+    prop_oneof![
+        (who.clone(), asset.clone(), chain.clone(), amount()).prop_map(
+            |(who, asset, chain, amount)| Op::Deposit {
+                who,
+                asset,
+                chain,
+                amount
+            }
+        ),
+        (who.clone(), asset.clone(), chain.clone(), amount()).prop_map(
+            |(who, asset, chain, amount)| Op::Withdraw {
+                who,
+                asset,
+                chain,
+                amount
+            }
+        ),
+        (
+            who.clone(),
+            deal_id.clone(),
+            (asset.clone(), asset.clone()),
+            (chain.clone(), chain.clone()),
+            amount(),
+            prop_oneof![1u128..10, Just(u128::MAX)],
+            proptest::option::of(0..ACTORS),
+        )
+            .prop_map(
+                |(who, deal_id, assets, chains, amount_base, price, direct_taker)| {
+                    Op::CreateDeal {
+                        who,
+                        deal_id,
+                        assets,
+                        chains,
+                        amount_base,
+                        price,
+                        direct_taker,
+                    }
+                }
+            ),
+        (who.clone(), deal_id.clone(), proptest::option::of(amount())).prop_map(
+            |(who, deal_id, amount)| Op::AcceptDeal {
+                who,
+                deal_id,
+                amount
+            }
+        ),
+        (who.clone(), deal_id).prop_map(|(who, deal_id)| Op::CancelDeal { who, deal_id }),
+        (who.clone(), who, asset, chain, amount()).prop_map(
+            |(who, to, asset, chain, amount)| Op::Transfer {
+                who,
+                to,
+                asset,
+                chain,
+                amount
+            }
+        ),
+    ]
+}
+
+fn build_tx(state: &State, op: &Op, index: usize) -> Tx {
+    let (who, kind, payload) = match op.clone() {
+        Op::Deposit {
+            who,
+            asset,
+            chain,
+            amount,
+        } => (
+            who,
+            TxKind::Deposit,
+            TxPayload::Deposit(Deposit {
+                tx_hash: deposit_hash(index),
+                account: actor(who),
+                asset_id: asset,
+                amount,
+                chain_id: CHAINS[chain],
+            }),
+        ),
+        Op::Withdraw {
+            who,
+            asset,
+            chain,
+            amount,
+        } => (
+            who,
+            TxKind::Withdraw,
+            TxPayload::Withdraw(Withdraw {
+                asset_id: asset,
+                amount,
+                to: actor(who),
+                chain_id: CHAINS[chain],
+            }),
+        ),
+        Op::CreateDeal {
+            who,
+            deal_id,
+            assets,
+            chains,
+            amount_base,
+            price,
+            direct_taker,
+        } => (
+            who,
+            TxKind::CreateDeal,
+            TxPayload::CreateDeal(CreateDeal {
+                deal_id,
+                visibility: if direct_taker.is_some() {
+                    DealVisibility::Direct
+                } else {
+                    DealVisibility::Public
+                },
+                taker: direct_taker.map(actor),
+                asset_base: assets.0,
+                asset_quote: assets.1,
+                chain_id_base: CHAINS[chains.0],
+                chain_id_quote: CHAINS[chains.1],
+                amount_base,
+                price_quote_per_base: price,
+                expires_at: None,
+                external_ref: None,
+            }),
+        ),
+        Op::AcceptDeal {
+            who,
+            deal_id,
+            amount,
+        } => (
+            who,
+            TxKind::AcceptDeal,
+            TxPayload::AcceptDeal(AcceptDeal {
+                deal_id,
+                amount,
+                max_price_quote_per_base: None,
+            }),
+        ),
+        Op::CancelDeal { who, deal_id } => (
+            who,
+            TxKind::CancelDeal,
+            TxPayload::CancelDeal(CancelDeal { deal_id }),
+        ),
+        Op::Transfer {
+            who,
+            to,
+            asset,
+            chain,
+            amount,
+        } => (
+            who,
+            TxKind::Transfer,
+            TxPayload::Transfer(Transfer {
+                to: actor(to),
+                asset_id: asset,
+                amount,
+                chain_id: CHAINS[chain],
+            }),
+        ),
+    };
+
+    let from = actor(who);
+    Tx {
+        id: 0,
+        from,
+        nonce: state
+            .get_account_by_address(from)
+            .map(|a| a.nonce)
+            .unwrap_or(0),
+        kind,
+        payload,
+        fee: 0,
+        domain: 0,
+        scheme: SignatureScheme::Secp256k1Recoverable,
+        signature: vec![0u8; 65],
+    }
+}
+
+/// Distinct L1 tx hash for the deposit at position `index` in a sequence
+fn deposit_hash(index: usize) -> [u8; 32] {
+    let mut tx_hash = [0u8; 32];
+    tx_hash[..8].copy_from_slice(&(index as u64).to_le_bytes());
+    tx_hash
+}
+
+/// Total balance held across all accounts per (asset, chain), counting both
+/// free and reserved amounts. Totals wrap so that large deposits spread over
+/// several accounts can still be compared.
+fn totals(state: &State) -> BTreeMap<(AssetId, ChainId), u128> {
+    let mut totals = BTreeMap::new();
+    for account in state.accounts.values() {
+        for b in account.balances.iter().chain(&account.reserved) {
+            let total: &mut u128 = totals.entry((b.asset_id, b.chain_id)).or_default();
+            *total = total.wrapping_add(b.amount);
+        }
+    }
+    totals.retain(|_, amount| *amount > 0);
+    totals
+}
+
+/// Per-account nonce and non-zero free and reserved balances, plus a
+/// rendering of all deals
+type CanonicalState = (
+    BTreeMap<
+        Address,
+        (
+            u64,
+            Vec<(AssetId, ChainId, u128)>,
+            Vec<(AssetId, ChainId, u128)>,
+        ),
+    >,
+    String,
+);
+
+/// Canonical view of the state that ignores empty accounts and zero balances,
+/// which are never observable through the API
+fn canonical(state: &State) -> CanonicalState {
+    let mut accounts = BTreeMap::new();
+    let non_zero = |balances: &zkclear_types::Balances| {
+        let mut balances: Vec<_> = balances
+            .iter()
+            .filter(|b| b.amount > 0)
+            .map(|b| (b.asset_id, b.chain_id, b.amount))
+            .collect();
+        balances.sort();
+        balances
+    };
+    for account in state.accounts.values() {
+        let balances = non_zero(&account.balances);
+        let reserved = non_zero(&account.reserved);
+        if account.nonce > 0 || !balances.is_empty() || !reserved.is_empty() {
+            accounts.insert(account.owner, (account.nonce, balances, reserved));
+        }
+    }
+
+    let deals: BTreeMap<_, _> = state.deals.iter().collect();
+    (accounts, format!("{:?}", deals))
+}
+
+proptest! {
+    #[test]
+    fn prop_value_conserved_and_failures_are_noops(ops in prop::collection::vec(op(), 1..40)) {
+        let mut state = State::new();
+
+        for (index, op) in ops.iter().enumerate() {
+            let tx = build_tx(&state, op, index);
+            let totals_before = totals(&state);
+            let canonical_before = canonical(&state);
+
+            let result = apply_tx(&mut state, &tx, 1_000);
+
+            let mut expected = totals_before.clone();
+            match (&result, &tx.payload) {
+                (Ok(()), TxPayload::Deposit(p)) => {
+                    let total = expected.entry((p.asset_id, p.chain_id)).or_default();
+                    *total = total.wrapping_add(p.amount);
+                }
+                (Ok(()), TxPayload::Withdraw(p)) => {
+                    let total = expected.entry((p.asset_id, p.chain_id)).or_default();
+                    *total = total.wrapping_sub(p.amount);
+                }
+                _ => {}
+            }
+            expected.retain(|_, amount| *amount > 0);
+            prop_assert_eq!(totals(&state), expected, "value not conserved by {:?}", op);
+
+            if let Err(e) = result {
+                prop_assert!(!matches!(e, StfError::InvalidNonce));
+                prop_assert_eq!(
+                    canonical(&state),
+                    canonical_before,
+                    "failed {:?} ({:?}) mutated state",
+                    op,
+                    e
+                );
+            }
+        }
+    }
+}
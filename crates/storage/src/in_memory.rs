@@ -1,15 +1,27 @@
-use crate::storage_trait::{Storage, StorageError, TxId};
+use crate::storage_trait::{
+    tx_hash, BlockIter, BuilderClaim, Storage, StorageError, TxHash, TxId, STORAGE_VERSION,
+};
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{Arc, RwLock};
-use zkclear_state::State;
-use zkclear_types::{Block, BlockId, Deal, DealId, Tx};
+use zkclear_state::{SnapshotReader, State, StateDiff, DEFAULT_SNAPSHOT_CHUNK_SIZE};
+use zkclear_types::{Block, BlockId, Checkpoint, Deal, DealId, Tx};
+
+/// Encoded manifest and chunks of a state snapshot
+type ChunkedSnapshot = (Vec<u8>, Vec<Vec<u8>>);
 
 pub struct InMemoryStorage {
     blocks: Arc<RwLock<HashMap<BlockId, Block>>>,
     transactions: Arc<RwLock<HashMap<TxId, Tx>>>,
+    tx_hashes: Arc<RwLock<HashMap<TxHash, TxId>>>,
     deals: Arc<RwLock<HashMap<DealId, Deal>>>,
-    state_snapshots: Arc<RwLock<HashMap<BlockId, State>>>,
+    state_snapshots: Arc<RwLock<HashMap<BlockId, ChunkedSnapshot>>>,
+    state_diffs: Arc<RwLock<HashMap<BlockId, StateDiff>>>,
+    withdrawals_accumulators: Arc<RwLock<HashMap<BlockId, [u8; 32]>>>,
     latest_block_id: Arc<RwLock<Option<BlockId>>>,
+    latest_checkpoint: Arc<RwLock<Option<Checkpoint>>>,
+    block_builder_claims: Arc<RwLock<HashMap<BlockId, BuilderClaim>>>,
+    version: AtomicU32,
 }
 
 impl InMemoryStorage {
@@ -17,14 +29,29 @@ impl InMemoryStorage {
         Self {
             blocks: Arc::new(RwLock::new(HashMap::new())),
             transactions: Arc::new(RwLock::new(HashMap::new())),
+            tx_hashes: Arc::new(RwLock::new(HashMap::new())),
             deals: Arc::new(RwLock::new(HashMap::new())),
             state_snapshots: Arc::new(RwLock::new(HashMap::new())),
+            state_diffs: Arc::new(RwLock::new(HashMap::new())),
+            withdrawals_accumulators: Arc::new(RwLock::new(HashMap::new())),
             latest_block_id: Arc::new(RwLock::new(None)),
+            latest_checkpoint: Arc::new(RwLock::new(None)),
+            block_builder_claims: Arc::new(RwLock::new(HashMap::new())),
+            version: AtomicU32::new(STORAGE_VERSION),
         }
     }
 }
 
 impl Storage for InMemoryStorage {
+    fn version(&self) -> u32 {
+        self.version.load(Ordering::SeqCst)
+    }
+
+    fn set_version(&self, version: u32) -> Result<(), StorageError> {
+        self.version.store(version, Ordering::SeqCst);
+        Ok(())
+    }
+
     fn save_block(&self, block: &Block) -> Result<(), StorageError> {
         let mut blocks = self.blocks.write().unwrap();
         blocks.insert(block.id, block.clone());
@@ -39,6 +66,36 @@ impl Storage for InMemoryStorage {
         Ok(())
     }
 
+    fn save_block_atomic(
+        &self,
+        block: &Block,
+        diff: Option<&StateDiff>,
+        deals: &[&Deal],
+    ) -> Result<(), StorageError> {
+        // Every lock is held until all writes are done, so readers see the
+        // block either whole or not at all
+        let mut blocks = self.blocks.write().unwrap();
+        let mut transactions = self.transactions.write().unwrap();
+        let mut tx_hashes = self.tx_hashes.write().unwrap();
+        let mut stored_deals = self.deals.write().unwrap();
+        let mut state_diffs = self.state_diffs.write().unwrap();
+        let mut latest = self.latest_block_id.write().unwrap();
+
+        for (index, tx) in block.transactions.iter().enumerate() {
+            transactions.insert((block.id, index), tx.clone());
+            tx_hashes.insert(tx_hash(tx), (block.id, index));
+        }
+        for deal in deals {
+            stored_deals.insert(deal.id, (*deal).clone());
+        }
+        if let Some(diff) = diff {
+            state_diffs.insert(block.id, diff.clone());
+        }
+        blocks.insert(block.id, block.clone());
+        *latest = Some(block.id);
+        Ok(())
+    }
+
     fn get_block(&self, block_id: BlockId) -> Result<Option<Block>, StorageError> {
         let blocks = self.blocks.read().unwrap();
         Ok(blocks.get(&block_id).cloned())
@@ -49,6 +106,21 @@ impl Storage for InMemoryStorage {
         Ok(*latest)
     }
 
+    fn iter_blocks(&self, from: BlockId, to: BlockId) -> BlockIter {
+        let blocks = self.blocks.read().unwrap();
+        let mut range = Vec::new();
+        for block_id in from..=to {
+            match blocks.get(&block_id) {
+                Some(block) => range.push(Ok(block.clone())),
+                None => {
+                    range.push(Err(StorageError::NotFound));
+                    break;
+                }
+            }
+        }
+        Box::new(range.into_iter())
+    }
+
     fn save_transaction(
         &self,
         tx: &Tx,
@@ -57,6 +129,10 @@ impl Storage for InMemoryStorage {
     ) -> Result<(), StorageError> {
         let mut transactions = self.transactions.write().unwrap();
         transactions.insert((block_id, index), tx.clone());
+        self.tx_hashes
+            .write()
+            .unwrap()
+            .insert(tx_hash(tx), (block_id, index));
         Ok(())
     }
 
@@ -76,6 +152,18 @@ impl Storage for InMemoryStorage {
         Ok(txs.into_iter().map(|(_, tx)| tx).collect())
     }
 
+    fn get_transaction_by_hash(
+        &self,
+        tx_hash: &TxHash,
+    ) -> Result<Option<(Tx, BlockId, usize)>, StorageError> {
+        let Some((block_id, index)) = self.tx_hashes.read().unwrap().get(tx_hash).copied() else {
+            return Ok(None);
+        };
+        Ok(self
+            .get_transaction(block_id, index)?
+            .map(|tx| (tx, block_id, index)))
+    }
+
     fn save_deal(&self, deal: &Deal) -> Result<(), StorageError> {
         let mut deals = self.deals.write().unwrap();
         deals.insert(deal.id, deal.clone());
@@ -93,24 +181,162 @@ impl Storage for InMemoryStorage {
     }
 
     fn save_state_snapshot(&self, state: &State, block_id: BlockId) -> Result<(), StorageError> {
+        let manifest = state.snapshot_manifest(DEFAULT_SNAPSHOT_CHUNK_SIZE)?;
+        let chunks = state
+            .snapshot_chunks(DEFAULT_SNAPSHOT_CHUNK_SIZE)
+            .collect::<Result<Vec<_>, _>>()?;
+
         let mut snapshots = self.state_snapshots.write().unwrap();
-        snapshots.insert(block_id, state.clone());
+        snapshots.insert(block_id, (manifest, chunks));
         Ok(())
     }
 
     fn get_latest_state_snapshot(&self) -> Result<Option<(State, BlockId)>, StorageError> {
+        self.get_state_snapshot_at_or_before(BlockId::MAX)
+    }
+
+    fn get_state_snapshot_at_or_before(
+        &self,
+        block_id: BlockId,
+    ) -> Result<Option<(State, BlockId)>, StorageError> {
         let snapshots = self.state_snapshots.read().unwrap();
-        let mut latest_block_id = None;
-        let mut latest_state = None;
+        let Some((id, (manifest, chunks))) = snapshots
+            .iter()
+            .filter(|(id, _)| **id <= block_id)
+            .max_by_key(|(id, _)| **id)
+        else {
+            return Ok(None);
+        };
+
+        let mut reader = SnapshotReader::new(manifest)?;
+        for chunk in chunks {
+            reader.push_chunk(chunk)?;
+        }
+        Ok(Some((reader.finish()?, *id)))
+    }
+
+    fn prune_snapshots_before(&self, block_id: BlockId) -> Result<usize, StorageError> {
+        let mut snapshots = self.state_snapshots.write().unwrap();
+        let Some(latest) = snapshots.keys().max().copied() else {
+            return Ok(0);
+        };
+
+        let before = snapshots.len();
+        snapshots.retain(|id, _| *id >= block_id || *id == latest);
+        Ok(before - snapshots.len())
+    }
+
+    fn save_state_diff(&self, block_id: BlockId, diff: &StateDiff) -> Result<(), StorageError> {
+        let mut diffs = self.state_diffs.write().unwrap();
+        diffs.insert(block_id, diff.clone());
+        Ok(())
+    }
+
+    fn get_state_diff(&self, block_id: BlockId) -> Result<Option<StateDiff>, StorageError> {
+        let diffs = self.state_diffs.read().unwrap();
+        Ok(diffs.get(&block_id).cloned())
+    }
+
+    fn save_withdrawals_accumulator(
+        &self,
+        block_id: BlockId,
+        accumulator: &[u8; 32],
+    ) -> Result<(), StorageError> {
+        let mut accumulators = self.withdrawals_accumulators.write().unwrap();
+        accumulators.insert(block_id, *accumulator);
+        Ok(())
+    }
 
-        for (block_id, state) in snapshots.iter() {
-            if latest_block_id.is_none() || *block_id > latest_block_id.unwrap() {
-                latest_block_id = Some(*block_id);
-                latest_state = Some(state.clone());
+    fn get_withdrawals_accumulator(
+        &self,
+        block_id: BlockId,
+    ) -> Result<Option<[u8; 32]>, StorageError> {
+        let accumulators = self.withdrawals_accumulators.read().unwrap();
+        Ok(accumulators.get(&block_id).copied())
+    }
+
+    fn truncate_after(&self, block_id: BlockId) -> Result<usize, StorageError> {
+        let mut blocks = self.blocks.write().unwrap();
+        let before = blocks.len();
+        blocks.retain(|id, _| *id <= block_id);
+        let removed = before - blocks.len();
+
+        let mut deals = self.deals.write().unwrap();
+        for (_, diff) in self
+            .state_diffs
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(id, _)| **id > block_id)
+        {
+            for deal_id in diff
+                .deals
+                .iter()
+                .map(|deal| deal.id)
+                .chain(diff.removed_deals.iter().copied())
+            {
+                deals.remove(&deal_id);
             }
         }
+        drop(deals);
+
+        self.transactions
+            .write()
+            .unwrap()
+            .retain(|(id, _), _| *id <= block_id);
+        self.tx_hashes
+            .write()
+            .unwrap()
+            .retain(|_, (id, _)| *id <= block_id);
+        self.state_diffs
+            .write()
+            .unwrap()
+            .retain(|id, _| *id <= block_id);
+        self.withdrawals_accumulators
+            .write()
+            .unwrap()
+            .retain(|id, _| *id <= block_id);
+        self.state_snapshots
+            .write()
+            .unwrap()
+            .retain(|id, _| *id <= block_id);
+        self.block_builder_claims
+            .write()
+            .unwrap()
+            .retain(|id, _| *id <= block_id);
+
+        let mut latest = self.latest_block_id.write().unwrap();
+        if latest.is_some_and(|latest| latest > block_id) {
+            *latest = Some(block_id);
+        }
+        Ok(removed)
+    }
 
-        Ok(latest_block_id.and_then(|id| latest_state.map(|s| (s, id))))
+    fn save_checkpoint(&self, checkpoint: &Checkpoint) -> Result<(), StorageError> {
+        let mut latest = self.latest_checkpoint.write().unwrap();
+        *latest = Some(checkpoint.clone());
+        Ok(())
+    }
+
+    fn get_latest_checkpoint(&self) -> Result<Option<Checkpoint>, StorageError> {
+        let latest = self.latest_checkpoint.read().unwrap();
+        Ok(latest.clone())
+    }
+
+    fn claim_block_builder(
+        &self,
+        block_id: BlockId,
+        claim: &BuilderClaim,
+    ) -> Result<bool, StorageError> {
+        let mut claims = self.block_builder_claims.write().unwrap();
+        claims.retain(|id, _| *id >= block_id);
+        match claims.get(&block_id) {
+            Some(current) if !claim.replaces(current) => Ok(false),
+            _ => {
+                claims.insert(block_id, claim.clone());
+                Ok(true)
+            }
+        }
     }
 
     fn flush(&self) -> Result<(), StorageError> {
@@ -128,7 +354,7 @@ impl Default for InMemoryStorage {
 mod tests {
     use super::*;
     use zkclear_types::{
-        Address, Deal, DealStatus, DealVisibility, Deposit, Tx, TxKind, TxPayload,
+        Address, Deal, DealStatus, DealVisibility, Deposit, SignatureScheme, Tx, TxKind, TxPayload,
     };
 
     fn dummy_address(byte: u8) -> Address {
@@ -148,7 +374,10 @@ mod tests {
                 amount: 100,
                 chain_id: zkclear_types::chain_ids::ETHEREUM,
             }),
-            signature: [0u8; 65],
+            fee: 0,
+            domain: 0,
+            scheme: SignatureScheme::Secp256k1Recoverable,
+            signature: vec![0u8; 65],
         }
     }
 
@@ -162,9 +391,9 @@ mod tests {
             id,
             transactions,
             timestamp: 1000,
-            state_root: [0u8; 32],
-            withdrawals_root: [0u8; 32],
-            block_proof: Vec::new(),
+            state_root: [0xAA; 32],
+            withdrawals_root: [0xBB; 32],
+            block_proof: vec![1, 2, 3],
         }
     }
 
@@ -179,6 +408,27 @@ mod tests {
         assert_eq!(retrieved.id, 0);
         assert_eq!(retrieved.transactions.len(), 3);
         assert_eq!(retrieved.timestamp, 1000);
+        assert_eq!(retrieved.state_root, block.state_root);
+        assert_eq!(retrieved.withdrawals_root, block.withdrawals_root);
+        assert_eq!(retrieved.block_proof, block.block_proof);
+    }
+
+    #[test]
+    fn test_iter_blocks_in_order_and_stops_at_gap() {
+        let storage = InMemoryStorage::new();
+        for id in [3, 1, 2, 5] {
+            storage.save_block(&dummy_block(id, 1)).unwrap();
+        }
+
+        let ids: Vec<BlockId> = storage.iter_blocks(1, 3).map(|b| b.unwrap().id).collect();
+        assert_eq!(ids, vec![1, 2, 3]);
+
+        let results: Vec<_> = storage.iter_blocks(2, 5).collect();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[1].as_ref().unwrap().id, 3);
+        assert!(matches!(results[2], Err(StorageError::NotFound)));
+
+        assert_eq!(storage.iter_blocks(4, 3).count(), 0);
     }
 
     #[test]
@@ -210,6 +460,33 @@ mod tests {
         assert_eq!(txs.len(), 5);
     }
 
+    #[test]
+    fn test_get_transaction_by_hash() {
+        let storage = InMemoryStorage::new();
+        let block = dummy_block(4, 3);
+        storage.save_block(&block).unwrap();
+
+        for (index, tx) in block.transactions.iter().enumerate() {
+            let (found, block_id, found_index) = storage
+                .get_transaction_by_hash(&tx_hash(tx))
+                .unwrap()
+                .unwrap();
+            assert_eq!(found.id, tx.id);
+            assert_eq!(block_id, 4);
+            assert_eq!(found_index, index);
+        }
+        assert!(storage
+            .get_transaction_by_hash(&[0xEE; 32])
+            .unwrap()
+            .is_none());
+
+        storage.truncate_after(3).unwrap();
+        assert!(storage
+            .get_transaction_by_hash(&tx_hash(&block.transactions[0]))
+            .unwrap()
+            .is_none());
+    }
+
     #[test]
     fn test_save_and_get_deal() {
         let storage = InMemoryStorage::new();
@@ -228,9 +505,11 @@ mod tests {
             status: DealStatus::Pending,
             visibility: DealVisibility::Public,
             created_at: 1000,
+            updated_at: 1000,
             expires_at: None,
             external_ref: None,
             is_cross_chain: false,
+            escrows: Vec::new(),
         };
 
         storage.save_deal(&deal).unwrap();
@@ -255,6 +534,117 @@ mod tests {
         assert_eq!(retrieved_state.accounts.len(), 1);
     }
 
+    #[test]
+    fn test_prune_snapshots_keeps_latest() {
+        let storage = InMemoryStorage::new();
+        let mut state = State::new();
+        for block_id in [10, 20, 30] {
+            state.get_or_create_account_by_owner(dummy_address(block_id as u8));
+            storage.save_state_snapshot(&state, block_id).unwrap();
+        }
+
+        assert_eq!(storage.prune_snapshots_before(30).unwrap(), 2);
+        assert_eq!(storage.state_snapshots.read().unwrap().len(), 1);
+
+        // A cutoff past every snapshot still leaves the latest in place
+        assert_eq!(storage.prune_snapshots_before(100).unwrap(), 0);
+        let (retrieved_state, block_id) = storage.get_latest_state_snapshot().unwrap().unwrap();
+        assert_eq!(block_id, 30);
+        assert_eq!(retrieved_state.accounts.len(), 3);
+    }
+
+    #[test]
+    fn test_truncate_after_drops_later_blocks() {
+        let storage = InMemoryStorage::new();
+        let state = State::new();
+        for block_id in 0..5 {
+            storage.save_block(&dummy_block(block_id, 2)).unwrap();
+            storage
+                .save_state_diff(block_id, &StateDiff::default())
+                .unwrap();
+        }
+        storage.save_state_snapshot(&state, 1).unwrap();
+        storage.save_state_snapshot(&state, 3).unwrap();
+
+        assert_eq!(
+            storage
+                .get_state_snapshot_at_or_before(2)
+                .unwrap()
+                .unwrap()
+                .1,
+            1
+        );
+        let deal = |id| Deal {
+            id,
+            maker: [1u8; 20],
+            taker: None,
+            visibility: zkclear_types::DealVisibility::Public,
+            asset_base: 0,
+            asset_quote: 1,
+            chain_id_base: 1,
+            chain_id_quote: 1,
+            amount_base: 100,
+            amount_remaining: 100,
+            price_quote_per_base: 1,
+            status: zkclear_types::DealStatus::Pending,
+            created_at: 0,
+            updated_at: 0,
+            expires_at: None,
+            external_ref: None,
+            is_cross_chain: false,
+            escrows: Vec::new(),
+        };
+        storage.save_deal(&deal(1)).unwrap();
+        storage.save_deal(&deal(2)).unwrap();
+        storage
+            .save_state_diff(
+                4,
+                &StateDiff {
+                    deals: vec![deal(2)],
+                    ..StateDiff::default()
+                },
+            )
+            .unwrap();
+        let claim = BuilderClaim {
+            holder: "builder-a".to_string(),
+            claimed_at: 0,
+            expires_at: 100,
+        };
+        assert!(storage.claim_block_builder(4, &claim).unwrap());
+
+        assert_eq!(storage.truncate_after(2).unwrap(), 2);
+        assert_eq!(storage.get_latest_block_id().unwrap(), Some(2));
+        assert!(storage.get_deal(1).unwrap().is_some());
+        assert!(storage.get_deal(2).unwrap().is_none());
+        assert!(storage.block_builder_claims.read().unwrap().is_empty());
+        assert!(storage.get_block(3).unwrap().is_none());
+        assert!(storage.get_transaction(3, 0).unwrap().is_none());
+        assert!(storage.get_state_diff(4).unwrap().is_none());
+        assert_eq!(storage.get_latest_state_snapshot().unwrap().unwrap().1, 1);
+        assert!(storage.get_block(2).unwrap().is_some());
+        assert!(storage
+            .get_state_snapshot_at_or_before(0)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_save_and_get_checkpoint() {
+        let storage = InMemoryStorage::new();
+        assert!(storage.get_latest_checkpoint().unwrap().is_none());
+
+        let checkpoint = Checkpoint {
+            block_id: 10,
+            state_root: [1u8; 32],
+            withdrawals_root_accumulator: [2u8; 32],
+            timestamp: 1000,
+        };
+        storage.save_checkpoint(&checkpoint).unwrap();
+
+        let retrieved = storage.get_latest_checkpoint().unwrap().unwrap();
+        assert_eq!(retrieved, checkpoint);
+    }
+
     #[test]
     fn test_get_latest_block_id() {
         let storage = InMemoryStorage::new();
@@ -287,9 +677,11 @@ mod tests {
                 status: DealStatus::Pending,
                 visibility: DealVisibility::Public,
                 created_at: 1000,
+                updated_at: 1000,
                 expires_at: None,
                 external_ref: None,
                 is_cross_chain: false,
+                escrows: Vec::new(),
             };
             storage.save_deal(&deal).unwrap();
         }
@@ -297,4 +689,46 @@ mod tests {
         let deals = storage.get_all_deals().unwrap();
         assert_eq!(deals.len(), 5);
     }
+
+    fn claim(holder: &str, claimed_at: u64) -> BuilderClaim {
+        BuilderClaim {
+            holder: holder.to_string(),
+            claimed_at,
+            expires_at: claimed_at + 10,
+        }
+    }
+
+    #[test]
+    fn test_claim_block_builder() {
+        let storage = InMemoryStorage::new();
+
+        assert!(storage.claim_block_builder(1, &claim("a", 0)).unwrap());
+        assert!(storage.claim_block_builder(1, &claim("a", 5)).unwrap());
+        assert!(!storage.claim_block_builder(1, &claim("b", 5)).unwrap());
+        // "a" renewed at 5, so its claim runs until 15
+        assert!(!storage.claim_block_builder(1, &claim("b", 14)).unwrap());
+        assert!(storage.claim_block_builder(1, &claim("b", 15)).unwrap());
+        assert!(!storage.claim_block_builder(1, &claim("a", 16)).unwrap());
+
+        assert!(storage.claim_block_builder(2, &claim("a", 16)).unwrap());
+        assert_eq!(storage.block_builder_claims.read().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_migrate_rejects_newer_version() {
+        let storage = InMemoryStorage::new();
+        assert_eq!(storage.version(), STORAGE_VERSION);
+        storage.migrate().unwrap();
+
+        storage.set_version(STORAGE_VERSION + 1).unwrap();
+        assert!(matches!(
+            storage.migrate(),
+            Err(StorageError::IncompatibleVersion { found, supported })
+                if found == STORAGE_VERSION + 1 && supported == STORAGE_VERSION
+        ));
+
+        storage.set_version(0).unwrap();
+        storage.migrate().unwrap();
+        assert_eq!(storage.version(), STORAGE_VERSION);
+    }
 }
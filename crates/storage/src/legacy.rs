@@ -0,0 +1,469 @@
+//! Encodings written by older storage versions, for `Storage::migrate` to
+//! re-encode in the current layout. Each `upgrade_*` function takes the
+//! stored bytes of a record from a version `found` store and returns the
+//! same record encoded by this binary.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use zkclear_state::{State, StateDiff};
+use zkclear_types::{
+    Account, AccountId, Address, Asset, AssetId, Block, BlockId, ChainId, Deal, DealId, DealStatus,
+    DealVisibility, Escrow, Fill, SignatureScheme, Tx, TxKind, TxPayload,
+};
+
+use crate::storage_trait::StorageError;
+#[cfg(test)]
+use crate::storage_trait::STORAGE_VERSION;
+
+/// `DealStatus` as stored by version 1, before `Settling`
+#[derive(Serialize, Deserialize)]
+enum DealStatusV1 {
+    Pending,
+    Settled,
+    Cancelled,
+    Expired,
+}
+
+impl From<DealStatusV1> for DealStatus {
+    fn from(status: DealStatusV1) -> Self {
+        match status {
+            DealStatusV1::Pending => DealStatus::Pending,
+            DealStatusV1::Settled => DealStatus::Settled,
+            DealStatusV1::Cancelled => DealStatus::Cancelled,
+            DealStatusV1::Expired => DealStatus::Expired,
+        }
+    }
+}
+
+/// Deal as stored by version 1, before escrow
+#[derive(Serialize, Deserialize)]
+struct DealV1 {
+    id: DealId,
+    maker: Address,
+    taker: Option<Address>,
+    visibility: DealVisibility,
+    asset_base: AssetId,
+    asset_quote: AssetId,
+    chain_id_base: ChainId,
+    chain_id_quote: ChainId,
+    amount_base: u128,
+    amount_remaining: u128,
+    price_quote_per_base: u128,
+    status: DealStatusV1,
+    created_at: u64,
+    expires_at: Option<u64>,
+    external_ref: Option<String>,
+    is_cross_chain: bool,
+}
+
+impl From<DealV1> for Deal {
+    fn from(deal: DealV1) -> Self {
+        DealV3 {
+            id: deal.id,
+            maker: deal.maker,
+            taker: deal.taker,
+            visibility: deal.visibility,
+            asset_base: deal.asset_base,
+            asset_quote: deal.asset_quote,
+            chain_id_base: deal.chain_id_base,
+            chain_id_quote: deal.chain_id_quote,
+            amount_base: deal.amount_base,
+            amount_remaining: deal.amount_remaining,
+            price_quote_per_base: deal.price_quote_per_base,
+            status: deal.status.into(),
+            created_at: deal.created_at,
+            expires_at: deal.expires_at,
+            external_ref: deal.external_ref,
+            is_cross_chain: deal.is_cross_chain,
+            escrow: None,
+        }
+        .into()
+    }
+}
+
+/// Deal as stored by versions 2 and 3, before `updated_at` and with at
+/// most one escrow
+#[derive(Serialize, Deserialize)]
+struct DealV3 {
+    id: DealId,
+    maker: Address,
+    taker: Option<Address>,
+    visibility: DealVisibility,
+    asset_base: AssetId,
+    asset_quote: AssetId,
+    chain_id_base: ChainId,
+    chain_id_quote: ChainId,
+    amount_base: u128,
+    amount_remaining: u128,
+    price_quote_per_base: u128,
+    status: DealStatus,
+    created_at: u64,
+    expires_at: Option<u64>,
+    external_ref: Option<String>,
+    is_cross_chain: bool,
+    escrow: Option<Escrow>,
+}
+
+impl From<DealV3> for Deal {
+    fn from(deal: DealV3) -> Self {
+        Deal {
+            id: deal.id,
+            maker: deal.maker,
+            taker: deal.taker,
+            visibility: deal.visibility,
+            asset_base: deal.asset_base,
+            asset_quote: deal.asset_quote,
+            chain_id_base: deal.chain_id_base,
+            chain_id_quote: deal.chain_id_quote,
+            amount_base: deal.amount_base,
+            amount_remaining: deal.amount_remaining,
+            price_quote_per_base: deal.price_quote_per_base,
+            status: deal.status,
+            created_at: deal.created_at,
+            // When the deal last changed was not recorded
+            updated_at: deal.created_at,
+            expires_at: deal.expires_at,
+            external_ref: deal.external_ref,
+            is_cross_chain: deal.is_cross_chain,
+            escrows: deal.escrow.into_iter().collect(),
+        }
+    }
+}
+
+/// Tx as stored by version 1, before `scheme`, when every signature was
+/// secp256k1. The 65 signature bytes were length-prefixed as a `Vec` is.
+#[derive(Serialize, Deserialize)]
+struct TxV1 {
+    id: u64,
+    #[serde(with = "serde_bytes")]
+    from: Address,
+    nonce: u64,
+    kind: TxKind,
+    payload: TxPayload,
+    fee: u128,
+    domain: u64,
+    #[serde(with = "serde_bytes")]
+    signature: Vec<u8>,
+}
+
+impl From<TxV1> for Tx {
+    fn from(tx: TxV1) -> Self {
+        Tx {
+            id: tx.id,
+            from: tx.from,
+            nonce: tx.nonce,
+            kind: tx.kind,
+            payload: tx.payload,
+            fee: tx.fee,
+            domain: tx.domain,
+            scheme: SignatureScheme::Secp256k1Recoverable,
+            signature: tx.signature,
+        }
+    }
+}
+
+/// Block as stored by version 1, holding `TxV1`s
+#[derive(Serialize, Deserialize)]
+struct BlockV1 {
+    id: BlockId,
+    transactions: Vec<TxV1>,
+    timestamp: u64,
+    #[serde(with = "serde_bytes")]
+    state_root: [u8; 32],
+    #[serde(with = "serde_bytes")]
+    withdrawals_root: [u8; 32],
+    #[serde(with = "serde_bytes")]
+    block_proof: Vec<u8>,
+}
+
+impl From<BlockV1> for Block {
+    fn from(block: BlockV1) -> Self {
+        Block {
+            id: block.id,
+            transactions: block.transactions.into_iter().map(Into::into).collect(),
+            timestamp: block.timestamp,
+            state_root: block.state_root,
+            withdrawals_root: block.withdrawals_root,
+            block_proof: block.block_proof,
+        }
+    }
+}
+
+/// `StateDiff`, generic over the deal layout
+#[derive(Serialize, Deserialize)]
+struct DiffLayout<D> {
+    accounts: Vec<Account>,
+    deals: Vec<D>,
+    removed_accounts: Vec<AccountId>,
+    removed_deals: Vec<DealId>,
+}
+
+impl<D: Into<Deal>> DiffLayout<D> {
+    fn upgrade(self) -> StateDiff {
+        StateDiff {
+            accounts: self.accounts,
+            deals: self.deals.into_iter().map(Into::into).collect(),
+            removed_accounts: self.removed_accounts,
+            removed_deals: self.removed_deals,
+        }
+    }
+}
+
+/// A snapshot chunk, generic over the deal layout
+#[derive(Serialize, Deserialize)]
+enum ChunkLayout<D> {
+    Accounts(Vec<Account>),
+    Deals(Vec<D>),
+}
+
+impl<D: Into<Deal>> ChunkLayout<D> {
+    fn upgrade(self) -> ChunkLayout<Deal> {
+        match self {
+            ChunkLayout::Accounts(accounts) => ChunkLayout::Accounts(accounts),
+            ChunkLayout::Deals(deals) => {
+                ChunkLayout::Deals(deals.into_iter().map(Into::into).collect())
+            }
+        }
+    }
+}
+
+/// The serialized fields of `State`, generic over the deal layout, as
+/// snapshots were saved whole before version 3
+#[derive(Serialize, Deserialize)]
+struct StateLayout<D> {
+    accounts: HashMap<AccountId, Account>,
+    deals: HashMap<DealId, D>,
+    account_index: HashMap<Address, AccountId>,
+    next_account_id: AccountId,
+    fills: HashMap<DealId, Vec<Fill>>,
+    fee_collector: Address,
+    processed_deposits: HashSet<[u8; 32]>,
+    processed_deposits_by_time: BTreeSet<(u64, [u8; 32])>,
+    assets: HashMap<(AssetId, ChainId), Asset>,
+}
+
+impl<D: Into<Deal>> StateLayout<D> {
+    fn upgrade(self) -> State {
+        let mut state = State::new();
+        state.accounts = self.accounts;
+        state.deals = self
+            .deals
+            .into_iter()
+            .map(|(id, deal)| (id, deal.into()))
+            .collect();
+        state.account_index = self.account_index;
+        state.next_account_id = self.next_account_id;
+        state.fills = self.fills;
+        state.fee_collector = self.fee_collector;
+        state.processed_deposits = self.processed_deposits;
+        state.processed_deposits_by_time = self.processed_deposits_by_time;
+        state.assets = self.assets;
+        state
+    }
+}
+
+fn decode<'a, T: Deserialize<'a>>(bytes: &'a [u8]) -> Result<T, StorageError> {
+    bincode::deserialize(bytes).map_err(|_| StorageError::DeserializationFailed)
+}
+
+fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, StorageError> {
+    bincode::serialize(value).map_err(|_| StorageError::SerializationFailed)
+}
+
+pub(crate) fn upgrade_tx(found: u32, bytes: &[u8]) -> Result<Vec<u8>, StorageError> {
+    let tx: Tx = match found {
+        ..=1 => decode::<TxV1>(bytes)?.into(),
+        _ => return Ok(bytes.to_vec()),
+    };
+    encode(&tx)
+}
+
+pub(crate) fn upgrade_block(found: u32, bytes: &[u8]) -> Result<Vec<u8>, StorageError> {
+    let block: Block = match found {
+        ..=1 => decode::<BlockV1>(bytes)?.into(),
+        _ => return Ok(bytes.to_vec()),
+    };
+    encode(&block)
+}
+
+pub(crate) fn upgrade_deal(found: u32, bytes: &[u8]) -> Result<Vec<u8>, StorageError> {
+    let deal: Deal = match found {
+        ..=1 => decode::<DealV1>(bytes)?.into(),
+        2..=3 => decode::<DealV3>(bytes)?.into(),
+        _ => return Ok(bytes.to_vec()),
+    };
+    encode(&deal)
+}
+
+pub(crate) fn upgrade_state_diff(found: u32, bytes: &[u8]) -> Result<Vec<u8>, StorageError> {
+    let diff = match found {
+        ..=1 => decode::<DiffLayout<DealV1>>(bytes)?.upgrade(),
+        2..=3 => decode::<DiffLayout<DealV3>>(bytes)?.upgrade(),
+        _ => return Ok(bytes.to_vec()),
+    };
+    encode(&diff)
+}
+
+pub(crate) fn upgrade_snapshot_chunk(found: u32, bytes: &[u8]) -> Result<Vec<u8>, StorageError> {
+    let chunk = match found {
+        ..=3 => decode::<ChunkLayout<DealV3>>(bytes)?.upgrade(),
+        _ => return Ok(bytes.to_vec()),
+    };
+    encode(&chunk)
+}
+
+/// Re-encode a snapshot saved whole, as versions before 3 did. The result
+/// is still a whole `State`, for `Storage::rechunk_snapshots` to split.
+pub(crate) fn upgrade_state(found: u32, bytes: &[u8]) -> Result<Vec<u8>, StorageError> {
+    let state = match found {
+        ..=1 => decode::<StateLayout<DealV1>>(bytes)?.upgrade(),
+        2 => decode::<StateLayout<DealV3>>(bytes)?.upgrade(),
+        _ => return Ok(bytes.to_vec()),
+    };
+    encode(&state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zkclear_state::SnapshotReader;
+
+    fn deal_v3(id: DealId) -> DealV3 {
+        DealV3 {
+            id,
+            maker: [1u8; 20],
+            taker: None,
+            visibility: DealVisibility::Public,
+            asset_base: 0,
+            asset_quote: 1,
+            chain_id_base: 1,
+            chain_id_quote: 1,
+            amount_base: 100,
+            amount_remaining: 60,
+            price_quote_per_base: 2,
+            status: DealStatus::Pending,
+            created_at: 1_000,
+            expires_at: Some(5_000),
+            external_ref: Some("order-1".to_string()),
+            is_cross_chain: false,
+            escrow: None,
+        }
+    }
+
+    #[test]
+    fn test_upgrade_deal_fills_updated_at() {
+        let bytes = encode(&deal_v3(7)).unwrap();
+        let deal: Deal = decode(&upgrade_deal(3, &bytes).unwrap()).unwrap();
+        assert_eq!(deal.id, 7);
+        assert_eq!(deal.updated_at, deal.created_at);
+        assert_eq!(deal.amount_remaining, 60);
+        assert_eq!(deal.expires_at, Some(5_000));
+        assert_eq!(deal.external_ref.as_deref(), Some("order-1"));
+
+        // Records already in the current layout are left alone
+        assert_eq!(upgrade_deal(STORAGE_VERSION, &bytes).unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_upgrade_deal_from_before_escrow() {
+        let deal = DealV1 {
+            id: 4,
+            maker: [1u8; 20],
+            taker: None,
+            visibility: DealVisibility::Public,
+            asset_base: 0,
+            asset_quote: 1,
+            chain_id_base: 1,
+            chain_id_quote: 1,
+            amount_base: 100,
+            amount_remaining: 0,
+            price_quote_per_base: 2,
+            status: DealStatusV1::Settled,
+            created_at: 1_000,
+            expires_at: None,
+            external_ref: None,
+            is_cross_chain: true,
+        };
+        let bytes = encode(&deal).unwrap();
+        let deal: Deal = decode(&upgrade_deal(1, &bytes).unwrap()).unwrap();
+        // `Settled` no longer has the same discriminant
+        assert_eq!(deal.status, DealStatus::Settled);
+        assert!(deal.escrows.is_empty());
+        assert_eq!(deal.updated_at, 1_000);
+        assert!(deal.is_cross_chain);
+    }
+
+    #[test]
+    fn test_upgrade_tx_and_block_from_before_scheme() {
+        let tx = TxV1 {
+            id: 3,
+            from: [2u8; 20],
+            nonce: 5,
+            kind: TxKind::CancelDeal,
+            payload: TxPayload::CancelDeal(zkclear_types::CancelDeal { deal_id: 9 }),
+            fee: 10,
+            domain: 1,
+            signature: vec![7u8; 65],
+        };
+        let upgraded: Tx = decode(&upgrade_tx(1, &encode(&tx).unwrap()).unwrap()).unwrap();
+        assert_eq!(upgraded.scheme, SignatureScheme::Secp256k1Recoverable);
+        assert_eq!(upgraded.signature, vec![7u8; 65]);
+        assert_eq!(
+            (upgraded.from, upgraded.nonce, upgraded.fee),
+            ([2u8; 20], 5, 10)
+        );
+
+        let block = BlockV1 {
+            id: 4,
+            transactions: vec![tx],
+            timestamp: 1_000,
+            state_root: [1u8; 32],
+            withdrawals_root: [2u8; 32],
+            block_proof: vec![3u8; 4],
+        };
+        let upgraded: Block = decode(&upgrade_block(1, &encode(&block).unwrap()).unwrap()).unwrap();
+        assert_eq!(upgraded.id, 4);
+        assert_eq!(upgraded.transactions.len(), 1);
+        assert_eq!(upgraded.transactions[0].signature, vec![7u8; 65]);
+        assert_eq!(upgraded.state_root, [1u8; 32]);
+        assert_eq!(upgraded.block_proof, vec![3u8; 4]);
+    }
+
+    #[test]
+    fn test_upgrade_state_diff_and_snapshot() {
+        let diff = DiffLayout {
+            accounts: Vec::new(),
+            deals: vec![deal_v3(1)],
+            removed_accounts: vec![3],
+            removed_deals: Vec::new(),
+        };
+        let diff: StateDiff =
+            decode(&upgrade_state_diff(2, &encode(&diff).unwrap()).unwrap()).unwrap();
+        assert_eq!(diff.deals[0].updated_at, 1_000);
+        assert_eq!(diff.removed_accounts, vec![3]);
+
+        let mut state = State::new();
+        state.deals.insert(1, deal_v3(1).into());
+        let chunk = encode(&ChunkLayout::Deals(vec![deal_v3(1)])).unwrap();
+        let mut reader = SnapshotReader::new(&state.snapshot_manifest(10).unwrap()).unwrap();
+        reader
+            .push_chunk(&upgrade_snapshot_chunk(3, &chunk).unwrap())
+            .unwrap();
+        assert_eq!(reader.finish().unwrap().deals, state.deals);
+
+        let whole = StateLayout {
+            accounts: HashMap::new(),
+            deals: HashMap::from([(1, deal_v3(1))]),
+            account_index: HashMap::new(),
+            next_account_id: 0,
+            fills: HashMap::new(),
+            fee_collector: [0u8; 20],
+            processed_deposits: HashSet::new(),
+            processed_deposits_by_time: BTreeSet::new(),
+            assets: HashMap::new(),
+        };
+        let upgraded: State = decode(&upgrade_state(2, &encode(&whole).unwrap()).unwrap()).unwrap();
+        assert_eq!(upgraded.deals, state.deals);
+    }
+}
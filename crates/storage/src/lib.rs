@@ -1,11 +1,16 @@
 mod in_memory;
+// Only the RocksDB backend persists records across versions
+#[cfg_attr(not(feature = "rocksdb"), allow(dead_code))]
+mod legacy;
 mod storage_trait;
 
 #[cfg(feature = "rocksdb")]
 mod rocksdb_impl;
 
 pub use in_memory::InMemoryStorage;
-pub use storage_trait::{Storage, StorageError};
+pub use storage_trait::{
+    tx_hash, BlockIter, BuilderClaim, Storage, StorageError, TxHash, STORAGE_VERSION,
+};
 
 #[cfg(feature = "rocksdb")]
 pub use rocksdb_impl::RocksDBStorage;
@@ -1,28 +1,74 @@
-use crate::storage_trait::{Storage, StorageError, TxId};
+use crate::legacy;
+use crate::storage_trait::{
+    tx_hash, BlockIter, BuilderClaim, Storage, StorageError, TxHash, TxId, STORAGE_VERSION,
+};
 use bincode;
 #[cfg(feature = "rocksdb")]
-use rocksdb::{ColumnFamilyDescriptor, Options, DB};
+use rocksdb::{ColumnFamilyDescriptor, MergeOperands, Options, WriteBatch, DB};
+#[cfg(feature = "rocksdb")]
+use std::collections::VecDeque;
 #[cfg(feature = "rocksdb")]
 use std::path::Path;
 #[cfg(feature = "rocksdb")]
+use std::sync::atomic::{AtomicU32, Ordering};
+#[cfg(feature = "rocksdb")]
 use std::sync::Arc;
-use zkclear_state::State;
-use zkclear_types::{Block, BlockId, Deal, DealId, Tx};
+use zkclear_state::{SnapshotReader, State, StateDiff, DEFAULT_SNAPSHOT_CHUNK_SIZE};
+use zkclear_types::{Block, BlockId, Checkpoint, Deal, DealId, Tx};
 
 #[cfg(feature = "rocksdb")]
 const CF_BLOCKS: &str = "blocks";
 #[cfg(feature = "rocksdb")]
 const CF_TRANSACTIONS: &str = "transactions";
+/// `tx_hash -> (block_id, index)` index over `CF_TRANSACTIONS`
+#[cfg(feature = "rocksdb")]
+const CF_TX_HASHES: &str = "tx_hashes";
 #[cfg(feature = "rocksdb")]
 const CF_DEALS: &str = "deals";
+/// Snapshot manifests by block id; their chunks are in `CF_SNAPSHOT_CHUNKS`
 #[cfg(feature = "rocksdb")]
 const CF_STATE_SNAPSHOTS: &str = "state_snapshots";
+/// Snapshot chunks keyed by `(block_id, chunk index)`
+#[cfg(feature = "rocksdb")]
+const CF_SNAPSHOT_CHUNKS: &str = "snapshot_chunks";
+#[cfg(feature = "rocksdb")]
+const CF_STATE_DIFFS: &str = "state_diffs";
+/// Withdrawals root accumulator after each block, by block id
+#[cfg(feature = "rocksdb")]
+const CF_WITHDRAWALS_ACCUMULATORS: &str = "withdrawals_accumulators";
 #[cfg(feature = "rocksdb")]
 const CF_METADATA: &str = "metadata";
+/// `BuilderClaim`s by big-endian block id, written through
+/// `merge_builder_claims` so competing claims resolve atomically
+#[cfg(feature = "rocksdb")]
+const CF_BUILDER_CLAIMS: &str = "builder_claims";
+
+#[cfg(feature = "rocksdb")]
+const COLUMN_FAMILIES: [&str; 10] = [
+    CF_BLOCKS,
+    CF_TRANSACTIONS,
+    CF_TX_HASHES,
+    CF_DEALS,
+    CF_STATE_SNAPSHOTS,
+    CF_SNAPSHOT_CHUNKS,
+    CF_STATE_DIFFS,
+    CF_WITHDRAWALS_ACCUMULATORS,
+    CF_METADATA,
+    CF_BUILDER_CLAIMS,
+];
+
+/// Blocks fetched per `multi_get_cf` call by `iter_blocks`
+#[cfg(feature = "rocksdb")]
+const BLOCK_ITER_BATCH_SIZE: u64 = 64;
 
 #[cfg(feature = "rocksdb")]
 pub struct RocksDBStorage {
     db: Arc<DB>,
+    /// On-disk schema version, kept in `CF_METADATA` under `storage_version`
+    version: AtomicU32,
+    /// Opened with `open_as_secondary`: reads only, and sees the primary's
+    /// writes after `catch_up_with_primary`
+    secondary: bool,
 }
 
 #[cfg(feature = "rocksdb")]
@@ -32,18 +78,86 @@ impl RocksDBStorage {
         opts.create_if_missing(true);
         opts.create_missing_column_families(true);
 
-        let cfs = vec![
-            ColumnFamilyDescriptor::new(CF_BLOCKS, Options::default()),
-            ColumnFamilyDescriptor::new(CF_TRANSACTIONS, Options::default()),
-            ColumnFamilyDescriptor::new(CF_DEALS, Options::default()),
-            ColumnFamilyDescriptor::new(CF_STATE_SNAPSHOTS, Options::default()),
-            ColumnFamilyDescriptor::new(CF_METADATA, Options::default()),
-        ];
+        let cfs = COLUMN_FAMILIES.iter().map(|name| {
+            let mut cf_opts = Options::default();
+            if *name == CF_BUILDER_CLAIMS {
+                cf_opts.set_merge_operator(
+                    "builder_claims",
+                    merge_builder_claims,
+                    refuse_partial_merge,
+                );
+            }
+            ColumnFamilyDescriptor::new(*name, cf_opts)
+        });
 
         let db = DB::open_cf_descriptors(&opts, path, cfs)
             .map_err(|e| StorageError::DatabaseError(e.to_string()))?;
 
-        Ok(Self { db: Arc::new(db) })
+        let mut storage = Self {
+            db: Arc::new(db),
+            version: AtomicU32::new(STORAGE_VERSION),
+            secondary: false,
+        };
+        match storage.stored_version()? {
+            Some(version) => *storage.version.get_mut() = version,
+            // First open of this directory
+            None => storage.set_version(STORAGE_VERSION)?,
+        }
+        storage.migrate()?;
+
+        Ok(storage)
+    }
+
+    /// Open the store a primary at `primary_path` is writing, for reads
+    /// only, keeping the secondary's own logs in `secondary_path`. Writes
+    /// made by the primary after opening show up once
+    /// `catch_up_with_primary` has run. A secondary can't migrate, so the
+    /// store must already be at `STORAGE_VERSION`.
+    pub fn open_as_secondary<P: AsRef<Path>>(
+        primary_path: P,
+        secondary_path: P,
+    ) -> Result<Self, StorageError> {
+        let mut opts = Options::default();
+        // Required for secondaries, which must keep every file open
+        opts.set_max_open_files(-1);
+
+        let db = DB::open_cf_as_secondary(&opts, primary_path, secondary_path, COLUMN_FAMILIES)
+            .map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+
+        let storage = Self {
+            db: Arc::new(db),
+            version: AtomicU32::new(STORAGE_VERSION),
+            secondary: true,
+        };
+        match storage.stored_version()? {
+            Some(STORAGE_VERSION) => Ok(storage),
+            Some(found) => Err(StorageError::IncompatibleVersion {
+                found,
+                supported: STORAGE_VERSION,
+            }),
+            None => Err(StorageError::NotFound),
+        }
+    }
+
+    fn stored_version(&self) -> Result<Option<u32>, StorageError> {
+        let metadata_cf = self
+            .db
+            .cf_handle(CF_METADATA)
+            .ok_or_else(|| StorageError::DatabaseError("CF_METADATA not found".to_string()))?;
+
+        match self
+            .db
+            .get_cf(metadata_cf, b"storage_version")
+            .map_err(|e| StorageError::DatabaseError(e.to_string()))?
+        {
+            Some(bytes) => {
+                let bytes: [u8; 4] = bytes[..]
+                    .try_into()
+                    .map_err(|_| StorageError::DeserializationFailed)?;
+                Ok(Some(u32::from_le_bytes(bytes)))
+            }
+            None => Ok(None),
+        }
     }
 
     fn encode_block_id(block_id: BlockId) -> Vec<u8> {
@@ -59,16 +173,337 @@ impl RocksDBStorage {
         Ok(u64::from_le_bytes(arr))
     }
 
+    fn latest_state_snapshot_block_id(&self) -> Result<Option<BlockId>, StorageError> {
+        let metadata_cf = self
+            .db
+            .cf_handle(CF_METADATA)
+            .ok_or_else(|| StorageError::DatabaseError("CF_METADATA not found".to_string()))?;
+
+        match self
+            .db
+            .get_cf(metadata_cf, b"latest_state_snapshot_block_id")
+            .map_err(|e| StorageError::DatabaseError(e.to_string()))?
+        {
+            Some(bytes) => Ok(Some(Self::decode_block_id(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Keys in `cf_name` whose leading block id is above `block_id`. Keys
+    /// are little-endian, so the whole column family is scanned.
+    fn keys_after(&self, cf_name: &str, block_id: BlockId) -> Result<Vec<Vec<u8>>, StorageError> {
+        let cf = self
+            .db
+            .cf_handle(cf_name)
+            .ok_or_else(|| StorageError::DatabaseError(format!("{} not found", cf_name)))?;
+
+        let mut keys = Vec::new();
+        let mut iter = self.db.raw_iterator_cf(cf);
+        iter.seek_to_first();
+        while let Some(key) = iter.key() {
+            let key_block_id = Self::decode_block_id(key.get(..8).unwrap_or(key))?;
+            if key_block_id > block_id {
+                keys.push(key.to_vec());
+            }
+            iter.next();
+        }
+        iter.status()
+            .map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+
+        Ok(keys)
+    }
+
+    /// Block ids of all stored state snapshots, unordered
+    fn snapshot_block_ids(&self) -> Result<Vec<BlockId>, StorageError> {
+        let cf = self.db.cf_handle(CF_STATE_SNAPSHOTS).ok_or_else(|| {
+            StorageError::DatabaseError("CF_STATE_SNAPSHOTS not found".to_string())
+        })?;
+
+        let mut ids = Vec::new();
+        let mut iter = self.db.raw_iterator_cf(cf);
+        iter.seek_to_first();
+        while let Some(key) = iter.key() {
+            ids.push(Self::decode_block_id(key)?);
+            iter.next();
+        }
+        iter.status()
+            .map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+
+        Ok(ids)
+    }
+
+    /// Block id prefix followed by the big-endian chunk index, so a
+    /// snapshot's chunks are contiguous and in order
+    fn encode_snapshot_chunk_key(block_id: BlockId, index: u32) -> Vec<u8> {
+        let mut key = Vec::with_capacity(12);
+        key.extend_from_slice(&block_id.to_le_bytes());
+        key.extend_from_slice(&index.to_be_bytes());
+        key
+    }
+
+    /// Write `state` as the snapshot of `block_id`, one chunk at a time.
+    /// The manifest goes last, so an interrupted write leaves no snapshot
+    /// behind. Does not move the latest snapshot pointer.
+    fn write_snapshot(&self, state: &State, block_id: BlockId) -> Result<(), StorageError> {
+        let cf = self.db.cf_handle(CF_STATE_SNAPSHOTS).ok_or_else(|| {
+            StorageError::DatabaseError("CF_STATE_SNAPSHOTS not found".to_string())
+        })?;
+        let chunks_cf = self.db.cf_handle(CF_SNAPSHOT_CHUNKS).ok_or_else(|| {
+            StorageError::DatabaseError("CF_SNAPSHOT_CHUNKS not found".to_string())
+        })?;
+
+        let manifest = state.snapshot_manifest(DEFAULT_SNAPSHOT_CHUNK_SIZE)?;
+        for (index, chunk) in state
+            .snapshot_chunks(DEFAULT_SNAPSHOT_CHUNK_SIZE)
+            .enumerate()
+        {
+            let key = Self::encode_snapshot_chunk_key(block_id, index as u32);
+            self.db
+                .put_cf(chunks_cf, key, chunk?)
+                .map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+        }
+
+        self.db
+            .put_cf(cf, Self::encode_block_id(block_id), manifest)
+            .map_err(|e| StorageError::DatabaseError(e.to_string()))
+    }
+
+    /// Load the snapshot of `block_id` chunk by chunk
+    fn read_snapshot(&self, block_id: BlockId) -> Result<Option<State>, StorageError> {
+        let cf = self.db.cf_handle(CF_STATE_SNAPSHOTS).ok_or_else(|| {
+            StorageError::DatabaseError("CF_STATE_SNAPSHOTS not found".to_string())
+        })?;
+        let chunks_cf = self.db.cf_handle(CF_SNAPSHOT_CHUNKS).ok_or_else(|| {
+            StorageError::DatabaseError("CF_SNAPSHOT_CHUNKS not found".to_string())
+        })?;
+
+        let Some(manifest) = self
+            .db
+            .get_cf(cf, Self::encode_block_id(block_id))
+            .map_err(|e| StorageError::DatabaseError(e.to_string()))?
+        else {
+            return Ok(None);
+        };
+
+        let mut reader = SnapshotReader::new(&manifest)?;
+        for index in 0..reader.chunk_count() {
+            let chunk = self
+                .db
+                .get_cf(chunks_cf, Self::encode_snapshot_chunk_key(block_id, index))
+                .map_err(|e| StorageError::DatabaseError(e.to_string()))?
+                .ok_or(StorageError::NotFound)?;
+            reader.push_chunk(&chunk)?;
+        }
+        Ok(Some(reader.finish()?))
+    }
+
+    /// Delete every chunk of the snapshot of `block_id`
+    fn delete_snapshot_chunks(&self, block_id: BlockId) -> Result<(), StorageError> {
+        let chunks_cf = self.db.cf_handle(CF_SNAPSHOT_CHUNKS).ok_or_else(|| {
+            StorageError::DatabaseError("CF_SNAPSHOT_CHUNKS not found".to_string())
+        })?;
+
+        // One byte longer than any chunk key with this prefix
+        let mut end = Self::encode_block_id(block_id);
+        end.extend_from_slice(&[0xFF; 5]);
+        self.db
+            .delete_range_cf(chunks_cf, Self::encode_snapshot_chunk_key(block_id, 0), end)
+            .map_err(|e| StorageError::DatabaseError(e.to_string()))
+    }
+
     fn encode_tx_id(tx_id: TxId) -> Vec<u8> {
         let mut key = Vec::with_capacity(16);
         key.extend_from_slice(&tx_id.0.to_le_bytes());
         key.extend_from_slice(&tx_id.1.to_le_bytes());
         key
     }
+
+    fn decode_tx_id(bytes: &[u8]) -> Result<TxId, StorageError> {
+        if bytes.len() != 16 {
+            return Err(StorageError::DeserializationFailed);
+        }
+        let block_id = Self::decode_block_id(&bytes[0..8])?;
+        let index = u64::from_le_bytes(
+            bytes[8..16]
+                .try_into()
+                .map_err(|_| StorageError::DeserializationFailed)?,
+        );
+        Ok((block_id, index as usize))
+    }
+
+    /// Re-encode every value in `cf_name` with `upgrade`, in one batch
+    /// that also marks the column family as upgraded to `STORAGE_VERSION`,
+    /// so a migration interrupted partway never upgrades a value twice
+    fn upgrade_cf(
+        &self,
+        cf_name: &str,
+        upgrade: impl Fn(&[u8]) -> Result<Vec<u8>, StorageError>,
+    ) -> Result<(), StorageError> {
+        let cf = self
+            .db
+            .cf_handle(cf_name)
+            .ok_or_else(|| StorageError::DatabaseError(format!("{} not found", cf_name)))?;
+        let metadata_cf = self
+            .db
+            .cf_handle(CF_METADATA)
+            .ok_or_else(|| StorageError::DatabaseError("CF_METADATA not found".to_string()))?;
+
+        let marker = format!("upgraded_{}", cf_name);
+        let upgraded_to = self
+            .db
+            .get_cf(metadata_cf, &marker)
+            .map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+        if upgraded_to.as_deref() == Some(&STORAGE_VERSION.to_le_bytes()[..]) {
+            return Ok(());
+        }
+
+        let mut batch = WriteBatch::default();
+        let mut iter = self.db.raw_iterator_cf(cf);
+        iter.seek_to_first();
+        while let (Some(key), Some(value)) = (iter.key(), iter.value()) {
+            batch.put_cf(cf, key, upgrade(value)?);
+            iter.next();
+        }
+        iter.status()
+            .map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+        batch.put_cf(metadata_cf, &marker, STORAGE_VERSION.to_le_bytes());
+
+        self.db
+            .write(batch)
+            .map_err(|e| StorageError::DatabaseError(e.to_string()))
+    }
+
+    /// Drop the hash index entries of the transactions stored under `keys`
+    fn unindex_transactions(&self, keys: &[Vec<u8>]) -> Result<(), StorageError> {
+        let tx_cf = self
+            .db
+            .cf_handle(CF_TRANSACTIONS)
+            .ok_or_else(|| StorageError::DatabaseError("CF_TRANSACTIONS not found".to_string()))?;
+        let hash_cf = self
+            .db
+            .cf_handle(CF_TX_HASHES)
+            .ok_or_else(|| StorageError::DatabaseError("CF_TX_HASHES not found".to_string()))?;
+
+        for key in keys {
+            let Some(bytes) = self
+                .db
+                .get_cf(tx_cf, key)
+                .map_err(|e| StorageError::DatabaseError(e.to_string()))?
+            else {
+                continue;
+            };
+            let tx: Tx = bincode::deserialize(&bytes[..])
+                .map_err(|_| StorageError::DeserializationFailed)?;
+            self.db
+                .delete_cf(hash_cf, tx_hash(&tx))
+                .map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Iterator behind `RocksDBStorage::iter_blocks`. Block keys are
+/// little-endian, so key order is not id order and a plain range scan would
+/// visit blocks out of sequence; instead each batch of ids is looked up with
+/// a single `multi_get_cf`.
+#[cfg(feature = "rocksdb")]
+struct BlockRangeIter {
+    db: Arc<DB>,
+    next: BlockId,
+    to: BlockId,
+    buffered: VecDeque<Result<Block, StorageError>>,
+    done: bool,
+}
+
+#[cfg(feature = "rocksdb")]
+impl BlockRangeIter {
+    fn fetch_batch(&mut self) {
+        let cf = match self.db.cf_handle(CF_BLOCKS) {
+            Some(cf) => cf,
+            None => {
+                self.buffered.push_back(Err(StorageError::DatabaseError(
+                    "CF_BLOCKS not found".to_string(),
+                )));
+                return;
+            }
+        };
+
+        let last = self
+            .next
+            .saturating_add(BLOCK_ITER_BATCH_SIZE - 1)
+            .min(self.to);
+        let values = self.db.multi_get_cf(
+            (self.next..=last).map(|block_id| (cf, RocksDBStorage::encode_block_id(block_id))),
+        );
+
+        for value in values {
+            let block = match value {
+                Ok(Some(bytes)) => bincode::deserialize(&bytes[..])
+                    .map_err(|_| StorageError::DeserializationFailed),
+                Ok(None) => Err(StorageError::NotFound),
+                Err(e) => Err(StorageError::DatabaseError(e.to_string())),
+            };
+            let failed = block.is_err();
+            self.buffered.push_back(block);
+            if failed {
+                return;
+            }
+        }
+
+        if last < self.to {
+            self.next = last + 1;
+        } else {
+            self.done = true;
+        }
+    }
+}
+
+#[cfg(feature = "rocksdb")]
+impl Iterator for BlockRangeIter {
+    type Item = Result<Block, StorageError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buffered.is_empty() && !self.done {
+            self.fetch_batch();
+        }
+
+        let item = self.buffered.pop_front()?;
+        if item.is_err() {
+            self.buffered.clear();
+            self.done = true;
+        }
+        Some(item)
+    }
 }
 
 #[cfg(feature = "rocksdb")]
 impl Storage for RocksDBStorage {
+    fn version(&self) -> u32 {
+        self.version.load(Ordering::SeqCst)
+    }
+
+    fn catch_up_with_primary(&self) -> Result<(), StorageError> {
+        if !self.secondary {
+            return Ok(());
+        }
+        self.db
+            .try_catch_up_with_primary()
+            .map_err(|e| StorageError::DatabaseError(e.to_string()))
+    }
+
+    fn set_version(&self, version: u32) -> Result<(), StorageError> {
+        let metadata_cf = self
+            .db
+            .cf_handle(CF_METADATA)
+            .ok_or_else(|| StorageError::DatabaseError("CF_METADATA not found".to_string()))?;
+
+        self.db
+            .put_cf(metadata_cf, b"storage_version", version.to_le_bytes())
+            .map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+        self.version.store(version, Ordering::SeqCst);
+        Ok(())
+    }
+
     fn save_block(&self, block: &Block) -> Result<(), StorageError> {
         let cf = self
             .db
@@ -102,6 +537,43 @@ impl Storage for RocksDBStorage {
         Ok(())
     }
 
+    fn save_block_atomic(
+        &self,
+        block: &Block,
+        diff: Option<&StateDiff>,
+        deals: &[&Deal],
+    ) -> Result<(), StorageError> {
+        let cf = |name: &str| {
+            self.db
+                .cf_handle(name)
+                .ok_or_else(|| StorageError::DatabaseError(format!("{} not found", name)))
+        };
+        let block_key = Self::encode_block_id(block.id);
+        let mut batch = WriteBatch::default();
+
+        for (index, tx) in block.transactions.iter().enumerate() {
+            let key = Self::encode_tx_id((block.id, index));
+            let value = bincode::serialize(tx).map_err(|_| StorageError::SerializationFailed)?;
+            batch.put_cf(cf(CF_TRANSACTIONS)?, &key, value);
+            batch.put_cf(cf(CF_TX_HASHES)?, tx_hash(tx), key);
+        }
+        for deal in deals {
+            let value = bincode::serialize(deal).map_err(|_| StorageError::SerializationFailed)?;
+            batch.put_cf(cf(CF_DEALS)?, deal.id.to_le_bytes(), value);
+        }
+        if let Some(diff) = diff {
+            let value = bincode::serialize(diff).map_err(|_| StorageError::SerializationFailed)?;
+            batch.put_cf(cf(CF_STATE_DIFFS)?, &block_key, value);
+        }
+        let value = bincode::serialize(block).map_err(|_| StorageError::SerializationFailed)?;
+        batch.put_cf(cf(CF_BLOCKS)?, &block_key, value);
+        batch.put_cf(cf(CF_METADATA)?, b"latest_block_id", &block_key);
+
+        self.db
+            .write(batch)
+            .map_err(|e| StorageError::DatabaseError(e.to_string()))
+    }
+
     fn get_block(&self, block_id: BlockId) -> Result<Option<Block>, StorageError> {
         let cf = self
             .db
@@ -123,6 +595,16 @@ impl Storage for RocksDBStorage {
         }
     }
 
+    fn iter_blocks(&self, from: BlockId, to: BlockId) -> BlockIter {
+        Box::new(BlockRangeIter {
+            db: self.db.clone(),
+            next: from,
+            to,
+            buffered: VecDeque::new(),
+            done: from > to,
+        })
+    }
+
     fn get_latest_block_id(&self) -> Result<Option<BlockId>, StorageError> {
         let cf = self
             .db
@@ -150,11 +632,19 @@ impl Storage for RocksDBStorage {
             .cf_handle(CF_TRANSACTIONS)
             .ok_or_else(|| StorageError::DatabaseError("CF_TRANSACTIONS not found".to_string()))?;
 
+        let hash_cf = self
+            .db
+            .cf_handle(CF_TX_HASHES)
+            .ok_or_else(|| StorageError::DatabaseError("CF_TX_HASHES not found".to_string()))?;
+
         let key = Self::encode_tx_id((block_id, index));
         let value = bincode::serialize(tx).map_err(|_| StorageError::SerializationFailed)?;
 
         self.db
-            .put_cf(cf, key, value)
+            .put_cf(cf, &key, value)
+            .map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+        self.db
+            .put_cf(hash_cf, tx_hash(tx), key)
             .map_err(|e| StorageError::DatabaseError(e.to_string()))?;
 
         Ok(())
@@ -207,6 +697,28 @@ impl Storage for RocksDBStorage {
         Ok(txs)
     }
 
+    fn get_transaction_by_hash(
+        &self,
+        tx_hash: &TxHash,
+    ) -> Result<Option<(Tx, BlockId, usize)>, StorageError> {
+        let cf = self
+            .db
+            .cf_handle(CF_TX_HASHES)
+            .ok_or_else(|| StorageError::DatabaseError("CF_TX_HASHES not found".to_string()))?;
+
+        let Some(bytes) = self
+            .db
+            .get_cf(cf, tx_hash)
+            .map_err(|e| StorageError::DatabaseError(e.to_string()))?
+        else {
+            return Ok(None);
+        };
+        let (block_id, index) = Self::decode_tx_id(&bytes)?;
+        Ok(self
+            .get_transaction(block_id, index)?
+            .map(|tx| (tx, block_id, index)))
+    }
+
     fn save_deal(&self, deal: &Deal) -> Result<(), StorageError> {
         let cf = self
             .db
@@ -264,67 +776,370 @@ impl Storage for RocksDBStorage {
     }
 
     fn save_state_snapshot(&self, state: &State, block_id: BlockId) -> Result<(), StorageError> {
+        self.write_snapshot(state, block_id)?;
+
+        let metadata_cf = self
+            .db
+            .cf_handle(CF_METADATA)
+            .ok_or_else(|| StorageError::DatabaseError("CF_METADATA not found".to_string()))?;
+
+        self.db
+            .put_cf(
+                metadata_cf,
+                b"latest_state_snapshot_block_id",
+                Self::encode_block_id(block_id),
+            )
+            .map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+
+        Ok(())
+    }
+
+    fn get_latest_state_snapshot(&self) -> Result<Option<(State, BlockId)>, StorageError> {
+        let snapshot_block_id = match self.latest_state_snapshot_block_id()? {
+            Some(block_id) => block_id,
+            None => return Ok(None),
+        };
+
+        Ok(self
+            .read_snapshot(snapshot_block_id)?
+            .map(|state| (state, snapshot_block_id)))
+    }
+
+    fn get_state_snapshot_at_or_before(
+        &self,
+        block_id: BlockId,
+    ) -> Result<Option<(State, BlockId)>, StorageError> {
+        let Some(snapshot_block_id) = self
+            .snapshot_block_ids()?
+            .into_iter()
+            .filter(|id| *id <= block_id)
+            .max()
+        else {
+            return Ok(None);
+        };
+
+        Ok(self
+            .read_snapshot(snapshot_block_id)?
+            .map(|state| (state, snapshot_block_id)))
+    }
+
+    fn prune_snapshots_before(&self, block_id: BlockId) -> Result<usize, StorageError> {
+        let latest = match self.latest_state_snapshot_block_id()? {
+            Some(latest) => latest,
+            None => return Ok(0),
+        };
+
+        let cf = self.db.cf_handle(CF_STATE_SNAPSHOTS).ok_or_else(|| {
+            StorageError::DatabaseError("CF_STATE_SNAPSHOTS not found".to_string())
+        })?;
+
+        // Keys are little-endian, so iteration order is not block order;
+        // scan the keys only and skip loading the snapshots themselves
+        let mut stale = Vec::new();
+        let mut iter = self.db.raw_iterator_cf(cf);
+        iter.seek_to_first();
+        while let Some(key) = iter.key() {
+            let snapshot_block_id = Self::decode_block_id(key)?;
+            if snapshot_block_id < block_id && snapshot_block_id != latest {
+                stale.push(snapshot_block_id);
+            }
+            iter.next();
+        }
+        iter.status()
+            .map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+
+        // Manifest first, so a snapshot is never listed without its chunks
+        for snapshot_block_id in &stale {
+            self.db
+                .delete_cf(cf, Self::encode_block_id(*snapshot_block_id))
+                .map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+            self.delete_snapshot_chunks(*snapshot_block_id)?;
+        }
+
+        Ok(stale.len())
+    }
+
+    fn upgrade_encodings(&self, found: u32) -> Result<(), StorageError> {
+        // Txs gained a signature scheme in version 2; there is no hash
+        // index to rewrite yet, as `reindex_transactions` builds it after
+        if found < 2 {
+            self.upgrade_cf(CF_TRANSACTIONS, |bytes| legacy::upgrade_tx(found, bytes))?;
+            self.upgrade_cf(CF_BLOCKS, |bytes| legacy::upgrade_block(found, bytes))?;
+        }
+        self.upgrade_cf(CF_DEALS, |bytes| legacy::upgrade_deal(found, bytes))?;
+        self.upgrade_cf(CF_STATE_DIFFS, |bytes| legacy::upgrade_state_diff(found, bytes))?;
+        // Snapshots are whole states until `rechunk_snapshots` splits them
+        if found < 3 {
+            self.upgrade_cf(CF_STATE_SNAPSHOTS, |bytes| legacy::upgrade_state(found, bytes))
+        } else {
+            self.upgrade_cf(CF_SNAPSHOT_CHUNKS, |bytes| {
+                legacy::upgrade_snapshot_chunk(found, bytes)
+            })
+        }
+    }
+
+    fn rechunk_snapshots(&self) -> Result<(), StorageError> {
         let cf = self.db.cf_handle(CF_STATE_SNAPSHOTS).ok_or_else(|| {
             StorageError::DatabaseError("CF_STATE_SNAPSHOTS not found".to_string())
         })?;
 
+        // Load one old snapshot at a time; they can be large
+        for snapshot_block_id in self.snapshot_block_ids()? {
+            let Some(bytes) = self
+                .db
+                .get_cf(cf, Self::encode_block_id(snapshot_block_id))
+                .map_err(|e| StorageError::DatabaseError(e.to_string()))?
+            else {
+                continue;
+            };
+            let state: State = bincode::deserialize(&bytes[..])
+                .map_err(|_| StorageError::DeserializationFailed)?;
+            drop(bytes);
+            self.write_snapshot(&state, snapshot_block_id)?;
+        }
+        Ok(())
+    }
+
+    fn save_state_diff(&self, block_id: BlockId, diff: &StateDiff) -> Result<(), StorageError> {
+        let cf = self
+            .db
+            .cf_handle(CF_STATE_DIFFS)
+            .ok_or_else(|| StorageError::DatabaseError("CF_STATE_DIFFS not found".to_string()))?;
+
         let key = Self::encode_block_id(block_id);
-        let value = bincode::serialize(state).map_err(|_| StorageError::SerializationFailed)?;
+        let value = bincode::serialize(diff).map_err(|_| StorageError::SerializationFailed)?;
 
         self.db
             .put_cf(cf, key, value)
             .map_err(|e| StorageError::DatabaseError(e.to_string()))?;
 
+        Ok(())
+    }
+
+    fn get_state_diff(&self, block_id: BlockId) -> Result<Option<StateDiff>, StorageError> {
+        let cf = self
+            .db
+            .cf_handle(CF_STATE_DIFFS)
+            .ok_or_else(|| StorageError::DatabaseError("CF_STATE_DIFFS not found".to_string()))?;
+
+        let key = Self::encode_block_id(block_id);
+        match self
+            .db
+            .get_cf(cf, key)
+            .map_err(|e| StorageError::DatabaseError(e.to_string()))?
+        {
+            Some(bytes) => {
+                let diff: StateDiff = bincode::deserialize(&bytes[..])
+                    .map_err(|_| StorageError::DeserializationFailed)?;
+                Ok(Some(diff))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn save_withdrawals_accumulator(
+        &self,
+        block_id: BlockId,
+        accumulator: &[u8; 32],
+    ) -> Result<(), StorageError> {
+        let cf = self
+            .db
+            .cf_handle(CF_WITHDRAWALS_ACCUMULATORS)
+            .ok_or_else(|| {
+                StorageError::DatabaseError("CF_WITHDRAWALS_ACCUMULATORS not found".to_string())
+            })?;
+
+        self.db
+            .put_cf(cf, Self::encode_block_id(block_id), accumulator)
+            .map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+
+        Ok(())
+    }
+
+    fn get_withdrawals_accumulator(
+        &self,
+        block_id: BlockId,
+    ) -> Result<Option<[u8; 32]>, StorageError> {
+        let cf = self
+            .db
+            .cf_handle(CF_WITHDRAWALS_ACCUMULATORS)
+            .ok_or_else(|| {
+                StorageError::DatabaseError("CF_WITHDRAWALS_ACCUMULATORS not found".to_string())
+            })?;
+
+        match self
+            .db
+            .get_cf(cf, Self::encode_block_id(block_id))
+            .map_err(|e| StorageError::DatabaseError(e.to_string()))?
+        {
+            Some(bytes) => {
+                let accumulator: [u8; 32] = bytes[..]
+                    .try_into()
+                    .map_err(|_| StorageError::DeserializationFailed)?;
+                Ok(Some(accumulator))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn truncate_after(&self, block_id: BlockId) -> Result<usize, StorageError> {
+        // Deals before the diffs naming them, and blocks last, so an
+        // interrupted truncation is retried in full
+        let deals_cf = self
+            .db
+            .cf_handle(CF_DEALS)
+            .ok_or_else(|| StorageError::DatabaseError("CF_DEALS not found".to_string()))?;
+        for key in self.keys_after(CF_STATE_DIFFS, block_id)? {
+            let Some(diff) = self.get_state_diff(Self::decode_block_id(&key)?)? else {
+                continue;
+            };
+            let deal_ids = diff.deals.iter().map(|deal| deal.id);
+            for deal_id in deal_ids.chain(diff.removed_deals.iter().copied()) {
+                self.db
+                    .delete_cf(deals_cf, deal_id.to_le_bytes())
+                    .map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+            }
+        }
+
+        let claims_cf = self.db.cf_handle(CF_BUILDER_CLAIMS).ok_or_else(|| {
+            StorageError::DatabaseError("CF_BUILDER_CLAIMS not found".to_string())
+        })?;
+        self.db
+            .delete_range_cf(
+                claims_cf,
+                block_id.saturating_add(1).to_be_bytes(),
+                BlockId::MAX.to_be_bytes(),
+            )
+            .map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+
+        let mut removed = 0;
+        for cf_name in [
+            CF_TRANSACTIONS,
+            CF_STATE_DIFFS,
+            CF_WITHDRAWALS_ACCUMULATORS,
+            CF_STATE_SNAPSHOTS,
+            CF_SNAPSHOT_CHUNKS,
+            CF_BLOCKS,
+        ] {
+            let stale = self.keys_after(cf_name, block_id)?;
+            if cf_name == CF_TRANSACTIONS {
+                self.unindex_transactions(&stale)?;
+            }
+            let cf = self
+                .db
+                .cf_handle(cf_name)
+                .ok_or_else(|| StorageError::DatabaseError(format!("{} not found", cf_name)))?;
+            for key in &stale {
+                self.db
+                    .delete_cf(cf, key)
+                    .map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+            }
+            if cf_name == CF_BLOCKS {
+                removed = stale.len();
+            }
+        }
+
         let metadata_cf = self
             .db
             .cf_handle(CF_METADATA)
             .ok_or_else(|| StorageError::DatabaseError("CF_METADATA not found".to_string()))?;
 
-        self.db
-            .put_cf(
+        if self
+            .get_latest_block_id()?
+            .is_some_and(|latest| latest > block_id)
+        {
+            self.db
+                .put_cf(
+                    metadata_cf,
+                    b"latest_block_id",
+                    Self::encode_block_id(block_id),
+                )
+                .map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+        }
+
+        match self.snapshot_block_ids()?.into_iter().max() {
+            Some(latest) => self.db.put_cf(
                 metadata_cf,
                 b"latest_state_snapshot_block_id",
-                Self::encode_block_id(block_id),
-            )
-            .map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+                Self::encode_block_id(latest),
+            ),
+            None => self
+                .db
+                .delete_cf(metadata_cf, b"latest_state_snapshot_block_id"),
+        }
+        .map_err(|e| StorageError::DatabaseError(e.to_string()))?;
 
-        Ok(())
+        Ok(removed)
     }
 
-    fn get_latest_state_snapshot(&self) -> Result<Option<(State, BlockId)>, StorageError> {
+    fn save_checkpoint(&self, checkpoint: &Checkpoint) -> Result<(), StorageError> {
         let metadata_cf = self
             .db
             .cf_handle(CF_METADATA)
             .ok_or_else(|| StorageError::DatabaseError("CF_METADATA not found".to_string()))?;
 
-        let snapshot_block_id = match self
-            .db
-            .get_cf(metadata_cf, b"latest_state_snapshot_block_id")
-            .map_err(|e| StorageError::DatabaseError(e.to_string()))?
-        {
-            Some(bytes) => Self::decode_block_id(&bytes)?,
-            None => return Ok(None),
-        };
+        let value =
+            bincode::serialize(checkpoint).map_err(|_| StorageError::SerializationFailed)?;
 
-        let cf = self.db.cf_handle(CF_STATE_SNAPSHOTS).ok_or_else(|| {
-            StorageError::DatabaseError("CF_STATE_SNAPSHOTS not found".to_string())
-        })?;
+        self.db
+            .put_cf(metadata_cf, b"latest_checkpoint", value)
+            .map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+
+        Ok(())
+    }
+
+    fn get_latest_checkpoint(&self) -> Result<Option<Checkpoint>, StorageError> {
+        let metadata_cf = self
+            .db
+            .cf_handle(CF_METADATA)
+            .ok_or_else(|| StorageError::DatabaseError("CF_METADATA not found".to_string()))?;
 
-        let key = Self::encode_block_id(snapshot_block_id);
         match self
             .db
-            .get_cf(cf, key)
+            .get_cf(metadata_cf, b"latest_checkpoint")
             .map_err(|e| StorageError::DatabaseError(e.to_string()))?
         {
             Some(bytes) => {
-                let state: State = bincode::deserialize(&bytes[..])
+                let checkpoint: Checkpoint = bincode::deserialize(&bytes[..])
                     .map_err(|_| StorageError::DeserializationFailed)?;
-                Ok(Some((state, snapshot_block_id)))
+                Ok(Some(checkpoint))
             }
             None => Ok(None),
         }
     }
 
+    fn claim_block_builder(
+        &self,
+        block_id: BlockId,
+        claim: &BuilderClaim,
+    ) -> Result<bool, StorageError> {
+        let claims_cf = self.db.cf_handle(CF_BUILDER_CLAIMS).ok_or_else(|| {
+            StorageError::DatabaseError("CF_BUILDER_CLAIMS not found".to_string())
+        })?;
+
+        let key = block_id.to_be_bytes();
+        let claim_bytes =
+            bincode::serialize(claim).map_err(|_| StorageError::SerializationFailed)?;
+
+        // The merge operator decides between competing claims, so this
+        // holds across every writer of the store without a read first
+        let mut batch = WriteBatch::default();
+        batch.delete_range_cf(claims_cf, 0u64.to_be_bytes(), key);
+        batch.merge_cf(claims_cf, key, claim_bytes);
+        self.db
+            .write(batch)
+            .map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+
+        let bytes = self
+            .db
+            .get_cf(claims_cf, key)
+            .map_err(|e| StorageError::DatabaseError(e.to_string()))?
+            .ok_or(StorageError::NotFound)?;
+        let current: BuilderClaim =
+            bincode::deserialize(&bytes).map_err(|_| StorageError::DeserializationFailed)?;
+        Ok(current.holder == claim.holder)
+    }
+
     fn flush(&self) -> Result<(), StorageError> {
         self.db
             .flush()
@@ -332,3 +1147,170 @@ impl Storage for RocksDBStorage {
         Ok(())
     }
 }
+
+/// Merge operator of `CF_BUILDER_CLAIMS`: each operand is a claim that
+/// replaces the current one only if `BuilderClaim::replaces` allows it
+#[cfg(feature = "rocksdb")]
+fn merge_builder_claims(
+    _key: &[u8],
+    existing: Option<&[u8]>,
+    operands: &MergeOperands,
+) -> Option<Vec<u8>> {
+    let mut current: Option<BuilderClaim> = existing.and_then(|b| bincode::deserialize(b).ok());
+    for operand in operands.iter() {
+        let Ok(claim) = bincode::deserialize::<BuilderClaim>(operand) else {
+            continue;
+        };
+        if current.as_ref().is_none_or(|c| claim.replaces(c)) {
+            current = Some(claim);
+        }
+    }
+    current.and_then(|c| bincode::serialize(&c).ok())
+}
+
+/// Claims are only ever merged onto the claim they would replace, so
+/// RocksDB keeps operands apart until it has the current value
+#[cfg(feature = "rocksdb")]
+fn refuse_partial_merge(
+    _key: &[u8],
+    _existing: Option<&[u8]>,
+    _operands: &MergeOperands,
+) -> Option<Vec<u8>> {
+    None
+}
+
+#[cfg(all(test, feature = "rocksdb"))]
+mod tests {
+    use super::*;
+
+    fn temp_storage(name: &str) -> (RocksDBStorage, std::path::PathBuf) {
+        let path =
+            std::env::temp_dir().join(format!("zkclear-storage-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        (RocksDBStorage::open(&path).unwrap(), path)
+    }
+
+    fn block(id: BlockId) -> Block {
+        Block {
+            id,
+            transactions: Vec::new(),
+            timestamp: 1000 + id,
+            state_root: [id as u8; 32],
+            withdrawals_root: [0u8; 32],
+            block_proof: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_iter_blocks_in_order_and_stops_at_gap() {
+        let (storage, path) = temp_storage("iter-blocks");
+        // Ids whose little-endian keys sort out of numeric order
+        for id in (1..=300).filter(|id| *id != 200) {
+            storage.save_block(&block(id)).unwrap();
+        }
+
+        let ids: Vec<BlockId> = storage.iter_blocks(1, 199).map(|b| b.unwrap().id).collect();
+        assert_eq!(ids, (1..=199).collect::<Vec<_>>());
+
+        let results: Vec<_> = storage.iter_blocks(150, 300).collect();
+        assert_eq!(results.len(), 51);
+        assert_eq!(results[49].as_ref().unwrap().id, 199);
+        assert!(matches!(results[50], Err(StorageError::NotFound)));
+
+        drop(storage);
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn test_prune_snapshots_keeps_latest() {
+        let (storage, path) = temp_storage("prune-snapshots");
+        let mut state = State::new();
+        for block_id in [10u64, 20, 30, 300] {
+            state.get_or_create_account_by_owner([block_id as u8; 20]);
+            storage.save_state_snapshot(&state, block_id).unwrap();
+        }
+
+        assert_eq!(storage.prune_snapshots_before(30).unwrap(), 2);
+        // A cutoff past every snapshot still leaves the latest in place
+        assert_eq!(storage.prune_snapshots_before(1_000).unwrap(), 1);
+        assert_eq!(storage.prune_snapshots_before(1_000).unwrap(), 0);
+
+        let (retrieved_state, block_id) = storage.get_latest_state_snapshot().unwrap().unwrap();
+        assert_eq!(block_id, 300);
+        assert_eq!(retrieved_state.accounts.len(), 4);
+
+        drop(storage);
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn test_secondary_sees_primary_writes_after_catch_up() {
+        let (primary, path) = temp_storage("secondary");
+        primary.save_block(&block(1)).unwrap();
+
+        let secondary_path = path.with_extension("secondary");
+        let secondary = RocksDBStorage::open_as_secondary(&path, &secondary_path).unwrap();
+        assert_eq!(secondary.get_latest_block_id().unwrap(), Some(1));
+
+        primary.save_block(&block(2)).unwrap();
+        primary.db.flush_wal(true).unwrap();
+        secondary.catch_up_with_primary().unwrap();
+        assert_eq!(secondary.get_latest_block_id().unwrap(), Some(2));
+        assert_eq!(secondary.get_block(2).unwrap().unwrap().id, 2);
+        assert!(secondary.save_block(&block(3)).is_err());
+
+        drop(secondary);
+        drop(primary);
+        let _ = std::fs::remove_dir_all(&path);
+        let _ = std::fs::remove_dir_all(&secondary_path);
+    }
+
+    #[test]
+    fn test_builder_claims_expire_and_prune() {
+        let (storage, path) = temp_storage("builder-claims");
+        let claim = |holder: &str, claimed_at| BuilderClaim {
+            holder: holder.to_string(),
+            claimed_at,
+            expires_at: claimed_at + 10,
+        };
+
+        assert!(storage.claim_block_builder(1, &claim("a", 0)).unwrap());
+        assert!(storage.claim_block_builder(1, &claim("a", 5)).unwrap());
+        assert!(!storage.claim_block_builder(1, &claim("b", 14)).unwrap());
+        assert!(storage.claim_block_builder(1, &claim("b", 15)).unwrap());
+        assert!(!storage.claim_block_builder(1, &claim("a", 16)).unwrap());
+
+        assert!(storage.claim_block_builder(2, &claim("a", 16)).unwrap());
+        let claims_cf = storage.db.cf_handle(CF_BUILDER_CLAIMS).unwrap();
+        assert!(storage
+            .db
+            .get_cf(claims_cf, 1u64.to_be_bytes())
+            .unwrap()
+            .is_none());
+
+        drop(storage);
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn test_open_rejects_store_from_newer_binary() {
+        let (storage, path) = temp_storage("version");
+        assert_eq!(storage.version(), STORAGE_VERSION);
+        storage.save_block(&block(1)).unwrap();
+        drop(storage);
+
+        // Reopening keeps the recorded version
+        let storage = RocksDBStorage::open(&path).unwrap();
+        assert_eq!(storage.version(), STORAGE_VERSION);
+        storage.set_version(STORAGE_VERSION + 1).unwrap();
+        drop(storage);
+
+        assert!(matches!(
+            RocksDBStorage::open(&path),
+            Err(StorageError::IncompatibleVersion { found, supported })
+                if found == STORAGE_VERSION + 1 && supported == STORAGE_VERSION
+        ));
+
+        let _ = std::fs::remove_dir_all(&path);
+    }
+}
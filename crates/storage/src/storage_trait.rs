@@ -1,5 +1,12 @@
-use zkclear_state::State;
-use zkclear_types::{Block, BlockId, Deal, DealId, Tx};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use zkclear_state::{SnapshotError, State, StateDiff};
+use zkclear_types::{Block, BlockId, Checkpoint, Deal, DealId, Tx};
+
+/// Schema version of the data this binary writes. Bump it, and teach
+/// `Storage::migrate` to convert the previous layout, whenever the stored
+/// encoding of blocks, snapshots or metadata changes.
+pub const STORAGE_VERSION: u32 = 4;
 
 #[derive(Debug)]
 pub enum StorageError {
@@ -8,13 +15,96 @@ pub enum StorageError {
     DeserializationFailed,
     DatabaseError(String),
     IOError(String),
+    /// The store was written by a binary with a newer schema than this one
+    IncompatibleVersion {
+        found: u32,
+        supported: u32,
+    },
+}
+
+impl From<SnapshotError> for StorageError {
+    fn from(error: SnapshotError) -> Self {
+        match error {
+            SnapshotError::Serialization => StorageError::SerializationFailed,
+            SnapshotError::Deserialization | SnapshotError::ChunkCountMismatch { .. } => {
+                StorageError::DeserializationFailed
+            }
+        }
+    }
 }
 
 pub trait Storage: Send + Sync {
+    /// Schema version of the stored data
+    fn version(&self) -> u32;
+    fn set_version(&self, version: u32) -> Result<(), StorageError>;
+
+    /// Bring the stored data up to `STORAGE_VERSION`, run when a store is
+    /// opened. Data from a newer binary is refused rather than misread.
+    fn migrate(&self) -> Result<(), StorageError> {
+        let found = self.version();
+        if found > STORAGE_VERSION {
+            return Err(StorageError::IncompatibleVersion {
+                found,
+                supported: STORAGE_VERSION,
+            });
+        }
+        // Version 2 added deal escrow and the tx signature scheme, and
+        // version 4 `Deal::updated_at` and an escrow per fill. Records are
+        // re-encoded first, as the steps below read them in the current
+        // layout.
+        if found < 4 {
+            self.upgrade_encodings(found)?;
+        }
+        // Version 2 added the tx hash index; fill it in from stored blocks
+        if found < 2 {
+            self.reindex_transactions()?;
+        }
+        // Version 3 stores snapshots in chunks instead of one blob
+        if found < 3 {
+            self.rechunk_snapshots()?;
+        }
+        if found < STORAGE_VERSION {
+            self.set_version(STORAGE_VERSION)?;
+        }
+        Ok(())
+    }
+
     fn save_block(&self, block: &Block) -> Result<(), StorageError>;
     fn get_block(&self, block_id: BlockId) -> Result<Option<Block>, StorageError>;
     fn get_latest_block_id(&self) -> Result<Option<BlockId>, StorageError>;
 
+    /// Save a block together with its transactions, its state diff and the
+    /// deals the block changed, all or nothing, so a crash can't leave the
+    /// block half written. The default writes piece by piece with the block
+    /// last, which is only safe for backends that can't fail midway.
+    fn save_block_atomic(
+        &self,
+        block: &Block,
+        diff: Option<&StateDiff>,
+        deals: &[&Deal],
+    ) -> Result<(), StorageError> {
+        if let Some(diff) = diff {
+            self.save_state_diff(block.id, diff)?;
+        }
+        for (index, tx) in block.transactions.iter().enumerate() {
+            self.save_transaction(tx, block.id, index)?;
+        }
+        for deal in deals {
+            self.save_deal(deal)?;
+        }
+        self.save_block(block)
+    }
+
+    /// Blocks `from..=to` in ascending id order, loaded as the iterator
+    /// advances. A block missing from the range yields
+    /// `StorageError::NotFound` and ends the iteration.
+    fn iter_blocks(&self, from: BlockId, to: BlockId) -> BlockIter;
+
+    /// Proof bytes of a stored block; empty when it was built without a prover
+    fn get_block_proof(&self, block_id: BlockId) -> Result<Option<Vec<u8>>, StorageError> {
+        Ok(self.get_block(block_id)?.map(|block| block.block_proof))
+    }
+
     fn save_transaction(
         &self,
         tx: &Tx,
@@ -24,14 +114,141 @@ pub trait Storage: Send + Sync {
     fn get_transaction(&self, block_id: BlockId, index: usize) -> Result<Option<Tx>, StorageError>;
     fn get_transactions_by_block(&self, block_id: BlockId) -> Result<Vec<Tx>, StorageError>;
 
+    /// Stored transaction with the given `tx_hash`, with the block id and
+    /// index it was saved under
+    fn get_transaction_by_hash(
+        &self,
+        tx_hash: &TxHash,
+    ) -> Result<Option<(Tx, BlockId, usize)>, StorageError>;
+
+    /// Re-save the transactions of every stored block so the tx hash index
+    /// covers them
+    fn reindex_transactions(&self) -> Result<(), StorageError> {
+        let Some(latest) = self.get_latest_block_id()? else {
+            return Ok(());
+        };
+        for block_id in 0..=latest {
+            if let Some(block) = self.get_block(block_id)? {
+                for (index, tx) in block.transactions.iter().enumerate() {
+                    self.save_transaction(tx, block.id, index)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
     fn save_deal(&self, deal: &Deal) -> Result<(), StorageError>;
     fn get_deal(&self, deal_id: DealId) -> Result<Option<Deal>, StorageError>;
     fn get_all_deals(&self) -> Result<Vec<Deal>, StorageError>;
 
+    /// Save `state` as the snapshot of `block_id`, written as a manifest
+    /// plus chunks of accounts and deals so no single encoding holds the
+    /// whole state
     fn save_state_snapshot(&self, state: &State, block_id: BlockId) -> Result<(), StorageError>;
     fn get_latest_state_snapshot(&self) -> Result<Option<(State, BlockId)>, StorageError>;
 
+    /// Most recent state snapshot taken at or before `block_id`
+    fn get_state_snapshot_at_or_before(
+        &self,
+        block_id: BlockId,
+    ) -> Result<Option<(State, BlockId)>, StorageError>;
+
+    /// Delete state snapshots taken before `block_id` and return how many
+    /// were removed. The latest snapshot is always kept, even when it is
+    /// older than `block_id`.
+    fn prune_snapshots_before(&self, block_id: BlockId) -> Result<usize, StorageError>;
+
+    /// Re-encode records written by a version `found` store in the current
+    /// layout. Stores that never persisted an older layout have nothing to
+    /// do.
+    fn upgrade_encodings(&self, _found: u32) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    /// Rewrite snapshots saved whole by a version 2 or older store in the
+    /// chunked layout. Stores that never persisted the old layout have
+    /// nothing to do.
+    fn rechunk_snapshots(&self) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    /// Bring a read-only secondary handle up to date with the primary
+    /// writing the same store. Handles that see every write as it happens
+    /// have nothing to do.
+    fn catch_up_with_primary(&self) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    /// Accounts and deals changed by block `block_id`, recorded when the
+    /// block was executed
+    fn save_state_diff(&self, block_id: BlockId, diff: &StateDiff) -> Result<(), StorageError>;
+    fn get_state_diff(&self, block_id: BlockId) -> Result<Option<StateDiff>, StorageError>;
+
+    /// Running hash over the withdrawals roots of blocks up to and
+    /// including `block_id`, recorded as each block is committed so a
+    /// restart or rollback doesn't fold every stored block again
+    fn save_withdrawals_accumulator(
+        &self,
+        block_id: BlockId,
+        accumulator: &[u8; 32],
+    ) -> Result<(), StorageError>;
+    fn get_withdrawals_accumulator(
+        &self,
+        block_id: BlockId,
+    ) -> Result<Option<[u8; 32]>, StorageError>;
+
+    /// Delete every block after `block_id` along with its txs, state diff,
+    /// withdrawals accumulator, snapshot and builder claim, leaving
+    /// `block_id` the latest block. Deals the deleted blocks changed, as
+    /// their state diffs record, are deleted too, as the stored versions
+    /// are from after `block_id`; the caller saves the versions it
+    /// re-derives. Returns how many blocks were removed.
+    fn truncate_after(&self, block_id: BlockId) -> Result<usize, StorageError>;
+
+    fn save_checkpoint(&self, checkpoint: &Checkpoint) -> Result<(), StorageError>;
+    fn get_latest_checkpoint(&self) -> Result<Option<Checkpoint>, StorageError>;
+
+    /// Atomically claim the right to build `block_id`. The claim is taken
+    /// when the block is unclaimed, already held by `claim.holder`, or held
+    /// by a claim that expired by `claim.claimed_at`. Claims on earlier
+    /// block ids are pruned. Returns whether `claim.holder` holds the block
+    /// afterwards.
+    fn claim_block_builder(
+        &self,
+        block_id: BlockId,
+        claim: &BuilderClaim,
+    ) -> Result<bool, StorageError>;
+
     fn flush(&self) -> Result<(), StorageError>;
 }
 
 pub type TxId = (BlockId, usize);
+
+pub type TxHash = [u8; 32];
+
+/// Canonical transaction hash: sha256 of the bincode-encoded `Tx`. Used as
+/// the storage index key and returned as `tx_hash` on submission.
+pub fn tx_hash(tx: &Tx) -> TxHash {
+    let bytes = bincode::serialize(tx).unwrap_or_default();
+    Sha256::digest(bytes).into()
+}
+
+pub type BlockIter = Box<dyn Iterator<Item = Result<Block, StorageError>> + Send>;
+
+/// A holder's claim on the right to build one block
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuilderClaim {
+    pub holder: String,
+    /// When the claim was made, in seconds since the Unix epoch
+    pub claimed_at: u64,
+    /// From when another holder may take the block over
+    pub expires_at: u64,
+}
+
+impl BuilderClaim {
+    /// Whether this claim takes the block from `current`: a holder renews
+    /// its own claim, and anyone may take over an expired one
+    pub fn replaces(&self, current: &BuilderClaim) -> bool {
+        self.holder == current.holder || current.expires_at <= self.claimed_at
+    }
+}
@@ -17,6 +17,10 @@ pub mod signature {
     pub const R_SIZE: usize = 32;
     pub const S_SIZE: usize = 32;
     pub const V_SIZE: usize = 1;
+    pub const ED25519_PUBLIC_KEY_SIZE: usize = 32;
+    pub const ED25519_SIGNATURE_SIZE: usize = 64;
+    /// EIP-191 `personal_sign` prefix, followed by the decimal message length
+    pub const ETH_SIGNED_MESSAGE_PREFIX: &[u8] = b"\x19Ethereum Signed Message:\n";
 }
 
 pub mod transaction {
@@ -0,0 +1,445 @@
+//! EIP-712 typed-data signing hashes
+//!
+//! Each payload is an EIP-712 struct named after its kind, and a whole tx is
+//! a `Transaction` struct holding its envelope fields and the payload, so
+//! wallets can show every field before signing. EIP-712 has no optional
+//! values: `None` is encoded as the zero value of the field's type.
+
+use sha3::{Digest, Keccak256};
+
+use crate::{
+    AcceptDeal, Address, BatchCreateDeal, CancelAllDeals, CancelDeal, ChainId, ConfirmSettlement,
+    CreateDeal, CreateFundedDeal, DeclineDeal, Deposit, Transfer, Tx, TxPayload, Withdraw,
+};
+
+pub const EIP712_DOMAIN_NAME: &str = "zkClear";
+pub const EIP712_DOMAIN_VERSION: &str = "1";
+
+const CREATE_DEAL_TYPE: &str = concat!(
+    "CreateDeal(uint64 dealId,uint8 visibility,address taker,uint16 assetBase,",
+    "uint16 assetQuote,uint64 chainIdBase,uint64 chainIdQuote,uint128 amountBase,",
+    "uint128 priceQuotePerBase,uint64 expiresAt,string externalRef)"
+);
+
+const DOMAIN_TYPE: &str =
+    "EIP712Domain(string name,string version,uint256 chainId,address verifyingContract)";
+
+/// Signing domain: the rollup's own chain id and the contract that settles it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Eip712Domain {
+    pub chain_id: ChainId,
+    pub verifying_contract: Address,
+}
+
+impl Eip712Domain {
+    pub fn new(chain_id: ChainId, verifying_contract: Address) -> Self {
+        Self {
+            chain_id,
+            verifying_contract,
+        }
+    }
+
+    pub fn separator(&self) -> [u8; 32] {
+        let mut data = Vec::with_capacity(5 * 32);
+        data.extend_from_slice(&keccak256(DOMAIN_TYPE.as_bytes()));
+        data.extend_from_slice(&keccak256(EIP712_DOMAIN_NAME.as_bytes()));
+        data.extend_from_slice(&keccak256(EIP712_DOMAIN_VERSION.as_bytes()));
+        data.extend_from_slice(&uint_word(self.chain_id as u128));
+        data.extend_from_slice(&address_word(&self.verifying_contract));
+        keccak256(&data)
+    }
+
+    /// `keccak256(0x19 0x01 || domainSeparator || hashStruct(value))`
+    pub fn typed_data_hash<T: Eip712Struct + ?Sized>(&self, value: &T) -> [u8; 32] {
+        let mut hasher = Keccak256::new();
+        hasher.update([0x19, 0x01]);
+        hasher.update(self.separator());
+        hasher.update(value.struct_hash());
+        hasher.finalize().into()
+    }
+}
+
+/// A value that can be signed as EIP-712 typed data
+pub trait Eip712Struct {
+    /// `encodeType`: the struct's own type followed by the types it references
+    fn encode_type(&self) -> String;
+
+    /// `encodeData` without the leading type hash: one 32-byte word per field
+    fn encode_data(&self) -> Vec<u8>;
+
+    fn struct_hash(&self) -> [u8; 32] {
+        let mut data = keccak256(self.encode_type().as_bytes()).to_vec();
+        data.extend_from_slice(&self.encode_data());
+        keccak256(&data)
+    }
+
+    /// Digest a wallet signs with `eth_signTypedData_v4`
+    fn signing_hash_eip712(&self, chain_id: ChainId, verifying_contract: Address) -> [u8; 32] {
+        Eip712Domain::new(chain_id, verifying_contract).typed_data_hash(self)
+    }
+}
+
+impl Eip712Struct for Deposit {
+    fn encode_type(&self) -> String {
+        "Deposit(bytes32 txHash,address account,uint16 assetId,uint128 amount,uint64 chainId)"
+            .to_string()
+    }
+
+    fn encode_data(&self) -> Vec<u8> {
+        [
+            self.tx_hash,
+            address_word(&self.account),
+            uint_word(self.asset_id as u128),
+            uint_word(self.amount),
+            uint_word(self.chain_id as u128),
+        ]
+        .concat()
+    }
+}
+
+impl Eip712Struct for Withdraw {
+    fn encode_type(&self) -> String {
+        "Withdraw(uint16 assetId,uint128 amount,address to,uint64 chainId)".to_string()
+    }
+
+    fn encode_data(&self) -> Vec<u8> {
+        [
+            uint_word(self.asset_id as u128),
+            uint_word(self.amount),
+            address_word(&self.to),
+            uint_word(self.chain_id as u128),
+        ]
+        .concat()
+    }
+}
+
+impl Eip712Struct for CreateDeal {
+    fn encode_type(&self) -> String {
+        CREATE_DEAL_TYPE.to_string()
+    }
+
+    fn encode_data(&self) -> Vec<u8> {
+        [
+            uint_word(self.deal_id as u128),
+            uint_word(self.visibility as u128),
+            address_word(&self.taker.unwrap_or_default()),
+            uint_word(self.asset_base as u128),
+            uint_word(self.asset_quote as u128),
+            uint_word(self.chain_id_base as u128),
+            uint_word(self.chain_id_quote as u128),
+            uint_word(self.amount_base),
+            uint_word(self.price_quote_per_base),
+            uint_word(self.expires_at.unwrap_or_default() as u128),
+            keccak256(self.external_ref.as_deref().unwrap_or_default().as_bytes()),
+        ]
+        .concat()
+    }
+}
+
+impl Eip712Struct for AcceptDeal {
+    fn encode_type(&self) -> String {
+        "AcceptDeal(uint64 dealId,uint128 amount,uint128 maxPriceQuotePerBase)".to_string()
+    }
+
+    fn encode_data(&self) -> Vec<u8> {
+        [
+            uint_word(self.deal_id as u128),
+            uint_word(self.amount.unwrap_or_default()),
+            uint_word(self.max_price_quote_per_base.unwrap_or_default()),
+        ]
+        .concat()
+    }
+}
+
+impl Eip712Struct for CancelDeal {
+    fn encode_type(&self) -> String {
+        "CancelDeal(uint64 dealId)".to_string()
+    }
+
+    fn encode_data(&self) -> Vec<u8> {
+        uint_word(self.deal_id as u128).to_vec()
+    }
+}
+
+impl Eip712Struct for DeclineDeal {
+    fn encode_type(&self) -> String {
+        "DeclineDeal(uint64 dealId)".to_string()
+    }
+
+    fn encode_data(&self) -> Vec<u8> {
+        uint_word(self.deal_id as u128).to_vec()
+    }
+}
+
+impl Eip712Struct for CreateFundedDeal {
+    fn encode_type(&self) -> String {
+        format!(
+            "CreateFundedDeal(Deposit deposit,CreateDeal deal){}{}",
+            self.deal.encode_type(),
+            self.deposit.encode_type()
+        )
+    }
+
+    fn encode_data(&self) -> Vec<u8> {
+        [self.deposit.struct_hash(), self.deal.struct_hash()].concat()
+    }
+}
+
+impl Eip712Struct for ConfirmSettlement {
+    fn encode_type(&self) -> String {
+        "ConfirmSettlement(uint64 dealId)".to_string()
+    }
+
+    fn encode_data(&self) -> Vec<u8> {
+        uint_word(self.deal_id as u128).to_vec()
+    }
+}
+
+impl Eip712Struct for BatchCreateDeal {
+    fn encode_type(&self) -> String {
+        format!("BatchCreateDeal(CreateDeal[] deals){}", CREATE_DEAL_TYPE)
+    }
+
+    /// An array is encoded as the hash of its elements' struct hashes
+    fn encode_data(&self) -> Vec<u8> {
+        let hashes: Vec<u8> = self.deals.iter().flat_map(|d| d.struct_hash()).collect();
+        keccak256(&hashes).to_vec()
+    }
+}
+
+impl Eip712Struct for CancelAllDeals {
+    fn encode_type(&self) -> String {
+        "CancelAllDeals(bool filterByAsset,uint16 assetBase)".to_string()
+    }
+
+    fn encode_data(&self) -> Vec<u8> {
+        [
+            uint_word(self.asset_base.is_some() as u128),
+            uint_word(self.asset_base.unwrap_or_default() as u128),
+        ]
+        .concat()
+    }
+}
+
+impl Eip712Struct for Transfer {
+    fn encode_type(&self) -> String {
+        "Transfer(address to,uint16 assetId,uint128 amount,uint64 chainId)".to_string()
+    }
+
+    fn encode_data(&self) -> Vec<u8> {
+        [
+            address_word(&self.to),
+            uint_word(self.asset_id as u128),
+            uint_word(self.amount),
+            uint_word(self.chain_id as u128),
+        ]
+        .concat()
+    }
+}
+
+impl TxPayload {
+    fn as_eip712(&self) -> &dyn Eip712Struct {
+        match self {
+            TxPayload::Deposit(p) => p,
+            TxPayload::Withdraw(p) => p,
+            TxPayload::CreateDeal(p) => p,
+            TxPayload::AcceptDeal(p) => p,
+            TxPayload::CancelDeal(p) => p,
+            TxPayload::Transfer(p) => p,
+            TxPayload::DeclineDeal(p) => p,
+            TxPayload::CreateFundedDeal(p) => p,
+            TxPayload::ConfirmSettlement(p) => p,
+            TxPayload::BatchCreateDeal(p) => p,
+            TxPayload::CancelAllDeals(p) => p,
+        }
+    }
+}
+
+impl Eip712Struct for TxPayload {
+    fn encode_type(&self) -> String {
+        self.as_eip712().encode_type()
+    }
+
+    fn encode_data(&self) -> Vec<u8> {
+        self.as_eip712().encode_data()
+    }
+}
+
+impl Eip712Struct for Tx {
+    /// e.g. `Transaction(uint64 id,uint64 nonce,uint128 fee,uint64 domain,Withdraw payload)Withdraw(...)`.
+    /// The payload's type and any it references follow sorted by name.
+    fn encode_type(&self) -> String {
+        let payload_type = self.payload.encode_type();
+        let payload_name = payload_type
+            .split('(')
+            .next()
+            .expect("split yields at least one item");
+        let mut referenced: Vec<&str> = payload_type.split_inclusive(')').collect();
+        referenced.sort_unstable();
+        format!(
+            "Transaction(uint64 id,uint64 nonce,uint128 fee,uint64 domain,{} payload){}",
+            payload_name,
+            referenced.concat()
+        )
+    }
+
+    fn encode_data(&self) -> Vec<u8> {
+        [
+            uint_word(self.id as u128),
+            uint_word(self.nonce as u128),
+            uint_word(self.fee),
+            uint_word(self.domain as u128),
+            self.payload.struct_hash(),
+        ]
+        .concat()
+    }
+}
+
+fn keccak256(data: &[u8]) -> [u8; 32] {
+    Keccak256::digest(data).into()
+}
+
+fn uint_word(value: u128) -> [u8; 32] {
+    let mut word = [0u8; 32];
+    word[16..].copy_from_slice(&value.to_be_bytes());
+    word
+}
+
+fn address_word(address: &Address) -> [u8; 32] {
+    let mut word = [0u8; 32];
+    word[12..].copy_from_slice(address);
+    word
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SignatureScheme, TxKind};
+
+    fn hex32(hex: &str) -> [u8; 32] {
+        let mut out = [0u8; 32];
+        for (i, byte) in out.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).unwrap();
+        }
+        out
+    }
+
+    fn address(hex: &str) -> Address {
+        let mut out = [0u8; 20];
+        for (i, byte) in out.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[2 + 2 * i..4 + 2 * i], 16).unwrap();
+        }
+        out
+    }
+
+    const VERIFYING_CONTRACT: &str = "0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC";
+
+    fn withdraw() -> Withdraw {
+        Withdraw {
+            asset_id: 1,
+            amount: 1_000_000,
+            to: address("0x70997970C51812dc3A010C7d01b50e0d17dc79C8"),
+            chain_id: crate::chain_ids::BASE,
+        }
+    }
+
+    /// Reference vector for wallet integrators, computed independently of
+    /// this crate. Typed data:
+    /// domain `{name: "zkClear", version: "1", chainId: 1337,
+    /// verifyingContract: 0xCcCC...cccC}`, primary type `Withdraw` with
+    /// `{assetId: 1, amount: 1000000,
+    /// to: 0x70997970C51812dc3A010C7d01b50e0d17dc79C8, chainId: 8453}`
+    #[test]
+    fn test_withdraw_reference_vector() {
+        let domain = Eip712Domain::new(1337, address(VERIFYING_CONTRACT));
+        assert_eq!(
+            domain.separator(),
+            hex32("4e0ae745e4352531b7f6df930baa2f537ddf4a5ab93b7250a59af0b22b41ccdc")
+        );
+        assert_eq!(
+            withdraw().struct_hash(),
+            hex32("8f4187f8cd8c272c1fd59b69d174a263923212d3859394cf66efa10805e0998a")
+        );
+        assert_eq!(
+            withdraw().signing_hash_eip712(1337, address(VERIFYING_CONTRACT)),
+            hex32("fba5b3da04ca6b31c7a230b72bd2cae092b8fbb1d185fb5667f35db62236753a")
+        );
+    }
+
+    /// The same withdrawal as a `Transaction` with id 7, nonce 3, fee 25 and
+    /// domain 1337
+    #[test]
+    fn test_tx_reference_vector() {
+        let tx = Tx {
+            id: 7,
+            from: [1u8; 20],
+            nonce: 3,
+            kind: TxKind::Withdraw,
+            payload: TxPayload::Withdraw(withdraw()),
+            fee: 25,
+            domain: 1337,
+            scheme: SignatureScheme::Secp256k1Recoverable,
+            signature: vec![0u8; 65],
+        };
+
+        assert_eq!(
+            tx.encode_type(),
+            "Transaction(uint64 id,uint64 nonce,uint128 fee,uint64 domain,Withdraw payload)\
+             Withdraw(uint16 assetId,uint128 amount,address to,uint64 chainId)"
+        );
+        assert_eq!(
+            tx.signing_hash_eip712(1337, address(VERIFYING_CONTRACT)),
+            hex32("75872aa97b57b9abf7b5eff20a8f2af7e600fd33af7cde72331e3c94ea90677f")
+        );
+        assert_ne!(
+            tx.signing_hash_eip712(1, address(VERIFYING_CONTRACT)),
+            tx.signing_hash_eip712(1337, address(VERIFYING_CONTRACT))
+        );
+    }
+
+    #[test]
+    fn test_funded_deal_lists_referenced_types_by_name() {
+        let deposit = Deposit {
+            tx_hash: [2u8; 32],
+            account: [1u8; 20],
+            asset_id: 1,
+            amount: 1_000,
+            chain_id: crate::chain_ids::BASE,
+        };
+        let deal = CreateDeal {
+            deal_id: 9,
+            visibility: crate::DealVisibility::Public,
+            taker: None,
+            asset_base: 1,
+            asset_quote: 2,
+            chain_id_base: crate::chain_ids::BASE,
+            chain_id_quote: crate::chain_ids::BASE,
+            amount_base: 1_000,
+            price_quote_per_base: 5,
+            expires_at: None,
+            external_ref: None,
+        };
+        let tx = Tx {
+            id: 0,
+            from: [1u8; 20],
+            nonce: 0,
+            kind: TxKind::CreateFundedDeal,
+            payload: TxPayload::CreateFundedDeal(CreateFundedDeal {
+                deposit: deposit.clone(),
+                deal: deal.clone(),
+            }),
+            fee: 0,
+            domain: 0,
+            scheme: SignatureScheme::Secp256k1Recoverable,
+            signature: vec![0u8; 65],
+        };
+
+        let encoded = tx.encode_type();
+        let create_deal = encoded.find(&deal.encode_type()).unwrap();
+        let funded = encoded.find("CreateFundedDeal(Deposit deposit").unwrap();
+        let deposit_at = encoded.find(&deposit.encode_type()).unwrap();
+        assert!(encoded.starts_with("Transaction("));
+        assert!(encoded.contains(",CreateFundedDeal payload)"));
+        assert!(create_deal < funded && funded < deposit_at);
+    }
+}
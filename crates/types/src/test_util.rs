@@ -0,0 +1,13 @@
+//! Helpers shared by other crates' tests, behind the `test-util` feature
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Distinct L1 tx hash for each test deposit, so deposits are never
+/// rejected as replays
+pub fn unique_tx_hash() -> [u8; 32] {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+
+    let mut tx_hash = [0u8; 32];
+    tx_hash[..8].copy_from_slice(&NEXT.fetch_add(1, Ordering::Relaxed).to_le_bytes());
+    tx_hash
+}
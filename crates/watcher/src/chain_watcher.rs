@@ -1,32 +1,34 @@
 use crate::config::ChainConfig;
 use crate::event_processor::EventProcessor;
+use crate::reorg::BlockHashWindow;
 use crate::rpc_client::RpcClient;
-use std::collections::HashSet;
 use std::sync::Arc;
 use tokio::time::{interval, Duration};
 use tracing::{debug, error, info, warn};
 use zkclear_sequencer::Sequencer;
+use zkclear_types::DealId;
 
 pub struct ChainWatcher {
     pub(crate) config: ChainConfig,
     processor: EventProcessor,
     pub(crate) rpc_client: RpcClient,
-    processed_txs: Arc<tokio::sync::Mutex<HashSet<[u8; 32]>>>,
     last_processed_block: Arc<tokio::sync::Mutex<u64>>,
-    last_confirmed_block_hash: Arc<tokio::sync::Mutex<Option<[u8; 32]>>>,
+    /// Hashes of the last `reorg_safety_blocks` processed blocks
+    block_hashes: Arc<tokio::sync::Mutex<BlockHashWindow>>,
 }
 
 impl ChainWatcher {
     pub fn new(config: ChainConfig, sequencer: Arc<Sequencer>) -> anyhow::Result<Self> {
-        let processor = EventProcessor::new(sequencer);
+        let processor =
+            EventProcessor::new(sequencer).with_allowed_assets(config.allowed_assets.clone());
         let rpc_client = RpcClient::new(config.clone());
+        let block_hashes = BlockHashWindow::new(config.reorg_safety_blocks);
         Ok(Self {
             config,
             processor,
             rpc_client,
-            processed_txs: Arc::new(tokio::sync::Mutex::new(HashSet::new())),
             last_processed_block: Arc::new(tokio::sync::Mutex::new(0)),
-            last_confirmed_block_hash: Arc::new(tokio::sync::Mutex::new(None)),
+            block_hashes: Arc::new(tokio::sync::Mutex::new(block_hashes)),
         })
     }
 
@@ -56,45 +58,50 @@ impl ChainWatcher {
         let latest_block = self.rpc_client.get_block_number().await?;
         let mut last_processed = *self.last_processed_block.lock().await;
 
-        // Check for reorgs by verifying block hash
-        if last_processed > 0 {
-            if let Err(e) = self.check_reorg(last_processed).await {
-                warn!(
-                    chain_id = self.config.chain_id,
-                    block = last_processed,
-                    error = %e,
-                    "Possible reorg detected, resetting to safety block"
-                );
-                last_processed = last_processed.saturating_sub(self.config.reorg_safety_blocks);
-                *self.last_processed_block.lock().await = last_processed;
-            }
-        }
-
-        if latest_block < last_processed + self.config.required_confirmations {
-            debug!(
+        // Rescan from the fork point if any recently processed block changed.
+        // Deposits are deduplicated by L1 tx hash, so those re-included in
+        // the new fork are not credited twice, and pending ones from the
+        // replaced blocks are dropped until the rescan observes them again.
+        if let Some(fork_block) = self.detect_reorg().await? {
+            warn!(
                 chain_id = self.config.chain_id,
-                latest = latest_block,
-                last_processed = last_processed,
-                "Waiting for more confirmations"
+                fork_block = fork_block,
+                "Reorg detected, rescanning deposits from fork point"
             );
-            return Ok(());
+            last_processed = fork_block.saturating_sub(1);
+            *self.last_processed_block.lock().await = last_processed;
+            self.processor
+                .discard_pending_from(self.config.chain_id, fork_block);
         }
 
-        let from_block = last_processed.saturating_sub(self.config.reorg_safety_blocks);
-        let to_block = latest_block - self.config.required_confirmations;
+        let from_block = if last_processed == 0 {
+            0
+        } else {
+            last_processed + 1
+        };
 
-        if to_block <= from_block {
-            return Ok(());
+        // Blocks are scanned up to the head; deposits are only submitted
+        // once `required_confirmations` blocks have been built on top
+        if latest_block < from_block {
+            return self.release_confirmed(latest_block);
         }
 
         info!(
             chain_id = self.config.chain_id,
             from_block = from_block,
-            to_block = to_block,
+            to_block = latest_block,
             "Polling blocks"
         );
 
-        for block_num in from_block..=to_block {
+        for block_num in from_block..=latest_block {
+            // Fetch the hash before the logs: if a reorg lands in between,
+            // the next poll sees the hash change and rescans this block
+            let block_hash = self
+                .rpc_client
+                .get_block_hash(block_num)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("Block {} not found", block_num))?;
+
             if let Err(e) = self.process_block(block_num).await {
                 error!(
                     chain_id = self.config.chain_id,
@@ -103,44 +110,73 @@ impl ChainWatcher {
                     "Error processing block"
                 );
             }
-        }
 
-        *self.last_processed_block.lock().await = to_block;
+            self.block_hashes.lock().await.insert(block_num, block_hash);
+            *self.last_processed_block.lock().await = block_num;
+        }
 
-        Ok(())
+        self.release_confirmed(latest_block)
     }
 
-    async fn check_reorg(&self, block_number: u64) -> anyhow::Result<()> {
-        // Get block hash for the last processed block
-        let params = serde_json::json!([format!("0x{:x}", block_number), false]);
-        let response = self.rpc_client.call("eth_getBlockByNumber", params).await?;
+    /// Submit pending deposits buried at least `required_confirmations`
+    /// blocks below `head`
+    fn release_confirmed(&self, head: u64) -> anyhow::Result<()> {
+        let released = self.processor.release_confirmed(
+            self.config.chain_id,
+            head,
+            self.config.required_confirmations,
+        )?;
+
+        for tx_hash in released {
+            info!(
+                chain_id = self.config.chain_id,
+                tx_hash = ?tx_hash,
+                head = head,
+                "Submitted confirmed deposit"
+            );
+        }
 
-        let block_hash_hex = response
-            .get("result")
-            .and_then(|v| v.get("hash"))
-            .and_then(|v| v.as_str())
-            .ok_or_else(|| anyhow::anyhow!("Missing block hash"))?;
+        let settled = self.processor.release_confirmed_settlements(
+            self.config.chain_id,
+            head,
+            self.config.required_confirmations,
+        )?;
+        for deal_id in settled {
+            info!(
+                chain_id = self.config.chain_id,
+                deal_id = deal_id,
+                head = head,
+                "Submitted settlement confirmation"
+            );
+        }
+
+        debug!(
+            chain_id = self.config.chain_id,
+            head = head,
+            pending = self.processor.pending_count(self.config.chain_id),
+            "Waiting for more confirmations"
+        );
+        Ok(())
+    }
 
-        let block_hash_bytes = hex::decode(block_hash_hex.trim_start_matches("0x"))
-            .map_err(|e| anyhow::anyhow!("Failed to decode block hash: {}", e))?;
+    /// Re-fetch the hashes of tracked blocks and return the lowest height
+    /// whose block was replaced, dropping it and everything above from the
+    /// window
+    async fn detect_reorg(&self) -> anyhow::Result<Option<u64>> {
+        let heights = self.block_hashes.lock().await.heights();
 
-        if block_hash_bytes.len() != 32 {
-            return Err(anyhow::anyhow!("Invalid block hash length"));
+        let mut current = Vec::with_capacity(heights.len());
+        for number in heights {
+            current.push((number, self.rpc_client.get_block_hash(number).await?));
         }
 
-        let mut block_hash = [0u8; 32];
-        block_hash.copy_from_slice(&block_hash_bytes);
-
-        let mut last_hash = self.last_confirmed_block_hash.lock().await;
-        if let Some(prev_hash) = *last_hash {
-            if prev_hash != block_hash {
-                return Err(anyhow::anyhow!("Block hash mismatch - reorg detected"));
-            }
-        } else {
-            *last_hash = Some(block_hash);
+        let mut block_hashes = self.block_hashes.lock().await;
+        let fork_block = block_hashes.fork_point(&current);
+        if let Some(fork_block) = fork_block {
+            block_hashes.truncate(fork_block);
         }
 
-        Ok(())
+        Ok(fork_block)
     }
 
     async fn process_block(&self, block_number: u64) -> anyhow::Result<()> {
@@ -162,47 +198,69 @@ impl ChainWatcher {
 
         for log in logs {
             let tx_hash = self.parse_tx_hash(&log)?;
+            let (account, asset_id, amount) = self.parse_deposit_log(&log)?;
 
-            let processed = self.processed_txs.lock().await;
-            if processed.contains(&tx_hash) {
+            if self.processor.observe_deposit(
+                self.config.chain_id,
+                block_number,
+                tx_hash,
+                account,
+                asset_id,
+                amount,
+            ) {
+                info!(
+                    chain_id = self.config.chain_id,
+                    block = block_number,
+                    tx_hash = ?tx_hash,
+                    account = ?account,
+                    asset_id = asset_id,
+                    amount = amount,
+                    "Observed deposit, waiting for confirmations"
+                );
+            } else {
                 debug!(
                     chain_id = self.config.chain_id,
                     tx_hash = ?tx_hash,
-                    "Skipping already processed transaction"
+                    "Skipping already processed or disallowed deposit"
                 );
-                continue;
             }
-            drop(processed);
+        }
 
-            let (account, asset_id, amount) = self.parse_deposit_log(&log)?;
+        if let Some(ref settlement_contract) = self.config.settlement_contract_address {
+            self.process_settlement_logs(block_number, settlement_contract)
+                .await?;
+        }
+
+        Ok(())
+    }
 
-            match self.processor.process_deposit_event(
+    async fn process_settlement_logs(
+        &self,
+        block_number: u64,
+        settlement_contract: &str,
+    ) -> anyhow::Result<()> {
+        let logs = self
+            .rpc_client
+            .get_logs(block_number, block_number, settlement_contract)
+            .await?;
+
+        for log in logs {
+            let tx_hash = self.parse_tx_hash(&log)?;
+            let deal_id = self.parse_settlement_log(&log)?;
+
+            if self.processor.observe_settlement(
                 self.config.chain_id,
+                block_number,
                 tx_hash,
-                account,
-                asset_id,
-                amount,
+                deal_id,
             ) {
-                Ok(_) => {
-                    let mut processed = self.processed_txs.lock().await;
-                    processed.insert(tx_hash);
-                    info!(
-                        chain_id = self.config.chain_id,
-                        tx_hash = ?tx_hash,
-                        account = ?account,
-                        asset_id = asset_id,
-                        amount = amount,
-                        "Processed deposit"
-                    );
-                }
-                Err(e) => {
-                    error!(
-                        chain_id = self.config.chain_id,
-                        tx_hash = ?tx_hash,
-                        error = %e,
-                        "Failed to process deposit event"
-                    );
-                }
+                info!(
+                    chain_id = self.config.chain_id,
+                    block = block_number,
+                    tx_hash = ?tx_hash,
+                    deal_id = deal_id,
+                    "Observed settlement, waiting for confirmations"
+                );
             }
         }
 
@@ -298,4 +356,34 @@ impl ChainWatcher {
 
         Ok((account, asset_id, amount))
     }
+
+    fn parse_settlement_log(&self, log: &serde_json::Value) -> anyhow::Result<DealId> {
+        let topics = log["topics"]
+            .as_array()
+            .ok_or_else(|| anyhow::anyhow!("Missing topics in log"))?;
+
+        // Settlement event has 2 indexed parameters: event signature, dealId
+        // topics[0] = event signature hash
+        // topics[1] = dealId (uint64, padded to 32 bytes)
+        if topics.len() < 2 {
+            return Err(anyhow::anyhow!(
+                "Invalid topics length, expected at least 2 (event signature, dealId)"
+            ));
+        }
+
+        let deal_id_hex = topics[1]
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("Missing deal_id in topics"))?;
+
+        let deal_id_bytes = hex::decode(deal_id_hex.trim_start_matches("0x"))
+            .map_err(|e| anyhow::anyhow!("Failed to decode deal_id: {}", e))?;
+
+        if deal_id_bytes.len() != 32 {
+            return Err(anyhow::anyhow!("Invalid deal_id length in topic"));
+        }
+
+        let mut deal_id = [0u8; 8];
+        deal_id.copy_from_slice(&deal_id_bytes[24..32]);
+        Ok(DealId::from_be_bytes(deal_id))
+    }
 }
@@ -1,5 +1,5 @@
 use serde::{Deserialize, Serialize};
-use zkclear_types::ChainId;
+use zkclear_types::{AssetId, ChainId};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChainConfig {
@@ -12,6 +12,14 @@ pub struct ChainConfig {
     pub max_retries: u32,
     pub retry_delay_seconds: u64,
     pub reorg_safety_blocks: u64,
+    /// Assets whose deposits are credited; deposits of any other asset are
+    /// dropped. Empty allows every asset.
+    #[serde(default)]
+    pub allowed_assets: Vec<AssetId>,
+    /// Contract whose settlement events confirm escrowed cross-chain fills
+    /// settled on this chain. Unset, settlements are not watched.
+    #[serde(default)]
+    pub settlement_contract_address: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -51,6 +59,8 @@ impl Default for ChainConfig {
                 .ok()
                 .and_then(|v| v.parse().ok())
                 .unwrap_or(10),
+            allowed_assets: allowed_assets_from_env("ALLOWED_ASSETS"),
+            settlement_contract_address: std::env::var("SETTLEMENT_CONTRACT_ADDRESS").ok(),
         }
     }
 }
@@ -73,6 +83,8 @@ impl Default for WatcherConfig {
                     max_retries: 3,
                     retry_delay_seconds: 1,
                     reorg_safety_blocks: 10,
+                    allowed_assets: allowed_assets_from_env("ETHEREUM_ALLOWED_ASSETS"),
+                    settlement_contract_address: std::env::var("ETHEREUM_SETTLEMENT_CONTRACT").ok(),
                 },
                 ChainConfig {
                     chain_id: zkclear_types::chain_ids::BASE,
@@ -88,8 +100,23 @@ impl Default for WatcherConfig {
                     max_retries: 3,
                     retry_delay_seconds: 1,
                     reorg_safety_blocks: 10,
+                    allowed_assets: allowed_assets_from_env("BASE_ALLOWED_ASSETS"),
+                    settlement_contract_address: std::env::var("BASE_SETTLEMENT_CONTRACT").ok(),
                 },
             ],
         }
     }
 }
+
+/// Parse a comma-separated list of asset ids from env var `name`, skipping
+/// entries that aren't valid ids. Unset means an empty list.
+pub fn allowed_assets_from_env(name: &str) -> Vec<AssetId> {
+    std::env::var(name)
+        .map(|value| {
+            value
+                .split(',')
+                .filter_map(|entry| entry.trim().parse().ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
@@ -1,16 +1,243 @@
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tracing::warn;
 use zkclear_sequencer::Sequencer;
-use zkclear_types::{Address, AssetId, ChainId, Deposit, Tx, TxKind, TxPayload};
+use zkclear_types::{
+    Address, AssetId, ChainId, ConfirmSettlement, DealId, Deposit, SignatureScheme, Tx, TxKind,
+    TxPayload,
+};
 
 pub struct EventProcessor {
     sequencer: Arc<Sequencer>,
+    /// L1 tx hashes of deposits already enqueued
+    processed: Mutex<HashSet<[u8; 32]>>,
+    /// Deposits seen on chain but not yet buried under enough blocks
+    pending: Mutex<HashMap<(ChainId, [u8; 32]), PendingDeposit>>,
+    /// Assets deposits may credit; empty allows every asset
+    allowed_assets: HashSet<AssetId>,
+    /// Deposits dropped because their asset is not allowed
+    disallowed_deposits: AtomicU64,
+    /// Settlements of cross-chain fills already confirmed, by L1 tx hash
+    /// and deal
+    settled: Mutex<HashSet<([u8; 32], DealId)>>,
+    /// Settlements seen on chain, by the L1 block they were seen in, not yet
+    /// buried under enough blocks
+    pending_settlements: Mutex<HashMap<SettlementKey, u64>>,
+}
+
+/// A settlement by chain, L1 tx hash and deal
+type SettlementKey = (ChainId, [u8; 32], DealId);
+
+/// A deposit observed in L1 block `block_number`, waiting for confirmations
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct PendingDeposit {
+    block_number: u64,
+    account: Address,
+    asset_id: AssetId,
+    amount: u128,
 }
 
 impl EventProcessor {
     pub fn new(sequencer: Arc<Sequencer>) -> Self {
-        Self { sequencer }
+        Self {
+            sequencer,
+            processed: Mutex::new(HashSet::new()),
+            pending: Mutex::new(HashMap::new()),
+            allowed_assets: HashSet::new(),
+            disallowed_deposits: AtomicU64::new(0),
+            settled: Mutex::new(HashSet::new()),
+            pending_settlements: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Only credit deposits of `assets`. An empty list allows every asset.
+    pub fn with_allowed_assets(mut self, assets: impl IntoIterator<Item = AssetId>) -> Self {
+        self.allowed_assets = assets.into_iter().collect();
+        self
+    }
+
+    /// Number of deposits dropped because their asset is not allowed
+    pub fn disallowed_deposits(&self) -> u64 {
+        self.disallowed_deposits.load(Ordering::Relaxed)
+    }
+
+    /// Whether deposits of `asset_id` may be credited, counting and logging
+    /// the deposit as dropped if not
+    fn admit_asset(&self, chain_id: ChainId, tx_hash: &[u8; 32], asset_id: AssetId) -> bool {
+        if self.allowed_assets.is_empty() || self.allowed_assets.contains(&asset_id) {
+            return true;
+        }
+
+        let dropped = self.disallowed_deposits.fetch_add(1, Ordering::Relaxed) + 1;
+        warn!(
+            chain_id = chain_id,
+            tx_hash = ?tx_hash,
+            asset_id = asset_id,
+            dropped = dropped,
+            "Dropping deposit of asset not on the allowlist"
+        );
+        false
+    }
+
+    /// Hold a deposit seen in L1 block `block_number` until it is confirmed.
+    /// Observing the same deposit again, as a rescan after a reorg or a
+    /// restart does, moves it to its latest block. Returns whether the
+    /// deposit is pending, i.e. it was not already enqueued and its asset is
+    /// allowed.
+    pub fn observe_deposit(
+        &self,
+        chain_id: ChainId,
+        block_number: u64,
+        tx_hash: [u8; 32],
+        account: Address,
+        asset_id: AssetId,
+        amount: u128,
+    ) -> bool {
+        if self.processed.lock().unwrap().contains(&tx_hash)
+            || !self.admit_asset(chain_id, &tx_hash, asset_id)
+        {
+            return false;
+        }
+
+        self.pending.lock().unwrap().insert(
+            (chain_id, tx_hash),
+            PendingDeposit {
+                block_number,
+                account,
+                asset_id,
+                amount,
+            },
+        );
+        true
+    }
+
+    /// Forget pending deposits on `chain_id` from `block_number` onwards,
+    /// whose blocks a reorg replaced. Rescanning re-observes those that
+    /// survived the reorg.
+    pub fn discard_pending_from(&self, chain_id: ChainId, block_number: u64) {
+        self.pending.lock().unwrap().retain(|(chain, _), deposit| {
+            *chain != chain_id || deposit.block_number < block_number
+        });
+        self.pending_settlements
+            .lock()
+            .unwrap()
+            .retain(|(chain, _, _), seen_at| *chain != chain_id || *seen_at < block_number);
+    }
+
+    /// Enqueue the pending deposits on `chain_id` that are at least
+    /// `required_confirmations` blocks below `head`, oldest block first.
+    /// Depth is derived from `head` on every call, so nothing about it needs
+    /// to be persisted across restarts. Returns the L1 tx hashes enqueued.
+    pub fn release_confirmed(
+        &self,
+        chain_id: ChainId,
+        head: u64,
+        required_confirmations: u64,
+    ) -> anyhow::Result<Vec<[u8; 32]>> {
+        let mut confirmed: Vec<_> = self
+            .pending
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|((chain, _), deposit)| {
+                *chain == chain_id
+                    && head >= deposit.block_number.saturating_add(required_confirmations)
+            })
+            .map(|((_, tx_hash), deposit)| (*tx_hash, *deposit))
+            .collect();
+        confirmed.sort_by_key(|(tx_hash, deposit)| (deposit.block_number, *tx_hash));
+
+        let mut released = Vec::with_capacity(confirmed.len());
+        for (tx_hash, deposit) in confirmed {
+            let enqueued = self.process_deposit_event(
+                chain_id,
+                tx_hash,
+                deposit.account,
+                deposit.asset_id,
+                deposit.amount,
+            )?;
+            self.pending.lock().unwrap().remove(&(chain_id, tx_hash));
+            if enqueued {
+                released.push(tx_hash);
+            }
+        }
+
+        Ok(released)
+    }
+
+    /// Hold the settlement of deal `deal_id`'s oldest escrowed fill, seen in
+    /// L1 block `block_number`, until it is confirmed. Returns whether the
+    /// settlement is pending, i.e. it was not already confirmed.
+    pub fn observe_settlement(
+        &self,
+        chain_id: ChainId,
+        block_number: u64,
+        tx_hash: [u8; 32],
+        deal_id: DealId,
+    ) -> bool {
+        if self.settled.lock().unwrap().contains(&(tx_hash, deal_id)) {
+            return false;
+        }
+
+        self.pending_settlements
+            .lock()
+            .unwrap()
+            .insert((chain_id, tx_hash, deal_id), block_number);
+        true
     }
 
+    /// Submit a `ConfirmSettlement` for each pending settlement on
+    /// `chain_id` at least `required_confirmations` blocks below `head`,
+    /// oldest block first. Returns the deals confirmed.
+    pub fn release_confirmed_settlements(
+        &self,
+        chain_id: ChainId,
+        head: u64,
+        required_confirmations: u64,
+    ) -> anyhow::Result<Vec<DealId>> {
+        let mut confirmed: Vec<_> = self
+            .pending_settlements
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|((chain, _, _), seen_at)| {
+                *chain == chain_id && head >= seen_at.saturating_add(required_confirmations)
+            })
+            .map(|((_, tx_hash, deal_id), seen_at)| (*seen_at, *tx_hash, *deal_id))
+            .collect();
+        confirmed.sort();
+
+        let mut released = Vec::with_capacity(confirmed.len());
+        for (_, tx_hash, deal_id) in confirmed {
+            let submitted = self.process_settlement_event(tx_hash, deal_id)?;
+            self.pending_settlements
+                .lock()
+                .unwrap()
+                .remove(&(chain_id, tx_hash, deal_id));
+            if submitted {
+                released.push(deal_id);
+            }
+        }
+
+        Ok(released)
+    }
+
+    /// Number of deposits still waiting for confirmations on `chain_id`
+    pub fn pending_count(&self, chain_id: ChainId) -> usize {
+        self.pending
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|(chain, _)| *chain == chain_id)
+            .count()
+    }
+
+    /// Enqueue a deposit unless one with the same L1 `tx_hash` was already
+    /// enqueued, as happens when blocks are rescanned after a reorg, or its
+    /// asset is not allowed. The deposit is relayed from the sequencer's
+    /// deposit watcher, or from `account` itself when none is configured.
+    /// Returns whether the deposit was enqueued.
     pub fn process_deposit_event(
         &self,
         chain_id: ChainId,
@@ -18,7 +245,12 @@ impl EventProcessor {
         account: Address,
         asset_id: AssetId,
         amount: u128,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<bool> {
+        let mut processed = self.processed.lock().unwrap();
+        if processed.contains(&tx_hash) || !self.admit_asset(chain_id, &tx_hash, asset_id) {
+            return Ok(false);
+        }
+
         let deposit = Deposit {
             tx_hash,
             account,
@@ -27,19 +259,243 @@ impl EventProcessor {
             chain_id,
         };
 
+        let from = self
+            .sequencer
+            .stf_config()
+            .deposit_watcher
+            .unwrap_or(account);
         let tx = Tx {
             id: 0,
-            from: account,
-            nonce: 0,
+            from,
+            nonce: self.sequencer.next_nonce(from),
             kind: TxKind::Deposit,
             payload: TxPayload::Deposit(deposit),
-            signature: [0u8; 65],
+            fee: 0,
+            domain: self.sequencer.network_id(),
+            scheme: SignatureScheme::Secp256k1Recoverable,
+            signature: vec![0u8; 65],
         };
 
         self.sequencer
             .submit_tx_with_validation(tx, false)
             .map_err(|e| anyhow::anyhow!("Failed to submit deposit tx: {:?}", e))?;
 
-        Ok(())
+        processed.insert(tx_hash);
+        Ok(true)
+    }
+
+    /// Submit a `ConfirmSettlement` for deal `deal_id` from the sequencer's
+    /// settlement confirmer, unless the settlement in L1 tx `tx_hash` was
+    /// already confirmed. Returns whether the tx was submitted; without a
+    /// confirmer configured, cross-chain fills are never escrowed and there
+    /// is nothing to confirm.
+    pub fn process_settlement_event(
+        &self,
+        tx_hash: [u8; 32],
+        deal_id: DealId,
+    ) -> anyhow::Result<bool> {
+        let Some(confirmer) = self.sequencer.stf_config().settlement_confirmer else {
+            return Ok(false);
+        };
+        let mut settled = self.settled.lock().unwrap();
+        if settled.contains(&(tx_hash, deal_id)) {
+            return Ok(false);
+        }
+
+        let tx = Tx {
+            id: 0,
+            from: confirmer,
+            nonce: self.sequencer.next_nonce(confirmer),
+            kind: TxKind::ConfirmSettlement,
+            payload: TxPayload::ConfirmSettlement(ConfirmSettlement { deal_id }),
+            fee: 0,
+            domain: self.sequencer.network_id(),
+            scheme: SignatureScheme::Secp256k1Recoverable,
+            signature: vec![0u8; 65],
+        };
+
+        self.sequencer
+            .submit_tx_with_validation(tx, false)
+            .map_err(|e| anyhow::anyhow!("Failed to submit settlement confirmation: {:?}", e))?;
+
+        settled.insert((tx_hash, deal_id));
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHAIN: ChainId = zkclear_types::chain_ids::ETHEREUM;
+    const CONFIRMATIONS: u64 = 12;
+
+    #[test]
+    fn test_deposit_withheld_until_confirmed() {
+        let sequencer = Arc::new(Sequencer::new());
+        let processor = EventProcessor::new(sequencer.clone());
+        let head = 100;
+
+        assert!(processor.observe_deposit(CHAIN, head, [1; 32], [1; 20], 0, 500));
+        for head in head..head + CONFIRMATIONS {
+            let released = processor
+                .release_confirmed(CHAIN, head, CONFIRMATIONS)
+                .unwrap();
+            assert!(released.is_empty(), "released at head {}", head);
+        }
+        assert_eq!(sequencer.queue_length(), 0);
+        assert_eq!(processor.pending_count(CHAIN), 1);
+
+        let released = processor
+            .release_confirmed(CHAIN, head + CONFIRMATIONS, CONFIRMATIONS)
+            .unwrap();
+        assert_eq!(released, vec![[1; 32]]);
+        assert_eq!(processor.pending_count(CHAIN), 0);
+
+        // Later polls and rescans of the same block do not resubmit it
+        assert!(!processor.observe_deposit(CHAIN, head, [1; 32], [1; 20], 0, 500));
+        let released = processor
+            .release_confirmed(CHAIN, head + CONFIRMATIONS + 1, CONFIRMATIONS)
+            .unwrap();
+        assert!(released.is_empty());
+        assert_eq!(sequencer.queue_length(), 1);
+    }
+
+    #[test]
+    fn test_restart_rederives_depth_from_head() {
+        let sequencer = Arc::new(Sequencer::new());
+        let processor = EventProcessor::new(sequencer.clone());
+        processor.observe_deposit(CHAIN, 100, [1; 32], [1; 20], 0, 500);
+        processor.observe_deposit(CHAIN, 105, [2; 32], [2; 20], 0, 500);
+
+        // A fresh processor rescanning the same blocks at head 115 releases
+        // only the deposit that is already deep enough
+        let restarted = EventProcessor::new(sequencer.clone());
+        restarted.observe_deposit(CHAIN, 100, [1; 32], [1; 20], 0, 500);
+        restarted.observe_deposit(CHAIN, 105, [2; 32], [2; 20], 0, 500);
+        let released = restarted
+            .release_confirmed(CHAIN, 115, CONFIRMATIONS)
+            .unwrap();
+        assert_eq!(released, vec![[1; 32]]);
+        assert_eq!(restarted.pending_count(CHAIN), 1);
+        assert_eq!(sequencer.queue_length(), 1);
+    }
+
+    #[test]
+    fn test_disallowed_asset_never_reaches_sequencer() {
+        let sequencer = Arc::new(Sequencer::new());
+        let processor = EventProcessor::new(sequencer.clone()).with_allowed_assets([0, 1]);
+
+        assert!(processor.observe_deposit(CHAIN, 100, [1; 32], [1; 20], 1, 500));
+        assert!(!processor.observe_deposit(CHAIN, 100, [2; 32], [2; 20], 7, 500));
+        assert!(!processor
+            .process_deposit_event(CHAIN, [3; 32], [3; 20], 7, 500)
+            .unwrap());
+
+        let released = processor
+            .release_confirmed(CHAIN, 100 + CONFIRMATIONS, CONFIRMATIONS)
+            .unwrap();
+        assert_eq!(released, vec![[1; 32]]);
+        assert_eq!(processor.disallowed_deposits(), 2);
+
+        let block = sequencer.build_block().unwrap();
+        assert_eq!(block.transactions.len(), 1);
+        match &block.transactions[0].payload {
+            TxPayload::Deposit(deposit) => assert_eq!(deposit.asset_id, 1),
+            other => panic!("unexpected payload {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_reorg_discards_pending_deposits() {
+        let sequencer = Arc::new(Sequencer::new());
+        let processor = EventProcessor::new(sequencer.clone());
+        processor.observe_deposit(CHAIN, 100, [1; 32], [1; 20], 0, 500);
+        processor.observe_deposit(CHAIN, 103, [2; 32], [2; 20], 0, 500);
+        processor.observe_deposit(
+            zkclear_types::chain_ids::BASE,
+            103,
+            [3; 32],
+            [3; 20],
+            0,
+            500,
+        );
+
+        processor.discard_pending_from(CHAIN, 102);
+        assert_eq!(processor.pending_count(CHAIN), 1);
+        assert_eq!(processor.pending_count(zkclear_types::chain_ids::BASE), 1);
+    }
+
+    #[test]
+    fn test_settlement_confirmed_from_confirmer() {
+        let confirmer = [9; 20];
+        let sequencer = Arc::new(
+            Sequencer::new().with_stf_config(zkclear_sequencer::StfConfig {
+                settlement_confirmer: Some(confirmer),
+                ..Default::default()
+            }),
+        );
+        let processor = EventProcessor::new(sequencer.clone());
+
+        assert!(processor.observe_settlement(CHAIN, 100, [1; 32], 4));
+        assert!(processor.observe_settlement(CHAIN, 101, [2; 32], 5));
+        let released = processor
+            .release_confirmed_settlements(CHAIN, 100 + CONFIRMATIONS, CONFIRMATIONS)
+            .unwrap();
+        assert_eq!(released, vec![4]);
+        let released = processor
+            .release_confirmed_settlements(CHAIN, 101 + CONFIRMATIONS, CONFIRMATIONS)
+            .unwrap();
+        assert_eq!(released, vec![5]);
+
+        // A rescan of the same block does not confirm the fill twice
+        assert!(!processor.observe_settlement(CHAIN, 100, [1; 32], 4));
+
+        // Queued from the confirmer with consecutive nonces
+        assert_eq!(sequencer.queue_length(), 2);
+        assert_eq!(sequencer.next_nonce(confirmer), 2);
+    }
+
+    #[test]
+    fn test_deposits_relayed_from_watcher() {
+        let watcher = [9; 20];
+        let sequencer = Arc::new(
+            Sequencer::new().with_stf_config(zkclear_sequencer::StfConfig {
+                deposit_watcher: Some(watcher),
+                ..Default::default()
+            }),
+        );
+        let processor = EventProcessor::new(sequencer.clone());
+
+        assert!(processor
+            .process_deposit_event(CHAIN, [1; 32], [1; 20], 0, 500)
+            .unwrap());
+        assert!(processor
+            .process_deposit_event(CHAIN, [2; 32], [2; 20], 0, 700)
+            .unwrap());
+        assert_eq!(sequencer.next_nonce(watcher), 2);
+
+        // Both deposits credit their depositors, not the watcher
+        let block = sequencer.build_and_execute_block().unwrap();
+        assert_eq!(block.transactions.len(), 2);
+        let state = sequencer.get_state();
+        let state = state.lock().unwrap();
+        let balance = |account: Address| {
+            state
+                .get_account_by_address(account)
+                .map_or(0, |a| a.balance_of(0, CHAIN))
+        };
+        assert_eq!(balance([1; 20]), 500);
+        assert_eq!(balance([2; 20]), 700);
+        assert_eq!(balance(watcher), 0);
+    }
+
+    #[test]
+    fn test_settlement_ignored_without_confirmer() {
+        let sequencer = Arc::new(Sequencer::new());
+        let processor = EventProcessor::new(sequencer.clone());
+
+        assert!(!processor.process_settlement_event([1; 32], 4).unwrap());
+        assert_eq!(sequencer.queue_length(), 0);
     }
 }
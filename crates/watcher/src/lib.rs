@@ -1,11 +1,13 @@
 mod chain_watcher;
 mod config;
 mod event_processor;
+mod reorg;
 mod rpc_client;
 
 pub use chain_watcher::ChainWatcher;
-pub use config::{ChainConfig, WatcherConfig};
+pub use config::{allowed_assets_from_env, ChainConfig, WatcherConfig};
 pub use event_processor::EventProcessor;
+pub use reorg::BlockHashWindow;
 pub use rpc_client::RpcClient;
 
 use std::sync::Arc;
@@ -0,0 +1,176 @@
+use std::collections::BTreeMap;
+
+/// Hashes of the most recently processed blocks, kept so the watcher can
+/// notice when the chain has reorganized underneath it
+#[derive(Debug, Clone, Default)]
+pub struct BlockHashWindow {
+    depth: u64,
+    hashes: BTreeMap<u64, [u8; 32]>,
+}
+
+impl BlockHashWindow {
+    /// Track the last `depth` blocks. A depth of 0 disables reorg detection.
+    pub fn new(depth: u64) -> Self {
+        Self {
+            depth,
+            hashes: BTreeMap::new(),
+        }
+    }
+
+    /// Record the hash of a processed block, forgetting blocks that fall out
+    /// of the window
+    pub fn insert(&mut self, number: u64, hash: [u8; 32]) {
+        if self.depth == 0 {
+            return;
+        }
+
+        self.hashes.insert(number, hash);
+        if let Some(oldest) = number.checked_sub(self.depth - 1) {
+            self.hashes = self.hashes.split_off(&oldest);
+        }
+    }
+
+    /// Heights currently tracked, lowest first
+    pub fn heights(&self) -> Vec<u64> {
+        self.hashes.keys().copied().collect()
+    }
+
+    /// Lowest tracked height whose hash no longer matches the chain. A
+    /// height missing from `current` (`None`) counts as changed, since the
+    /// chain got shorter.
+    pub fn fork_point(&self, current: &[(u64, Option<[u8; 32]>)]) -> Option<u64> {
+        current
+            .iter()
+            .filter(|(number, hash)| match self.hashes.get(number) {
+                Some(seen) => hash.as_ref() != Some(seen),
+                None => false,
+            })
+            .map(|(number, _)| *number)
+            .min()
+    }
+
+    /// Forget blocks at or above `number`, which a reorg replaced
+    pub fn truncate(&mut self, number: u64) {
+        self.hashes.split_off(&number);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EventProcessor;
+    use std::sync::Arc;
+    use zkclear_sequencer::Sequencer;
+    use zkclear_types::TxPayload;
+
+    /// A block on the simulated chain: its hash and the L1 tx hashes of the
+    /// deposits it contains
+    struct SimBlock {
+        hash: [u8; 32],
+        deposits: Vec<[u8; 32]>,
+    }
+
+    fn block(hash: u8, deposits: &[u8]) -> SimBlock {
+        SimBlock {
+            hash: [hash; 32],
+            deposits: deposits.iter().map(|d| [*d; 32]).collect(),
+        }
+    }
+
+    /// One poll of the watcher against `chain` (indexed by height): detect a
+    /// fork among tracked blocks, then scan every block after the last
+    /// processed one. Returns the new last processed height.
+    fn poll(
+        window: &mut BlockHashWindow,
+        processor: &EventProcessor,
+        chain: &[SimBlock],
+        mut last_processed: Option<u64>,
+    ) -> Option<u64> {
+        let current: Vec<_> = window
+            .heights()
+            .into_iter()
+            .map(|n| (n, chain.get(n as usize).map(|b| b.hash)))
+            .collect();
+        if let Some(fork) = window.fork_point(&current) {
+            window.truncate(fork);
+            last_processed = fork.checked_sub(1);
+        }
+
+        let from = last_processed.map_or(0, |n| n + 1);
+        for number in from..chain.len() as u64 {
+            let block = &chain[number as usize];
+            for tx_hash in &block.deposits {
+                // One account per deposit, so nonces never conflict
+                let account = [tx_hash[0]; 20];
+                processor
+                    .process_deposit_event(1, *tx_hash, account, 0, 100)
+                    .unwrap();
+            }
+            window.insert(number, block.hash);
+        }
+
+        chain.len().checked_sub(1).map(|n| n as u64)
+    }
+
+    #[test]
+    fn test_window_keeps_last_blocks() {
+        let mut window = BlockHashWindow::new(3);
+        for number in 1..=5 {
+            window.insert(number, [number as u8; 32]);
+        }
+        assert_eq!(window.heights(), vec![3, 4, 5]);
+
+        let current = [(3, Some([3; 32])), (4, Some([9; 32])), (5, None)];
+        assert_eq!(window.fork_point(&current), Some(4));
+        window.truncate(4);
+        assert_eq!(window.heights(), vec![3]);
+
+        let mut disabled = BlockHashWindow::new(0);
+        disabled.insert(1, [1; 32]);
+        assert!(disabled.heights().is_empty());
+    }
+
+    #[test]
+    fn test_reorg_rescan_does_not_duplicate_deposits() {
+        let sequencer = Arc::new(Sequencer::new());
+        let processor = EventProcessor::new(sequencer.clone());
+        let mut window = BlockHashWindow::new(4);
+
+        let mut chain = vec![
+            block(0, &[]),
+            block(1, &[1]),
+            block(2, &[2]),
+            block(3, &[3, 4]),
+        ];
+        let last = poll(&mut window, &processor, &chain, None);
+        assert_eq!(sequencer.queue_length(), 4);
+
+        // Blocks 2 and 3 are replaced. Deposit 2 is re-included at the same
+        // height, deposit 4 moves to a later block, deposit 3 is dropped and
+        // deposit 5 is new.
+        chain.truncate(2);
+        chain.push(block(12, &[2]));
+        chain.push(block(13, &[]));
+        chain.push(block(14, &[5, 4]));
+        let last = poll(&mut window, &processor, &chain, last);
+        assert_eq!(last, Some(4));
+        assert_eq!(window.heights(), vec![1, 2, 3, 4]);
+
+        // A quiet poll rescans nothing
+        poll(&mut window, &processor, &chain, last);
+
+        let block = sequencer.build_block().unwrap();
+        let mut deposits: Vec<u8> = block
+            .transactions
+            .iter()
+            .map(|tx| match &tx.payload {
+                TxPayload::Deposit(d) => d.tx_hash[0],
+                other => panic!("unexpected payload {:?}", other),
+            })
+            .collect();
+        deposits.sort();
+        // Deposit 3 was enqueued before the reorg dropped it; rescanning
+        // only guarantees that nothing is enqueued twice
+        assert_eq!(deposits, vec![1, 2, 3, 4, 5]);
+    }
+}
@@ -107,6 +107,30 @@ impl RpcClient {
         Ok(block_num)
     }
 
+    /// Hash of block `number`, or `None` if the chain has no such block
+    pub async fn get_block_hash(&self, number: u64) -> Result<Option<[u8; 32]>> {
+        let params = serde_json::json!([format!("0x{:x}", number), false]);
+        let response = self.call("eth_getBlockByNumber", params).await?;
+
+        let Some(block) = response.get("result").filter(|v| !v.is_null()) else {
+            return Ok(None);
+        };
+
+        let hash_hex = block
+            .get("hash")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| anyhow::anyhow!("Missing block hash"))?;
+
+        let hash_bytes = hex::decode(hash_hex.trim_start_matches("0x"))
+            .map_err(|e| anyhow::anyhow!("Failed to decode block hash: {}", e))?;
+
+        let hash: [u8; 32] = hash_bytes
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("Invalid block hash length"))?;
+
+        Ok(Some(hash))
+    }
+
     pub async fn get_logs(
         &self,
         from_block: u64,
@@ -59,6 +59,8 @@ impl HardhatNode {
             max_retries: 1,
             retry_delay_seconds: 1,
             reorg_safety_blocks: 2,
+            allowed_assets: Vec::new(),
+            settlement_contract_address: None,
         };
         let client = RpcClient::new(config);
         client.get_block_number().await.is_ok()
@@ -35,6 +35,8 @@ fn create_test_chain_config(deposit_contract_address: String) -> ChainConfig {
         max_retries: 3,
         retry_delay_seconds: 1,
         reorg_safety_blocks: 0, // No reorgs in Hardhat local node
+        allowed_assets: Vec::new(),
+        settlement_contract_address: None,
     }
 }
 
@@ -64,7 +66,7 @@ fn get_queue_length(sequencer: &Sequencer) -> usize {
 }
 
 #[tokio::test]
-#[ignore] // Requires Hardhat node to be running
+#[ignore]// Requires Hardhat node to be running
 async fn test_watcher_detects_single_deposit() {
     // Initialize
     let sequencer = create_test_sequencer();